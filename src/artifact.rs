@@ -0,0 +1,477 @@
+//! Prebuilt binaries published by CI
+//!
+//! Bitcoin Core's CI already builds each PR. Instead of building the standard configuration
+//! locally, benchkit can download the binary of a commit from a URL template or from the GitHub
+//! Actions artifacts of a repository, verify its sha256 and install it as `bitcoind-<commit>`.
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::fs::File;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::benchmarks::{get_binary_path, sha256_file, BinaryMetadata};
+use crate::command::CommandExecutor;
+use crate::path_utils;
+use crate::reference::expected_sha256;
+
+/// Where to download prebuilt binaries from, one of `url` or `github`
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ArtifactConfig {
+    /// URL of a bitcoind binary or an archive containing it, with `{commit}` replaced by the full
+    /// commit hash
+    pub url: Option<String>,
+    /// URL of the download's sha256, either the bare hash or a SHA256SUMS listing, with
+    /// `{commit}` replaced. Defaults to `<url>.sha256`
+    pub sha256_url: Option<String>,
+    /// Download GitHub Actions artifacts
+    pub github: Option<GithubArtifacts>,
+    /// Path of bitcoind within an archive
+    #[serde(default = "default_binary")]
+    pub binary: String,
+}
+
+/// GitHub Actions artifacts of a repository's workflow runs
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GithubArtifacts {
+    /// Repository whose workflow runs upload the artifacts, e.g. "bitcoin/bitcoin"
+    pub repo: String,
+    /// Artifact name, with `{commit}` replaced. Without `{commit}` the artifact of a run for the
+    /// commit is used
+    pub name: String,
+    /// Environment variable holding a token allowed to download the artifacts
+    #[serde(default = "default_token_env")]
+    pub token_env: String,
+    /// GitHub API, for GitHub Enterprise servers
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+}
+
+fn default_binary() -> String {
+    "bin/bitcoind".to_string()
+}
+
+fn default_token_env() -> String {
+    "GITHUB_TOKEN".to_string()
+}
+
+fn default_api_url() -> String {
+    "https://api.github.com".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtifactList {
+    artifacts: Vec<Artifact>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Artifact {
+    name: String,
+    expired: bool,
+    archive_download_url: String,
+    /// `sha256:<hash>` of the zip archive
+    digest: Option<String>,
+    workflow_run: Option<WorkflowRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WorkflowRun {
+    head_sha: Option<String>,
+}
+
+/// A download and the sha256 it must have
+struct Download {
+    request: RequestBuilder,
+    file_name: String,
+    sha256: String,
+}
+
+impl ArtifactConfig {
+    pub fn validate(&self) -> Result<()> {
+        match (&self.url, &self.github) {
+            (Some(_), Some(_)) => anyhow::bail!("artifacts sets both url and github"),
+            (None, None) => anyhow::bail!("artifacts needs a url or github"),
+            _ => {}
+        }
+        if self.sha256_url.is_some() && self.url.is_none() {
+            anyhow::bail!("artifacts.sha256_url needs artifacts.url");
+        }
+        Ok(())
+    }
+
+    /// Download and install the binary of `commit` into `bin_dir`, using a directory in `scratch`
+    ///
+    /// Returns false if no artifact is published for the commit, and fails if one is published
+    /// but doesn't match its sha256.
+    pub fn fetch(&self, commit: &str, scratch: &Path, bin_dir: &Path) -> Result<bool> {
+        let client = Client::builder()
+            .user_agent(concat!("benchkit/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let download = match (&self.url, &self.github) {
+            (Some(url), _) => self.url_download(&client, url, commit)?,
+            (_, Some(github)) => github.download(&client, commit)?,
+            (None, None) => None,
+        };
+        let Some(download) = download else {
+            info!("No prebuilt binary is published for {commit}");
+            return Ok(false);
+        };
+
+        let dir = scratch.join(format!("artifact-{commit}"));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        path_utils::ensure_directory(&dir)?;
+        let result = self.install(download, commit, &dir, bin_dir);
+        std::fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        result.map(|()| true)
+    }
+
+    fn url_download(&self, client: &Client, url: &str, commit: &str) -> Result<Option<Download>> {
+        let url = url.replace("{commit}", commit);
+        let sha256_url = match &self.sha256_url {
+            Some(sha256_url) => sha256_url.replace("{commit}", commit),
+            None => format!("{url}.sha256"),
+        };
+        let file_name = url
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("bitcoind")
+            .to_string();
+
+        let response = client.get(&sha256_url).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let sums = response
+            .error_for_status()
+            .with_context(|| format!("Failed to download {sha256_url}"))?
+            .text()?;
+        let sha256 = expected_sha256(&sums, &file_name)
+            .or_else(|| sums.split_whitespace().next())
+            .with_context(|| format!("No sha256 found in {sha256_url}"))?
+            .to_string();
+        Ok(Some(Download {
+            request: client.get(url),
+            file_name,
+            sha256,
+        }))
+    }
+
+    /// Download, verify and unpack an artifact, installing its binary with metadata
+    fn install(&self, download: Download, commit: &str, dir: &Path, bin_dir: &Path) -> Result<()> {
+        let path = dir.join(&download.file_name);
+        let mut response = download.request.send()?.error_for_status()?;
+        let url = response.url().clone();
+        info!("Downloading prebuilt binary of {commit} from {url}");
+        let mut file = File::create(&path)?;
+        response.copy_to(&mut file)?;
+
+        let actual = sha256_file(&path)?;
+        if !actual.eq_ignore_ascii_case(&download.sha256) {
+            anyhow::bail!(
+                "Artifact {} of {commit} has sha256 {actual}, expected {}",
+                download.file_name,
+                download.sha256
+            );
+        }
+
+        let binary = self.unpack(&path, dir)?;
+        let dest = get_binary_path(bin_dir, commit);
+        path_utils::ensure_directory(bin_dir)?;
+        std::fs::copy(&binary, &dest)
+            .with_context(|| format!("Failed to install {}", dest.display()))?;
+        #[cfg(unix)]
+        std::fs::set_permissions(&dest, std::fs::Permissions::from_mode(0o755))?;
+
+        let metadata = BinaryMetadata {
+            commit: commit.to_string(),
+            variant: None,
+            build_system: "artifact".to_string(),
+            compiler: None,
+            compiler_flags: None,
+            configure_args: Vec::new(),
+            build_host: url.host_str().unwrap_or_default().to_string(),
+            build_duration_secs: 0.0,
+            built_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            sha256: sha256_file(&dest)?,
+            patched: false,
+            patches: Vec::new(),
+            settings: None,
+        };
+        metadata.save(&dest)?;
+        info!(
+            "Installed prebuilt binary of {commit} at {}",
+            dest.display()
+        );
+        Ok(())
+    }
+
+    /// Extract an archive, returning the path of bitcoind in it, or the download itself if it
+    /// isn't an archive
+    fn unpack(&self, path: &Path, dir: &Path) -> Result<PathBuf> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (program, args): (&str, &[&str]) = if name.ends_with(".zip") {
+            ("unzip", &["-q", "-o"])
+        } else if name.contains(".tar") || name.ends_with(".tgz") {
+            ("tar", &["-xf"])
+        } else {
+            return Ok(path.to_path_buf());
+        };
+        let mut args = args.to_vec();
+        args.push(&name);
+        CommandExecutor::builder()
+            .name(format!("extract {name}"))
+            .working_dir(Some(dir))
+            .build()?
+            .execute_command_with_args(program, &args)
+            .with_context(|| format!("Failed to extract {name}"))?;
+
+        let binary = dir.join(&self.binary);
+        if !binary.is_file() {
+            anyhow::bail!("{name} does not contain {}", self.binary);
+        }
+        Ok(binary)
+    }
+}
+
+impl GithubArtifacts {
+    fn download(&self, client: &Client, commit: &str) -> Result<Option<Download>> {
+        let token = std::env::var(&self.token_env).with_context(|| {
+            format!(
+                "Downloading GitHub Actions artifacts needs a token in ${}",
+                self.token_env
+            )
+        })?;
+        let name = self.name.replace("{commit}", commit);
+        let url = format!(
+            "{}/repos/{}/actions/artifacts",
+            self.api_url.trim_end_matches('/'),
+            self.repo
+        );
+        debug!("Listing artifacts named {name} at {url}");
+        let list = client
+            .get(&url)
+            .query(&[("name", name.as_str()), ("per_page", "100")])
+            .bearer_auth(&token)
+            .header("Accept", "application/vnd.github+json")
+            .send()?
+            .error_for_status()
+            .with_context(|| format!("Failed to list the artifacts of {}", self.repo))?
+            .text()?;
+        let list: ArtifactList =
+            serde_json::from_str(&list).context("Invalid artifact list from GitHub")?;
+
+        let by_commit = !self.name.contains("{commit}");
+        let Some(artifact) = list.artifacts.into_iter().find(|artifact| {
+            artifact.name == name
+                && !artifact.expired
+                && (!by_commit
+                    || artifact
+                        .workflow_run
+                        .as_ref()
+                        .and_then(|run| run.head_sha.as_deref())
+                        == Some(commit))
+        }) else {
+            return Ok(None);
+        };
+        let sha256 = artifact
+            .digest
+            .as_deref()
+            .and_then(|digest| digest.strip_prefix("sha256:"))
+            .with_context(|| format!("Artifact {name} has no sha256 digest to verify"))?
+            .to_string();
+        Ok(Some(Download {
+            request: client
+                .get(&artifact.archive_download_url)
+                .bearer_auth(&token),
+            file_name: format!("{name}.zip"),
+            sha256,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::sync::{Arc, Mutex};
+    use tempfile::tempdir;
+
+    /// Serve fixed responses by path, recording the request heads
+    fn serve(routes: Vec<(String, Vec<u8>)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut reader = BufReader::new(stream.unwrap());
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let path = head.split_whitespace().nth(1).unwrap_or_default();
+                let path = path.split('?').next().unwrap_or_default().to_string();
+                recorded.lock().unwrap().push(head);
+                let stream = reader.get_mut();
+                match routes.iter().find(|(route, _)| *route == path) {
+                    Some((_, body)) => {
+                        let head = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        );
+                        stream.write_all(head.as_bytes()).unwrap();
+                        stream.write_all(body).unwrap();
+                    }
+                    None => stream
+                        .write_all(
+                            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+                              Connection: close\r\n\r\n",
+                        )
+                        .unwrap(),
+                }
+            }
+        });
+        (base, requests)
+    }
+
+    fn sha256(bytes: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        format!("{:x}", Sha256::digest(bytes))
+    }
+
+    #[test]
+    fn test_url_artifacts() {
+        let temp_dir = tempdir().unwrap();
+        let bin_dir = temp_dir.path().join("bin");
+        let (base, _) = serve(vec![
+            ("/abc/bitcoind".to_string(), b"bitcoind".to_vec()),
+            (
+                "/abc/bitcoind.sha256".to_string(),
+                format!("{}  bitcoind\n", sha256(b"bitcoind")).into_bytes(),
+            ),
+            ("/bad/bitcoind".to_string(), b"tampered".to_vec()),
+            (
+                "/bad/bitcoind.sha256".to_string(),
+                sha256(b"bitcoind").into_bytes(),
+            ),
+        ]);
+        let config: ArtifactConfig =
+            serde_yaml::from_str(&format!("url: {base}/{{commit}}/bitcoind")).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.binary, "bin/bitcoind");
+
+        assert!(config.fetch("abc", temp_dir.path(), &bin_dir).unwrap());
+        let binary = get_binary_path(&bin_dir, "abc");
+        assert_eq!(std::fs::read(&binary).unwrap(), b"bitcoind");
+        let metadata = BinaryMetadata::load(&binary).unwrap().unwrap();
+        assert_eq!(metadata.build_system, "artifact");
+        assert!(!metadata.patched);
+        assert!(!temp_dir.path().join("artifact-abc").exists());
+
+        // Unpublished commits are built locally, tampered ones fail
+        assert!(!config.fetch("def", temp_dir.path(), &bin_dir).unwrap());
+        let err = config.fetch("bad", temp_dir.path(), &bin_dir).unwrap_err();
+        assert!(err.to_string().contains("expected"), "{err}");
+        assert!(!get_binary_path(&bin_dir, "bad").exists());
+
+        let invalid = ArtifactConfig {
+            github: Some(GithubArtifacts {
+                repo: "bitcoin/bitcoin".to_string(),
+                name: "bitcoind".to_string(),
+                token_env: default_token_env(),
+                api_url: default_api_url(),
+            }),
+            ..config
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_github_artifacts() {
+        let temp_dir = tempdir().unwrap();
+        let bin_dir = temp_dir.path().join("bin");
+        // An artifact as uploaded by a workflow
+        let staging = temp_dir.path().join("staging");
+        std::fs::create_dir_all(staging.join("bin")).unwrap();
+        std::fs::write(staging.join("bin").join("bitcoind"), "bitcoind").unwrap();
+        let status = std::process::Command::new("zip")
+            .current_dir(&staging)
+            .args(["-q", "-r", "artifact.zip", "bin"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let archive = std::fs::read(staging.join("artifact.zip")).unwrap();
+
+        let (base, downloads) = serve(vec![("/download/1".to_string(), archive.clone())]);
+        let listing = serde_json::json!({
+            "artifacts": [
+                {
+                    "name": "bitcoind",
+                    "expired": true,
+                    "archive_download_url": format!("{base}/download/0"),
+                    "digest": null,
+                    "workflow_run": {"head_sha": "abc"},
+                },
+                {
+                    "name": "bitcoind",
+                    "expired": false,
+                    "archive_download_url": format!("{base}/download/1"),
+                    "digest": format!("sha256:{}", sha256(&archive)),
+                    "workflow_run": {"head_sha": "abc"},
+                },
+            ],
+        });
+        let (api, api_requests) = serve(vec![(
+            "/repos/bitcoin/bitcoin/actions/artifacts".to_string(),
+            listing.to_string().into_bytes(),
+        )]);
+
+        let config: ArtifactConfig = serde_yaml::from_str(&format!(
+            "github:\n  repo: bitcoin/bitcoin\n  name: bitcoind\n  \
+             token_env: BENCHKIT_TEST_ARTIFACT_TOKEN\n  api_url: {api}\n"
+        ))
+        .unwrap();
+        config.validate().unwrap();
+        let err = config.fetch("abc", temp_dir.path(), &bin_dir).unwrap_err();
+        assert!(
+            err.to_string().contains("BENCHKIT_TEST_ARTIFACT_TOKEN"),
+            "{err}"
+        );
+
+        std::env::set_var("BENCHKIT_TEST_ARTIFACT_TOKEN", "secret");
+        // Artifacts of runs for other commits are ignored
+        assert!(!config.fetch("def", temp_dir.path(), &bin_dir).unwrap());
+        assert!(config.fetch("abc", temp_dir.path(), &bin_dir).unwrap());
+        let binary = get_binary_path(&bin_dir, "abc");
+        assert_eq!(std::fs::read(&binary).unwrap(), b"bitcoind");
+
+        let api_requests = api_requests.lock().unwrap();
+        assert!(api_requests[0].contains("name=bitcoind"));
+        assert!(api_requests[0]
+            .to_lowercase()
+            .contains("authorization: bearer secret"));
+        let downloads = downloads.lock().unwrap();
+        assert_eq!(downloads.len(), 1);
+        assert!(downloads[0].starts_with("GET /download/1"));
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::benchmarks::{sha256_file, RunOverrides, OVERRIDES_FILE};
+use crate::job::{decode_hex, encode_hex, parse_public_key};
+use crate::logging::LOG_FILE_NAME;
+use crate::system_info::{SystemInfo, SYSTEM_INFO_FILE};
+
+/// File in the output directory listing the hashes of the files it attests to
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Signature over the manifest
+pub const SIGNATURE_FILE: &str = "manifest.sig";
+
+/// Prefix of signed messages, so a result signature can't pass as a job signature
+const SIGNING_DOMAIN: &[u8] = b"benchkit-results\n";
+
+/// What a signed output directory contains, and where it was produced
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub benchkit_version: String,
+    /// Unix timestamp of when the manifest was written
+    pub created_at: u64,
+    /// Fingerprint of the machine the results were produced on, from `system_info.json`
+    pub machine_fingerprint: Option<String>,
+    /// Configuration overridden on the command line, from `overrides.json`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overrides: Option<RunOverrides>,
+    /// SHA256 of each file covered, by file name
+    pub files: BTreeMap<String, String>,
+}
+
+/// Ed25519 signature of a manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestSignature {
+    /// Hex-encoded public key of the signer
+    pub public_key: String,
+    /// Hex-encoded signature
+    pub signature: String,
+}
+
+impl Manifest {
+    /// Hash the top-level files of an output directory
+    ///
+    /// Per-run artifacts in subdirectories, and the log which is still written to after
+    /// signing, aren't covered.
+    pub fn create(out_dir: &Path) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for entry in std::fs::read_dir(out_dir)
+            .with_context(|| format!("Failed to read {}", out_dir.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let covered = entry.file_type()?.is_file()
+                && name != MANIFEST_FILE
+                && name != SIGNATURE_FILE
+                && !name.starts_with(LOG_FILE_NAME);
+            if covered {
+                files.insert(name, sha256_file(&entry.path())?);
+            }
+        }
+        if !files.contains_key("results.json") {
+            anyhow::bail!("{} has no results.json to sign", out_dir.display());
+        }
+
+        let system_info = out_dir.join(SYSTEM_INFO_FILE);
+        let machine_fingerprint = system_info
+            .exists()
+            .then(|| SystemInfo::load(&system_info))
+            .transpose()?
+            .map(|info| info.fingerprint);
+        let overrides_path = out_dir.join(OVERRIDES_FILE);
+        let overrides = overrides_path
+            .exists()
+            .then(|| -> Result<RunOverrides> {
+                serde_json::from_slice(&std::fs::read(&overrides_path)?)
+                    .with_context(|| format!("Failed to parse {}", overrides_path.display()))
+            })
+            .transpose()?;
+        Ok(Self {
+            benchkit_version: env!("CARGO_PKG_VERSION").to_string(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            machine_fingerprint,
+            overrides,
+            files,
+        })
+    }
+}
+
+/// Read a signing key stored as 64 hex characters, e.g. made with `openssl rand -hex 32`
+pub fn load_signing_key(path: &Path) -> Result<SigningKey> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read signing key {}", path.display()))?;
+    let seed: [u8; 32] = decode_hex(contents.trim())?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Signing key {} must be 32 bytes", path.display()))?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+fn signing_message(manifest: &[u8]) -> Vec<u8> {
+    [SIGNING_DOMAIN, manifest].concat()
+}
+
+/// Write a manifest of an output directory and sign it
+pub fn sign_output(out_dir: &Path, key: &SigningKey) -> Result<Manifest> {
+    let manifest = Manifest::create(out_dir)?;
+    let json = serde_json::to_string_pretty(&manifest)?;
+    let signature = ManifestSignature {
+        public_key: encode_hex(key.verifying_key().as_bytes()),
+        signature: encode_hex(&key.sign(&signing_message(json.as_bytes())).to_bytes()),
+    };
+    std::fs::write(out_dir.join(MANIFEST_FILE), &json)?;
+    std::fs::write(
+        out_dir.join(SIGNATURE_FILE),
+        serde_json::to_string_pretty(&signature)?,
+    )?;
+    info!(
+        "Signed {} files in {} with key {}",
+        manifest.files.len(),
+        out_dir.display(),
+        signature.public_key
+    );
+    Ok(manifest)
+}
+
+/// Check an output directory's manifest is signed, by one of `trusted_keys` if any are given,
+/// and that the files it lists are unchanged
+pub fn verify_output(
+    out_dir: &Path,
+    trusted_keys: &[VerifyingKey],
+) -> Result<(Manifest, ManifestSignature)> {
+    let manifest_path = out_dir.join(MANIFEST_FILE);
+    let json = std::fs::read(&manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    let signature_path = out_dir.join(SIGNATURE_FILE);
+    let signature: ManifestSignature = serde_json::from_slice(
+        &std::fs::read(&signature_path)
+            .with_context(|| format!("Failed to read {}", signature_path.display()))?,
+    )
+    .with_context(|| format!("Failed to parse {}", signature_path.display()))?;
+
+    let public_key = parse_public_key(&signature.public_key)?;
+    if !trusted_keys.is_empty() && !trusted_keys.contains(&public_key) {
+        anyhow::bail!(
+            "{} is signed by untrusted key {}",
+            out_dir.display(),
+            signature.public_key
+        );
+    }
+    let bytes = decode_hex(&signature.signature)?;
+    let sig = Signature::from_slice(&bytes).context("Invalid manifest signature")?;
+    public_key
+        .verify(&signing_message(&json), &sig)
+        .with_context(|| format!("Signature verification failed for {}", out_dir.display()))?;
+
+    let manifest: Manifest = serde_json::from_slice(&json)
+        .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+    for (name, expected) in &manifest.files {
+        let actual = sha256_file(&out_dir.join(name))?;
+        if &actual != expected {
+            anyhow::bail!("{name} was modified after it was signed");
+        }
+    }
+    Ok((manifest, signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sign_and_verify_output() {
+        let out_dir = tempdir().unwrap();
+        let dir = out_dir.path();
+        let key_path = dir.join("key");
+        std::fs::write(&key_path, format!("{}\n", "07".repeat(32))).unwrap();
+        let key = load_signing_key(&key_path).unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+
+        // Nothing to sign without results
+        assert!(sign_output(dir, &key).is_err());
+
+        std::fs::write(dir.join("results.json"), r#"{"results": []}"#).unwrap();
+        std::fs::write(dir.join(LOG_FILE_NAME), "log").unwrap();
+        std::fs::create_dir(dir.join("abc123")).unwrap();
+        let manifest = sign_output(dir, &key).unwrap();
+        assert_eq!(manifest.files.keys().collect::<Vec<_>>(), ["results.json"]);
+        assert_eq!(manifest.overrides, None);
+
+        let (verified, signer) = verify_output(dir, &[key.verifying_key()]).unwrap();
+        assert_eq!(verified, manifest);
+        assert_eq!(
+            signer.public_key,
+            encode_hex(key.verifying_key().as_bytes())
+        );
+        // The log may keep growing
+        std::fs::write(dir.join(LOG_FILE_NAME), "more log").unwrap();
+        assert!(verify_output(dir, &[]).is_ok());
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(verify_output(dir, &[other]).is_err());
+
+        std::fs::write(dir.join("results.json"), r#"{"results": [1]}"#).unwrap();
+        let err = verify_output(dir, &[]).unwrap_err().to_string();
+        assert!(err.contains("results.json was modified"), "{err}");
+
+        // Overrides given on the command line are recorded
+        std::fs::write(dir.join(OVERRIDES_FILE), r#"{"runs": 1}"#).unwrap();
+        let manifest = sign_output(dir, &key).unwrap();
+        assert_eq!(manifest.overrides.unwrap().runs, Some(1));
+        assert!(manifest.files.contains_key(OVERRIDES_FILE));
+    }
+}
@@ -1,18 +1,53 @@
 use anyhow::{Context, Result};
 use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::os::unix::process::ExitStatusExt;
-use std::path::PathBuf;
-use std::time::Instant;
-
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::benchmarks::checkpoint::{
+    archive_debug_log, CheckpointConfig, CheckpointState, CHECKPOINT_FILE, CHECKPOINT_GRACE_PERIOD,
+};
+use crate::benchmarks::core_dump::{crash_signal, CoreCollector, Crash};
 use crate::benchmarks::export::ResultExporter;
-use crate::benchmarks::hook_runner::{HookArgs, HookRunner, HookStage};
+use crate::benchmarks::failure_bundle::FailureBundle;
+use crate::benchmarks::feeder::{
+    handshake, parse_peer_connections, FeederIdentity, PeerConnection, PeerVersion,
+};
+use crate::benchmarks::history::history_label;
+use crate::benchmarks::hook_runner::{process_env, HookArgs, HookRunner, HookStage, NodePorts};
+use crate::benchmarks::live::{Activity, LiveStatus, PartialResult};
 use crate::benchmarks::log_monitor::LogMonitor;
+use crate::benchmarks::node_logging::{has_console_flag, NodeLogging};
+use crate::benchmarks::out_layout::{OutDirLayout, DEFAULT_OUT_DIR_TEMPLATE};
+use crate::benchmarks::overrides::CommandOverride;
 use crate::benchmarks::parameters::{ParameterList, ParameterMatrix, ParameterUtils};
-use crate::benchmarks::perf::PerfInstrumentor;
+use crate::benchmarks::perf::{archive_perf_data, PerfAttach, PerfInstrumentor};
+use crate::benchmarks::profiler::analysis::ProfileMetrics;
 use crate::benchmarks::profiler::{ProfileResult, Profiler};
-use crate::benchmarks::results::{BenchmarkResult, InstrumentationType, ResultAnalyzer, RunResult};
-use crate::command::CommandExecutor;
+use crate::benchmarks::requirements::parse_version;
+use crate::benchmarks::result_cache::ResultCache;
+use crate::benchmarks::results::{
+    BenchmarkResult, InstrumentationType, ResultAnalyzer, RunFailure, RunResult,
+};
+use crate::benchmarks::topology::{start_order, NodeRole, RunningNodes};
+use crate::benchmarks::usdt::{check_probes, validate_bpftrace_available, Tracepoint, UsdtTracer};
+use crate::benchmarks::utils::parse_node_settings;
+use crate::benchmarks::zmq::{ZmqListener, ZmqProgress};
+use crate::cgroup::Cgroup;
+use crate::command::{exit_status_from_code, CommandExecutor, ProcessPriority};
+use crate::cpu_limit::{self, CpuLimits};
+use crate::disk_guard::DiskGuard;
+use crate::error::BenchkitError;
+use crate::memory::{MemoryLimit, SwapCounters};
+use crate::path_utils;
+use crate::process;
+use crate::quiescence::QuietGate;
+use crate::telemetry;
+use crate::timing::Stopwatch;
+use crate::types::{CacheState, DurationMs, Network};
 
 /// Low-level benchmark executor that handles the actual command execution and measurement
 /// It is created and configured by the Runner for each benchmark, and focuses
@@ -37,6 +72,177 @@ pub struct BenchmarkRunner {
     stop_on_log_pattern: Option<String>,
     /// Whether to enable perf instrumentation
     enable_perf_instrumentation: bool,
+    /// Bundle the symbols of perf.data files after perf-instrumented runs
+    perf_archive: bool,
+    /// Attach perf to the running command after startup instead of starting the command under it
+    perf_attach: Option<PerfAttach>,
+    /// USDT tracepoints of the binary to collect during each run
+    tracepoints: Vec<Tracepoint>,
+    /// Configured labels of commits, keyed by short commit hash
+    commit_labels: HashMap<String, String>,
+    /// Scheduling and OOM killer priority of the benchmark command
+    priority: Option<ProcessPriority>,
+    /// Thresholds the system must be under before each run starts
+    quiet_gate: Option<QuietGate>,
+    /// Keep adding runs until the mean is precise enough
+    adaptive_runs: Option<AdaptiveRuns>,
+    /// Untimed runs before the measured ones
+    warmup: Warmup,
+    /// Memory the benchmark command may use
+    memory_limit: Option<MemoryLimit>,
+    /// CPU bandwidth and `-par` scenarios, selected by the `cpu_quota` and `par` parameters
+    cpu_limits: Option<CpuLimits>,
+    /// Group enforcing `memory_limit` and the CPU quotas, if either is enabled
+    cgroup: Option<Cgroup>,
+    /// Record failing runs and hooks instead of aborting the parameter matrix
+    continue_on_failure: bool,
+    /// Stop running a parameter combination once one of its runs failed
+    skip_runs_after_failure: bool,
+    /// Abort parameter combinations which are obviously broken
+    fail_fast: Option<FailFast>,
+    /// Mean of each parameter combination the last time it was recorded, by history label
+    historical_means: HashMap<String, DurationMs>,
+    /// Environment variables of the benchmark command, from the benchmark's `env`
+    env: HashMap<String, String>,
+    /// Stop and restart the command at regular intervals, recording its progress
+    checkpoint: Option<CheckpointConfig>,
+    /// Free space the datadir and output filesystems must keep while the command runs
+    disk_guard: Option<DiskGuard>,
+    /// Process group of the command last launched, for the disk guard to stop
+    command_group: Arc<AtomicU32>,
+    /// Changes to the command for some commits or versions
+    command_overrides: Vec<CommandOverride>,
+    /// Count the blocks the node connects from its ZMQ notifications
+    zmq_progress: Option<ZmqProgress>,
+    /// Debug categories and timestamps the node logs with
+    node_logging: Option<NodeLogging>,
+    /// Nodes started alongside the benchmarked one for each run
+    nodes: Vec<NodeRole>,
+    /// Earlier results of combinations to reuse instead of running them again
+    result_cache: Option<ResultCache>,
+    /// Connect address of the node the runs sync from, whose identity is recorded
+    feeder: Option<String>,
+    /// Collects the core dumps of crashed runs, if the command may dump core
+    core_collector: Option<CoreCollector>,
+    /// Layout of the runs' output directories within `out_dir`
+    out_layout: OutDirLayout,
+}
+
+/// Settings for adding runs until the confidence interval of the mean is narrow enough
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveRuns {
+    /// Target half-width of the 95% confidence interval of the mean, relative to the mean
+    pub target_relative_error: f64,
+    /// Maximum number of runs
+    pub max_runs: usize,
+}
+
+/// When to give up on a parameter combination and move on to the next one
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FailFast {
+    /// Abort when the first run takes this many times the mean recorded in the history
+    pub max_slowdown: Option<f64>,
+    /// Abort after this many runs in a row failed
+    pub max_consecutive_failures: Option<usize>,
+}
+
+impl FailFast {
+    pub fn validate(&self) -> Result<()> {
+        if self.max_slowdown.is_none() && self.max_consecutive_failures.is_none() {
+            anyhow::bail!("fail_fast needs max_slowdown or max_consecutive_failures");
+        }
+        if let Some(slowdown) = self.max_slowdown {
+            if !(slowdown.is_finite() && slowdown > 1.0) {
+                anyhow::bail!("fail_fast max_slowdown must be greater than 1, got {slowdown}");
+            }
+        }
+        if self.max_consecutive_failures == Some(0) {
+            anyhow::bail!("fail_fast max_consecutive_failures cannot be zero");
+        }
+        Ok(())
+    }
+
+    /// Why the remaining runs of a parameter combination should be skipped, if they should
+    ///
+    /// `first_run` is the duration of the combination's first uninstrumented run, once it
+    /// completed successfully.
+    fn abort_reason(
+        &self,
+        first_run: Option<DurationMs>,
+        historical_mean: Option<DurationMs>,
+        consecutive_failures: usize,
+    ) -> Option<String> {
+        if let Some(max) = self.max_consecutive_failures {
+            if consecutive_failures >= max {
+                return Some(format!("{consecutive_failures} runs in a row failed"));
+            }
+        }
+        match (self.max_slowdown, first_run, historical_mean) {
+            (Some(max), Some(duration), Some(mean))
+                if mean.0 > 0.0 && duration.0 > max * mean.0 =>
+            {
+                Some(format!(
+                    "the first run took {:.1}x the historical mean of {mean:.1} ms",
+                    duration / mean
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Untimed runs before the measured runs of each parameter combination
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Warmup {
+    /// Number of warmup runs
+    pub runs: usize,
+    /// Command template of warmup runs, the measured command if None
+    pub command: Option<String>,
+    /// Per-run hook stages to run around each warmup run
+    pub hooks: Vec<HookStage>,
+}
+
+/// Durations of the uninstrumented runs, which are the ones timings are compared on
+fn uninstrumented_durations(results: &[RunResult]) -> Vec<f64> {
+    results
+        .iter()
+        .filter(|r| r.instrumentation == InstrumentationType::Uninstrumented)
+        .map(|r| r.duration_ms.0)
+        .collect()
+}
+
+/// Read a child's output stream to the end on another thread
+fn read_to_end_in_thread<R: std::io::Read + Send + 'static>(
+    mut stream: R,
+) -> std::thread::JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Err(e) = stream.read_to_end(&mut buffer) {
+            debug!("Failed to read command output: {e}");
+        }
+        buffer
+    })
+}
+
+fn join_output(reader: std::thread::JoinHandle<Vec<u8>>) -> Vec<u8> {
+    reader.join().unwrap_or_default()
+}
+
+/// Number of no-op commands launched to measure process spawn overhead
+const SPAWN_CALIBRATION_RUNS: usize = 5;
+
+/// How often a checkpointed command is checked for having exited
+const CHECKPOINT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Number of stderr lines kept for failed runs
+const STDERR_TAIL_LINES: usize = 20;
+
+/// The last lines of a command's stderr
+fn stderr_tail(stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr.trim_end().lines().collect();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
 }
 
 /// Builder for BenchmarkRunner
@@ -50,6 +256,31 @@ pub struct BenchmarkRunnerBuilder {
     benchmark_cores: Option<String>,
     stop_on_log_pattern: Option<String>,
     enable_perf_instrumentation: bool,
+    perf_archive: bool,
+    perf_attach: Option<PerfAttach>,
+    tracepoints: Vec<Tracepoint>,
+    commit_labels: HashMap<String, String>,
+    priority: Option<ProcessPriority>,
+    quiet_gate: Option<QuietGate>,
+    adaptive_runs: Option<AdaptiveRuns>,
+    warmup: Warmup,
+    memory_limit: Option<MemoryLimit>,
+    cpu_limits: Option<CpuLimits>,
+    continue_on_failure: bool,
+    skip_runs_after_failure: bool,
+    fail_fast: Option<FailFast>,
+    historical_means: HashMap<String, DurationMs>,
+    env: HashMap<String, String>,
+    checkpoint: Option<CheckpointConfig>,
+    disk_guard: Option<DiskGuard>,
+    command_overrides: Vec<CommandOverride>,
+    zmq_progress: Option<ZmqProgress>,
+    node_logging: Option<NodeLogging>,
+    nodes: Vec<NodeRole>,
+    result_cache: Option<ResultCache>,
+    feeder: Option<String>,
+    core_dumps: bool,
+    out_dir_template: Option<String>,
 }
 
 impl BenchmarkRunnerBuilder {
@@ -65,6 +296,31 @@ impl BenchmarkRunnerBuilder {
             benchmark_cores: None,
             stop_on_log_pattern: None,
             enable_perf_instrumentation: false,
+            perf_archive: false,
+            perf_attach: None,
+            tracepoints: Vec::new(),
+            commit_labels: HashMap::new(),
+            priority: None,
+            quiet_gate: None,
+            adaptive_runs: None,
+            warmup: Warmup::default(),
+            memory_limit: None,
+            cpu_limits: None,
+            continue_on_failure: false,
+            skip_runs_after_failure: false,
+            fail_fast: None,
+            historical_means: HashMap::new(),
+            env: HashMap::new(),
+            checkpoint: None,
+            disk_guard: None,
+            command_overrides: Vec::new(),
+            zmq_progress: None,
+            node_logging: None,
+            nodes: Vec::new(),
+            result_cache: None,
+            feeder: None,
+            core_dumps: false,
+            out_dir_template: None,
         }
     }
 
@@ -107,6 +363,151 @@ impl BenchmarkRunnerBuilder {
         self
     }
 
+    /// Bundle the symbols perf.data refers to after each perf-instrumented run
+    pub fn perf_archive(mut self, enable: bool) -> Self {
+        self.perf_archive = enable;
+        self
+    }
+
+    /// Attach perf to the running command once startup is over
+    pub fn perf_attach(mut self, attach: Option<PerfAttach>) -> Self {
+        self.perf_attach = attach;
+        self
+    }
+
+    /// Collect these USDT tracepoints of the binary during each run
+    pub fn tracepoints(mut self, tracepoints: Vec<Tracepoint>) -> Self {
+        self.tracepoints = tracepoints;
+        self
+    }
+
+    /// Set display labels for commits, keyed by short commit hash
+    pub fn commit_labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.commit_labels = labels;
+        self
+    }
+
+    /// Set environment variables of the benchmark command
+    pub fn env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Set the scheduling and OOM killer priority of the benchmark command
+    pub fn priority(mut self, priority: Option<ProcessPriority>) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Wait for the system to be quiet before each run
+    pub fn quiet_gate(mut self, quiet_gate: Option<QuietGate>) -> Self {
+        self.quiet_gate = quiet_gate;
+        self
+    }
+
+    /// Add runs beyond the configured count until the mean is within a target relative error
+    pub fn adaptive_runs(mut self, adaptive_runs: Option<AdaptiveRuns>) -> Self {
+        self.adaptive_runs = adaptive_runs;
+        self
+    }
+
+    /// Run untimed warmup runs before the measured runs of each parameter combination
+    pub fn warmup(mut self, warmup: Warmup) -> Self {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Check, and optionally enforce, that the command's dbcache fits in memory
+    pub fn memory_limit(mut self, memory_limit: Option<MemoryLimit>) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    pub fn cpu_limits(mut self, cpu_limits: Option<CpuLimits>) -> Self {
+        self.cpu_limits = cpu_limits;
+        self
+    }
+
+    /// Carry on past failing runs and hooks, optionally skipping the rest of a parameter
+    /// combination's runs once one failed
+    pub fn continue_on_failure(
+        mut self,
+        continue_on_failure: bool,
+        skip_runs_after_failure: bool,
+    ) -> Self {
+        self.continue_on_failure = continue_on_failure;
+        self.skip_runs_after_failure = skip_runs_after_failure;
+        self
+    }
+
+    /// Abort parameter combinations which are obviously broken, comparing first runs with the
+    /// means recorded in the history, keyed by history label
+    pub fn fail_fast(
+        mut self,
+        fail_fast: Option<FailFast>,
+        historical_means: HashMap<String, DurationMs>,
+    ) -> Self {
+        self.fail_fast = fail_fast;
+        self.historical_means = historical_means;
+        self
+    }
+
+    /// Stop and restart the command at regular intervals, so long runs can be resumed
+    pub fn checkpoint(mut self, checkpoint: Option<CheckpointConfig>) -> Self {
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    /// Stop runs whose datadir or output filesystem is about to fill up
+    pub fn disk_guard(mut self, disk_guard: Option<DiskGuard>) -> Self {
+        self.disk_guard = disk_guard;
+        self
+    }
+
+    pub fn command_overrides(mut self, command_overrides: Vec<CommandOverride>) -> Self {
+        self.command_overrides = command_overrides;
+        self
+    }
+
+    pub fn zmq_progress(mut self, zmq_progress: Option<ZmqProgress>) -> Self {
+        self.zmq_progress = zmq_progress;
+        self
+    }
+
+    pub fn node_logging(mut self, node_logging: Option<NodeLogging>) -> Self {
+        self.node_logging = node_logging;
+        self
+    }
+
+    pub fn nodes(mut self, nodes: Vec<NodeRole>) -> Self {
+        self.nodes = nodes;
+        self
+    }
+
+    pub fn result_cache(mut self, result_cache: Option<ResultCache>) -> Self {
+        self.result_cache = result_cache;
+        self
+    }
+
+    /// Let the command dump core when it crashes, and collect its core dump
+    pub fn core_dumps(mut self, enable: bool) -> Self {
+        self.core_dumps = enable;
+        self
+    }
+
+    /// Lay out the runs' output directories with this template rather than
+    /// `DEFAULT_OUT_DIR_TEMPLATE`
+    pub fn out_dir_template(mut self, template: Option<String>) -> Self {
+        self.out_dir_template = template;
+        self
+    }
+
+    /// Record the identity of the node at this connect address with the results
+    pub fn feeder(mut self, feeder: Option<String>) -> Self {
+        self.feeder = feeder;
+        self
+    }
+
     /// Build the BenchmarkRunner, validating parameters if needed
     pub fn build(self) -> Result<BenchmarkRunner> {
         // Validate configuration
@@ -119,6 +520,29 @@ impl BenchmarkRunnerBuilder {
             PerfInstrumentor::validate_perf_available()
                 .context("perf instrumentation requested but perf is not available")?;
         }
+        if !self.tracepoints.is_empty() {
+            validate_bpftrace_available()?;
+        }
+
+        let mut controllers = Vec::new();
+        if self
+            .memory_limit
+            .as_ref()
+            .is_some_and(|limit| limit.enforce)
+        {
+            controllers.push("memory");
+        }
+        if self
+            .cpu_limits
+            .as_ref()
+            .is_some_and(|limits| !limits.quotas.is_empty())
+        {
+            controllers.push("cpu");
+        }
+        let cgroup = match controllers.is_empty() {
+            true => None,
+            false => Some(Cgroup::create(&controllers)?),
+        };
 
         // Create the BenchmarkRunner
         Ok(BenchmarkRunner {
@@ -131,6 +555,37 @@ impl BenchmarkRunnerBuilder {
             benchmark_cores: self.benchmark_cores,
             stop_on_log_pattern: self.stop_on_log_pattern,
             enable_perf_instrumentation: self.enable_perf_instrumentation,
+            perf_archive: self.perf_archive,
+            perf_attach: self.perf_attach,
+            tracepoints: self.tracepoints,
+            commit_labels: self.commit_labels,
+            priority: self.priority,
+            quiet_gate: self.quiet_gate,
+            adaptive_runs: self.adaptive_runs,
+            warmup: self.warmup,
+            memory_limit: self.memory_limit,
+            cpu_limits: self.cpu_limits,
+            cgroup,
+            continue_on_failure: self.continue_on_failure,
+            skip_runs_after_failure: self.skip_runs_after_failure,
+            fail_fast: self.fail_fast,
+            historical_means: self.historical_means,
+            env: self.env,
+            checkpoint: self.checkpoint,
+            disk_guard: self.disk_guard,
+            command_overrides: self.command_overrides,
+            zmq_progress: self.zmq_progress,
+            node_logging: self.node_logging,
+            nodes: self.nodes,
+            result_cache: self.result_cache,
+            feeder: self.feeder,
+            core_collector: self.core_dumps.then(CoreCollector::new).transpose()?,
+            out_layout: OutDirLayout::new(
+                self.out_dir_template
+                    .as_deref()
+                    .unwrap_or(DEFAULT_OUT_DIR_TEMPLATE),
+            )?,
+            command_group: Arc::new(AtomicU32::new(0)),
         })
     }
 }
@@ -163,14 +618,30 @@ impl BenchmarkRunner {
     ) -> Result<BenchmarkResult> {
         let commit = &hook_args.commit;
 
-        let total_runs = if self.enable_perf_instrumentation {
-            runs * 2 // Each benchmark gets both uninstrumented and instrumented runs
+        let runs_per_iteration = if self.enable_perf_instrumentation {
+            2 // Each benchmark gets both uninstrumented and instrumented runs
         } else {
-            runs
+            1
+        };
+        let max_runs = match &self.adaptive_runs {
+            Some(adaptive) => adaptive.max_runs.max(runs),
+            None => runs,
         };
 
+        let commit_display = match self.commit_labels.get(commit) {
+            Some(label) => format!("{commit} ({label})"),
+            None => commit.clone(),
+        };
+        let version = Self::binary_version(&hook_args.binary);
+        let overridden = self.override_command(command, commit, version.as_deref());
+        let command = overridden.as_str();
+        let run_count = if max_runs > runs {
+            format!("{runs}-{max_runs}")
+        } else {
+            runs.to_string()
+        };
         info!(
-            "Running benchmark: {command} for {runs} runs (commit: {commit}){}",
+            "Running benchmark: {command} for {run_count} runs (commit: {commit_display}){}",
             if self.enable_perf_instrumentation {
                 " with perf instrumentation"
             } else {
@@ -178,50 +649,416 @@ impl BenchmarkRunner {
             }
         );
 
-        // Run the setup script once before all benchmark runs
-        self.hook_runner.run_hook(HookStage::Setup, hook_args)?;
-        let mut results = Vec::with_capacity(total_runs);
+        // Fail before any run when the binary wasn't built with the tracepoints
+        if !self.tracepoints.is_empty() {
+            check_probes(
+                Path::new(&hook_args.binary.replace("{commit}", commit)),
+                &self.tracepoints,
+            )?;
+        }
 
-        // Execute the benchmark runs
-        if self.enable_perf_instrumentation {
-            // Run each benchmark twice: uninstrumented then instrumented
-            for i in 0..runs {
-                // Run uninstrumented version first
-                let result =
-                    self.execute_single_run(command, i * 2, commit, params, hook_args, false)?;
-                results.push(result);
-
-                // Run instrumented version second
-                let result =
-                    self.execute_single_run(command, i * 2 + 1, commit, params, hook_args, true)?;
-                results.push(result);
-            }
+        // Run the setup script once before all benchmark runs. When resuming, it would remove the
+        // datadirs of interrupted runs.
+        let resuming = self.has_checkpoints(hook_args, params);
+        let mut failures = Vec::new();
+        let setup = if resuming {
+            info!("Resuming the checkpointed runs of {commit_display}, skipping setup and warmups");
+            Ok(())
         } else {
-            // Run normally without instrumentation
-            for i in 0..runs {
-                let result =
-                    self.execute_single_run(command, i, commit, params, hook_args, false)?;
-                results.push(result);
+            self.hook_runner
+                .run_hook(HookStage::Setup, hook_args)
+                .and_then(|()| self.run_warmups(command, hook_args, params))
+        };
+        let mut stopped = self.tolerate(setup, None, &mut failures)?.is_none();
+        let spawn_overhead_ms = if stopped {
+            None
+        } else {
+            self.measure_spawn_overhead(params)
+        };
+        let feeder_before = if stopped {
+            None
+        } else {
+            self.feeder_version(hook_args.network)
+        };
+        let mut results = Vec::with_capacity(runs * runs_per_iteration);
+        let historical_mean = self
+            .historical_means
+            .get(&history_label(
+                params,
+                self.commit_labels.get(commit).map(String::as_str),
+            ))
+            .copied();
+        let mut consecutive_failures = 0;
+        let mut executed = 0;
+
+        // Execute the benchmark runs, adding more past `runs` until the target error is reached
+        let mut completed = 0;
+        while !stopped
+            && (completed < runs || (completed < max_runs && self.needs_more_runs(&results)))
+        {
+            // With perf, each benchmark runs twice: uninstrumented then instrumented
+            let instrumented: &[bool] = if self.enable_perf_instrumentation {
+                &[false, true]
+            } else {
+                &[false]
+            };
+            for (i, &use_perf) in instrumented.iter().enumerate() {
+                let iteration = completed * runs_per_iteration + i;
+                let mut error_bundle = None;
+                let run = match self.finished_run(hook_args, params, iteration)? {
+                    Some(result) => Ok(result),
+                    None => {
+                        executed += 1;
+                        let mut span = telemetry::span("run");
+                        span.attr("benchkit.commit", commit)
+                            .attr("benchkit.iteration", iteration)
+                            .attr("benchkit.instrumented", use_perf);
+                        let log_offset = FailureBundle::log_offset(&self.out_dir);
+                        let mut run = self.execute_single_run(
+                            command, iteration, commit, params, hook_args, use_perf,
+                        );
+                        error_bundle =
+                            self.bundle_failure(&mut run, params, hook_args, iteration, log_offset);
+                        if let Ok(result) = &run {
+                            if let Err(e) = PartialResult::append(&self.out_dir, hook_args, result)
+                            {
+                                debug!("Failed to append to the partial results: {e:#}");
+                            }
+                            span.attr("benchkit.duration_ms", result.duration_ms.0)
+                                .attr("benchkit.exit_code", result.exit_code);
+                            if result.exit_code != 0 {
+                                span.error(format!("exited with {}", result.exit_code));
+                            }
+                        }
+                        span.record(run)
+                    }
+                };
+                let mut first_run = None;
+                let failed = match self.tolerate(run, Some(iteration), &mut failures)? {
+                    Some(result) => {
+                        let failed = result.exit_code != 0;
+                        if iteration == 0 && !failed {
+                            first_run = Some(result.duration_ms);
+                        }
+                        results.push(result);
+                        failed
+                    }
+                    None => {
+                        if let Some(failure) = failures.last_mut() {
+                            failure.failure_bundle = error_bundle;
+                        }
+                        true
+                    }
+                };
+                consecutive_failures = if failed { consecutive_failures + 1 } else { 0 };
+                if failed && self.skip_runs_after_failure {
+                    warn!("Skipping the remaining runs of {commit_display} after run {iteration} failed");
+                    stopped = true;
+                    break;
+                }
+                let abort = self.fail_fast.as_ref().and_then(|fail_fast| {
+                    fail_fast.abort_reason(first_run, historical_mean, consecutive_failures)
+                });
+                if let Some(reason) = abort {
+                    warn!("Aborting the remaining runs of {commit_display}: {reason}");
+                    // Of the combination rather than the run, whose failure is recorded already
+                    failures.push(RunFailure {
+                        iteration: None,
+                        stage: None,
+                        message: format!("Aborted: {reason}"),
+                        failure_bundle: None,
+                    });
+                    stopped = true;
+                    break;
+                }
             }
+            completed += 1;
         }
 
-        // Run the cleanup script once after all benchmark runs
-        self.hook_runner.run_hook(HookStage::Cleanup, hook_args)?;
+        if let Some(adaptive) = &self.adaptive_runs {
+            let error = ResultAnalyzer::relative_error(&uninstrumented_durations(&results));
+            let error = error.map_or("unknown".to_string(), |e| format!("{:.2}%", e * 100.0));
+            if self.needs_more_runs(&results) {
+                warn!(
+                    "Relative error {error} still above the target of {:.2}% after {completed} runs",
+                    adaptive.target_relative_error * 100.0
+                );
+            } else {
+                info!("Reached a relative error of {error} after {completed} runs");
+            }
+        }
 
-        // Calculate statistics
-        let summary = ResultAnalyzer::calculate_summary(&results);
+        // Run the cleanup script once after all benchmark runs, unless they all finished before
+        // resuming, as it could remove the datadir of an interrupted run of another combination
+        if resuming && executed == 0 {
+            debug!("All runs of {commit_display} finished before resuming, skipping cleanup");
+        } else {
+            let cleanup = self.hook_runner.run_hook(HookStage::Cleanup, hook_args);
+            self.tolerate(cleanup, None, &mut failures)?;
+        }
+
+        // Calculate statistics, separately for perf-instrumented runs
+        let (summary, instrumented_summary) = ResultAnalyzer::calculate_summaries(&results);
+        let feeder = self.feeder.as_deref().map(|address| {
+            let connections: Vec<&PeerConnection> =
+                results.iter().flat_map(|run| &run.peers).collect();
+            let feeder_after = self.feeder_version(hook_args.network);
+            FeederIdentity::new(address, feeder_before, feeder_after, &connections)
+        });
 
         // Create the benchmark result
         let benchmark_result = BenchmarkResult {
             command: command.to_string(),
             parameters: params.clone(), // Copy the parameters into the result
+            commit_label: self.commit_labels.get(commit).cloned(),
             runs: results,
+            runs_needed: self.adaptive_runs.as_ref().map(|_| completed),
+            version,
+            failures,
+            spawn_overhead_ms,
+            cached_from: None,
+            out_dir: Some(
+                self.out_layout
+                    .combination_dir(&hook_args.benchmark, commit, params),
+            ),
+            feeder,
             summary,
+            instrumented_summary,
         };
+        if let (Some(overhead), Some(fraction)) = (
+            benchmark_result.spawn_overhead_ms,
+            benchmark_result.spawn_overhead(),
+        ) {
+            info!(
+                "Process spawn overhead of {overhead:.2} ms is {:.2}% of the mean",
+                fraction * 100.0
+            );
+        }
+        if let Some(overhead) = benchmark_result.perf_overhead() {
+            info!(
+                "perf instrumentation added {:.1}% to the mean",
+                overhead * 100.0
+            );
+        }
 
         Ok(benchmark_result)
     }
 
+    /// Run the configured warmup runs, without timing or recording them
+    fn run_warmups(
+        &self,
+        command: &str,
+        hook_args: &HookArgs,
+        params: &HashMap<String, String>,
+    ) -> Result<()> {
+        if self.warmup.runs == 0 {
+            return Ok(());
+        }
+        let command = match (&self.warmup.command, &self.parameter_matrix) {
+            (Some(template), Some(matrix)) => matrix.apply_parameters(template, params),
+            (Some(template), None) => template.clone(),
+            (None, _) => command.to_string(),
+        };
+        let command = self.console_command(&self.logging_command(&command));
+
+        for iteration in 0..self.warmup.runs {
+            info!("Warmup run {}/{}", iteration + 1, self.warmup.runs);
+            // Keep debug.logs moved by the conclude hook apart from those of measured runs. All
+            // warmups share a datadir, so without hooks each one continues from the last.
+            let params_dir = format!("{}/warmup", ParameterUtils::params_to_dirname(params));
+            let warmup_args = HookArgs {
+                iteration,
+                datadir: hook_args.run_dir(&params_dir),
+                params_dir,
+                run_out_dir: self
+                    .combination_out_dir(hook_args, params)
+                    .join("warmup")
+                    .join(iteration.to_string()),
+                ..hook_args.clone()
+            };
+            if self.warmup.hooks.contains(&HookStage::Prepare) {
+                self.hook_runner
+                    .run_hook(HookStage::Prepare, &warmup_args)?;
+            }
+            path_utils::ensure_directory(&warmup_args.datadir)?;
+            let nodes = self.start_nodes(&warmup_args, params)?;
+            LiveStatus::record(Activity::Warmup, &warmup_args);
+            let output = self.execute_monitored(&warmup_args.substitute(&command), params)?;
+            nodes.stop(None)?;
+            if !output.status.success() {
+                warn!(
+                    "Warmup run {} failed with status {}",
+                    iteration + 1,
+                    output.status.code().unwrap_or(-1)
+                );
+            }
+            if self.warmup.hooks.contains(&HookStage::Conclude) {
+                self.hook_runner
+                    .run_hook(HookStage::Conclude, &warmup_args)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Output directory of the runs of a parameter combination
+    fn combination_out_dir(
+        &self,
+        hook_args: &HookArgs,
+        params: &HashMap<String, String>,
+    ) -> PathBuf {
+        self.out_dir.join(self.out_layout.combination_dir(
+            &hook_args.benchmark,
+            &hook_args.commit,
+            params,
+        ))
+    }
+
+    /// Args of a run of a parameter combination, with its own datadir and output directory
+    fn run_args(
+        &self,
+        hook_args: &HookArgs,
+        params: &HashMap<String, String>,
+        iteration: usize,
+    ) -> HookArgs {
+        hook_args.for_run(
+            &ParameterUtils::params_to_dirname(params),
+            iteration,
+            self.combination_out_dir(hook_args, params)
+                .join(iteration.to_string()),
+        )
+    }
+
+    /// The command with the overrides applying to a commit and the version of its binary
+    fn override_command(&self, command: &str, commit: &str, version: Option<&str>) -> String {
+        let label = self.commit_labels.get(commit).map(String::as_str);
+        let version = version.and_then(parse_version);
+        self.command_overrides
+            .iter()
+            .filter(|o| o.applies_to(commit, label, version))
+            .fold(command.to_string(), |command, o| {
+                let overridden = o.apply(&command);
+                debug!("Overrode the command of {commit} to: {overridden}");
+                overridden
+            })
+    }
+
+    /// Whether checkpointing is enabled and a run of a parameter combination recorded
+    /// checkpoints, i.e. it is being resumed
+    fn has_checkpoints(&self, hook_args: &HookArgs, params: &HashMap<String, String>) -> bool {
+        if self.checkpoint.is_none() {
+            return false;
+        }
+        std::fs::read_dir(self.combination_out_dir(hook_args, params))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .any(|entry| entry.path().join(CHECKPOINT_FILE).exists())
+            })
+            .unwrap_or(false)
+    }
+
+    /// Result of a checkpointed run which finished before benchkit was resumed
+    fn finished_run(
+        &self,
+        hook_args: &HookArgs,
+        params: &HashMap<String, String>,
+        iteration: usize,
+    ) -> Result<Option<RunResult>> {
+        if self.checkpoint.is_none() {
+            return Ok(None);
+        }
+        let run_args = self.run_args(hook_args, params, iteration);
+        let state = CheckpointState::load(&run_args.run_out_dir)?;
+        let result = state.and_then(|state| state.result);
+        if result.is_some() {
+            info!("Run {iteration} finished before resuming, keeping its result");
+        }
+        Ok(result)
+    }
+
+    /// Record an error as a failure and carry on if `continue_on_failure` is set, otherwise
+    /// return it
+    fn tolerate<T>(
+        &self,
+        result: Result<T>,
+        iteration: Option<usize>,
+        failures: &mut Vec<RunFailure>,
+    ) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if self.continue_on_failure => {
+                let stage = match BenchkitError::find(&error) {
+                    Some(BenchkitError::Hook { stage, .. }) => Some(*stage),
+                    _ => None,
+                };
+                warn!("Continuing after failure: {error:#}");
+                failures.push(RunFailure {
+                    iteration,
+                    stage,
+                    message: format!("{error:#}"),
+                    failure_bundle: None,
+                });
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Collect the diagnostics of a failed run into a bundle
+    ///
+    /// The bundle of a run which exited with an error is recorded in its result, that of a run
+    /// which couldn't complete is returned. Runs stopped by the disk guard aren't bundled, as
+    /// there's no space for it.
+    fn bundle_failure(
+        &self,
+        run: &mut Result<RunResult>,
+        params: &HashMap<String, String>,
+        hook_args: &HookArgs,
+        iteration: usize,
+        log_offset: Option<u64>,
+    ) -> Option<PathBuf> {
+        let (error, stderr) = match run {
+            Ok(result) if result.exit_code != 0 => (
+                format!("Run {iteration} exited with code {}", result.exit_code),
+                result.stderr_tail.clone(),
+            ),
+            Err(error)
+                if !matches!(
+                    BenchkitError::find(error),
+                    Some(BenchkitError::DiskFull { .. })
+                ) =>
+            {
+                (format!("{error:#}"), None)
+            }
+            _ => return None,
+        };
+        let run_args = self.run_args(hook_args, params, iteration);
+        let bundle = FailureBundle::new(&run_args, &run_args.run_out_dir)
+            .since_log_offset(log_offset)
+            .stderr(stderr.as_deref())
+            .write(&error)
+            .inspect_err(|e| warn!("Failed to bundle the diagnostics of run {iteration}: {e:#}"))
+            .ok();
+        match run {
+            Ok(result) => {
+                result.failure_bundle = bundle;
+                None
+            }
+            Err(_) => bundle,
+        }
+    }
+
+    /// Whether adaptive runs are enabled and the mean isn't yet within the target relative error
+    fn needs_more_runs(&self, results: &[RunResult]) -> bool {
+        let Some(adaptive) = &self.adaptive_runs else {
+            return false;
+        };
+        match ResultAnalyzer::relative_error(&uninstrumented_durations(results)) {
+            Some(error) => error > adaptive.target_relative_error,
+            None => true,
+        }
+    }
+
     /// Execute a single benchmark run (either instrumented or uninstrumented)
     fn execute_single_run(
         &self,
@@ -232,39 +1069,159 @@ impl BenchmarkRunner {
         hook_args: &HookArgs,
         use_perf_instrumentation: bool,
     ) -> Result<RunResult> {
-        // Create iteration-specific hook args with parameter directory
-        let params_dir = ParameterUtils::params_to_dirname(params);
-        let iter_args = HookArgs {
-            iteration,
-            params_dir: params_dir.clone(),
-            ..hook_args.clone()
+        // Create iteration-specific hook args with their own datadir and output directory
+        let iter_args = self.run_args(hook_args, params, iteration);
+        let mut command = self.logging_command(&iter_args.substitute(command));
+
+        let cache_state = params
+            .get("cache_state")
+            .map(|state| state.parse::<CacheState>())
+            .transpose()?;
+
+        // Continue from the last checkpoint of an interrupted run, as long as its datadir survived
+        let run_out_dir = iter_args.run_out_dir.clone();
+        let mut checkpoints = match &self.checkpoint {
+            Some(_) => CheckpointState::load(&run_out_dir)?.unwrap_or_default(),
+            None => CheckpointState::default(),
         };
+        if !checkpoints.checkpoints.is_empty() && iter_args.datadir.is_dir() {
+            info!(
+                "Resuming run {iteration} from checkpoint {}",
+                checkpoints.checkpoints.len()
+            );
+        } else {
+            if !checkpoints.checkpoints.is_empty() {
+                warn!("The datadir of run {iteration} is gone, starting it over");
+                checkpoints = CheckpointState::default();
+            }
+            // Run prepare script before the benchmark run
+            self.hook_runner.run_hook(HookStage::Prepare, &iter_args)?;
+        }
+
+        // Wait after the prepare script so I/O it caused (e.g. copying a datadir) can settle
+        let quiet_wait_ms = self.wait_for_quiet()?.map(DurationMs::from);
+
+        // Drop caches last, so nothing read by the prepare script is still cached. Warm runs
+        // keep whatever the previous run of this bitcoind left in the page cache.
+        if cache_state == Some(CacheState::Cold) {
+            debug!("Dropping OS caches before cold run");
+            #[cfg(target_os = "linux")]
+            crate::system::drop_caches()?;
+            // Rejected by config validation
+            #[cfg(not(target_os = "linux"))]
+            anyhow::bail!("cache_state cold is only supported on Linux");
+        }
 
-        // Run prepare script before the benchmark run
-        self.hook_runner.run_hook(HookStage::Prepare, &iter_args)?;
+        // Start the other nodes of the benchmark, which aren't part of the timing
+        let nodes = self.start_nodes(&iter_args, params)?;
+        LiveStatus::record(Activity::Run, &iter_args);
 
-        let start = Instant::now();
-        let (output, profile_result) = if use_perf_instrumentation {
-            let (output, profile, _) =
-                self.execute_command_with_perf(command, iteration, commit, params)?;
-            (output, profile)
+        let binary = iter_args.binary.replace("{commit}", commit);
+        let tracer = if self.tracepoints.is_empty() {
+            None
         } else {
-            self.execute_command(command, iteration, commit, params)?
+            let trace_dir = run_out_dir.join("tracepoints");
+            Some(UsdtTracer::start(
+                Path::new(&binary),
+                &self.tracepoints,
+                &trace_dir,
+            )?)
+        };
+
+        // Stop the command before bitcoind fails on a full disk, leaving an opaque I/O error
+        let disk_watch = match &self.disk_guard {
+            Some(disk_guard) => {
+                let paths = vec![iter_args.datadir.clone(), self.out_dir.clone()];
+                disk_guard.check(&paths)?;
+                Some(disk_guard.watch(paths, Arc::clone(&self.command_group)))
+            }
+            None => None,
         };
 
-        // Stop timing (if we're not profiling, otherwise the profiler takes care of timing)
-        let duration = start.elapsed();
-        let duration_ms = if let Some(profile) = &profile_result {
-            // Use the duration from the profiler if available
-            profile.duration * 1000.0
+        // Follow the node's progress through ZMQ notifications, which it publishes to a free port
+        let zmq_listener = match &self.zmq_progress {
+            Some(zmq_progress) => {
+                let address = ZmqProgress::address()?;
+                command = ZmqProgress::command(&command, address);
+                Some(zmq_progress.listen(address, Arc::clone(&self.command_group)))
+            }
+            None => None,
+        };
+        let command = &command;
+
+        let swap_before = Self::read_swap_counters();
+        let started = SystemTime::now();
+        let stopwatch = Stopwatch::start();
+        let (output, profile_result, perf_data) = if use_perf_instrumentation {
+            let debug_log = iter_args.network_datadir().join("debug.log");
+            self.execute_command_with_perf(command, &run_out_dir, params, &debug_log)?
+        } else if let Some(checkpoint) = &self.checkpoint {
+            let output = self.execute_checkpointed(
+                command,
+                params,
+                &iter_args,
+                checkpoint,
+                &mut checkpoints,
+                &run_out_dir,
+            )?;
+            (output, None, None)
         } else {
-            duration.as_secs_f64() * 1000.0
+            let (output, profile) = self.execute_command(command, &run_out_dir, params)?;
+            (output, profile, None)
+        };
+
+        // Runs are timed the same way whether or not they are profiled
+        let (timing, usage) = stopwatch.stop();
+        let blocks_connected = zmq_listener.map(ZmqListener::finish);
+        nodes.stop(Some(&run_out_dir))?;
+        let crash = crash_signal(&output.status).map(|signal| match &self.core_collector {
+            Some(collector) => collector.collect(signal, Path::new(&binary), started, &run_out_dir),
+            None => Crash::from_signal(signal),
+        });
+        if let Some(crash) = &crash {
+            warn!("Run {iteration} crashed with {}", crash.signal_name);
+        }
+        if let Some(disk_watch) = disk_watch {
+            disk_watch.finish()?;
+        }
+        if let Some(blocks) = blocks_connected {
+            info!("Node connected {blocks} blocks during run {iteration}");
+        }
+        let tracepoints = tracer
+            .map(UsdtTracer::finish)
+            .transpose()?
+            .unwrap_or_default();
+
+        if let (true, Some(perf_data)) = (self.perf_archive, &perf_data) {
+            if let Err(e) = archive_perf_data(perf_data, Path::new(&binary)) {
+                warn!(
+                    "Failed to archive the symbols of {}: {e:#}",
+                    perf_data.display()
+                );
+            }
+        }
+        let swap = match (swap_before, Self::read_swap_counters()) {
+            (Some(before), Some(after)) => Some(after.since(&before)),
+            _ => None,
         };
+        let swapped = swap.map(|swap| swap.swapped());
+        if swapped == Some(true) {
+            warn!(
+                "The system swapped during run {iteration}, its timing is not comparable \
+                 to runs that didn't"
+            );
+        }
 
         // Record result
+        let debug_log = Self::debug_log(&iter_args);
         let run_result = RunResult {
             iteration,
-            duration_ms,
+            // Checkpointed runs took the time the node ran for, over all benchkit invocations
+            duration_ms: checkpoints
+                .checkpoints
+                .last()
+                .map_or(timing.monotonic_ms, |checkpoint| checkpoint.elapsed_ms),
+            timing: Some(timing),
             exit_code: output.status.code().unwrap_or(-1),
             instrumentation: if use_perf_instrumentation {
                 InstrumentationType::PerfInstrumented
@@ -277,32 +1234,122 @@ impl BenchmarkRunner {
             } else {
                 None
             },
+            profile_metrics: profile_result.as_ref().map(ProfileMetrics::from_profile),
             profile: profile_result,
+            quiet_wait_ms,
+            cache_state,
+            major_faults: usage.map(|usage| usage.major_faults),
+            minor_faults: usage.map(|usage| usage.minor_faults),
+            user_time_ms: usage.map(|usage| usage.user_time.into()),
+            system_time_ms: usage.map(|usage| usage.system_time.into()),
+            voluntary_context_switches: usage.map(|usage| usage.voluntary_context_switches),
+            involuntary_context_switches: usage.map(|usage| usage.involuntary_context_switches),
+            swap_in: swap.map(|swap| swap.swap_in),
+            swap_out: swap.map(|swap| swap.swap_out),
+            swapped,
+            node_settings: debug_log
+                .as_deref()
+                .map(parse_node_settings)
+                .unwrap_or_default(),
+            stderr_tail: (!output.status.success() && !output.stderr.is_empty())
+                .then(|| stderr_tail(&output.stderr)),
+            tracepoints,
+            checkpoints: checkpoints.checkpoints.clone(),
+            blocks_connected,
+            crash,
+            failure_bundle: None,
+            peers: debug_log
+                .as_deref()
+                .map(parse_peer_connections)
+                .unwrap_or_default(),
         };
+        if self.checkpoint.is_some() {
+            checkpoints.result = Some(run_result.clone());
+            checkpoints.save(&run_out_dir)?;
+        }
 
-        // Run conclude script after the benchmark run
+        // Run conclude script after the benchmark run, which moves debug.log out of the datadir
         self.hook_runner.run_hook(HookStage::Conclude, &iter_args)?;
 
         Ok(run_result)
     }
 
+    /// Wait for the system to be quiet if a quiet gate is configured, returning the time waited
+    fn wait_for_quiet(&self) -> Result<Option<Duration>> {
+        let Some(quiet_gate) = &self.quiet_gate else {
+            return Ok(None);
+        };
+
+        #[cfg(target_os = "linux")]
+        let cores = match &self.benchmark_cores {
+            Some(spec) => crate::config::parse_core_list(spec)?,
+            None => Vec::new(),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let cores = Vec::new();
+
+        quiet_gate.wait(&cores).map(Some)
+    }
+
+    /// Read the system-wide swap counters, if available
+    fn read_swap_counters() -> Option<SwapCounters> {
+        if cfg!(not(target_os = "linux")) {
+            return None;
+        }
+        SwapCounters::read()
+            .inspect_err(|e| debug!("Not tracking swap: {e}"))
+            .ok()
+    }
+
+    /// First line of a binary's `-version` output, if it can be run
+    fn binary_version(binary: &str) -> Option<String> {
+        let output = CommandExecutor::builder()
+            .name(format!("{binary} -version"))
+            .capture_output(true)
+            .build()
+            .and_then(|executor| executor.execute_command_with_args(binary, &["-version"]))
+            .inspect_err(|e| debug!("Could not get the version of {binary}: {e}"))
+            .ok()?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let version = stdout.lines().next()?.trim().to_string();
+        info!("Benchmarking {version}");
+        Some(version)
+    }
+
+    /// The debug.log in the node's datadir, to record its settings and peers from
+    fn debug_log(hook_args: &HookArgs) -> Option<String> {
+        std::fs::read_to_string(hook_args.network_datadir().join("debug.log"))
+            .inspect_err(|e| debug!("No node settings or peers recorded: {e}"))
+            .ok()
+    }
+
+    /// What the node at the feeder address advertises, None without a feeder or if it can't be
+    /// reached
+    fn feeder_version(&self, network: Network) -> Option<PeerVersion> {
+        let address = self.feeder.as_deref()?;
+        handshake(address, network)
+            .inspect_err(|e| warn!("Could not record the identity of the node at {address}: {e:#}"))
+            .ok()
+    }
+
     /// Execute a command with perf instrumentation
     fn execute_command_with_perf(
         &self,
         command: &str,
-        iteration: usize,
-        commit: &str,
+        run_out_dir: &Path,
         params: &HashMap<String, String>,
+        debug_log: &Path,
     ) -> Result<(std::process::Output, Option<ProfileResult>, Option<PathBuf>)> {
-        // Create the output directory structure for this specific run
-        let params_dir = ParameterUtils::params_to_dirname(params);
-        let perf_out_dir = self
-            .out_dir
-            .join(commit)
-            .join(params_dir)
-            .join(iteration.to_string());
-
-        let perf_instrumentor = PerfInstrumentor::new(perf_out_dir);
+        let perf_instrumentor = PerfInstrumentor::new(run_out_dir.to_path_buf());
+        if let Some(attach) = &self.perf_attach {
+            return self.execute_with_perf_attached(
+                command,
+                params,
+                &perf_instrumentor,
+                attach,
+                debug_log,
+            );
+        }
         // Wrap the command with perf
         let (perf_command_vec, perf_data_path) = perf_instrumentor.wrap_command(command)?;
         // Convert Vec<String> to a single command string for shell execution
@@ -313,7 +1360,7 @@ impl BenchmarkRunner {
             perf_command
         );
 
-        let child = self.launch_command_with_affinity(&perf_command)?;
+        let child = self.launch_command_with_affinity(&perf_command, params)?;
         let output = child
             .wait_with_output()
             .context("Failed to wait for perf command completion")?;
@@ -332,51 +1379,156 @@ impl BenchmarkRunner {
         Ok((output, None, Some(perf_data_path)))
     }
 
+    /// Run a command, attaching perf to it and its descendants once startup is over
+    fn execute_with_perf_attached(
+        &self,
+        command: &str,
+        params: &HashMap<String, String>,
+        perf_instrumentor: &PerfInstrumentor,
+        attach: &PerfAttach,
+        debug_log: &Path,
+    ) -> Result<(std::process::Output, Option<ProfileResult>, Option<PathBuf>)> {
+        let mut child = self.launch_command_with_affinity(command, params)?;
+        // Drain the output while waiting, so a chatty command can't block on a full pipe
+        let stdout = child.stdout.take().map(read_to_end_in_thread);
+        let stderr = child.stderr.take().map(read_to_end_in_thread);
+
+        let perf = if attach.wait(&mut child, debug_log)? {
+            #[cfg(target_os = "linux")]
+            let pids = process::descendants(child.id());
+            #[cfg(not(target_os = "linux"))]
+            let pids = vec![child.id()];
+            let (perf_command, perf_data_path) = perf_instrumentor.attach_command(&pids)?;
+            info!("Attaching perf: {}", perf_command.join(" "));
+            let perf_child = std::process::Command::new(&perf_command[0])
+                .args(&perf_command[1..])
+                .spawn()
+                .context("Failed to start perf record")?;
+            Some((perf_child, perf_data_path))
+        } else {
+            warn!("Command exited before perf attached");
+            None
+        };
+
+        let status = child
+            .wait()
+            .context("Failed to wait for command completion")?;
+        let output = std::process::Output {
+            status,
+            stdout: stdout.map(join_output).unwrap_or_default(),
+            stderr: stderr.map(join_output).unwrap_or_default(),
+        };
+
+        let Some((mut perf_child, perf_data_path)) = perf else {
+            return Ok((output, None, None));
+        };
+        // perf exits by itself once everything it follows has exited, and writes perf.data on
+        // SIGINT otherwise
+        std::thread::sleep(Duration::from_millis(500));
+        if perf_child.try_wait()?.is_none() {
+            process::interrupt(&mut perf_child)?;
+        }
+        perf_child
+            .wait()
+            .context("Failed to wait for perf record")?;
+        if !perf_instrumentor.finalize_perf_data()? {
+            warn!("perf instrumentation may have failed - no perf.data generated");
+        }
+        Ok((output, None, Some(perf_data_path)))
+    }
+
+    /// Median time to launch a no-op command the way runs are launched and wait for it to exit
+    ///
+    /// This includes starting the shell, applying CPU affinity, priority and the cgroup, so it is
+    /// the part of each run's duration spent by the harness rather than the command.
+    fn measure_spawn_overhead(&self, params: &HashMap<String, String>) -> Option<DurationMs> {
+        let mut durations = Vec::with_capacity(SPAWN_CALIBRATION_RUNS);
+        for _ in 0..SPAWN_CALIBRATION_RUNS {
+            let start = Instant::now();
+            let exited = self
+                .launch_command_with_affinity("true", params)
+                .and_then(|child| Ok(child.wait_with_output()?));
+            match exited {
+                Ok(output) if output.status.success() => durations.push(start.elapsed()),
+                Ok(output) => {
+                    debug!("Spawn calibration exited with {}", output.status);
+                    return None;
+                }
+                Err(e) => {
+                    debug!("Failed to measure spawn overhead: {e:#}");
+                    return None;
+                }
+            }
+        }
+        durations.sort();
+        durations
+            .get(durations.len() / 2)
+            .map(|&median| median.into())
+    }
+
     /// Launch a command with CPU affinity constraints
     /// This is a helper function that can be used by both regular execution and profiling
-    fn launch_command_with_affinity(&self, command: &str) -> Result<std::process::Child> {
+    ///
+    /// The benchmark's `env` is set, and the parameters are exported to the command as
+    /// `BENCHKIT_PARAM_<NAME>` variables.
+    fn launch_command_with_affinity(
+        &self,
+        command: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<std::process::Child> {
         debug!("Launching command with affinity: {command}");
 
         // Determine if we need to capture output
         // We capture output if:
         // 1. capture_output is true (for storing in results)
         // 2. stop_on_log_pattern is configured (for monitoring)
-        // 3. We're not profiling (profiling doesn't capture output)
-        let should_capture =
-            !self.enable_profiling && (self.capture_output || self.stop_on_log_pattern.is_some());
+        // 3. continue_on_failure is set (for recording why runs failed)
+        // 4. We're not profiling (profiling doesn't capture output)
+        let should_capture = !self.enable_profiling
+            && (self.capture_output
+                || self.stop_on_log_pattern.is_some()
+                || self.continue_on_failure);
+
+        // Each command gets the limit derived from its own dbcache
+        if let (Some(limit), Some(cgroup)) = (&self.memory_limit, &self.cgroup) {
+            if limit.enforce {
+                cgroup.set_memory_limit_mb(limit.required_mb(command))?;
+            }
+        }
+        // Combinations of the `cpu_quota` axis each get their own bandwidth
+        if let (Some(limits), Some(cgroup)) = (&self.cpu_limits, &self.cgroup) {
+            if !limits.quotas.is_empty() {
+                let quota = params.get("cpu_quota").map_or("max", String::as_str);
+                cgroup.set_cpu_max(&cpu_limit::cpu_max(quota)?)?;
+            }
+        }
 
         // Create a command executor with our benchmark settings
         let executor = CommandExecutor::builder()
             .name(command.to_string())
             .cpu_cores(self.benchmark_cores.clone())
+            .priority(self.priority.clone())
+            .cgroup(self.cgroup.as_ref().map(|c| c.path().to_path_buf()))
+            .env_vars(process_env(&self.env, params))
             .process_group(true)
+            .core_dumps(self.core_collector.is_some())
             .capture_output(should_capture)
             .build()?;
 
         // Launch the command using the executor
-        executor.launch_command("sh", &["-c", command])
+        let child = executor.launch_shell(command)?;
+        self.command_group.store(child.id(), Ordering::SeqCst);
+        Ok(child)
     }
 
     /// Execute a command and capture its output, optionally with profiling
     fn execute_command(
         &self,
         command: &str,
-        iteration: usize,
-        commit: &str,
+        run_out_dir: &Path,
         params: &HashMap<String, String>,
     ) -> Result<(std::process::Output, Option<ProfileResult>)> {
-        // Automatically append -printtoconsole if stop_on_log_pattern is configured
-        // and the command doesn't already contain it
-        let final_command =
-            if self.stop_on_log_pattern.is_some() && !command.contains("-printtoconsole") {
-                let updated_command = format!("{command} -printtoconsole");
-                debug!(
-                "Automatically added -printtoconsole for log pattern matching: {updated_command}"
-            );
-                updated_command
-            } else {
-                command.to_string()
-            };
+        let final_command = self.console_command(command);
 
         debug!("Executing command: {final_command}");
 
@@ -387,29 +1539,22 @@ impl BenchmarkRunner {
 
         // If profiling is enabled, use the profiler to execute the command
         if self.enable_profiling {
-            // Create a directory structure with commit/params/iteration
-            let params_dir = ParameterUtils::params_to_dirname(params);
-            let profile_out_dir = self
-                .out_dir
-                .join(commit)
-                .join(params_dir)
-                .join(iteration.to_string());
-            std::fs::create_dir_all(&profile_out_dir)?;
+            std::fs::create_dir_all(run_out_dir)?;
 
             // Create the profiler with our benchmark cores
-            let mut profiler = Profiler::builder(&profile_out_dir)
+            let mut profiler = Profiler::builder(run_out_dir)
                 .sample_interval(self.profile_interval)
                 .benchmark_cores(self.benchmark_cores.clone())
                 .build()?;
 
             // Launch the command using our helper, which handles CPU affinity
             info!("Profiling command: {final_command}");
-            let child = self.launch_command_with_affinity(&final_command)?;
+            let child = self.launch_command_with_affinity(&final_command, params)?;
             let profile_result = profiler.profile_process(&final_command, child)?;
 
             // Make an Output manually for profile
             let output = std::process::Output {
-                status: ExitStatusExt::from_raw(profile_result.exit_code),
+                status: exit_status_from_code(profile_result.exit_code),
                 stdout: Vec::new(),
                 stderr: Vec::new(),
             };
@@ -417,8 +1562,129 @@ impl BenchmarkRunner {
             return Ok((output, Some(profile_result)));
         }
 
-        // For non-profiled commands, launch and potentially monitor
-        let mut child = self.launch_command_with_affinity(&final_command)?;
+        Ok((self.execute_monitored(&final_command, params)?, None))
+    }
+
+    /// Start the nodes of the other roles for a run in dependency order, with their datadirs
+    /// next to the run's
+    fn start_nodes(
+        &self,
+        run_args: &HookArgs,
+        params: &HashMap<String, String>,
+    ) -> Result<RunningNodes> {
+        let mut running = RunningNodes::default();
+        for role in start_order(&self.nodes)? {
+            let role_args = HookArgs {
+                datadir: role.datadir(&run_args.datadir),
+                ..run_args.clone()
+            };
+            let command = match &self.parameter_matrix {
+                Some(matrix) => matrix.apply_parameters(&role.command, params),
+                None => role.command.clone(),
+            };
+            let command = role_args.substitute(&command);
+            running.start(role, &command, role_args)?;
+        }
+        Ok(running)
+    }
+
+    /// Append the flags of the configured node logging to a command
+    fn logging_command(&self, command: &str) -> String {
+        match &self.node_logging {
+            Some(logging) => logging.apply(command),
+            None => command.to_string(),
+        }
+    }
+
+    /// Append -printtoconsole if stop_on_log_pattern is configured and the command doesn't
+    /// already contain it, so the log can be matched
+    fn console_command(&self, command: &str) -> String {
+        if self.stop_on_log_pattern.is_some() && !has_console_flag(command) {
+            let updated_command = format!("{command} -printtoconsole");
+            debug!(
+                "Automatically added -printtoconsole for log pattern matching: {updated_command}"
+            );
+            updated_command
+        } else {
+            command.to_string()
+        }
+    }
+
+    /// Run a command in slices of the checkpoint interval until it exits by itself
+    ///
+    /// At the end of each slice the node is stopped gracefully, its elapsed time and height are
+    /// recorded and its debug.log archived, and it is restarted on the same datadir.
+    fn execute_checkpointed(
+        &self,
+        command: &str,
+        params: &HashMap<String, String>,
+        hook_args: &HookArgs,
+        checkpoint: &CheckpointConfig,
+        state: &mut CheckpointState,
+        run_out_dir: &Path,
+    ) -> Result<std::process::Output> {
+        let debug_log = hook_args.network_datadir().join("debug.log");
+        loop {
+            let start = Instant::now();
+            let mut child = self.launch_command_with_affinity(command, params)?;
+            // Drain the output while waiting, so a chatty command can't block on a full pipe
+            let stdout = child.stdout.take().map(read_to_end_in_thread);
+            let stderr = child.stderr.take().map(read_to_end_in_thread);
+
+            let exited = loop {
+                if let Some(status) = child.try_wait()? {
+                    break Some(status);
+                }
+                if start.elapsed() >= checkpoint.interval() {
+                    break None;
+                }
+                std::thread::sleep(CHECKPOINT_POLL_INTERVAL);
+            };
+            let segment = start.elapsed();
+
+            if let Some(status) = exited {
+                let finished = state.record(segment, &debug_log);
+                info!(
+                    "Run finished after {:.2} hours of checkpointed execution",
+                    finished.elapsed_ms.as_secs_f64() / 3600.0
+                );
+                return Ok(std::process::Output {
+                    status,
+                    stdout: stdout.map(join_output).unwrap_or_default(),
+                    stderr: stderr.map(join_output).unwrap_or_default(),
+                });
+            }
+
+            // The time the node takes to shut down isn't part of the run
+            let status = process::terminate(&mut child, CHECKPOINT_GRACE_PERIOD)?;
+            for reader in [stdout, stderr].into_iter().flatten() {
+                join_output(reader);
+            }
+            if !status.success() {
+                warn!("Node exited with {status} when stopped at a checkpoint");
+            }
+            let index = state.checkpoints.len();
+            let reached = state.record(segment, &debug_log);
+            archive_debug_log(&debug_log, run_out_dir, index)?;
+            state.save(run_out_dir)?;
+            info!(
+                "Checkpoint {} at height {} after {:.2} hours, restarting the node",
+                index + 1,
+                reached
+                    .height
+                    .map_or("unknown".to_string(), |height| height.to_string()),
+                reached.elapsed_ms.as_secs_f64() / 3600.0
+            );
+        }
+    }
+
+    /// Launch a command without profiling, stopping it early if the log pattern matches
+    fn execute_monitored(
+        &self,
+        command: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<std::process::Output> {
+        let mut child = self.launch_command_with_affinity(command, params)?;
 
         if let Some(pattern) = &self.stop_on_log_pattern {
             info!("Monitoring command output for pattern: {pattern}");
@@ -429,17 +1695,8 @@ impl BenchmarkRunner {
 
             if pattern_matched {
                 info!("Pattern matched, terminating process");
-                match child.kill() {
-                    Ok(_) => debug!("Process terminated successfully"),
-                    Err(e) => warn!("Failed to terminate process: {e}"),
-                }
-
-                // Also try to terminate any child processes via process group
-                if let Some(pgid) = child.id().checked_neg() {
-                    unsafe {
-                        libc::kill(pgid as i32, libc::SIGTERM);
-                    }
-                }
+                let status = process::terminate(&mut child, process::GRACE_PERIOD)?;
+                debug!("Process terminated with status {status}");
             }
         }
 
@@ -455,7 +1712,7 @@ impl BenchmarkRunner {
             // and include them in the results
         }
 
-        Ok((output, None))
+        Ok(output)
     }
 
     /// Run all parameter combinations for a command template
@@ -467,12 +1724,23 @@ impl BenchmarkRunner {
     ) -> Result<Vec<BenchmarkResult>> {
         // If no parameter matrix is set, just run the command as-is
         if self.parameter_matrix.is_none() {
-            let result = self.run_benchmark(command_template, runs, hook_args)?;
+            let command = hook_args.substitute_combination(command_template);
+            self.out_layout
+                .check(&hook_args.benchmark, &[HashMap::new()])?;
+            self.check_memory(&command)?;
+            let result = self.run_or_reuse(&command, runs, hook_args, &HashMap::new())?;
             return Ok(vec![result]);
         }
 
         let matrix = self.parameter_matrix.as_ref().unwrap();
         let commands = matrix.generate_commands(command_template);
+
+        // Check every combination up front rather than failing part way through a sweep
+        let combinations: Vec<_> = commands.iter().map(|(_, params)| params.clone()).collect();
+        self.out_layout.check(&hook_args.benchmark, &combinations)?;
+        for (command, _) in &commands {
+            self.check_memory(command)?;
+        }
         let mut results = Vec::with_capacity(commands.len());
 
         for (command, params) in commands {
@@ -486,9 +1754,22 @@ impl BenchmarkRunner {
                 current_hook_args.commit = commit.clone();
             }
 
+            current_hook_args.params = params.clone();
+            current_hook_args.ports = NodePorts::from_command(&command, hook_args.network);
+
+            // Resolve the binary for this combination (e.g. commit and build variant)
+            current_hook_args.binary = matrix.apply_parameters(&hook_args.binary, &params);
+            let mut command = current_hook_args.substitute_combination(&command);
+            let par_axis = self
+                .cpu_limits
+                .as_ref()
+                .is_some_and(|limits| !limits.par.is_empty());
+            if let Some(par) = params.get("par").filter(|_| par_axis) {
+                command = cpu_limit::par_command(&command, par);
+            }
+
             // Create a modified copy of run_benchmark that uses the params for directory structure
-            let mut result =
-                self.run_benchmark_with_params(&command, runs, &current_hook_args, &params)?;
+            let mut result = self.run_or_reuse(&command, runs, &current_hook_args, &params)?;
             result.parameters = params.clone();
             results.push(result);
         }
@@ -496,6 +1777,53 @@ impl BenchmarkRunner {
         Ok(results)
     }
 
+    /// Run a parameter combination, or reuse its result from the result cache
+    fn run_or_reuse(
+        &self,
+        command: &str,
+        runs: usize,
+        hook_args: &HookArgs,
+        params: &HashMap<String, String>,
+    ) -> Result<BenchmarkResult> {
+        let mut span = telemetry::span("combination");
+        span.attr("benchkit.command", command);
+        for (name, value) in params {
+            span.attr(&format!("benchkit.param.{name}"), value);
+        }
+        let binary = Path::new(&hook_args.binary);
+        let cache = self.result_cache.as_ref().filter(|_| binary.is_file());
+        // Every invocation has its own output directory, which doesn't change the results
+        let key_command = command.replace(&hook_args.out_dir.display().to_string(), "{out_dir}");
+        if let Some(cache) = cache {
+            match cache.load(binary, &key_command, params) {
+                Ok(Some(result)) => {
+                    span.attr("benchkit.cached", true);
+                    return Ok(result);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read the result cache: {e:#}"),
+            }
+        }
+        let result =
+            span.record(self.run_benchmark_with_params(command, runs, hook_args, params))?;
+        span.attr("benchkit.mean_ms", result.summary.mean.0)
+            .attr("benchkit.failures", result.failures.len());
+        if let Some(cache) = cache {
+            if let Err(e) = cache.store(binary, &key_command, params, &result, &self.out_dir) {
+                warn!("Failed to cache the result of {command}: {e:#}");
+            }
+        }
+        Ok(result)
+    }
+
+    /// Fail if a memory limit is configured and the command's dbcache doesn't fit in RAM
+    fn check_memory(&self, command: &str) -> Result<()> {
+        match &self.memory_limit {
+            Some(limit) => limit.check(command),
+            None => Ok(()),
+        }
+    }
+
     // Export methods - delegating to the ResultExporter
 
     pub fn export_json(result: &BenchmarkResult, path: &impl AsRef<std::path::Path>) -> Result<()> {
@@ -509,3 +1837,108 @@ impl BenchmarkRunner {
         ResultExporter::export_json_multiple(results, path.as_ref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::hook_runner::NodePorts;
+    use crate::benchmarks::{FileSystem, HookMode, MockFileSystem};
+    use crate::command::MockProcessRunner;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_mock_hooks() {
+        let out_dir = tempdir().unwrap();
+        let fs = Arc::new(MockFileSystem::new());
+        let hook_runner = HookRunner::with_io(
+            HookMode::FullIbd,
+            Arc::new(MockProcessRunner::new()),
+            fs.clone(),
+        );
+        let runner = BenchmarkRunner::builder(out_dir.path().to_path_buf(), hook_runner)
+            .parameter_lists(vec![ParameterList {
+                var: "commit".to_string(),
+                values: vec!["abc".to_string()],
+            }])
+            .build()
+            .unwrap();
+        let tmp_data_dir = PathBuf::from("/benchkit-mock/data");
+        let hook_args = HookArgs {
+            benchmark: "mock".to_string(),
+            options: Default::default(),
+            binary: "true".to_string(),
+            connect_address: String::new(),
+            network: crate::types::Network::Regtest,
+            ports: NodePorts::from_command("true", crate::types::Network::Regtest),
+            env: HashMap::new(),
+            out_dir: out_dir.path().to_path_buf(),
+            run_out_dir: out_dir.path().to_path_buf(),
+            snapshot_path: PathBuf::new(),
+            tmp_data_dir: tmp_data_dir.clone(),
+            datadir: tmp_data_dir.clone(),
+            iteration: 0,
+            commit: "{commit}".to_string(),
+            params_dir: "default".to_string(),
+            loadblock: Vec::new(),
+            header_cache: None,
+            params: HashMap::new(),
+        };
+
+        let results = runner
+            .run_parameter_matrix("true -datadir={datadir}", 2, &hook_args)
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].runs.len(), 2);
+        // The hooks created and removed each run's datadir in memory only
+        assert!(fs.is_dir(&tmp_data_dir));
+        assert!(fs.read_dir(&tmp_data_dir).unwrap().is_empty());
+        assert!(!tmp_data_dir.exists());
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let fail_fast = FailFast {
+            max_slowdown: Some(10.0),
+            max_consecutive_failures: Some(3),
+        };
+        assert!(fail_fast.validate().is_ok());
+
+        assert_eq!(
+            fail_fast.abort_reason(Some(DurationMs(500.0)), Some(DurationMs(100.0)), 0),
+            None
+        );
+        assert_eq!(
+            fail_fast
+                .abort_reason(Some(DurationMs(1500.0)), Some(DurationMs(100.0)), 0)
+                .as_deref(),
+            Some("the first run took 15.0x the historical mean of 100.0 ms")
+        );
+        // Nothing to compare with before the first run, or without history
+        assert_eq!(
+            fail_fast.abort_reason(None, Some(DurationMs(100.0)), 0),
+            None
+        );
+        assert_eq!(
+            fail_fast.abort_reason(Some(DurationMs(1500.0)), None, 0),
+            None
+        );
+
+        assert_eq!(fail_fast.abort_reason(None, None, 2), None);
+        assert_eq!(
+            fail_fast.abort_reason(None, None, 3).as_deref(),
+            Some("3 runs in a row failed")
+        );
+
+        let empty = FailFast {
+            max_slowdown: None,
+            max_consecutive_failures: None,
+        };
+        assert!(empty.validate().is_err());
+        let faster = FailFast {
+            max_slowdown: Some(0.5),
+            max_consecutive_failures: None,
+        };
+        assert!(faster.validate().is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::benchmarks::build::Builder;
+use crate::benchmarks::export::ResultExporter;
+use crate::benchmarks::git::Git;
+use crate::benchmarks::overrides::RunOverrides;
+use crate::benchmarks::repository::{resolve_commit, RepositoryManager};
+use crate::benchmarks::runner::Runner;
+use crate::config::{BenchmarkSelection, GlobalConfig};
+use crate::path_utils;
+use crate::types::DurationMs;
+
+/// File in the output directory recording the commits benchmarked during a bisection
+pub const BISECT_FILE: &str = "bisect.json";
+
+/// How a commit was judged during a bisection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BisectVerdict {
+    Good,
+    Bad,
+    /// The commit couldn't be built or benchmarked
+    Skip,
+}
+
+impl BisectVerdict {
+    /// Judge a mean against the good commit's, calling it bad when it is slower by more than
+    /// `threshold`, as a fraction of the good mean
+    pub fn judge(mean: DurationMs, good_mean: DurationMs, threshold: f64) -> Self {
+        if mean.0 > good_mean.0 * (1.0 + threshold) {
+            Self::Bad
+        } else {
+            Self::Good
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Good => "good",
+            Self::Bad => "bad",
+            Self::Skip => "skip",
+        }
+    }
+}
+
+/// A commit benchmarked during a bisection
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BisectStep {
+    pub commit: String,
+    /// Mean duration, if the commit could be benchmarked
+    pub mean: Option<DurationMs>,
+    /// Change of the mean relative to the good commit's
+    pub relative: Option<f64>,
+    pub verdict: BisectVerdict,
+    /// Directory of the commit's benchmark run, relative to the bisection's output directory
+    pub out_dir: PathBuf,
+}
+
+/// Outcome of a bisection, written to `bisect.json`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BisectReport {
+    pub benchmark: String,
+    pub good: String,
+    pub bad: String,
+    /// Slowdown relative to the good commit which makes a commit bad, as a fraction
+    pub threshold: f64,
+    pub steps: Vec<BisectStep>,
+    /// The first commit slower than the good one by more than the threshold
+    pub first_bad: String,
+}
+
+/// Parse a regression threshold like "3%" or "3" into a fraction
+pub fn parse_threshold(threshold: &str) -> Result<f64> {
+    let percent: f64 = threshold
+        .trim()
+        .trim_end_matches('%')
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid threshold '{threshold}', expected e.g. 3%"))?;
+    if !(percent > 0.0 && percent.is_finite()) {
+        anyhow::bail!("Threshold must be positive: {threshold}");
+    }
+    Ok(percent / 100.0)
+}
+
+/// Finds the commit which made a benchmark slower, by building and running it at each step of
+/// `git bisect` between a good and a bad commit
+///
+/// Each commit's runs go to their own subdirectory of the output directory. Runs are added
+/// adaptively until the mean is known to within half the threshold, unless the benchmark sets
+/// its own `target_relative_error`.
+pub struct Bisect {
+    config: GlobalConfig,
+    out_dir: PathBuf,
+    benchmark: String,
+    good: String,
+    bad: String,
+    threshold: f64,
+    overrides: RunOverrides,
+}
+
+impl Bisect {
+    /// Bisect `benchmark` between the `good` and `bad` commits, with `threshold` being the
+    /// slowdown that makes a commit bad as a fraction
+    pub fn new(
+        config: GlobalConfig,
+        out_dir: PathBuf,
+        benchmark: &str,
+        good: &str,
+        bad: &str,
+        threshold: f64,
+    ) -> Self {
+        Self {
+            config,
+            out_dir,
+            benchmark: benchmark.to_string(),
+            good: good.to_string(),
+            bad: bad.to_string(),
+            threshold,
+            overrides: RunOverrides::default(),
+        }
+    }
+
+    /// Override the benchmark's configuration for every commit, e.g. to pin its parameters
+    pub fn overrides(mut self, overrides: RunOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Bisect, writing the report to `bisect.json` in the output directory
+    pub fn run(&self) -> Result<BisectReport> {
+        if !self
+            .config
+            .bench
+            .benchmarks
+            .iter()
+            .any(|bench| bench.name == self.benchmark)
+        {
+            anyhow::bail!("Benchmark not found: {}", self.benchmark);
+        }
+        path_utils::prepare_output_directory(&self.out_dir)?;
+
+        // Building the endpoints also fetches the repository if it is remote
+        self.build(&[self.good.clone(), self.bad.clone()])?;
+        let global = &self.config.bench.global;
+        let repo = RepositoryManager::new(&global.source.to_string_lossy(), &global.scratch);
+        let repo_path = repo.local_path();
+        let resolve = |commit: &str| {
+            resolve_commit(&repo_path, commit)?
+                .with_context(|| format!("Commit not found in repository: {commit}"))
+        };
+        let good = repo.resolve_cached_commit(&self.good)?;
+        let bad = repo.resolve_cached_commit(&self.bad)?;
+
+        let mut steps = Vec::new();
+        let good_mean = self
+            .benchmark_commit(&good, "good")
+            .context("Failed to benchmark the good commit")?;
+        steps.push(self.step(&good, "good", Some(good_mean), good_mean));
+        let bad_mean = self
+            .benchmark_commit(&bad, "bad")
+            .context("Failed to benchmark the bad commit")?;
+        let bad_step = self.step(&bad, "bad", Some(bad_mean), good_mean);
+        if bad_step.verdict != BisectVerdict::Bad {
+            anyhow::bail!(
+                "{bad} is {:+.1}% slower than {good}, which is within the threshold of {:.1}%, so \
+                 there is no regression to bisect",
+                bad_step.relative.unwrap_or_default() * 100.0,
+                self.threshold * 100.0
+            );
+        }
+        steps.push(bad_step);
+
+        let git_bisect = GitBisect::start(&repo_path, &good, &bad)?;
+        let mut output = git_bisect.last_output.clone();
+        let first_bad = loop {
+            if let Some(first_bad) = first_bad_commit(&output) {
+                break resolve(&first_bad)?;
+            }
+            if output.contains("only 'skip'ped commits left") {
+                anyhow::bail!("Could not find the first bad commit:\n{output}");
+            }
+            let commit = resolve("BISECT_HEAD")?;
+            let dir = steps.len().to_string();
+            let step = match self.benchmark_commit(&commit, &dir) {
+                Ok(mean) => self.step(&commit, &dir, Some(mean), good_mean),
+                Err(e) => {
+                    warn!("Skipping {commit}: {e:#}");
+                    self.step(&commit, &dir, None, good_mean)
+                }
+            };
+            info!(
+                "{commit} is {}{}",
+                step.verdict.as_str(),
+                step.relative
+                    .map(|relative| format!(" ({:+.1}%)", relative * 100.0))
+                    .unwrap_or_default()
+            );
+            output = git_bisect.mark(step.verdict, &commit)?;
+            steps.push(step);
+        };
+        drop(git_bisect);
+
+        let report = BisectReport {
+            benchmark: self.benchmark.clone(),
+            good,
+            bad,
+            threshold: self.threshold,
+            steps,
+            first_bad,
+        };
+        let path = self.out_dir.join(BISECT_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(report)
+    }
+
+    fn step(
+        &self,
+        commit: &str,
+        dir: &str,
+        mean: Option<DurationMs>,
+        good_mean: DurationMs,
+    ) -> BisectStep {
+        BisectStep {
+            commit: commit.to_string(),
+            mean,
+            relative: mean.map(|mean| mean / good_mean - 1.0),
+            verdict: mean.map_or(BisectVerdict::Skip, |mean| {
+                BisectVerdict::judge(mean, good_mean, self.threshold)
+            }),
+            out_dir: PathBuf::from(format!("{dir}-{commit}")),
+        }
+    }
+
+    /// Configuration benchmarking only `commits`
+    fn config_for(&self, commits: &[String]) -> GlobalConfig {
+        let mut config = self.config.clone();
+        let global = &mut config.bench.global;
+        global.commits = commits.to_vec();
+        global.reference_binary = None;
+        global.exports.clear();
+        global
+            .benchmark
+            .get_or_insert_with(Default::default)
+            .target_relative_error
+            .get_or_insert(self.threshold / 2.0);
+        config
+    }
+
+    fn build(&self, commits: &[String]) -> Result<()> {
+        Builder::new(self.config_for(commits))?.build()
+    }
+
+    /// Build and benchmark a commit into `<name>-<commit>`, returning its mean duration
+    fn benchmark_commit(&self, commit: &str, name: &str) -> Result<DurationMs> {
+        info!("Benchmarking {commit}");
+        let commits = [commit.to_string()];
+        self.build(&commits)?;
+        let out_dir = self.out_dir.join(format!("{name}-{commit}"));
+        Runner::new(self.config_for(&commits), out_dir.clone())?
+            .quiet(true)
+            .overrides(self.overrides.clone())
+            .run(&BenchmarkSelection {
+                names: vec![self.benchmark.clone()],
+                ..Default::default()
+            })?;
+        let results = ResultExporter::load_json(&out_dir.join("results.json"))?;
+        match results.as_slice() {
+            [result] if !result.runs.is_empty() => Ok(result.summary.mean),
+            [_] => anyhow::bail!("No run of {commit} completed"),
+            _ => anyhow::bail!(
+                "Benchmark {} has {} parameter combinations, pin them with --param to bisect",
+                self.benchmark,
+                results.len()
+            ),
+        }
+    }
+}
+
+/// A `git bisect` session which doesn't check out commits, reset when dropped
+struct GitBisect<'a> {
+    repo_path: &'a Path,
+    git: Git,
+    /// Output of the last command, telling the next commit to test or the first bad one
+    last_output: String,
+}
+
+impl<'a> GitBisect<'a> {
+    fn start(repo_path: &'a Path, good: &str, bad: &str) -> Result<Self> {
+        let git = Git::default();
+        let bisect_start = git.stdout(repo_path, &["rev-parse", "--git-path", "BISECT_START"])?;
+        if repo_path.join(bisect_start).exists() {
+            anyhow::bail!(
+                "A bisection is already in progress in {}, finish it with 'git bisect reset'",
+                repo_path.display()
+            );
+        }
+        let last_output =
+            git.stdout(repo_path, &["bisect", "start", "--no-checkout", bad, good])?;
+        Ok(Self {
+            repo_path,
+            git,
+            last_output,
+        })
+    }
+
+    /// Mark a commit, returning git's output
+    fn mark(&self, verdict: BisectVerdict, commit: &str) -> Result<String> {
+        self.git
+            .stdout(self.repo_path, &["bisect", verdict.as_str(), commit])
+    }
+}
+
+impl Drop for GitBisect<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.git.run(self.repo_path, &["bisect", "reset"]) {
+            warn!("Failed to reset the bisection: {e:#}");
+        }
+    }
+}
+
+/// The commit `git bisect` reports as the first bad one, if it has finished
+fn first_bad_commit(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        line.strip_suffix(" is the first bad commit")
+            .map(|commit| commit.trim().to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bisect_verdict() {
+        assert_eq!(parse_threshold("3%").unwrap(), 0.03);
+        assert_eq!(parse_threshold("2.5").unwrap(), 0.025);
+        assert!(parse_threshold("0%").is_err());
+        assert!(parse_threshold("fast").is_err());
+
+        assert_eq!(
+            BisectVerdict::judge(DurationMs(102.0), DurationMs(100.0), 0.03),
+            BisectVerdict::Good
+        );
+        assert_eq!(
+            BisectVerdict::judge(DurationMs(104.0), DurationMs(100.0), 0.03),
+            BisectVerdict::Bad
+        );
+        assert_eq!(
+            BisectVerdict::judge(DurationMs(80.0), DurationMs(100.0), 0.03),
+            BisectVerdict::Good
+        );
+
+        let output = "abc123def456 is the first bad commit\ncommit abc123def456\n";
+        assert_eq!(first_bad_commit(output).as_deref(), Some("abc123def456"));
+        assert_eq!(
+            first_bad_commit("Bisecting: 3 revisions left to test after this"),
+            None
+        );
+    }
+}
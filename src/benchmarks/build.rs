@@ -1,16 +1,80 @@
 use anyhow::{Context, Result};
-use log::{debug, info};
-use std::path::PathBuf;
+use log::{debug, info, warn};
+use sha2::{Digest, Sha256};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-
-use crate::benchmarks::{binary_exists, RepoSource, RepositoryManager};
-use crate::config::GlobalConfig;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::benchmarks::git::Git;
+use crate::benchmarks::metadata::{detect_compiler, sha256_file, BinaryMetadata, BuildSettings};
+use crate::benchmarks::remote_build::RemoteBuild;
+use crate::benchmarks::repository::resolve_commits;
+use crate::benchmarks::{binary_exists, binary_id, get_binary_path, RepoSource, RepositoryManager};
+use crate::command::{CommandExecutor, ProcessRunner};
+use crate::config::{BuildVariant, DependsConfig, GlobalConfig, RemoteBuildConfig};
+use crate::error::BenchkitError;
 use crate::path_utils;
+use crate::telemetry;
+
+/// Build system used by a checked out commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildSystem {
+    /// CMake, used by Bitcoin Core since v29
+    CMake,
+    /// Autotools, used by Bitcoin Core up to and including v28
+    Autotools,
+}
+
+impl BuildSystem {
+    /// Detect the build system of a source tree
+    pub fn detect(source_dir: &Path) -> Result<Self> {
+        if source_dir.join("CMakeLists.txt").exists() {
+            Ok(BuildSystem::CMake)
+        } else if source_dir.join("configure.ac").exists() {
+            Ok(BuildSystem::Autotools)
+        } else {
+            anyhow::bail!(
+                "Could not detect build system in {}: neither CMakeLists.txt nor configure.ac found",
+                source_dir.display()
+            )
+        }
+    }
+
+    /// Location of the bitcoind binary relative to the build directory
+    fn binary_subpath(&self) -> String {
+        let dir = match self {
+            BuildSystem::CMake => "bin",
+            BuildSystem::Autotools => "src",
+        };
+        format!("{dir}/bitcoind{}", std::env::consts::EXE_SUFFIX)
+    }
+}
+
+/// Patches applied to every commit before building
+const BENCHKIT_PATCHES: &[&str] = &["0001-validation-assumeutxo-benchmarking-patches.patch"];
+
+/// Names of the benchkit patches applied before building
+pub fn benchkit_patches() -> Vec<String> {
+    BENCHKIT_PATCHES.iter().map(|p| p.to_string()).collect()
+}
 
 pub struct Builder {
     config: GlobalConfig,
     patches: Vec<String>,
     repo_manager: Option<RepositoryManager>,
+    git: Git,
+    /// Only build these of the configured commits, all of them if empty
+    selected_commits: Vec<String>,
+    /// Rebuild binaries which already exist, removing them first
+    force_rebuild: bool,
+    /// Parallel jobs of the compiler, the build tool's default (cmake) or the number of CPUs
+    /// (make) if unset
+    jobs: Option<usize>,
+    /// Build on this machine even if a build server is configured
+    local: bool,
 }
 
 impl Builder {
@@ -52,7 +116,7 @@ impl Builder {
         // Create RepoSource based on the corrected source
         let repo_source = RepoSource::new(&actual_source);
 
-        let patches = vec!["0001-validation-assumeutxo-benchmarking-patches.patch".to_string()];
+        let patches = benchkit_patches();
 
         match &repo_source {
             RepoSource::Local(path) => {
@@ -67,6 +131,11 @@ impl Builder {
                     config,
                     patches,
                     repo_manager: None,
+                    git: Git::default(),
+                    selected_commits: Vec::new(),
+                    force_rebuild: false,
+                    jobs: None,
+                    local: false,
                 })
             }
             RepoSource::Remote(url) => {
@@ -75,24 +144,70 @@ impl Builder {
                 // Use the scratch directory directly to avoid duplicate "repos" in path
                 let scratch_dir = config.bench.global.scratch.clone();
                 // Important: pass the raw URL string, not the processed path
-                let repo_manager = RepositoryManager::builder(url, &scratch_dir).build()?;
+                let repository = &config.bench.global.repository;
+                let repo_manager = RepositoryManager::builder(url, &scratch_dir)
+                    .remotes(repository.remotes.iter().cloned())
+                    .fetch_prune(repository.fetch_prune)
+                    .gc_interval(
+                        repository
+                            .gc_interval_days
+                            .map(|days| Duration::from_secs(days * 24 * 60 * 60)),
+                    )
+                    .gc_aggressive(repository.gc_aggressive)
+                    .max_cache_size(repository.max_cache_size_gb.map(|gb| gb << 30))
+                    .build()?;
 
                 Ok(Self {
                     config,
                     patches,
                     repo_manager: Some(repo_manager),
+                    git: Git::default(),
+                    selected_commits: Vec::new(),
+                    force_rebuild: false,
+                    jobs: None,
+                    local: false,
                 })
             }
         }
     }
 
+    /// Run git through a different process layer, e.g. a mock in tests
+    pub fn process_runner(mut self, process: Arc<dyn ProcessRunner>) -> Self {
+        self.git = Git::new(process);
+        self
+    }
+
+    /// Only build these commits, which must be in the benchmark config
+    pub fn commits(mut self, commits: Vec<String>) -> Self {
+        self.selected_commits = commits;
+        self
+    }
+
+    /// Rebuild binaries even if they exist and are up to date
+    pub fn force_rebuild(mut self, force_rebuild: bool) -> Self {
+        self.force_rebuild = force_rebuild;
+        self
+    }
+
+    pub fn jobs(mut self, jobs: Option<usize>) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Build on this machine, ignoring `build.remote`
+    pub fn local(mut self, local: bool) -> Self {
+        self.local = local;
+        self
+    }
+
     pub fn build(&mut self) -> Result<()> {
         debug!("Starting build");
+        if self.jobs == Some(0) {
+            anyhow::bail!("The number of build jobs cannot be zero");
+        }
         // If we're using a remote repository, ensure it's available
         let source_dir = if let Some(repo_manager) = &mut self.repo_manager {
-            let repo_path = repo_manager.ensure_repository_available()?;
-            repo_manager.validate_commits(&self.config.bench.global.commits)?;
-            repo_path
+            repo_manager.ensure_repository_available()?
         } else {
             // Using a local repository
             self.config.bench.global.source.clone()
@@ -105,39 +220,204 @@ impl Builder {
         if !git_dir.exists() {
             anyhow::bail!("Not a valid git repository: {}", source_dir.display());
         }
+        self.resolve_commits(&source_dir)?;
+        let commits = self.commits_to_build(&source_dir)?;
+        let commits = self.fetch_artifacts(commits)?;
+        if let Some(remote) = &self.config.bench.global.build.remote {
+            if !self.local {
+                return self.build_remote(remote, &commits);
+            }
+        }
 
         self.check_clean_worktree(&source_dir)?;
         // Get the initial reference to restore later
         let initial_ref = self.get_initial_ref(&source_dir)?;
 
+        let patch_changed = self.patch_changed_commits(&commits);
+        if !patch_changed.is_empty() {
+            warn!(
+                "Patches changed since these commits were built, rebuilding them: {}",
+                patch_changed.join(", ")
+            );
+        }
+
         // Build all commits up-front
-        for commit in &self.config.bench.global.commits {
-            if !binary_exists(&self.config.app.bin_dir, commit) {
-                info!("Building binary for commit {commit}");
-                self.build_commit(&source_dir, commit)?;
-            } else {
+        for commit in &commits {
+            if self.force_rebuild {
+                self.remove_binaries(commit)?;
+            }
+            let missing_variants = self.missing_variants(commit);
+            if missing_variants.is_empty() {
                 info!("Binary already exists for commit {commit}, skipping build");
-            };
+                continue;
+            }
+
+            info!("Building binary for commit {commit}");
+            let mut span = telemetry::span("build");
+            span.attr("benchkit.commit", commit);
+            span.record(self.build_commit(&source_dir, commit, &missing_variants))
+                .map_err(|source| BenchkitError::Build {
+                    commit: commit.clone(),
+                    source,
+                })?;
         }
 
         self.restore_git_state(&source_dir, &initial_ref)?;
         Ok(())
     }
 
-    fn check_clean_worktree(&self, source_dir: &PathBuf) -> Result<()> {
-        let unstaged = Command::new("git")
-            .current_dir(source_dir)
-            .args(["diff", "--quiet"])
-            .status()?;
+    /// Download the binaries of commits CI published, returning the commits left to build
+    fn fetch_artifacts(&self, commits: Vec<String>) -> Result<Vec<String>> {
+        let global = &self.config.bench.global;
+        let Some(artifacts) = &global.build.artifacts else {
+            return Ok(commits);
+        };
+        if !global.standard_build() {
+            info!("Not downloading CI artifacts since the build settings differ from CI's");
+            return Ok(commits);
+        }
+        let mut remaining = Vec::new();
+        for commit in commits {
+            if self.force_rebuild {
+                self.remove_binaries(&commit)?;
+            } else if self.missing_variants(&commit).is_empty() {
+                remaining.push(commit);
+                continue;
+            }
+            let mut span = telemetry::span("artifact");
+            span.attr("benchkit.commit", &commit);
+            if !span.record(artifacts.fetch(&commit, &global.scratch, &self.config.app.bin_dir))? {
+                remaining.push(commit);
+            }
+        }
+        Ok(remaining)
+    }
+
+    /// Build the commits whose binaries are missing or stale here on the build server, copying
+    /// the binaries back
+    fn build_remote(&self, remote: &RemoteBuildConfig, commits: &[String]) -> Result<()> {
+        if self.repo_manager.is_none() {
+            anyhow::bail!(
+                "build.remote needs a git URL as the source, {} is a local repository",
+                self.config.bench.global.source.display()
+            );
+        }
+        let mut needed = Vec::new();
+        let mut ids = Vec::new();
+        for commit in commits {
+            let variants: Vec<Option<String>> = if self.force_rebuild {
+                self.config.bench.global.variant_names()
+            } else {
+                self.missing_variants(commit)
+                    .into_iter()
+                    .map(|variant| variant.map(|v| v.name))
+                    .collect()
+            };
+            if variants.is_empty() {
+                info!("Binary already exists for commit {commit}, skipping build");
+                continue;
+            }
+            ids.extend(
+                variants
+                    .iter()
+                    .map(|variant| binary_id(commit, variant.as_deref())),
+            );
+            needed.push(commit.clone());
+        }
+        if needed.is_empty() {
+            return Ok(());
+        }
+
+        let mut span = telemetry::span("remote build");
+        span.attr("benchkit.build_host", &remote.host);
+        span.record(RemoteBuild::new(remote, &self.config.app.bin_dir).build(
+            &self.config.bench.path,
+            &needed,
+            &ids,
+            self.force_rebuild,
+            self.jobs,
+        ))
+    }
+
+    /// The resolved commits to build, the selected ones if any were selected
+    fn commits_to_build(&self, source_dir: &Path) -> Result<Vec<String>> {
+        let configured = &self.config.bench.global.commits;
+        if self.selected_commits.is_empty() {
+            return Ok(configured.clone());
+        }
+        let selected = match &self.repo_manager {
+            Some(repo_manager) => repo_manager.resolve_commits(&self.selected_commits)?,
+            None => resolve_commits(source_dir, &self.selected_commits)?,
+        };
+        for (name, hash) in self.selected_commits.iter().zip(&selected) {
+            if !configured.contains(hash) {
+                anyhow::bail!(
+                    "Commit {name} is not one of the benchmark config's commits: {}",
+                    configured.join(", ")
+                );
+            }
+        }
+        Ok(selected)
+    }
+
+    /// Remove the binaries of a commit, their metadata and build directories, so they are built
+    /// from scratch
+    fn remove_binaries(&self, commit: &str) -> Result<()> {
+        for variant in self.config.bench.global.variant_names() {
+            let id = binary_id(commit, variant.as_deref());
+            let binary_path = get_binary_path(&self.config.app.bin_dir, &id);
+            let metadata_path = BinaryMetadata::path_for(&binary_path);
+            for path in [&binary_path, &metadata_path] {
+                if path.exists() {
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Failed to remove {}", path.display()))?;
+                }
+            }
+            let build_dir = self.build_dir(&id);
+            if build_dir.exists() {
+                std::fs::remove_dir_all(&build_dir)
+                    .with_context(|| format!("Failed to remove {}", build_dir.display()))?;
+            }
+            info!("Removed the binary of {id} to rebuild it");
+        }
+        Ok(())
+    }
+
+    /// A command running `program` under the wrapper of the variant's build, if any
+    fn build_command(&self, program: impl AsRef<OsStr>, variant: Option<&BuildVariant>) -> Command {
+        match self.config.bench.global.build.wrapper_for(variant) {
+            [] => Command::new(program),
+            [wrapper, args @ ..] => {
+                let mut cmd = Command::new(wrapper);
+                cmd.args(args).arg(program);
+                cmd
+            }
+        }
+    }
+
+    /// Parallel jobs of make, the number of CPUs unless configured
+    fn make_jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+
+    fn check_clean_worktree(&self, source_dir: &Path) -> Result<()> {
+        let unstaged = self
+            .git
+            .output(source_dir, &["diff", "--quiet"], true)?
+            .status;
 
         if !unstaged.success() {
             anyhow::bail!("Worktree has unstaged changes. Please commit or stash them first.");
         }
 
-        let staged = Command::new("git")
-            .current_dir(source_dir)
-            .args(["diff", "--quiet", "--staged"])
-            .status()?;
+        let staged = self
+            .git
+            .output(source_dir, &["diff", "--quiet", "--staged"], true)?
+            .status;
 
         if !staged.success() {
             anyhow::bail!("Worktree has staged changes. Please commit or stash them first.");
@@ -145,23 +425,16 @@ impl Builder {
         Ok(())
     }
 
-    fn get_initial_ref(&self, source_dir: &PathBuf) -> Result<String> {
+    fn get_initial_ref(&self, source_dir: &Path) -> Result<String> {
         // Get the initial ref to check back out to afterwards
-        let output = Command::new("git")
-            .current_dir(source_dir)
-            .arg("symbolic-ref")
-            .arg("-q")
-            .arg("HEAD")
-            .output()?;
+        let output = self
+            .git
+            .output(source_dir, &["symbolic-ref", "-q", "HEAD"], true)?;
 
         if output.status.success() {
             Ok(String::from_utf8(output.stdout)?.trim().to_string())
         } else {
-            let output = Command::new("git")
-                .current_dir(source_dir)
-                .arg("rev-parse")
-                .arg("HEAD")
-                .output()?;
+            let output = self.git.output(source_dir, &["rev-parse", "HEAD"], true)?;
 
             if output.status.success() {
                 Ok(String::from_utf8(output.stdout)?.trim().to_string())
@@ -171,25 +444,262 @@ impl Builder {
         }
     }
 
-    fn build_commit(&self, source_dir: &PathBuf, original_commit: &str) -> Result<()> {
+    /// Settings the configuration requests for a variant, with the hash of the patches on disk
+    fn requested_settings(&self, variant: Option<&str>) -> BuildSettings {
+        BuildSettings::requested(&self.config.bench.global, variant, &self.patches)
+            .with_patch_hash(&self.config.app.patch_dir)
+    }
+
+    /// Commits with a binary built from patches whose contents changed since
+    fn patch_changed_commits(&self, commits: &[String]) -> Vec<String> {
+        commits
+            .iter()
+            .filter(|commit| {
+                self.config
+                    .bench
+                    .global
+                    .variant_names()
+                    .iter()
+                    .any(|variant| {
+                        let id = binary_id(commit, variant.as_deref());
+                        let binary_path = get_binary_path(&self.config.app.bin_dir, &id);
+                        let recorded = BinaryMetadata::load(&binary_path)
+                            .ok()
+                            .flatten()
+                            .and_then(|metadata| metadata.settings);
+                        recorded.is_some_and(|settings| {
+                            settings.patches_changed(&self.requested_settings(variant.as_deref()))
+                        })
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Build variants (or the default build, as `None`) whose binary is missing for a commit
+    fn missing_variants(&self, commit: &str) -> Vec<Option<BuildVariant>> {
+        let variants: Vec<Option<BuildVariant>> =
+            if self.config.bench.global.build.variants.is_empty() {
+                vec![None]
+            } else {
+                self.config
+                    .bench
+                    .global
+                    .build
+                    .variants
+                    .iter()
+                    .cloned()
+                    .map(Some)
+                    .collect()
+            };
+
+        variants
+            .into_iter()
+            .filter(|variant| {
+                let variant_name = variant.as_ref().map(|v| v.name.as_str());
+                let id = binary_id(commit, variant_name);
+                if !binary_exists(&self.config.app.bin_dir, &id) {
+                    return true;
+                }
+
+                let requested = self.requested_settings(variant_name);
+                let binary_path = get_binary_path(&self.config.app.bin_dir, &id);
+                match BinaryMetadata::stale_reasons(&binary_path, &requested) {
+                    Ok(reasons) if reasons.is_empty() => {
+                        debug!("Binary already exists for {id}, skipping build");
+                        false
+                    }
+                    Ok(reasons) => {
+                        warn!(
+                            "Binary for {id} is stale, rebuilding: {}",
+                            reasons.join("; ")
+                        );
+                        true
+                    }
+                    Err(e) => {
+                        warn!("Failed to read metadata for {id}, rebuilding: {e}");
+                        true
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn build_commit(
+        &self,
+        source_dir: &PathBuf,
+        original_commit: &str,
+        variants: &[Option<BuildVariant>],
+    ) -> Result<()> {
         self.checkout_commit(source_dir, original_commit)?;
         let patched_commit = self.apply_patches(source_dir)?;
         debug!("Commit hash after applying patches: {patched_commit}");
-        self.run_build(source_dir, original_commit)?;
-        self.copy_binary(original_commit)?;
+        if let Some(script) = &self.config.bench.global.build.pre_build {
+            self.run_build_hook(
+                "pre_build",
+                script,
+                source_dir,
+                &[("BENCHKIT_COMMIT", original_commit.to_string())],
+            )?;
+        }
+        let build_system = BuildSystem::detect(source_dir)?;
+        info!("Using {build_system:?} build system for commit {original_commit}");
+        let depends_prefix = match &self.config.bench.global.build.depends {
+            Some(depends) => Some(self.build_depends(source_dir, depends)?),
+            None => None,
+        };
+        for variant in variants {
+            let variant_name = variant.as_ref().map(|v| v.name.clone());
+            let id = binary_id(original_commit, variant_name.as_deref());
+            info!("Building {id}");
+            let start = Instant::now();
+            let configure_args = match build_system {
+                BuildSystem::CMake => {
+                    self.run_build(source_dir, &id, variant.as_ref(), depends_prefix.as_deref())?
+                }
+                BuildSystem::Autotools => self.run_autotools_build(
+                    source_dir,
+                    &id,
+                    variant.as_ref(),
+                    depends_prefix.as_deref(),
+                )?,
+            };
+            let build_duration = start.elapsed();
+
+            let (compiler, compiler_flags) = detect_compiler(&self.build_dir(&id), build_system);
+            let binary_path = self.copy_binary(&id, build_system)?;
+            if let Some(script) = &self.config.bench.global.build.post_build {
+                self.run_build_hook(
+                    "post_build",
+                    script,
+                    source_dir,
+                    &[
+                        ("BENCHKIT_COMMIT", original_commit.to_string()),
+                        ("BENCHKIT_BINARY_ID", id.clone()),
+                        ("BENCHKIT_BINARY", binary_path.display().to_string()),
+                        ("BENCHKIT_VARIANT", variant_name.clone().unwrap_or_default()),
+                    ],
+                )?;
+            }
+            let settings = self.requested_settings(variant_name.as_deref());
+            let metadata = BinaryMetadata {
+                commit: original_commit.to_string(),
+                variant: variant_name,
+                build_system: format!("{build_system:?}"),
+                compiler,
+                compiler_flags,
+                configure_args,
+                build_host: sysinfo::System::host_name().unwrap_or_default(),
+                build_duration_secs: build_duration.as_secs_f64(),
+                built_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default(),
+                sha256: sha256_file(&binary_path)?,
+                patched: !self.patches.is_empty(),
+                patches: self.patches.clone(),
+                settings: Some(settings),
+            };
+            metadata.save(&binary_path)?;
+        }
         Ok(())
     }
 
+    /// Run a pre_build or post_build hook through the shell in the source directory
+    fn run_build_hook(
+        &self,
+        name: &str,
+        script: &str,
+        source_dir: &Path,
+        env: &[(&str, String)],
+    ) -> Result<()> {
+        info!("Running {name} hook: {script}");
+        let mut builder = CommandExecutor::builder()
+            .name(name)
+            .working_dir(Some(source_dir))
+            .env_var("BENCHKIT_SOURCE_DIR", source_dir.display().to_string());
+        for (key, value) in env {
+            builder = builder.env_var(*key, value.clone());
+        }
+        builder
+            .build()?
+            .execute_shell(script)
+            .with_context(|| format!("{name} hook failed"))?;
+        Ok(())
+    }
+
+    /// Scratch build directory for a binary
+    fn build_dir(&self, binary_id: &str) -> PathBuf {
+        self.config
+            .bench
+            .global
+            .scratch
+            .join(format!("build-{binary_id}"))
+    }
+
+    /// Build the depends tree for the checked out commit and return its host prefix
+    ///
+    /// Downloaded sources and built packages are cached in the scratch directory, so
+    /// commits sharing package definitions only build them once.
+    fn build_depends(&self, source_dir: &Path, depends: &DependsConfig) -> Result<PathBuf> {
+        let depends_dir = source_dir.join("depends");
+        if !depends_dir.exists() {
+            anyhow::bail!("No depends directory found in {}", source_dir.display());
+        }
+
+        let host = match &depends.host {
+            Some(host) => host.clone(),
+            None => {
+                let output = self
+                    .build_command("sh", None)
+                    .current_dir(&depends_dir)
+                    .arg("./config.guess")
+                    .output()
+                    .context("Failed to run depends/config.guess")?;
+                if !output.status.success() {
+                    anyhow::bail!("depends/config.guess failed to determine the host");
+                }
+                String::from_utf8(output.stdout)?.trim().to_string()
+            }
+        };
+
+        let cache_dir = self.config.bench.global.scratch.join("depends-cache");
+        path_utils::ensure_directory(&cache_dir)?;
+        let jobs = self.make_jobs();
+
+        info!("Building depends for host {host}");
+        let mut cmd = self.build_command("make", None);
+        cmd.arg("-C")
+            .arg(&depends_dir)
+            .arg(format!("-j{jobs}"))
+            .arg(format!("HOST={host}"))
+            .arg(format!("BASE_CACHE={}", cache_dir.join("built").display()))
+            .arg(format!(
+                "SOURCES_PATH={}",
+                cache_dir.join("sources").display()
+            ))
+            .args(&depends.make_args);
+        let status = cmd.status().context("Failed to run make for depends")?;
+        if !status.success() {
+            anyhow::bail!("Building depends failed for host {host}");
+        }
+
+        let prefix = depends_dir.join(&host);
+        if !prefix.exists() {
+            anyhow::bail!("depends output not found at {}", prefix.display());
+        }
+        Ok(prefix)
+    }
+
     pub fn test_patch_commits(&mut self) -> Result<()> {
         // If we're using a remote repository, ensure it's available
         let source_dir = if let Some(repo_manager) = &mut self.repo_manager {
-            let repo_path = repo_manager.ensure_repository_available()?;
-            repo_manager.validate_commits(&self.config.bench.global.commits)?;
-            repo_path
+            repo_manager.ensure_repository_available()?
         } else {
             // For local repos, use the path directly
             self.config.bench.global.source.clone()
         };
+        self.resolve_commits(&source_dir)?;
 
         debug!("Testing patches on repository at: {source_dir:?}");
         self.check_clean_worktree(&source_dir)?;
@@ -204,85 +714,163 @@ impl Builder {
         Ok(())
     }
 
-    fn checkout_commit(&self, source_dir: &PathBuf, commit: &str) -> Result<()> {
-        let status = Command::new("git")
-            .current_dir(source_dir)
-            .arg("checkout")
-            .arg(commit)
-            .status()
-            .with_context(|| format!("Failed to checkout commit {commit}"))?;
-
-        if !status.success() {
-            anyhow::bail!("Git checkout failed for commit {}", commit);
-        }
+    /// Replace the configured commits with their canonical short hashes
+    ///
+    /// Remote repositories fetch any commits which are missing from the cached clone.
+    fn resolve_commits(&mut self, source_dir: &Path) -> Result<()> {
+        let commits = &self.config.bench.global.commits;
+        let resolved = match &self.repo_manager {
+            Some(repo_manager) => repo_manager.resolve_commits(commits)?,
+            None => resolve_commits(source_dir, commits)?,
+        };
+        self.config.bench.global.set_resolved_commits(resolved);
         Ok(())
     }
 
-    fn apply_patches(&self, source_dir: &PathBuf) -> Result<String> {
+    fn checkout_commit(&self, source_dir: &Path, commit: &str) -> Result<()> {
+        self.git
+            .run(source_dir, &["checkout", commit])
+            .with_context(|| format!("Git checkout failed for commit {commit}"))
+    }
+
+    fn apply_patches(&self, source_dir: &Path) -> Result<String> {
         self.process_patches(source_dir, false)?;
 
         // Get the current commit hash after applying patches
-        let output = Command::new("git")
-            .current_dir(source_dir)
-            .arg("rev-parse")
-            .arg("HEAD")
-            .output()
-            .context("Failed to get HEAD commit hash after applying patches")?;
-
-        if !output.status.success() {
-            anyhow::bail!("Failed to get HEAD commit hash after applying patches");
-        }
-
-        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+        self.git
+            .stdout(source_dir, &["rev-parse", "HEAD"])
+            .context("Failed to get HEAD commit hash after applying patches")
     }
 
-    fn test_patches(&self, source_dir: &PathBuf) -> Result<()> {
+    fn test_patches(&self, source_dir: &Path) -> Result<()> {
         self.process_patches(source_dir, true)
     }
 
-    fn download_patch(&self, patch_name: &str, patches_dir: &PathBuf) -> Result<()> {
+    /// Fetch a patch from the configured patch source
+    fn fetch_patch(&self, patch_name: &str) -> Result<Vec<u8>> {
+        let source = &self.config.bench.global.patches;
+
+        if let Some(local) = &source.local {
+            let path = local.join(patch_name);
+            return std::fs::read(&path)
+                .with_context(|| format!("Failed to read patch {}", path.display()));
+        }
+
         let client = reqwest::blocking::Client::new();
         let url = format!(
-            "https://raw.githubusercontent.com/bitcoin-dev-tools/benchkit/master/patches/{patch_name}"
+            "https://raw.githubusercontent.com/{}/{}/patches/{patch_name}",
+            source.repo, source.git_ref
         );
         let response = client.get(&url).send()?;
 
         if !response.status().is_success() {
             anyhow::bail!(
-                "Failed to download patch {}: {}",
+                "Failed to download patch {} from {}: {}",
                 patch_name,
+                url,
                 response.status()
             );
         }
 
-        let content = response.bytes()?;
-        let patch_path = patches_dir.join(patch_name);
+        Ok(response.bytes()?.to_vec())
+    }
 
-        // Ensure the patches directory exists
-        if !patches_dir.exists() {
-            std::fs::create_dir_all(patches_dir)?;
+    /// Check patch contents against the sha256 pinned in the bench config, if any
+    fn verify_patch_pin(&self, patch_name: &str, content: &[u8]) -> Result<()> {
+        let Some(pinned) = self.config.bench.global.patches.sha256.get(patch_name) else {
+            return Ok(());
+        };
+        let actual = format!("{:x}", Sha256::digest(content));
+        if !actual.eq_ignore_ascii_case(pinned) {
+            anyhow::bail!(
+                "Patch {} has sha256 {}, but {} is pinned in the benchmark config",
+                patch_name,
+                actual,
+                pinned
+            );
         }
+        Ok(())
+    }
 
-        std::fs::write(&patch_path, content)?;
-        info!("Successfully downloaded patch: {patch_name}");
+    /// Print a diff between the current patch file and its replacement
+    fn show_patch_diff(&self, current: &Path, new_content: &[u8]) -> Result<()> {
+        let mut new_file = tempfile::NamedTempFile::new()?;
+        new_file.write_all(new_content)?;
+
+        // git diff exits 1 when the files differ, so only failure to run is an error
+        let output = self
+            .git
+            .output(
+                current.parent().unwrap_or(Path::new(".")),
+                &[
+                    "diff",
+                    "--no-index",
+                    "--",
+                    &current.to_string_lossy(),
+                    &new_file.path().to_string_lossy(),
+                ],
+                true,
+            )
+            .context("Failed to diff patch")?;
+        std::io::stdout().write_all(&output.stdout)?;
         Ok(())
     }
 
-    pub fn update_patches(&self, force: bool) -> Result<()> {
+    /// Fetch patches from the configured source
+    ///
+    /// Existing patches are kept unless `force` is set, in which case changes are
+    /// shown as a diff. Changed patches are only overwritten if `replace_changed` is set too,
+    /// as the patch that was built with would be lost.
+    pub fn update_patches(&self, force: bool, replace_changed: bool) -> Result<()> {
+        let patches_dir = &self.config.app.patch_dir;
+        if !patches_dir.exists() {
+            std::fs::create_dir_all(patches_dir)?;
+        }
+
+        let mut kept = Vec::new();
         for patch in &self.patches {
-            let patch_path = &self.config.app.patch_dir.join(patch);
-            if !patch_path.exists() || force {
-                info!("Downloading patch: {patch}");
-                self.download_patch(patch, &self.config.app.patch_dir)?;
-            } else {
-                info!("Patch {patch} already exists, skipping download");
+            let patch_path = patches_dir.join(patch);
+            if patch_path.exists() && !force {
+                debug!("Patch {patch} already exists, skipping download");
+                let content = std::fs::read(&patch_path)?;
+                self.verify_patch_pin(patch, &content)?;
+                continue;
+            }
+
+            info!("Fetching patch: {patch}");
+            let content = self.fetch_patch(patch)?;
+            self.verify_patch_pin(patch, &content)?;
+
+            if patch_path.exists() {
+                if std::fs::read(&patch_path)? == content {
+                    info!("Patch {patch} is up to date");
+                    continue;
+                }
+                info!("Changes to patch {patch}:");
+                self.show_patch_diff(&patch_path, &content)?;
+                if !replace_changed {
+                    kept.push(patch.as_str());
+                    continue;
+                }
             }
+
+            std::fs::write(&patch_path, &content)?;
+            info!(
+                "Updated patch {patch} (sha256 {:x})",
+                Sha256::digest(&content)
+            );
+        }
+        if !kept.is_empty() {
+            anyhow::bail!(
+                "Kept changed patches {}, rerun with --yes to replace them",
+                kept.join(", ")
+            );
         }
         Ok(())
     }
 
-    fn process_patches(&self, source_dir: &PathBuf, check_only: bool) -> Result<()> {
-        self.update_patches(false)?;
+    fn process_patches(&self, source_dir: &Path, check_only: bool) -> Result<()> {
+        self.update_patches(false, false)?;
 
         let patches_dir = &self.config.app.patch_dir;
 
@@ -300,44 +888,37 @@ impl Builder {
             let operation = if check_only { "Testing" } else { "Applying" };
             info!("{} patch: {}", operation, patch_path.display());
 
-            let mut cmd = Command::new("git");
-            cmd.current_dir(source_dir);
-
-            if check_only {
-                cmd.arg("apply")
-                    .arg("--check")
-                    .arg("--verbose")
-                    .arg("--3way")
-                    .arg(patch_path.display().to_string());
+            let patch_arg = patch_path.display().to_string();
+            let args: &[&str] = if check_only {
+                &["apply", "--check", "--verbose", "--3way", &patch_arg]
             } else {
-                cmd.arg("-c")
-                    .arg("user.name=temp")
-                    .arg("-c")
-                    .arg("user.email=temp@temp.com")
-                    .arg("am")
-                    .arg("--3way")
-                    .arg("--no-signoff")
-                    .arg(patch_path.display().to_string());
-            }
+                &[
+                    "-c",
+                    "user.name=temp",
+                    "-c",
+                    "user.email=temp@temp.com",
+                    "am",
+                    "--3way",
+                    "--no-signoff",
+                    &patch_arg,
+                ]
+            };
 
-            let status = cmd.status().with_context(|| {
+            let output = self.git.output(source_dir, args, true).with_context(|| {
                 let action = if check_only { "test" } else { "apply" };
                 format!("Failed to {action} patch {patch}")
             })?;
 
-            if !status.success() {
+            if !output.status.success() {
                 if !check_only {
                     // If patch application fails, abort the am session
-                    let _ = Command::new("git")
-                        .current_dir(source_dir)
-                        .arg("am")
-                        .arg("--abort")
-                        .status();
+                    let _ = self.git.output(source_dir, &["am", "--abort"], true);
                 }
                 anyhow::bail!(
-                    "Failed to {} patch: {}",
+                    "Failed to {} patch: {}\n{}",
                     if check_only { "test" } else { "apply" },
-                    patch
+                    patch,
+                    String::from_utf8_lossy(&output.stderr).trim()
                 );
             }
 
@@ -347,28 +928,45 @@ impl Builder {
         Ok(())
     }
 
-    fn run_build(&self, source_dir: &PathBuf, commit_hash: &str) -> Result<()> {
+    fn run_build(
+        &self,
+        source_dir: &PathBuf,
+        commit_hash: &str,
+        variant: Option<&BuildVariant>,
+        depends_prefix: Option<&Path>,
+    ) -> Result<Vec<String>> {
         // Make a build-dir using the commit-hash
-        let dir = self
-            .config
-            .bench
-            .global
-            .scratch
-            .join(format!("build-{commit_hash}"));
+        let dir = self.build_dir(commit_hash);
 
         info!("Making build dir: {dir:?}");
         path_utils::ensure_directory(&dir)?;
         let canonical_dir = dir.canonicalize()?;
 
         // cmake configuration
-        let mut cmd = Command::new("cmake");
-        cmd.current_dir(source_dir).arg("-B").arg(&canonical_dir);
+        let mut configure_args = Vec::new();
+        if let Some(prefix) = depends_prefix {
+            let toolchain_file = prefix.join("toolchain.cmake");
+            if !toolchain_file.exists() {
+                anyhow::bail!(
+                    "depends toolchain file not found at {}",
+                    toolchain_file.display()
+                );
+            }
+            configure_args.push(format!("--toolchain={}", toolchain_file.display()));
+        }
         // Add custom build flags if configured
         if let Some(cmake_args) = &self.config.bench.global.cmake_build_args {
-            for arg in cmake_args {
-                cmd.arg(arg);
-            }
+            configure_args.extend(cmake_args.iter().cloned());
         }
+        // Variant arguments come last so they take precedence
+        if let Some(variant) = variant {
+            configure_args.extend(variant.cmake_args());
+        }
+        let mut cmd = self.build_command("cmake", variant);
+        cmd.current_dir(source_dir)
+            .arg("-B")
+            .arg(&canonical_dir)
+            .args(&configure_args);
         let config_status = cmd
             .status()
             .with_context(|| format!("Failed to configure cmake for commit {commit_hash}"))?;
@@ -377,36 +975,110 @@ impl Builder {
         }
 
         // cmake build
-        let mut cmd = Command::new("cmake");
+        let mut cmd = self.build_command("cmake", variant);
         cmd.current_dir(source_dir)
             .arg("--build")
             .arg(&canonical_dir)
             .arg("--target")
             .arg("bitcoind")
             .arg("--parallel");
+        if let Some(jobs) = self.jobs {
+            cmd.arg(jobs.to_string());
+        }
+        // Multi-config generators (e.g. Visual Studio) choose the build type at build time.
+        // Single-config generators ignore --config.
+        let build_type = variant
+            .and_then(|v| v.build_type.as_deref())
+            .unwrap_or("Release");
+        cmd.arg("--config").arg(build_type);
         let build_status = cmd
             .status()
             .with_context(|| format!("Failed to build bitcoind for commit {commit_hash}"))?;
         if !build_status.success() {
             anyhow::bail!("CMake build failed for commit {commit_hash}");
         }
-        Ok(())
+        Ok(configure_args)
     }
 
-    fn copy_binary(&self, commit_hash: &str) -> Result<()> {
-        let dir = self
-            .config
-            .bench
-            .global
-            .scratch
-            .join(format!("build-{commit_hash}"));
+    /// Build a pre-cmake commit using autogen.sh, configure and make
+    fn run_autotools_build(
+        &self,
+        source_dir: &PathBuf,
+        commit_hash: &str,
+        variant: Option<&BuildVariant>,
+        depends_prefix: Option<&Path>,
+    ) -> Result<Vec<String>> {
+        let dir = self.build_dir(commit_hash);
 
-        let src_path = dir.clone().join("bin/bitcoind");
-        let dest_path = self
-            .config
-            .app
-            .bin_dir
-            .join(format!("bitcoind-{commit_hash}"));
+        info!("Making build dir: {dir:?}");
+        path_utils::ensure_directory(&dir)?;
+        let canonical_dir = dir.canonicalize()?;
+
+        let status = self
+            .build_command("./autogen.sh", variant)
+            .current_dir(source_dir)
+            .status()
+            .with_context(|| format!("Failed to run autogen.sh for commit {commit_hash}"))?;
+        if !status.success() {
+            anyhow::bail!("autogen.sh failed for commit {}", commit_hash);
+        }
+
+        // Configure out-of-tree from within the build directory
+        let mut configure_args = self.config.bench.global.build.configure_args.clone();
+        if let Some(variant) = variant {
+            if variant.build_type.is_some() || !variant.cmake_build_args.is_empty() {
+                warn!(
+                    "Build variant {} has cmake options, which are ignored for autotools builds",
+                    variant.name
+                );
+            }
+            configure_args.extend(variant.configure_args.iter().cloned());
+        }
+        let mut cmd = self.build_command(source_dir.join("configure"), variant);
+        cmd.current_dir(&canonical_dir).args(&configure_args);
+        if let Some(prefix) = depends_prefix {
+            cmd.env("CONFIG_SITE", prefix.join("share").join("config.site"));
+        }
+        let config_status = cmd
+            .status()
+            .with_context(|| format!("Failed to run configure for commit {commit_hash}"))?;
+        if !config_status.success() {
+            anyhow::bail!("configure failed for commit {}", commit_hash);
+        }
+
+        let jobs = self.make_jobs();
+        let build_status = self
+            .build_command("make", variant)
+            .arg("-C")
+            .arg(canonical_dir.join("src"))
+            .arg(format!("-j{jobs}"))
+            .arg("bitcoind")
+            .status()
+            .with_context(|| format!("Failed to build bitcoind for commit {commit_hash}"))?;
+        if !build_status.success() {
+            anyhow::bail!("make failed for commit {commit_hash}");
+        }
+        Ok(configure_args)
+    }
+
+    fn copy_binary(&self, commit_hash: &str, build_system: BuildSystem) -> Result<PathBuf> {
+        let dir = self.build_dir(commit_hash);
+
+        let mut src_path = dir.join(build_system.binary_subpath());
+        if !src_path.exists() {
+            // Multi-config generators place binaries in a per-configuration subdirectory
+            let file_name = src_path.file_name().unwrap_or_default().to_os_string();
+            if let Some(found) = std::fs::read_dir(dir.join("bin"))
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().join(&file_name))
+                .find(|path| path.exists())
+            {
+                src_path = found;
+            }
+        }
+        let dest_path = get_binary_path(&self.config.app.bin_dir, commit_hash);
 
         if let Some(parent) = dest_path.parent() {
             path_utils::ensure_directory(parent)?;
@@ -419,21 +1091,347 @@ impl Builder {
             format!("Failed to cleanup extracted files for commit {commit_hash} from {dir:?}")
         })?;
 
-        Ok(())
+        Ok(dest_path)
     }
 
     fn restore_git_state(&self, source_dir: &PathBuf, initial_ref: &str) -> Result<()> {
         debug!("restoring git state of {source_dir:?}");
-        let status = Command::new("git")
-            .current_dir(source_dir)
-            .arg("checkout")
-            .arg(initial_ref)
-            .status()
-            .with_context(|| format!("Failed to restore git state to {initial_ref}"))?;
+        self.git
+            .run(source_dir, &["checkout", initial_ref])
+            .with_context(|| format!("Failed to restore git state to {initial_ref}"))
+    }
 
-        if !status.success() {
-            anyhow::bail!("Failed to restore git state");
+    /// Apply benchkit patches to a local worktree on a new branch
+    ///
+    /// The ref that was checked out and the patched commit are recorded in the branch's git
+    /// config so that `revert_worktree_patches` can undo the patches later.
+    pub fn apply_worktree_patches(&self, worktree: &PathBuf, branch: &str) -> Result<()> {
+        self.check_clean_worktree(worktree)?;
+
+        if self
+            .git
+            .stdout(worktree, &["branch", "--list", branch])?
+            .is_empty()
+        {
+            debug!("Creating branch {branch} in {worktree:?}");
+        } else {
+            anyhow::bail!(
+                "Branch {} already exists. Run 'benchkit patch revert' first or choose another branch.",
+                branch
+            );
+        }
+
+        let initial_ref = self.get_initial_ref(worktree)?;
+        let base_commit = self.git.stdout(worktree, &["rev-parse", "HEAD"])?;
+
+        self.git.run(worktree, &["checkout", "-b", branch])?;
+        let patched_commit = match self.apply_patches(worktree) {
+            Ok(commit) => commit,
+            Err(e) => {
+                self.restore_git_state(worktree, initial_ref.trim_start_matches("refs/heads/"))?;
+                self.git.run(worktree, &["branch", "-D", branch])?;
+                return Err(e);
+            }
+        };
+
+        let section = format!("branch.{branch}");
+        self.git.run(
+            worktree,
+            &["config", &format!("{section}.benchkitRef"), &initial_ref],
+        )?;
+        self.git.run(
+            worktree,
+            &["config", &format!("{section}.benchkitBase"), &base_commit],
+        )?;
+        self.git.run(
+            worktree,
+            &[
+                "config",
+                &format!("{section}.benchkitPatched"),
+                &patched_commit,
+            ],
+        )?;
+
+        info!("Applied benchkit patches on branch {branch} (based on {base_commit})");
+        Ok(())
+    }
+
+    /// Remove benchkit patches from a branch created by `apply_worktree_patches`
+    ///
+    /// If no commits were made on top of the patches the branch is deleted and the original
+    /// ref checked out again. Otherwise the additional commits are rebased onto the original
+    /// base, dropping only the patch commits.
+    pub fn revert_worktree_patches(&self, worktree: &PathBuf, branch: &str) -> Result<()> {
+        self.check_clean_worktree(worktree)?;
+
+        let section = format!("branch.{branch}");
+        let read_config = |key: &str| {
+            self.git
+                .stdout(worktree, &["config", &format!("{section}.{key}")])
+                .with_context(|| {
+                    format!("Branch {branch} was not created by 'benchkit patch apply'")
+                })
+        };
+        let initial_ref = read_config("benchkitRef")?;
+        let base_commit = read_config("benchkitBase")?;
+        let patched_commit = read_config("benchkitPatched")?;
+
+        let branch_head = self
+            .git
+            .stdout(worktree, &["rev-parse", &format!("refs/heads/{branch}")])?;
+        if branch_head == patched_commit {
+            self.restore_git_state(worktree, initial_ref.trim_start_matches("refs/heads/"))?;
+            self.git.run(worktree, &["branch", "-D", branch])?;
+            info!("Removed branch {branch} and restored {initial_ref}");
+            return Ok(());
         }
+
+        self.git
+            .run(
+                worktree,
+                &["rebase", "--onto", &base_commit, &patched_commit, branch],
+            )
+            .with_context(|| {
+                format!("Failed to remove patches from {branch}; resolve the rebase manually")
+            })?;
+        for key in ["benchkitRef", "benchkitBase", "benchkitPatched"] {
+            self.git.run(
+                worktree,
+                &["config", "--unset", &format!("{section}.{key}")],
+            )?;
+        }
+        info!("Removed benchkit patches from branch {branch}, keeping later commits");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::MockProcessRunner;
+    use tempfile::tempdir;
+
+    fn builder(dir: &Path) -> Builder {
+        std::fs::create_dir_all(dir.join("bitcoin")).unwrap();
+        std::fs::write(
+            dir.join("config.yml"),
+            "home_dir: ./home\nbin_dir: ./bin\npatch_dir: ./patches\nsnapshot_dir: ./snapshots\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("benchmark.yml"),
+            "global:\n  source: ./bitcoin\n  scratch: ./scratch\n  commits: [main]\n  \
+             tmp_data_dir: ./data\nbenchmarks:\n  - name: ibd\n    network: signet\n    \
+             benchmark:\n      command: bitcoind\n",
+        )
+        .unwrap();
+        let config = GlobalConfig {
+            app: crate::config::load_app_config(&dir.join("config.yml")).unwrap(),
+            bench: crate::config::load_bench_config(&dir.join("benchmark.yml")).unwrap(),
+        };
+        Builder::new(config).unwrap()
+    }
+
+    #[test]
+    fn test_update_patches() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("upstream");
+        std::fs::create_dir(&source).unwrap();
+        let git = Arc::new(MockProcessRunner::new().respond("git diff", 1, "-v1\n+v2\n"));
+        let mut builder = builder(temp_dir.path()).process_runner(git.clone());
+        builder.config.app.patch_dir = temp_dir.path().join("patches");
+        builder.config.bench.global.patches.local = Some(source.clone());
+        let name = &BENCHKIT_PATCHES[0];
+        let installed = builder.config.app.patch_dir.join(name);
+
+        std::fs::write(source.join(name), "v1").unwrap();
+        builder.update_patches(true, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&installed).unwrap(), "v1");
+
+        // A changed patch is only replaced when asked to
+        std::fs::write(source.join(name), "v2").unwrap();
+        let err = builder.update_patches(true, false).unwrap_err().to_string();
+        assert!(err.contains("--yes"), "{err}");
+        assert_eq!(std::fs::read_to_string(&installed).unwrap(), "v1");
+        let diff = &git.command_lines()[0];
+        assert!(
+            diff.starts_with(&format!("git diff --no-index -- {}", installed.display())),
+            "{diff}"
+        );
+        builder.update_patches(true, true).unwrap();
+        assert_eq!(std::fs::read_to_string(&installed).unwrap(), "v2");
+    }
+
+    #[test]
+    fn test_revert_worktree_patches() {
+        let temp_dir = tempdir().unwrap();
+        let worktree = temp_dir.path().join("bitcoin");
+        let git = MockProcessRunner::new()
+            .respond(
+                "git config branch.bench.benchkitRef",
+                0,
+                "refs/heads/main\n",
+            )
+            .respond("git config branch.bench.benchkitBase", 0, "base\n")
+            .respond("git config branch.bench.benchkitPatched", 0, "patched\n")
+            .respond("git rev-parse refs/heads/bench", 0, "patched\n");
+        let git = Arc::new(git);
+        let builder = builder(temp_dir.path()).process_runner(git.clone());
+
+        // Without commits on top of the patches the branch is removed
+        builder.revert_worktree_patches(&worktree, "bench").unwrap();
+        let calls = git.command_lines();
+        assert_eq!(
+            calls[calls.len() - 2..],
+            ["git checkout main", "git branch -D bench"]
+        );
+        assert_eq!(
+            git.calls()[0].working_dir.as_deref(),
+            Some(worktree.to_str().unwrap())
+        );
+
+        // Later commits are kept, rebased onto the original base
+        let git = Arc::new(
+            MockProcessRunner::new()
+                .respond("git config branch.bench.benchkit", 0, "base\n")
+                .respond("git config branch.bench.benchkitPatched", 0, "patched\n")
+                .respond("git rev-parse refs/heads/bench", 0, "later\n"),
+        );
+        let builder = builder.process_runner(git.clone());
+        builder.revert_worktree_patches(&worktree, "bench").unwrap();
+        let calls = git.command_lines();
+        assert!(calls.contains(&"git rebase --onto base patched bench".to_string()));
+        assert_eq!(
+            calls.last().unwrap(),
+            "git config --unset branch.bench.benchkitPatched"
+        );
+
+        // A dirty worktree is left alone
+        let git = Arc::new(MockProcessRunner::new().respond("git diff --quiet", 1, ""));
+        let builder = builder.process_runner(git.clone());
+        let err = builder
+            .revert_worktree_patches(&worktree, "bench")
+            .unwrap_err();
+        assert!(err.to_string().contains("unstaged changes"), "{err}");
+        assert_eq!(git.command_lines(), ["git diff --quiet"]);
+    }
+
+    #[test]
+    fn test_selected_commits_and_force_rebuild() {
+        let temp_dir = tempdir().unwrap();
+        let mut builder = builder(temp_dir.path());
+        let source_dir = temp_dir.path().join("bitcoin");
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .current_dir(&source_dir)
+                .args([
+                    "-c",
+                    "user.name=bench",
+                    "-c",
+                    "user.email=bench@example.com",
+                ])
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?}");
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+        git(&["tag", "first"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "second"]);
+        builder.resolve_commits(&source_dir).unwrap();
+        let main = builder.config.bench.global.commits[0].clone();
+
+        assert_eq!(
+            builder.commits_to_build(&source_dir).unwrap(),
+            vec![main.clone()]
+        );
+        let mut builder = builder.commits(vec!["main".to_string()]);
+        assert_eq!(
+            builder.commits_to_build(&source_dir).unwrap(),
+            vec![main.clone()]
+        );
+        builder = builder.commits(vec!["first".to_string()]);
+        let err = builder.commits_to_build(&source_dir).unwrap_err();
+        assert!(err.to_string().contains("Commit first is not one"), "{err}");
+
+        // Forcing a rebuild removes the binary, its metadata and build directory
+        let binary = get_binary_path(&builder.config.app.bin_dir, &main);
+        std::fs::create_dir_all(binary.parent().unwrap()).unwrap();
+        std::fs::write(&binary, "").unwrap();
+        std::fs::write(BinaryMetadata::path_for(&binary), "{}").unwrap();
+        std::fs::create_dir_all(builder.build_dir(&main)).unwrap();
+        builder.remove_binaries(&main).unwrap();
+        assert!(!binary.exists());
+        assert!(!BinaryMetadata::path_for(&binary).exists());
+        assert!(!builder.build_dir(&main).exists());
+
+        assert!(builder.make_jobs() >= 1);
+        let builder = builder.jobs(Some(3));
+        assert_eq!(builder.make_jobs(), 3);
+    }
+
+    #[test]
+    fn test_build_wrapper() {
+        let temp_dir = tempdir().unwrap();
+        let mut builder = builder(temp_dir.path());
+        let args = |cmd: &Command| {
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(args(&builder.build_command("cmake", None)), ["cmake"]);
+        let unwrapped = builder.requested_settings(None);
+
+        builder.config.bench.global.build.wrapper =
+            ["nix", "develop", ".#gcc", "-c"].map(String::from).to_vec();
+        assert_eq!(
+            args(&builder.build_command("cmake", None)),
+            ["nix", "develop", ".#gcc", "-c", "cmake"]
+        );
+        let reasons = unwrapped.differences(&builder.requested_settings(None));
+        assert!(reasons[0].starts_with("wrapper"), "{reasons:?}");
+
+        // A variant's own wrapper replaces the global one, an empty one disables it
+        let mut variant = BuildVariant {
+            name: "clang".to_string(),
+            build_type: None,
+            cmake_build_args: Vec::new(),
+            configure_args: Vec::new(),
+            wrapper: Some(
+                ["guix", "shell", "clang-toolchain", "--"]
+                    .map(String::from)
+                    .to_vec(),
+            ),
+        };
+        assert_eq!(
+            args(&builder.build_command("make", Some(&variant))),
+            ["guix", "shell", "clang-toolchain", "--", "make"]
+        );
+        variant.wrapper = Some(Vec::new());
+        assert_eq!(
+            args(&builder.build_command("make", Some(&variant))),
+            ["make"]
+        );
+    }
+
+    #[test]
+    fn test_detect_build_system() {
+        let temp_dir = tempdir().unwrap();
+        assert!(BuildSystem::detect(temp_dir.path()).is_err());
+
+        std::fs::write(temp_dir.path().join("configure.ac"), "").unwrap();
+        assert_eq!(
+            BuildSystem::detect(temp_dir.path()).unwrap(),
+            BuildSystem::Autotools
+        );
+
+        // Transitional trees containing both are built with cmake
+        std::fs::write(temp_dir.path().join("CMakeLists.txt"), "").unwrap();
+        assert_eq!(
+            BuildSystem::detect(temp_dir.path()).unwrap(),
+            BuildSystem::CMake
+        );
+    }
+}
@@ -0,0 +1,377 @@
+//! SVG charts comparing the profiles of benchmark results
+//!
+//! Each chart overlays one line per parameter combination (e.g. per commit) on shared axes. The
+//! line of a combination is its median run, so a single disturbed run doesn't distort it.
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+use regex::Regex;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::benchmarks::parameters::ParameterUtils;
+use crate::benchmarks::profiler::analysis::resample;
+use crate::benchmarks::report::display_label;
+use crate::benchmarks::results::{BenchmarkResult, RunResult};
+
+const WIDTH: f64 = 960.0;
+const HEIGHT: f64 = 540.0;
+const MARGIN_LEFT: f64 = 90.0;
+const MARGIN_RIGHT: f64 = 30.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 60.0;
+/// Height of a legend entry below the plot
+const LEGEND_LINE: f64 = 18.0;
+
+/// Line colors, cycled through for more series
+const COLORS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+];
+
+/// A named line of (x, y) points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Series {
+    pub label: String,
+    pub points: Vec<(f64, f64)>,
+}
+
+/// A line chart of several series sharing the same axes
+#[derive(Debug, Clone)]
+pub struct LineChart {
+    pub title: String,
+    pub x_label: String,
+    pub y_label: String,
+    pub series: Vec<Series>,
+}
+
+impl LineChart {
+    /// Render the chart as an SVG document
+    pub fn to_svg(&self) -> String {
+        let points = self.series.iter().flat_map(|s| &s.points);
+        let x_max = points.clone().map(|p| p.0).fold(0.0, f64::max);
+        let y_max = points.map(|p| p.1).fold(0.0, f64::max);
+        let (x_step, x_max) = nice_axis(x_max);
+        let (y_step, y_max) = nice_axis(y_max);
+
+        let height = HEIGHT + LEGEND_LINE * self.series.len() as f64;
+        let plot_width = WIDTH - MARGIN_LEFT - MARGIN_RIGHT;
+        let plot_height = HEIGHT - MARGIN_TOP - MARGIN_BOTTOM;
+        let x_pos = |x: f64| MARGIN_LEFT + x / x_max * plot_width;
+        let y_pos = |y: f64| MARGIN_TOP + plot_height - y / y_max * plot_height;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" font-family="sans-serif" font-size="12">"#
+        );
+        let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+        let _ = writeln!(
+            svg,
+            r#"<text x="{}" y="24" text-anchor="middle" font-size="16">{}</text>"#,
+            WIDTH / 2.0,
+            escape(&self.title)
+        );
+
+        // Grid lines and tick labels
+        for i in 0..=(x_max / x_step).round() as usize {
+            let x = i as f64 * x_step;
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{0:.1}" y1="{1}" x2="{0:.1}" y2="{2}" stroke="#ddd"/><text x="{0:.1}" y="{3}" text-anchor="middle">{4}</text>"##,
+                x_pos(x),
+                MARGIN_TOP,
+                MARGIN_TOP + plot_height,
+                MARGIN_TOP + plot_height + 16.0,
+                format_tick(x, x_step)
+            );
+        }
+        for i in 0..=(y_max / y_step).round() as usize {
+            let y = i as f64 * y_step;
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{0}" y1="{1:.1}" x2="{2}" y2="{1:.1}" stroke="#ddd"/><text x="{3}" y="{4:.1}" text-anchor="end">{5}</text>"##,
+                MARGIN_LEFT,
+                y_pos(y),
+                MARGIN_LEFT + plot_width,
+                MARGIN_LEFT - 6.0,
+                y_pos(y) + 4.0,
+                format_tick(y, y_step)
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r#"<rect x="{MARGIN_LEFT}" y="{MARGIN_TOP}" width="{plot_width}" height="{plot_height}" fill="none" stroke="black"/>"#
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+            MARGIN_LEFT + plot_width / 2.0,
+            MARGIN_TOP + plot_height + 40.0,
+            escape(&self.x_label)
+        );
+        let _ = writeln!(
+            svg,
+            r#"<text transform="translate(20 {:.1}) rotate(-90)" text-anchor="middle">{}</text>"#,
+            MARGIN_TOP + plot_height / 2.0,
+            escape(&self.y_label)
+        );
+
+        for (i, series) in self.series.iter().enumerate() {
+            let color = COLORS[i % COLORS.len()];
+            let points: Vec<String> = series
+                .points
+                .iter()
+                .map(|&(x, y)| format!("{:.1},{:.1}", x_pos(x), y_pos(y)))
+                .collect();
+            let _ = writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="{color}" stroke-width="1.5"/>"#,
+                points.join(" ")
+            );
+
+            let legend_y = HEIGHT + LEGEND_LINE * i as f64;
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{MARGIN_LEFT}" y1="{legend_y}" x2="{}" y2="{legend_y}" stroke="{color}" stroke-width="3"/><text x="{}" y="{}">{}</text>"#,
+                MARGIN_LEFT + 20.0,
+                MARGIN_LEFT + 26.0,
+                legend_y + 4.0,
+                escape(&series.label)
+            );
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// A round tick step and the axis maximum (a multiple of it) covering `max`
+fn nice_axis(max: f64) -> (f64, f64) {
+    if max <= 0.0 || !max.is_finite() {
+        return (1.0, 1.0);
+    }
+    let rough = max / 5.0;
+    let magnitude = 10f64.powf(rough.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= rough)
+        .unwrap_or(10.0 * magnitude);
+    (step, (max / step).ceil() * step)
+}
+
+/// Format a tick value with as many decimals as its step needs
+fn format_tick(value: f64, step: f64) -> String {
+    let decimals = if step >= 1.0 {
+        0
+    } else {
+        (-step.log10().floor()) as usize
+    };
+    format!("{value:.decimals$}")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The profiled run with the median duration, representing a parameter combination
+fn median_profiled_run(result: &BenchmarkResult) -> Option<&RunResult> {
+    let mut runs: Vec<&RunResult> = result
+        .runs
+        .iter()
+        .filter(|run| run.profile.as_ref().is_some_and(|p| !p.samples.is_empty()))
+        .collect();
+    runs.sort_by(|a, b| a.duration_ms.total_cmp(&b.duration_ms));
+    runs.get(runs.len().saturating_sub(1) / 2).copied()
+}
+
+/// Seconds since the first log line and the new height of every UpdateTip line in a debug.log
+pub fn height_progress(debug_log: &str) -> Vec<(f64, f64)> {
+    let update_tip = Regex::new(r"UpdateTip: .*\bheight=(\d+)").unwrap();
+    let mut start = None;
+    let mut points = Vec::new();
+    for line in debug_log.lines() {
+        let Some(time) = line.split_whitespace().next().and_then(parse_log_time) else {
+            continue;
+        };
+        let start = *start.get_or_insert(time);
+        if let Some(caps) = update_tip.captures(line) {
+            if let Ok(height) = caps[1].parse::<f64>() {
+                points.push((time - start, height));
+            }
+        }
+    }
+    points
+}
+
+/// Seconds since the Unix epoch of a debug.log timestamp like `2025-01-31T12:00:00.123456Z`
+fn parse_log_time(timestamp: &str) -> Option<f64> {
+    let timestamp = timestamp.strip_suffix('Z')?;
+    let (date, time) = timestamp.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':');
+    let hours: f64 = time.next()?.parse().ok()?;
+    let minutes: f64 = time.next()?.parse().ok()?;
+    let seconds: f64 = time.next()?.parse().ok()?;
+
+    // Days since the epoch of a proleptic Gregorian date (Howard Hinnant's days_from_civil)
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    Some(days as f64 * 86400.0 + hours * 3600.0 + minutes * 60.0 + seconds)
+}
+
+/// Write CPU, memory, disk and height progress charts for a set of results into `charts_dir`
+///
+/// `results_dir` is the output directory the results were written to, where the debug.log of
+/// each run is looked up. Returns the paths of the charts written.
+pub fn write_charts(
+    results: &[BenchmarkResult],
+    results_dir: &Path,
+    charts_dir: &Path,
+) -> Result<Vec<PathBuf>> {
+    let mut cpu = Vec::new();
+    let mut memory = Vec::new();
+    let mut disk_read = Vec::new();
+    let mut disk_write = Vec::new();
+    let mut height = Vec::new();
+
+    for result in results {
+        let Some(run) = median_profiled_run(result) else {
+            debug!("No profiled runs for {}", display_label(result));
+            continue;
+        };
+        let label = display_label(result);
+        let profile = resample(run.profile.as_ref().unwrap(), 1.0);
+        let times = profile.times();
+        let series = |values: &[f64], scale: f64| Series {
+            label: label.clone(),
+            points: times
+                .iter()
+                .zip(values)
+                .map(|(&t, &v)| (t, v / scale))
+                .collect(),
+        };
+        cpu.push(series(&profile.cpu_usage, 1.0));
+        memory.push(series(&profile.memory, 1024.0 * 1024.0));
+        disk_read.push(series(&profile.disk_read, 1024.0 * 1024.0));
+        disk_write.push(series(&profile.disk_write, 1024.0 * 1024.0));
+
+        // Results recorded before their out_dir was have the default layout
+        let combination_dir = result.out_dir.clone().unwrap_or_else(|| {
+            let commit = result.parameters.get("commit").cloned().unwrap_or_default();
+            PathBuf::from(commit).join(ParameterUtils::params_to_dirname(&result.parameters))
+        });
+        let debug_log = results_dir
+            .join(combination_dir)
+            .join(run.iteration.to_string())
+            .join("debug.log");
+        match std::fs::read_to_string(&debug_log) {
+            Ok(log) => {
+                let points = height_progress(&log);
+                if !points.is_empty() {
+                    height.push(Series { label, points });
+                }
+            }
+            Err(e) => debug!("No height progress from {}: {e}", debug_log.display()),
+        }
+    }
+
+    if cpu.is_empty() {
+        anyhow::bail!("No profiled runs to chart, run benchmarks with `profile: true`");
+    }
+
+    let charts = [
+        ("cpu.svg", "CPU usage", "CPU (% of one core)", cpu),
+        ("memory.svg", "Resident memory", "RSS (MiB)", memory),
+        (
+            "disk_read.svg",
+            "Disk reads",
+            "Read (MiB per sample)",
+            disk_read,
+        ),
+        (
+            "disk_write.svg",
+            "Disk writes",
+            "Written (MiB per sample)",
+            disk_write,
+        ),
+        ("height.svg", "Block height progress", "Height", height),
+    ];
+
+    std::fs::create_dir_all(charts_dir)
+        .with_context(|| format!("Failed to create {}", charts_dir.display()))?;
+    let mut written = Vec::new();
+    for (file, title, y_label, series) in charts {
+        if series.is_empty() {
+            continue;
+        }
+        let chart = LineChart {
+            title: title.to_string(),
+            x_label: "Time (s)".to_string(),
+            y_label: y_label.to_string(),
+            series,
+        };
+        let path = charts_dir.join(file);
+        std::fs::write(&path, chart.to_svg())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("Wrote {}", path.display());
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nice_axis() {
+        assert_eq!(nice_axis(0.0), (1.0, 1.0));
+        assert_eq!(nice_axis(97.0), (20.0, 100.0));
+        assert_eq!(nice_axis(430.0), (100.0, 500.0));
+        assert_eq!(nice_axis(0.7), (0.2, 0.8));
+        assert_eq!(format_tick(0.4, 0.2), "0.4");
+        assert_eq!(format_tick(800000.0, 200000.0), "800000");
+    }
+
+    #[test]
+    fn test_height_progress() {
+        let log = "\
+2025-01-31T12:00:00Z Bitcoin Core version v28.1.0 (release build)
+2025-01-31T12:00:05.500000Z UpdateTip: new best=00000000839a height=1 version=0x00000001 log2_work=33.000022 tx=2 date='2009-01-09T02:54:25Z' progress=0.000000 cache=0.3MiB(1txo)
+garbage line
+2025-01-31T12:01:00Z [msghand] UpdateTip: new best=000000006a62 height=2 version=0x00000001
+";
+        assert_eq!(height_progress(log), vec![(5.5, 1.0), (60.0, 2.0)]);
+        assert_eq!(parse_log_time("1970-01-02T00:00:01Z"), Some(86401.0));
+        assert_eq!(parse_log_time("2000-03-01T00:00:00Z"), Some(951868800.0));
+        assert_eq!(parse_log_time("not-a-time"), None);
+    }
+
+    #[test]
+    fn test_svg_escapes_labels() {
+        let chart = LineChart {
+            title: "CPU <usage>".to_string(),
+            x_label: "Time (s)".to_string(),
+            y_label: "CPU".to_string(),
+            series: vec![Series {
+                label: "commit=a & b".to_string(),
+                points: vec![(0.0, 10.0), (1.0, 20.0)],
+            }],
+        };
+        let svg = chart.to_svg();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("CPU &lt;usage&gt;"));
+        assert!(svg.contains("commit=a &amp; b"));
+        assert!(svg.contains("<polyline"));
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::benchmarks::charts::height_progress;
+use crate::benchmarks::results::RunResult;
+use crate::types::DurationMs;
+
+/// File in a run's output directory recording its checkpoints
+pub const CHECKPOINT_FILE: &str = "checkpoints.json";
+
+/// Directory in a run's output directory the debug.log of each checkpoint is archived in
+pub const CHECKPOINT_LOG_DIR: &str = "checkpoints";
+
+/// How long a node stopped at a checkpoint may take to exit, as flushing a large dbcache to
+/// disk can take many minutes
+pub const CHECKPOINT_GRACE_PERIOD: Duration = Duration::from_secs(60 * 60);
+
+/// Time-sliced execution of long runs: the node is stopped and restarted every `interval_hours`,
+/// recording its progress so the run can resume after benchkit or the machine restarts
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CheckpointConfig {
+    /// Hours the node runs for between checkpoints
+    pub interval_hours: f64,
+}
+
+impl CheckpointConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !(self.interval_hours.is_finite() && self.interval_hours > 0.0) {
+            anyhow::bail!(
+                "checkpoint interval_hours must be positive, got {}",
+                self.interval_hours
+            );
+        }
+        Ok(())
+    }
+
+    /// Time the node runs for between checkpoints
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs_f64(self.interval_hours * 3600.0)
+    }
+}
+
+/// Progress of a run when the node was stopped, at a checkpoint or because it exited
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Checkpoint {
+    /// Time the node ran for in all segments up to this point, not counting restarts
+    pub elapsed_ms: DurationMs,
+    /// Height of the chain tip, if the node logged one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+}
+
+/// Checkpoints of a run so far, saved after each so an interrupted run can resume
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CheckpointState {
+    pub checkpoints: Vec<Checkpoint>,
+    /// Result of the run once the node exited by itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<RunResult>,
+}
+
+impl CheckpointState {
+    /// Load the state saved in a run's output directory, if there is one
+    pub fn load(run_out_dir: &Path) -> Result<Option<Self>> {
+        let path = run_out_dir.join(CHECKPOINT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let state = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(state))
+    }
+
+    /// Save the state to a run's output directory, replacing the previous state atomically so a
+    /// crash can't leave it half written
+    pub fn save(&self, run_out_dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(run_out_dir)?;
+        let path = run_out_dir.join(CHECKPOINT_FILE);
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!(
+            "Saved {} checkpoints to {}",
+            self.checkpoints.len(),
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// Time the node ran for up to the last checkpoint
+    pub fn elapsed(&self) -> Duration {
+        self.checkpoints.last().map_or(Duration::ZERO, |c| {
+            Duration::from_secs_f64(c.elapsed_ms.as_secs_f64())
+        })
+    }
+
+    /// Record where the node got to, `segment` being how long it ran for since the last
+    /// checkpoint, and return the checkpoint
+    pub fn record(&mut self, segment: Duration, debug_log: &Path) -> Checkpoint {
+        let height = std::fs::read_to_string(debug_log)
+            .ok()
+            .and_then(|log| tip_height(&log));
+        let checkpoint = Checkpoint {
+            elapsed_ms: (self.elapsed() + segment).into(),
+            height,
+        };
+        self.checkpoints.push(checkpoint);
+        checkpoint
+    }
+}
+
+/// Height of the last UpdateTip line of a debug.log
+pub fn tip_height(debug_log: &str) -> Option<u64> {
+    height_progress(debug_log)
+        .last()
+        .map(|&(_, height)| height as u64)
+}
+
+/// Copy the debug.log of a node stopped at a checkpoint to the run's output directory
+pub fn archive_debug_log(debug_log: &Path, run_out_dir: &Path, checkpoint: usize) -> Result<()> {
+    if !debug_log.exists() {
+        debug!("No debug.log to archive at {}", debug_log.display());
+        return Ok(());
+    }
+    let dir = run_out_dir.join(CHECKPOINT_LOG_DIR);
+    std::fs::create_dir_all(&dir)?;
+    let archived = dir.join(format!("{checkpoint}-debug.log"));
+    std::fs::copy(debug_log, &archived)
+        .with_context(|| format!("Failed to archive {}", debug_log.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_checkpoint_state() {
+        let dir = tempdir().unwrap();
+        assert!(CheckpointState::load(dir.path()).unwrap().is_none());
+
+        let debug_log = dir.path().join("debug.log");
+        std::fs::write(
+            &debug_log,
+            "2025-01-31T12:00:00Z Bitcoin Core version v28.1.0\n\
+             2025-01-31T12:00:01Z UpdateTip: new best=00000000839a height=1\n\
+             2025-01-31T12:00:02Z UpdateTip: new best=000000006a62 height=2\n",
+        )
+        .unwrap();
+        let mut state = CheckpointState::default();
+        state.record(Duration::from_secs(10), &debug_log);
+        state.record(Duration::from_secs(5), &dir.path().join("missing.log"));
+        state.save(dir.path()).unwrap();
+
+        let state = CheckpointState::load(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            state.checkpoints,
+            [
+                Checkpoint {
+                    elapsed_ms: DurationMs(10000.0),
+                    height: Some(2),
+                },
+                Checkpoint {
+                    elapsed_ms: DurationMs(15000.0),
+                    height: None,
+                },
+            ]
+        );
+        assert_eq!(state.elapsed(), Duration::from_secs(15));
+        assert!(state.result.is_none());
+
+        archive_debug_log(&debug_log, dir.path(), 0).unwrap();
+        assert!(dir.path().join("checkpoints/0-debug.log").exists());
+    }
+
+    #[test]
+    fn test_checkpoint_config() {
+        let config = CheckpointConfig {
+            interval_hours: 0.5,
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!(config.interval(), Duration::from_secs(1800));
+        for interval_hours in [0.0, -1.0, f64::NAN] {
+            assert!(CheckpointConfig { interval_hours }.validate().is_err());
+        }
+    }
+}
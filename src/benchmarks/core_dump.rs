@@ -0,0 +1,420 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where the kernel writes core dumps
+const CORE_PATTERN_FILE: &str = "/proc/sys/kernel/core_pattern";
+
+/// Whether the kernel appends the pid to core dumps whose pattern lacks `%p`
+const CORE_USES_PID_FILE: &str = "/proc/sys/kernel/core_uses_pid";
+
+/// Name of a collected core dump in the run's output directory
+pub const CORE_FILE: &str = "core";
+
+/// gdb's backtraces of every thread of a crashed node, in the run's output directory
+pub const BACKTRACE_FILE: &str = "backtrace.txt";
+
+/// Separates the crashing thread's backtrace from those of all threads in gdb's output
+const ALL_THREADS_MARKER: &str = "--- all threads ---";
+
+/// Signals whose default action is to dump core, with their names
+#[cfg(unix)]
+const CORE_SIGNALS: &[(i32, &str)] = &[
+    (libc::SIGQUIT, "SIGQUIT"),
+    (libc::SIGILL, "SIGILL"),
+    (libc::SIGTRAP, "SIGTRAP"),
+    (libc::SIGABRT, "SIGABRT"),
+    (libc::SIGBUS, "SIGBUS"),
+    (libc::SIGFPE, "SIGFPE"),
+    (libc::SIGSEGV, "SIGSEGV"),
+    (libc::SIGSYS, "SIGSYS"),
+    (libc::SIGXCPU, "SIGXCPU"),
+    (libc::SIGXFSZ, "SIGXFSZ"),
+];
+
+/// A run's command crashing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Crash {
+    /// Signal the command was killed by
+    pub signal: i32,
+    /// Name of the signal, e.g. SIGSEGV
+    pub signal_name: String,
+    /// Core dump moved into the run's output directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub core: Option<PathBuf>,
+    /// gdb's backtrace of the crashing thread, if the core dump could be symbolized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option<String>,
+}
+
+impl Crash {
+    /// A crash by `signal`, without a core dump
+    pub fn from_signal((signal, signal_name): (i32, &str)) -> Self {
+        Self {
+            signal,
+            signal_name: signal_name.to_string(),
+            core: None,
+            backtrace: None,
+        }
+    }
+
+    /// Function of the innermost frame of the backtrace
+    pub fn top_frame(&self) -> Option<&str> {
+        let frame = self
+            .backtrace
+            .as_deref()?
+            .lines()
+            .find_map(|line| line.strip_prefix("#0"))?
+            .trim_start();
+        // Frames without debug info start with their address
+        let frame = match frame.split_once(" in ") {
+            Some((address, rest)) if address.starts_with("0x") => rest,
+            _ => frame,
+        };
+        frame.split(" (").next().filter(|name| !name.is_empty())
+    }
+}
+
+/// The core-dumping signal a command was killed by, also when the shell running it reported it
+/// as exit code 128 + signal
+#[cfg(unix)]
+pub fn crash_signal(status: &ExitStatus) -> Option<(i32, &'static str)> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let signal = status.signal().or_else(|| {
+        status
+            .code()
+            .filter(|code| *code > 128)
+            .map(|code| code - 128)
+    })?;
+    CORE_SIGNALS.iter().copied().find(|(s, _)| *s == signal)
+}
+
+/// Processes aren't killed by signals outside of Unix
+#[cfg(not(unix))]
+pub fn crash_signal(_status: &ExitStatus) -> Option<(i32, &'static str)> {
+    None
+}
+
+/// Where the kernel sends core dumps, from `/proc/sys/kernel/core_pattern`
+#[derive(Debug, Clone, PartialEq)]
+pub enum CorePattern {
+    /// A file path template, relative to the crashing process's working directory
+    File { template: String, uses_pid: bool },
+    /// A program the core is piped to, e.g. systemd-coredump or apport
+    Pipe(String),
+}
+
+impl CorePattern {
+    /// The system's core pattern, None where there is none (e.g. on macOS)
+    pub fn read() -> Option<Self> {
+        let pattern = std::fs::read_to_string(CORE_PATTERN_FILE).ok()?;
+        let uses_pid = std::fs::read_to_string(CORE_USES_PID_FILE)
+            .is_ok_and(|uses_pid| uses_pid.trim() != "0");
+        Some(Self::parse(pattern.trim(), uses_pid))
+    }
+
+    pub fn parse(pattern: &str, uses_pid: bool) -> Self {
+        match pattern.strip_prefix('|') {
+            Some(handler) => Self::Pipe(handler.trim().to_string()),
+            None => Self::File {
+                template: pattern.to_string(),
+                uses_pid,
+            },
+        }
+    }
+
+    /// Whether benchkit can find the core dumps written with this pattern
+    pub fn collectable(&self) -> bool {
+        match self {
+            Self::File { template, .. } => !Path::new(template)
+                .parent()
+                .is_some_and(|dir| dir.to_string_lossy().contains('%')),
+            Self::Pipe(handler) => handler.contains("systemd-coredump"),
+        }
+    }
+
+    /// Warn about patterns benchkit can't collect core dumps from
+    pub fn check(&self) {
+        if !self.collectable() {
+            warn!(
+                "Core dumps go to `{}`, which benchkit can't collect them from. Set \
+                 kernel.core_pattern to a file name like core.%p to collect them.",
+                match self {
+                    Self::File { template, .. } => template.as_str(),
+                    Self::Pipe(handler) => handler.as_str(),
+                }
+            );
+        }
+    }
+
+    /// Regex matching the file names of core dumps written with a file pattern
+    fn file_name_regex(template: &str, uses_pid: bool) -> Regex {
+        let name = Path::new(template)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let mut regex = String::from("^");
+        let mut chars = name.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                regex.push_str(&regex::escape(&c.to_string()));
+                continue;
+            }
+            match chars.next() {
+                Some('%') => regex.push('%'),
+                // Every other specifier expands to something about the crash
+                Some(_) => regex.push_str(".*"),
+                None => {}
+            }
+        }
+        if uses_pid && !name.contains("%p") {
+            regex.push_str(r"(\.\d+)?");
+        }
+        regex.push('$');
+        Regex::new(&regex).unwrap()
+    }
+}
+
+/// Finds the core dump of a crashed run and symbolizes it with gdb
+pub struct CoreCollector {
+    pattern: Option<CorePattern>,
+    /// Working directory of the benchmark command, which relative patterns are resolved against
+    cwd: PathBuf,
+}
+
+impl CoreCollector {
+    /// A collector for the system's core pattern
+    pub fn new() -> Result<Self> {
+        let pattern = CorePattern::read();
+        match &pattern {
+            Some(pattern) => pattern.check(),
+            None => warn!("No {CORE_PATTERN_FILE}, core dumps won't be collected"),
+        }
+        Ok(Self {
+            pattern,
+            cwd: std::env::current_dir().context("Failed to get the working directory")?,
+        })
+    }
+
+    pub fn with_pattern(pattern: CorePattern, cwd: PathBuf) -> Self {
+        Self {
+            pattern: Some(pattern),
+            cwd,
+        }
+    }
+
+    /// Describe the crash of a run which started at `started`, moving its core dump into
+    /// `run_out_dir` and writing the backtraces of all threads next to it
+    pub fn collect(
+        &self,
+        signal: (i32, &str),
+        binary: &Path,
+        started: SystemTime,
+        run_out_dir: &Path,
+    ) -> Crash {
+        let core = self
+            .move_core(binary, started, run_out_dir)
+            .inspect_err(|e| warn!("Failed to collect the core dump: {e:#}"))
+            .ok()
+            .flatten();
+        let backtrace = core
+            .as_deref()
+            .and_then(|core| backtrace(binary, core, run_out_dir));
+        Crash {
+            core,
+            backtrace,
+            ..Crash::from_signal(signal)
+        }
+    }
+
+    fn move_core(
+        &self,
+        binary: &Path,
+        started: SystemTime,
+        run_out_dir: &Path,
+    ) -> Result<Option<PathBuf>> {
+        std::fs::create_dir_all(run_out_dir)?;
+        let dest = run_out_dir.join(CORE_FILE);
+        match &self.pattern {
+            Some(CorePattern::File { template, uses_pid }) => {
+                let dir = match Path::new(template).parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => self.cwd.join(dir),
+                    _ => self.cwd.clone(),
+                };
+                let name = CorePattern::file_name_regex(template, *uses_pid);
+                let Some(core) = newest_match(&dir, &name, started) else {
+                    info!("No core dump in {}", dir.display());
+                    return Ok(None);
+                };
+                if std::fs::rename(&core, &dest).is_err() {
+                    // Across filesystems
+                    std::fs::copy(&core, &dest)
+                        .with_context(|| format!("Failed to copy {}", core.display()))?;
+                    std::fs::remove_file(&core)?;
+                }
+            }
+            Some(CorePattern::Pipe(handler)) if handler.contains("systemd-coredump") => {
+                let since = started.duration_since(UNIX_EPOCH)?.as_secs();
+                let output = Command::new("coredumpctl")
+                    .arg("dump")
+                    .arg(format!("--since=@{since}"))
+                    .arg(format!("--output={}", dest.display()))
+                    .arg(binary)
+                    .output()
+                    .context("Failed to run coredumpctl")?;
+                if !output.status.success() || !dest.exists() {
+                    info!(
+                        "coredumpctl has no core dump of {}: {}",
+                        binary.display(),
+                        String::from_utf8_lossy(&output.stderr).trim()
+                    );
+                    return Ok(None);
+                }
+            }
+            _ => return Ok(None),
+        }
+        info!("Saved the core dump to {}", dest.display());
+        Ok(Some(dest))
+    }
+}
+
+/// The newest file in `dir` whose name matches, modified since `since`
+fn newest_match(dir: &Path, name: &Regex, since: SystemTime) -> Option<PathBuf> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| name.is_match(&entry.file_name().to_string_lossy()))
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .filter(|(modified, _)| *modified >= since)
+        .max()
+        .map(|(_, path)| path)
+}
+
+/// The crashing thread's backtrace, writing those of all threads to `backtrace.txt`
+///
+/// None if gdb isn't installed or fails to read the core dump.
+fn backtrace(binary: &Path, core: &Path, run_out_dir: &Path) -> Option<String> {
+    let output = Command::new("gdb")
+        .args(["--batch", "-nx", "-ex", "bt", "-ex"])
+        .arg(format!("echo {ALL_THREADS_MARKER}\\n"))
+        .args(["-ex", "thread apply all bt"])
+        .arg(binary)
+        .arg(core)
+        .output()
+        .inspect_err(|e| debug!("Not symbolizing the core dump, gdb failed to run: {e}"))
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (crashing, all) = stdout.split_once(ALL_THREADS_MARKER)?;
+    if let Err(e) = std::fs::write(run_out_dir.join(BACKTRACE_FILE), all.trim_start()) {
+        warn!("Failed to write {BACKTRACE_FILE}: {e}");
+    }
+    let crashing = crashing.trim();
+    (!crashing.is_empty()).then(|| crashing.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_crash_signal() {
+        use crate::command::exit_status_from_code;
+        use std::os::unix::process::ExitStatusExt;
+
+        assert_eq!(
+            crash_signal(&ExitStatus::from_raw(libc::SIGSEGV)),
+            Some((libc::SIGSEGV, "SIGSEGV"))
+        );
+        // Reported by the shell running the command
+        assert_eq!(
+            crash_signal(&exit_status_from_code(128 + libc::SIGABRT)),
+            Some((libc::SIGABRT, "SIGABRT"))
+        );
+        assert_eq!(crash_signal(&exit_status_from_code(1)), None);
+        // Being stopped isn't a crash
+        assert_eq!(crash_signal(&ExitStatus::from_raw(libc::SIGTERM)), None);
+    }
+
+    #[test]
+    fn test_core_pattern() {
+        let pattern = CorePattern::parse("|/usr/lib/systemd/systemd-coredump %P %u %g", false);
+        assert!(pattern.collectable());
+        assert!(!CorePattern::parse("|/usr/share/apport/apport -p%p", false).collectable());
+        assert!(!CorePattern::parse("/var/crash/%e/core", false).collectable());
+
+        let name = CorePattern::file_name_regex("/tmp/cores/core.%e.%p", false);
+        assert!(name.is_match("core.bitcoind.1234"));
+        assert!(!name.is_match("core"));
+        let name = CorePattern::file_name_regex("core", true);
+        assert!(name.is_match("core") && name.is_match("core.1234"));
+        assert!(!name.is_match("core.txt"));
+        assert!(CorePattern::file_name_regex("core%%", false).is_match("core%"));
+    }
+
+    #[test]
+    fn test_collect_core() {
+        let temp_dir = tempdir().unwrap();
+        let cores = temp_dir.path().join("cores");
+        std::fs::create_dir(&cores).unwrap();
+        let started = SystemTime::now() - Duration::from_secs(1);
+        std::fs::write(cores.join("core.bitcoind.42"), "core").unwrap();
+        std::fs::write(cores.join("unrelated"), "").unwrap();
+
+        let run_out_dir = temp_dir.path().join("out/abc/default/0");
+        let collector = CoreCollector::with_pattern(
+            CorePattern::parse("cores/core.%e.%p", false),
+            temp_dir.path().to_path_buf(),
+        );
+        let binary = temp_dir.path().join("bitcoind");
+        let crash = collector.collect((libc::SIGSEGV, "SIGSEGV"), &binary, started, &run_out_dir);
+        assert_eq!(crash.signal_name, "SIGSEGV");
+        assert_eq!(crash.core, Some(run_out_dir.join(CORE_FILE)));
+        assert_eq!(
+            std::fs::read_to_string(run_out_dir.join(CORE_FILE)).unwrap(),
+            "core"
+        );
+        assert!(!cores.join("core.bitcoind.42").exists());
+        // gdb can't read the fake core dump, if it's installed at all
+        assert_eq!(crash.backtrace, None);
+
+        // Core dumps from before the run aren't its own
+        std::fs::write(cores.join("core.bitcoind.43"), "old").unwrap();
+        let crash = collector.collect(
+            (libc::SIGSEGV, "SIGSEGV"),
+            &binary,
+            SystemTime::now() + Duration::from_secs(60),
+            &temp_dir.path().join("out/abc/default/1"),
+        );
+        assert_eq!(crash.core, None);
+    }
+
+    #[test]
+    fn test_top_frame() {
+        let mut crash = Crash {
+            signal: libc::SIGSEGV,
+            signal_name: "SIGSEGV".to_string(),
+            core: None,
+            backtrace: Some(
+                "#0  0x00007f3a1c2a in __pthread_kill_implementation () from /lib/libc.so.6\n\
+                 #1  0x00007f3a1c2b in raise () from /lib/libc.so.6"
+                    .to_string(),
+            ),
+        };
+        assert_eq!(crash.top_frame(), Some("__pthread_kill_implementation"));
+        crash.backtrace = Some(
+            "#0  Chainstate::ConnectBlock (this=0x1, block=...) at validation.cpp:2412".to_string(),
+        );
+        assert_eq!(crash.top_frame(), Some("Chainstate::ConnectBlock"));
+        crash.backtrace = None;
+        assert_eq!(crash.top_frame(), None);
+    }
+}
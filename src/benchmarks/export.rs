@@ -1,7 +1,21 @@
 use anyhow::{Context, Result};
-use std::path::Path;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::Write as _;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::benchmarks::results::{BenchmarkResult, MasterSummary, ResultAnalyzer};
+use crate::benchmarks::report::display_label;
+use crate::benchmarks::results::{
+    BenchmarkResult, InstrumentationType, MasterSummary, ResultAnalyzer,
+};
+
+/// Current version of the results.json format
+///
+/// Bump this whenever the serialized format changes, and add a step to `migrate_results` which
+/// upgrades files from the previous version.
+pub const RESULTS_SCHEMA_VERSION: u32 = 3;
 
 /// Functions for exporting benchmark results
 pub struct ResultExporter;
@@ -9,12 +23,7 @@ pub struct ResultExporter;
 impl ResultExporter {
     /// Export a single benchmark result to JSON
     pub fn export_json(result: &BenchmarkResult, path: &Path) -> Result<()> {
-        let json_data = serde_json::to_string_pretty(result)
-            .context("Failed to serialize benchmark results")?;
-
-        std::fs::write(path, json_data).context("Failed to write benchmark results to file")?;
-
-        Ok(())
+        Self::export_json_multiple(std::slice::from_ref(result), path)
     }
 
     /// Export multiple benchmark results to JSON, including a master summary
@@ -29,12 +38,14 @@ impl ResultExporter {
         // Create a combined structure with both results and summary
         #[derive(serde::Serialize)]
         struct ExportData<'a> {
+            schema_version: u32,
             results: &'a [BenchmarkResult],
             #[serde(skip_serializing_if = "Option::is_none")]
             master_summary: Option<MasterSummary>,
         }
 
         let export_data = ExportData {
+            schema_version: RESULTS_SCHEMA_VERSION,
             results,
             master_summary,
         };
@@ -47,19 +58,51 @@ impl ResultExporter {
         Ok(())
     }
 
+    /// Load benchmark results from a results.json, migrating older schema versions
+    pub fn load_json(path: &Path) -> Result<Vec<BenchmarkResult>> {
+        let json_data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read benchmark results: {}", path.display()))?;
+        Self::parse_json(&json_data)
+            .with_context(|| format!("Failed to parse benchmark results: {}", path.display()))
+    }
+
+    /// Parse benchmark results from JSON, migrating older schema versions
+    pub fn parse_json(json_data: &str) -> Result<Vec<BenchmarkResult>> {
+        let mut data: Value = serde_json::from_str(json_data)?;
+        migrate_results(&mut data)?;
+
+        #[derive(serde::Deserialize)]
+        struct ImportData {
+            results: Vec<BenchmarkResult>,
+        }
+        let data: ImportData = serde_json::from_value(data)?;
+        Ok(data.results)
+    }
+
     /// Export benchmark results to CSV format
     pub fn export_csv(result: &BenchmarkResult, path: &Path) -> Result<()> {
         let mut file = std::fs::File::create(path)?;
         use std::io::Write;
 
         // header
-        writeln!(file, "iteration,duration_ms,exit_code")?;
-        // data rows
+        writeln!(
+            file,
+            "iteration,duration_ms,exit_code,user_time_ms,system_time_ms,\
+             voluntary_context_switches,involuntary_context_switches"
+        )?;
+        // data rows, leaving resource usage empty where it wasn't recorded
+        let optional = |value: Option<String>| value.unwrap_or_default();
         for run in &result.runs {
             writeln!(
                 file,
-                "{},{:.2},{}",
-                run.iteration, run.duration_ms, run.exit_code
+                "{},{:.2},{},{},{},{},{}",
+                run.iteration,
+                run.duration_ms,
+                run.exit_code,
+                optional(run.user_time_ms.map(|ms| format!("{ms:.2}"))),
+                optional(run.system_time_ms.map(|ms| format!("{ms:.2}"))),
+                optional(run.voluntary_context_switches.map(|n| n.to_string())),
+                optional(run.involuntary_context_switches.map(|n| n.to_string())),
             )?;
         }
         // summary
@@ -70,7 +113,655 @@ impl ResultExporter {
         writeln!(file, "mean,{:.2}", result.summary.mean)?;
         writeln!(file, "median,{:.2}", result.summary.median)?;
         writeln!(file, "std_dev,{:.2}", result.summary.std_dev)?;
+        if let Some(cpu) = &result.summary.cpu {
+            writeln!(file, "mean_user_time_ms,{:.2}", cpu.user_time_ms)?;
+            writeln!(file, "mean_system_time_ms,{:.2}", cpu.system_time_ms)?;
+            writeln!(
+                file,
+                "mean_voluntary_context_switches,{:.2}",
+                cpu.voluntary_context_switches
+            )?;
+            writeln!(
+                file,
+                "mean_involuntary_context_switches,{:.2}",
+                cpu.involuntary_context_switches
+            )?;
+        }
 
         Ok(())
     }
 }
+
+/// Format of a file exported at the end of a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExportFormat {
+    /// results.json format, with the results of every benchmark
+    Json,
+    /// One row of summary statistics per benchmark and parameter combination
+    Csv,
+    /// Summary table for pasting into a PR or issue
+    Markdown,
+    /// Standalone page with the summary table
+    Html,
+    /// hyperfine's `--export-json` format, for tools which read it
+    HyperfineJson,
+    /// InfluxDB line protocol
+    Influx,
+}
+
+impl ExportFormat {
+    fn default_file_name(&self) -> &'static str {
+        match self {
+            ExportFormat::Json => "export.json",
+            ExportFormat::Csv => "export.csv",
+            ExportFormat::Markdown => "export.md",
+            ExportFormat::Html => "export.html",
+            ExportFormat::HyperfineJson => "hyperfine.json",
+            ExportFormat::Influx => "export.influx",
+        }
+    }
+}
+
+/// A file the Runner writes once all benchmarks have run
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    pub format: ExportFormat,
+    /// File to write, relative to the output directory, defaults to `export.<extension>`
+    /// (`hyperfine.json` for hyperfine-json)
+    pub path: Option<PathBuf>,
+    /// markdown and html: highlight results more than this fraction slower than the fastest or
+    /// reference result of their benchmark
+    pub threshold: Option<f64>,
+    /// influx: measurement name, defaults to `benchkit`
+    pub measurement: Option<String>,
+}
+
+impl ExportConfig {
+    pub fn validate(&self) -> Result<()> {
+        let highlights = matches!(self.format, ExportFormat::Markdown | ExportFormat::Html);
+        match self.threshold {
+            Some(_) if !highlights => {
+                anyhow::bail!("threshold only applies to markdown and html exports")
+            }
+            Some(threshold) if !(threshold.is_finite() && threshold > 0.0) => {
+                anyhow::bail!("Export threshold must be a positive fraction, got {threshold}")
+            }
+            _ => {}
+        }
+        match &self.measurement {
+            Some(_) if self.format != ExportFormat::Influx => {
+                anyhow::bail!("measurement only applies to influx exports")
+            }
+            Some(measurement) if measurement.is_empty() => {
+                anyhow::bail!("Export measurement cannot be empty")
+            }
+            _ => {}
+        }
+        if let Some(path) = &self.path {
+            if path.as_os_str().is_empty()
+                || !path
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_)))
+            {
+                anyhow::bail!(
+                    "Export path must be a relative path within the output directory, got {}",
+                    path.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Where the export is written
+    pub fn path(&self, out_dir: &Path) -> PathBuf {
+        out_dir.join(
+            self.path
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(self.format.default_file_name())),
+        )
+    }
+
+    /// Write the results of each named benchmark, returning the path written to
+    pub fn write(
+        &self,
+        benchmarks: &[(String, Vec<BenchmarkResult>)],
+        out_dir: &Path,
+    ) -> Result<PathBuf> {
+        let path = self.path(out_dir);
+        if let Some(parent) = path.parent() {
+            crate::path_utils::ensure_directory(parent)?;
+        }
+        let contents = match self.format {
+            ExportFormat::Json => {
+                let results: Vec<BenchmarkResult> = benchmarks
+                    .iter()
+                    .flat_map(|(_, results)| results.iter().cloned())
+                    .collect();
+                ResultExporter::export_json_multiple(&results, &path)?;
+                info!("Exported results to {}", path.display());
+                return Ok(path);
+            }
+            ExportFormat::Csv => summary_csv(benchmarks),
+            ExportFormat::Markdown => summary_markdown(benchmarks, self.threshold),
+            ExportFormat::Html => summary_html(benchmarks, self.threshold),
+            ExportFormat::HyperfineJson => hyperfine_json(benchmarks)?,
+            ExportFormat::Influx => influx_lines(
+                benchmarks,
+                self.measurement.as_deref().unwrap_or("benchkit"),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_nanos()),
+            ),
+        };
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("Exported results to {}", path.display());
+        Ok(path)
+    }
+}
+
+/// A result's mean relative to the baseline of its benchmark, 1.0 for the baseline itself
+fn relative_mean(summary: Option<&MasterSummary>, result: &BenchmarkResult) -> Option<f64> {
+    let summary = summary?;
+    Some(
+        summary
+            .comparison(&result.parameters)
+            .map_or(1.0, |comparison| comparison.times_faster),
+    )
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn summary_csv(benchmarks: &[(String, Vec<BenchmarkResult>)]) -> String {
+    let mut out = String::from(
+        "benchmark,parameters,runs,mean_ms,std_dev_ms,median_ms,min_ms,max_ms,relative\n",
+    );
+    for (name, results) in benchmarks {
+        let summary = ResultAnalyzer::calculate_master_summary(results);
+        for result in results {
+            let runs = result
+                .runs
+                .iter()
+                .filter(|run| run.instrumentation == InstrumentationType::Uninstrumented)
+                .count();
+            let _ = writeln!(
+                out,
+                "{},{},{runs},{:.2},{:.2},{:.2},{:.2},{:.2},{}",
+                csv_field(name),
+                csv_field(&ResultAnalyzer::parameter_label(&result.parameters)),
+                result.summary.mean,
+                result.summary.std_dev,
+                result.summary.median,
+                result.summary.min,
+                result.summary.max,
+                relative_mean(summary.as_ref(), result)
+                    .map(|relative| format!("{relative:.3}"))
+                    .unwrap_or_default(),
+            );
+        }
+    }
+    out
+}
+
+/// Rows of the markdown and html tables: benchmark, parameters, mean ± σ, relative, and whether
+/// the result is slower than the threshold
+fn summary_rows(
+    benchmarks: &[(String, Vec<BenchmarkResult>)],
+    threshold: Option<f64>,
+) -> Vec<[String; 5]> {
+    let mut rows = Vec::new();
+    for (name, results) in benchmarks {
+        let summary = ResultAnalyzer::calculate_master_summary(results);
+        for result in results {
+            let relative = relative_mean(summary.as_ref(), result);
+            let flagged = matches!((relative, threshold), (Some(r), Some(t)) if r > 1.0 + t);
+            rows.push([
+                name.clone(),
+                display_label(result),
+                format!(
+                    "{:.1} ± {:.1} ms",
+                    result.summary.mean, result.summary.std_dev
+                ),
+                relative
+                    .map(|relative| format!("{relative:.2}"))
+                    .unwrap_or_default(),
+                if flagged { "slower" } else { "" }.to_string(),
+            ]);
+        }
+    }
+    rows
+}
+
+const TABLE_HEADER: [&str; 4] = ["Benchmark", "Parameters", "Mean ± σ", "Relative"];
+
+fn summary_markdown(
+    benchmarks: &[(String, Vec<BenchmarkResult>)],
+    threshold: Option<f64>,
+) -> String {
+    let mut out = format!("| {} |\n|---|---|---:|---:|\n", TABLE_HEADER.join(" | "));
+    for [name, label, mean, relative, flag] in summary_rows(benchmarks, threshold) {
+        let relative = if flag.is_empty() {
+            relative
+        } else {
+            format!("**{relative}** :warning:")
+        };
+        let cells = [name, label, mean, relative].map(|cell| cell.replace('|', "\\|"));
+        let _ = writeln!(out, "| {} |", cells.join(" | "));
+    }
+    out
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn summary_html(benchmarks: &[(String, Vec<BenchmarkResult>)], threshold: Option<f64>) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>benchkit results</title>\n\
+         <style>\ntable { border-collapse: collapse; font-family: sans-serif; }\n\
+         th, td { border: 1px solid #ccc; padding: 4px 8px; }\n\
+         tr.slower td { background: #fdd; }\n</style>\n</head>\n<body>\n<table>\n<tr>",
+    );
+    for heading in TABLE_HEADER {
+        let _ = write!(out, "<th>{}</th>", escape_html(heading));
+    }
+    out.push_str("</tr>\n");
+    for [name, label, mean, relative, flag] in summary_rows(benchmarks, threshold) {
+        if flag.is_empty() {
+            out.push_str("<tr>");
+        } else {
+            out.push_str("<tr class=\"slower\">");
+        }
+        for cell in [name, label, mean, relative] {
+            let _ = write!(out, "<td>{}</td>", escape_html(&cell));
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+fn hyperfine_json(benchmarks: &[(String, Vec<BenchmarkResult>)]) -> Result<String> {
+    let results: Vec<Value> = benchmarks
+        .iter()
+        .flat_map(|(name, results)| results.iter().map(move |result| (name, result)))
+        .map(|(name, result)| {
+            let runs: Vec<_> = result
+                .runs
+                .iter()
+                .filter(|run| run.instrumentation == InstrumentationType::Uninstrumented)
+                .collect();
+            json!({
+                "command": format!("{name}: {}", display_label(result)),
+                "mean": result.summary.mean.as_secs_f64(),
+                "stddev": result.summary.std_dev.as_secs_f64(),
+                "median": result.summary.median.as_secs_f64(),
+                "user": result.summary.cpu.as_ref().map_or(0.0, |cpu| cpu.user_time_ms.as_secs_f64()),
+                "system": result.summary.cpu.as_ref().map_or(0.0, |cpu| cpu.system_time_ms.as_secs_f64()),
+                "min": result.summary.min.as_secs_f64(),
+                "max": result.summary.max.as_secs_f64(),
+                "times": runs.iter().map(|run| run.duration_ms.as_secs_f64()).collect::<Vec<_>>(),
+                "exit_codes": runs.iter().map(|run| run.exit_code).collect::<Vec<_>>(),
+                "parameters": result.parameters,
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(
+        &json!({ "results": results }),
+    )?)
+}
+
+/// Escape a tag key or value, or the measurement, for the line protocol
+fn escape_influx(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn influx_lines(
+    benchmarks: &[(String, Vec<BenchmarkResult>)],
+    measurement: &str,
+    timestamp_ns: u128,
+) -> String {
+    let mut out = String::new();
+    for (name, results) in benchmarks {
+        for result in results {
+            let _ = write!(
+                out,
+                "{},benchmark={}",
+                escape_influx(measurement),
+                escape_influx(name)
+            );
+            // Tags sorted by key, as InfluxDB recommends
+            let mut parameters: Vec<_> = result.parameters.iter().collect();
+            parameters.sort();
+            for (key, value) in parameters.into_iter().filter(|(_, v)| !v.is_empty()) {
+                let _ = write!(out, ",{}={}", escape_influx(key), escape_influx(value));
+            }
+            let _ = writeln!(
+                out,
+                " mean_ms={},std_dev_ms={},median_ms={},min_ms={},max_ms={},runs={}i {timestamp_ns}",
+                result.summary.mean,
+                result.summary.std_dev,
+                result.summary.median,
+                result.summary.min,
+                result.summary.max,
+                result
+                    .runs
+                    .iter()
+                    .filter(|run| run.instrumentation == InstrumentationType::Uninstrumented)
+                    .count(),
+            );
+        }
+    }
+    out
+}
+
+/// Schema version of a results.json document
+///
+/// Files from before versioning have no `schema_version` and are version 1. They were either a
+/// single result, a list of results, or the `{results, master_summary}` object.
+fn schema_version(data: &Value) -> Result<u32> {
+    match data.get("schema_version") {
+        None => Ok(1),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| anyhow::anyhow!("Invalid schema_version: {}", version)),
+    }
+}
+
+/// Upgrade a results.json document to the current schema version in place
+fn migrate_results(data: &mut Value) -> Result<()> {
+    let version = schema_version(data)?;
+    if version > RESULTS_SCHEMA_VERSION {
+        warn!(
+            "Results use schema version {}, newer than supported version {}",
+            version, RESULTS_SCHEMA_VERSION
+        );
+        return Ok(());
+    }
+
+    if version < 2 {
+        debug!("Migrating results from schema version 1");
+        migrate_v1_to_v2(data);
+    }
+    if version < 3 {
+        debug!("Migrating results from schema version 2");
+        migrate_v2_to_v3(data);
+    }
+    Ok(())
+}
+
+/// Version 2 always wraps results in an object and records each run's instrumentation
+fn migrate_v1_to_v2(data: &mut Value) {
+    let mut wrapped = match data.take() {
+        Value::Array(results) => json!({ "results": results }),
+        object if object.get("results").is_some() => object,
+        result => json!({ "results": [result] }),
+    };
+
+    if let Some(results) = wrapped["results"].as_array_mut() {
+        for result in results {
+            let Some(runs) = result.get_mut("runs").and_then(Value::as_array_mut) else {
+                continue;
+            };
+            for run in runs.iter_mut().filter_map(Value::as_object_mut) {
+                run.entry("instrumentation")
+                    .or_insert_with(|| json!("Uninstrumented"));
+            }
+        }
+    }
+
+    wrapped["schema_version"] = json!(2);
+    *data = wrapped;
+}
+
+/// Version 3 keys the master summary's comparisons by commit
+///
+/// The master summary is derived from the results, so the old one is dropped rather than
+/// converted and is recomputed when the results are exported again.
+fn migrate_v2_to_v3(data: &mut Value) {
+    if let Some(object) = data.as_object_mut() {
+        object.remove("master_summary");
+    }
+    data["schema_version"] = json!(3);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::results::{InstrumentationType, ResultAnalyzer, RunResult, RunSummary};
+    use crate::types::DurationMs;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    const LEGACY_RESULT: &str = r#"{
+        "command": "bitcoind",
+        "parameters": {"commit": "abc"},
+        "runs": [{"iteration": 0, "duration_ms": 10.0, "exit_code": 0}],
+        "summary": {"min": 10.0, "max": 10.0, "mean": 10.0, "median": 10.0, "std_dev": 0.0}
+    }"#;
+
+    fn result() -> BenchmarkResult {
+        BenchmarkResult {
+            command: "bitcoind".to_string(),
+            parameters: HashMap::from([("commit".to_string(), "abc".to_string())]),
+            commit_label: None,
+            runs: vec![RunResult {
+                iteration: 0,
+                duration_ms: DurationMs(10.0),
+                exit_code: 0,
+                instrumentation: InstrumentationType::PerfInstrumented,
+                ..Default::default()
+            }],
+            runs_needed: None,
+            version: None,
+            failures: Vec::new(),
+            spawn_overhead_ms: None,
+            cached_from: None,
+            out_dir: None,
+            feeder: None,
+            summary: RunSummary {
+                min: DurationMs(10.0),
+                max: DurationMs(10.0),
+                mean: DurationMs(10.0),
+                median: DurationMs(10.0),
+                std_dev: DurationMs(0.0),
+                profile: None,
+                cpu: None,
+            },
+            instrumented_summary: None,
+        }
+    }
+
+    #[test]
+    fn test_serialized_format() {
+        // Changing this format requires bumping RESULTS_SCHEMA_VERSION and adding a migration
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("results.json");
+        ResultExporter::export_json(&result(), &path).unwrap();
+
+        let written: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            json!({
+                "schema_version": 3,
+                "results": [{
+                    "command": "bitcoind",
+                    "parameters": {"commit": "abc"},
+                    "runs": [{
+                        "iteration": 0,
+                        "duration_ms": 10.0,
+                        "exit_code": 0,
+                        "instrumentation": "PerfInstrumented"
+                    }],
+                    "summary": {
+                        "min": 10.0,
+                        "max": 10.0,
+                        "mean": 10.0,
+                        "median": 10.0,
+                        "std_dev": 0.0
+                    }
+                }]
+            })
+        );
+
+        let loaded = ResultExporter::load_json(&path).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(
+            loaded[0].runs[0].instrumentation,
+            InstrumentationType::PerfInstrumented
+        );
+    }
+
+    #[test]
+    fn test_export_csv() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("results.csv");
+        let mut result = result();
+        let mut measured = result.runs[0].clone();
+        measured.iteration = 1;
+        measured.user_time_ms = Some(DurationMs(6.0));
+        measured.system_time_ms = Some(DurationMs(2.5));
+        measured.voluntary_context_switches = Some(40);
+        measured.involuntary_context_switches = Some(3);
+        result.runs.push(measured);
+        result.summary = ResultAnalyzer::calculate_summary(&result.runs);
+        ResultExporter::export_csv(&result, &path).unwrap();
+
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "iteration,duration_ms,exit_code,user_time_ms,system_time_ms,\
+             voluntary_context_switches,involuntary_context_switches"
+        );
+        assert_eq!(lines[1], "0,10.00,0,,,,");
+        assert_eq!(lines[2], "1,10.00,0,6.00,2.50,40,3");
+        // Means only cover the run which recorded its usage
+        assert!(csv.contains("mean_user_time_ms,6.00\n"));
+        assert!(csv.contains("mean_involuntary_context_switches,3.00\n"));
+    }
+
+    #[test]
+    fn test_migrate_v1() {
+        let wrapped = format!(r#"{{"results": [{LEGACY_RESULT}]}}"#);
+        let list = format!("[{LEGACY_RESULT}, {LEGACY_RESULT}]");
+
+        for (json, count) in [(LEGACY_RESULT.to_string(), 1), (wrapped, 1), (list, 2)] {
+            let results = ResultExporter::parse_json(&json).unwrap();
+            assert_eq!(results.len(), count);
+            assert_eq!(results[0].parameters["commit"], "abc");
+            assert_eq!(
+                results[0].runs[0].instrumentation,
+                InstrumentationType::Uninstrumented
+            );
+        }
+    }
+
+    #[test]
+    fn test_migrate_v2() {
+        let mut data = json!({
+            "schema_version": 2,
+            "results": [],
+            "master_summary": {"fastest_command": "bitcoind", "comparisons": []}
+        });
+        migrate_results(&mut data).unwrap();
+        assert_eq!(data, json!({"schema_version": 3, "results": []}));
+    }
+
+    #[test]
+    fn test_schema_version() {
+        assert_eq!(schema_version(&json!({})).unwrap(), 1);
+        assert_eq!(schema_version(&json!({"schema_version": 2})).unwrap(), 2);
+        assert!(schema_version(&json!({"schema_version": "two"})).is_err());
+
+        // Newer versions are loaded as-is, ignoring unknown fields
+        let newer = r#"{"schema_version": 99, "results": [], "machine": "bench-1"}"#;
+        assert!(ResultExporter::parse_json(newer).unwrap().is_empty());
+
+        assert!(ResultExporter::parse_json("{}").is_err());
+    }
+
+    #[test]
+    fn test_exports() {
+        let mut slow = result();
+        slow.parameters
+            .insert("commit".to_string(), "b,c d".to_string());
+        slow.summary.mean = DurationMs(12.0);
+        slow.runs[0].instrumentation = InstrumentationType::Uninstrumented;
+        let benchmarks = vec![("ibd".to_string(), vec![result(), slow])];
+
+        let csv = summary_csv(&benchmarks);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[2],
+            "ibd,\"commit=b,c d\",1,12.00,0.00,10.00,10.00,10.00,1.200"
+        );
+
+        let export = |format, threshold| ExportConfig {
+            format,
+            path: None,
+            threshold,
+            measurement: None,
+        };
+        let markdown = summary_markdown(&benchmarks, Some(0.1));
+        assert!(markdown.contains("| ibd | commit=abc | 10.0 ± 0.0 ms | 1.00 |"));
+        assert!(markdown.contains("**1.20** :warning:"));
+        assert!(!summary_markdown(&benchmarks, Some(0.5)).contains(":warning:"));
+        assert!(summary_html(&benchmarks, Some(0.1)).contains("<tr class=\"slower\">"));
+
+        let influx = influx_lines(&benchmarks, "bench mark", 7);
+        assert_eq!(
+            influx.lines().nth(1).unwrap(),
+            "bench\\ mark,benchmark=ibd,commit=b\\,c\\ d mean_ms=12,std_dev_ms=0,median_ms=10,\
+             min_ms=10,max_ms=10,runs=1i 7"
+        );
+
+        let hyperfine: Value = serde_json::from_str(&hyperfine_json(&benchmarks).unwrap()).unwrap();
+        assert_eq!(hyperfine["results"][1]["mean"], 0.012);
+        assert_eq!(hyperfine["results"][1]["times"], json!([0.01]));
+        assert_eq!(hyperfine["results"][0]["times"], json!([]));
+
+        let out_dir = tempdir().unwrap();
+        let json = export(ExportFormat::Json, None);
+        let path = json.write(&benchmarks, out_dir.path()).unwrap();
+        assert_eq!(path, out_dir.path().join("export.json"));
+        assert_eq!(ResultExporter::load_json(&path).unwrap().len(), 2);
+
+        assert!(export(ExportFormat::Markdown, Some(0.05))
+            .validate()
+            .is_ok());
+        assert!(export(ExportFormat::Csv, Some(0.05)).validate().is_err());
+        assert!(export(ExportFormat::Html, Some(-1.0)).validate().is_err());
+        let config: ExportConfig =
+            serde_yaml::from_str("format: hyperfine-json\npath: hf.json").unwrap();
+        assert_eq!(config.path(Path::new("out")), Path::new("out/hf.json"));
+        assert!(config.validate().is_ok());
+        for path in ["/etc/hf.json", "../../hf.json", "exports/../../hf.json", ""] {
+            let config = ExportConfig {
+                path: Some(PathBuf::from(path)),
+                ..config.clone()
+            };
+            assert!(config.validate().is_err(), "{path}");
+        }
+    }
+}
@@ -0,0 +1,296 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::benchmarks::core_dump::BACKTRACE_FILE;
+use crate::benchmarks::HookArgs;
+use crate::command::CommandExecutor;
+use crate::logging::LOG_FILE_NAME;
+use crate::path_utils;
+use crate::system_info::{dump_sys_info, CoreAssignment};
+
+/// Lines of each debug.log kept in a bundle
+const DEBUG_LOG_TAIL_LINES: usize = 1000;
+
+/// Live system state copied into a bundle next to `system_info.json`, where it exists
+const SYSTEM_STATE: &[(&str, &str)] = &[
+    ("/proc/loadavg", "loadavg"),
+    ("/proc/vmstat", "vmstat"),
+    ("/proc/pressure/cpu", "pressure-cpu"),
+    ("/proc/pressure/io", "pressure-io"),
+    ("/proc/pressure/memory", "pressure-memory"),
+];
+
+/// Collects what a failed run left behind into `failure-<commit>-<iteration>.tar.zst`
+///
+/// The bundle holds the error, the tails of the node's debug.logs, the part of benchkit.log
+/// written during the run, the command's stderr, core dumps and their backtraces and a snapshot
+/// of the system, so a failure on a remote worker can be investigated from a single file.
+pub struct FailureBundle<'a> {
+    /// Args of the failed run, whose datadir is searched for logs and core dumps
+    run_args: &'a HookArgs,
+    /// Output directory of the failed run, which the conclude hook moves its debug.log to
+    run_out_dir: &'a Path,
+    /// Size of benchkit.log when the run started
+    log_offset: Option<u64>,
+    stderr: Option<&'a str>,
+}
+
+impl<'a> FailureBundle<'a> {
+    pub fn new(run_args: &'a HookArgs, run_out_dir: &'a Path) -> Self {
+        Self {
+            run_args,
+            run_out_dir,
+            log_offset: None,
+            stderr: None,
+        }
+    }
+
+    /// Size of benchkit.log in `out_dir` when the run started, to bundle what was logged since
+    pub fn log_offset(out_dir: &Path) -> Option<u64> {
+        std::fs::metadata(out_dir.join(LOG_FILE_NAME))
+            .ok()
+            .map(|metadata| metadata.len())
+    }
+
+    pub fn since_log_offset(mut self, log_offset: Option<u64>) -> Self {
+        self.log_offset = log_offset;
+        self
+    }
+
+    pub fn stderr(mut self, stderr: Option<&'a str>) -> Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// Write the bundle next to the run's output directory, returning its path
+    pub fn write(&self, error: &str) -> Result<PathBuf> {
+        let name = format!(
+            "failure-{}-{}",
+            self.run_args.commit, self.run_args.iteration
+        );
+        let dest_dir = self.run_out_dir.parent().unwrap_or(self.run_out_dir);
+        path_utils::ensure_directory(dest_dir)?;
+        let staging = tempfile::tempdir_in(dest_dir)
+            .context("Failed to create a directory for the failure bundle")?;
+        let contents = staging.path().join(&name);
+        std::fs::create_dir(&contents)?;
+
+        std::fs::write(contents.join("error.txt"), format!("{error}\n"))?;
+        std::fs::write(contents.join("hook_args.json"), self.run_args.to_json()?)?;
+        if let Some(stderr) = self.stderr {
+            std::fs::write(contents.join("stderr.txt"), stderr)?;
+        }
+        let backtrace = self.run_out_dir.join(BACKTRACE_FILE);
+        if backtrace.exists() {
+            std::fs::copy(&backtrace, contents.join(BACKTRACE_FILE))?;
+        }
+        self.collect_debug_logs(&contents)?;
+        self.collect_benchkit_log(&contents)?;
+        self.collect_core_dumps(&contents)?;
+        self.collect_system_state(&contents.join("system"))?;
+
+        archive(staging.path(), &name, dest_dir)
+    }
+
+    /// Tails of the benchmarked node's debug.log and those of the other nodes
+    fn collect_debug_logs(&self, contents: &Path) -> Result<()> {
+        let mut logs = vec![
+            (
+                self.run_args.network_datadir().join("debug.log"),
+                "debug.log".to_string(),
+            ),
+            (self.run_out_dir.join("debug.log"), "debug.log".to_string()),
+        ];
+        for path in files_in(self.run_out_dir) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.ends_with("-debug.log") {
+                logs.push((path.clone(), name.to_string()));
+            }
+        }
+        for (path, name) in logs {
+            let Ok(log) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            let lines: Vec<&str> = log.lines().collect();
+            let tail = &lines[lines.len().saturating_sub(DEBUG_LOG_TAIL_LINES)..];
+            std::fs::write(
+                contents.join(format!("{name}.tail")),
+                tail.join("\n") + "\n",
+            )?;
+        }
+        Ok(())
+    }
+
+    /// What benchkit logged since the run started, all of benchkit.log if it was rotated since
+    fn collect_benchkit_log(&self, contents: &Path) -> Result<()> {
+        let Ok(mut log) = std::fs::File::open(self.run_args.out_dir.join(LOG_FILE_NAME)) else {
+            return Ok(());
+        };
+        let offset = self.log_offset.unwrap_or_default();
+        if offset <= log.metadata()?.len() {
+            log.seek(SeekFrom::Start(offset))?;
+        }
+        let mut segment = Vec::new();
+        log.read_to_end(&mut segment)?;
+        std::fs::write(contents.join(LOG_FILE_NAME), segment)?;
+        Ok(())
+    }
+
+    /// Core dumps the kernel wrote into the datadir or output directory of the run
+    fn collect_core_dumps(&self, contents: &Path) -> Result<()> {
+        let dirs = [
+            self.run_args.datadir.as_path(),
+            &self.run_args.network_datadir(),
+            self.run_out_dir,
+        ]
+        .map(Path::to_path_buf);
+        for path in dirs.iter().flat_map(|dir| files_in(dir)) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name == "core" || name.starts_with("core.") {
+                info!("Adding core dump {} to the failure bundle", path.display());
+                std::fs::copy(&path, contents.join(name.as_ref()))
+                    .with_context(|| format!("Failed to copy {}", path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_system_state(&self, dir: &Path) -> Result<()> {
+        dump_sys_info(dir, &self.run_args.tmp_data_dir, &CoreAssignment::default())?;
+        for (source, name) in SYSTEM_STATE {
+            // Missing on other platforms and older kernels
+            if let Err(e) = std::fs::copy(source, dir.join(name)) {
+                debug!("Not capturing {source}: {e}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The files directly in a directory, empty if it doesn't exist
+fn files_in(dir: &Path) -> Vec<PathBuf> {
+    std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_file())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Archive `dir/name` into `dest_dir` with zstd, or gzip where zstd isn't installed
+fn archive(dir: &Path, name: &str, dest_dir: &Path) -> Result<PathBuf> {
+    let tar = |extension: &str, compression: &str| -> Result<PathBuf> {
+        let dest = dest_dir.join(format!("{name}.{extension}"));
+        CommandExecutor::builder()
+            .name(format!("archive {name}"))
+            .capture_output(true)
+            .build()?
+            .execute_command_with_args(
+                "tar",
+                &[
+                    compression,
+                    "-cf",
+                    &dest.to_string_lossy(),
+                    "-C",
+                    &dir.to_string_lossy(),
+                    name,
+                ],
+            )
+            .with_context(|| format!("Failed to archive {name}"))?;
+        Ok(dest)
+    };
+    let bundle = tar("tar.zst", "--zstd").or_else(|e| {
+        warn!("Compressing the failure bundle with gzip, as zstd failed: {e:#}");
+        let _ = std::fs::remove_file(dest_dir.join(format!("{name}.tar.zst")));
+        tar("tar.gz", "--gzip")
+    })?;
+    info!(
+        "Saved the diagnostics of the failed run to {}",
+        bundle.display()
+    );
+    Ok(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::hook_runner::NodePorts;
+    use crate::types::Network;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_failure_bundle() {
+        let temp_dir = tempdir().unwrap();
+        let out_dir = temp_dir.path().join("out");
+        let run_out_dir = out_dir.join("abc/default/1");
+        std::fs::create_dir_all(&run_out_dir).unwrap();
+        let args = HookArgs {
+            benchmark: "ibd".to_string(),
+            options: Default::default(),
+            binary: "bitcoind".to_string(),
+            connect_address: String::new(),
+            network: Network::Signet,
+            ports: NodePorts::from_command("bitcoind", Network::Signet),
+            env: Default::default(),
+            out_dir: out_dir.clone(),
+            run_out_dir: run_out_dir.clone(),
+            snapshot_path: PathBuf::new(),
+            tmp_data_dir: temp_dir.path().join("data"),
+            datadir: temp_dir.path().join("data/abc/default/1"),
+            iteration: 1,
+            commit: "abc".to_string(),
+            params_dir: "default".to_string(),
+            loadblock: Vec::new(),
+            header_cache: None,
+            params: Default::default(),
+        };
+        std::fs::create_dir_all(args.network_datadir()).unwrap();
+        let log: String = (0..1500).map(|i| format!("line {i}\n")).collect();
+        std::fs::write(args.network_datadir().join("debug.log"), log).unwrap();
+        std::fs::write(run_out_dir.join("feeder-debug.log"), "feeder\n").unwrap();
+        std::fs::write(args.datadir.join("core.1234"), "core").unwrap();
+        std::fs::write(out_dir.join(LOG_FILE_NAME), "before\nduring\n").unwrap();
+
+        let bundle = FailureBundle::new(&args, &run_out_dir)
+            .since_log_offset(Some("before\n".len() as u64))
+            .stderr(Some("Assertion failed"))
+            .write("run 1 exited with code 134")
+            .unwrap();
+        assert_eq!(bundle.parent().unwrap(), out_dir.join("abc/default"));
+        let name = bundle.file_name().unwrap().to_string_lossy();
+        assert!(name.starts_with("failure-abc-1.tar."), "{name}");
+
+        let extracted = temp_dir.path().join("extracted");
+        std::fs::create_dir(&extracted).unwrap();
+        let status = std::process::Command::new("tar")
+            .arg("-xf")
+            .arg(&bundle)
+            .arg("-C")
+            .arg(&extracted)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let contents = extracted.join("failure-abc-1");
+        let read = |name: &str| std::fs::read_to_string(contents.join(name)).unwrap();
+        assert_eq!(read("error.txt"), "run 1 exited with code 134\n");
+        assert_eq!(read("stderr.txt"), "Assertion failed");
+        assert_eq!(read(LOG_FILE_NAME), "during\n");
+        let tail = read("debug.log.tail");
+        assert_eq!(tail.lines().count(), DEBUG_LOG_TAIL_LINES);
+        assert!(tail.ends_with("line 1499\n"));
+        assert_eq!(read("feeder-debug.log.tail"), "feeder\n");
+        assert_eq!(read("core.1234"), "core");
+        assert!(contents.join("system/system_info.json").exists());
+        assert!(contents.join("hook_args.json").exists());
+        // Nothing is left behind but the bundle
+        assert_eq!(
+            files_in(&out_dir.join("abc/default")),
+            std::slice::from_ref(&bundle)
+        );
+    }
+}
@@ -0,0 +1,553 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::job::encode_hex;
+use crate::rpc::RpcClient;
+use crate::types::Network;
+
+/// How long connecting to and handshaking with a feeder node may take
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Protocol version sent in the handshake
+const PROTOCOL_VERSION: i32 = 70016;
+
+/// User agent benchkit identifies itself with
+const USER_AGENT: &str = "/benchkit/";
+
+/// Largest version message accepted from a feeder node
+const MAX_VERSION_SIZE: usize = 4096;
+
+/// Blocks an inbound peer of the feeder may lag behind it before it counts as syncing from it
+const SYNCING_PEER_LAG: i64 = 144;
+
+/// RPC access to the node at a benchmark's `connect` address, for checking it before runs
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FeederRpc {
+    /// URL of the node's RPC server, e.g. http://127.0.0.1:38332
+    pub url: String,
+    /// The node's cookie file, e.g. ~/.bitcoin/signet/.cookie
+    pub cookie: Option<PathBuf>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+}
+
+impl FeederRpc {
+    pub fn validate(&self) -> Result<()> {
+        match (&self.cookie, &self.user, &self.password) {
+            (Some(_), None, None) | (None, Some(_), Some(_)) => Ok(()),
+            _ => anyhow::bail!("connect_rpc needs either a cookie, or a user and password"),
+        }
+    }
+
+    fn client(&self) -> Result<RpcClient> {
+        match (&self.cookie, &self.user, &self.password) {
+            (Some(cookie), _, _) => {
+                let cookie = PathBuf::from(shellexpand::tilde(&cookie.to_string_lossy()).as_ref());
+                RpcClient::with_cookie_file(&self.url, &cookie, HANDSHAKE_TIMEOUT)
+            }
+            (None, Some(user), Some(password)) => {
+                RpcClient::new(&self.url, user, password, HANDSHAKE_TIMEOUT)
+            }
+            _ => anyhow::bail!("connect_rpc needs either a cookie, or a user and password"),
+        }
+    }
+}
+
+/// What a node advertised about itself in its version message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerVersion {
+    /// Protocol version
+    pub version: i32,
+    /// User agent, e.g. "/Satoshi:28.1.0/"
+    pub user_agent: String,
+    /// Height of the node's best chain
+    pub start_height: i32,
+}
+
+/// An outbound connection a benchmarked node logged to its debug.log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeerConnection {
+    /// The node's id for the peer
+    pub peer: u64,
+    /// Protocol version the peer advertised
+    pub version: i32,
+    /// User agent the peer advertised, only logged with `-debug=net`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Height the peer advertised
+    pub start_height: i32,
+    /// Address of the peer, only logged with `-logips`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+}
+
+/// The outbound peers a node connected to, from its debug.log
+///
+/// Nodes log `New <type> peer connected` lines for outbound peers, and the user agent in
+/// `receive version message` lines with `-debug=net`.
+pub fn parse_peer_connections(debug_log: &str) -> Vec<PeerConnection> {
+    let connected = Regex::new(
+        r"New ([\w-]+) (?:v\d )?peer connected: version: (-?\d+), blocks=(-?\d+), peer=(\d+)(?:, peeraddr=(\S+))?",
+    )
+    .unwrap();
+    let received =
+        Regex::new(r"receive version message: (.*?): version -?\d+, .*peer=(\d+)").unwrap();
+    let mut user_agents = std::collections::HashMap::new();
+    let mut connections = Vec::new();
+    for line in debug_log.lines() {
+        if let Some(caps) = received.captures(line) {
+            user_agents.insert(caps[2].to_string(), caps[1].to_string());
+        } else if let Some(caps) = connected.captures(line) {
+            if &caps[1] == "inbound" {
+                continue;
+            }
+            connections.push(PeerConnection {
+                peer: caps[4].parse().unwrap_or_default(),
+                version: caps[2].parse().unwrap_or_default(),
+                user_agent: user_agents.get(&caps[4]).cloned(),
+                start_height: caps[3].parse().unwrap_or_default(),
+                address: caps.get(5).map(|address| address.as_str().to_string()),
+            });
+        }
+    }
+    connections
+}
+
+/// The node a network-sourced benchmark synced from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeederIdentity {
+    /// The benchmark's `connect` address
+    pub address: String,
+    /// What the node advertised when benchkit connected to it before the runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<PeerVersion>,
+    /// What the node advertised when benchkit connected to it after the runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<PeerVersion>,
+    /// Whether the node advertised a different version or user agent during the benchmark, e.g.
+    /// because it was upgraded or another node took over its address
+    pub changed: bool,
+}
+
+impl FeederIdentity {
+    /// The identity of the node at `address` from the handshakes around the runs and the
+    /// connections the runs logged
+    pub fn new(
+        address: &str,
+        before: Option<PeerVersion>,
+        after: Option<PeerVersion>,
+        connections: &[&PeerConnection],
+    ) -> Self {
+        let handshakes = before.iter().chain(&after);
+        let versions: BTreeSet<i32> = handshakes
+            .clone()
+            .map(|peer| peer.version)
+            .chain(connections.iter().map(|connection| connection.version))
+            .collect();
+        let user_agents: BTreeSet<&str> = handshakes
+            .map(|peer| peer.user_agent.as_str())
+            .chain(
+                connections
+                    .iter()
+                    .filter_map(|connection| connection.user_agent.as_deref()),
+            )
+            .collect();
+        let changed = versions.len() > 1 || user_agents.len() > 1;
+        if changed {
+            warn!(
+                "The node at {address} changed during the benchmark, advertising {}",
+                user_agents
+                    .iter()
+                    .map(|agent| agent.to_string())
+                    .chain(versions.iter().map(|version| format!("protocol {version}")))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        Self {
+            address: address.to_string(),
+            before,
+            after,
+            changed,
+        }
+    }
+
+    /// The user agent the node advertised, preferring the one from before the runs
+    pub fn user_agent(&self) -> Option<&str> {
+        self.before
+            .as_ref()
+            .or(self.after.as_ref())
+            .map(|peer| peer.user_agent.as_str())
+    }
+}
+
+/// Check the node at a benchmark's `connect` address accepts connections and handshakes on the
+/// benchmark's network, and, if its RPC is configured, that it is synced and not serving other
+/// syncing nodes
+pub fn check_feeder(
+    address: &str,
+    network: Network,
+    rpc: Option<&FeederRpc>,
+) -> Result<PeerVersion> {
+    let peer = handshake(address, network)?;
+    info!(
+        "Feeder node {address} is {} (protocol {}) at height {}",
+        peer.user_agent, peer.version, peer.start_height
+    );
+    if let Some(rpc) = rpc {
+        check_feeder_rpc(rpc, network).with_context(|| {
+            format!("Feeder node {address} failed its RPC checks at {}", rpc.url)
+        })?;
+    }
+    Ok(peer)
+}
+
+/// The socket addresses of a `connect` address, which may leave out the network's default port
+fn resolve(address: &str, network: Network) -> Result<Vec<SocketAddr>> {
+    let has_port = if address.starts_with('[') {
+        address.contains("]:")
+    } else {
+        address.matches(':').count() == 1
+    };
+    let with_port = match (has_port, address.contains(':')) {
+        (true, _) => address.to_string(),
+        // A bare IPv6 address
+        (false, true) => format!("[{address}]:{}", network.default_p2p_port()),
+        (false, false) => format!("{address}:{}", network.default_p2p_port()),
+    };
+    let addrs: Vec<SocketAddr> = with_port
+        .to_socket_addrs()
+        .with_context(|| format!("Cannot resolve the connect address {address}"))?
+        .collect();
+    if addrs.is_empty() {
+        anyhow::bail!("The connect address {address} resolves to no addresses");
+    }
+    Ok(addrs)
+}
+
+/// Connect to a node and exchange version messages with it
+pub fn handshake(address: &str, network: Network) -> Result<PeerVersion> {
+    let addrs = resolve(address, network)?;
+    let mut stream = connect(address, &addrs, network)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream
+        .write_all(&message(network.magic(), "version", &version_payload()))
+        .with_context(|| format!("Failed to send a version message to {address}"))?;
+
+    let mut header = [0u8; 24];
+    stream.read_exact(&mut header).map_err(|e| match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => anyhow::anyhow!(
+            "{address} accepted the connection but didn't answer the handshake within {}s. Is it \
+             a bitcoind P2P port, rather than e.g. its RPC port?",
+            HANDSHAKE_TIMEOUT.as_secs()
+        ),
+        _ => anyhow::anyhow!(
+            "{address} closed the connection during the handshake ({e}). It may have banned this \
+             machine, or be at its -maxconnections limit"
+        ),
+    })?;
+    if header[..4] != network.magic() {
+        let message = format!(
+            "{address} is not a {network} node, its messages start with {}",
+            encode_hex(&header[..4])
+        );
+        // Custom signets have their own message start
+        if network == Network::Signet {
+            warn!("{message}, as on a custom signet");
+        } else {
+            anyhow::bail!(message);
+        }
+    }
+    let command = String::from_utf8_lossy(&header[4..16])
+        .trim_end_matches('\0')
+        .to_string();
+    let length = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+    if command != "version" || length > MAX_VERSION_SIZE {
+        anyhow::bail!("{address} answered the handshake with an unexpected {command} message");
+    }
+    let mut payload = vec![0u8; length];
+    stream
+        .read_exact(&mut payload)
+        .with_context(|| format!("Failed to read the version message of {address}"))?;
+    parse_version(&payload).with_context(|| format!("Invalid version message from {address}"))
+}
+
+fn connect(address: &str, addrs: &[SocketAddr], network: Network) -> Result<TcpStream> {
+    let mut last_error = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, HANDSHAKE_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let e = last_error.expect("addresses are never empty");
+    Err(match e.kind() {
+        ErrorKind::ConnectionRefused => anyhow::anyhow!(
+            "Nothing is listening at the connect address {address}. Is the feeder node running \
+             with -listen=1 on that port (-port, {} by default on {network})?",
+            network.default_p2p_port()
+        ),
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => anyhow::anyhow!(
+            "Timed out connecting to {address} after {}s. Is the host up, and does its firewall \
+             allow connections to the port?",
+            HANDSHAKE_TIMEOUT.as_secs()
+        ),
+        _ => anyhow::anyhow!("Failed to connect to {address}: {e}"),
+    })
+}
+
+fn check_feeder_rpc(rpc: &FeederRpc, network: Network) -> Result<()> {
+    let client = rpc.client()?;
+    let info = client.call("getblockchaininfo", json!([]))?;
+    let chain = info["chain"].as_str().unwrap_or_default();
+    if chain != network.chain_arg() {
+        anyhow::bail!("The feeder node is on {chain}, not {network}");
+    }
+    let blocks = info["blocks"].as_i64().unwrap_or_default();
+    if info["initialblockdownload"].as_bool() == Some(true) {
+        warn!(
+            "The feeder node is still syncing at height {blocks}, runs can't sync past it, and \
+             their timings depend on its progress"
+        );
+    }
+
+    let peers = client.call("getpeerinfo", json!([]))?;
+    let syncing: Vec<&Value> = peers
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|peer| {
+            peer["inbound"].as_bool() == Some(true)
+                && peer["synced_blocks"]
+                    .as_i64()
+                    .is_some_and(|synced| synced >= 0 && synced < blocks - SYNCING_PEER_LAG)
+        })
+        .collect();
+    if !syncing.is_empty() {
+        let names: Vec<String> = syncing
+            .iter()
+            .map(|peer| {
+                format!(
+                    "{} ({})",
+                    peer["addr"].as_str().unwrap_or_default(),
+                    peer["subver"].as_str().unwrap_or_default()
+                )
+            })
+            .collect();
+        warn!(
+            "The feeder node is serving blocks to {} other syncing peers, which share its \
+             bandwidth with the benchmark: {}",
+            syncing.len(),
+            names.join(", ")
+        );
+    }
+    debug!(
+        "Feeder node at height {blocks} has {} peers",
+        peers.as_array().map_or(0, Vec::len)
+    );
+    Ok(())
+}
+
+/// A P2P message with its header
+fn message(magic: [u8; 4], command: &str, payload: &[u8]) -> Vec<u8> {
+    let mut name = [0u8; 12];
+    name[..command.len()].copy_from_slice(command.as_bytes());
+    let checksum = Sha256::digest(Sha256::digest(payload));
+    let mut message = magic.to_vec();
+    message.extend_from_slice(&name);
+    message.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    message.extend_from_slice(&checksum[..4]);
+    message.extend_from_slice(payload);
+    message
+}
+
+fn version_payload() -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+    // Services, an IPv4-mapped unspecified address and port 0
+    let mut addr = vec![0u8; 8];
+    addr.extend_from_slice(&[0; 10]);
+    addr.extend_from_slice(&[0xff, 0xff, 0, 0, 0, 0, 0, 0]);
+
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    payload.extend_from_slice(&0u64.to_le_bytes());
+    payload.extend_from_slice(&timestamp.to_le_bytes());
+    payload.extend_from_slice(&addr);
+    payload.extend_from_slice(&addr);
+    payload.extend_from_slice(&(std::process::id() as u64 ^ timestamp as u64).to_le_bytes());
+    payload.push(USER_AGENT.len() as u8);
+    payload.extend_from_slice(USER_AGENT.as_bytes());
+    payload.extend_from_slice(&0i32.to_le_bytes());
+    // Don't relay transactions
+    payload.push(0);
+    payload
+}
+
+fn parse_version(payload: &[u8]) -> Result<PeerVersion> {
+    // version, services, timestamp, addr_recv, addr_from and nonce come before the user agent
+    const USER_AGENT_OFFSET: usize = 4 + 8 + 8 + 26 + 26 + 8;
+    let version = i32::from_le_bytes(payload.get(..4).context("truncated")?.try_into().unwrap());
+    let length = *payload.get(USER_AGENT_OFFSET).context("truncated")? as usize;
+    if length >= 0xfd {
+        anyhow::bail!("user agent too long");
+    }
+    let start = USER_AGENT_OFFSET + 1;
+    let user_agent = payload
+        .get(start..start + length)
+        .context("truncated user agent")?;
+    let start_height = payload
+        .get(start + length..start + length + 4)
+        .map(|height| i32::from_le_bytes(height.try_into().unwrap()))
+        .unwrap_or_default();
+    Ok(PeerVersion {
+        version,
+        user_agent: String::from_utf8_lossy(user_agent).to_string(),
+        start_height,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    /// A node answering one handshake with a version message using `magic`
+    fn fake_node(magic: [u8; 4]) -> String {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0u8; 24];
+            stream.read_exact(&mut header).unwrap();
+            let length = u32::from_le_bytes(header[16..20].try_into().unwrap()) as usize;
+            let mut payload = vec![0u8; length];
+            stream.read_exact(&mut payload).unwrap();
+            let received = parse_version(&payload).unwrap();
+            assert_eq!(received.user_agent, USER_AGENT);
+
+            let mut reply = version_payload();
+            let user_agent = b"/Satoshi:28.1.0/";
+            reply.truncate(80);
+            reply.push(user_agent.len() as u8);
+            reply.extend_from_slice(user_agent);
+            reply.extend_from_slice(&123_456i32.to_le_bytes());
+            reply.push(1);
+            stream
+                .write_all(&message(magic, "version", &reply))
+                .unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn test_handshake() {
+        let address = fake_node(Network::Signet.magic());
+        let peer = check_feeder(&address, Network::Signet, None).unwrap();
+        assert_eq!(peer.user_agent, "/Satoshi:28.1.0/");
+        assert_eq!(peer.version, PROTOCOL_VERSION);
+        assert_eq!(peer.start_height, 123_456);
+
+        let address = fake_node(Network::Main.magic());
+        let err = handshake(&address, Network::Regtest).unwrap_err();
+        assert!(err.to_string().contains("is not a regtest node"), "{err}");
+
+        // A closed port is reported as nothing listening
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let closed = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let err = handshake(&closed, Network::Signet).unwrap_err();
+        assert!(err.to_string().contains("Nothing is listening"), "{err}");
+
+        // A node closing the connection without answering
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_exact(&mut [0u8; 24]).unwrap();
+        });
+        let err = handshake(&address, Network::Signet).unwrap_err();
+        assert!(err.to_string().contains("closed the connection"), "{err}");
+    }
+
+    #[test]
+    fn test_feeder_identity() {
+        let log = "\
+2025-01-01T00:00:00Z [net] receive version message: /Satoshi:28.1.0/: version 70016, blocks=880000, us=127.0.0.1:12000, txrelay=1, peer=0, peeraddr=127.0.0.1:38333
+2025-01-01T00:00:00Z New manual v2 peer connected: version: 70016, blocks=880000, peer=0, peeraddr=127.0.0.1:38333
+2025-01-01T00:00:01Z New inbound v1 peer connected: version: 70016, blocks=0, peer=1
+2025-01-01T00:05:00Z New outbound-full-relay v1 peer connected: version: 70015, blocks=880010, peer=2
+";
+        let connections = parse_peer_connections(log);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(
+            connections[0],
+            PeerConnection {
+                peer: 0,
+                version: 70016,
+                user_agent: Some("/Satoshi:28.1.0/".to_string()),
+                start_height: 880000,
+                address: Some("127.0.0.1:38333".to_string()),
+            }
+        );
+        assert_eq!(connections[1].user_agent, None);
+        assert_eq!(connections[1].address, None);
+
+        let peer = PeerVersion {
+            version: 70016,
+            user_agent: "/Satoshi:28.1.0/".to_string(),
+            start_height: 880000,
+        };
+        let identity = FeederIdentity::new(
+            "127.0.0.1:38333",
+            Some(peer.clone()),
+            Some(peer.clone()),
+            &[&connections[0]],
+        );
+        assert!(!identity.changed);
+        assert_eq!(identity.user_agent(), Some("/Satoshi:28.1.0/"));
+        // Reconnecting to a node with another protocol version mid-run
+        let identity = FeederIdentity::new(
+            "127.0.0.1:38333",
+            Some(peer.clone()),
+            None,
+            &connections.iter().collect::<Vec<_>>(),
+        );
+        assert!(identity.changed);
+        let upgraded = PeerVersion {
+            user_agent: "/Satoshi:29.0.0/".to_string(),
+            ..peer.clone()
+        };
+        assert!(FeederIdentity::new("127.0.0.1:38333", Some(peer), Some(upgraded), &[]).changed);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let port = |address: &str| resolve(address, Network::Signet).unwrap()[0].port();
+        assert_eq!(port("127.0.0.1"), 38333);
+        assert_eq!(port("127.0.0.1:39333"), 39333);
+        assert_eq!(port("::1"), 38333);
+        assert_eq!(port("[::1]:39333"), 39333);
+
+        let rpc = FeederRpc {
+            url: "http://127.0.0.1:38332".to_string(),
+            cookie: None,
+            user: Some("user".to_string()),
+            password: None,
+        };
+        assert!(rpc.validate().is_err());
+    }
+}
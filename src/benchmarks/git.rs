@@ -0,0 +1,86 @@
+use anyhow::Result;
+use std::path::Path;
+use std::process::Output;
+use std::sync::Arc;
+
+use crate::command::{CommandContext, ProcessRunner, SystemProcessRunner};
+
+/// Runs git in repositories
+///
+/// Goes through a `ProcessRunner`, so code using it can be tested with `MockProcessRunner`.
+/// Failures are reported as a `BenchkitError::Command` naming the git command and holding its
+/// stderr.
+#[derive(Clone)]
+pub struct Git {
+    process: Arc<dyn ProcessRunner>,
+}
+
+impl Default for Git {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemProcessRunner))
+    }
+}
+
+impl Git {
+    pub fn new(process: Arc<dyn ProcessRunner>) -> Self {
+        Self { process }
+    }
+
+    /// Run git in a directory, failing if it exits unsuccessfully unless `allow_failure` is set
+    pub fn output(&self, dir: &Path, args: &[&str], allow_failure: bool) -> Result<Output> {
+        let context = CommandContext {
+            command_name: Some(format!("git {}", args.join(" "))),
+            working_dir: Some(dir.to_string_lossy().to_string()),
+            capture_output: true,
+            allow_failure,
+            ..CommandContext::default()
+        };
+        self.process.run("git", args, &context)
+    }
+
+    /// Run git in a directory, failing if it exits unsuccessfully
+    pub fn run(&self, dir: &Path, args: &[&str]) -> Result<()> {
+        self.output(dir, args, false)?;
+        Ok(())
+    }
+
+    /// Run git in a directory and return its trimmed stdout
+    pub fn stdout(&self, dir: &Path, args: &[&str]) -> Result<String> {
+        let output = self.output(dir, args, false)?;
+        Ok(String::from_utf8(output.stdout)?.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::MockProcessRunner;
+    use crate::error::BenchkitError;
+
+    #[test]
+    fn test_git() {
+        let process = Arc::new(
+            MockProcessRunner::new()
+                .respond("git rev-parse", 0, "abc\n")
+                .respond("git diff", 1, ""),
+        );
+        let git = Git::new(process.clone());
+        let dir = Path::new("/src/bitcoin");
+        assert_eq!(git.stdout(dir, &["rev-parse", "HEAD"]).unwrap(), "abc");
+        assert!(!git
+            .output(dir, &["diff", "--quiet"], true)
+            .unwrap()
+            .status
+            .success());
+
+        let err = git.run(dir, &["diff", "--quiet"]).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<BenchkitError>(),
+            Some(BenchkitError::Command { command, exit_code: 1, .. }) if command == "git diff --quiet"
+        ));
+        assert_eq!(
+            process.calls()[0].working_dir.as_deref(),
+            Some("/src/bitcoin")
+        );
+    }
+}
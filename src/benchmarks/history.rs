@@ -0,0 +1,293 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::benchmarks::results::{
+    t_critical_95, BenchmarkResult, InstrumentationType, ResultAnalyzer,
+};
+use crate::path_utils;
+use crate::types::DurationMs;
+
+/// The summary of one parameter combination of a benchmark, as recorded after each run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Unix timestamp of when the entry was recorded
+    pub recorded_at: u64,
+    /// Parameters of the result, with the commit by its configured label (e.g. a branch) so
+    /// the label's history is followed as it moves
+    pub label: String,
+    /// Mean duration of the uninstrumented runs
+    pub mean: DurationMs,
+    /// Standard deviation
+    pub std_dev: DurationMs,
+    /// Number of uninstrumented runs
+    pub runs: usize,
+}
+
+/// Label results are recorded under: their parameters, with the commit by its configured label
+pub fn history_label(parameters: &HashMap<String, String>, commit_label: Option<&str>) -> String {
+    let mut parameters = parameters.clone();
+    if let Some(label) = commit_label {
+        parameters.insert("commit".to_string(), label.to_string());
+    }
+    ResultAnalyzer::parameter_label(&parameters)
+}
+
+impl HistoryEntry {
+    fn from_result(result: &BenchmarkResult, recorded_at: u64) -> Option<Self> {
+        let runs = result
+            .runs
+            .iter()
+            .filter(|run| run.instrumentation == InstrumentationType::Uninstrumented)
+            .count();
+        if runs == 0 {
+            return None;
+        }
+        Some(Self {
+            recorded_at,
+            label: history_label(&result.parameters, result.commit_label.as_deref()),
+            mean: result.summary.mean,
+            std_dev: result.summary.std_dev,
+            runs,
+        })
+    }
+}
+
+/// Whether a change from the previous run is larger than the noise of both runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Verdict {
+    Improvement,
+    Regression,
+    NoChange,
+}
+
+/// How a benchmark's parameter combination changed since it was last recorded
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub previous: HistoryEntry,
+    pub current: HistoryEntry,
+    /// Change of the mean, as a fraction of the previous mean
+    pub relative: f64,
+    pub verdict: Verdict,
+}
+
+impl Change {
+    /// Compare two entries, calling a change likely when the 95% confidence interval of the
+    /// difference of their means excludes zero
+    fn between(previous: HistoryEntry, current: HistoryEntry) -> Self {
+        let std_error = |entry: &HistoryEntry| entry.std_dev.0 / (entry.runs as f64).sqrt();
+        let difference = (current.mean - previous.mean).0;
+        let combined = (std_error(&previous).powi(2) + std_error(&current).powi(2)).sqrt();
+        // Conservative degrees of freedom, the smaller sample's
+        let df = previous.runs.min(current.runs).saturating_sub(1);
+        let significant = difference.abs() > t_critical_95(df) * combined;
+        let verdict = match (significant, difference < 0.0) {
+            (false, _) => Verdict::NoChange,
+            (true, true) => Verdict::Improvement,
+            (true, false) => Verdict::Regression,
+        };
+        Self {
+            relative: if previous.mean.0 > 0.0 {
+                difference / previous.mean.0
+            } else {
+                0.0
+            },
+            previous,
+            current,
+            verdict,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: change vs last run: {:+.1}% ({})",
+            self.current.label,
+            self.relative * 100.0,
+            match self.verdict {
+                Verdict::Improvement => "likely improvement",
+                Verdict::Regression => "likely regression",
+                Verdict::NoChange => "within noise",
+            }
+        )
+    }
+}
+
+/// Summaries of earlier runs of each benchmark on one machine, in
+/// `<home_dir>/history/<machine fingerprint>/<benchmark>.jsonl`
+pub struct History {
+    dir: PathBuf,
+}
+
+impl History {
+    pub fn new(home_dir: &Path, fingerprint: &str) -> Self {
+        Self {
+            dir: home_dir.join("history").join(fingerprint),
+        }
+    }
+
+    fn path(&self, benchmark: &str) -> PathBuf {
+        let name: String = benchmark
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        self.dir.join(format!("{name}.jsonl"))
+    }
+
+    /// Every entry recorded for a benchmark, oldest first
+    pub fn load(&self, benchmark: &str) -> Result<Vec<HistoryEntry>> {
+        let path = self.path(benchmark);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("Invalid history entry in {}", path.display()))
+            })
+            .collect()
+    }
+
+    /// The most recently recorded mean of each parameter combination of a benchmark, by label
+    pub fn last_means(&self, benchmark: &str) -> Result<HashMap<String, DurationMs>> {
+        Ok(self
+            .load(benchmark)?
+            .into_iter()
+            .map(|entry| (entry.label, entry.mean))
+            .collect())
+    }
+
+    /// Append the summaries of a benchmark's results, returning how each parameter
+    /// combination changed since it was last recorded
+    pub fn record(&self, benchmark: &str, results: &[BenchmarkResult]) -> Result<Vec<Change>> {
+        let mut last: HashMap<String, HistoryEntry> = HashMap::new();
+        for entry in self.load(benchmark)? {
+            last.insert(entry.label.clone(), entry);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let entries: Vec<HistoryEntry> = results
+            .iter()
+            .filter_map(|result| HistoryEntry::from_result(result, now))
+            .collect();
+        let changes = entries
+            .iter()
+            .filter_map(|entry| {
+                let previous = last.get(&entry.label)?.clone();
+                Some(Change::between(previous, entry.clone()))
+            })
+            .collect();
+
+        path_utils::ensure_directory(&self.dir)?;
+        let path = self.path(benchmark);
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        for entry in &entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::results::{RunResult, RunSummary};
+    use crate::types::DurationMs;
+    use tempfile::tempdir;
+
+    fn result(commit: &str, durations: &[f64]) -> BenchmarkResult {
+        let runs: Vec<RunResult> = durations
+            .iter()
+            .enumerate()
+            .map(|(iteration, &ms)| {
+                serde_json::from_value(serde_json::json!({
+                    "iteration": iteration,
+                    "duration_ms": ms,
+                    "exit_code": 0,
+                    "instrumentation": "Uninstrumented",
+                }))
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(runs[0].duration_ms, DurationMs(durations[0]));
+        let summary: RunSummary = ResultAnalyzer::calculate_summary(&runs);
+        BenchmarkResult {
+            command: "bitcoind".to_string(),
+            parameters: HashMap::from([("commit".to_string(), commit.to_string())]),
+            commit_label: Some("master".to_string()),
+            runs,
+            runs_needed: None,
+            version: None,
+            failures: Vec::new(),
+            spawn_overhead_ms: None,
+            cached_from: None,
+            out_dir: None,
+            feeder: None,
+            summary,
+            instrumented_summary: None,
+        }
+    }
+
+    #[test]
+    fn test_history_changes() {
+        let home = tempdir().unwrap();
+        let history = History::new(home.path(), "0123456789abcdef");
+
+        let first = history
+            .record("ibd/signet", &[result("aaa", &[100.0, 101.0, 99.0])])
+            .unwrap();
+        assert!(first.is_empty());
+
+        // A new commit of the same branch, clearly faster
+        let changes = history
+            .record("ibd/signet", &[result("bbb", &[90.0, 91.0, 89.0])])
+            .unwrap();
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].verdict, Verdict::Improvement);
+        assert_eq!(
+            changes[0].to_string(),
+            "commit=master: change vs last run: -10.0% (likely improvement)"
+        );
+
+        // Within the noise of the previous run
+        let changes = history
+            .record("ibd/signet", &[result("ccc", &[80.0, 100.0, 90.5])])
+            .unwrap();
+        assert_eq!(changes[0].verdict, Verdict::NoChange);
+
+        let changes = history
+            .record("ibd/signet", &[result("ddd", &[120.0, 121.0, 119.0])])
+            .unwrap();
+        assert_eq!(changes[0].verdict, Verdict::Regression);
+
+        assert_eq!(history.load("ibd/signet").unwrap().len(), 4);
+        let means = history.last_means("ibd/signet").unwrap();
+        assert_eq!(means["commit=master"], DurationMs(120.0));
+        assert!(history.path("ibd/signet").ends_with("ibd_signet.jsonl"));
+        assert!(History::new(home.path(), "other")
+            .load("ibd/signet")
+            .unwrap()
+            .is_empty());
+    }
+}
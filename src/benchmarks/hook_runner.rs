@@ -1,13 +1,26 @@
 use anyhow::Result;
 use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::benchmarks::hooks::{
-    AssumeUtxoHookExecutor, FullIbdHookExecutor, HookExecutor, HookMode,
+    AssumeUtxoHookExecutor, FileSystem, FullIbdHookExecutor, HookExecutor, HookMode,
+    LoadBlockHookExecutor, SystemFileSystem,
 };
+use crate::benchmarks::live::LiveStatus;
+use crate::benchmarks::parameters::{substitute_placeholders, ParameterUtils};
+use crate::command::{ProcessRunner, SystemProcessRunner};
+use crate::config::BenchmarkOptions;
+use crate::error::BenchkitError;
+use crate::telemetry;
+use crate::types::Network;
 
 /// Represents the different hook script stages
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum HookStage {
     Setup,
     Prepare,
@@ -15,27 +28,166 @@ pub enum HookStage {
     Cleanup,
 }
 
+impl HookStage {
+    /// Name of the stage as written in configs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookStage::Setup => "setup",
+            HookStage::Prepare => "prepare",
+            HookStage::Conclude => "conclude",
+            HookStage::Cleanup => "cleanup",
+        }
+    }
+}
+
 /// Arguments to pass to hook scripts
-#[derive(Debug, Clone)]
+///
+/// Serializable so the full context of a run can be handed to external hook scripts as JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HookArgs {
+    /// Name of the benchmark
+    pub benchmark: String,
+    /// The benchmark's options merged with the global ones
+    pub options: BenchmarkOptions,
     /// Path to the binary being benchmarked
     pub binary: String,
     /// Address to connect to (e.g., for Bitcoin Core)
     pub connect_address: String,
-    /// Network to use (e.g., mainnet, testnet, signet)
-    pub network: String,
+    /// Network to use
+    pub network: Network,
+    /// Ports the benchmarked node listens on
+    pub ports: NodePorts,
+    /// Environment variables from the benchmark's `env`, set for the command and hook processes
+    pub env: HashMap<String, String>,
     /// Output directory for benchmark results
     pub out_dir: PathBuf,
+    /// Output directory of the current run within `out_dir`, laid out by the benchmark's
+    /// `out_dir_template`, which the conclude hook moves the run's debug.log into
+    pub run_out_dir: PathBuf,
     /// Path to snapshot file
     pub snapshot_path: PathBuf,
-    /// Temporary data directory for the benchmarked process
+    /// Root of the temporary data directories, cleared by the setup and cleanup hooks
     pub tmp_data_dir: PathBuf,
+    /// Datadir of the current run, `{datadir}` in commands, created by the prepare hook and
+    /// removed by the conclude hook
+    pub datadir: PathBuf,
     /// Current iteration number
     pub iteration: usize,
     /// Commit being benchmarked
     pub commit: String,
     /// Parameter string for directory organization (always present, "default" if no params)
     pub params_dir: String,
+    /// Block files imported with -loadblock in loadblock mode
+    pub loadblock: Vec<PathBuf>,
+    /// Datadir with the network's headers synced, shared by the runs of all commits, or None to
+    /// sync headers in every run
+    pub header_cache: Option<PathBuf>,
+    /// Values of the active parameter combination, exported to processes hooks start as
+    /// `BENCHKIT_PARAM_<NAME>` variables
+    pub params: HashMap<String, String>,
+}
+
+impl HookArgs {
+    /// Args of a single run, with a datadir of its own at
+    /// `<tmp_data_dir>/<commit>/<params_dir>/<iteration>` and its output in `run_out_dir`
+    pub fn for_run(&self, params_dir: &str, iteration: usize, run_out_dir: PathBuf) -> Self {
+        Self {
+            iteration,
+            params_dir: params_dir.to_string(),
+            datadir: self.run_dir(params_dir).join(iteration.to_string()),
+            run_out_dir,
+            ..self.clone()
+        }
+    }
+
+    /// Directory the datadirs of a parameter combination's runs are created in
+    pub fn run_dir(&self, params_dir: &str) -> PathBuf {
+        self.tmp_data_dir.join(&self.commit).join(params_dir)
+    }
+
+    /// Environment of processes started by hooks: the benchmark's `env` and the active
+    /// parameters
+    pub fn process_env(&self) -> HashMap<String, String> {
+        process_env(&self.env, &self.params)
+    }
+
+    /// Value of a built-in placeholder (see `BUILTIN_PLACEHOLDERS`)
+    pub fn placeholder(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "commit" => self.commit.clone(),
+            "binary" => self.binary.replace("{commit}", &self.commit),
+            "datadir" => self.datadir.display().to_string(),
+            "snapshot" => self.snapshot_path.display().to_string(),
+            "out_dir" => self.out_dir.display().to_string(),
+            "network" => self.network.chain_arg().to_string(),
+            "connect" => self.connect_address.clone(),
+            "iteration" => self.iteration.to_string(),
+            _ => return None,
+        })
+    }
+
+    /// Resolve the built-in placeholders in a command template or hook argument
+    pub fn substitute(&self, template: &str) -> String {
+        substitute_placeholders(template, |name| self.placeholder(name))
+    }
+
+    /// Resolve the built-in placeholders which are the same for every run of a parameter
+    /// combination, leaving `{datadir}` and `{iteration}` to be resolved for each run
+    pub fn substitute_combination(&self, template: &str) -> String {
+        substitute_placeholders(template, |name| match name {
+            "datadir" | "iteration" => None,
+            _ => self.placeholder(name),
+        })
+    }
+
+    /// Serialize the args for an external hook script
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Directory bitcoind writes its debug.log to for the network
+    pub fn network_datadir(&self) -> PathBuf {
+        match self.network.data_subdir() {
+            Some(subdir) => self.datadir.join(subdir),
+            None => self.datadir.clone(),
+        }
+    }
+}
+
+/// The benchmark's `env` together with the parameters as `BENCHKIT_PARAM_<NAME>` variables
+pub fn process_env(
+    env: &HashMap<String, String>,
+    params: &HashMap<String, String>,
+) -> HashMap<String, String> {
+    let mut vars = env.clone();
+    vars.extend(ParameterUtils::params_to_env(params));
+    vars
+}
+
+/// P2P and RPC ports of a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodePorts {
+    pub p2p: u16,
+    pub rpc: u16,
+}
+
+impl NodePorts {
+    /// Ports set with `-port` and `-rpcport` in a command, or the network's defaults
+    ///
+    /// The last value of each argument is used, like bitcoind does.
+    pub fn from_command(command: &str, network: Network) -> Self {
+        let port = |name: &str| {
+            Regex::new(&format!(r"(?:^|\s)--?{name}=(\d+)"))
+                .unwrap()
+                .captures_iter(command)
+                .last()
+                .and_then(|caps| caps[1].parse().ok())
+        };
+        Self {
+            p2p: port("port").unwrap_or_else(|| network.default_p2p_port()),
+            rpc: port("rpcport").unwrap_or_else(|| network.default_rpc_port()),
+        }
+    }
 }
 
 /// HookRunner manages the lifecycle hooks for benchmarks
@@ -57,23 +209,124 @@ impl HookRunner {
 
     /// Create a new HookRunner with the specified mode
     pub fn with_mode(mode: HookMode) -> Self {
+        Self::with_io(
+            mode,
+            Arc::new(SystemProcessRunner),
+            Arc::new(SystemFileSystem),
+        )
+    }
+
+    /// Create a HookRunner for a mode whose hooks run processes and touch files through the
+    /// given layers, e.g. mocks in tests
+    pub fn with_io(
+        mode: HookMode,
+        process: Arc<dyn ProcessRunner>,
+        fs: Arc<dyn FileSystem>,
+    ) -> Self {
         let executor: Box<dyn HookExecutor> = match mode {
-            HookMode::AssumeUtxo => Box::new(AssumeUtxoHookExecutor::new()),
-            HookMode::FullIbd => Box::new(FullIbdHookExecutor::new()),
+            HookMode::AssumeUtxo => Box::new(AssumeUtxoHookExecutor::with_io(process, fs)),
+            HookMode::FullIbd => Box::new(FullIbdHookExecutor::with_fs(fs)),
+            HookMode::LoadBlock => Box::new(LoadBlockHookExecutor::with_fs(fs)),
         };
 
         Self { executor }
     }
 
+    /// Create a HookRunner with a custom executor
+    pub fn with_executor(executor: Box<dyn HookExecutor>) -> Self {
+        Self { executor }
+    }
+
     /// Run a hook for the given stage
     pub fn run_hook(&self, stage: HookStage, args: &HookArgs) -> Result<()> {
         info!("Running {stage:?} hook");
+        let mut span = telemetry::span(format!("hook {}", stage.as_str()));
+        span.attr("benchkit.commit", &args.commit)
+            .attr("benchkit.params_dir", &args.params_dir);
+        LiveStatus::record(stage.into(), args);
 
-        match stage {
+        let result = match stage {
             HookStage::Setup => self.executor.setup(args),
             HookStage::Prepare => self.executor.prepare(args),
             HookStage::Conclude => self.executor.conclude(args),
             HookStage::Cleanup => self.executor.cleanup(args),
+        };
+        span.record(result)
+            .map_err(|source| BenchkitError::Hook { stage, source }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_args_json() {
+        assert_eq!(
+            NodePorts::from_command(
+                "bitcoind -port=1000 -rpcport=2000 -port=3000",
+                Network::Main
+            ),
+            NodePorts {
+                p2p: 3000,
+                rpc: 2000
+            }
+        );
+        assert_eq!(
+            NodePorts::from_command("bitcoind -rpcport={rpcport}", Network::Signet),
+            NodePorts {
+                p2p: 38333,
+                rpc: 38332
+            }
+        );
+
+        let args = HookArgs {
+            benchmark: "ibd".to_string(),
+            options: BenchmarkOptions {
+                runs: 3,
+                profile: Some(true),
+                ..Default::default()
+            },
+            binary: "bitcoind".to_string(),
+            connect_address: "127.0.0.1:8333".to_string(),
+            network: Network::Main,
+            ports: NodePorts::from_command("bitcoind", Network::Main),
+            env: HashMap::from([("MALLOC_ARENA_MAX".to_string(), "1".to_string())]),
+            out_dir: PathBuf::from("/out"),
+            run_out_dir: PathBuf::from("/out"),
+            snapshot_path: PathBuf::new(),
+            tmp_data_dir: PathBuf::from("/tmp/benchkit"),
+            datadir: PathBuf::from("/tmp/benchkit"),
+            iteration: 0,
+            commit: "abc".to_string(),
+            params_dir: "dbcache_450".to_string(),
+            loadblock: Vec::new(),
+            header_cache: None,
+            params: HashMap::from([("dbcache".to_string(), "450".to_string())]),
+        };
+        assert_eq!(
+            args.process_env(),
+            HashMap::from([
+                ("MALLOC_ARENA_MAX".to_string(), "1".to_string()),
+                ("BENCHKIT_PARAM_DBCACHE".to_string(), "450".to_string()),
+            ])
+        );
+
+        for name in crate::benchmarks::BUILTIN_PLACEHOLDERS {
+            assert!(args.placeholder(name).is_some(), "{name}");
         }
+        let template = "{binary} -chain={network} -datadir={datadir} -par=${PAR} {dbcache}";
+        assert_eq!(
+            args.substitute_combination(template),
+            "bitcoind -chain=main -datadir={datadir} -par=${PAR} {dbcache}"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&args.to_json().unwrap()).unwrap();
+        assert_eq!(json["network"], "main");
+        assert_eq!(json["ports"]["rpc"], 8332);
+        assert_eq!(json["options"]["profile"], true);
+        let parsed: HookArgs = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.options.runs, 3);
+        assert_eq!(parsed.params, args.params);
     }
 }
@@ -1,10 +1,13 @@
 use anyhow::{Context, Result};
 use log::{debug, info};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use crate::benchmarks::hook_runner::HookArgs;
-use crate::command::{CommandContext, CommandExecutor};
+use crate::command::{CommandContext, ProcessRunner, SystemProcessRunner};
+use crate::path_utils;
 
 /// Different modes for benchmark hook execution
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -14,6 +17,8 @@ pub enum HookMode {
     AssumeUtxo,
     /// Full IBD mode - no snapshot, full initial block download
     FullIbd,
+    /// Block replay mode - imports blocks from local block files with -loadblock, without peers
+    LoadBlock,
 }
 
 impl HookMode {
@@ -22,6 +27,7 @@ impl HookMode {
         match s.to_lowercase().as_str() {
             "assumeutxo" => Ok(HookMode::AssumeUtxo),
             "full_ibd" | "fullibd" | "full-ibd" => Ok(HookMode::FullIbd),
+            "loadblock" | "load_block" | "load-block" => Ok(HookMode::LoadBlock),
             _ => anyhow::bail!("Unknown hook mode: {}", s),
         }
     }
@@ -35,37 +41,263 @@ pub trait HookExecutor {
     fn cleanup(&self, args: &HookArgs) -> Result<()>;
 }
 
+/// Filesystem operations of the hooks
+///
+/// Hooks can be unit tested with `MockFileSystem`, without touching the disk.
+pub trait FileSystem: Send + Sync {
+    fn exists(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+    fn is_file(&self, path: &Path) -> bool;
+    /// Create a directory, including all parent directories
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+    /// Paths of a directory's entries
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+    fn remove_file(&self, path: &Path) -> Result<()>;
+    /// Rename a file or directory, failing if `to` is a non-empty directory
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<()>;
+    /// Copy a directory's contents into another, creating it if needed
+    fn copy_dir_all(&self, from: &Path, to: &Path) -> Result<()>;
+}
+
+/// The real filesystem
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemFileSystem;
+
+impl FileSystem for SystemFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        fs::create_dir_all(path)
+            .with_context(|| format!("Failed to create directory: {}", path.display()))
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        fs::read_dir(path)
+            .with_context(|| format!("Failed to read directory: {}", path.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .collect()
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        fs::remove_dir_all(path)
+            .with_context(|| format!("Failed to remove directory: {}", path.display()))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        fs::remove_file(path).with_context(|| format!("Failed to remove file: {}", path.display()))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)
+            .with_context(|| format!("Failed to rename {} to {}", from.display(), to.display()))
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::copy(from, to)
+            .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+        Ok(())
+    }
+
+    fn copy_dir_all(&self, from: &Path, to: &Path) -> Result<()> {
+        path_utils::copy_dir_all(from, to)
+    }
+}
+
+/// An entry of a `MockFileSystem`
+#[derive(Debug, Clone, PartialEq)]
+enum MockEntry {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// An in-memory filesystem
+#[derive(Debug, Default)]
+pub struct MockFileSystem {
+    entries: Mutex<BTreeMap<PathBuf, MockEntry>>,
+}
+
+impl MockFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write a file, creating its parent directories
+    pub fn write(&self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent).unwrap();
+        }
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            MockEntry::File(contents.as_ref().to_vec()),
+        );
+    }
+
+    /// Contents of a file, None if there is none
+    pub fn read(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        match self.entries.lock().unwrap().get(path.as_ref()) {
+            Some(MockEntry::File(contents)) => Some(contents.clone()),
+            _ => None,
+        }
+    }
+
+    /// Every file and directory, in order
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.entries.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// A path and everything below it
+    fn subtree(entries: &BTreeMap<PathBuf, MockEntry>, path: &Path) -> Vec<(PathBuf, MockEntry)> {
+        entries
+            .iter()
+            .filter(|(entry, _)| entry.starts_with(path))
+            .map(|(entry, kind)| (entry.clone(), kind.clone()))
+            .collect()
+    }
+}
+
+impl FileSystem for MockFileSystem {
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().get(path) == Some(&MockEntry::Dir)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(
+            self.entries.lock().unwrap().get(path),
+            Some(MockEntry::File(_))
+        )
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        for ancestor in path.ancestors().filter(|a| !a.as_os_str().is_empty()) {
+            match entries.get(ancestor) {
+                Some(MockEntry::File(_)) => {
+                    anyhow::bail!("Failed to create directory: {}", path.display())
+                }
+                Some(MockEntry::Dir) => {}
+                None => {
+                    entries.insert(ancestor.to_path_buf(), MockEntry::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            anyhow::bail!("Failed to read directory: {}", path.display());
+        }
+        Ok(self
+            .paths()
+            .into_iter()
+            .filter(|entry| entry.parent() == Some(path))
+            .collect())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        if !self.is_dir(path) {
+            anyhow::bail!("Failed to remove directory: {}", path.display());
+        }
+        self.entries
+            .lock()
+            .unwrap()
+            .retain(|entry, _| !entry.starts_with(path));
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        if !self.is_file(path) {
+            anyhow::bail!("Failed to remove file: {}", path.display());
+        }
+        self.entries.lock().unwrap().remove(path);
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        if !self.exists(from) || !self.read_dir(to).map_or(true, |e| e.is_empty()) {
+            anyhow::bail!("Failed to rename {} to {}", from.display(), to.display());
+        }
+        if let Some(parent) = to.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        for (entry, kind) in Self::subtree(&entries, from) {
+            entries.remove(&entry);
+            let relative = entry.strip_prefix(from).unwrap();
+            entries.insert(to.join(relative), kind);
+        }
+        Ok(())
+    }
+
+    fn copy_file(&self, from: &Path, to: &Path) -> Result<()> {
+        let contents = self
+            .read(from)
+            .with_context(|| format!("Failed to copy {} to {}", from.display(), to.display()))?;
+        self.write(to, contents);
+        Ok(())
+    }
+
+    fn copy_dir_all(&self, from: &Path, to: &Path) -> Result<()> {
+        if !self.is_dir(from) {
+            anyhow::bail!("Failed to copy {} to {}", from.display(), to.display());
+        }
+        self.create_dir_all(to)?;
+        let mut entries = self.entries.lock().unwrap();
+        for (entry, kind) in Self::subtree(&entries, from) {
+            let relative = entry.strip_prefix(from).unwrap();
+            entries.insert(to.join(relative), kind);
+        }
+        Ok(())
+    }
+}
+
 /// Base hook executor with common functionality
-struct BaseHookExecutor;
+struct BaseHookExecutor {
+    fs: Arc<dyn FileSystem>,
+}
+
+impl Default for BaseHookExecutor {
+    fn default() -> Self {
+        Self {
+            fs: Arc::new(SystemFileSystem),
+        }
+    }
+}
 
 impl BaseHookExecutor {
     /// Create a directory, including all parent directories
     fn create_directory(&self, path: &Path) -> Result<()> {
         debug!("Creating directory: {}", path.display());
-        fs::create_dir_all(path)
-            .with_context(|| format!("Failed to create directory: {}", path.display()))?;
-        Ok(())
+        self.fs.create_dir_all(path)
     }
 
     /// Clear all contents of a directory
     fn clear_directory(&self, path: &Path) -> Result<()> {
         debug!("Clearing directory contents: {}", path.display());
 
-        if path.exists() {
-            for entry in fs::read_dir(path)
-                .with_context(|| format!("Failed to read directory: {}", path.display()))?
-            {
-                let entry = entry?;
-                let entry_path = entry.path();
-
-                if entry_path.is_dir() {
-                    fs::remove_dir_all(&entry_path).with_context(|| {
-                        format!("Failed to remove directory: {}", entry_path.display())
-                    })?;
+        if self.fs.exists(path) {
+            for entry_path in self.fs.read_dir(path)? {
+                if self.fs.is_dir(&entry_path) {
+                    self.fs.remove_dir_all(&entry_path)?;
                 } else {
-                    fs::remove_file(&entry_path).with_context(|| {
-                        format!("Failed to remove file: {}", entry_path.display())
-                    })?;
+                    self.fs.remove_file(&entry_path)?;
                 }
             }
         }
@@ -73,6 +305,70 @@ impl BaseHookExecutor {
         Ok(())
     }
 
+    /// Move the run's debug.log into its output directory
+    fn move_debug_log(&self, args: &HookArgs) -> Result<()> {
+        let output_path = &args.run_out_dir;
+        info!("Moving debug.log to {}", output_path.display());
+        self.create_directory(output_path)?;
+
+        let debug_log_source = args.network_datadir().join("debug.log");
+
+        let debug_log_dest = output_path.join("debug.log");
+
+        // Move debug.log
+        if self.fs.exists(&debug_log_source) {
+            self.fs
+                .rename(&debug_log_source, &debug_log_dest)
+                .or_else(|_| -> Result<()> {
+                    // If rename fails (e.g., cross-filesystem), fall back to copy and delete
+                    self.fs.copy_file(&debug_log_source, &debug_log_dest)?;
+                    self.fs.remove_file(&debug_log_source)
+                })
+                .with_context(|| {
+                    format!(
+                        "Failed to move debug.log from {} to {}",
+                        debug_log_source.display(),
+                        debug_log_dest.display()
+                    )
+                })?;
+        } else {
+            debug!("debug.log not found at {}", debug_log_source.display());
+        }
+        Ok(())
+    }
+
+    /// Remove a directory and everything in it, if it exists
+    fn remove_directory(&self, path: &Path) -> Result<()> {
+        debug!("Removing directory: {}", path.display());
+        if self.fs.exists(path) {
+            self.fs.remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+
+    /// Save the run's header-synced datadir as the header cache, without its debug.log
+    ///
+    /// The copy is renamed into place, so an interrupted copy is never used. If another benchkit
+    /// filled the cache in the meantime, its copy is kept.
+    fn cache_headers(&self, args: &HookArgs, cache: &Path) -> Result<()> {
+        info!("Caching headers in {}", cache.display());
+        let partial = cache.with_extension(format!("partial-{}", std::process::id()));
+        self.remove_directory(&partial)?;
+        self.fs.copy_dir_all(&args.datadir, &partial)?;
+        let debug_log = match args.network.data_subdir() {
+            Some(subdir) => partial.join(subdir).join("debug.log"),
+            None => partial.join("debug.log"),
+        };
+        if self.fs.exists(&debug_log) {
+            self.fs.remove_file(&debug_log)?;
+        }
+        if let Err(e) = self.fs.rename(&partial, cache) {
+            debug!("Keeping the existing header cache: {e}");
+            self.remove_directory(&partial)?;
+        }
+        Ok(())
+    }
+
     /// Clear and recreate a directory
     fn clear_and_recreate_directory(&self, path: &Path) -> Result<()> {
         self.create_directory(path)?;
@@ -84,28 +380,42 @@ impl BaseHookExecutor {
 /// AssumeUTXO hook executor - uses snapshot syncing
 pub struct AssumeUtxoHookExecutor {
     base: BaseHookExecutor,
+    process: Arc<dyn ProcessRunner>,
 }
 
 impl AssumeUtxoHookExecutor {
     pub fn new() -> Self {
+        Self::with_io(Arc::new(SystemProcessRunner), Arc::new(SystemFileSystem))
+    }
+
+    /// Run bitcoind and touch files through the given layers
+    pub fn with_io(process: Arc<dyn ProcessRunner>, fs: Arc<dyn FileSystem>) -> Self {
         Self {
-            base: BaseHookExecutor,
+            base: BaseHookExecutor { fs },
+            process,
         }
     }
 
-    fn execute_bitcoin_command(&self, binary: &str, args: &[&str]) -> Result<()> {
+    fn execute_bitcoin_command(
+        &self,
+        binary: &str,
+        args: &[&str],
+        env_vars: HashMap<String, String>,
+    ) -> Result<()> {
         let command_desc = format!("{} {}", binary, args.join(" "));
         let context = CommandContext {
             command_name: Some(command_desc),
             allow_failure: false,
             capture_output: true,
+            env_vars,
             ..CommandContext::default()
         };
 
-        let executor = CommandExecutor::with_context(context);
-        let status = executor
-            .execute_check_status(binary, args)
-            .with_context(|| "Failed to execute Bitcoin Core command".to_string())?;
+        let status = self
+            .process
+            .run(binary, args, &context)
+            .with_context(|| "Failed to execute Bitcoin Core command".to_string())?
+            .status;
 
         if !status.success() {
             return Err(anyhow::anyhow!(
@@ -117,18 +427,48 @@ impl AssumeUtxoHookExecutor {
         Ok(())
     }
 
+    /// Sync the network's headers into the run's datadir
+    fn sync_headers(
+        &self,
+        binary: &str,
+        args: &HookArgs,
+        env_vars: HashMap<String, String>,
+    ) -> Result<()> {
+        info!("Syncing headers");
+        let mut base_args = crate::benchmarks::utils::build_bitcoind_base_args(
+            args.network,
+            &args.datadir,
+            &args.connect_address,
+        );
+
+        base_args.extend_from_slice(&[
+            "-daemon=0".to_string(),
+            "-stopatheight=1".to_string(),
+            "-printtoconsole=0".to_string(),
+        ]);
+
+        // Convert to &str references for execute_bitcoin_command
+        let sync_args: Vec<&str> = base_args.iter().map(|s| s.as_str()).collect();
+        self.execute_bitcoin_command(binary, &sync_args, env_vars)
+    }
+
     /// Execute a Bitcoin Core command that may fail (like loadutxosnapshot)
-    fn execute_bitcoin_command_allow_failure(&self, binary: &str, args: &[&str]) -> Result<()> {
+    fn execute_bitcoin_command_allow_failure(
+        &self,
+        binary: &str,
+        args: &[&str],
+        env_vars: HashMap<String, String>,
+    ) -> Result<()> {
         let command_desc = format!("{} {}", binary, args.join(" "));
         let context = CommandContext {
             command_name: Some(command_desc),
             allow_failure: true,
             capture_output: true,
+            env_vars,
             ..CommandContext::default()
         };
 
-        let executor = CommandExecutor::with_context(context);
-        let _ = executor.execute_check_status(binary, args);
+        let _ = self.process.run(binary, args, &context);
 
         Ok(())
     }
@@ -151,30 +491,27 @@ impl HookExecutor for AssumeUtxoHookExecutor {
 
     fn prepare(&self, args: &HookArgs) -> Result<()> {
         info!("Running AssumeUTXO prepare hook");
-        self.base.clear_and_recreate_directory(&args.tmp_data_dir)?;
+        self.base.clear_and_recreate_directory(&args.datadir)?;
         let binary_path = args.binary.replace("{commit}", &args.commit);
-        info!("Syncing headers");
-        let mut base_args = crate::benchmarks::utils::build_bitcoind_base_args(
-            &args.network,
-            &args.tmp_data_dir,
-            &args.connect_address,
-        );
-
-        base_args.extend_from_slice(&[
-            "-daemon=0".to_string(),
-            "-stopatheight=1".to_string(),
-            "-printtoconsole=0".to_string(),
-        ]);
-
-        // Convert to &str references for execute_bitcoin_command
-        let sync_args: Vec<&str> = base_args.iter().map(|s| s.as_str()).collect();
-        self.execute_bitcoin_command(&binary_path, &sync_args)?;
+        let env_vars = args.process_env();
+        match &args.header_cache {
+            Some(cache) if self.base.fs.is_dir(cache) => {
+                info!("Copying cached headers from {}", cache.display());
+                self.base.fs.copy_dir_all(cache, &args.datadir)?;
+            }
+            _ => {
+                self.sync_headers(&binary_path, args, env_vars.clone())?;
+                if let Some(cache) = &args.header_cache {
+                    self.base.cache_headers(args, cache)?;
+                }
+            }
+        }
 
         // Load snapshot
         info!("Loading snapshot");
         let mut base_args = crate::benchmarks::utils::build_bitcoind_base_args(
-            &args.network,
-            &args.tmp_data_dir,
+            args.network,
+            &args.datadir,
             &args.connect_address,
         );
 
@@ -188,7 +525,7 @@ impl HookExecutor for AssumeUtxoHookExecutor {
 
         // Convert to &str references for execute_bitcoin_command
         let snapshot_args: Vec<&str> = base_args.iter().map(|s| s.as_str()).collect();
-        self.execute_bitcoin_command_allow_failure(&binary_path, &snapshot_args)?;
+        self.execute_bitcoin_command_allow_failure(&binary_path, &snapshot_args, env_vars)?;
 
         Ok(())
     }
@@ -196,47 +533,10 @@ impl HookExecutor for AssumeUtxoHookExecutor {
     fn conclude(&self, args: &HookArgs) -> Result<()> {
         info!("Running AssumeUTXO conclude hook");
 
-        // Create output directory structure
-        let output_path = args
-            .out_dir
-            .join(&args.commit)
-            .join(&args.params_dir)
-            .join(args.iteration.to_string());
+        self.base.move_debug_log(args)?;
 
-        info!("Moving debug.log to {}", output_path.display());
-        self.base.create_directory(&output_path)?;
-
-        // Determine debug.log source path based on network
-        let debug_log_source = if args.network == "main" {
-            args.tmp_data_dir.join("debug.log")
-        } else {
-            args.tmp_data_dir.join(&args.network).join("debug.log")
-        };
-
-        let debug_log_dest = output_path.join("debug.log");
-
-        // Move debug.log
-        if debug_log_source.exists() {
-            fs::rename(&debug_log_source, &debug_log_dest)
-                .or_else(|_| -> Result<()> {
-                    // If rename fails (e.g., cross-filesystem), fall back to copy and delete
-                    fs::copy(&debug_log_source, &debug_log_dest)?;
-                    fs::remove_file(&debug_log_source)?;
-                    Ok(())
-                })
-                .with_context(|| {
-                    format!(
-                        "Failed to move debug.log from {} to {}",
-                        debug_log_source.display(),
-                        debug_log_dest.display()
-                    )
-                })?;
-        } else {
-            debug!("debug.log not found at {}", debug_log_source.display());
-        }
-
-        // Clean datadir contents
-        self.base.clear_directory(&args.tmp_data_dir)?;
+        // The next run gets a new datadir
+        self.base.remove_directory(&args.datadir)?;
 
         Ok(())
     }
@@ -258,8 +558,13 @@ pub struct FullIbdHookExecutor {
 
 impl FullIbdHookExecutor {
     pub fn new() -> Self {
+        Self::with_fs(Arc::new(SystemFileSystem))
+    }
+
+    /// Touch files through the given layer
+    pub fn with_fs(fs: Arc<dyn FileSystem>) -> Self {
         Self {
-            base: BaseHookExecutor,
+            base: BaseHookExecutor { fs },
         }
     }
 }
@@ -283,7 +588,7 @@ impl HookExecutor for FullIbdHookExecutor {
         info!("Running Full IBD prepare hook");
 
         // Create datadir and clear contents
-        self.base.clear_and_recreate_directory(&args.tmp_data_dir)?;
+        self.base.clear_and_recreate_directory(&args.datadir)?;
 
         Ok(())
     }
@@ -291,53 +596,93 @@ impl HookExecutor for FullIbdHookExecutor {
     fn conclude(&self, args: &HookArgs) -> Result<()> {
         info!("Running Full IBD conclude hook");
 
-        // Create output directory structure
-        let output_path = args
-            .out_dir
-            .join(&args.commit)
-            .join(&args.params_dir)
-            .join(args.iteration.to_string());
+        self.base.move_debug_log(args)?;
 
-        info!("Moving debug.log to {}", output_path.display());
-        self.base.create_directory(&output_path)?;
+        // The next run gets a new datadir
+        self.base.remove_directory(&args.datadir)?;
 
-        // Determine debug.log source path based on network
-        let debug_log_source = if args.network == "main" {
-            args.tmp_data_dir.join("debug.log")
-        } else {
-            args.tmp_data_dir.join(&args.network).join("debug.log")
-        };
+        Ok(())
+    }
 
-        let debug_log_dest = output_path.join("debug.log");
+    fn cleanup(&self, args: &HookArgs) -> Result<()> {
+        info!("Running Full IBD cleanup hook");
 
-        // Move debug.log
-        if debug_log_source.exists() {
-            fs::rename(&debug_log_source, &debug_log_dest)
-                .or_else(|_| -> Result<()> {
-                    // If rename fails (e.g., cross-filesystem), fall back to copy and delete
-                    fs::copy(&debug_log_source, &debug_log_dest)?;
-                    fs::remove_file(&debug_log_source)?;
-                    Ok(())
-                })
-                .with_context(|| {
-                    format!(
-                        "Failed to move debug.log from {} to {}",
-                        debug_log_source.display(),
-                        debug_log_dest.display()
-                    )
-                })?;
-        } else {
-            debug!("debug.log not found at {}", debug_log_source.display());
+        // Final cleanup of datadir
+        self.base.clear_directory(&args.tmp_data_dir)?;
+
+        Ok(())
+    }
+}
+
+/// Block replay hook executor - starts every run from an empty datadir so bitcoind imports the
+/// configured block files with -loadblock
+pub struct LoadBlockHookExecutor {
+    base: BaseHookExecutor,
+}
+
+impl LoadBlockHookExecutor {
+    pub fn new() -> Self {
+        Self::with_fs(Arc::new(SystemFileSystem))
+    }
+
+    /// Touch files through the given layer
+    pub fn with_fs(fs: Arc<dyn FileSystem>) -> Self {
+        Self {
+            base: BaseHookExecutor { fs },
+        }
+    }
+}
+
+impl Default for LoadBlockHookExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HookExecutor for LoadBlockHookExecutor {
+    fn setup(&self, args: &HookArgs) -> Result<()> {
+        info!("Running loadblock setup hook");
+        if args.loadblock.is_empty() {
+            anyhow::bail!("loadblock mode requires at least one block file");
+        }
+        let missing: Vec<String> = args
+            .loadblock
+            .iter()
+            .filter(|path| !self.base.fs.is_file(path))
+            .map(|path| path.display().to_string())
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!("Missing block files: {}", missing.join(", "));
         }
 
-        // Clean datadir contents
+        self.base.create_directory(&args.tmp_data_dir)?;
         self.base.clear_directory(&args.tmp_data_dir)?;
 
         Ok(())
     }
 
+    fn prepare(&self, args: &HookArgs) -> Result<()> {
+        info!("Running loadblock prepare hook");
+
+        // Every replay starts from genesis
+        self.base.clear_and_recreate_directory(&args.datadir)?;
+
+        Ok(())
+    }
+
+    fn conclude(&self, args: &HookArgs) -> Result<()> {
+        info!("Running loadblock conclude hook");
+
+        self.base.move_debug_log(args)?;
+
+        // The next run gets a new datadir
+        self.base.remove_directory(&args.datadir)?;
+
+        Ok(())
+    }
+
     fn cleanup(&self, args: &HookArgs) -> Result<()> {
-        info!("Running Full IBD cleanup hook");
+        info!("Running loadblock cleanup hook");
 
         // Final cleanup of datadir
         self.base.clear_directory(&args.tmp_data_dir)?;
@@ -349,11 +694,13 @@ impl HookExecutor for FullIbdHookExecutor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::benchmarks::hook_runner::NodePorts;
+    use crate::command::MockProcessRunner;
     use tempfile::tempdir;
 
     #[test]
     fn test_create_directory() {
-        let base_executor = BaseHookExecutor;
+        let base_executor = BaseHookExecutor::default();
         let temp_dir = tempdir().unwrap();
         let test_path = temp_dir.path().join("test").join("nested").join("dir");
 
@@ -363,7 +710,7 @@ mod tests {
 
     #[test]
     fn test_clear_directory() {
-        let base_executor = BaseHookExecutor;
+        let base_executor = BaseHookExecutor::default();
         let temp_dir = tempdir().unwrap();
         let test_path = temp_dir.path();
 
@@ -386,4 +733,164 @@ mod tests {
         // Directory itself should still exist
         assert!(test_path.exists());
     }
+
+    #[test]
+    fn test_mock_assumeutxo_hooks() {
+        let fs = Arc::new(MockFileSystem::new());
+        let process = Arc::new(MockProcessRunner::new());
+        let executor = AssumeUtxoHookExecutor::with_io(process.clone(), fs.clone());
+        let args = HookArgs {
+            benchmark: "test".to_string(),
+            options: Default::default(),
+            binary: "/bin/bitcoind-{commit}".to_string(),
+            connect_address: String::new(),
+            network: crate::types::Network::Signet,
+            ports: NodePorts::from_command("bitcoind", crate::types::Network::Signet),
+            env: HashMap::new(),
+            out_dir: PathBuf::from("/out"),
+            run_out_dir: PathBuf::from("/out"),
+            snapshot_path: PathBuf::from("/snapshots/signet.dat"),
+            tmp_data_dir: PathBuf::from("/data"),
+            datadir: PathBuf::from("/data"),
+            iteration: 0,
+            commit: "abc".to_string(),
+            params_dir: "default".to_string(),
+            loadblock: Vec::new(),
+            header_cache: Some(PathBuf::from("/headers/signet")),
+            params: HashMap::new(),
+        };
+        fs.write("/data/stale", "old run");
+
+        executor.setup(&args).unwrap();
+        assert!(!fs.exists(Path::new("/data/stale")));
+
+        // The first run syncs headers and caches them, later runs copy the cache
+        let run = args.for_run("default", 0, "/out/abc/default/0".into());
+        executor.prepare(&run).unwrap();
+        let calls = process.command_lines();
+        assert_eq!(calls.len(), 2);
+        assert!(calls[0].starts_with("/bin/bitcoind-abc "), "{calls:?}");
+        assert!(calls[0].contains("-stopatheight=1"), "{calls:?}");
+        assert!(calls[1].contains("-loadutxosnapshot=/snapshots/signet.dat"));
+        assert!(fs.is_dir(Path::new("/headers/signet")));
+
+        let run = args.for_run("default", 1, "/out/abc/default/1".into());
+        executor.prepare(&run).unwrap();
+        assert_eq!(process.command_lines().len(), 3);
+
+        fs.write(run.network_datadir().join("debug.log"), "log");
+        executor.conclude(&run).unwrap();
+        assert_eq!(fs.read("/out/abc/default/1/debug.log").unwrap(), b"log");
+        assert!(!fs.exists(&run.datadir));
+
+        executor.cleanup(&args).unwrap();
+        assert!(fs.read_dir(Path::new("/data")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_loadblock_setup() {
+        let temp_dir = tempdir().unwrap();
+        let block_file = temp_dir.path().join("blk00000.dat");
+        let mut args = HookArgs {
+            benchmark: "test".to_string(),
+            options: Default::default(),
+            binary: "bitcoind".to_string(),
+            connect_address: String::new(),
+            network: crate::types::Network::Regtest,
+            ports: NodePorts::from_command("bitcoind", crate::types::Network::Regtest),
+            env: HashMap::new(),
+            out_dir: temp_dir.path().join("out"),
+            run_out_dir: temp_dir.path().join("out"),
+            snapshot_path: Default::default(),
+            tmp_data_dir: temp_dir.path().join("datadir"),
+            datadir: temp_dir.path().join("datadir"),
+            iteration: 0,
+            commit: "abc".to_string(),
+            params_dir: "default".to_string(),
+            loadblock: vec![block_file.clone()],
+            header_cache: None,
+            params: HashMap::new(),
+        };
+        let executor = LoadBlockHookExecutor::new();
+
+        let err = executor.setup(&args).unwrap_err();
+        assert!(err.to_string().contains("blk00000.dat"));
+
+        fs::write(&block_file, "blocks").unwrap();
+        executor.setup(&args).unwrap();
+        assert!(args.tmp_data_dir.exists());
+
+        args.loadblock.clear();
+        assert!(executor.setup(&args).is_err());
+    }
+
+    #[test]
+    fn test_per_run_datadir() {
+        let temp_dir = tempdir().unwrap();
+        let tmp_data_dir = temp_dir.path().join("datadir");
+        let args = HookArgs {
+            benchmark: "test".to_string(),
+            options: Default::default(),
+            binary: "bitcoind".to_string(),
+            connect_address: String::new(),
+            network: crate::types::Network::Signet,
+            ports: NodePorts::from_command("bitcoind", crate::types::Network::Signet),
+            env: HashMap::new(),
+            out_dir: temp_dir.path().join("out"),
+            run_out_dir: temp_dir.path().join("out"),
+            snapshot_path: Default::default(),
+            tmp_data_dir: tmp_data_dir.clone(),
+            datadir: tmp_data_dir.clone(),
+            iteration: 0,
+            commit: "abc".to_string(),
+            params_dir: "default".to_string(),
+            loadblock: Vec::new(),
+            header_cache: None,
+            params: HashMap::new(),
+        };
+        let run = args.for_run("dbcache_450", 2, args.out_dir.join("ibd/abc-450/2"));
+        assert_eq!(run.datadir, tmp_data_dir.join("abc/dbcache_450/2"));
+        assert_eq!(run.iteration, 2);
+
+        let executor = FullIbdHookExecutor::new();
+        executor.setup(&args).unwrap();
+        executor.prepare(&run).unwrap();
+        assert!(run.datadir.is_dir());
+        fs::create_dir_all(run.network_datadir()).unwrap();
+        fs::write(run.network_datadir().join("debug.log"), "log").unwrap();
+
+        executor.conclude(&run).unwrap();
+        assert!(!run.datadir.exists());
+        let debug_log = args.out_dir.join("ibd/abc-450/2/debug.log");
+        assert_eq!(fs::read_to_string(debug_log).unwrap(), "log");
+
+        executor.cleanup(&args).unwrap();
+        assert_eq!(fs::read_dir(&tmp_data_dir).unwrap().count(), 0);
+
+        // A header-synced datadir is cached without its debug.log, and an existing cache is kept
+        let cache = temp_dir.path().join("headers/signet");
+        fs::create_dir_all(run.network_datadir().join("blocks")).unwrap();
+        fs::write(run.network_datadir().join("blocks/index"), "headers").unwrap();
+        fs::write(run.network_datadir().join("debug.log"), "log").unwrap();
+        let base = BaseHookExecutor::default();
+        base.cache_headers(&run, &cache).unwrap();
+        assert_eq!(
+            fs::read_to_string(cache.join("signet/blocks/index")).unwrap(),
+            "headers"
+        );
+        assert!(!cache.join("signet/debug.log").exists());
+
+        fs::write(run.network_datadir().join("blocks/index"), "newer").unwrap();
+        base.cache_headers(&run, &cache).unwrap();
+        assert_eq!(
+            fs::read_to_string(cache.join("signet/blocks/index")).unwrap(),
+            "headers"
+        );
+        assert_eq!(
+            fs::read_dir(temp_dir.path().join("headers"))
+                .unwrap()
+                .count(),
+            1
+        );
+    }
 }
@@ -0,0 +1,441 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::benchmarks::hook_runner::{HookArgs, HookStage};
+use crate::benchmarks::perf::LogTail;
+use crate::benchmarks::repository::SHORT_HASH_LEN;
+use crate::benchmarks::results::RunResult;
+
+/// File in the output directory recording what benchkit is doing, for `benchkit tail`
+pub const STATUS_FILE: &str = "status.json";
+
+/// File in the output directory each finished run's result is appended to as a JSON line, as
+/// results.json is only written once a benchmark completes
+pub const PARTIAL_RESULTS_FILE: &str = "results.partial.jsonl";
+
+/// How often `benchkit tail` checks the output directory for changes
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What benchkit is doing: running a hook, a warmup or a measured run, or nothing anymore
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Activity {
+    Setup,
+    Warmup,
+    Prepare,
+    Run,
+    Conclude,
+    Cleanup,
+    Finished,
+}
+
+impl From<HookStage> for Activity {
+    fn from(stage: HookStage) -> Self {
+        match stage {
+            HookStage::Setup => Activity::Setup,
+            HookStage::Prepare => Activity::Prepare,
+            HookStage::Conclude => Activity::Conclude,
+            HookStage::Cleanup => Activity::Cleanup,
+        }
+    }
+}
+
+impl Activity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Activity::Setup => "setup",
+            Activity::Warmup => "warmup",
+            Activity::Prepare => "prepare",
+            Activity::Run => "run",
+            Activity::Conclude => "conclude",
+            Activity::Cleanup => "cleanup",
+            Activity::Finished => "finished",
+        }
+    }
+
+    /// Whether the activity belongs to a single run rather than a parameter combination
+    fn per_run(&self) -> bool {
+        matches!(
+            self,
+            Activity::Warmup | Activity::Prepare | Activity::Run | Activity::Conclude
+        )
+    }
+}
+
+/// What benchkit is doing in an output directory, replaced as it moves through the matrix
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveStatus {
+    /// Process id of benchkit, to tell a finished run from one that was killed
+    pub pid: u32,
+    pub activity: Activity,
+    pub benchmark: String,
+    pub commit: String,
+    /// Values of the running parameter combination
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+    /// Iteration of the run, for activities which belong to one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iteration: Option<usize>,
+    /// debug.log of the benchmarked node while it runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub debug_log: Option<PathBuf>,
+    /// Unix time in seconds the activity started
+    pub started: u64,
+}
+
+impl LiveStatus {
+    pub fn new(activity: Activity, args: &HookArgs) -> Self {
+        let per_run = activity.per_run();
+        Self {
+            pid: std::process::id(),
+            activity,
+            benchmark: args.benchmark.clone(),
+            commit: args.commit.clone(),
+            params: args.params.clone(),
+            iteration: per_run.then_some(args.iteration),
+            debug_log: per_run.then(|| args.network_datadir().join("debug.log")),
+            started: unix_time(SystemTime::now()),
+        }
+    }
+
+    /// Record the activity in the args' output directory
+    ///
+    /// The status only serves `benchkit tail`, so failing to write it doesn't stop the run.
+    pub fn record(activity: Activity, args: &HookArgs) {
+        if let Err(e) = Self::new(activity, args).write(&args.out_dir) {
+            debug!("Failed to record the status: {e:#}");
+        }
+    }
+
+    /// Record that benchkit is done with an output directory
+    pub fn finish(out_dir: &Path) {
+        let finished = Self::load(out_dir).and_then(|status| match status {
+            Some(status) => Self {
+                activity: Activity::Finished,
+                iteration: None,
+                debug_log: None,
+                started: unix_time(SystemTime::now()),
+                ..status
+            }
+            .write(out_dir),
+            None => Ok(()),
+        });
+        if let Err(e) = finished {
+            debug!("Failed to record the status: {e:#}");
+        }
+    }
+
+    /// Load the status of an output directory, if benchkit started in it
+    pub fn load(out_dir: &Path) -> Result<Option<Self>> {
+        let path = out_dir.join(STATUS_FILE);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let status = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(status))
+    }
+
+    /// Replace the status atomically, so `benchkit tail` never reads it half written
+    pub fn write(&self, out_dir: &Path) -> Result<()> {
+        let path = out_dir.join(STATUS_FILE);
+        let partial = path.with_extension("json.partial");
+        std::fs::write(&partial, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The status as `ibd 0123456789ab dbcache=450 run 2: prepare`
+    pub fn describe(&self) -> String {
+        if self.activity == Activity::Finished {
+            return format!("{}: finished", self.benchmark);
+        }
+        let mut cell = describe_cell(&self.benchmark, &self.commit, &self.params);
+        if let Some(iteration) = self.iteration {
+            cell.push_str(&format!(" run {iteration}"));
+        }
+        format!("{cell}: {}", self.activity.as_str())
+    }
+
+    /// Whether the benchkit which wrote the status is still running
+    fn writer_alive(&self) -> bool {
+        process_alive(self.pid)
+    }
+}
+
+/// A finished run, as appended to `PARTIAL_RESULTS_FILE`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialResult {
+    pub benchmark: String,
+    pub parameters: HashMap<String, String>,
+    pub run: RunResult,
+}
+
+impl PartialResult {
+    /// Append a finished run to the partial results of an output directory
+    pub fn append(out_dir: &Path, args: &HookArgs, run: &RunResult) -> Result<()> {
+        let path = out_dir.join(PARTIAL_RESULTS_FILE);
+        let line = serde_json::to_string(&PartialResult {
+            benchmark: args.benchmark.clone(),
+            parameters: args.params.clone(),
+            run: run.clone(),
+        })?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        writeln!(file, "{line}").with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The result as `ibd 0123456789ab dbcache=450 run 2: 1234.5 ms`
+    pub fn describe(&self) -> String {
+        let commit = self
+            .parameters
+            .get("commit")
+            .map(String::as_str)
+            .unwrap_or_default();
+        let cell = describe_cell(&self.benchmark, commit, &self.parameters);
+        let outcome = match (&self.run.crash, self.run.exit_code) {
+            (Some(crash), _) => format!("crashed with {}", crash.signal_name),
+            (None, 0) => format!("{:.1} ms", self.run.duration_ms.0),
+            (None, code) => format!("exited with code {code}"),
+        };
+        format!("{cell} run {}: {outcome}", self.run.iteration)
+    }
+}
+
+/// A parameter combination as `ibd 0123456789ab dbcache=450`, with its parameters sorted by name
+fn describe_cell(benchmark: &str, commit: &str, params: &HashMap<String, String>) -> String {
+    let mut params: Vec<String> = params
+        .iter()
+        .filter(|(name, _)| *name != "commit")
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    params.sort();
+    let commit: String = commit.chars().take(SHORT_HASH_LEN).collect();
+    [benchmark.to_string(), commit]
+        .into_iter()
+        .chain(params)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// Follows an output directory: the status, the partial results and the running node's
+/// debug.log, as lines prefixed with where they came from
+pub struct LiveTail {
+    out_dir: PathBuf,
+    status: Option<LiveStatus>,
+    results: LogTail,
+    debug_log: Option<LogTail>,
+}
+
+impl LiveTail {
+    pub fn new(out_dir: &Path) -> Self {
+        Self {
+            out_dir: out_dir.to_path_buf(),
+            status: None,
+            results: LogTail::new(&out_dir.join(PARTIAL_RESULTS_FILE)),
+            debug_log: None,
+        }
+    }
+
+    /// The last status read
+    pub fn status(&self) -> Option<&LiveStatus> {
+        self.status.as_ref()
+    }
+
+    /// Lines describing what happened since the last poll
+    ///
+    /// The debug.log followed is drained before switching to the next run's, which is read from
+    /// its start. When attaching to a running node only the lines it logs from then on are shown.
+    pub fn poll(&mut self) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        if let Some(debug_log) = &mut self.debug_log {
+            lines.extend(
+                debug_log
+                    .read_lines()
+                    .into_iter()
+                    .map(|line| format!("[debug.log] {line}")),
+            );
+        }
+        for line in self.results.read_lines() {
+            match serde_json::from_str::<PartialResult>(&line) {
+                Ok(result) => lines.push(format!("[result] {}", result.describe())),
+                Err(e) => debug!("Skipping unreadable partial result: {e}"),
+            }
+        }
+
+        let Some(status) = LiveStatus::load(&self.out_dir)? else {
+            return Ok(lines);
+        };
+        if self.status.as_ref() != Some(&status) {
+            lines.push(format!("[status] {}", status.describe()));
+            let previous = self.status.as_ref().and_then(|s| s.debug_log.as_ref());
+            if status.debug_log.as_ref() != previous {
+                self.debug_log = status.debug_log.as_deref().map(|path| {
+                    match self.status.is_none() && status.activity == Activity::Run {
+                        true => LogTail::from_end(path),
+                        false => LogTail::new(path),
+                    }
+                });
+            }
+            self.status = Some(status);
+        }
+        Ok(lines)
+    }
+}
+
+/// Print what benchkit is doing in an output directory until it finishes
+pub fn tail(out_dir: &Path) -> Result<()> {
+    if !out_dir.is_dir() {
+        anyhow::bail!("{} is not a directory", out_dir.display());
+    }
+    let mut live = LiveTail::new(out_dir);
+    let mut waiting = false;
+    loop {
+        for line in live.poll()? {
+            println!("{line}");
+        }
+        match live.status() {
+            Some(status) if status.activity == Activity::Finished => return Ok(()),
+            Some(status) if !status.writer_alive() => {
+                anyhow::bail!(
+                    "benchkit (pid {}) exited while at {}",
+                    status.pid,
+                    status.describe()
+                );
+            }
+            Some(_) => {}
+            None if !waiting => {
+                println!("Waiting for benchkit to start in {}", out_dir.display());
+                waiting = true;
+            }
+            None => {}
+        }
+        std::thread::sleep(TAIL_POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::hook_runner::NodePorts;
+    use crate::types::{DurationMs, Network};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_live_tail() {
+        let temp_dir = tempdir().unwrap();
+        let out_dir = temp_dir.path();
+        let args = HookArgs {
+            benchmark: "ibd".to_string(),
+            options: Default::default(),
+            binary: "bitcoind".to_string(),
+            connect_address: String::new(),
+            network: Network::Regtest,
+            ports: NodePorts::from_command("bitcoind", Network::Regtest),
+            env: Default::default(),
+            out_dir: out_dir.to_path_buf(),
+            run_out_dir: out_dir.join("abc/dbcache-450/1"),
+            snapshot_path: PathBuf::new(),
+            tmp_data_dir: out_dir.join("data"),
+            datadir: out_dir.join("data/abc/dbcache-450/1"),
+            iteration: 1,
+            commit: "0123456789abcdef".to_string(),
+            params_dir: "dbcache-450".to_string(),
+            loadblock: Vec::new(),
+            header_cache: None,
+            params: HashMap::from([
+                ("commit".to_string(), "0123456789abcdef".to_string()),
+                ("dbcache".to_string(), "450".to_string()),
+            ]),
+        };
+        let mut live = LiveTail::new(out_dir);
+        assert!(live.poll().unwrap().is_empty());
+
+        LiveStatus::record(Activity::Setup, &args);
+        assert_eq!(
+            live.poll().unwrap(),
+            ["[status] ibd 0123456789ab dbcache=450: setup"]
+        );
+        // Nothing changed
+        assert!(live.poll().unwrap().is_empty());
+
+        // The debug.log of the run is followed from its start
+        LiveStatus::record(Activity::Prepare, &args);
+        std::fs::create_dir_all(args.network_datadir()).unwrap();
+        std::fs::write(
+            args.network_datadir().join("debug.log"),
+            "Bitcoin Core starting\n",
+        )
+        .unwrap();
+        assert_eq!(
+            live.poll().unwrap(),
+            ["[status] ibd 0123456789ab dbcache=450 run 1: prepare"]
+        );
+        let mut log = std::fs::OpenOptions::new()
+            .append(true)
+            .open(args.network_datadir().join("debug.log"))
+            .unwrap();
+        write!(log, "UpdateTip: height=1\nUpdateTip: hei").unwrap();
+        assert_eq!(
+            live.poll().unwrap(),
+            [
+                "[debug.log] Bitcoin Core starting",
+                "[debug.log] UpdateTip: height=1"
+            ]
+        );
+
+        // Lines logged before the run ended are shown before its result
+        writeln!(log, "ght=2").unwrap();
+        let run = RunResult {
+            iteration: 1,
+            duration_ms: DurationMs(1234.5),
+            exit_code: 0,
+            ..Default::default()
+        };
+        PartialResult::append(out_dir, &args, &run).unwrap();
+        LiveStatus::record(Activity::Conclude, &args);
+        LiveStatus::finish(out_dir);
+        assert_eq!(
+            live.poll().unwrap(),
+            [
+                "[debug.log] UpdateTip: height=2",
+                "[result] ibd 0123456789ab dbcache=450 run 1: 1234.5 ms",
+                "[status] ibd: finished",
+            ]
+        );
+        assert_eq!(live.status().unwrap().activity, Activity::Finished);
+        assert!(live.status().unwrap().writer_alive());
+    }
+}
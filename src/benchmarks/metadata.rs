@@ -0,0 +1,441 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::benchmarks::build::BuildSystem;
+use crate::config::{BenchmarkGlobalConfig, DependsConfig};
+
+/// Build settings requested by the configuration, used to detect stale binaries
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuildSettings {
+    /// cmake arguments from cmake_build_args and the build variant
+    pub cmake_args: Vec<String>,
+    /// ./configure arguments for autotools builds
+    pub configure_args: Vec<String>,
+    /// depends toolchain configuration, if used
+    pub depends: Option<DependsConfig>,
+    /// Names of the benchkit patches applied before building
+    pub patches: Vec<String>,
+    /// SHA256 of the patches' contents, so editing a patch makes the binary stale
+    #[serde(default)]
+    pub patch_hash: Option<String>,
+    /// pre_build hook command
+    pub pre_build: Option<String>,
+    /// post_build hook command
+    pub post_build: Option<String>,
+    /// Command the build ran under
+    #[serde(default)]
+    pub wrapper: Vec<String>,
+}
+
+impl BuildSettings {
+    /// Settings the configuration requests for a build variant
+    pub fn requested(
+        global: &BenchmarkGlobalConfig,
+        variant: Option<&str>,
+        patches: &[String],
+    ) -> Self {
+        let variant =
+            variant.and_then(|name| global.build.variants.iter().find(|v| v.name == name));
+
+        let mut cmake_args = global.cmake_build_args.clone().unwrap_or_default();
+        let mut configure_args = global.build.configure_args.clone();
+        if let Some(variant) = variant {
+            cmake_args.extend(variant.cmake_args());
+            configure_args.extend(variant.configure_args.iter().cloned());
+        }
+
+        Self {
+            cmake_args,
+            configure_args,
+            depends: global.build.depends.clone(),
+            patches: patches.to_vec(),
+            patch_hash: None,
+            pre_build: global.build.pre_build.clone(),
+            post_build: global.build.post_build.clone(),
+            wrapper: global.build.wrapper_for(variant).to_vec(),
+        }
+    }
+
+    /// Record the hash of the patches as found in `patch_dir`, unless some are missing
+    pub fn with_patch_hash(mut self, patch_dir: &Path) -> Self {
+        self.patch_hash = patch_set_hash(patch_dir, &self.patches);
+        self
+    }
+
+    /// Whether the same patches have different contents, when both hashes are known
+    pub fn patches_changed(&self, requested: &BuildSettings) -> bool {
+        match (&self.patch_hash, &requested.patch_hash) {
+            (Some(recorded), Some(requested_hash)) => {
+                self.patches == requested.patches && recorded != requested_hash
+            }
+            _ => false,
+        }
+    }
+
+    /// Describe each setting which differs from `requested`
+    pub fn differences(&self, requested: &BuildSettings) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.cmake_args != requested.cmake_args {
+            differences.push(format!(
+                "cmake args {:?} -> {:?}",
+                self.cmake_args, requested.cmake_args
+            ));
+        }
+        if self.configure_args != requested.configure_args {
+            differences.push(format!(
+                "configure args {:?} -> {:?}",
+                self.configure_args, requested.configure_args
+            ));
+        }
+        if self.depends != requested.depends {
+            differences.push(format!(
+                "depends {:?} -> {:?}",
+                self.depends, requested.depends
+            ));
+        }
+        if self.patches != requested.patches {
+            differences.push(format!(
+                "patches {:?} -> {:?}",
+                self.patches, requested.patches
+            ));
+        }
+        if self.patches_changed(requested) {
+            differences.push(format!(
+                "patch contents (sha256 {:.12} -> {:.12})",
+                self.patch_hash.as_deref().unwrap_or_default(),
+                requested.patch_hash.as_deref().unwrap_or_default()
+            ));
+        }
+        if self.pre_build != requested.pre_build {
+            differences.push(format!(
+                "pre_build {:?} -> {:?}",
+                self.pre_build, requested.pre_build
+            ));
+        }
+        if self.post_build != requested.post_build {
+            differences.push(format!(
+                "post_build {:?} -> {:?}",
+                self.post_build, requested.post_build
+            ));
+        }
+        if self.wrapper != requested.wrapper {
+            differences.push(format!(
+                "wrapper {:?} -> {:?}",
+                self.wrapper, requested.wrapper
+            ));
+        }
+        differences
+    }
+}
+
+/// Provenance of a built binary, stored in a sidecar file next to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BinaryMetadata {
+    /// Commit the binary was built from
+    pub commit: String,
+    /// Build variant name, if any
+    pub variant: Option<String>,
+    /// Build system used for the commit (cmake or autotools)
+    pub build_system: String,
+    /// First line of the compiler's --version output
+    pub compiler: Option<String>,
+    /// Compiler flags recorded by the build system
+    pub compiler_flags: Option<String>,
+    /// Arguments passed to cmake (or ./configure)
+    pub configure_args: Vec<String>,
+    /// Hostname of the machine which built the binary
+    pub build_host: String,
+    /// Wall-clock duration of the configure and build steps in seconds
+    pub build_duration_secs: f64,
+    /// Unix timestamp of when the build finished
+    pub built_at: u64,
+    /// SHA256 of the binary
+    pub sha256: String,
+    /// Whether benchkit patches were applied before building
+    pub patched: bool,
+    /// Names of the applied patches
+    pub patches: Vec<String>,
+    /// Settings requested by the configuration at build time
+    #[serde(default)]
+    pub settings: Option<BuildSettings>,
+}
+
+impl BinaryMetadata {
+    /// Path of the sidecar metadata file for a binary
+    pub fn path_for(binary_path: &Path) -> PathBuf {
+        let mut name = binary_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".json");
+        binary_path.with_file_name(name)
+    }
+
+    /// Load the sidecar metadata for a binary, if present
+    pub fn load(binary_path: &Path) -> Result<Option<Self>> {
+        let path = Self::path_for(binary_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read binary metadata: {}", path.display()))?;
+        let metadata = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse binary metadata: {}", path.display()))?;
+        Ok(Some(metadata))
+    }
+
+    /// Write the sidecar metadata for a binary
+    pub fn save(&self, binary_path: &Path) -> Result<()> {
+        let path = Self::path_for(binary_path);
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, json)
+            .with_context(|| format!("Failed to write binary metadata: {}", path.display()))?;
+        debug!("Wrote binary metadata to {}", path.display());
+        Ok(())
+    }
+
+    /// Check the binary still matches the hash recorded at build time
+    ///
+    /// Binaries without metadata are assumed valid, as they predate metadata recording.
+    pub fn verify_binary(binary_path: &Path) -> Result<bool> {
+        let Some(metadata) = Self::load(binary_path)? else {
+            return Ok(true);
+        };
+        let actual = sha256_file(binary_path)?;
+        if actual != metadata.sha256 {
+            warn!(
+                "Binary {} does not match its recorded sha256 (expected {}, found {})",
+                binary_path.display(),
+                metadata.sha256,
+                actual
+            );
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Describe how a binary's recorded build settings differ from the requested ones
+    ///
+    /// Returns an empty list when the binary is up to date, or when no settings were recorded.
+    pub fn stale_reasons(binary_path: &Path, requested: &BuildSettings) -> Result<Vec<String>> {
+        let recorded = Self::load(binary_path)?.and_then(|metadata| metadata.settings);
+        Ok(recorded
+            .map(|settings| settings.differences(requested))
+            .unwrap_or_default())
+    }
+}
+
+/// Hex-encoded SHA256 over the names and contents of the patches in `patch_dir`, in order
+///
+/// None without patches, or when one of them isn't there to hash (e.g. not downloaded yet).
+pub fn patch_set_hash(patch_dir: &Path, patches: &[String]) -> Option<String> {
+    if patches.is_empty() {
+        return None;
+    }
+    let mut hasher = Sha256::new();
+    for patch in patches {
+        let content = std::fs::read(patch_dir.join(patch)).ok()?;
+        hasher.update(patch.as_bytes());
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+/// Compute the hex-encoded SHA256 of a file
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)
+        .with_context(|| format!("Failed to open {} for hashing", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Determine the compiler version and flags used in a configured build directory
+pub fn detect_compiler(
+    build_dir: &Path,
+    build_system: BuildSystem,
+) -> (Option<String>, Option<String>) {
+    let (compiler, flags) = match build_system {
+        BuildSystem::CMake => (
+            read_build_variable(&build_dir.join("CMakeCache.txt"), "CMAKE_CXX_COMPILER:"),
+            read_build_variable(&build_dir.join("CMakeCache.txt"), "CMAKE_CXX_FLAGS:"),
+        ),
+        BuildSystem::Autotools => (
+            read_build_variable(&build_dir.join("Makefile"), "CXX "),
+            read_build_variable(&build_dir.join("Makefile"), "CXXFLAGS "),
+        ),
+    };
+
+    let version = compiler.and_then(|compiler| {
+        // Autotools records the full compiler command line, e.g. "g++ -std=c++20"
+        let program = compiler.split_whitespace().next()?.to_string();
+        let output = Command::new(program).arg("--version").output().ok()?;
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .map(|line| line.trim().to_string())
+    });
+
+    (version, flags)
+}
+
+/// Read a `NAME<sep>=value` style variable from a CMakeCache.txt or Makefile
+fn read_build_variable(file: &Path, prefix: &str) -> Option<String> {
+    let contents = std::fs::read_to_string(file).ok()?;
+    contents
+        .lines()
+        .find(|line| line.starts_with(prefix))
+        .and_then(|line| line.split_once('='))
+        .map(|(_, value)| value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sha256_file() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("file");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_verify_binary() {
+        let temp_dir = tempdir().unwrap();
+        let binary = temp_dir.path().join("bitcoind-abc123");
+        std::fs::write(&binary, "binary contents").unwrap();
+
+        // No metadata: assumed valid
+        assert!(BinaryMetadata::verify_binary(&binary).unwrap());
+
+        let metadata = BinaryMetadata {
+            commit: "abc123".to_string(),
+            variant: None,
+            build_system: "CMake".to_string(),
+            compiler: None,
+            compiler_flags: None,
+            configure_args: Vec::new(),
+            build_host: "host".to_string(),
+            build_duration_secs: 1.0,
+            built_at: 0,
+            sha256: sha256_file(&binary).unwrap(),
+            patched: true,
+            patches: Vec::new(),
+            settings: None,
+        };
+        metadata.save(&binary).unwrap();
+        assert!(temp_dir.path().join("bitcoind-abc123.json").exists());
+        assert!(BinaryMetadata::verify_binary(&binary).unwrap());
+
+        std::fs::write(&binary, "modified contents").unwrap();
+        assert!(!BinaryMetadata::verify_binary(&binary).unwrap());
+    }
+
+    #[test]
+    fn test_stale_reasons() {
+        let temp_dir = tempdir().unwrap();
+        let binary = temp_dir.path().join("bitcoind-abc123");
+        std::fs::write(&binary, "binary contents").unwrap();
+
+        let recorded = BuildSettings {
+            cmake_args: vec!["-DCMAKE_BUILD_TYPE=Release".to_string()],
+            patches: vec!["0001.patch".to_string()],
+            ..BuildSettings::default()
+        };
+        let metadata = BinaryMetadata {
+            commit: "abc123".to_string(),
+            variant: None,
+            build_system: "CMake".to_string(),
+            compiler: None,
+            compiler_flags: None,
+            configure_args: Vec::new(),
+            build_host: "host".to_string(),
+            build_duration_secs: 1.0,
+            built_at: 0,
+            sha256: sha256_file(&binary).unwrap(),
+            patched: true,
+            patches: Vec::new(),
+            settings: Some(recorded.clone()),
+        };
+        metadata.save(&binary).unwrap();
+
+        assert!(BinaryMetadata::stale_reasons(&binary, &recorded)
+            .unwrap()
+            .is_empty());
+
+        let requested = BuildSettings {
+            cmake_args: vec!["-DCMAKE_BUILD_TYPE=Debug".to_string()],
+            ..recorded.clone()
+        };
+        let reasons = BinaryMetadata::stale_reasons(&binary, &requested).unwrap();
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].starts_with("cmake args"));
+
+        let requested = BuildSettings {
+            post_build: Some("strip \"$BENCHKIT_BINARY\"".to_string()),
+            ..recorded.clone()
+        };
+        let reasons = BinaryMetadata::stale_reasons(&binary, &requested).unwrap();
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].starts_with("post_build"));
+
+        // Editing a patch makes binaries built with it stale, once its contents are known
+        let patch_dir = temp_dir.path().join("patches");
+        let built = recorded.clone().with_patch_hash(&patch_dir);
+        assert_eq!(built.patch_hash, None);
+        std::fs::create_dir_all(&patch_dir).unwrap();
+        std::fs::write(patch_dir.join("0001.patch"), "+ one").unwrap();
+        let built = recorded.with_patch_hash(&patch_dir);
+        assert!(built.patch_hash.is_some());
+        assert!(built.differences(&built.clone()).is_empty());
+        // Binaries built before patch hashes were recorded aren't stale
+        let unknown = BuildSettings {
+            patch_hash: None,
+            ..built.clone()
+        };
+        assert!(unknown.differences(&built).is_empty());
+        std::fs::write(patch_dir.join("0001.patch"), "+ two").unwrap();
+        let requested = built.clone().with_patch_hash(&patch_dir);
+        assert!(built.patches_changed(&requested));
+        let reasons = built.differences(&requested);
+        assert_eq!(reasons.len(), 1);
+        assert!(reasons[0].starts_with("patch contents"), "{reasons:?}");
+    }
+
+    #[test]
+    fn test_read_build_variable() {
+        let temp_dir = tempdir().unwrap();
+        let cache = temp_dir.path().join("CMakeCache.txt");
+        std::fs::write(
+            &cache,
+            "CMAKE_CXX_COMPILER:FILEPATH=/usr/bin/c++\nCMAKE_CXX_FLAGS:STRING=-O2 -g\n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_build_variable(&cache, "CMAKE_CXX_COMPILER:"),
+            Some("/usr/bin/c++".to_string())
+        );
+        assert_eq!(
+            read_build_variable(&cache, "CMAKE_CXX_FLAGS:"),
+            Some("-O2 -g".to_string())
+        );
+        assert_eq!(read_build_variable(&cache, "MISSING:"), None);
+    }
+}
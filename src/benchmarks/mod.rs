@@ -8,47 +8,138 @@
 //! - `Runner`: Top-level orchestrator that coordinates benchmark execution
 //! - `BenchmarkRunner`: Low-level executor that handles command execution and timing
 //! - `Builder`: Manages building Bitcoin Core from source
+//! - `BinaryMetadata`: Records the provenance of built binaries
 //! - `RepositoryManager`: Handles Git repositories (local and remote)
 //! - `HookRunner`: Executes lifecycle scripts around benchmarks
 //! - `ParameterMatrix`: Manages parameter substitution for commands
-//! - `ResultExporter`: Exports benchmark results to various formats
+//! - `ResultExporter`, `ExportConfig`: Export benchmark results to various formats
+//! - `summary_table`, `results_report`: Render benchmark results for the terminal
+//! - `History`: Records summaries of each run to report changes since the last one
+//! - `ResultSource`: Loads results from output directories to merge them into one report
+//! - `write_charts`: Renders SVG charts comparing the profiles of results
 //! - `Profiler`: Collects performance metrics during benchmark runs
+//! - `UsdtTracer`: Collects bitcoind's USDT tracepoints during benchmark runs
+//! - `OutDirLayout`: Lays out the output directories of a benchmark's runs
+//! - `CheckpointState`: Records the progress of long runs stopped and restarted at checkpoints
+//! - `ZmqProgress`: Follows the node's block progress through its ZMQ notifications
+//! - `verify_commits`: Smoke tests built binaries on regtest
+//! - `LiveTail`: Follows the status, results and node of a run in progress for `benchkit tail`
 
 mod build;
-pub use build::Builder;
+pub use build::{BuildSystem, Builder};
+
+mod remote_build;
+
+mod git;
+
+mod metadata;
+pub use metadata::{sha256_file, BinaryMetadata, BuildSettings};
 
 mod repository;
-pub use repository::{RepoSource, RepositoryManager};
+pub use repository::{
+    cached_repositories, evict_lru, format_size, CachedRepository, RepoSource, RepositoryManager,
+};
 
 mod hook_runner;
 pub use hook_runner::{HookArgs, HookRunner, HookStage};
 
 mod hooks;
-pub use hooks::{AssumeUtxoHookExecutor, FullIbdHookExecutor, HookExecutor, HookMode};
+pub use hooks::{
+    AssumeUtxoHookExecutor, FileSystem, FullIbdHookExecutor, HookExecutor, HookMode,
+    LoadBlockHookExecutor, MockFileSystem, SystemFileSystem,
+};
+
+mod overrides;
+pub use overrides::{CommandOverride, RunOverrides, VersionRange, OVERRIDES_FILE};
+
+mod requirements;
+pub use requirements::{BinaryFeatures, Feature, Requirements};
 
 mod results;
-pub use results::{BenchmarkResult, InstrumentationType, RunResult, RunSummary};
+pub use results::{BenchmarkResult, InstrumentationType, RunFailure, RunResult, RunSummary};
 
 mod parameters;
-pub use parameters::{ParameterList, ParameterMatrix};
+pub use parameters::{
+    template_placeholders, ParameterList, ParameterMatrix, ParameterUtils, ValuesFrom,
+    BUILTIN_PLACEHOLDERS,
+};
 
 mod export;
-pub use export::ResultExporter;
+pub use export::{ExportConfig, ExportFormat, ResultExporter, RESULTS_SCHEMA_VERSION};
+
+mod report;
+pub use report::{
+    failure_summary, merge_results, merged_report, outlier_warnings, parameter_breakdown,
+    resource_usage, results_report, source_breakdown, summary_table, ResultSource,
+};
+
+mod history;
+pub use history::{history_label, Change, History, HistoryEntry, Verdict};
+
+mod charts;
+pub use charts::{height_progress, write_charts, LineChart, Series};
 
 mod profiler;
-pub use profiler::{ProfileSample, Profiler};
+pub use profiler::analysis::{align, resample, ProfileMetrics, ResampledProfile};
+pub use profiler::{ProfileResult, ProfileSample, Profiler};
+
+mod out_layout;
+pub use out_layout::{OutDirLayout, DEFAULT_OUT_DIR_TEMPLATE, OUT_DIR_PLACEHOLDERS};
+
+mod checkpoint;
+pub use checkpoint::{Checkpoint, CheckpointConfig, CheckpointState, CHECKPOINT_FILE};
 
 mod benchmark_runner;
-pub use benchmark_runner::BenchmarkRunner;
+pub use benchmark_runner::{AdaptiveRuns, BenchmarkRunner, FailFast, Warmup};
 
 mod runner;
 pub use runner::Runner;
 
+mod result_cache;
+pub use result_cache::ResultCache;
+
+mod bisect;
+pub use bisect::{parse_threshold, Bisect, BisectReport, BisectStep, BisectVerdict, BISECT_FILE};
+
+mod core_dump;
+pub use core_dump::{crash_signal, CoreCollector, CorePattern, Crash};
+
+mod failure_bundle;
+pub use failure_bundle::FailureBundle;
+
+mod live;
+pub use live::{
+    tail, Activity, LiveStatus, LiveTail, PartialResult, PARTIAL_RESULTS_FILE, STATUS_FILE,
+};
+
+mod feeder;
+pub use feeder::{
+    check_feeder, handshake, parse_peer_connections, FeederIdentity, FeederRpc, PeerConnection,
+    PeerVersion,
+};
+
+mod topology;
+pub use topology::{check_ports, start_order, NodeRole, RunningNodes};
+
+mod node_logging;
+pub use node_logging::NodeLogging;
+
+mod zmq;
+pub use zmq::{ZmqListener, ZmqProgress};
+
 mod log_monitor;
 pub use log_monitor::{LogMonitor, LogMonitorBuilder};
 
 mod perf;
 pub use perf::{PerfInstrumentor, PerfInstrumentorBuilder};
 
+mod usdt;
+pub use usdt::{resolve_tracepoints, Tracepoint, TracepointSummary, UsdtTracer, TRACEPOINTS};
+
+mod verify;
+pub use verify::{smoke_test, verify_commits, SmokeTest};
+
 mod utils;
-pub use utils::{binary_exists, check_binaries_exist, get_binary_path};
+pub use utils::{
+    binary_exists, binary_id, check_binaries_exist, get_binary_path, parse_node_settings,
+};
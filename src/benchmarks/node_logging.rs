@@ -0,0 +1,154 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// bitcoind logging a benchmark relies on, translated into the node's flags so the command
+/// template doesn't have to spell them out
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeLogging {
+    /// Debug categories to log, each enabled with `-debug=<category>`, like "bench" or "coindb"
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Log timestamps with microseconds, with `-logtimemicros`
+    #[serde(default)]
+    pub microseconds: bool,
+}
+
+impl NodeLogging {
+    pub fn validate(&self) -> Result<()> {
+        for category in &self.categories {
+            if category.is_empty()
+                || !category
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit())
+            {
+                anyhow::bail!("Invalid node_logging category '{category}'");
+            }
+        }
+        Ok(())
+    }
+
+    /// Fail if a command template sets flags contradicting the logging, or `-printtoconsole`
+    /// when the log must be printed to the console
+    pub fn check_command(logging: Option<&Self>, console: bool, command: &str) -> Result<()> {
+        let args = node_args(command);
+        let disables = |name: &str| {
+            args.iter()
+                .any(|(arg, value)| arg == name && matches!(value.as_deref(), Some("0")))
+        };
+        if console && disables("printtoconsole") {
+            anyhow::bail!("stop_on_log_pattern needs -printtoconsole, which the command disables");
+        }
+        let Some(logging) = logging else {
+            return Ok(());
+        };
+        if !logging.categories.is_empty()
+            && args.iter().any(|(arg, value)| {
+                arg == "debug" && matches!(value.as_deref(), Some("0" | "none"))
+            })
+        {
+            anyhow::bail!("The command disables the debug categories node_logging enables");
+        }
+        if let Some((_, Some(category))) = args.iter().find(|(arg, value)| {
+            arg == "debugexclude"
+                && value
+                    .as_ref()
+                    .is_some_and(|category| logging.categories.contains(category))
+        }) {
+            anyhow::bail!(
+                "The command excludes the debug category {category} node_logging enables"
+            );
+        }
+        if logging.microseconds && (disables("logtimemicros") || disables("logtimestamps")) {
+            anyhow::bail!("The command disables the microsecond timestamps node_logging enables");
+        }
+        Ok(())
+    }
+
+    /// The command with the flags enabling the logging, skipping those it already has
+    pub fn apply(&self, command: &str) -> String {
+        let args = node_args(command);
+        let has = |name: &str, wanted: &str| {
+            args.iter()
+                .any(|(arg, value)| arg == name && value.as_deref().unwrap_or("1") == wanted)
+        };
+        let mut command = command.to_string();
+        for category in &self.categories {
+            if !has("debug", category) && !has("debug", "1") && !has("debug", "all") {
+                command.push_str(&format!(" -debug={category}"));
+            }
+        }
+        if self.microseconds && !has("logtimemicros", "1") {
+            command.push_str(" -logtimemicros");
+        }
+        command
+    }
+}
+
+/// Whether a command already sets `-printtoconsole`, enabled or not
+pub fn has_console_flag(command: &str) -> bool {
+    node_args(command)
+        .iter()
+        .any(|(arg, _)| arg == "printtoconsole")
+}
+
+/// Names and values of the options in a command, with `-noname` read as `-name=0`
+fn node_args(command: &str) -> Vec<(String, Option<String>)> {
+    command
+        .split_whitespace()
+        .filter_map(|arg| arg.strip_prefix("--").or_else(|| arg.strip_prefix('-')))
+        .map(|arg| {
+            let (name, value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg, None),
+            };
+            match name.strip_prefix("no") {
+                Some(negated) if value.is_none() => (negated.to_string(), Some("0".to_string())),
+                _ => (name.to_string(), value),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_node_logging() {
+        let logging = NodeLogging {
+            categories: vec!["bench".to_string(), "coindb".to_string()],
+            microseconds: true,
+        };
+        assert!(logging.validate().is_ok());
+        assert_eq!(
+            logging.apply("{binary} -debug=coindb -dbcache=450"),
+            "{binary} -debug=coindb -dbcache=450 -debug=bench -logtimemicros"
+        );
+        assert_eq!(
+            logging.apply("bitcoind -debug -logtimemicros=1"),
+            "bitcoind -debug -logtimemicros=1"
+        );
+
+        let check = |console, command| NodeLogging::check_command(Some(&logging), console, command);
+        assert!(check(true, "bitcoind -debug=net -printtoconsole").is_ok());
+        assert!(check(true, "bitcoind -printtoconsole=0").is_err());
+        assert!(check(true, "bitcoind -noprinttoconsole").is_err());
+        assert!(check(false, "bitcoind -printtoconsole=0").is_ok());
+        assert!(check(false, "bitcoind -nodebug").is_err());
+        assert!(check(false, "bitcoind -debug=none").is_err());
+        assert!(check(false, "bitcoind -debugexclude=bench").is_err());
+        assert!(check(false, "bitcoind -debugexclude=net").is_ok());
+        assert!(check(false, "bitcoind -logtimestamps=0").is_err());
+        assert!(NodeLogging::check_command(None, false, "bitcoind -nodebug").is_ok());
+
+        assert!(has_console_flag("bitcoind -printtoconsole=0"));
+        assert!(!has_console_flag("bitcoind -printtoconsolex"));
+
+        let invalid = NodeLogging {
+            categories: vec!["Bench".to_string()],
+            microseconds: false,
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::benchmarks::parameters::{
+    percent_encode, substitute_placeholders, template_placeholders,
+};
+use crate::benchmarks::repository::SHORT_HASH_LEN;
+use crate::benchmarks::ParameterUtils;
+
+/// Layout of the output directory used when a benchmark doesn't set `out_dir_template`
+pub const DEFAULT_OUT_DIR_TEMPLATE: &str = "{commit}/{params}/{iteration}";
+
+/// Placeholders of an `out_dir_template` besides the names of the benchmark's parameters
+///
+/// `{commit_short}` is the commit abbreviated to `SHORT_HASH_LEN` characters, like the commits
+/// benchkit resolves and builds.
+pub const OUT_DIR_PLACEHOLDERS: &[&str] =
+    &["benchmark", "commit", "commit_short", "params", "iteration"];
+
+/// Where the runs of a benchmark write their output, relative to the output directory
+///
+/// The template's last component is always `{iteration}`, so the runs of a parameter combination
+/// share a combination directory, which also holds its warmups and failure bundles.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutDirLayout {
+    /// Template of the combination directory, the template without its `/{iteration}`
+    combination: String,
+}
+
+impl Default for OutDirLayout {
+    fn default() -> Self {
+        Self::new(DEFAULT_OUT_DIR_TEMPLATE).unwrap()
+    }
+}
+
+impl OutDirLayout {
+    pub fn new(template: &str) -> Result<Self> {
+        let Some(combination) = template.strip_suffix("/{iteration}") else {
+            bail!("out_dir_template must end with /{{iteration}}, got {template}");
+        };
+        if template_placeholders(combination).contains("iteration") {
+            bail!("out_dir_template may only use {{iteration}} as its last component");
+        }
+        let path = Path::new(combination);
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            bail!("out_dir_template must be a relative path within the output directory, got {template}");
+        }
+        Ok(Self {
+            combination: combination.to_string(),
+        })
+    }
+
+    /// Output directory of the runs of a parameter combination, relative to the output
+    /// directory
+    pub fn combination_dir(
+        &self,
+        benchmark: &str,
+        commit: &str,
+        params: &HashMap<String, String>,
+    ) -> PathBuf {
+        let dir = substitute_placeholders(&self.combination, |name| {
+            let value = match name {
+                "benchmark" => benchmark.to_string(),
+                "commit" => commit.to_string(),
+                "commit_short" => commit.chars().take(SHORT_HASH_LEN).collect(),
+                // Escaped already
+                "params" => return Some(ParameterUtils::params_to_dirname(params)),
+                _ => params.get(name)?.clone(),
+            };
+            // Parameter values are paths at times, which mustn't add directories
+            Some(percent_encode(&value, |c| {
+                !matches!(c, '/' | '\\' | '%') && !c.is_control()
+            }))
+        });
+        // A value making up a whole component, like `..`, mustn't lead out of the output
+        // directory either
+        dir.split('/')
+            .map(|component| match component {
+                "." => "%2E",
+                ".." => "%2E%2E",
+                component => component,
+            })
+            .collect()
+    }
+
+    /// Output directory of a run, relative to the output directory
+    pub fn run_dir(
+        &self,
+        benchmark: &str,
+        commit: &str,
+        params: &HashMap<String, String>,
+        iteration: usize,
+    ) -> PathBuf {
+        self.combination_dir(benchmark, commit, params)
+            .join(iteration.to_string())
+    }
+
+    /// Check the template only uses known placeholders and gives every parameter combination
+    /// a directory of its own
+    ///
+    /// Runs of combinations sharing a directory would overwrite each other's debug.logs and
+    /// profiles, so this is checked before anything runs.
+    pub fn check(&self, benchmark: &str, combinations: &[HashMap<String, String>]) -> Result<()> {
+        for combination in combinations {
+            let unknown: Vec<String> = template_placeholders(&self.combination)
+                .into_iter()
+                .filter(|name| {
+                    !OUT_DIR_PLACEHOLDERS.contains(&name.as_str())
+                        && !combination.contains_key(name)
+                })
+                .collect();
+            if !unknown.is_empty() {
+                bail!(
+                    "out_dir_template of {benchmark} uses {{{}}}, which is neither a parameter \
+                     nor one of {{{}}}",
+                    unknown.join("}, {"),
+                    OUT_DIR_PLACEHOLDERS.join("}, {")
+                );
+            }
+        }
+
+        let mut dirs: HashMap<PathBuf, &HashMap<String, String>> = HashMap::new();
+        for combination in combinations {
+            let commit = combination.get("commit").map(String::as_str).unwrap_or("");
+            let dir = self.combination_dir(benchmark, commit, combination);
+            if let Some(other) = dirs.insert(dir.clone(), combination) {
+                bail!(
+                    "out_dir_template of {benchmark} puts the runs of {} and {} in {}, add the \
+                     parameters telling them apart to it",
+                    describe(other),
+                    describe(combination),
+                    dir.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A parameter combination as `commit=abc dbcache=450`, sorted by name
+fn describe(params: &HashMap<String, String>) -> String {
+    let mut params: Vec<String> = params.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    params.sort();
+    match params.is_empty() {
+        true => "the default parameters".to_string(),
+        false => params.join(" "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_out_dir_layout() {
+        let combination = params(&[("commit", "0123456789abcdef"), ("dbcache", "450")]);
+        assert_eq!(
+            OutDirLayout::default().run_dir("ibd", "0123456789abcdef", &combination, 2),
+            PathBuf::from("0123456789abcdef/dbcache-450/2")
+        );
+        let path = params(&[("snapshot", "/a b")]);
+        assert_eq!(
+            OutDirLayout::default().combination_dir("ibd", "abc", &path),
+            PathBuf::from("abc/snapshot-%2Fa%20b")
+        );
+
+        let layout = OutDirLayout::new("{benchmark}/{commit_short}-{dbcache}/{iteration}").unwrap();
+        assert_eq!(
+            layout.run_dir("ibd", "0123456789abcdef", &combination, 2),
+            PathBuf::from("ibd/0123456789ab-450/2")
+        );
+        // Values don't add directories
+        let path = params(&[("commit", "abc"), ("dbcache", "a/b")]);
+        assert_eq!(
+            layout.combination_dir("ibd", "abc", &path),
+            PathBuf::from("ibd/abc-a%2Fb")
+        );
+
+        // Nor leave the output directory
+        let layout = OutDirLayout::new("{commit}/{dbcache}/{prune}/{iteration}").unwrap();
+        let dots = params(&[("dbcache", ".."), ("prune", ".")]);
+        let dir = layout.combination_dir("ibd", "..", &dots);
+        assert_eq!(dir, PathBuf::from("%2E%2E/%2E%2E/%2E"));
+        assert!(dir
+            .components()
+            .all(|component| matches!(component, Component::Normal(_))));
+
+        for template in [
+            "{commit}/{params}",
+            "{iteration}/{commit}/{iteration}",
+            "/tmp/{commit}/{iteration}",
+            "../{commit}/{iteration}",
+        ] {
+            assert!(OutDirLayout::new(template).is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn test_out_dir_layout_check() {
+        let combinations = vec![
+            params(&[("commit", "aaa"), ("dbcache", "450")]),
+            params(&[("commit", "aaa"), ("dbcache", "4500")]),
+            params(&[("commit", "bbb"), ("dbcache", "450")]),
+        ];
+        let layout = OutDirLayout::new("{benchmark}/{commit_short}-{dbcache}/{iteration}").unwrap();
+        layout.check("ibd", &combinations).unwrap();
+
+        let layout = OutDirLayout::new("{commit}/{iteration}").unwrap();
+        let err = layout.check("ibd", &combinations).unwrap_err().to_string();
+        assert!(
+            err.contains("commit=aaa dbcache=450 and commit=aaa dbcache=4500 in aaa"),
+            "{err}"
+        );
+
+        let layout = OutDirLayout::new("{commit}/{prune}/{iteration}").unwrap();
+        let err = layout.check("ibd", &combinations).unwrap_err().to_string();
+        assert!(err.contains("uses {prune}"), "{err}");
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::benchmarks::parameters::ParameterList;
+use crate::benchmarks::requirements::parse_version;
+
+/// File in the output directory recording the overrides given on the command line
+pub const OVERRIDES_FILE: &str = "overrides.json";
+
+/// Changes to the arguments of a benchmark command for some commits or versions, so that one
+/// benchmark can span versions which renamed or dropped flags
+///
+/// An override applies to a parameter combination when its commit is one of `commits`, or its
+/// binary's `-version` is within `versions`. Arguments are matched by name, so `-par` matches
+/// both `-par` and `-par=4`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CommandOverride {
+    /// Commits the override applies to, as configured (e.g. a tag) or as a hash
+    #[serde(default)]
+    pub commits: Vec<String>,
+    /// Versions the override applies to, as comma separated comparisons like ">=0.21, <26.0"
+    pub versions: Option<String>,
+    /// New names of arguments, keeping their values
+    #[serde(default)]
+    pub rename: BTreeMap<String, String>,
+    /// Arguments to remove
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Arguments to add at the end of the command
+    #[serde(default)]
+    pub append: Vec<String>,
+}
+
+impl CommandOverride {
+    pub fn validate(&self) -> Result<()> {
+        if self.commits.is_empty() && self.versions.is_none() {
+            anyhow::bail!("command_overrides entries need commits or versions to apply to");
+        }
+        if self.rename.is_empty() && self.remove.is_empty() && self.append.is_empty() {
+            anyhow::bail!("command_overrides entries need rename, remove or append");
+        }
+        if let Some(versions) = &self.versions {
+            VersionRange::parse(versions)?;
+        }
+        for name in self
+            .rename
+            .keys()
+            .chain(self.rename.values())
+            .chain(&self.remove)
+        {
+            if !name.starts_with('-') || name.contains(['=', ' ']) {
+                anyhow::bail!("Invalid argument name in command_overrides: '{name}'");
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the override applies to a commit, known by its hash and configured label, and
+    /// the version of its binary
+    pub fn applies_to(
+        &self,
+        commit: &str,
+        label: Option<&str>,
+        version: Option<(u32, u32, u32)>,
+    ) -> bool {
+        let commit_matches = self.commits.iter().any(|wanted| {
+            Some(wanted.as_str()) == label
+                || (!wanted.is_empty()
+                    && (commit.starts_with(wanted) || wanted.starts_with(commit)))
+        });
+        let version_matches = match (&self.versions, version) {
+            (Some(versions), Some(version)) => {
+                VersionRange::parse(versions).is_ok_and(|range| range.contains(version))
+            }
+            _ => false,
+        };
+        commit_matches || version_matches
+    }
+
+    /// The command with the override's changes made to its arguments
+    pub fn apply(&self, command: &str) -> String {
+        let mut args: Vec<String> = command
+            .split_whitespace()
+            .filter(|arg| !self.remove.iter().any(|name| arg_name(arg) == name))
+            .map(|arg| match self.rename.get(arg_name(arg)) {
+                Some(renamed) => format!("{renamed}{}", &arg[arg_name(arg).len()..]),
+                None => arg.to_string(),
+            })
+            .collect();
+        args.extend(self.append.iter().cloned());
+        args.join(" ")
+    }
+}
+
+/// Name of an argument like `-dbcache=450`, i.e. `-dbcache`
+fn arg_name(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(name, _)| name)
+}
+
+/// Versions satisfying every one of a list of comparisons
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionRange {
+    comparisons: Vec<(String, (u32, u32, u32))>,
+}
+
+impl VersionRange {
+    /// Parse comma separated comparisons like ">=0.21, <26.0", where a bare version is exact
+    pub fn parse(range: &str) -> Result<Self> {
+        let comparisons = range
+            .split(',')
+            .map(|comparison| {
+                let comparison = comparison.trim();
+                let operator_len = comparison
+                    .find(|c: char| !matches!(c, '<' | '>' | '='))
+                    .unwrap_or(comparison.len());
+                let (operator, version) = comparison.split_at(operator_len);
+                if !matches!(operator, "" | "=" | "<" | "<=" | ">" | ">=") {
+                    anyhow::bail!("Invalid comparison '{operator}' in version range {range}");
+                }
+                let version = parse_version(version)
+                    .with_context(|| format!("Invalid version range: {range}"))?;
+                Ok((operator.to_string(), version))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { comparisons })
+    }
+
+    pub fn contains(&self, version: (u32, u32, u32)) -> bool {
+        self.comparisons
+            .iter()
+            .all(|(operator, bound)| match operator.as_str() {
+                "<" => version < *bound,
+                "<=" => version <= *bound,
+                ">" => version > *bound,
+                ">=" => version >= *bound,
+                _ => version == *bound,
+            })
+    }
+}
+
+/// Configuration overridden on the command line for one invocation of `benchkit run`, for quick
+/// experiments without editing benchmark.yml
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct RunOverrides {
+    /// Runs of each parameter combination, instead of the configured `runs`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runs: Option<usize>,
+    /// Warmup runs, instead of the configured `warmup`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warmup: Option<usize>,
+    /// Values of parameters, replacing the configured values or adding the parameter
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, Vec<String>>,
+}
+
+impl RunOverrides {
+    /// Parse `--param` arguments like `dbcache=1000` or `dbcache=450,1000`
+    pub fn parse_params(params: &[String]) -> Result<BTreeMap<String, Vec<String>>> {
+        let mut parsed = BTreeMap::new();
+        for param in params {
+            let Some((var, values)) = param.split_once('=') else {
+                anyhow::bail!("Invalid --param '{param}', expected name=value[,value...]");
+            };
+            let var = var.trim();
+            if var.is_empty()
+                || !var
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            {
+                anyhow::bail!("Invalid parameter name in --param '{param}'");
+            }
+            let values: Vec<String> = values.split(',').map(|v| v.trim().to_string()).collect();
+            if values.iter().any(String::is_empty) {
+                anyhow::bail!("Empty value in --param '{param}'");
+            }
+            if parsed.insert(var.to_string(), values).is_some() {
+                anyhow::bail!("Parameter {var} is given more than once");
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.runs == Some(0) {
+            anyhow::bail!("--runs cannot be zero");
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Replace the values of overridden parameters, adding those the configuration lacks
+    pub fn apply_params(&self, parameter_lists: &mut Vec<ParameterList>) {
+        for (var, values) in &self.params {
+            match parameter_lists.iter_mut().find(|list| list.var == *var) {
+                Some(list) => list.values = values.clone(),
+                None => parameter_lists.push(ParameterList {
+                    var: var.clone(),
+                    values: values.clone(),
+                }),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_override() {
+        let range = VersionRange::parse(">=0.21, <26.0").unwrap();
+        assert!(range.contains((0, 21, 0)));
+        assert!(range.contains((25, 2, 0)));
+        assert!(!range.contains((26, 0, 0)));
+        assert!(!range.contains((0, 20, 1)));
+        assert!(VersionRange::parse("25.1").unwrap().contains((25, 1, 0)));
+        assert!(VersionRange::parse("~25").is_err());
+        assert!(VersionRange::parse("<latest").is_err());
+
+        let old = CommandOverride {
+            versions: Some("<26".to_string()),
+            rename: BTreeMap::from([("-dbbatchsize".to_string(), "-dbbatch".to_string())]),
+            remove: vec!["-blocksxor".to_string()],
+            append: vec!["-par=2".to_string()],
+            ..Default::default()
+        };
+        assert!(old.validate().is_ok());
+        assert_eq!(
+            old.apply("{binary} -datadir={datadir} -dbbatchsize=16777216 -blocksxor=0"),
+            "{binary} -datadir={datadir} -dbbatch=16777216 -par=2"
+        );
+        assert!(old.applies_to("abc1234", None, Some((25, 0, 0))));
+        assert!(!old.applies_to("abc1234", None, Some((26, 0, 0))));
+        assert!(!old.applies_to("abc1234", None, None));
+
+        let tagged = CommandOverride {
+            commits: vec!["v25.0".to_string(), "def5678".to_string()],
+            remove: vec!["-blocksxor".to_string()],
+            ..Default::default()
+        };
+        assert!(tagged.applies_to("abc1234", Some("v25.0"), None));
+        assert!(tagged.applies_to("def5678", None, None));
+        assert!(tagged.applies_to("def56789abc", None, None));
+        assert!(!tagged.applies_to("abc1234", Some("master"), Some((25, 0, 0))));
+
+        let no_selector = CommandOverride {
+            append: vec!["-par=2".to_string()],
+            ..Default::default()
+        };
+        assert!(no_selector.validate().is_err());
+        let no_change = CommandOverride {
+            commits: vec!["v25.0".to_string()],
+            ..Default::default()
+        };
+        assert!(no_change.validate().is_err());
+        let bad_name = CommandOverride {
+            commits: vec!["v25.0".to_string()],
+            remove: vec!["-par=2".to_string()],
+            ..Default::default()
+        };
+        assert!(bad_name.validate().is_err());
+    }
+
+    #[test]
+    fn test_run_overrides() {
+        let params =
+            RunOverrides::parse_params(&["dbcache=450, 1000".to_string(), "par=4".to_string()])
+                .unwrap();
+        let overrides = RunOverrides {
+            runs: Some(1),
+            warmup: Some(0),
+            params,
+        };
+        assert!(overrides.validate().is_ok());
+        assert!(!overrides.is_empty());
+        assert!(RunOverrides::default().is_empty());
+
+        let mut lists = vec![ParameterList {
+            var: "dbcache".to_string(),
+            values: vec!["4000".to_string()],
+        }];
+        overrides.apply_params(&mut lists);
+        assert_eq!(lists.len(), 2);
+        assert_eq!(lists[0].values, ["450", "1000"]);
+        assert_eq!(
+            (lists[1].var.as_str(), lists[1].values.clone()),
+            ("par", vec!["4".to_string()])
+        );
+
+        assert!(RunOverrides::parse_params(&["dbcache".to_string()]).is_err());
+        assert!(RunOverrides::parse_params(&["=1".to_string()]).is_err());
+        assert!(RunOverrides::parse_params(&["dbcache=1,".to_string()]).is_err());
+        assert!(RunOverrides::parse_params(&["a=1".to_string(), "a=2".to_string()]).is_err());
+        let zero_runs = RunOverrides {
+            runs: Some(0),
+            ..Default::default()
+        };
+        assert!(zero_runs.validate().is_err());
+    }
+}
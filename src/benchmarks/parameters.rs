@@ -1,6 +1,61 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Placeholders substituted in every command template besides the parameters
+pub const BUILTIN_PLACEHOLDERS: &[&str] = &[
+    "commit",
+    "binary",
+    "datadir",
+    "snapshot",
+    "out_dir",
+    "network",
+    "connect",
+    "iteration",
+];
+
+/// Matches `{name}` placeholders, capturing a preceding `$` to tell shell expansions apart
+fn placeholder_regex() -> Regex {
+    Regex::new(r"(\$?)\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap()
+}
+
+/// Replace the placeholders `value` knows in a single pass, so substituted values aren't
+/// expanded again, leaving the others as they are
+pub fn substitute_placeholders(template: &str, value: impl Fn(&str) -> Option<String>) -> String {
+    placeholder_regex()
+        .replace_all(template, |caps: &regex::Captures| {
+            match (caps[1].is_empty(), value(&caps[2])) {
+                (true, Some(value)) => value,
+                _ => caps[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Names as they appear in templates, e.g. `{commit}, {dbcache}`
+fn braced<'a>(names: impl IntoIterator<Item = &'a String>) -> String {
+    names
+        .into_iter()
+        .map(|name| format!("{{{name}}}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Names of the `{name}` placeholders in a command template
+///
+/// Shell expansions like `${HOME}` and `{dataset:<name>}`, which is resolved before the matrix
+/// is, aren't placeholders.
+pub fn template_placeholders(template: &str) -> BTreeSet<String> {
+    placeholder_regex()
+        .captures_iter(template)
+        .filter(|caps| caps[1].is_empty())
+        .map(|caps| caps[2].to_string())
+        .collect()
+}
 
 /// A parameter list from the benchmark config
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -11,6 +66,82 @@ pub struct ParameterList {
     pub values: Vec<String>,
 }
 
+/// Values of a parameter list read from a file rather than listed in the config, for sweeps
+/// generated by scripts
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum ValuesFrom {
+    /// A text file with one value per line, skipping blank lines and `#` comments
+    Lines(PathBuf),
+    /// A column of a CSV file, named in its header row
+    Column { file: PathBuf, column: String },
+}
+
+impl ValuesFrom {
+    /// Read the values, resolving a relative file against `dir`
+    pub fn read(&self, dir: &Path) -> Result<Vec<String>> {
+        let file = match self {
+            Self::Lines(file) | Self::Column { file, .. } => dir.join(file),
+        };
+        let contents = std::fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read values from {}", file.display()))?;
+        let mut lines = contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let values: Vec<String> = match self {
+            Self::Lines(_) => lines.map(str::to_string).collect(),
+            Self::Column { column, .. } => {
+                let header = csv_fields(lines.next().unwrap_or_default());
+                let index = header
+                    .iter()
+                    .position(|name| name == column)
+                    .with_context(|| format!("{} has no column named {column}", file.display()))?;
+                lines
+                    .enumerate()
+                    .map(|(row, line)| {
+                        csv_fields(line).get(index).cloned().with_context(|| {
+                            format!(
+                                "Row {} of {} has no {column} value",
+                                row + 1,
+                                file.display()
+                            )
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .filter(|value| !value.is_empty())
+                    .collect()
+            }
+        };
+        if values.is_empty() {
+            anyhow::bail!("No values in {}", file.display());
+        }
+        Ok(values)
+    }
+}
+
+/// Fields of a CSV line, which may be double quoted to contain commas
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
+
 /// A parameter matrix that contains all combinations of parameters
 #[derive(Debug, Clone)]
 pub struct ParameterMatrix {
@@ -87,14 +218,45 @@ impl ParameterMatrix {
     }
 }
 
+/// Longest directory name `params_to_dirname` returns, well below the 255 bytes most
+/// filesystems allow so the name can still be extended (e.g. with a `.partial` suffix)
+pub const MAX_PARAMS_DIRNAME_LEN: usize = 128;
+
+/// Hex digits of the hash ending a shortened directory name
+const DIRNAME_HASH_LEN: usize = 16;
+
+/// Percent-encode the bytes of `s` which `keep` rejects, as `%XX` with upper-case hex digits
+pub fn percent_encode(s: &str, keep: impl Fn(char) -> bool) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for c in s.chars() {
+        if keep(c) {
+            encoded.push(c);
+        } else {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                write!(encoded, "%{byte:02X}").unwrap();
+            }
+        }
+    }
+    encoded
+}
+
 /// Utilities for working with parameter combinations
 pub struct ParameterUtils;
 
 impl ParameterUtils {
     /// Generate a directory name from a set of parameters
+    ///
+    /// The name is `<name>-<value>` for each parameter but the commit, sorted by name and joined
+    /// by `_`, or `default` without parameters. Names keep only letters, digits and `_`, values
+    /// only letters, digits, `.`, `+` and `-`, all other bytes are percent-encoded, so no two
+    /// combinations share a name. Names longer than `MAX_PARAMS_DIRNAME_LEN` are cut short and
+    /// end with `~` and a hash of the full name.
+    ///
+    /// Output directories are kept long after benchkit wrote them, so this format must not
+    /// change.
     pub fn params_to_dirname(params: &HashMap<String, String>) -> String {
         // Filter out commit parameter as it's already part of the directory structure
-        let filtered_params: Vec<(&String, &String)> =
+        let mut filtered_params: Vec<(&String, &String)> =
             params.iter().filter(|(k, _)| *k != "commit").collect();
 
         if filtered_params.is_empty() {
@@ -102,13 +264,53 @@ impl ParameterUtils {
         }
 
         // Sort params for consistent ordering
-        let mut param_strs: Vec<String> = filtered_params
+        filtered_params.sort();
+        let dirname = filtered_params
             .iter()
-            .map(|(k, v)| format!("{k}-{v}"))
-            .collect();
-        param_strs.sort();
+            .map(|(k, v)| format!("{}-{}", Self::escape_name(k), Self::escape_value(v)))
+            .collect::<Vec<_>>()
+            .join("_");
+        if dirname.len() <= MAX_PARAMS_DIRNAME_LEN {
+            return dirname;
+        }
+
+        let hash = format!("{:x}", Sha256::digest(dirname.as_bytes()));
+        let kept = MAX_PARAMS_DIRNAME_LEN - DIRNAME_HASH_LEN - 1;
+        format!("{}~{}", &dirname[..kept], &hash[..DIRNAME_HASH_LEN])
+    }
 
-        param_strs.join("_")
+    /// A parameter name as it appears in directory names
+    fn escape_name(name: &str) -> String {
+        percent_encode(name, |c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// A parameter value as it appears in directory names, with no `/`, `_` or `%`
+    fn escape_value(value: &str) -> String {
+        percent_encode(value, |c| {
+            c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-')
+        })
+    }
+
+    /// Environment variables exporting each parameter as `BENCHKIT_PARAM_<NAME>`
+    ///
+    /// Names are upper-cased, with characters other than letters, digits and `_` replaced by `_`.
+    pub fn params_to_env(params: &HashMap<String, String>) -> HashMap<String, String> {
+        params
+            .iter()
+            .map(|(name, value)| {
+                let name: String = name
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() {
+                            c.to_ascii_uppercase()
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect();
+                (format!("BENCHKIT_PARAM_{name}"), value.clone())
+            })
+            .collect()
     }
 
     /// Create parameter lists from benchmark configuration
@@ -140,12 +342,91 @@ impl ParameterUtils {
 
         Ok(parameter_lists)
     }
+
+    /// Replace `values_from` in parameter list definitions with the values it reads, resolving
+    /// relative files against `dir`, the directory of the benchmark config
+    pub fn resolve_values_from(lists: &mut [serde_json::Value], dir: &Path) -> Result<()> {
+        for list in lists.iter_mut().filter_map(|list| list.as_object_mut()) {
+            let Some(from) = list.remove("values_from") else {
+                continue;
+            };
+            let var = list
+                .get("var")
+                .and_then(|var| var.as_str())
+                .unwrap_or_default()
+                .to_string();
+            if list.contains_key("values") {
+                anyhow::bail!("Parameter {var} has both values and values_from");
+            }
+            let from: ValuesFrom = serde_json::from_value(from).with_context(|| {
+                format!(
+                    "Invalid values_from of parameter {var}, expected a file or file and column"
+                )
+            })?;
+            let values = from
+                .read(dir)
+                .with_context(|| format!("Invalid values_from of parameter {var}"))?;
+            list.insert("values".to_string(), values.into());
+        }
+        Ok(())
+    }
+
+    /// Check a command template only uses placeholders which will be substituted
+    ///
+    /// `available` are the parameter and built-in names. Returns the names of `parameters` the
+    /// template doesn't use, which still vary the matrix and reach hooks as environment variables.
+    pub fn check_placeholders(
+        template: &str,
+        available: &BTreeSet<String>,
+        parameters: &[String],
+    ) -> Result<Vec<String>> {
+        let used = template_placeholders(template);
+        let unused: Vec<String> = parameters
+            .iter()
+            .filter(|name| !used.contains(*name))
+            .cloned()
+            .collect();
+        let unknown: Vec<&String> = used.difference(available).collect();
+        if !unknown.is_empty() {
+            let mut message = format!(
+                "unknown placeholders {} (available: {})",
+                braced(unknown),
+                braced(available)
+            );
+            if !unused.is_empty() {
+                message.push_str(&format!(", parameters not used: {}", braced(&unused)));
+            }
+            anyhow::bail!(message);
+        }
+        Ok(unused)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_template_placeholders() {
+        let placeholders =
+            template_placeholders("bitcoind -dbcache={dbcache} {dataset:blocks} ${HOME} {commit}");
+        assert_eq!(
+            placeholders.into_iter().collect::<Vec<_>>(),
+            ["commit", "dbcache"]
+        );
+
+        let available = BTreeSet::from(["commit".to_string(), "dbcache".to_string()]);
+        let parameters = ["dbcache".to_string()];
+        assert!(
+            ParameterUtils::check_placeholders("bitcoind {commit}", &available, &parameters)
+                .unwrap()
+                .contains(&"dbcache".to_string())
+        );
+        assert!(
+            ParameterUtils::check_placeholders("bitcoind {par}", &available, &parameters).is_err()
+        );
+    }
+
     #[test]
     fn test_parameter_matrix_empty() {
         let matrix = ParameterMatrix::new(&[]);
@@ -233,4 +514,103 @@ mod tests {
         let dirname = ParameterUtils::params_to_dirname(&params);
         assert_eq!(dirname, "default");
     }
+
+    #[test]
+    fn test_params_to_dirname_escaping() {
+        let dirname = |pairs: &[(&str, &str)]| {
+            ParameterUtils::params_to_dirname(
+                &pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            )
+        };
+        // These names are part of stored output directories, don't change them
+        assert_eq!(
+            dirname(&[("dbcache", "450"), ("cache_state", "cold")]),
+            "cache_state-cold_dbcache-450"
+        );
+        assert_eq!(
+            dirname(&[("sleep", "0.3"), ("par", "-1")]),
+            "par--1_sleep-0.3"
+        );
+        assert_eq!(
+            dirname(&[("snapshot", "/data/utxo 840000.dat")]),
+            "snapshot-%2Fdata%2Futxo%20840000.dat"
+        );
+        assert_eq!(dirname(&[("db-cache", "1_000%")]), "db%2Dcache-1%5F000%25");
+        assert_eq!(dirname(&[("label", "ünï")]), "label-%C3%BCn%C3%AF");
+        assert_eq!(dirname(&[("args", "")]), "args-");
+
+        // Values with separators don't collide with other combinations
+        assert_ne!(
+            dirname(&[("a", "b_c-d")]),
+            dirname(&[("a", "b"), ("c", "d")])
+        );
+
+        // Long names are cut short, told apart by a hash of the full name
+        let long = dirname(&[("args", &"-par=1 ".repeat(40))]);
+        assert_eq!(long.len(), MAX_PARAMS_DIRNAME_LEN);
+        assert_eq!(
+            long,
+            format!("args-{}-par%3D~2f4c1d0e7f343f79", "-par%3D1%20".repeat(9))
+        );
+        assert_ne!(long, dirname(&[("args", &"-par=1 ".repeat(41))]));
+    }
+
+    #[test]
+    fn test_params_to_env() {
+        let params = HashMap::from([
+            ("commit".to_string(), "abc123".to_string()),
+            ("db-cache".to_string(), "450".to_string()),
+        ]);
+        let env = ParameterUtils::params_to_env(&params);
+        assert_eq!(env.len(), 2);
+        assert_eq!(env["BENCHKIT_PARAM_COMMIT"], "abc123");
+        assert_eq!(env["BENCHKIT_PARAM_DB_CACHE"], "450");
+    }
+
+    #[test]
+    fn test_values_from() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("commits.txt"),
+            "# bisect range\naaaaaaa\n\n  bbbbbbb\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("sweep.csv"),
+            "name,dbcache\n\"small, cold\",450\nlarge,\"4000\"\n",
+        )
+        .unwrap();
+
+        let mut lists: Vec<serde_json::Value> = serde_json::from_str(
+            r#"[
+                {"var": "commit", "values_from": "commits.txt"},
+                {"var": "dbcache", "values_from": {"file": "sweep.csv", "column": "dbcache"}},
+                {"var": "par", "values": ["2"]}
+            ]"#,
+        )
+        .unwrap();
+        ParameterUtils::resolve_values_from(&mut lists, dir.path()).unwrap();
+        let lists =
+            ParameterUtils::create_parameter_lists(&serde_json::Value::Array(lists)).unwrap();
+        assert_eq!(lists[0].values, ["aaaaaaa", "bbbbbbb"]);
+        assert_eq!(lists[1].values, ["450", "4000"]);
+        assert_eq!(lists[2].values, ["2"]);
+        assert_eq!(csv_fields(r#"a,"b, ""c""",d"#), ["a", r#"b, "c""#, "d"]);
+
+        let resolve = |list: &str| {
+            let mut lists = vec![serde_json::from_str(list).unwrap()];
+            ParameterUtils::resolve_values_from(&mut lists, dir.path())
+        };
+        assert!(resolve(r#"{"var": "a", "values_from": "missing.txt"}"#).is_err());
+        assert!(resolve(r#"{"var": "a", "values": ["1"], "values_from": "commits.txt"}"#).is_err());
+        let err = resolve(r#"{"var": "a", "values_from": {"file": "sweep.csv", "column": "par"}}"#)
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("no column named par"),
+            "{err:#}"
+        );
+    }
 }
@@ -1,7 +1,13 @@
 use anyhow::{Context, Result};
 use log::{debug, info, warn};
+use regex::Regex;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+/// How often the command is checked while waiting to attach perf
+const ATTACH_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Manages perf instrumentation for benchmark commands
 ///
@@ -96,6 +102,30 @@ impl PerfInstrumentor {
         Ok((perf_cmd, perf_data_path))
     }
 
+    /// Construct a perf record command that attaches to running processes
+    ///
+    /// Returns the command and the path of the perf.data file it writes.
+    pub fn attach_command(&self, pids: &[u32]) -> Result<(Vec<String>, PathBuf)> {
+        let perf_data_path = self.output_dir.join("perf.data");
+        std::fs::create_dir_all(&self.output_dir).with_context(|| {
+            format!(
+                "Failed to create perf output directory: {}",
+                self.output_dir.display()
+            )
+        })?;
+
+        let pids: Vec<String> = pids.iter().map(|pid| pid.to_string()).collect();
+        let mut perf_cmd = vec!["perf".to_string(), "record".to_string()];
+        perf_cmd.extend(self.perf_options.clone());
+        perf_cmd.push("-o".to_string());
+        perf_cmd.push(perf_data_path.to_string_lossy().to_string());
+        perf_cmd.push("-p".to_string());
+        perf_cmd.push(pids.join(","));
+
+        debug!("Constructed perf attach command: {:?}", perf_cmd);
+        Ok((perf_cmd, perf_data_path))
+    }
+
     pub fn get_perf_data_path(&self) -> PathBuf {
         self.output_dir.join("perf.data")
     }
@@ -146,6 +176,164 @@ impl PerfInstrumentor {
     }
 }
 
+/// When perf attaches to an already running command instead of starting it, to skip startup
+///
+/// perf attaches once the delay has passed and the pattern has matched the node's debug.log,
+/// whichever of them are set.
+#[derive(Debug, Clone)]
+pub struct PerfAttach {
+    /// Time to wait after the command started
+    pub delay: Option<Duration>,
+    /// Regex to wait for in the node's debug.log
+    pub log_pattern: Option<Regex>,
+}
+
+impl PerfAttach {
+    /// Attach settings from the benchmark options, None if perf should start with the command
+    pub fn from_options(
+        delay_secs: Option<u64>,
+        log_pattern: Option<&str>,
+    ) -> Result<Option<Self>> {
+        let log_pattern = log_pattern
+            .map(Regex::new)
+            .transpose()
+            .context("Invalid regex pattern in perf_attach_on_log_pattern")?;
+        Ok(
+            (delay_secs.is_some() || log_pattern.is_some()).then(|| Self {
+                delay: delay_secs.map(Duration::from_secs),
+                log_pattern,
+            }),
+        )
+    }
+
+    /// Wait until perf should attach to the running command
+    ///
+    /// Returns false if the command exited first.
+    pub fn wait(&self, child: &mut Child, debug_log: &Path) -> Result<bool> {
+        let start = Instant::now();
+        let mut log = LogTail::new(debug_log);
+        let mut matched = self.log_pattern.is_none();
+        loop {
+            if child.try_wait()?.is_some() {
+                return Ok(false);
+            }
+            if let Some(pattern) = &self.log_pattern {
+                matched = matched || log.read_lines().iter().any(|line| pattern.is_match(line));
+            }
+            if matched && self.delay.is_none_or(|delay| start.elapsed() >= delay) {
+                info!(
+                    "Attaching perf {:.1}s after the command started",
+                    start.elapsed().as_secs_f64()
+                );
+                return Ok(true);
+            }
+            std::thread::sleep(ATTACH_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Reads the lines appended to a log file since the last read
+pub struct LogTail {
+    path: PathBuf,
+    offset: u64,
+    partial: String,
+}
+
+impl LogTail {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            offset: 0,
+            partial: String::new(),
+        }
+    }
+
+    /// Skip what the file holds so far, reading only the lines appended from now on
+    pub fn from_end(path: &Path) -> Self {
+        Self {
+            offset: std::fs::metadata(path).map_or(0, |metadata| metadata.len()),
+            ..Self::new(path)
+        }
+    }
+
+    /// Complete lines written since the last call. The file may not exist yet, and is read
+    /// from the start again if it was truncated or replaced by a shorter one.
+    pub fn read_lines(&mut self) -> Vec<String> {
+        let Ok(mut file) = std::fs::File::open(&self.path) else {
+            return Vec::new();
+        };
+        if file
+            .metadata()
+            .is_ok_and(|metadata| metadata.len() < self.offset)
+        {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        let mut appended = Vec::new();
+        if file.seek(SeekFrom::Start(self.offset)).is_err()
+            || file.read_to_end(&mut appended).is_err()
+        {
+            return Vec::new();
+        }
+        self.offset += appended.len() as u64;
+        self.partial.push_str(&String::from_utf8_lossy(&appended));
+        let Some(end) = self.partial.rfind('\n') else {
+            return Vec::new();
+        };
+        let rest = self.partial.split_off(end + 1);
+        let lines = std::mem::replace(&mut self.partial, rest);
+        lines.lines().map(str::to_string).collect()
+    }
+}
+
+/// Bundle what is needed to analyze a perf.data file on another machine
+///
+/// `perf archive` packs the objects with the build-ids perf.data refers to into
+/// `perf.data.tar.bz2`, to be extracted into `~/.debug` where it is analyzed. If that fails (some
+/// distributions don't ship the script), the unstripped binary is linked or copied next to
+/// perf.data instead, to be added with `perf buildid-cache --add`.
+pub fn archive_perf_data(perf_data: &Path, binary: &Path) -> Result<()> {
+    let archived = Command::new("perf")
+        .arg("archive")
+        .arg(perf_data)
+        .output()
+        .context("Failed to run perf archive")
+        .and_then(|output| {
+            if output.status.success() {
+                Ok(())
+            } else {
+                anyhow::bail!(
+                    "perf archive failed: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )
+            }
+        });
+    match archived {
+        Ok(()) => {
+            info!("Archived the symbols of {}", perf_data.display());
+            Ok(())
+        }
+        Err(e) => {
+            warn!("{e:#}, copying {} instead", binary.display());
+            copy_binary_next_to(perf_data, binary)
+        }
+    }
+}
+
+/// Hard link, or copy across filesystems, a binary into the directory of perf.data
+fn copy_binary_next_to(perf_data: &Path, binary: &Path) -> Result<()> {
+    let name = binary
+        .file_name()
+        .with_context(|| format!("Not a binary path: {}", binary.display()))?;
+    let dest = perf_data.with_file_name(name);
+    if dest.exists() {
+        return Ok(());
+    }
+    std::fs::hard_link(binary, &dest)
+        .or_else(|_| std::fs::copy(binary, &dest).map(|_| ()))
+        .with_context(|| format!("Failed to copy {} to {}", binary.display(), dest.display()))
+}
+
 /// Builder for PerfInstrumentor with custom options
 pub struct PerfInstrumentorBuilder {
     output_dir: PathBuf,
@@ -246,6 +434,63 @@ mod tests {
         assert!(!instrumentor.perf_options.contains(&"99".to_string()));
     }
 
+    #[test]
+    fn test_attach_command() {
+        let temp_dir = tempdir().unwrap();
+        let instrumentor = PerfInstrumentor::new(temp_dir.path().to_path_buf());
+        let (perf_cmd, perf_data_path) = instrumentor.attach_command(&[10, 12]).unwrap();
+        assert_eq!(perf_cmd[..2], ["perf", "record"]);
+        assert_eq!(perf_cmd[perf_cmd.len() - 2..], ["-p", "10,12"]);
+        assert_eq!(perf_data_path, temp_dir.path().join("perf.data"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_perf_attach_wait() {
+        assert!(PerfAttach::from_options(None, None).unwrap().is_none());
+        assert!(PerfAttach::from_options(None, Some("(")).is_err());
+
+        let temp_dir = tempdir().unwrap();
+        let debug_log = temp_dir.path().join("debug.log");
+        let mut tail = LogTail::new(&debug_log);
+        assert!(tail.read_lines().is_empty());
+        std::fs::write(&debug_log, "Loading block index\nLoaded snap").unwrap();
+        assert_eq!(tail.read_lines(), ["Loading block index"]);
+        std::fs::write(&debug_log, "Loading block index\nLoaded snapshot\n").unwrap();
+        assert_eq!(tail.read_lines(), ["Loaded snapshot"]);
+
+        let attach = PerfAttach::from_options(None, Some("^Loaded snapshot"))
+            .unwrap()
+            .unwrap();
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        assert!(attach.wait(&mut child, &debug_log).unwrap());
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        // A command exiting before the delay has passed never gets perf attached
+        let attach = PerfAttach::from_options(Some(30), None).unwrap().unwrap();
+        let mut child = Command::new("true").spawn().unwrap();
+        assert!(!attach.wait(&mut child, &debug_log).unwrap());
+    }
+
+    #[test]
+    fn test_copy_binary_next_to() {
+        let temp_dir = tempdir().unwrap();
+        let binary = temp_dir.path().join("bitcoind-abc");
+        std::fs::write(&binary, "elf").unwrap();
+        let run_dir = temp_dir.path().join("abc/default/1");
+        std::fs::create_dir_all(&run_dir).unwrap();
+
+        copy_binary_next_to(&run_dir.join("perf.data"), &binary).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(run_dir.join("bitcoind-abc")).unwrap(),
+            "elf"
+        );
+        // An existing copy is kept
+        copy_binary_next_to(&run_dir.join("perf.data"), &binary).unwrap();
+        assert!(copy_binary_next_to(&run_dir.join("perf.data"), Path::new("/")).is_err());
+    }
+
     #[test]
     fn test_get_perf_data_path() {
         let temp_dir = tempdir().unwrap();
@@ -0,0 +1,293 @@
+//! Analysis of profiler samples
+//!
+//! Profiles of different runs have different lengths and sample offsets. These functions resample
+//! them onto a common timeline for plotting, and reduce them to area-under-curve metrics for
+//! summaries.
+
+use serde::{Deserialize, Serialize};
+
+use super::{ProfileResult, ProfileSample};
+use crate::types::Bytes;
+
+/// Aggregate metrics of a profile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProfileMetrics {
+    /// CPU time in seconds, where one fully used core for one second is one CPU-second
+    pub cpu_seconds: f64,
+    /// Resident memory integrated over time, in byte-seconds
+    pub rss_byte_seconds: f64,
+    /// Highest resident memory of any sample in bytes
+    #[serde(default)]
+    pub peak_rss: u64,
+    /// Mean CPU usage as percentage (100% per core)
+    #[serde(default)]
+    pub mean_cpu_usage: f64,
+    /// 95th percentile of CPU usage as percentage (100% per core)
+    #[serde(default)]
+    pub p95_cpu_usage: f64,
+    /// Total bytes read from disk
+    #[serde(default)]
+    pub disk_read_bytes: Bytes,
+    /// Total bytes written to disk
+    #[serde(default)]
+    pub disk_write_bytes: Bytes,
+}
+
+impl ProfileMetrics {
+    /// Compute the metrics of a profile
+    ///
+    /// Areas under the curve use the trapezoidal rule. Disk totals sum the samples, as each one
+    /// records the bytes transferred since the previous sample.
+    pub fn from_profile(profile: &ProfileResult) -> Self {
+        let samples = &profile.samples;
+        let cpu: Vec<f64> = samples.iter().map(|s| f64::from(s.cpu_usage)).collect();
+        Self {
+            cpu_seconds: integrate(samples, |s| f64::from(s.cpu_usage) / 100.0),
+            rss_byte_seconds: integrate(samples, |s| s.memory as f64),
+            peak_rss: samples.iter().map(|s| s.memory).max().unwrap_or_default(),
+            mean_cpu_usage: if cpu.is_empty() {
+                0.0
+            } else {
+                cpu.iter().sum::<f64>() / cpu.len() as f64
+            },
+            p95_cpu_usage: percentile(&cpu, 95.0),
+            disk_read_bytes: samples.iter().map(|s| Bytes(s.disk_read)).sum(),
+            disk_write_bytes: samples.iter().map(|s| Bytes(s.disk_write)).sum(),
+        }
+    }
+
+    /// Mean of the metrics of several profiles, or None if there are none
+    pub fn mean(metrics: &[Self]) -> Option<Self> {
+        if metrics.is_empty() {
+            return None;
+        }
+        let n = metrics.len();
+        let mean_f64 = |value: fn(&Self) -> f64| metrics.iter().map(value).sum::<f64>() / n as f64;
+        let mean_u64 = |value: fn(&Self) -> u64| metrics.iter().map(value).sum::<u64>() / n as u64;
+        Some(Self {
+            cpu_seconds: mean_f64(|m| m.cpu_seconds),
+            rss_byte_seconds: mean_f64(|m| m.rss_byte_seconds),
+            peak_rss: mean_u64(|m| m.peak_rss),
+            mean_cpu_usage: mean_f64(|m| m.mean_cpu_usage),
+            p95_cpu_usage: mean_f64(|m| m.p95_cpu_usage),
+            disk_read_bytes: Bytes(mean_u64(|m| m.disk_read_bytes.0)),
+            disk_write_bytes: Bytes(mean_u64(|m| m.disk_write_bytes.0)),
+        })
+    }
+}
+
+/// Profile samples interpolated at fixed intervals from the start of profiling
+///
+/// The value at index `i` is the interpolated value at `i * interval` seconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResampledProfile {
+    /// Seconds between points
+    pub interval: f64,
+    /// CPU usage as percentage (100% per core)
+    pub cpu_usage: Vec<f64>,
+    /// Memory usage in bytes
+    pub memory: Vec<f64>,
+    /// Virtual memory usage in bytes
+    pub virtual_memory: Vec<f64>,
+    /// Disk read in bytes per sample
+    pub disk_read: Vec<f64>,
+    /// Disk write in bytes per sample
+    pub disk_write: Vec<f64>,
+}
+
+impl ResampledProfile {
+    /// Number of points in the timeline
+    pub fn len(&self) -> usize {
+        self.cpu_usage.len()
+    }
+
+    /// Whether the profile has no points
+    pub fn is_empty(&self) -> bool {
+        self.cpu_usage.is_empty()
+    }
+
+    /// Time in seconds of each point
+    pub fn times(&self) -> Vec<f64> {
+        (0..self.len()).map(|i| i as f64 * self.interval).collect()
+    }
+}
+
+/// Resample a profile at a fixed interval in seconds
+///
+/// Values are linearly interpolated between samples. Before the first and after the last sample
+/// the nearest sample's value is used.
+pub fn resample(profile: &ProfileResult, interval: f64) -> ResampledProfile {
+    let Some(last) = profile.samples.last() else {
+        return ResampledProfile {
+            interval,
+            ..Default::default()
+        };
+    };
+    let points = if interval > 0.0 {
+        (last.time as f64 / interval).floor() as usize + 1
+    } else {
+        1
+    };
+    let times: Vec<f64> = (0..points).map(|i| i as f64 * interval).collect();
+    let series = |value: fn(&ProfileSample) -> f64| -> Vec<f64> {
+        times
+            .iter()
+            .map(|&time| interpolate(&profile.samples, time, value))
+            .collect()
+    };
+
+    ResampledProfile {
+        interval,
+        cpu_usage: series(|s| f64::from(s.cpu_usage)),
+        memory: series(|s| s.memory as f64),
+        virtual_memory: series(|s| s.virtual_memory as f64),
+        disk_read: series(|s| s.disk_read as f64),
+        disk_write: series(|s| s.disk_write as f64),
+    }
+}
+
+/// Resample several profiles onto a common timeline of at most `max_points` points
+///
+/// All profiles share the same interval, chosen so the longest profile fits in `max_points`.
+/// Shorter profiles end earlier, so their point `i` lines up with point `i` of the others.
+pub fn align(profiles: &[ProfileResult], max_points: usize) -> Vec<ResampledProfile> {
+    let longest = profiles
+        .iter()
+        .filter_map(|profile| profile.samples.last())
+        .map(|sample| sample.time)
+        .max()
+        .unwrap_or_default();
+    let interval = if max_points > 1 && longest > 0 {
+        longest as f64 / (max_points - 1) as f64
+    } else {
+        1.0
+    };
+    profiles
+        .iter()
+        .map(|profile| resample(profile, interval))
+        .collect()
+}
+
+/// Integrate a sample value over time with the trapezoidal rule
+fn integrate(samples: &[ProfileSample], value: impl Fn(&ProfileSample) -> f64) -> f64 {
+    samples
+        .windows(2)
+        .map(|pair| {
+            let dt = pair[1].time.saturating_sub(pair[0].time) as f64;
+            dt * (value(&pair[0]) + value(&pair[1])) / 2.0
+        })
+        .sum()
+}
+
+/// Nearest-rank percentile of a set of values, or 0 if there are none
+fn percentile(values: &[f64], percentile: f64) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Linearly interpolate a sample value at a point in time
+fn interpolate(samples: &[ProfileSample], time: f64, value: fn(&ProfileSample) -> f64) -> f64 {
+    let after = samples.partition_point(|s| (s.time as f64) < time);
+    match (
+        after.checked_sub(1).map(|i| &samples[i]),
+        samples.get(after),
+    ) {
+        (Some(before), Some(after)) if after.time > before.time => {
+            let fraction = (time - before.time as f64) / (after.time - before.time) as f64;
+            value(before) + fraction * (value(after) - value(before))
+        }
+        (_, Some(sample)) | (Some(sample), None) => value(sample),
+        (None, None) => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DurationMs;
+
+    fn sample(time: u64, cpu_usage: f32, memory: u64) -> ProfileSample {
+        ProfileSample {
+            time,
+            cpu_usage,
+            memory,
+            virtual_memory: 0,
+            disk_read: 100,
+            disk_write: 10,
+        }
+    }
+
+    fn profile(samples: Vec<ProfileSample>) -> ProfileResult {
+        ProfileResult {
+            command: "bitcoind".to_string(),
+            duration: DurationMs::from_secs_f64(
+                samples.last().map(|s| s.time as f64).unwrap_or_default(),
+            ),
+            exit_code: 0,
+            samples,
+        }
+    }
+
+    #[test]
+    fn test_metrics() {
+        let profile = profile(vec![
+            sample(0, 100.0, 0),
+            sample(2, 300.0, 1000),
+            sample(4, 300.0, 1000),
+        ]);
+        let metrics = ProfileMetrics::from_profile(&profile);
+        assert_eq!(metrics.cpu_seconds, 4.0 + 6.0);
+        assert_eq!(metrics.rss_byte_seconds, 1000.0 + 2000.0);
+        assert_eq!(metrics.peak_rss, 1000);
+        assert!((metrics.mean_cpu_usage - 700.0 / 3.0).abs() < 1e-9);
+        assert_eq!(metrics.p95_cpu_usage, 300.0);
+        assert_eq!(metrics.disk_read_bytes, Bytes(300));
+        assert_eq!(metrics.disk_write_bytes, Bytes(30));
+
+        let mean = ProfileMetrics::mean(&[metrics, ProfileMetrics::default()]).unwrap();
+        assert_eq!(mean.cpu_seconds, 5.0);
+        assert_eq!(mean.peak_rss, 500);
+        assert!(ProfileMetrics::mean(&[]).is_none());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<f64> = (1..=20).map(f64::from).collect();
+        assert_eq!(percentile(&values, 95.0), 19.0);
+        assert_eq!(percentile(&values, 100.0), 20.0);
+        assert_eq!(percentile(&values, 0.0), 1.0);
+        assert_eq!(percentile(&[], 95.0), 0.0);
+    }
+
+    #[test]
+    fn test_resample() {
+        // Sampling started late and was irregular
+        let resampled = resample(
+            &profile(vec![sample(1, 100.0, 0), sample(5, 200.0, 400)]),
+            2.0,
+        );
+        assert_eq!(resampled.times(), vec![0.0, 2.0, 4.0]);
+        assert_eq!(resampled.cpu_usage, vec![100.0, 125.0, 175.0]);
+        assert_eq!(resampled.memory, vec![0.0, 100.0, 300.0]);
+
+        assert!(resample(&profile(Vec::new()), 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_align() {
+        let long = profile(vec![sample(0, 0.0, 0), sample(100, 100.0, 0)]);
+        let short = profile(vec![sample(0, 0.0, 0), sample(50, 100.0, 0)]);
+        let aligned = align(&[long, short], 11);
+        assert_eq!(aligned[0].interval, 10.0);
+        assert_eq!(aligned[1].interval, 10.0);
+        assert_eq!(aligned[0].len(), 11);
+        assert_eq!(aligned[1].len(), 6);
+        assert_eq!(aligned[0].cpu_usage[5], 50.0);
+        assert_eq!(aligned[1].cpu_usage[5], 100.0);
+    }
+}
@@ -1,5 +1,4 @@
 use anyhow::Result;
-use libc;
 use log::{debug, info, trace, warn};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
@@ -7,7 +6,13 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::time::{Duration, Instant};
-use sysinfo::{Pid, System};
+use sysinfo::{Pid, ProcessesToUpdate, System};
+
+use crate::command::SHELL;
+use crate::process;
+use crate::types::DurationMs;
+
+pub mod analysis;
 
 /// Data collected during a single profiling sample point
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,8 +36,9 @@ pub struct ProfileSample {
 pub struct ProfileResult {
     /// The command that was profiled
     pub command: String,
-    /// Total duration of the profiling session in seconds
-    pub duration: f64,
+    /// Total duration of the profiling session, recorded in seconds
+    #[serde(with = "crate::types::as_secs")]
+    pub duration: DurationMs,
     /// Process exit code
     pub exit_code: i32,
     /// Samples collected during profiling
@@ -129,15 +135,16 @@ impl Profiler {
 
         let start_time = Instant::now();
         let mut samples = Vec::new();
-        let mut sys = System::new_all();
+        let mut sys = System::new();
 
         // Main profiling loop with timeout guard for bitcoind stalling
         let mut last_active_time = Instant::now();
         const MAX_INACTIVE_DURATION: Duration = Duration::from_secs(300); // 5 minutes timeout
 
         while child.try_wait()?.is_none() {
-            // Refresh system info to get latest process data
-            sys.refresh_all();
+            // Refresh the data of the process tree only
+            let pids = related_pids(&mut sys, parent_pid);
+            sys.refresh_processes(ProcessesToUpdate::Some(&pids), true);
 
             // Check if the process is still running
             if sys.process(parent_pid).is_none() {
@@ -146,7 +153,7 @@ impl Profiler {
             }
 
             // Collect sample data
-            let sample = collect_process_sample(&sys, parent_pid, start_time.elapsed().as_secs());
+            let sample = collect_process_sample(&sys, &pids, start_time.elapsed().as_secs());
 
             trace!(
                 "Sample at {}s: CPU: {:.2}%, Memory: {:.2}MB, VMemory: {:.2}MB, Disk R/W: {}/{} bytes",
@@ -168,22 +175,7 @@ impl Profiler {
             if Instant::now().duration_since(last_active_time) > MAX_INACTIVE_DURATION {
                 warn!("Process seems to be stalled (no activity for 5 minutes). Terminating.");
 
-                // Try to terminate gracefully first
-                if let Err(e) = child.kill() {
-                    warn!("Failed to kill stalled process: {e}");
-                    // Try direct kill via system call as fallback
-                    unsafe {
-                        libc::kill(parent_pid.as_u32() as i32, libc::SIGTERM);
-                    }
-                }
-
-                // Also try to terminate any child processes
-                let pgid = -(parent_pid.as_u32() as i32);
-                unsafe {
-                    // Send SIGTERM to the process group
-                    libc::kill(pgid, libc::SIGTERM);
-                }
-
+                process::terminate(&mut child, process::GRACE_PERIOD)?;
                 break;
             }
 
@@ -192,7 +184,7 @@ impl Profiler {
         }
 
         let exit_status = child.wait()?;
-        let duration = start_time.elapsed().as_secs_f64();
+        let duration = start_time.elapsed().into();
         let exit_code = exit_status.code().unwrap_or(-1);
         let profile_result = ProfileResult {
             command: command.to_string(),
@@ -219,8 +211,8 @@ impl Profiler {
 
         // Spawning the command directly without CPU affinity
         // For CPU affinity control, use profile_process instead
-        let child = Command::new("sh")
-            .arg("-c")
+        let child = Command::new(SHELL[0])
+            .arg(SHELL[1])
             .arg(command)
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -230,18 +222,15 @@ impl Profiler {
     }
 }
 
-/// Collect a sample for a process and all its children
-fn collect_process_sample(sys: &System, parent_pid: Pid, elapsed_seconds: u64) -> ProfileSample {
-    let mut all_pids = Vec::new();
-    get_all_related_pids(sys, parent_pid, &mut all_pids);
-
+/// Collect a sample summed over a process and all its descendants
+fn collect_process_sample(sys: &System, all_pids: &[Pid], elapsed_seconds: u64) -> ProfileSample {
     let mut total_cpu = 0.0;
     let mut total_memory = 0;
     let mut total_virtual_memory = 0;
     let mut total_disk_read = 0;
     let mut total_disk_write = 0;
 
-    for &pid in &all_pids {
+    for &pid in all_pids {
         if let Some(process) = sys.process(pid) {
             total_cpu += process.cpu_usage();
             total_memory += process.memory();
@@ -262,34 +251,36 @@ fn collect_process_sample(sys: &System, parent_pid: Pid, elapsed_seconds: u64) -
     }
 }
 
-/// Recursively collect process tree PIDs
-fn get_all_related_pids(sys: &System, parent_pid: Pid, result: &mut Vec<Pid>) {
-    // Check if the parent_pid exists in the system before adding it
-    if sys.process(parent_pid).is_some() && !result.contains(&parent_pid) {
-        result.push(parent_pid);
-
-        // Find all children recursively
-        for process in sys.processes().values() {
-            if let Some(parent) = process.parent() {
-                if parent == parent_pid {
-                    get_all_related_pids(sys, process.pid(), result);
-                }
-            }
+/// PIDs of a process and all its descendants, including reparented ones
+#[cfg(target_os = "linux")]
+fn related_pids(_sys: &mut System, pid: Pid) -> Vec<Pid> {
+    process::descendants(pid.as_u32())
+        .into_iter()
+        .map(Pid::from_u32)
+        .collect()
+}
+
+/// PIDs of a process and all its descendants
+///
+/// Without /proc, children which were reparented after their parent exited are missed.
+#[cfg(not(target_os = "linux"))]
+fn related_pids(sys: &mut System, pid: Pid) -> Vec<Pid> {
+    sys.refresh_processes(ProcessesToUpdate::All, true);
+    let mut children: std::collections::HashMap<Pid, Vec<Pid>> = Default::default();
+    for process in sys.processes().values() {
+        if let Some(parent) = process.parent() {
+            children.entry(parent).or_default().push(process.pid());
         }
-    } else if !result.contains(&parent_pid) {
-        // If the parent isn't in the system but we're looking for it,
-        // add it anyway so we can detect when it's gone
-        result.push(parent_pid);
     }
-
-    // If no processes were found and the parent was in the list,
-    // it means all processes have likely terminated
-    if result.len() == 1 && result[0] == parent_pid && sys.process(parent_pid).is_none() {
-        debug!(
-            "No processes found for PID {}, it may have terminated",
-            parent_pid.as_u32()
-        );
+    let mut related = vec![pid];
+    let mut i = 0;
+    while i < related.len() {
+        if let Some(pids) = children.get(&related[i]) {
+            related.extend(pids);
+        }
+        i += 1;
     }
+    related
 }
 
 /// Export profile results to JSON
@@ -0,0 +1,277 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde_yaml::{Mapping, Value};
+use std::fs::File;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::benchmarks::get_binary_path;
+use crate::benchmarks::metadata::{sha256_file, BinaryMetadata};
+use crate::config::RemoteBuildConfig;
+
+/// App config of the build server's benchkit, with paths relative to the remote directory
+const REMOTE_APP_CONFIG: &str =
+    "home_dir: ./home\nbin_dir: ./binaries\npatch_dir: ./patches\nsnapshot_dir: ./snapshots\n";
+
+/// Builds binaries with benchkit on a build server and copies them to the local `bin_dir`
+pub struct RemoteBuild<'a> {
+    remote: &'a RemoteBuildConfig,
+    bin_dir: &'a Path,
+}
+
+impl<'a> RemoteBuild<'a> {
+    pub fn new(remote: &'a RemoteBuildConfig, bin_dir: &'a Path) -> Self {
+        Self { remote, bin_dir }
+    }
+
+    /// Build `commits` on the build server and copy back the binaries with the ids in `ids`
+    ///
+    /// `bench_config` is the local benchmark config, of which the build server uses the global
+    /// section. Each copied binary is checked against the sha256 recorded in its metadata.
+    pub fn build(
+        &self,
+        bench_config: &Path,
+        commits: &[String],
+        ids: &[String],
+        force_rebuild: bool,
+        jobs: Option<usize>,
+    ) -> Result<()> {
+        let raw = std::fs::read_to_string(bench_config)
+            .with_context(|| format!("Failed to read {}", bench_config.display()))?;
+        self.upload("config.yml", REMOTE_APP_CONFIG)?;
+        self.upload("benchmark.yml", &remote_bench_config(&raw, commits)?)?;
+
+        info!("Building {} on {}", commits.join(", "), self.remote.host);
+        let mut build = format!(
+            "cd {} && {} --app-config config.yml --bench-config benchmark.yml build --local",
+            shell_quote(&self.remote.dir),
+            shell_quote(&self.remote.benchkit)
+        );
+        if force_rebuild {
+            build.push_str(" --force-rebuild");
+        }
+        if let Some(jobs) = jobs {
+            build.push_str(&format!(" --jobs {jobs}"));
+        }
+        self.run(&build, Stdio::inherit())
+            .with_context(|| format!("Remote build on {} failed", self.remote.host))?;
+
+        for id in ids {
+            self.fetch(id)?;
+        }
+        Ok(())
+    }
+
+    /// An ssh command running a shell command line on the build server
+    fn ssh(&self, command_line: &str) -> Command {
+        let (program, args) = self
+            .remote
+            .ssh
+            .split_first()
+            .map(|(program, args)| (program.as_str(), args))
+            .unwrap_or(("ssh", &[]));
+        let mut cmd = Command::new(program);
+        cmd.args(args).arg(&self.remote.host).arg(command_line);
+        cmd
+    }
+
+    fn run(&self, command_line: &str, stdout: Stdio) -> Result<()> {
+        debug!("Running on {}: {command_line}", self.remote.host);
+        let status = self
+            .ssh(command_line)
+            .stdout(stdout)
+            .status()
+            .with_context(|| format!("Failed to run {:?}", self.remote.ssh))?;
+        if !status.success() {
+            anyhow::bail!("`{command_line}` failed on {}: {status}", self.remote.host);
+        }
+        Ok(())
+    }
+
+    /// Write a file into the remote directory
+    fn upload(&self, name: &str, contents: &str) -> Result<()> {
+        let dir = shell_quote(&self.remote.dir);
+        let mut child = self
+            .ssh(&format!("mkdir -p {dir} && cat > {dir}/{name}"))
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run {:?}", self.remote.ssh))?;
+        child
+            .stdin
+            .take()
+            .context("Failed to open ssh's stdin")?
+            .write_all(contents.as_bytes())
+            .with_context(|| format!("Failed to upload {name} to {}", self.remote.host))?;
+        let status = child.wait()?;
+        if !status.success() {
+            anyhow::bail!("Failed to upload {name} to {}: {status}", self.remote.host);
+        }
+        Ok(())
+    }
+
+    /// Copy a binary and its metadata from the build server, verifying its hash
+    fn fetch(&self, id: &str) -> Result<()> {
+        let binary_path = get_binary_path(self.bin_dir, id);
+        let metadata_path = BinaryMetadata::path_for(&binary_path);
+        if let Some(parent) = binary_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        for path in [&binary_path, &metadata_path] {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            let remote_path = format!("{}/binaries/{name}", self.remote.dir);
+            self.run(&format!("cat {}", shell_quote(&remote_path)), file.into())
+                .with_context(|| format!("Failed to copy {name} from {}", self.remote.host))?;
+        }
+
+        let expected = BinaryMetadata::load(&binary_path)?.map(|metadata| metadata.sha256);
+        let actual = sha256_file(&binary_path)?;
+        if expected.as_deref() != Some(actual.as_str()) {
+            for path in [&binary_path, &metadata_path] {
+                let _ = std::fs::remove_file(path);
+            }
+            anyhow::bail!(
+                "Binary {id} copied from {} does not match its recorded sha256 (expected {}, \
+                 found {actual})",
+                self.remote.host,
+                expected.as_deref().unwrap_or("none")
+            );
+        }
+        #[cfg(unix)]
+        std::fs::set_permissions(&binary_path, std::fs::Permissions::from_mode(0o755))?;
+        info!("Copied binary {id} from {}", self.remote.host);
+        Ok(())
+    }
+}
+
+/// The benchmark config for the build server: the local global section building `commits`
+///
+/// The benchmarks are replaced by a placeholder since only the build settings are needed, and the
+/// build server builds locally.
+fn remote_bench_config(raw: &str, commits: &[String]) -> Result<String> {
+    let config: Value = serde_yaml::from_str(raw).context("Failed to parse benchmark config")?;
+    let mut global = config
+        .get("global")
+        .cloned()
+        .context("Benchmark config has no global section")?;
+    global["commits"] = Value::Sequence(commits.iter().cloned().map(Value::String).collect());
+    if let Some(build) = global.get_mut("build").and_then(Value::as_mapping_mut) {
+        build.remove("remote");
+    }
+
+    let placeholder: Value =
+        serde_yaml::from_str("name: build\nnetwork: regtest\nbenchmark:\n  command: bitcoind\n")?;
+    let mut remote = Mapping::new();
+    remote.insert("global".into(), global);
+    remote.insert("benchmarks".into(), Value::Sequence(vec![placeholder]));
+    Ok(serde_yaml::to_string(&remote)?)
+}
+
+/// Quote a string as a single shell word
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+// The fake build server is a shell script
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn write_script(path: &Path, contents: &str) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_remote_build() {
+        let temp_dir = tempdir().unwrap();
+        let remote_dir = temp_dir.path().join("remote");
+        let bin_dir = temp_dir.path().join("binaries");
+        // The build server's binary, which the fake benchkit "builds" by copying it
+        let built_dir = temp_dir.path().join("built");
+        std::fs::create_dir_all(&built_dir).unwrap();
+        let built = built_dir.join("bitcoind-abc");
+        std::fs::write(&built, "bitcoind").unwrap();
+        let mut metadata = BinaryMetadata {
+            commit: "abc".to_string(),
+            variant: None,
+            build_system: "CMake".to_string(),
+            compiler: None,
+            compiler_flags: None,
+            configure_args: Vec::new(),
+            build_host: "buildbox".to_string(),
+            build_duration_secs: 1.0,
+            built_at: 0,
+            sha256: sha256_file(&built).unwrap(),
+            patched: true,
+            patches: Vec::new(),
+            settings: None,
+        };
+        metadata.save(&built).unwrap();
+
+        // ssh running the command line locally
+        let ssh = temp_dir.path().join("ssh");
+        write_script(&ssh, "#!/bin/sh\nshift\nexec sh -c \"$*\"\n");
+        let benchkit = temp_dir.path().join("benchkit");
+        write_script(
+            &benchkit,
+            &format!(
+                "#!/bin/sh\necho \"$@\" > args\nmkdir -p binaries\ncp {}/* binaries/\n",
+                built_dir.display()
+            ),
+        );
+        let bench_config = temp_dir.path().join("benchmark.yml");
+        std::fs::write(
+            &bench_config,
+            "global:\n  source: https://github.com/bitcoin/bitcoin\n  commits: [main]\n  \
+             build:\n    remote:\n      host: buildbox\nbenchmarks:\n  - name: ibd\n",
+        )
+        .unwrap();
+
+        let remote = RemoteBuildConfig {
+            host: "buildbox".to_string(),
+            dir: remote_dir.to_string_lossy().to_string(),
+            benchkit: benchkit.to_string_lossy().to_string(),
+            ssh: vec![ssh.to_string_lossy().to_string()],
+        };
+        let remote_build = RemoteBuild::new(&remote, &bin_dir);
+        let commits = ["abc".to_string()];
+
+        remote_build
+            .build(&bench_config, &commits, &commits, true, Some(4))
+            .unwrap();
+        let binary = get_binary_path(&bin_dir, "abc");
+        assert_eq!(std::fs::read_to_string(&binary).unwrap(), "bitcoind");
+        let mode = std::fs::metadata(&binary).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        let args = std::fs::read_to_string(remote_dir.join("args")).unwrap();
+        assert_eq!(
+            args.trim(),
+            "--app-config config.yml --bench-config benchmark.yml build --local \
+             --force-rebuild --jobs 4"
+        );
+
+        // The build server builds the requested commits without the remote setting
+        let uploaded = std::fs::read_to_string(remote_dir.join("benchmark.yml")).unwrap();
+        let uploaded: Value = serde_yaml::from_str(&uploaded).unwrap();
+        assert_eq!(uploaded["global"]["commits"][0], "abc");
+        assert!(uploaded["global"]["build"].get("remote").is_none());
+        assert_eq!(uploaded["benchmarks"][0]["name"], "build");
+
+        // A binary not matching its recorded hash is removed
+        metadata.sha256 = "0000".to_string();
+        metadata.save(&built).unwrap();
+        let err = remote_build
+            .build(&bench_config, &commits, &commits, false, None)
+            .unwrap_err();
+        assert!(err.to_string().contains("does not match"), "{err}");
+        assert!(!binary.exists());
+
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,877 @@
+use anyhow::{Context, Result};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use crate::attestation::{Manifest, MANIFEST_FILE};
+use crate::benchmarks::export::ResultExporter;
+use crate::benchmarks::metadata::sha256_file;
+use crate::benchmarks::profiler::analysis::ProfileMetrics;
+use crate::benchmarks::repository::format_size;
+use crate::benchmarks::results::{
+    BenchmarkResult, InstrumentationType, ResultAnalyzer, RunFailure, RunResult,
+};
+use crate::types::DurationMs;
+
+/// Results loaded from one output directory
+#[derive(Debug, Clone)]
+pub struct ResultSource {
+    /// Output directory (or results.json) the results were loaded from
+    pub path: PathBuf,
+    /// SHA256 of the manifest.json attesting to the results, or of the results.json without
+    /// one, identifying the source
+    pub sha256: String,
+    /// The loaded results
+    pub results: Vec<BenchmarkResult>,
+}
+
+impl ResultSource {
+    /// Load the results.json in an output directory, or a results.json directly
+    pub fn load(path: &Path) -> Result<Self> {
+        let results_path = if path.is_dir() {
+            path.join("results.json")
+        } else {
+            path.to_path_buf()
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            sha256: source_hash(&results_path)?,
+            results: ResultExporter::load_json(&results_path)?,
+        })
+    }
+
+    /// Directory the results were loaded from, where per-run files like debug.log are
+    pub fn dir(&self) -> &Path {
+        if self.path.is_dir() {
+            &self.path
+        } else {
+            self.path.parent().unwrap_or(Path::new("."))
+        }
+    }
+
+    /// Load several sources, skipping any which duplicates an earlier one
+    pub fn load_all(paths: &[PathBuf]) -> Result<Vec<Self>> {
+        let mut sources: Vec<Self> = Vec::new();
+        for path in paths {
+            let source = Self::load(path)?;
+            if let Some(existing) = sources.iter().find(|s| s.sha256 == source.sha256) {
+                warn!(
+                    "Skipping {}: same results as {}",
+                    path.display(),
+                    existing.path.display()
+                );
+                continue;
+            }
+            sources.push(source);
+        }
+        Ok(sources)
+    }
+}
+
+/// Hash identifying the results in a results.json
+///
+/// Signed output directories are identified by their manifest, which also records when and
+/// where the results were produced. A manifest listing other results, e.g. as the results.json
+/// was replaced after signing, is ignored.
+fn source_hash(results_path: &Path) -> Result<String> {
+    let sha256 = sha256_file(results_path)?;
+    let manifest_path = results_path.with_file_name(MANIFEST_FILE);
+    if !manifest_path.is_file() {
+        return Ok(sha256);
+    }
+    let manifest: Manifest = serde_json::from_slice(&std::fs::read(&manifest_path)?)
+        .with_context(|| format!("Failed to parse {}", manifest_path.display()))?;
+    let name = results_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    if manifest.files.get(name.as_ref()) != Some(&sha256) {
+        return Ok(sha256);
+    }
+    sha256_file(&manifest_path)
+}
+
+/// Combine the runs of results with the same parameters across sources
+///
+/// Runs are renumbered in source order and the summaries recalculated over all runs.
+pub fn merge_results(sources: &[ResultSource]) -> Vec<BenchmarkResult> {
+    let mut merged: Vec<BenchmarkResult> = Vec::new();
+    for result in sources.iter().flat_map(|source| &source.results) {
+        match merged
+            .iter_mut()
+            .find(|m| m.parameters == result.parameters)
+        {
+            Some(existing) => {
+                existing.runs.extend(result.runs.iter().cloned());
+                existing.failures.extend(result.failures.iter().cloned());
+                // Pooled runs no longer correspond to a single adaptive run count
+                existing.runs_needed = None;
+                if existing.commit_label.is_none() {
+                    existing.commit_label = result.commit_label.clone();
+                }
+            }
+            None => merged.push(result.clone()),
+        }
+    }
+
+    for result in &mut merged {
+        for (iteration, run) in result.runs.iter_mut().enumerate() {
+            run.iteration = iteration;
+        }
+        // Keep the stored summary when no individual runs were recorded
+        if !result.runs.is_empty() {
+            (result.summary, result.instrumented_summary) =
+                ResultAnalyzer::calculate_summaries(&result.runs);
+        }
+    }
+    merged
+}
+
+/// Render each parameter combination's mean in every source it appears in
+///
+/// Combinations are those `merge_results` pools runs over, labelled as in the merged table.
+pub fn source_breakdown(sources: &[ResultSource]) -> String {
+    let combinations = merge_results(sources);
+
+    let names: Vec<String> = sources
+        .iter()
+        .map(|source| source.path.display().to_string())
+        .collect();
+    let width = names
+        .iter()
+        .map(|n| n.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    for combination in &combinations {
+        let _ = writeln!(out, "  {}:", display_label(combination));
+        for (source, name) in sources.iter().zip(&names) {
+            let Some(result) = source
+                .results
+                .iter()
+                .find(|r| r.parameters == combination.parameters)
+            else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "    {name:<width$}  {} ± {} ({} run{})",
+                format_duration(result.summary.mean),
+                format_duration(result.summary.std_dev),
+                result.runs.len(),
+                if result.runs.len() == 1 { "" } else { "s" }
+            );
+        }
+    }
+    out
+}
+
+/// Render a single comparison over several sources, followed by the per-source breakdown
+pub fn merged_report(sources: &[ResultSource]) -> String {
+    let merged = merge_results(sources);
+    let mut out = results_report(&format!("merged ({} sources)", sources.len()), &merged);
+    let _ = writeln!(out, "\nBy source:");
+    out.push_str(&source_breakdown(sources));
+    out
+}
+
+/// Render a hyperfine-style summary table for the results of a single benchmark
+///
+/// Each parameter combination is listed with its mean and standard deviation, and its speed
+/// relative to the fastest combination, or to the reference binary if it was benchmarked.
+pub fn summary_table(name: &str, results: &[BenchmarkResult]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Benchmark: {name}");
+
+    let Some(summary) = ResultAnalyzer::calculate_master_summary(results) else {
+        let _ = writeln!(out, "  No results");
+        return out;
+    };
+    let rows: Vec<(String, String, String)> = results
+        .iter()
+        .map(|result| {
+            let mean = format!(
+                "{} ± {}",
+                format_duration(result.summary.mean),
+                format_duration(result.summary.std_dev)
+            );
+            let is_reference = summary.reference_commit.is_some()
+                && result.parameters.get("commit") == summary.reference_commit.as_ref();
+            let relative = if is_reference {
+                "1.00 (reference)".to_string()
+            } else if summary.reference_commit.is_none()
+                && result.parameters == summary.fastest_parameters
+            {
+                "1.00 (fastest)".to_string()
+            } else {
+                summary
+                    .comparison(&result.parameters)
+                    .map(|c| format!("{:.2} ± {:.2}", c.times_faster, c.error))
+                    .unwrap_or_default()
+            };
+            let label = match &result.cached_from {
+                Some(_) => format!("{} (cached)", display_label(result)),
+                None => display_label(result),
+            };
+            (label, mean, relative)
+        })
+        .collect();
+
+    let label_width = rows
+        .iter()
+        .map(|(label, _, _)| label.chars().count())
+        .chain(std::iter::once("Parameters".len()))
+        .max()
+        .unwrap_or_default();
+    let mean_width = rows
+        .iter()
+        .map(|(_, mean, _)| mean.chars().count())
+        .chain(std::iter::once("Mean ± σ".chars().count()))
+        .max()
+        .unwrap_or_default();
+
+    let _ = writeln!(
+        out,
+        "  {:<label_width$}  {:<mean_width$}  Relative",
+        "Parameters", "Mean ± σ"
+    );
+    for (label, mean, relative) in rows {
+        let _ = writeln!(
+            out,
+            "  {label:<label_width$}  {mean:<mean_width$}  {relative}"
+        );
+    }
+    out
+}
+
+/// Render the mean duration for each value of every parameter which varies between results
+///
+/// Each value's mean is averaged over all parameter combinations containing it. Returns an empty
+/// string when there is nothing to break down.
+pub fn parameter_breakdown(results: &[BenchmarkResult]) -> String {
+    let mut values: BTreeMap<&str, BTreeMap<&str, Vec<DurationMs>>> = BTreeMap::new();
+    for result in results {
+        for (name, value) in &result.parameters {
+            values
+                .entry(name)
+                .or_default()
+                .entry(value)
+                .or_default()
+                .push(result.summary.mean);
+        }
+    }
+
+    let mut out = String::new();
+    for (name, values) in values.iter().filter(|(_, values)| values.len() > 1) {
+        let _ = writeln!(out, "  {name}:");
+        let width = values
+            .keys()
+            .map(|v| v.chars().count())
+            .max()
+            .unwrap_or_default();
+        for (value, means) in values {
+            let mean = means.iter().copied().sum::<DurationMs>() / means.len() as f64;
+            let _ = writeln!(
+                out,
+                "    {value:<width$}  {} (over {} result{})",
+                format_duration(mean),
+                means.len(),
+                if means.len() == 1 { "" } else { "s" }
+            );
+        }
+    }
+    out
+}
+
+/// Warnings about failed runs, swapping, statistical outliers, process spawn overhead and
+/// changing feeder nodes in a set of results
+///
+/// Outliers are runs whose modified z-score (based on the median absolute deviation) exceeds
+/// 3.5, which usually means the system was disturbed during the run.
+pub fn outlier_warnings(results: &[BenchmarkResult]) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for result in results {
+        let label = display_label(result);
+
+        let failed: Vec<String> = result
+            .runs
+            .iter()
+            .filter(|run| run.exit_code != 0)
+            .map(|run| format!("#{} (exit code {})", run.iteration, run.exit_code))
+            .collect();
+        if !failed.is_empty() {
+            warnings.push(format!("{label}: failed runs {}", failed.join(", ")));
+        }
+        for failure in &result.failures {
+            warnings.push(format!("{label}: {}", describe_failure(failure)));
+        }
+
+        let swapped: Vec<String> = result
+            .runs
+            .iter()
+            .filter(|run| run.swapped == Some(true))
+            .map(|run| {
+                format!(
+                    "#{} ({} pages in, {} out)",
+                    run.iteration,
+                    run.swap_in.unwrap_or(0),
+                    run.swap_out.unwrap_or(0)
+                )
+            })
+            .collect();
+        if !swapped.is_empty() {
+            warnings.push(format!(
+                "{label}: system swapped during runs {}",
+                swapped.join(", ")
+            ));
+        }
+
+        // Perf-instrumented runs are slower by design, so only the others are compared
+        let runs: Vec<&RunResult> = result
+            .runs
+            .iter()
+            .filter(|run| run.instrumentation == InstrumentationType::Uninstrumented)
+            .collect();
+        let durations: Vec<f64> = runs.iter().map(|run| run.duration_ms.0).collect();
+        let outliers: Vec<String> = outliers(&durations)
+            .into_iter()
+            .map(|i| {
+                let run = runs[i];
+                format!("#{} ({})", run.iteration, format_duration(run.duration_ms))
+            })
+            .collect();
+        if !outliers.is_empty() {
+            warnings.push(format!(
+                "{label}: statistical outliers {} (median {})",
+                outliers.join(", "),
+                format_duration(result.summary.median)
+            ));
+        }
+
+        if let (Some(overhead), Some(fraction)) =
+            (result.spawn_overhead_ms, result.spawn_overhead())
+        {
+            if fraction > SPAWN_OVERHEAD_WARNING {
+                warnings.push(format!(
+                    "{label}: process spawn overhead of {} is {:.1}% of the mean",
+                    format_duration(overhead),
+                    fraction * 100.0
+                ));
+            }
+        }
+
+        if let Some(feeder) = result.feeder.as_ref().filter(|feeder| feeder.changed) {
+            warnings.push(format!(
+                "{label}: the node at {} changed during the runs",
+                feeder.address
+            ));
+        }
+    }
+
+    // Timings synced from different node software aren't comparable
+    let feeders: Vec<(String, &str)> = results
+        .iter()
+        .filter_map(|result| {
+            let user_agent = result.feeder.as_ref()?.user_agent()?;
+            Some((display_label(result), user_agent))
+        })
+        .collect();
+    if feeders
+        .iter()
+        .any(|(_, user_agent)| *user_agent != feeders[0].1)
+    {
+        warnings.push(format!(
+            "results synced from different nodes: {}",
+            feeders
+                .iter()
+                .map(|(label, user_agent)| format!("{label} from {user_agent}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    warnings
+}
+
+/// Fraction of the mean above which process spawn overhead is warned about
+const SPAWN_OVERHEAD_WARNING: f64 = 0.01;
+
+/// Render the profiled resource usage of each parameter combination
+///
+/// Returns an empty string when no runs were profiled.
+pub fn resource_usage(results: &[BenchmarkResult]) -> String {
+    let rows: Vec<(String, ProfileMetrics)> = results
+        .iter()
+        .filter_map(|result| Some((display_label(result), result.summary.profile?)))
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    for (label, metrics) in rows {
+        let _ = writeln!(
+            out,
+            "  {label:<width$}  peak RSS {}, CPU mean {:.0}% p95 {:.0}%, read {}, written {}, \
+             {:.1} CPU-s, {:.2} GiB-s RSS",
+            format_size(metrics.peak_rss),
+            metrics.mean_cpu_usage,
+            metrics.p95_cpu_usage,
+            format_size(metrics.disk_read_bytes.0),
+            format_size(metrics.disk_write_bytes.0),
+            metrics.cpu_seconds,
+            metrics.rss_byte_seconds / (1u64 << 30) as f64
+        );
+    }
+    out
+}
+
+/// Render the failed runs and the errors a benchmark continued past, one per line
+///
+/// Returns an empty string when nothing failed.
+pub fn failure_summary(name: &str, results: &[BenchmarkResult]) -> String {
+    let mut lines = Vec::new();
+    for result in results {
+        let label = display_label(result);
+        for run in result.runs.iter().filter(|run| run.exit_code != 0) {
+            let stderr = run
+                .stderr_tail
+                .as_deref()
+                .and_then(|tail| tail.lines().last())
+                .map(|line| format!(": {line}"))
+                .unwrap_or_default();
+            let outcome = match &run.crash {
+                Some(crash) => match crash.top_frame() {
+                    Some(frame) => format!("crashed with {} in {frame}", crash.signal_name),
+                    None => format!("crashed with {}", crash.signal_name),
+                },
+                None => format!("exited with code {}", run.exit_code),
+            };
+            lines.push(format!("{label}: run #{} {outcome}{stderr}", run.iteration));
+            lines.extend(diagnostics(run.failure_bundle.as_deref()));
+        }
+        for failure in &result.failures {
+            lines.push(format!("{label}: {}", describe_failure(failure)));
+            lines.extend(diagnostics(failure.failure_bundle.as_deref()));
+        }
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    let mut out = String::new();
+    let _ = writeln!(out, "Failures in {name}:");
+    for line in lines {
+        let _ = writeln!(out, "  {line}");
+    }
+    out
+}
+
+/// Where the diagnostics of a failure were bundled, indented under it
+fn diagnostics(bundle: Option<&Path>) -> Option<String> {
+    bundle.map(|bundle| format!("  diagnostics in {}", bundle.display()))
+}
+
+/// The run an error occurred in, if any, and the first line of the error
+fn describe_failure(failure: &RunFailure) -> String {
+    let message = failure.message.lines().next().unwrap_or_default();
+    match failure.iteration {
+        Some(iteration) => format!("run #{iteration}: {message}"),
+        None => message.to_string(),
+    }
+}
+
+/// Render the summary table, parameter breakdown and warnings for a set of results
+pub fn results_report(name: &str, results: &[BenchmarkResult]) -> String {
+    let mut out = summary_table(name, results);
+
+    let breakdown = parameter_breakdown(results);
+    if !breakdown.is_empty() {
+        let _ = writeln!(out, "\nBy parameter:");
+        out.push_str(&breakdown);
+    }
+
+    let usage = resource_usage(results);
+    if !usage.is_empty() {
+        let _ = writeln!(out, "\nResource usage (mean of profiled runs):");
+        out.push_str(&usage);
+    }
+
+    let warnings = outlier_warnings(results);
+    if !warnings.is_empty() {
+        let _ = writeln!(out, "\nWarnings:");
+        for warning in warnings {
+            let _ = writeln!(out, "  {warning}");
+        }
+    }
+    out
+}
+
+/// Indices of the outlying values, using the modified z-score
+fn outliers(values: &[f64]) -> Vec<usize> {
+    if values.len() < 3 {
+        return Vec::new();
+    }
+    let center = median(values);
+    let deviations: Vec<f64> = values.iter().map(|v| (v - center).abs()).collect();
+    let mad = median(&deviations);
+    if mad == 0.0 {
+        return Vec::new();
+    }
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| 0.6745 * (*v - center).abs() / mad > 3.5)
+        .map(|(i, _)| i)
+        .collect()
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mid = sorted.len() / 2;
+    if sorted.len() % 2 == 0 {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    }
+}
+
+/// Parameter label with the commit's configured label (e.g. a branch name) appended
+pub(crate) fn display_label(result: &BenchmarkResult) -> String {
+    let label = ResultAnalyzer::parameter_label(&result.parameters);
+    match (&result.commit_label, result.parameters.get("commit")) {
+        (Some(alias), Some(commit)) => label.replace(
+            &format!("commit={commit}"),
+            &format!("commit={commit} ({alias})"),
+        ),
+        _ => label,
+    }
+}
+
+/// Format a duration in milliseconds for display
+fn format_duration(duration: DurationMs) -> String {
+    if duration.0 >= 1000.0 {
+        format!("{:.3} s", duration.as_secs_f64())
+    } else {
+        format!("{duration:.1} ms")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::results::{RunResult, RunSummary};
+    use crate::benchmarks::{Crash, FeederIdentity, HookStage, PeerVersion};
+    use crate::types::{Bytes, DurationMs};
+    use std::collections::HashMap;
+
+    fn result(commit: &str, mean: f64, label: Option<&str>) -> BenchmarkResult {
+        BenchmarkResult {
+            command: "bitcoind".to_string(),
+            parameters: HashMap::from([("commit".to_string(), commit.to_string())]),
+            commit_label: label.map(str::to_string),
+            runs: Vec::new(),
+            runs_needed: None,
+            version: None,
+            failures: Vec::new(),
+            spawn_overhead_ms: None,
+            cached_from: None,
+            out_dir: None,
+            feeder: None,
+            summary: RunSummary {
+                min: DurationMs(mean),
+                max: DurationMs(mean),
+                mean: DurationMs(mean),
+                median: DurationMs(mean),
+                std_dev: DurationMs(10.0),
+                profile: None,
+                cpu: None,
+            },
+            instrumented_summary: None,
+        }
+    }
+
+    #[test]
+    fn test_summary_table() {
+        let results = vec![
+            result("aaa", 2000.0, Some("master")),
+            result("bbb", 1000.0, None),
+        ];
+        let table = summary_table("sync", &results);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines[0], "Benchmark: sync");
+        assert!(lines[2].contains("commit=aaa (master)"));
+        assert!(lines[2].contains("2.000 s ± 10.0 ms"));
+        assert!(lines[2].contains("2.00 ± 0.02"));
+        assert!(lines[3].contains("commit=bbb"));
+        assert!(lines[3].ends_with("1.00 (fastest)"));
+
+        // A benchmarked reference binary is the baseline, even when it isn't the fastest
+        let results = vec![
+            result("reference", 2000.0, Some("v28.1")),
+            result("bbb", 1000.0, None),
+        ];
+        let table = summary_table("sync", &results);
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[2].contains("commit=reference (v28.1)"));
+        assert!(lines[2].ends_with("1.00 (reference)"));
+        assert!(lines[3].contains("0.50 ± 0.01"));
+    }
+
+    fn run(iteration: usize, duration_ms: f64, exit_code: i32) -> RunResult {
+        RunResult {
+            iteration,
+            duration_ms: DurationMs(duration_ms),
+            exit_code,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parameter_breakdown() {
+        let mut results = vec![
+            result("aaa", 1000.0, None),
+            result("aaa", 3000.0, None),
+            result("bbb", 500.0, None),
+        ];
+        results[0].parameters.insert("dbcache".into(), "450".into());
+        results[1]
+            .parameters
+            .insert("dbcache".into(), "4500".into());
+        results[2]
+            .parameters
+            .insert("dbcache".into(), "4500".into());
+
+        let breakdown = parameter_breakdown(&results);
+        let lines: Vec<&str> = breakdown.lines().collect();
+        assert_eq!(lines[0], "  commit:");
+        assert_eq!(lines[1], "    aaa  2.000 s (over 2 results)");
+        assert_eq!(lines[2], "    bbb  500.0 ms (over 1 result)");
+        assert_eq!(lines[3], "  dbcache:");
+        assert_eq!(lines[4], "    450   1.000 s (over 1 result)");
+        assert_eq!(lines[5], "    4500  1.750 s (over 2 results)");
+
+        // Constant parameters are not broken down
+        assert!(parameter_breakdown(&results[..1]).is_empty());
+    }
+
+    #[test]
+    fn test_outlier_warnings() {
+        let mut stable = result("aaa", 100.0, None);
+        stable.runs = vec![run(0, 100.0, 0), run(1, 101.0, 0), run(2, 99.0, 0)];
+        stable.spawn_overhead_ms = Some(DurationMs(0.5));
+        assert!(outlier_warnings(&[stable.clone()]).is_empty());
+        stable.spawn_overhead_ms = Some(DurationMs(2.0));
+        assert_eq!(
+            outlier_warnings(&[stable]),
+            ["commit=aaa: process spawn overhead of 2.0 ms is 2.0% of the mean"]
+        );
+
+        let mut noisy = result("bbb", 100.0, None);
+        noisy.runs = vec![
+            run(0, 100.0, 0),
+            run(1, 102.0, 0),
+            run(2, 98.0, 0),
+            run(3, 101.0, 1),
+            run(4, 250.0, 0),
+        ];
+        noisy.runs[2].swap_in = Some(12);
+        noisy.runs[2].swap_out = Some(0);
+        noisy.runs[2].swapped = Some(true);
+        let warnings = outlier_warnings(&[noisy]);
+        assert_eq!(warnings.len(), 3);
+        assert_eq!(warnings[0], "commit=bbb: failed runs #3 (exit code 1)");
+        assert_eq!(
+            warnings[1],
+            "commit=bbb: system swapped during runs #2 (12 pages in, 0 out)"
+        );
+        assert!(warnings[2].starts_with("commit=bbb: statistical outliers #4 (250.0 ms)"));
+    }
+
+    #[test]
+    fn test_feeder_warnings() {
+        let feeder = |user_agent: &str, changed| FeederIdentity {
+            address: "127.0.0.1:38333".to_string(),
+            before: Some(PeerVersion {
+                version: 70016,
+                user_agent: user_agent.to_string(),
+                start_height: 880000,
+            }),
+            after: None,
+            changed,
+        };
+        let mut a = result("aaa", 100.0, None);
+        a.feeder = Some(feeder("/Satoshi:28.1.0/", false));
+        let mut b = result("bbb", 100.0, None);
+        b.feeder = Some(feeder("/Satoshi:28.1.0/", false));
+        assert!(outlier_warnings(&[a.clone(), b.clone()]).is_empty());
+
+        b.feeder = Some(feeder("/Satoshi:29.0.0/", true));
+        assert_eq!(
+            outlier_warnings(&[a, b]),
+            [
+                "commit=bbb: the node at 127.0.0.1:38333 changed during the runs",
+                "results synced from different nodes: commit=aaa from /Satoshi:28.1.0/, \
+                 commit=bbb from /Satoshi:29.0.0/"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failure_summary() {
+        let mut crashed = result("aaa", 100.0, None);
+        crashed.runs = vec![run(0, 100.0, 0), run(1, 5.0, 134), run(3, 5.0, 139)];
+        crashed.runs[2].crash = Some(Crash {
+            backtrace: Some(
+                "#0  Chainstate::ConnectBlock (this=0x1) at validation.cpp:2412".into(),
+            ),
+            ..Crash::from_signal((libc::SIGSEGV, "SIGSEGV"))
+        });
+        crashed.runs[1].stderr_tail = Some("Assertion failed\nAborted".to_string());
+        crashed.runs[1].failure_bundle =
+            Some(PathBuf::from("out/aaa/default/failure-aaa-1.tar.zst"));
+        crashed.failures = vec![RunFailure {
+            iteration: Some(2),
+            stage: Some(HookStage::Prepare),
+            message: "Prepare hook failed: disk full\nmore detail".to_string(),
+            failure_bundle: None,
+        }];
+        let mut broken = result("bbb", 0.0, None);
+        broken.failures = vec![RunFailure {
+            iteration: None,
+            stage: None,
+            message: "Failed to spawn command".to_string(),
+            failure_bundle: None,
+        }];
+
+        assert_eq!(failure_summary("sync", &[result("ccc", 1.0, None)]), "");
+        let summary = failure_summary("sync", &[crashed, broken.clone()]);
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "Failures in sync:",
+                "  commit=aaa: run #1 exited with code 134: Aborted",
+                "    diagnostics in out/aaa/default/failure-aaa-1.tar.zst",
+                "  commit=aaa: run #3 crashed with SIGSEGV in Chainstate::ConnectBlock",
+                "  commit=aaa: run #2: Prepare hook failed: disk full",
+                "  commit=bbb: Failed to spawn command",
+            ]
+        );
+
+        // A combination without any completed run is left out of the comparison
+        let table = summary_table("sync", &[result("aaa", 100.0, None), broken]);
+        assert!(table.lines().nth(2).unwrap().ends_with("1.00 (fastest)"));
+    }
+
+    #[test]
+    fn test_merge_results() {
+        let mut first = result("aaa", 100.0, None);
+        first.runs = vec![run(0, 90.0, 0), run(1, 110.0, 0)];
+        let mut second = result("aaa", 200.0, Some("master"));
+        second.runs = vec![run(0, 200.0, 0)];
+        let other = result("bbb", 50.0, None);
+
+        let sources = vec![
+            ResultSource {
+                path: PathBuf::from("out-a"),
+                sha256: "a".to_string(),
+                results: vec![first],
+            },
+            ResultSource {
+                path: PathBuf::from("out-b"),
+                sha256: "b".to_string(),
+                results: vec![second, other],
+            },
+        ];
+        let merged = merge_results(&sources);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].commit_label.as_deref(), Some("master"));
+        let iterations: Vec<usize> = merged[0].runs.iter().map(|r| r.iteration).collect();
+        assert_eq!(iterations, vec![0, 1, 2]);
+        assert!((merged[0].summary.mean.0 - 400.0 / 3.0).abs() < 1e-9);
+
+        let breakdown = source_breakdown(&sources);
+        let lines: Vec<&str> = breakdown.lines().collect();
+        // Listing the results merged above together
+        assert_eq!(lines[0], "  commit=aaa (master):");
+        assert_eq!(lines[1], "    out-a  100.0 ms ± 10.0 ms (2 runs)");
+        assert_eq!(lines[2], "    out-b  200.0 ms ± 10.0 ms (1 run)");
+        assert_eq!(lines[3], "  commit=bbb:");
+        assert_eq!(lines[4], "    out-b  50.0 ms ± 10.0 ms (0 runs)");
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_load_sources_deduplicates() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let first = temp_dir.path().join("first");
+        let copy = temp_dir.path().join("copy");
+        for dir in [&first, &copy] {
+            std::fs::create_dir(dir).unwrap();
+            ResultExporter::export_json(&result("aaa", 100.0, None), &dir.join("results.json"))
+                .unwrap();
+        }
+        let sources = ResultSource::load_all(&[first.clone(), copy.clone()]).unwrap();
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].path, first);
+
+        // Signed results are identified by their manifest, telling apart runs which happen to
+        // have the same results
+        let write_manifest = |dir: &Path, created_at: u64| {
+            let manifest = Manifest {
+                benchkit_version: "0.1.0".to_string(),
+                created_at,
+                machine_fingerprint: None,
+                overrides: None,
+                files: BTreeMap::from([(
+                    "results.json".to_string(),
+                    sha256_file(&dir.join("results.json")).unwrap(),
+                )]),
+            };
+            std::fs::write(
+                dir.join(MANIFEST_FILE),
+                serde_json::to_string(&manifest).unwrap(),
+            )
+            .unwrap();
+        };
+        write_manifest(&first, 1);
+        write_manifest(&copy, 2);
+        let sources = ResultSource::load_all(&[first.clone(), copy.clone()]).unwrap();
+        assert_eq!(sources.len(), 2);
+        write_manifest(&copy, 1);
+        let sources = ResultSource::load_all(&[first, copy]).unwrap();
+        assert_eq!(sources.len(), 1);
+    }
+
+    #[test]
+    fn test_resource_usage() {
+        let mut profiled = result("aaa", 100.0, None);
+        profiled.summary.profile = Some(ProfileMetrics {
+            cpu_seconds: 12.34,
+            rss_byte_seconds: 3.0 * (1u64 << 30) as f64,
+            peak_rss: 1 << 30,
+            mean_cpu_usage: 150.0,
+            p95_cpu_usage: 380.4,
+            disk_read_bytes: Bytes(2 << 20),
+            disk_write_bytes: Bytes(512),
+        });
+        let results = vec![profiled, result("bbb", 100.0, None)];
+        assert_eq!(
+            resource_usage(&results),
+            "  commit=aaa  peak RSS 1.0 GiB, CPU mean 150% p95 380%, read 2.0 MiB, \
+             written 512.0 B, 12.3 CPU-s, 3.00 GiB-s RSS\n"
+        );
+        assert!(resource_usage(&results[1..]).is_empty());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(DurationMs(12.34)), "12.3 ms");
+        assert_eq!(format_duration(DurationMs(1234.5)), "1.234 s");
+    }
+}
@@ -1,9 +1,12 @@
 use anyhow::{Context, Result};
 use log::{debug, info, warn};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use url::Url;
 
+use crate::benchmarks::git::Git;
+use crate::config::{BenchmarkGlobalConfig, RemoteConfig};
+
 /// Repository source can be either local or remote
 #[derive(Debug, Clone)]
 pub enum RepoSource {
@@ -65,6 +68,16 @@ pub struct RepositoryManagerBuilder {
     custom_repo_name: Option<String>,
     /// Skip validation of repository structure
     skip_validation: bool,
+    /// Additional remotes to fetch alongside origin
+    remotes: Vec<RemoteConfig>,
+    /// Prune stale remote-tracking refs when fetching
+    fetch_prune: bool,
+    /// Minimum time between `git gc` runs
+    gc_interval: Option<Duration>,
+    /// Use `git gc --aggressive`
+    gc_aggressive: bool,
+    /// Maximum total size of the repository cache in bytes
+    max_cache_size: Option<u64>,
 }
 
 impl RepositoryManagerBuilder {
@@ -84,6 +97,11 @@ impl RepositoryManagerBuilder {
             cache_dir,
             custom_repo_name: None,
             skip_validation: false,
+            remotes: Vec::new(),
+            fetch_prune: false,
+            gc_interval: None,
+            gc_aggressive: false,
+            max_cache_size: None,
         }
     }
 
@@ -106,6 +124,36 @@ impl RepositoryManagerBuilder {
         self
     }
 
+    /// Add remotes to fetch alongside origin
+    pub fn remotes(mut self, remotes: impl IntoIterator<Item = RemoteConfig>) -> Self {
+        self.remotes.extend(remotes);
+        self
+    }
+
+    /// Prune remote-tracking refs which no longer exist on the remote when fetching
+    pub fn fetch_prune(mut self, prune: bool) -> Self {
+        self.fetch_prune = prune;
+        self
+    }
+
+    /// Run `git gc` on the cached repository when the last gc is older than `interval`
+    pub fn gc_interval(mut self, interval: Option<Duration>) -> Self {
+        self.gc_interval = interval;
+        self
+    }
+
+    /// Use `git gc --aggressive` for periodic garbage collection
+    pub fn gc_aggressive(mut self, aggressive: bool) -> Self {
+        self.gc_aggressive = aggressive;
+        self
+    }
+
+    /// Evict least recently used repositories once the cache exceeds `bytes`
+    pub fn max_cache_size(mut self, bytes: Option<u64>) -> Self {
+        self.max_cache_size = bytes;
+        self
+    }
+
     /// Build the RepositoryManager
     pub fn build(self) -> Result<RepositoryManager> {
         // Create the cache directory if it doesn't exist
@@ -145,6 +193,12 @@ impl RepositoryManagerBuilder {
             cache_dir: self.cache_dir,
             repo_path: None,
             custom_repo_name: self.custom_repo_name,
+            remotes: self.remotes,
+            fetch_prune: self.fetch_prune,
+            gc_interval: self.gc_interval,
+            gc_aggressive: self.gc_aggressive,
+            max_cache_size: self.max_cache_size,
+            git: Git::default(),
         })
     }
 }
@@ -159,6 +213,17 @@ pub struct RepositoryManager {
     repo_path: Option<PathBuf>,
     /// Custom repository name for caching
     custom_repo_name: Option<String>,
+    /// Additional remotes to fetch alongside origin
+    remotes: Vec<RemoteConfig>,
+    /// Prune stale remote-tracking refs when fetching
+    fetch_prune: bool,
+    /// Minimum time between `git gc` runs
+    gc_interval: Option<Duration>,
+    /// Use `git gc --aggressive`
+    gc_aggressive: bool,
+    /// Maximum total size of the repository cache in bytes
+    max_cache_size: Option<u64>,
+    git: Git,
 }
 
 impl RepositoryManager {
@@ -180,6 +245,12 @@ impl RepositoryManager {
             cache_dir,
             repo_path: None,
             custom_repo_name: None,
+            remotes: Vec::new(),
+            fetch_prune: false,
+            gc_interval: None,
+            gc_aggressive: false,
+            max_cache_size: None,
+            git: Git::default(),
         }
     }
 
@@ -212,20 +283,33 @@ impl RepositoryManager {
             }
             RepoSource::Remote(url) => {
                 // For remote repos, check if we have it cached already
-                let repo_name = self
-                    .custom_repo_name
-                    .clone()
-                    .unwrap_or_else(|| self.source.get_cache_name());
-                let repo_path = self.cache_dir.join(&repo_name);
+                let repo_path = self.local_path();
 
                 if repo_path.exists() {
                     debug!("Using cached repository: {}", repo_path.display());
                     // Repository already exists, just update it
+                    self.configure_remotes(&repo_path)?;
                     self.update_repository(&repo_path)?;
                 } else {
                     // Repository doesn't exist, clone it
                     info!("Cloning repository: {} to {}", url, repo_path.display());
                     self.clone_repository(url, &repo_path)?;
+                    if !self.remotes.is_empty() {
+                        self.configure_remotes(&repo_path)?;
+                        self.update_repository(&repo_path)?;
+                    }
+                }
+
+                touch_marker(&repo_path, LAST_USED_MARKER)?;
+                self.maybe_gc(&repo_path)?;
+                if let Some(max_size) = self.max_cache_size {
+                    for evicted in evict_lru(&self.cache_dir, max_size, Some(&repo_path))? {
+                        info!(
+                            "Evicted cached repository {} ({})",
+                            evicted.path.display(),
+                            format_size(evicted.size)
+                        );
+                    }
                 }
 
                 self.repo_path = Some(repo_path.clone());
@@ -237,44 +321,108 @@ impl RepositoryManager {
     /// Clone a repository
     fn clone_repository(&self, url: &str, target_path: &Path) -> Result<()> {
         // Create parent directory if needed
-        if let Some(parent) = target_path.parent() {
-            std::fs::create_dir_all(parent)?;
-        }
+        let parent = target_path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(parent)?;
 
-        let status = Command::new("git")
-            .arg("clone")
-            .arg(url)
-            .arg(target_path)
-            .status()
-            .context(format!("Failed to clone repository: {url}"))?;
+        self.git
+            .run(parent, &["clone", url, &target_path.to_string_lossy()])
+            .context(format!("Failed to clone repository: {url}"))
+    }
+
+    /// Run `git gc` if the configured interval has passed since the last gc
+    fn maybe_gc(&self, repo_path: &Path) -> Result<()> {
+        let Some(interval) = self.gc_interval else {
+            return Ok(());
+        };
+        if let Some(last_gc) = read_marker(repo_path, LAST_GC_MARKER) {
+            if last_gc.elapsed().unwrap_or_default() < interval {
+                return Ok(());
+            }
+        }
 
-        if !status.success() {
-            anyhow::bail!("Git clone failed with status code: {}", status);
+        info!("Running git gc on {}", repo_path.display());
+        let mut args = vec!["gc"];
+        if self.gc_aggressive {
+            args.push("--aggressive");
         }
+        self.git.run(repo_path, &args)?;
+        touch_marker(repo_path, LAST_GC_MARKER)
+    }
 
+    /// Add or update the configured remotes, including pull request refspecs
+    fn configure_remotes(&self, repo_path: &Path) -> Result<()> {
+        for remote in &self.remotes {
+            let current_url = self
+                .git
+                .output(repo_path, &["remote", "get-url", &remote.name], true)
+                .context(format!("Failed to query remote: {}", remote.name))?;
+
+            let action = if !current_url.status.success() {
+                info!("Adding remote {}: {}", remote.name, remote.url);
+                Some("add")
+            } else if String::from_utf8_lossy(&current_url.stdout).trim() != remote.url {
+                info!("Updating remote {} to {}", remote.name, remote.url);
+                Some("set-url")
+            } else {
+                None
+            };
+            if let Some(action) = action {
+                self.git
+                    .run(repo_path, &["remote", action, &remote.name, &remote.url])?;
+            }
+
+            if remote.pull_requests {
+                let refspec = format!("+refs/pull/*/head:refs/remotes/{}/pr/*", remote.name);
+                let key = format!("remote.{}.fetch", remote.name);
+                let existing = self
+                    .git
+                    .output(repo_path, &["config", "--get-all", &key], true)
+                    .context(format!(
+                        "Failed to read refspecs for remote: {}",
+                        remote.name
+                    ))?;
+                if !String::from_utf8_lossy(&existing.stdout)
+                    .lines()
+                    .any(|line| line.trim() == refspec)
+                {
+                    debug!("Adding pull request refspec to remote {}", remote.name);
+                    self.git
+                        .run(repo_path, &["config", "--add", &key, &refspec])?;
+                }
+            }
+        }
         Ok(())
     }
 
     /// Update an existing repository
     fn update_repository(&self, repo_path: &Path) -> Result<()> {
-        // Fetch latest changes
-        let status = Command::new("git")
-            .current_dir(repo_path)
-            .arg("fetch")
-            .status()
-            .context(format!(
-                "Failed to update repository: {}",
-                repo_path.display()
-            ))?;
-
-        if !status.success() {
-            warn!("Git fetch failed with status code: {status}");
+        // Fetch latest changes from all remotes
+        let mut args = vec!["fetch", "--all"];
+        if self.fetch_prune {
+            args.push("--prune");
+        }
+        if let Err(e) = self.git.run(repo_path, &args) {
             // Continue anyway, as the repository might still be usable
+            warn!("Failed to update repository {}: {e}", repo_path.display());
         }
 
         Ok(())
     }
 
+    /// Path of the repository on disk: the source itself, or the cached clone of a remote
+    pub fn local_path(&self) -> PathBuf {
+        match &self.source {
+            RepoSource::Local(path) => path.clone(),
+            RepoSource::Remote(_) => {
+                let repo_name = self
+                    .custom_repo_name
+                    .clone()
+                    .unwrap_or_else(|| self.source.get_cache_name());
+                self.cache_dir.join(repo_name)
+            }
+        }
+    }
+
     /// Get the path to the repository
     pub fn get_repository_path(&self) -> Result<PathBuf> {
         self.repo_path.clone().ok_or_else(|| {
@@ -284,31 +432,398 @@ impl RepositoryManager {
 
     /// Validate that all required commits are available in the repository
     pub fn validate_commits(&self, commits: &[String]) -> Result<()> {
+        self.resolve_commits(commits).map(|_| ())
+    }
+
+    /// Resolve commit-ish names (branches, tags, abbreviated hashes) to short commit hashes
+    ///
+    /// For remote sources, commits missing from the cached clone are fetched from origin and
+    /// then from each additional remote before giving up.
+    pub fn resolve_commits(&self, commits: &[String]) -> Result<Vec<String>> {
         let repo_path = self.repo_path.as_ref().ok_or_else(|| {
             anyhow::anyhow!("Repository not initialized. Call ensure_repository_available() first.")
         })?;
 
+        let mut resolved = Vec::with_capacity(commits.len());
         for commit in commits {
-            // Try to get commit info to verify it exists
-            let output = Command::new("git")
-                .current_dir(repo_path)
-                .arg("cat-file")
-                .arg("-t")
-                .arg(commit)
-                .output()
-                .context(format!("Failed to check commit: {commit}"))?;
-
-            if !output.status.success() {
-                anyhow::bail!("Commit not found in repository: {}", commit);
+            let mut hash = self.resolve_in_clone(repo_path, commit)?;
+            if hash.is_none() && matches!(self.source, RepoSource::Remote(_)) {
+                let remotes = std::iter::once("origin")
+                    .chain(self.remotes.iter().map(|remote| remote.name.as_str()));
+                for remote in remotes {
+                    info!("Commit {commit} not found locally, fetching from {remote}");
+                    if let Err(e) = self.git.run(repo_path, &["fetch", remote, commit]) {
+                        debug!("Fetching {commit} from {remote} failed: {e}");
+                        continue;
+                    }
+                    hash = self.resolve_in_clone(repo_path, commit)?;
+                    if hash.is_none() {
+                        // Fetching a branch or tag by name only updates FETCH_HEAD
+                        hash = resolve_commit(repo_path, "FETCH_HEAD")?;
+                    }
+                    if hash.is_some() {
+                        break;
+                    }
+                }
             }
 
-            // Verify it's a commit object
-            let obj_type = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if obj_type != "commit" {
-                anyhow::bail!("Object is not a commit: {} (type: {})", commit, obj_type);
+            let hash =
+                hash.ok_or_else(|| anyhow::anyhow!("Commit not found in repository: {}", commit))?;
+            debug!("Resolved commit {commit} to {hash}");
+            resolved.push(hash);
+        }
+
+        Ok(resolved)
+    }
+
+    /// Resolve commit-ish names in the repository on disk, without fetching anything
+    ///
+    /// Finds the hashes `resolve_commits` resolved the same names to when building.
+    pub fn resolve_cached_commits(&self, commits: &[String]) -> Result<Vec<String>> {
+        commits
+            .iter()
+            .map(|commit| self.resolve_cached_commit(commit))
+            .collect()
+    }
+
+    /// Resolve a commit-ish name in the repository on disk, without fetching anything
+    pub fn resolve_cached_commit(&self, commit: &str) -> Result<String> {
+        self.resolve_in_clone(&self.local_path(), commit)?
+            .ok_or_else(|| anyhow::anyhow!("Commit not found in repository: {}", commit))
+    }
+
+    /// Resolve a commit-ish, preferring origin's branches in clones of remote sources
+    ///
+    /// `git fetch` moves the remote-tracking branches but never the clone's local branches,
+    /// which stay wherever they were when the repository was cloned.
+    fn resolve_in_clone(&self, repo_path: &Path, commit: &str) -> Result<Option<String>> {
+        if matches!(self.source, RepoSource::Remote(_)) {
+            let tracking = resolve_commit(repo_path, &format!("refs/remotes/origin/{commit}"))?;
+            if tracking.is_some() {
+                return Ok(tracking);
             }
         }
+        resolve_commit(repo_path, commit)
+    }
+}
 
-        Ok(())
+/// Resolve commits to the hashes their binaries were built under
+///
+/// Without a repository on disk, e.g. when the binaries were downloaded, the commits must be the
+/// names the binaries were installed under and are used as given.
+pub fn resolve_built_commits(
+    global: &BenchmarkGlobalConfig,
+    commits: &[String],
+) -> Result<Vec<String>> {
+    let repo = RepositoryManager::new(&global.source.to_string_lossy(), &global.scratch);
+    let repo_path = repo.local_path();
+    if !repo_path.join(".git").exists() {
+        debug!(
+            "No repository at {}, using commits as given",
+            repo_path.display()
+        );
+        return Ok(commits.to_vec());
+    }
+    repo.resolve_cached_commits(commits)
+        .with_context(|| format!("Failed to resolve commits in {}", repo_path.display()))
+}
+
+/// Length of the abbreviated commit hashes used to identify binaries and results
+pub const SHORT_HASH_LEN: usize = 12;
+
+/// Resolve a commit-ish to its canonical short commit hash, peeling annotated tags
+///
+/// Hashes are abbreviated to `SHORT_HASH_LEN` characters, or longer if that is ambiguous.
+/// Returns `None` if the name does not refer to a commit in the repository.
+pub fn resolve_commit(repo_path: &Path, commit: &str) -> Result<Option<String>> {
+    let short = format!("--short={SHORT_HASH_LEN}");
+    let peeled = format!("{commit}^{{commit}}");
+    let output = Git::default()
+        .output(
+            repo_path,
+            &["rev-parse", "--verify", "--quiet", &short, &peeled],
+            true,
+        )
+        .context(format!("Failed to check commit: {commit}"))?;
+
+    if !output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(String::from_utf8(output.stdout)?.trim().to_string()))
+}
+
+/// Resolve each commit-ish to its short commit hash, failing on any which are missing
+pub fn resolve_commits(repo_path: &Path, commits: &[String]) -> Result<Vec<String>> {
+    commits
+        .iter()
+        .map(|commit| {
+            resolve_commit(repo_path, commit)?
+                .ok_or_else(|| anyhow::anyhow!("Commit not found in repository: {}", commit))
+        })
+        .collect()
+}
+
+/// Marker recording when a cached repository was last used by benchkit
+const LAST_USED_MARKER: &str = "benchkit-last-used";
+/// Marker recording when `git gc` last ran on a cached repository
+const LAST_GC_MARKER: &str = "benchkit-last-gc";
+
+/// Write the current time to a marker file in a repository's .git directory
+fn touch_marker(repo_path: &Path, marker: &str) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let path = repo_path.join(".git").join(marker);
+    std::fs::write(&path, now.to_string()).context(format!("Failed to write {}", path.display()))
+}
+
+/// Read the time stored in a marker file, if present
+fn read_marker(repo_path: &Path, marker: &str) -> Option<SystemTime> {
+    let contents = std::fs::read_to_string(repo_path.join(".git").join(marker)).ok()?;
+    let secs = contents.trim().parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// A cached repository clone
+#[derive(Debug, Clone)]
+pub struct CachedRepository {
+    /// Path to the clone
+    pub path: PathBuf,
+    /// Size on disk in bytes
+    pub size: u64,
+    /// When benchkit last used the repository, if known
+    pub last_used: Option<SystemTime>,
+}
+
+/// List the repositories in a cache directory, least recently used first
+pub fn cached_repositories(cache_dir: &Path) -> Result<Vec<CachedRepository>> {
+    let mut repos = Vec::new();
+    if !cache_dir.exists() {
+        return Ok(repos);
+    }
+
+    for entry in std::fs::read_dir(cache_dir)? {
+        let path = entry?.path();
+        if !path.join(".git").is_dir() {
+            continue;
+        }
+        repos.push(CachedRepository {
+            size: dir_size(&path)?,
+            last_used: read_marker(&path, LAST_USED_MARKER),
+            path,
+        });
+    }
+
+    // Repositories without a marker predate usage tracking and are evicted first
+    repos.sort_by_key(|repo| repo.last_used.unwrap_or(UNIX_EPOCH));
+    Ok(repos)
+}
+
+/// Remove least recently used repositories until the cache fits in `max_size` bytes
+///
+/// The repository at `keep` is never evicted. Returns the evicted repositories.
+pub fn evict_lru(
+    cache_dir: &Path,
+    max_size: u64,
+    keep: Option<&Path>,
+) -> Result<Vec<CachedRepository>> {
+    let repos = cached_repositories(cache_dir)?;
+    let mut total: u64 = repos.iter().map(|repo| repo.size).sum();
+
+    let mut evicted = Vec::new();
+    for repo in repos {
+        if total <= max_size {
+            break;
+        }
+        if keep.is_some_and(|keep| keep == repo.path) {
+            continue;
+        }
+        debug!("Removing cached repository {}", repo.path.display());
+        std::fs::remove_dir_all(&repo.path)
+            .context(format!("Failed to remove {}", repo.path.display()))?;
+        total -= repo.size;
+        evicted.push(repo);
+    }
+
+    if total > max_size {
+        warn!(
+            "Repository cache is {} after eviction, above the configured maximum of {}",
+            format_size(total),
+            format_size(max_size)
+        );
+    }
+    Ok(evicted)
+}
+
+/// Total size of the files under a directory, not following symlinks
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Format a byte count for display
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn fake_repo(cache_dir: &Path, name: &str, size: usize, last_used: u64) -> PathBuf {
+        let path = cache_dir.join(name);
+        std::fs::create_dir_all(path.join(".git")).unwrap();
+        std::fs::write(path.join("data"), vec![0u8; size]).unwrap();
+        std::fs::write(
+            path.join(".git").join(LAST_USED_MARKER),
+            last_used.to_string(),
+        )
+        .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_evict_lru() {
+        let temp_dir = tempdir().unwrap();
+        let cache = temp_dir.path();
+        let oldest = fake_repo(cache, "oldest", 1000, 100);
+        let middle = fake_repo(cache, "middle", 1000, 200);
+        let newest = fake_repo(cache, "newest", 1000, 300);
+
+        let repos = cached_repositories(cache).unwrap();
+        assert_eq!(repos[0].path, oldest);
+        assert_eq!(repos[2].path, newest);
+
+        // Keep the oldest, so the next least recently used goes instead
+        let evicted = evict_lru(cache, 2500, Some(&oldest)).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].path, middle);
+        assert!(oldest.exists());
+        assert!(!middle.exists());
+        assert!(newest.exists());
+    }
+
+    #[test]
+    fn test_resolve_commit() {
+        let temp_dir = tempdir().unwrap();
+        let repo = temp_dir.path();
+        for args in [
+            vec!["init", "-q"],
+            vec![
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@test.com",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "initial",
+            ],
+            vec![
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@test.com",
+                "tag",
+                "-a",
+                "v1.0",
+                "-m",
+                "annotated",
+            ],
+        ] {
+            Git::default().run(repo, &args).unwrap();
+        }
+
+        let head = resolve_commit(repo, "HEAD").unwrap().unwrap();
+        assert_eq!(head.len(), SHORT_HASH_LEN);
+        assert_eq!(
+            resolve_commit(repo, &head[..8]).unwrap(),
+            Some(head.clone())
+        );
+        // Annotated tags resolve to the tagged commit, not the tag object
+        assert_eq!(resolve_commit(repo, "v1.0").unwrap(), Some(head.clone()));
+        assert_eq!(resolve_commit(repo, "missing").unwrap(), None);
+        assert!(resolve_commits(repo, &["missing".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_remote_branch() {
+        let temp_dir = tempdir().unwrap();
+        let upstream = temp_dir.path().join("upstream");
+        std::fs::create_dir(&upstream).unwrap();
+        let commit = |repo: &Path, message: &str| {
+            Git::default()
+                .run(
+                    repo,
+                    &[
+                        "-c",
+                        "user.name=test",
+                        "-c",
+                        "user.email=test@test.com",
+                        "commit",
+                        "-q",
+                        "--allow-empty",
+                        "-m",
+                        message,
+                    ],
+                )
+                .unwrap();
+        };
+        Git::default()
+            .run(&upstream, &["init", "-q", "-b", "master"])
+            .unwrap();
+        commit(&upstream, "initial");
+
+        let manager = RepositoryManager::new(
+            "https://example.com/bitcoin.git",
+            &temp_dir.path().join("scratch"),
+        );
+        let clone = manager.local_path();
+        std::fs::create_dir_all(&clone).unwrap();
+        Git::default()
+            .run(&clone, &["clone", "-q", upstream.to_str().unwrap(), "."])
+            .unwrap();
+        let stale = resolve_commit(&clone, "master").unwrap().unwrap();
+
+        // Fetching moves origin/master, but not the clone's own master
+        commit(&upstream, "second");
+        Git::default()
+            .run(&clone, &["fetch", "-q", "--all"])
+            .unwrap();
+        let latest = resolve_commit(&upstream, "master").unwrap().unwrap();
+        assert_eq!(
+            resolve_commit(&clone, "master").unwrap(),
+            Some(stale.clone())
+        );
+        assert_eq!(manager.resolve_cached_commit("master").unwrap(), latest);
+        // Names which aren't branches of origin resolve as usual
+        assert_eq!(manager.resolve_cached_commit(&stale[..8]).unwrap(), stale);
+        assert!(manager.resolve_cached_commit("missing").is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512.0 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
     }
 }
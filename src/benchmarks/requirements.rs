@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use log::debug;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+
+use crate::benchmarks::usdt::probes_in_binary;
+
+/// Optional bitcoind features a benchmark can depend on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Feature {
+    /// USDT tracepoints, compiled in with -DWITH_USDT=ON
+    Usdt,
+    /// ZeroMQ notifications, compiled in with -DWITH_ZMQ=ON
+    Zmq,
+    /// The wallet, compiled in unless -DENABLE_WALLET=OFF
+    Wallet,
+}
+
+impl Feature {
+    /// Option only listed in `bitcoind -help` when the feature is compiled in
+    fn help_option(self) -> Option<&'static str> {
+        match self {
+            Feature::Usdt => None,
+            Feature::Zmq => Some("-zmqpubrawblock"),
+            Feature::Wallet => Some("-disablewallet"),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Feature::Usdt => write!(f, "usdt"),
+            Feature::Zmq => write!(f, "zmq"),
+            Feature::Wallet => write!(f, "wallet"),
+        }
+    }
+}
+
+/// What a benchmark needs of each bitcoind it runs, checked before any benchmark starts
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Requirements {
+    /// Features the binaries must be built with
+    #[serde(default)]
+    pub features: Vec<Feature>,
+    /// Oldest version the binaries may report with `-version`, like "28.0"
+    pub min_version: Option<String>,
+}
+
+impl Requirements {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(min_version) = &self.min_version {
+            parse_version(min_version)
+                .with_context(|| format!("Invalid min_version: {min_version}"))?;
+        }
+        Ok(())
+    }
+
+    /// Why a binary doesn't meet the requirements, empty if it does
+    pub fn unmet(&self, binary: &BinaryFeatures) -> Vec<String> {
+        let mut unmet = Vec::new();
+        if let Some(min_version) = self.min_version.as_deref() {
+            match (parse_version(min_version), binary.version) {
+                (Some(min), Some(version)) if version < min => unmet.push(format!(
+                    "version {} is older than {min_version}",
+                    format_version(version)
+                )),
+                (_, None) => unmet.push(format!(
+                    "its version is unknown, {min_version} or later is required"
+                )),
+                _ => {}
+            }
+        }
+        for feature in &self.features {
+            if !binary.features.contains(feature) {
+                unmet.push(format!("it was built without {feature}"));
+            }
+        }
+        unmet
+    }
+}
+
+/// Version and optional features of a bitcoind binary
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BinaryFeatures {
+    /// Major, minor and patch version
+    pub version: Option<(u32, u32, u32)>,
+    pub features: Vec<Feature>,
+}
+
+impl BinaryFeatures {
+    /// Find out what a binary supports from its `-version` and `-help` output and its ELF notes
+    pub fn probe(binary: &Path) -> Result<Self> {
+        let run = |arg: &str| -> Result<String> {
+            let output = Command::new(binary)
+                .arg(arg)
+                .output()
+                .with_context(|| format!("Failed to run {} {arg}", binary.display()))?;
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+        let version = run("-version")?;
+        let help = run("-help")?;
+        // Binaries without tracepoints, or which readelf can't read, just lack the feature
+        let usdt = probes_in_binary(binary)
+            .inspect_err(|e| debug!("No USDT tracepoints found: {e:#}"))
+            .is_ok_and(|probes| !probes.is_empty());
+        Ok(Self::parse(&version, &help, usdt))
+    }
+
+    /// Features from `-version` and `-help` output, and whether the binary has tracepoints
+    fn parse(version: &str, help: &str, usdt: bool) -> Self {
+        let mut features: Vec<Feature> = [Feature::Zmq, Feature::Wallet]
+            .into_iter()
+            .filter(|feature| {
+                feature
+                    .help_option()
+                    .is_some_and(|option| help.contains(option))
+            })
+            .collect();
+        if usdt {
+            features.insert(0, Feature::Usdt);
+        }
+        Self {
+            version: version.lines().next().and_then(parse_version),
+            features,
+        }
+    }
+}
+
+/// Version in text like "Bitcoin Core version v28.1.0", "28.0" or "28"
+pub(crate) fn parse_version(text: &str) -> Option<(u32, u32, u32)> {
+    let version = Regex::new(r"(?:^|[\sv])(\d+)(?:\.(\d+))?(?:\.(\d+))?").unwrap();
+    let caps = version.captures(text.trim())?;
+    let number = |i: usize| caps.get(i).map_or(Some(0), |m| m.as_str().parse().ok());
+    Some((number(1)?, number(2)?, number(3)?))
+}
+
+fn format_version((major, minor, patch): (u32, u32, u32)) -> String {
+    format!("{major}.{minor}.{patch}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_requirements() {
+        assert_eq!(
+            parse_version("Bitcoin Core version v28.1.0"),
+            Some((28, 1, 0))
+        );
+        assert_eq!(
+            parse_version("Bitcoin Core daemon version v0.21.0rc1"),
+            Some((0, 21, 0))
+        );
+        assert_eq!(parse_version("28.0"), Some((28, 0, 0)));
+        assert_eq!(parse_version("28"), Some((28, 0, 0)));
+        assert_eq!(parse_version("v29.99.0-6b4f6c8b6a1c"), Some((29, 99, 0)));
+        assert_eq!(parse_version("latest"), None);
+
+        let help = "Options:\n  -datadir=<dir>\nZeroMQ notification options:\n  \
+                    -zmqpubrawblock=<address>\n";
+        let binary = BinaryFeatures::parse("Bitcoin Core version v27.2.0\n", help, true);
+        assert_eq!(binary.version, Some((27, 2, 0)));
+        assert_eq!(binary.features, [Feature::Usdt, Feature::Zmq]);
+
+        let met = Requirements {
+            features: vec![Feature::Zmq, Feature::Usdt],
+            min_version: Some("27.0".to_string()),
+        };
+        assert!(met.validate().is_ok());
+        assert!(met.unmet(&binary).is_empty());
+
+        let unmet = Requirements {
+            features: vec![Feature::Wallet],
+            min_version: Some("28".to_string()),
+        };
+        assert_eq!(
+            unmet.unmet(&binary),
+            [
+                "version 27.2.0 is older than 28",
+                "it was built without wallet"
+            ]
+        );
+        assert_eq!(
+            unmet.unmet(&BinaryFeatures::default())[0],
+            "its version is unknown, 28 or later is required"
+        );
+
+        let invalid = Requirements {
+            features: Vec::new(),
+            min_version: Some("latest".to_string()),
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::benchmarks::metadata::sha256_file;
+use crate::benchmarks::results::BenchmarkResult;
+use crate::command::ProcessPriority;
+use crate::config::BenchmarkOptions;
+use crate::path_utils;
+use crate::system_info::CoreAssignment;
+
+/// Results of parameter combinations measured earlier on one machine, in
+/// `<home_dir>/result-cache/<machine fingerprint>/<key>.json`, so unchanged combinations needn't
+/// be run again
+///
+/// A result is keyed by the hash of its binary, the benchmark's configuration and the
+/// combination's command and parameters. Only results without failures are cached.
+pub struct ResultCache {
+    dir: PathBuf,
+    /// Hash of the benchmark configuration shared by all combinations
+    config_hash: String,
+}
+
+impl ResultCache {
+    pub fn new(home_dir: &Path, fingerprint: &str, config_hash: String) -> Self {
+        Self {
+            dir: home_dir.join("result-cache").join(fingerprint),
+            config_hash,
+        }
+    }
+
+    /// Hash the options and settings of a benchmark which affect its results, leaving out the
+    /// parameter lists as each combination's parameters are part of its key
+    ///
+    /// The cores the commands and benchkit are bound to, benchkit's priority and whether the
+    /// system had to be tuned are part of the hash, as they affect the timings too.
+    pub fn config_hash(
+        options: &BenchmarkOptions,
+        mode: &str,
+        env: &HashMap<String, String>,
+        cores: &CoreAssignment,
+        runner_priority: Option<&ProcessPriority>,
+        require_tuned_system: bool,
+    ) -> Result<String> {
+        let mut options = serde_json::to_value(options)?;
+        if let Some(options) = options.as_object_mut() {
+            options.remove("parameter_lists");
+        }
+        let env: BTreeMap<_, _> = env.iter().collect();
+        let config = serde_json::json!({
+            "options": options,
+            "mode": mode,
+            "env": env,
+            "cores": cores,
+            "runner_priority": runner_priority,
+            "require_tuned_system": require_tuned_system,
+        });
+        Ok(format!(
+            "{:x}",
+            Sha256::digest(serde_json::to_vec(&config)?)
+        ))
+    }
+
+    fn key(
+        &self,
+        binary: &Path,
+        command: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<String> {
+        let params: BTreeMap<_, _> = params.iter().collect();
+        let key = serde_json::json!({
+            "binary": sha256_file(binary)?,
+            "config": self.config_hash,
+            "command": command,
+            "params": params,
+        });
+        Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(&key)?)))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+
+    /// The cached result of a combination, marked with the output directory it was measured in
+    pub fn load(
+        &self,
+        binary: &Path,
+        command: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<Option<BenchmarkResult>> {
+        let path = self.path(&self.key(binary, command, params)?);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let cached: CachedResult = serde_json::from_slice(&std::fs::read(&path)?)
+            .with_context(|| format!("Invalid cached result {}", path.display()))?;
+        info!(
+            "Reusing the result of {command} measured in {}",
+            cached.out_dir.display()
+        );
+        let mut result = cached.result;
+        result.cached_from = Some(cached.out_dir);
+        Ok(Some(result))
+    }
+
+    /// Cache the result of a combination measured in `out_dir`, unless it had failures
+    pub fn store(
+        &self,
+        binary: &Path,
+        command: &str,
+        params: &HashMap<String, String>,
+        result: &BenchmarkResult,
+        out_dir: &Path,
+    ) -> Result<()> {
+        let failed = result.runs.is_empty()
+            || !result.failures.is_empty()
+            || result.runs.iter().any(|run| run.exit_code != 0);
+        if failed || result.cached_from.is_some() {
+            return Ok(());
+        }
+        path_utils::ensure_directory(&self.dir)?;
+        let path = self.path(&self.key(binary, command, params)?);
+        let cached = CachedResult {
+            out_dir: std::path::absolute(out_dir).unwrap_or_else(|_| out_dir.to_path_buf()),
+            result: result.clone(),
+        };
+        std::fs::write(&path, serde_json::to_string(&cached)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        debug!("Cached the result of {command} in {}", path.display());
+        Ok(())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedResult {
+    out_dir: PathBuf,
+    result: BenchmarkResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::results::ResultAnalyzer;
+
+    #[test]
+    fn test_result_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("bitcoind-abc");
+        std::fs::write(&binary, "v1").unwrap();
+        let options = BenchmarkOptions::new();
+        let env = HashMap::new();
+        let cores = CoreAssignment {
+            benchmark: vec![2, 3],
+            runner: vec![0],
+            auto: false,
+        };
+        let config_hash =
+            ResultCache::config_hash(&options, "full_ibd", &env, &cores, None, false).unwrap();
+        let cache = ResultCache::new(dir.path(), "machine", config_hash.clone());
+        let params = HashMap::from([("commit".to_string(), "abc".to_string())]);
+        let runs = vec![serde_json::from_value(serde_json::json!({
+            "iteration": 0,
+            "duration_ms": 100.0,
+            "exit_code": 0,
+            "instrumentation": "Uninstrumented",
+        }))
+        .unwrap()];
+        let result = BenchmarkResult {
+            command: "bitcoind".to_string(),
+            parameters: params.clone(),
+            commit_label: None,
+            summary: ResultAnalyzer::calculate_summary(&runs),
+            runs,
+            runs_needed: None,
+            version: None,
+            failures: Vec::new(),
+            spawn_overhead_ms: None,
+            cached_from: None,
+            out_dir: None,
+            feeder: None,
+            instrumented_summary: None,
+        };
+
+        assert!(cache.load(&binary, "bitcoind", &params).unwrap().is_none());
+        let out_dir = dir.path().join("out");
+        cache
+            .store(&binary, "bitcoind", &params, &result, &out_dir)
+            .unwrap();
+        let cached = cache.load(&binary, "bitcoind", &params).unwrap().unwrap();
+        assert_eq!(cached.cached_from.as_deref(), Some(out_dir.as_path()));
+        assert_eq!(cached.runs.len(), 1);
+
+        // A different binary, command or configuration misses
+        assert!(cache
+            .load(&binary, "bitcoind -par=1", &params)
+            .unwrap()
+            .is_none());
+        let mut other_options = BenchmarkOptions::new();
+        other_options.runs += 1;
+        let other = ResultCache::new(
+            dir.path(),
+            "machine",
+            ResultCache::config_hash(&other_options, "full_ibd", &env, &cores, None, false)
+                .unwrap(),
+        );
+        assert!(other.load(&binary, "bitcoind", &params).unwrap().is_none());
+        let other_cores = CoreAssignment {
+            benchmark: vec![4, 5],
+            ..cores.clone()
+        };
+        let other = ResultCache::new(
+            dir.path(),
+            "machine",
+            ResultCache::config_hash(&options, "full_ibd", &env, &other_cores, None, false)
+                .unwrap(),
+        );
+        assert!(other.load(&binary, "bitcoind", &params).unwrap().is_none());
+        std::fs::write(&binary, "v2").unwrap();
+        assert!(cache.load(&binary, "bitcoind", &params).unwrap().is_none());
+    }
+}
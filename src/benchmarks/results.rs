@@ -1,24 +1,38 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
+use crate::benchmarks::checkpoint::Checkpoint;
+use crate::benchmarks::core_dump::Crash;
+use crate::benchmarks::feeder::{FeederIdentity, PeerConnection};
+use crate::benchmarks::profiler::analysis::ProfileMetrics;
 use crate::benchmarks::profiler::ProfileResult;
+use crate::benchmarks::usdt::TracepointSummary;
+use crate::benchmarks::HookStage;
+use crate::reference::REFERENCE_COMMIT;
+use crate::timing::RunTiming;
+use crate::types::{CacheState, DurationMs};
 
 /// Type of instrumentation used for a benchmark run
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum InstrumentationType {
     /// Standard benchmark run without additional instrumentation
+    #[default]
     Uninstrumented,
     /// Benchmark run under perf profiling instrumentation
     PerfInstrumented,
 }
 
 /// Results from a single benchmark run
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct RunResult {
     /// The iteration number (0-indexed)
     pub iteration: usize,
-    /// Duration in milliseconds
-    pub duration_ms: f64,
+    /// Duration measured on the monotonic clock
+    pub duration_ms: DurationMs,
+    /// Monotonic, wall-clock and CPU time of the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing: Option<RunTiming>,
     /// Exit code from the command
     pub exit_code: i32,
     /// Type of instrumentation used for this run
@@ -29,27 +43,149 @@ pub struct RunResult {
     /// Profiling results (optional)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub profile: Option<ProfileResult>,
+    /// Statistics derived from the profiling results
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_metrics: Option<ProfileMetrics>,
+    /// Time spent waiting for the system to become quiet before the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quiet_wait_ms: Option<DurationMs>,
+    /// Page cache state the run started from, when a `cache_state` axis is configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_state: Option<CacheState>,
+    /// Page faults of the command that needed I/O
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub major_faults: Option<u64>,
+    /// Page faults of the command served without I/O
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minor_faults: Option<u64>,
+    /// CPU time the command spent in user mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_time_ms: Option<DurationMs>,
+    /// CPU time the command spent in the kernel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_time_ms: Option<DurationMs>,
+    /// Times the command gave up the CPU while waiting, e.g. for I/O
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub voluntary_context_switches: Option<u64>,
+    /// Times the command was preempted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub involuntary_context_switches: Option<u64>,
+    /// Pages swapped in system-wide during the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_in: Option<u64>,
+    /// Pages swapped out system-wide during the run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap_out: Option<u64>,
+    /// Whether the system swapped during the run, which makes it incomparable to runs that didn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swapped: Option<bool>,
+    /// Arguments the node logged at startup, i.e. the settings that were in effect
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_settings: BTreeMap<String, String>,
+    /// Last lines of the command's stderr, for failed runs whose output was captured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stderr_tail: Option<String>,
+    /// Event counts and durations of the collected tracepoints, keyed by `provider:name`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tracepoints: BTreeMap<String, TracepointSummary>,
+    /// Elapsed time and height at each checkpoint of a checkpointed run, and when it finished
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checkpoints: Vec<Checkpoint>,
+    /// Blocks the node connected less those it disconnected, from its ZMQ notifications
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blocks_connected: Option<u64>,
+    /// Outbound peers the node connected to, from its debug.log
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub peers: Vec<PeerConnection>,
+    /// Signal, core dump and backtrace of a run whose command crashed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crash: Option<Crash>,
+    /// Archive of the logs, core dumps and system state of a failed run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_bundle: Option<PathBuf>,
+}
+
+/// An error a benchmark carried on past because `continue_on_failure` is set
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunFailure {
+    /// Run that failed, None for failures outside of the runs (e.g. in the setup hook)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iteration: Option<usize>,
+    /// Hook stage that failed, None if running the command itself failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<HookStage>,
+    /// The error and its causes
+    pub message: String,
+    /// Archive of the logs, core dumps and system state of the failed run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_bundle: Option<PathBuf>,
 }
 
 /// Statistical summary of benchmark runs
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RunSummary {
-    /// Minimum time in milliseconds
-    pub min: f64,
-    /// Maximum time in milliseconds
-    pub max: f64,
-    /// Mean time in milliseconds
-    pub mean: f64,
-    /// Median time in milliseconds
-    pub median: f64,
-    /// Standard deviation in milliseconds
-    pub std_dev: f64,
+    /// Minimum time
+    pub min: DurationMs,
+    /// Maximum time
+    pub max: DurationMs,
+    /// Mean time
+    pub mean: DurationMs,
+    /// Median time
+    pub median: DurationMs,
+    /// Standard deviation
+    pub std_dev: DurationMs,
+    /// Mean resource usage of the profiled runs, if any were profiled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<ProfileMetrics>,
+    /// Mean CPU time and context switches of the runs they were recorded for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<CpuSummary>,
+}
+
+/// Mean CPU usage of benchmark runs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CpuSummary {
+    /// Mean user-mode CPU time
+    pub user_time_ms: DurationMs,
+    /// Mean kernel CPU time
+    pub system_time_ms: DurationMs,
+    /// Mean number of voluntary context switches
+    pub voluntary_context_switches: f64,
+    /// Mean number of involuntary context switches
+    pub involuntary_context_switches: f64,
+}
+
+impl CpuSummary {
+    /// Means over the runs that recorded their CPU usage, None if none did
+    pub fn from_runs(runs: &[RunResult]) -> Option<Self> {
+        let usage: Vec<(DurationMs, DurationMs, u64, u64)> = runs
+            .iter()
+            .filter_map(|run| {
+                Some((
+                    run.user_time_ms?,
+                    run.system_time_ms?,
+                    run.voluntary_context_switches?,
+                    run.involuntary_context_switches?,
+                ))
+            })
+            .collect();
+        if usage.is_empty() {
+            return None;
+        }
+        let n = usage.len() as f64;
+        Some(Self {
+            user_time_ms: usage.iter().map(|u| u.0).sum::<DurationMs>() / n,
+            system_time_ms: usage.iter().map(|u| u.1).sum::<DurationMs>() / n,
+            voluntary_context_switches: usage.iter().map(|u| u.2 as f64).sum::<f64>() / n,
+            involuntary_context_switches: usage.iter().map(|u| u.3 as f64).sum::<f64>() / n,
+        })
+    }
 }
 
 /// Relative speed comparison between benchmark runs
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SpeedComparison {
-    /// The command/parameters this comparison is made against
+    /// The commit and parameters this comparison is made against
     pub reference_label: String,
     /// How many times faster this benchmark is compared to the reference
     pub times_faster: f64,
@@ -60,12 +196,25 @@ pub struct SpeedComparison {
 /// Master summary comparing all benchmark runs
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MasterSummary {
-    /// The fastest benchmark command
-    pub fastest_command: String,
+    /// Commit of the fastest benchmark
+    pub fastest_commit: String,
     /// The parameters used for the fastest benchmark
     pub fastest_parameters: HashMap<String, String>,
-    /// Relative speed comparisons with other benchmarks
-    pub comparisons: Vec<SpeedComparison>,
+    /// Relative speed comparisons with other benchmarks, keyed by commit and then by the label
+    /// of the remaining parameters
+    pub comparisons: BTreeMap<String, BTreeMap<String, SpeedComparison>>,
+    /// Commit the comparisons are relative to instead of the fastest benchmark, when a reference
+    /// binary was benchmarked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_commit: Option<String>,
+}
+
+impl MasterSummary {
+    /// The comparison of the benchmark with these parameters, if it isn't the baseline
+    pub fn comparison(&self, parameters: &HashMap<String, String>) -> Option<&SpeedComparison> {
+        let (commit, label) = ResultAnalyzer::comparison_key(parameters);
+        self.comparisons.get(&commit)?.get(&label)
+    }
 }
 
 /// Complete results from a benchmark
@@ -75,32 +224,105 @@ pub struct BenchmarkResult {
     pub command: String,
     /// Parameters used in the command
     pub parameters: HashMap<String, String>,
+    /// Label the commit was configured with (e.g. a branch or tag), if it differs from its hash
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_label: Option<String>,
     /// Results from each run
     pub runs: Vec<RunResult>,
-    /// Statistical summary
+    /// Number of runs needed to reach the target relative error, if runs were added adaptively
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runs_needed: Option<usize>,
+    /// First line of the benchmarked binary's `-version` output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Errors the benchmark continued past, with `continue_on_failure`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<RunFailure>,
+    /// Median time to launch and wait for a no-op command the way runs are launched, which is
+    /// included in each run's duration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spawn_overhead_ms: Option<DurationMs>,
+    /// Output directory the result was measured in, if it was reused from the result cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_from: Option<PathBuf>,
+    /// Directory of the runs' output within the output directory, as laid out by the
+    /// benchmark's `out_dir_template`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub out_dir: Option<PathBuf>,
+    /// The node at the benchmark's connect address the runs synced from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feeder: Option<FeederIdentity>,
+    /// Statistical summary of the uninstrumented runs
     pub summary: RunSummary,
+    /// Statistical summary of the perf-instrumented runs, kept apart so perf's overhead doesn't
+    /// affect timing comparisons
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instrumented_summary: Option<RunSummary>,
+}
+
+impl BenchmarkResult {
+    /// How much slower the perf-instrumented runs were on average, as a fraction of the
+    /// uninstrumented mean
+    pub fn perf_overhead(&self) -> Option<f64> {
+        let instrumented = self.instrumented_summary.as_ref()?;
+        (self.summary.mean.0 > 0.0).then(|| instrumented.mean / self.summary.mean - 1.0)
+    }
+
+    /// The process spawn overhead as a fraction of the uninstrumented mean
+    pub fn spawn_overhead(&self) -> Option<f64> {
+        let overhead = self.spawn_overhead_ms?;
+        (self.summary.mean.0 > 0.0).then(|| overhead / self.summary.mean)
+    }
 }
 
 /// Functions for analyzing benchmark results
 pub struct ResultAnalyzer;
 
 impl ResultAnalyzer {
+    /// Summaries of the uninstrumented runs and, if any runs were perf-instrumented, of those
+    pub fn calculate_summaries(results: &[RunResult]) -> (RunSummary, Option<RunSummary>) {
+        let (instrumented, uninstrumented): (Vec<RunResult>, Vec<RunResult>) = results
+            .iter()
+            .cloned()
+            .partition(|r| r.instrumentation == InstrumentationType::PerfInstrumented);
+        let instrumented_summary =
+            (!instrumented.is_empty()).then(|| Self::calculate_summary(&instrumented));
+        (
+            Self::calculate_summary(&uninstrumented),
+            instrumented_summary,
+        )
+    }
+
     /// Calculate a statistical summary for benchmark run results
     pub fn calculate_summary(results: &[RunResult]) -> RunSummary {
         if results.is_empty() {
             return RunSummary {
-                min: 0.0,
-                max: 0.0,
-                mean: 0.0,
-                median: 0.0,
-                std_dev: 0.0,
+                min: DurationMs(0.0),
+                max: DurationMs(0.0),
+                mean: DurationMs(0.0),
+                median: DurationMs(0.0),
+                std_dev: DurationMs(0.0),
+                profile: None,
+                cpu: None,
             };
         }
 
         // Extract durations
-        let durations: Vec<f64> = results.iter().map(|r| r.duration_ms).collect();
+        let durations: Vec<f64> = results.iter().map(|r| r.duration_ms.0).collect();
 
-        Self::calculate_summary_from_durations(&durations)
+        let profiles: Vec<ProfileMetrics> = results
+            .iter()
+            .filter_map(|r| {
+                r.profile_metrics
+                    .or_else(|| r.profile.as_ref().map(ProfileMetrics::from_profile))
+            })
+            .collect();
+
+        RunSummary {
+            profile: ProfileMetrics::mean(&profiles),
+            cpu: CpuSummary::from_runs(results),
+            ..Self::calculate_summary_from_durations(&durations)
+        }
     }
 
     /// Calculate statistical summary from duration values
@@ -140,16 +362,67 @@ impl ResultAnalyzer {
         let std_dev = variance.sqrt();
 
         RunSummary {
-            min,
-            max,
-            mean,
-            median,
-            std_dev,
+            min: DurationMs(min),
+            max: DurationMs(max),
+            mean: DurationMs(mean),
+            median: DurationMs(median),
+            std_dev: DurationMs(std_dev),
+            profile: None,
+            cpu: None,
         }
     }
 
+    /// Half-width of the 95% confidence interval of the mean, relative to the mean
+    ///
+    /// Uses Student's t-distribution, so it is valid for the small sample sizes typical of
+    /// long-running benchmarks. Needs at least two durations.
+    pub fn relative_error(durations: &[f64]) -> Option<f64> {
+        let n = durations.len();
+        if n < 2 {
+            return None;
+        }
+        let mean = durations.iter().sum::<f64>() / n as f64;
+        if mean == 0.0 {
+            return None;
+        }
+        let variance = durations.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / (n - 1) as f64;
+        let std_error = (variance / n as f64).sqrt();
+        Some(t_critical_95(n - 1) * std_error / mean.abs())
+    }
+
+    /// Label identifying a parameter combination, e.g. "commit=abc, dbcache=450"
+    pub fn parameter_label(parameters: &HashMap<String, String>) -> String {
+        if parameters.is_empty() {
+            return "default".to_string();
+        }
+        let mut params: Vec<String> = parameters.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        params.sort();
+        params.join(", ")
+    }
+
+    /// Commit and label of the remaining parameters, which key a `MasterSummary`'s comparisons
+    ///
+    /// Benchmarks without a commit parameter are keyed as the "default" commit.
+    pub fn comparison_key(parameters: &HashMap<String, String>) -> (String, String) {
+        let commit = parameters
+            .get("commit")
+            .cloned()
+            .unwrap_or_else(|| "default".to_string());
+        let mut others = parameters.clone();
+        others.remove("commit");
+        (commit, Self::parameter_label(&others))
+    }
+
     /// Calculate a master summary for a set of benchmark results
+    ///
+    /// Results are compared with the fastest result, or, if the reference binary was
+    /// benchmarked, with the reference result of the same parameters.
+    /// Parameter combinations which failed before completing any run are left out.
     pub fn calculate_master_summary(results: &[BenchmarkResult]) -> Option<MasterSummary> {
+        let results: Vec<&BenchmarkResult> = results
+            .iter()
+            .filter(|result| !result.runs.is_empty() || result.failures.is_empty())
+            .collect();
         if results.is_empty() {
             return None;
         }
@@ -167,54 +440,209 @@ impl ResultAnalyzer {
             .map(|(idx, _)| idx)
             .unwrap_or(0);
 
-        let fastest = &results[fastest_idx];
-        let fastest_mean = fastest.summary.mean;
+        let fastest = results[fastest_idx];
+
+        let is_reference = |result: &BenchmarkResult| {
+            result.parameters.get("commit").map(String::as_str) == Some(REFERENCE_COMMIT)
+        };
+        let has_reference = results.iter().any(|result| is_reference(result));
 
         // Create comparisons for all other benchmarks
-        let mut comparisons = Vec::new();
+        let mut comparisons: BTreeMap<String, BTreeMap<String, SpeedComparison>> = BTreeMap::new();
         for (i, result) in results.iter().enumerate() {
-            if i == fastest_idx {
+            let baseline = if has_reference {
+                if is_reference(result) {
+                    continue;
+                }
+                // The reference run with the same parameters, apart from the commit
+                let same_parameters = |other: &&BenchmarkResult| {
+                    is_reference(other)
+                        && other.parameters.len() == result.parameters.len()
+                        && other
+                            .parameters
+                            .iter()
+                            .all(|(k, v)| k == "commit" || result.parameters.get(k) == Some(v))
+                };
+                match results.iter().copied().find(same_parameters) {
+                    Some(reference) => reference,
+                    None => continue,
+                }
+            } else if i == fastest_idx {
                 continue; // Skip the fastest one (it would just be 1.0× faster than itself)
-            }
-
-            // Create a label from parameters
-            let param_str = if result.parameters.is_empty() {
-                "default".to_string()
             } else {
-                result
-                    .parameters
-                    .iter()
-                    .map(|(k, v)| format!("{k}={v}"))
-                    .collect::<Vec<_>>()
-                    .join(", ")
+                fastest
             };
-
-            // Calculate how many times slower this benchmark is
-            let times_slower = result.summary.mean / fastest_mean;
-
-            // Simple error propagation (approximate)
-            let relative_error_squared = (fastest.summary.std_dev / fastest_mean).powi(2)
-                + (result.summary.std_dev / result.summary.mean).powi(2);
-            let error = times_slower * relative_error_squared.sqrt();
-
-            comparisons.push(SpeedComparison {
-                reference_label: param_str,
-                times_faster: times_slower,
-                error,
-            });
+            let (commit, label) = Self::comparison_key(&result.parameters);
+            comparisons
+                .entry(commit)
+                .or_default()
+                .insert(label, Self::speed_comparison(result, baseline));
         }
 
-        // Sort comparisons by slowest first (largest times_faster value)
-        comparisons.sort_by(|a, b| {
-            b.times_faster
-                .partial_cmp(&a.times_faster)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-
         Some(MasterSummary {
-            fastest_command: fastest.command.clone(),
+            fastest_commit: Self::comparison_key(&fastest.parameters).0,
             fastest_parameters: fastest.parameters.clone(),
             comparisons,
+            reference_commit: has_reference.then(|| REFERENCE_COMMIT.to_string()),
         })
     }
+
+    /// How many times slower a result is than a baseline
+    fn speed_comparison(result: &BenchmarkResult, baseline: &BenchmarkResult) -> SpeedComparison {
+        let baseline_mean = baseline.summary.mean;
+        let times_slower = result.summary.mean / baseline_mean;
+
+        // Simple error propagation (approximate)
+        let relative_error_squared = (baseline.summary.std_dev / baseline_mean).powi(2)
+            + (result.summary.std_dev / result.summary.mean).powi(2);
+        let error = times_slower * relative_error_squared.sqrt();
+
+        SpeedComparison {
+            reference_label: Self::parameter_label(&baseline.parameters),
+            times_faster: times_slower,
+            error,
+        }
+    }
+}
+
+/// Two-sided 95% critical value of Student's t-distribution with `df` degrees of freedom
+pub(crate) fn t_critical_95(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    match df {
+        0 => f64::INFINITY,
+        1..=30 => TABLE[df - 1],
+        // Close enough to the normal distribution
+        _ => 1.96,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(parameters: &[(&str, &str)], mean: f64) -> BenchmarkResult {
+        BenchmarkResult {
+            command: format!("bitcoind -mean={mean}"),
+            parameters: parameters
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            commit_label: None,
+            runs: Vec::new(),
+            runs_needed: None,
+            version: None,
+            failures: Vec::new(),
+            spawn_overhead_ms: None,
+            cached_from: None,
+            out_dir: None,
+            feeder: None,
+            summary: RunSummary {
+                min: DurationMs(mean),
+                max: DurationMs(mean),
+                mean: DurationMs(mean),
+                median: DurationMs(mean),
+                std_dev: DurationMs(0.0),
+                profile: None,
+                cpu: None,
+            },
+            instrumented_summary: None,
+        }
+    }
+
+    #[test]
+    fn test_master_summary_keyed_by_commit() {
+        let results = vec![
+            result(&[("commit", "aaa"), ("dbcache", "450")], 100.0),
+            result(&[("commit", "aaa"), ("dbcache", "4500")], 50.0),
+            result(&[("commit", "bbb"), ("dbcache", "450")], 200.0),
+            result(&[("commit", "bbb"), ("dbcache", "4500")], 100.0),
+        ];
+        let summary = ResultAnalyzer::calculate_master_summary(&results).unwrap();
+        assert_eq!(summary.fastest_commit, "aaa");
+        assert_eq!(summary.fastest_parameters["dbcache"], "4500");
+        assert_eq!(
+            summary.comparisons.keys().collect::<Vec<_>>(),
+            ["aaa", "bbb"]
+        );
+        assert_eq!(
+            summary.comparisons["aaa"].keys().collect::<Vec<_>>(),
+            ["dbcache=450"]
+        );
+        let comparison = &summary.comparisons["bbb"]["dbcache=450"];
+        assert_eq!(comparison.times_faster, 4.0);
+        assert_eq!(comparison.reference_label, "commit=aaa, dbcache=4500");
+        assert!(summary.comparison(&results[1].parameters).is_none());
+        assert_eq!(
+            summary
+                .comparison(&results[3].parameters)
+                .unwrap()
+                .times_faster,
+            2.0
+        );
+
+        // Each commit is compared with the reference run of the same parameters
+        let mut results = results;
+        results[0]
+            .parameters
+            .insert("commit".into(), REFERENCE_COMMIT.into());
+        results[1]
+            .parameters
+            .insert("commit".into(), REFERENCE_COMMIT.into());
+        let summary = ResultAnalyzer::calculate_master_summary(&results).unwrap();
+        assert_eq!(summary.reference_commit.as_deref(), Some(REFERENCE_COMMIT));
+        assert_eq!(summary.comparisons.keys().collect::<Vec<_>>(), ["bbb"]);
+        assert_eq!(summary.comparisons["bbb"]["dbcache=450"].times_faster, 2.0);
+        assert_eq!(summary.comparisons["bbb"]["dbcache=4500"].times_faster, 2.0);
+    }
+
+    #[test]
+    fn test_relative_error() {
+        assert_eq!(ResultAnalyzer::relative_error(&[]), None);
+        assert_eq!(ResultAnalyzer::relative_error(&[100.0]), None);
+        assert_eq!(ResultAnalyzer::relative_error(&[100.0, 100.0]), Some(0.0));
+
+        // mean 100, sample std dev sqrt(400 / 3), n 4: 3.182 * 11.547 / 2 / 100
+        let durations = [90.0, 110.0, 90.0, 110.0];
+        let error = ResultAnalyzer::relative_error(&durations).unwrap();
+        assert!((error - 0.1837).abs() < 1e-3, "{error}");
+
+        // More samples of the same spread narrow the interval
+        let more = [durations; 4].concat();
+        assert!(ResultAnalyzer::relative_error(&more).unwrap() < error);
+    }
+
+    #[test]
+    fn test_paired_perf_summaries() {
+        let json = |iteration: usize, duration_ms: f64, instrumentation: &str| {
+            serde_json::from_value::<RunResult>(serde_json::json!({
+                "iteration": iteration,
+                "duration_ms": duration_ms,
+                "exit_code": 0,
+                "instrumentation": instrumentation,
+            }))
+            .unwrap()
+        };
+        let runs = vec![
+            json(0, 100.0, "Uninstrumented"),
+            json(1, 130.0, "PerfInstrumented"),
+            json(2, 110.0, "Uninstrumented"),
+            json(3, 150.0, "PerfInstrumented"),
+        ];
+        let (summary, instrumented) = ResultAnalyzer::calculate_summaries(&runs);
+        assert_eq!(summary.mean, DurationMs(105.0));
+        assert_eq!(summary.max, DurationMs(110.0));
+        let instrumented = instrumented.unwrap();
+        assert_eq!(instrumented.mean, DurationMs(140.0));
+
+        let mut result = result(&[("commit", "aaa")], 105.0);
+        result.instrumented_summary = Some(instrumented);
+        assert!((result.perf_overhead().unwrap() - 1.0 / 3.0).abs() < 1e-9);
+
+        let (_, instrumented) = ResultAnalyzer::calculate_summaries(&runs[..1]);
+        assert!(instrumented.is_none());
+    }
 }
@@ -1,15 +1,42 @@
 use anyhow::{Context, Result};
-use clap::ValueEnum;
-use log::{debug, info};
+use log::{debug, info, warn};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-use crate::benchmarks::hook_runner::HookArgs;
+use crate::benchmarks::benchmark_runner::{AdaptiveRuns, Warmup};
+use crate::benchmarks::build::benchkit_patches;
+use crate::benchmarks::feeder::check_feeder;
+use crate::benchmarks::history::History;
+use crate::benchmarks::hook_runner::{HookArgs, HookStage, NodePorts};
+use crate::benchmarks::hooks::HookMode;
+use crate::benchmarks::live::LiveStatus;
+use crate::benchmarks::metadata::{BinaryMetadata, BuildSettings};
+use crate::benchmarks::overrides::{RunOverrides, OVERRIDES_FILE};
 use crate::benchmarks::parameters::ParameterList;
-use crate::benchmarks::utils::check_binaries_exist;
-use crate::config::{get_merged_options, GlobalConfig, SingleConfig};
+use crate::benchmarks::perf::PerfAttach;
+use crate::benchmarks::report::{failure_summary, summary_table};
+use crate::benchmarks::repository::resolve_built_commits;
+use crate::benchmarks::requirements::BinaryFeatures;
+use crate::benchmarks::result_cache::ResultCache;
+use crate::benchmarks::results::BenchmarkResult;
+use crate::benchmarks::topology::{check_ports, NodeRole};
+use crate::benchmarks::usdt::resolve_tracepoints;
+use crate::benchmarks::utils::{binary_id, check_binaries_exist, get_binary_path};
+use crate::config::{
+    get_merged_options, BenchmarkOptions, BenchmarkSelection, GlobalConfig, SingleConfig,
+    DEFAULT_MAX_RUNS,
+};
+use crate::dataset::expand_dataset_placeholders;
 use crate::download::SnapshotInfo;
+use crate::error::BenchkitError;
 use crate::path_utils;
-use crate::types::Network;
+use crate::reference::{ReferenceBinary, REFERENCE_COMMIT};
+use crate::system::SystemChecker;
+use crate::telemetry;
+use crate::types::DurationMs;
+
+/// File in the output directory recording the system checks run for `require_tuned_system`
+pub const SYSTEM_CHECKS_FILE: &str = "system_checks.json";
 
 /// High-level benchmark orchestrator that coordinates benchmark execution
 ///
@@ -27,15 +54,60 @@ pub struct Runner {
     global_config: GlobalConfig,
     /// Directory to store benchmark outputs and results
     out_dir: PathBuf,
+    /// Run binaries whose recorded build settings differ from the configuration
+    allow_stale: bool,
+    /// Suppress the summary table printed after all benchmarks have run
+    quiet: bool,
+    /// Only warn when `require_tuned_system` is set and required system checks fail
+    allow_untuned: bool,
+    /// Only run benchmarks with one of these tags, if any are given
+    tags: Vec<String>,
+    /// Skip benchmarks with any of these tags
+    exclude_tags: Vec<String>,
+    /// Fingerprint of this machine, keying its benchmark history
+    fingerprint: String,
+    /// Configuration overridden on the command line for this invocation
+    overrides: RunOverrides,
+    /// Don't check the nodes at the benchmarks' connect addresses before running
+    skip_connect_check: bool,
 }
 
 impl Runner {
     /// Create a new Runner
     pub fn new(global_config: GlobalConfig, out_dir: PathBuf) -> Result<Self> {
+        Self::create(global_config, out_dir, false)
+    }
+
+    /// Create a Runner continuing an interrupted run in `out_dir`: checkpointed runs carry on
+    /// from their last checkpoint, and those which finished keep their results
+    pub fn resume(global_config: GlobalConfig, out_dir: PathBuf) -> Result<Self> {
+        Self::create(global_config, out_dir, true)
+    }
+
+    fn create(mut global_config: GlobalConfig, out_dir: PathBuf, resume: bool) -> Result<Self> {
         debug!("Using output directory: {}", out_dir.display());
 
+        // Binaries are named by short commit hash, so resolve commits the same way the builder did
+        let commits = resolve_built_commits(
+            &global_config.bench.global,
+            &global_config.bench.global.commits,
+        )?;
+        global_config.bench.global.set_resolved_commits(commits);
+
+        if let Some(reference) = global_config.bench.global.reference_binary.clone() {
+            global_config
+                .bench
+                .global
+                .commit_labels
+                .insert(REFERENCE_COMMIT.to_string(), reference);
+        }
+
         // Create output directory and check it's empty
-        path_utils::prepare_output_directory(&out_dir)?;
+        if !resume {
+            path_utils::prepare_output_directory(&out_dir)?;
+        } else if !out_dir.is_dir() {
+            anyhow::bail!("Cannot resume, {} doesn't exist", out_dir.display());
+        }
 
         // Copy config files to output directory
         let app_config_name = global_config.app.path.file_name().unwrap_or_default();
@@ -46,70 +118,426 @@ impl Runner {
         path_utils::copy_file(&global_config.bench.path, &out_dir.join(bench_config_name))?;
 
         // Dump system info
-        crate::system_info::dump_sys_info(&out_dir.join("system_info"))?;
+        let system_info = crate::system_info::dump_sys_info(
+            &out_dir,
+            &global_config.bench.global.tmp_data_dir,
+            &global_config.bench.global.cores,
+        )?;
 
         Ok(Self {
             global_config,
             out_dir,
+            allow_stale: false,
+            quiet: false,
+            allow_untuned: false,
+            tags: Vec::new(),
+            exclude_tags: Vec::new(),
+            fingerprint: system_info.fingerprint,
+            overrides: RunOverrides::default(),
+            skip_connect_check: false,
         })
     }
 
-    /// Run all or a specific benchmark
-    pub fn run(&self, name: Option<&str>) -> Result<()> {
+    /// Allow running binaries built with settings that differ from the configuration
+    pub fn allow_stale(mut self, allow_stale: bool) -> Self {
+        self.allow_stale = allow_stale;
+        self
+    }
+
+    /// Warn instead of refusing to run when required system checks fail
+    pub fn allow_untuned(mut self, allow_untuned: bool) -> Self {
+        self.allow_untuned = allow_untuned;
+        self
+    }
+
+    /// Only run benchmarks having at least one of `tags` and none of `exclude_tags`
+    pub fn tags(mut self, tags: Vec<String>, exclude_tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self.exclude_tags = exclude_tags;
+        self
+    }
+
+    /// Suppress the summary table printed after all benchmarks have run
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// Override the configuration of every selected benchmark for this invocation
+    pub fn overrides(mut self, overrides: RunOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Skip checking that the nodes at the benchmarks' connect addresses are reachable
+    pub fn skip_connect_check(mut self, skip: bool) -> Self {
+        self.skip_connect_check = skip;
+        self
+    }
+
+    /// Check the overrides and record them in the output directory, unless there are none
+    fn record_overrides(&self) -> Result<()> {
+        self.overrides.validate()?;
+        if self.overrides.is_empty() {
+            return Ok(());
+        }
+        info!("Overriding the configuration with {:?}", self.overrides);
+        let path = self.out_dir.join(OVERRIDES_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(&self.overrides)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Check binaries were built with the settings the configuration requests
+    fn check_binaries_current(&self) -> Result<()> {
+        let global = &self.global_config.bench.global;
+        let patches = benchkit_patches();
+
+        let mut stale = Vec::new();
+        for commit in &global.commits {
+            for variant in global.variant_names() {
+                let id = binary_id(commit, variant.as_deref());
+                let requested = BuildSettings::requested(global, variant.as_deref(), &patches)
+                    .with_patch_hash(&self.global_config.app.patch_dir);
+                let binary_path = get_binary_path(&self.global_config.app.bin_dir, &id);
+                let reasons = BinaryMetadata::stale_reasons(&binary_path, &requested)?;
+                if !reasons.is_empty() {
+                    stale.push((id, reasons));
+                }
+            }
+        }
+
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        let mut msg = String::from("Binaries were built with different settings:\n");
+        for (id, reasons) in &stale {
+            msg.push_str(&format!("  - bitcoind-{}: {}\n", id, reasons.join("; ")));
+        }
+
+        if self.allow_stale {
+            warn!("{}Continuing as --allow-stale was given.", msg);
+            return Ok(());
+        }
+
+        msg.push_str(
+            "\nPlease run 'benchkit build' to rebuild them, or pass --allow-stale to use them anyway.",
+        );
+        anyhow::bail!(msg);
+    }
+
+    /// Check every binary has the features and version the selected benchmarks require
+    fn check_requirements(&self, benchmarks: &[(usize, &SingleConfig)]) -> Result<()> {
+        let global = &self.global_config.bench.global;
+        let mut probed: HashMap<String, BinaryFeatures> = HashMap::new();
+        let mut unmet = Vec::new();
+        for (_, bench) in benchmarks {
+            let Some(requires) = &bench.requires else {
+                continue;
+            };
+            for id in global.binary_ids() {
+                if !probed.contains_key(&id) {
+                    let binary = get_binary_path(&self.global_config.app.bin_dir, &id);
+                    probed.insert(id.clone(), BinaryFeatures::probe(&binary)?);
+                }
+                for reason in requires.unmet(&probed[&id]) {
+                    unmet.push(format!("  - {}: bitcoind-{id}: {reason}\n", bench.name));
+                }
+            }
+        }
+
+        if unmet.is_empty() {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "Binaries don't meet the requirements of the benchmarks:\n{}",
+            unmet.concat()
+        );
+    }
+
+    /// Check the node at each benchmark's connect address accepts connections on its network
+    fn check_feeders(&self, benchmarks: &[(usize, &SingleConfig)]) -> Result<()> {
+        if self.skip_connect_check {
+            return Ok(());
+        }
+        let mut checked = Vec::new();
+        let mut unreachable = Vec::new();
+        for (index, bench) in benchmarks {
+            let Some(address) = self.feeder_address(*index, bench)? else {
+                continue;
+            };
+            let key = (address.clone(), bench.network, bench.connect_rpc.clone());
+            if checked.contains(&key) {
+                continue;
+            }
+            if let Err(e) = check_feeder(&address, bench.network, bench.connect_rpc.as_ref()) {
+                unreachable.push(format!("  - {}: {e:#}\n", bench.name));
+            }
+            checked.push(key);
+        }
+
+        if unreachable.is_empty() {
+            return Ok(());
+        }
+        anyhow::bail!(
+            "The nodes at the benchmarks' connect addresses aren't ready:\n{}\nStart them, fix \
+             `connect`, or pass --skip-connect-check to run anyway.",
+            unreachable.concat()
+        );
+    }
+
+    /// The connect address of a benchmark syncing from a node benchmark doesn't start itself
+    fn feeder_address(&self, index: usize, bench: &SingleConfig) -> Result<Option<String>> {
+        let Some(address) = bench.connect.as_deref() else {
+            return Ok(None);
+        };
+        if address.is_empty() || address == "0" || address.contains('{') {
+            return Ok(None);
+        }
+        if let Some(mode) = &bench.mode {
+            if HookMode::mode_from_str(mode)? == HookMode::LoadBlock {
+                return Ok(None);
+            }
+        }
+        // Node roles are only started with the runs
+        let options = get_merged_options(&self.global_config.bench, index)?;
+        let port = address
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .unwrap_or_else(|| bench.network.default_p2p_port());
+        let role_port = options
+            .nodes
+            .iter()
+            .flatten()
+            .any(|role| NodePorts::from_command(&role.command, bench.network).p2p == port);
+        Ok((!role_port).then(|| address.to_string()))
+    }
+
+    /// Check the system is tuned for benchmarking if the configuration requires it
+    ///
+    /// The results of every check are recorded in `system_checks.json` in the output directory.
+    fn check_system_tuned(&self) -> Result<()> {
+        if !self.global_config.bench.global.require_tuned_system {
+            return Ok(());
+        }
+
+        let checks = SystemChecker::new()
+            .and_then(|checker| checker.checks())
+            .context("Failed to run system checks required by require_tuned_system")?;
+        let checks_path = self.out_dir.join(SYSTEM_CHECKS_FILE);
+        std::fs::write(&checks_path, serde_json::to_string_pretty(&checks)?)
+            .with_context(|| format!("Failed to write {}", checks_path.display()))?;
+
+        let failed: Vec<_> = checks.iter().filter(|c| c.required && !c.ok).collect();
+        if failed.is_empty() {
+            info!("System checks passed");
+            return Ok(());
+        }
+
+        let mut msg = String::from("System is not tuned for benchmarking:\n");
+        for check in &failed {
+            msg.push_str(&format!("  {check}\n"));
+        }
+
+        if self.allow_untuned {
+            warn!("{}Continuing as --allow-untuned was given.", msg);
+            return Ok(());
+        }
+
+        msg.push_str("\nPlease run 'benchkit system tune', or pass --allow-untuned to run anyway.");
+        anyhow::bail!(msg);
+    }
+
+    /// Install the reference binary, if configured, under its commit name
+    fn install_reference_binary(&self) -> Result<()> {
+        let global = &self.global_config.bench.global;
+        let Some(reference) = &global.reference_binary else {
+            return Ok(());
+        };
+        ReferenceBinary::parse(reference)?
+            .install(
+                &self.global_config.app.home_dir.join("releases"),
+                &self.global_config.app.bin_dir,
+                &global.variant_names(),
+            )
+            .context("Failed to install reference_binary")
+    }
+
+    /// Run the selected benchmarks, or all of them if the selection is empty
+    pub fn run(&self, selection: &BenchmarkSelection) -> Result<()> {
+        self.record_overrides()?;
+        self.install_reference_binary()?;
+
         // Check if all required binaries exist
         if let Err(missing_binaries) = check_binaries_exist(
             &self.global_config.app.bin_dir,
-            &self.global_config.bench.global.commits,
+            &self.global_config.bench.global.binary_ids(),
         ) {
             let mut error_msg = String::from("Missing required binaries:\n");
-            for (commit, path) in missing_binaries {
+            for (id, path) in missing_binaries {
                 error_msg.push_str(&format!(
                     "  - Binary 'bitcoind-{}' not found at expected path: {}\n",
-                    commit,
+                    id,
                     path.display()
                 ));
             }
             error_msg.push_str("\nPlease run 'benchkit build' to build the required binaries.");
             anyhow::bail!(error_msg);
         }
+        self.check_binaries_current()?;
+        self.check_system_tuned()?;
 
-        let benchmarks = match name {
-            Some(n) => {
-                let bench = self
-                    .global_config
-                    .bench
-                    .benchmarks
-                    .iter()
-                    .enumerate()
-                    .find(|(_, b)| b.name == n)
-                    .with_context(|| format!("Benchmark not found: {n}"))?;
-                vec![bench]
-            }
-            None => self
-                .global_config
-                .bench
-                .benchmarks
-                .iter()
-                .enumerate()
-                .collect(),
-        };
+        let benchmarks = selection.select(&self.global_config.bench.benchmarks)?;
+        if benchmarks.is_empty() {
+            anyhow::bail!("No benchmarks match the selection {selection:?}");
+        }
+        let benchmarks: Vec<_> = benchmarks
+            .into_iter()
+            .filter(|(_, bench)| bench.matches_tags(&self.tags, &self.exclude_tags))
+            .collect();
+        if benchmarks.is_empty() {
+            anyhow::bail!(
+                "No benchmarks match the tags {:?} (excluding {:?})",
+                self.tags,
+                self.exclude_tags
+            );
+        }
+        self.check_requirements(&benchmarks)?;
+        self.check_feeders(&benchmarks)?;
 
+        let mut summaries = Vec::new();
+        let mut failures = Vec::new();
+        let mut changes = Vec::new();
+        let mut exported = Vec::new();
+        let history = self
+            .global_config
+            .app
+            .history
+            .then(|| History::new(&self.global_config.app.home_dir, &self.fingerprint));
         for (index, bench) in benchmarks {
             self.check_snapshot(bench, &self.global_config.app.snapshot_dir)?;
-            self.run_benchmark(index, bench)?;
+            let mut span = telemetry::span("benchmark");
+            span.attr("benchkit.benchmark", &bench.name)
+                .attr("benchkit.network", bench.network.to_string());
+            let results = span
+                .record(self.run_benchmark(index, bench))
+                .map_err(|source| BenchkitError::Benchmark {
+                    name: bench.name.clone(),
+                    source,
+                })?;
+            drop(span);
+            summaries.push(summary_table(&bench.name, &results));
+            failures.push(failure_summary(&bench.name, &results));
+            if let Some(history) = &history {
+                // Reused results were recorded when they were measured
+                let measured: Vec<BenchmarkResult> = results
+                    .iter()
+                    .filter(|result| result.cached_from.is_none())
+                    .cloned()
+                    .collect();
+                match history.record(&bench.name, &measured) {
+                    Ok(bench_changes) => changes.extend(
+                        bench_changes
+                            .into_iter()
+                            .map(|change| format!("{}: {change}", bench.name)),
+                    ),
+                    Err(e) => warn!("Failed to record history of {}: {e:#}", bench.name),
+                }
+            }
+            exported.push((bench.name.clone(), results));
+        }
+
+        if !self.quiet {
+            println!();
+            for summary in summaries {
+                println!("{summary}");
+            }
+            for change in &changes {
+                println!("{change}");
+            }
+        }
+        LiveStatus::finish(&self.out_dir);
+        // Failures are listed even when quiet, as they are easy to miss in the log
+        for failure in failures.iter().filter(|failure| !failure.is_empty()) {
+            println!("{failure}");
+        }
+
+        for export in &self.global_config.bench.global.exports {
+            export.write(&exported, &self.out_dir)?;
+        }
+
+        if let Some(signing_key) = &self.global_config.app.signing_key {
+            let key = crate::attestation::load_signing_key(signing_key)?;
+            crate::attestation::sign_output(&self.out_dir, &key)?;
         }
 
         Ok(())
     }
 
+    /// Means recorded in the history, for `fail_fast` to compare first runs against
+    fn historical_means(
+        &self,
+        bench: &SingleConfig,
+        options: &BenchmarkOptions,
+    ) -> HashMap<String, DurationMs> {
+        if options
+            .fail_fast
+            .as_ref()
+            .is_none_or(|fail_fast| fail_fast.max_slowdown.is_none())
+        {
+            return HashMap::new();
+        }
+        if !self.global_config.app.history {
+            warn!(
+                "fail_fast max_slowdown of {} needs history enabled in the app config",
+                bench.name
+            );
+            return HashMap::new();
+        }
+        History::new(&self.global_config.app.home_dir, &self.fingerprint)
+            .last_means(&bench.name)
+            .unwrap_or_else(|e| {
+                warn!("Failed to read the history of {}: {e:#}", bench.name);
+                HashMap::new()
+            })
+    }
+
+    /// The result cache of a benchmark, if enabled in the app config
+    fn result_cache(
+        &self,
+        options: &BenchmarkOptions,
+        bench: &SingleConfig,
+    ) -> Result<Option<ResultCache>> {
+        if !self.global_config.app.result_cache {
+            return Ok(None);
+        }
+        let global = &self.global_config.bench.global;
+        let config_hash = ResultCache::config_hash(
+            options,
+            bench.mode.as_deref().unwrap_or_default(),
+            &bench.env.clone().unwrap_or_default(),
+            &global.cores,
+            global.runner_priority.as_ref(),
+            global.require_tuned_system,
+        )?;
+        Ok(Some(ResultCache::new(
+            &self.global_config.app.home_dir,
+            &self.fingerprint,
+            config_hash,
+        )))
+    }
+
     /// Check if required snapshot exists
     fn check_snapshot(&self, bench: &SingleConfig, snapshot_dir: &Path) -> Result<()> {
-        // Check if we have the correct snapshot
-        let network = Network::from_str(&bench.network, true)
-            .map_err(|e| anyhow::anyhow!("{}", e))
-            .with_context(|| format!("Invalid network: {:?}", bench.network))?;
+        // Only assumeutxo mode loads a snapshot
+        if let Some(mode) = &bench.mode {
+            if HookMode::mode_from_str(mode)? != HookMode::AssumeUtxo {
+                return Ok(());
+            }
+        }
 
-        if let Some(snapshot_info) = SnapshotInfo::for_network(&network) {
+        // Check if we have the correct snapshot
+        if let Some(snapshot_info) = SnapshotInfo::for_network(&bench.network) {
             let snapshot_path = snapshot_dir.join(snapshot_info.filename);
             if !snapshot_path.exists() {
                 anyhow::bail!(
@@ -126,11 +554,17 @@ This can be downloaded with `benchkit snapshot download {}`",
     }
 
     /// Run a specific benchmark
-    fn run_benchmark(&self, index: usize, bench: &SingleConfig) -> Result<()> {
+    fn run_benchmark(&self, index: usize, bench: &SingleConfig) -> Result<Vec<BenchmarkResult>> {
         info!("Running benchmark: {:?}", bench.name);
 
         // Get merged options for this benchmark
-        let options = get_merged_options(&self.global_config.bench, index)?;
+        let mut options = get_merged_options(&self.global_config.bench, index)?;
+        if let Some(runs) = self.overrides.runs {
+            options.runs = runs;
+        }
+        if let Some(warmup) = self.overrides.warmup {
+            options.warmup = warmup;
+        }
 
         // Create parameter lists for substitution
         let mut parameter_lists = if let Some(params) = &options.parameter_lists {
@@ -141,42 +575,76 @@ This can be downloaded with `benchkit snapshot download {}`",
         } else {
             Vec::new()
         };
+        self.overrides.apply_params(&mut parameter_lists);
 
-        // Add commits parameter list if not already present
+        // Add commits parameter list if not already present, with the reference binary first
         if !parameter_lists.iter().any(|list| list.var == "commit") {
+            let global = &self.global_config.bench.global;
+            let reference = global
+                .reference_binary
+                .as_ref()
+                .map(|_| REFERENCE_COMMIT.to_string());
             parameter_lists.push(ParameterList {
                 var: "commit".to_string(),
-                values: self.global_config.bench.global.commits.clone(),
+                values: reference
+                    .into_iter()
+                    .chain(global.commits.clone())
+                    .collect(),
             });
         }
 
+        // Build variants are an additional matrix axis, selecting between binaries
+        let variants = &self.global_config.bench.global.build.variants;
+        let binary_placeholder = if variants.is_empty() {
+            "{commit}"
+        } else {
+            if !parameter_lists.iter().any(|list| list.var == "variant") {
+                parameter_lists.push(ParameterList {
+                    var: "variant".to_string(),
+                    values: variants.iter().map(|v| v.name.clone()).collect(),
+                });
+            }
+            "{commit}-{variant}"
+        };
+
+        // Cache state is an axis the benchmark runner acts on, rather than a command placeholder
+        if let Some(states) = &options.cache_state {
+            if !parameter_lists.iter().any(|list| list.var == "cache_state") {
+                parameter_lists.push(ParameterList {
+                    var: "cache_state".to_string(),
+                    values: states.iter().map(|s| s.to_string()).collect(),
+                });
+            }
+        }
+
+        // CPU limits are axes too, so each scenario is recorded in the results' parameters
+        if let Some(cpu_limits) = &options.cpu_limits {
+            if !cpu_limits.quotas.is_empty()
+                && !parameter_lists.iter().any(|list| list.var == "cpu_quota")
+            {
+                parameter_lists.push(ParameterList {
+                    var: "cpu_quota".to_string(),
+                    values: cpu_limits.quotas.clone(),
+                });
+            }
+            if !cpu_limits.par.is_empty() && !parameter_lists.iter().any(|list| list.var == "par") {
+                parameter_lists.push(ParameterList {
+                    var: "par".to_string(),
+                    values: cpu_limits.par.iter().map(|par| par.to_string()).collect(),
+                });
+            }
+        }
+
         // Create hook runner with appropriate mode
         let mode = if let Some(mode_str) = &bench.mode {
-            crate::benchmarks::HookMode::mode_from_str(mode_str)?
+            HookMode::mode_from_str(mode_str)?
         } else {
-            crate::benchmarks::HookMode::default()
+            HookMode::default()
         };
         let hook_runner = crate::benchmarks::hook_runner::HookRunner::with_mode(mode);
 
-        // Create benchmark runner with optional profiling
-        let benchmark_runner = crate::benchmarks::benchmark_runner::BenchmarkRunner::builder(
-            self.out_dir.clone(),
-            hook_runner,
-        )
-        .capture_output(options.capture_output)
-        .parameter_lists(parameter_lists)
-        .profiling(options.profile.unwrap_or(false), options.profile_interval)
-        .benchmark_cores(self.global_config.bench.global.benchmark_cores.clone())
-        .stop_on_log_pattern(options.stop_on_log_pattern.clone())
-        .perf_instrumentation(options.perf_instrumentation.unwrap_or(false))
-        .build()?;
-
         // Get snapshot info
-        let snapshot_path = if let Some(snapshot_info) = SnapshotInfo::for_network(
-            &Network::from_str(&bench.network, true)
-                .map_err(|e| anyhow::anyhow!("{}", e))
-                .with_context(|| format!("Invalid network: {:?}", bench.network))?,
-        ) {
+        let snapshot_path = if let Some(snapshot_info) = SnapshotInfo::for_network(&bench.network) {
             self.global_config
                 .app
                 .snapshot_dir
@@ -185,36 +653,163 @@ This can be downloaded with `benchkit snapshot download {}`",
             self.global_config.app.snapshot_dir.clone() // Fallback
         };
 
+        // In loadblock mode blocks come from local files only, so don't connect to any peers
+        let datasets = &self.global_config.bench.global.datasets;
+        let dataset_dir = &self.global_config.app.dataset_dir;
+        let loadblock = bench
+            .loadblock
+            .iter()
+            .map(|path| {
+                expand_dataset_placeholders(path, datasets, dataset_dir)
+                    .map(PathBuf::from)
+                    .with_context(|| format!("Invalid loadblock path for benchmark {}", bench.name))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (connect, extra_args) = if mode == HookMode::LoadBlock {
+            let mut args: Vec<String> = loadblock
+                .iter()
+                .map(|path| format!("-loadblock={}", path.display()))
+                .collect();
+            args.push("-stopafterblockimport=1".to_string());
+            ("0".to_string(), args)
+        } else {
+            (bench.connect.clone().unwrap_or_default(), Vec::new())
+        };
+
         // Get command template
+        let expand_command = |cmd: &str| -> Result<String> {
+            Ok(crate::benchmarks::utils::build_benchmark_command(
+                &connect,
+                &extra_args,
+                &expand_dataset_placeholders(cmd, datasets, dataset_dir)
+                    .with_context(|| format!("Invalid command for benchmark {}", bench.name))?,
+            ))
+        };
         let command_template = match &options.command {
-            Some(cmd) => crate::benchmarks::utils::build_benchmark_command(
-                &self.global_config.app.bin_dir,
-                "{commit}",
-                &bench.network,
-                &self.global_config.bench.global.tmp_data_dir,
-                &bench.connect.clone().unwrap_or_default(),
-                cmd,
-            ),
+            Some(cmd) => expand_command(cmd)?,
             None => anyhow::bail!(
                 "No command template specified for benchmark: {}",
                 bench.name
             ),
         };
+        // Other nodes only get the network, ports and datadir, peers are up to their commands
+        let nodes = options
+            .nodes
+            .iter()
+            .flatten()
+            .map(|role| -> Result<NodeRole> {
+                let command = expand_dataset_placeholders(&role.command, datasets, dataset_dir)
+                    .with_context(|| {
+                        format!(
+                            "Invalid command of node role {} of {}",
+                            role.name, bench.name
+                        )
+                    })?;
+                Ok(NodeRole {
+                    command: crate::benchmarks::utils::build_benchmark_command("", &[], &command),
+                    ..role.clone()
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        check_ports(&command_template, &nodes, bench.network)
+            .with_context(|| format!("Invalid nodes of benchmark {}", bench.name))?;
+        let warmup = Warmup {
+            runs: options.warmup,
+            command: options
+                .warmup_command
+                .as_deref()
+                .map(expand_command)
+                .transpose()?,
+            hooks: options
+                .warmup_hooks
+                .clone()
+                .unwrap_or_else(|| vec![HookStage::Prepare, HookStage::Conclude]),
+        };
+
+        // Create benchmark runner with optional profiling
+        let benchmark_runner =
+            crate::benchmarks::benchmark_runner::BenchmarkRunner::builder(
+                self.out_dir.clone(),
+                hook_runner,
+            )
+            .capture_output(options.capture_output)
+            .parameter_lists(parameter_lists)
+            .profiling(options.profile.unwrap_or(false), options.profile_interval)
+            .benchmark_cores(self.global_config.bench.global.benchmark_cores.clone())
+            .stop_on_log_pattern(options.stop_on_log_pattern.clone())
+            .perf_instrumentation(options.perf_instrumentation.unwrap_or(false))
+            .perf_archive(options.perf_archive.unwrap_or(false))
+            .tracepoints(resolve_tracepoints(
+                options.tracepoints.as_deref().unwrap_or_default(),
+            )?)
+            .perf_attach(PerfAttach::from_options(
+                options.perf_delay_secs,
+                options.perf_attach_on_log_pattern.as_deref(),
+            )?)
+            .priority(options.priority.clone())
+            .quiet_gate(options.quiet_gate.clone())
+            .memory_limit(options.memory_limit.clone())
+            .cpu_limits(options.cpu_limits.clone())
+            .adaptive_runs(options.target_relative_error.map(|target_relative_error| {
+                AdaptiveRuns {
+                    target_relative_error,
+                    max_runs: options.max_runs.unwrap_or(DEFAULT_MAX_RUNS),
+                }
+            }))
+            .warmup(warmup)
+            .continue_on_failure(
+                options.continue_on_failure.unwrap_or(false),
+                options.skip_runs_after_failure.unwrap_or(false),
+            )
+            .fail_fast(
+                options.fail_fast.clone(),
+                self.historical_means(bench, &options),
+            )
+            .checkpoint(options.checkpoint.clone())
+            .disk_guard(options.disk_guard.clone())
+            .command_overrides(options.command_overrides.clone().unwrap_or_default())
+            .zmq_progress(options.zmq_progress.clone())
+            .node_logging(options.node_logging.clone())
+            .nodes(nodes)
+            .result_cache(self.result_cache(&options, bench)?)
+            .feeder(self.feeder_address(index, bench)?)
+            .core_dumps(options.core_dumps.unwrap_or(false))
+            .out_dir_template(options.out_dir_template.clone())
+            .commit_labels(self.global_config.bench.global.commit_labels.clone())
+            .env(bench.env.clone().unwrap_or_default())
+            .build()?;
 
         // Hooks are the various hyperfine-esque prepare/setup/conclude/cleanup scripts
         let hook_args = HookArgs {
+            benchmark: bench.name.clone(),
+            options: options.clone(),
             binary: format!(
-                "{}/bitcoind-{{commit}}",
-                self.global_config.app.bin_dir.display()
+                "{}/bitcoind-{}",
+                self.global_config.app.bin_dir.display(),
+                binary_placeholder
             ),
-            connect_address: bench.connect.clone().unwrap_or_default(),
-            network: bench.network.clone(),
+            connect_address: connect,
+            network: bench.network,
+            // Updated for each parameter combination
+            ports: NodePorts::from_command(&command_template, bench.network),
+            env: bench.env.clone().unwrap_or_default(),
             out_dir: self.out_dir.clone(),
+            run_out_dir: self.out_dir.clone(), // Set for each run
             snapshot_path,
             tmp_data_dir: self.global_config.bench.global.tmp_data_dir.clone(),
+            datadir: self.global_config.bench.global.tmp_data_dir.clone(), // Set for each run
             iteration: 0,
             commit: "{commit}".to_string(), // Will be replaced by parameter substitution
             params_dir: "default".to_string(), // Will be updated during parameter matrix expansion
+            loadblock,
+            header_cache: options.header_cache.unwrap_or(true).then(|| {
+                self.global_config
+                    .app
+                    .snapshot_dir
+                    .join("headers")
+                    .join(bench.network.chain_arg())
+            }),
+            params: HashMap::new(), // Set for each parameter combination
         };
 
         let results =
@@ -227,6 +822,6 @@ This can be downloaded with `benchkit snapshot download {}`",
         )?;
 
         info!("Benchmark {} completed successfully", bench.name);
-        Ok(())
+        Ok(results)
     }
 }
@@ -0,0 +1,332 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::{Ipv4Addr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Child;
+use std::time::{Duration, Instant};
+
+use crate::benchmarks::hook_runner::{HookArgs, NodePorts};
+use crate::command::CommandExecutor;
+use crate::process;
+use crate::types::Network;
+
+/// How often a starting node is checked for accepting connections
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A node running alongside the benchmarked one, e.g. a miner or a peer feeding it blocks
+///
+/// Roles are started before each run in dependency order and stopped after it in reverse order.
+/// Only the benchmarked node, the primary role, is timed.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeRole {
+    /// Name of the role, which its datadir and archived debug.log are named after
+    pub name: String,
+    /// Command running the node, with `bitcoind` expanded like in the benchmark's command and
+    /// `{datadir}` being the role's own datadir
+    pub command: String,
+    /// Roles started before this one and stopped after it
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    /// Seconds to wait for the node's P2P port to accept connections once started
+    #[serde(default = "default_ready_timeout")]
+    pub ready_timeout: u64,
+}
+
+fn default_ready_timeout() -> u64 {
+    60
+}
+
+impl NodeRole {
+    /// Datadir of the role next to `datadir`, the benchmarked node's datadir for the run
+    pub fn datadir(&self, datadir: &Path) -> PathBuf {
+        let mut name = datadir.file_name().unwrap_or_default().to_os_string();
+        name.push(format!("-{}", self.name));
+        datadir.with_file_name(name)
+    }
+}
+
+/// Check the roles and order them so that each starts after the roles it depends on, keeping
+/// the configured order otherwise
+pub fn start_order(roles: &[NodeRole]) -> Result<Vec<&NodeRole>> {
+    for (i, role) in roles.iter().enumerate() {
+        if role.name.is_empty()
+            || !role
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            anyhow::bail!(
+                "Invalid node role name '{}': use only letters, digits, '-' and '_'",
+                role.name
+            );
+        }
+        if roles[..i].iter().any(|other| other.name == role.name) {
+            anyhow::bail!("Duplicate node role name: {}", role.name);
+        }
+        if role.ready_timeout == 0 {
+            anyhow::bail!("Node role {} ready_timeout cannot be zero", role.name);
+        }
+        if let Some(unknown) = role
+            .depends_on
+            .iter()
+            .find(|dependency| !roles.iter().any(|other| other.name == **dependency))
+        {
+            anyhow::bail!("Node role {} depends on unknown role {unknown}", role.name);
+        }
+    }
+
+    let mut ordered: Vec<&NodeRole> = Vec::with_capacity(roles.len());
+    while ordered.len() < roles.len() {
+        let started = |name: &String| ordered.iter().any(|role| role.name == *name);
+        let next = roles
+            .iter()
+            .find(|role| !started(&role.name) && role.depends_on.iter().all(started));
+        match next {
+            Some(role) => ordered.push(role),
+            None => {
+                let waiting: Vec<&str> = roles
+                    .iter()
+                    .filter(|role| !started(&role.name))
+                    .map(|role| role.name.as_str())
+                    .collect();
+                anyhow::bail!(
+                    "Node roles {} depend on each other in a cycle",
+                    waiting.join(", ")
+                );
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// Fail if two nodes of a benchmark would listen on the same P2P or RPC port
+pub fn check_ports(primary: &str, roles: &[NodeRole], network: Network) -> Result<()> {
+    let mut nodes = vec![(
+        "the benchmarked node",
+        NodePorts::from_command(primary, network),
+    )];
+    for role in roles {
+        nodes.push((
+            role.name.as_str(),
+            NodePorts::from_command(&role.command, network),
+        ));
+    }
+    for (i, (name, ports)) in nodes.iter().enumerate() {
+        for (other, other_ports) in &nodes[..i] {
+            let used = [ports.p2p, ports.rpc];
+            if let Some(port) = [other_ports.p2p, other_ports.rpc]
+                .into_iter()
+                .find(|port| used.contains(port))
+            {
+                anyhow::bail!(
+                    "Node role {name} and {other} both use port {port}, set -port and -rpcport \
+                     in the role's command"
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Nodes of the roles running for a run, stopped in reverse start order
+#[derive(Default)]
+pub struct RunningNodes {
+    nodes: Vec<RunningNode>,
+}
+
+struct RunningNode {
+    name: String,
+    child: Child,
+    args: HookArgs,
+}
+
+impl RunningNodes {
+    /// Start a role's node on a fresh datadir and wait until it accepts P2P connections
+    ///
+    /// `args` are those of the run with the role's datadir, and `command` is fully resolved.
+    pub fn start(&mut self, role: &NodeRole, command: &str, args: HookArgs) -> Result<()> {
+        if args.datadir.exists() {
+            std::fs::remove_dir_all(&args.datadir).with_context(|| {
+                format!("Failed to clear the datadir {}", args.datadir.display())
+            })?;
+        }
+        std::fs::create_dir_all(&args.datadir)?;
+
+        info!("Starting node role {}: {command}", role.name);
+        let child = CommandExecutor::builder()
+            .name(format!("node role {}", role.name))
+            .env_vars(args.process_env())
+            .process_group(true)
+            .build()?
+            .launch_shell(command)?;
+        let port = NodePorts::from_command(command, args.network).p2p;
+        self.nodes.push(RunningNode {
+            name: role.name.clone(),
+            child,
+            args,
+        });
+        let node = self.nodes.last_mut().unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(role.ready_timeout);
+        loop {
+            if let Some(status) = node.child.try_wait()? {
+                anyhow::bail!(
+                    "Node role {} exited with {status} before accepting connections",
+                    role.name
+                );
+            }
+            if TcpStream::connect((Ipv4Addr::LOCALHOST, port)).is_ok() {
+                debug!("Node role {} is accepting connections on {port}", role.name);
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!(
+                    "Node role {} didn't accept connections on port {port} within {}s",
+                    role.name,
+                    role.ready_timeout
+                );
+            }
+            std::thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+
+    /// Stop the nodes, keeping each one's debug.log as `<role>-debug.log` in `archive_dir`, and
+    /// remove their datadirs
+    pub fn stop(mut self, archive_dir: Option<&Path>) -> Result<()> {
+        while let Some(mut node) = self.nodes.pop() {
+            let status = process::terminate(&mut node.child, process::GRACE_PERIOD)?;
+            debug!("Node role {} exited with {status}", node.name);
+            let debug_log = node.args.network_datadir().join("debug.log");
+            if let (Some(dir), true) = (archive_dir, debug_log.exists()) {
+                std::fs::create_dir_all(dir)?;
+                std::fs::copy(&debug_log, dir.join(format!("{}-debug.log", node.name)))
+                    .with_context(|| format!("Failed to keep {}", debug_log.display()))?;
+            }
+            std::fs::remove_dir_all(&node.args.datadir).with_context(|| {
+                format!(
+                    "Failed to remove the datadir {}",
+                    node.args.datadir.display()
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RunningNodes {
+    fn drop(&mut self) {
+        while let Some(mut node) = self.nodes.pop() {
+            if let Err(e) = process::terminate(&mut node.child, process::GRACE_PERIOD) {
+                warn!("Failed to stop node role {}: {e:#}", node.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    fn role(name: &str, command: &str, depends_on: &[&str]) -> NodeRole {
+        NodeRole {
+            name: name.to_string(),
+            command: command.to_string(),
+            depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+            ready_timeout: 5,
+        }
+    }
+
+    #[test]
+    fn test_start_order() {
+        let roles = [
+            role("validator", "bitcoind", &["feeder", "miner"]),
+            role("feeder", "bitcoind", &["miner"]),
+            role("miner", "bitcoind", &[]),
+        ];
+        let names: Vec<&str> = start_order(&roles)
+            .unwrap()
+            .iter()
+            .map(|role| role.name.as_str())
+            .collect();
+        assert_eq!(names, ["miner", "feeder", "validator"]);
+
+        let cycle = [role("a", "", &["b"]), role("b", "", &["a"])];
+        let err = start_order(&cycle).unwrap_err().to_string();
+        assert!(err.contains("a, b depend on each other"), "{err}");
+        assert!(start_order(&[role("a", "", &["c"])]).is_err());
+        assert!(start_order(&[role("a", "", &[]), role("a", "", &[])]).is_err());
+        assert!(start_order(&[role("a b", "", &[])]).is_err());
+
+        assert_eq!(
+            roles[1].datadir(Path::new("/tmp/data/abc/default/0")),
+            Path::new("/tmp/data/abc/default/0-feeder")
+        );
+
+        let primary = "bitcoind -port=18444 -rpcport=18443";
+        assert!(check_ports(
+            primary,
+            &[role("feeder", "bitcoind -port=18500 -rpcport=18501", &[])],
+            Network::Regtest
+        )
+        .is_ok());
+        assert!(check_ports(
+            primary,
+            &[role("feeder", "bitcoind -port=18500", &[])],
+            Network::Regtest
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_running_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let args = HookArgs {
+            benchmark: "relay".to_string(),
+            options: Default::default(),
+            binary: "bitcoind".to_string(),
+            connect_address: String::new(),
+            network: Network::Regtest,
+            ports: NodePorts::from_command("bitcoind", Network::Regtest),
+            env: Default::default(),
+            out_dir: dir.path().join("out"),
+            run_out_dir: dir.path().join("out/abc/default/0"),
+            snapshot_path: PathBuf::new(),
+            tmp_data_dir: dir.path().to_path_buf(),
+            datadir: dir.path().join("0-feeder"),
+            iteration: 0,
+            commit: "abc".to_string(),
+            params_dir: "default".to_string(),
+            loadblock: Vec::new(),
+            header_cache: None,
+            params: Default::default(),
+        };
+
+        // The node is ready once its port accepts connections
+        let mut nodes = RunningNodes::default();
+        let feeder = role("feeder", "", &[]);
+        nodes
+            .start(&feeder, &format!("sleep 30 # -port={port}"), args.clone())
+            .unwrap();
+        assert!(args.datadir.is_dir());
+        std::fs::create_dir_all(args.network_datadir()).unwrap();
+        std::fs::write(args.network_datadir().join("debug.log"), "started").unwrap();
+        let archive = dir.path().join("out");
+        nodes.stop(Some(&archive)).unwrap();
+        assert!(archive.join("feeder-debug.log").exists());
+        assert!(!args.datadir.exists());
+
+        // A node exiting before it is ready fails the run
+        drop(listener);
+        let mut nodes = RunningNodes::default();
+        let err = nodes
+            .start(&feeder, &format!("true # -port={port}"), args)
+            .unwrap_err();
+        assert!(err.to_string().contains("exited"), "{err}");
+    }
+}
@@ -0,0 +1,438 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::types::DurationMs;
+
+/// How long bpftrace gets to compile its script and attach to the probes
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Line bpftrace prints once every probe is attached
+const ATTACHED_MARKER: &str = "benchkit:attached";
+
+/// A USDT tracepoint of bitcoind and how its arguments are recorded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tracepoint {
+    pub provider: &'static str,
+    pub name: &'static str,
+    /// Indices of the integer arguments written to the event stream. Pointer arguments (hashes
+    /// and strings) are left out.
+    args: &'static [usize],
+    /// Position in `args` of the argument holding the event's duration, and the number of
+    /// those units in a millisecond
+    duration: Option<(usize, f64)>,
+}
+
+impl Tracepoint {
+    const fn new(provider: &'static str, name: &'static str, args: &'static [usize]) -> Self {
+        Self {
+            provider,
+            name,
+            args,
+            duration: None,
+        }
+    }
+
+    const fn timed(self, position: usize, units_per_ms: f64) -> Self {
+        Self {
+            duration: Some((position, units_per_ms)),
+            ..self
+        }
+    }
+
+    /// `provider:name`, as tracepoints are configured and keyed in results
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.provider, self.name)
+    }
+}
+
+/// The tracepoints of Bitcoin Core (doc/tracing.md) that can be collected
+pub const TRACEPOINTS: &[Tracepoint] = &[
+    // height, transactions, inputs, sigops, duration in ns
+    Tracepoint::new("validation", "block_connected", &[1, 2, 3, 4, 5]).timed(4, 1e6),
+    // duration in µs, mode, coins, memory usage, for prune
+    Tracepoint::new("utxocache", "flush", &[0, 1, 2, 3, 4]).timed(0, 1e3),
+    // output index, height, value, coinbase
+    Tracepoint::new("utxocache", "add", &[1, 2, 3, 4]),
+    Tracepoint::new("utxocache", "spent", &[1, 2, 3, 4]),
+    Tracepoint::new("utxocache", "uncache", &[1, 2, 3, 4]),
+    // vsize, fee
+    Tracepoint::new("mempool", "added", &[1, 2]),
+    // vsize, fee, entry time
+    Tracepoint::new("mempool", "removed", &[2, 3, 4]),
+    // replaced vsize, fee and entry time, replacement vsize and fee
+    Tracepoint::new("mempool", "replaced", &[1, 2, 3, 5, 6]),
+    Tracepoint::new("mempool", "rejected", &[]),
+    // target, waste, selected value
+    Tracepoint::new("coin_selection", "selected_coins", &[2, 3, 4]),
+    // success, fee, change position
+    Tracepoint::new("coin_selection", "normal_create_tx_internal", &[1, 2, 3]),
+    Tracepoint::new("coin_selection", "attempting_aps_create_tx", &[]),
+    // use aps, success, fee, change position
+    Tracepoint::new("coin_selection", "aps_create_tx_internal", &[1, 2, 3, 4]),
+    // peer id, message size
+    Tracepoint::new("net", "inbound_message", &[0, 4]),
+    Tracepoint::new("net", "outbound_message", &[0, 4]),
+];
+
+/// Resolve configured tracepoints, each `provider:name` or a whole `provider`
+pub fn resolve_tracepoints(names: &[String]) -> Result<Vec<Tracepoint>> {
+    let mut resolved: Vec<Tracepoint> = Vec::new();
+    for name in names {
+        let matching: Vec<&Tracepoint> = TRACEPOINTS
+            .iter()
+            .filter(|tp| match name.split_once(':') {
+                Some((provider, probe)) => tp.provider == provider && tp.name == probe,
+                None => tp.provider == name,
+            })
+            .collect();
+        if matching.is_empty() {
+            let known: Vec<String> = TRACEPOINTS.iter().map(Tracepoint::id).collect();
+            anyhow::bail!(
+                "Unknown tracepoint '{name}', known tracepoints are: {}",
+                known.join(", ")
+            );
+        }
+        for tp in matching {
+            if !resolved.contains(tp) {
+                resolved.push(*tp);
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// How often a tracepoint fired during a run, and the time spent in the events it reports
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TracepointSummary {
+    pub count: u64,
+    /// Total duration of the events, for tracepoints which report one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<DurationMs>,
+}
+
+/// Fail unless bpftrace, which collects the tracepoints, is installed
+pub fn validate_bpftrace_available() -> Result<()> {
+    if cfg!(not(target_os = "linux")) {
+        anyhow::bail!("tracepoints are only supported on Linux");
+    }
+    Command::new("bpftrace")
+        .arg("--version")
+        .output()
+        .context("bpftrace not found, it is needed to collect tracepoints")?;
+    Ok(())
+}
+
+/// The USDT probes compiled into a binary, as `provider:name`, read from its stapsdt ELF notes
+pub fn probes_in_binary(binary: &Path) -> Result<Vec<String>> {
+    let output = Command::new("readelf")
+        .args(["--notes", "--wide"])
+        .arg(binary)
+        .output()
+        .context("readelf not found, it is needed to find the tracepoints of bitcoind")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "readelf failed on {}: {}",
+            binary.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(parse_stapsdt_notes(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+/// Probes described by the `Provider:` and `Name:` lines of `readelf --notes` output
+fn parse_stapsdt_notes(notes: &str) -> Vec<String> {
+    let mut probes = Vec::new();
+    let mut provider = None;
+    for line in notes.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("Provider:") {
+            provider = Some(value.trim());
+        } else if let (Some(name), Some(provider)) = (line.strip_prefix("Name:"), provider.take()) {
+            let probe = format!("{provider}:{}", name.trim());
+            if !probes.contains(&probe) {
+                probes.push(probe);
+            }
+        }
+    }
+    probes
+}
+
+/// Fail unless the binary was built with every one of the tracepoints, rather than letting
+/// runs collect nothing
+pub fn check_probes(binary: &Path, tracepoints: &[Tracepoint]) -> Result<()> {
+    let probes = probes_in_binary(binary)?;
+    if probes.is_empty() {
+        anyhow::bail!(
+            "{} has no USDT tracepoints, build bitcoind with -DWITH_USDT=ON \
+             (--enable-usdt for autotools builds) with systemtap's sys/sdt.h installed",
+            binary.display()
+        );
+    }
+    let missing = missing_probes(&probes, tracepoints);
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "{} lacks the tracepoints {}, this commit may predate them or need the patch \
+             adding them",
+            binary.display(),
+            missing.join(", ")
+        );
+    }
+    debug!(
+        "{} has all {} tracepoints",
+        binary.display(),
+        tracepoints.len()
+    );
+    Ok(())
+}
+
+/// Ids of the tracepoints which aren't among the probes of a binary
+fn missing_probes(probes: &[String], tracepoints: &[Tracepoint]) -> Vec<String> {
+    tracepoints
+        .iter()
+        .map(Tracepoint::id)
+        .filter(|id| !probes.contains(id))
+        .collect()
+}
+
+/// bpftrace program printing `<tracepoint> <ns timestamp> <args...>` for each event
+fn bpftrace_script(binary: &Path, tracepoints: &[Tracepoint]) -> String {
+    let mut script = format!("BEGIN {{ printf(\"{ATTACHED_MARKER}\\n\"); }}\n");
+    for tp in tracepoints {
+        let formats: String = tp.args.iter().map(|_| " %ld").collect();
+        let args: String = tp.args.iter().map(|i| format!(", (int64)arg{i}")).collect();
+        script.push_str(&format!(
+            "usdt:{}:{}:{} {{ printf(\"{} %lu{formats}\\n\", nsecs{args}); }}\n",
+            binary.display(),
+            tp.provider,
+            tp.name,
+            tp.id(),
+        ));
+    }
+    script
+}
+
+/// Collects the tracepoints of a binary with bpftrace while a run executes
+///
+/// Every process running the binary is traced, which during a run is only the benchmarked node.
+pub struct UsdtTracer {
+    child: Child,
+    reader: Option<thread::JoinHandle<Vec<String>>>,
+    tracepoints: Vec<Tracepoint>,
+    out_dir: PathBuf,
+}
+
+impl UsdtTracer {
+    /// Start bpftrace and wait until it is attached, writing event streams to `out_dir` later
+    pub fn start(binary: &Path, tracepoints: &[Tracepoint], out_dir: &Path) -> Result<Self> {
+        let script = bpftrace_script(binary, tracepoints);
+        debug!("Starting bpftrace with:\n{script}");
+        let mut child = Command::new("bpftrace")
+            .arg("-e")
+            .arg(&script)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to start bpftrace")?;
+
+        let stdout = child.stdout.take().context("bpftrace has no stdout")?;
+        let (attached, attached_rx) = mpsc::channel();
+        let reader = thread::spawn(move || {
+            let mut events = Vec::new();
+            for line in BufReader::new(stdout).lines().map_while(|line| line.ok()) {
+                if line == ATTACHED_MARKER {
+                    let _ = attached.send(());
+                } else {
+                    events.push(line);
+                }
+            }
+            events
+        });
+
+        // The reader drops the sender when bpftrace exits, e.g. because a probe is missing
+        if attached_rx.recv_timeout(ATTACH_TIMEOUT).is_err() {
+            let _ = child.kill();
+            let mut stderr = String::new();
+            if let Some(mut pipe) = child.stderr.take() {
+                let _ = pipe.read_to_string(&mut stderr);
+            }
+            let _ = child.wait();
+            anyhow::bail!(
+                "bpftrace failed to attach to tracepoints: {}",
+                stderr.trim()
+            );
+        }
+        info!("Tracing {} tracepoints", tracepoints.len());
+
+        Ok(Self {
+            child,
+            reader: Some(reader),
+            tracepoints: tracepoints.to_vec(),
+            out_dir: out_dir.to_path_buf(),
+        })
+    }
+
+    /// Stop tracing, write each tracepoint's events to `<out_dir>/<provider>_<name>.log`, and
+    /// summarize them
+    pub fn finish(mut self) -> Result<BTreeMap<String, TracepointSummary>> {
+        crate::process::interrupt(&mut self.child)?;
+        let status = self.child.wait().context("Failed to wait for bpftrace")?;
+        debug!("bpftrace exited with {status}");
+        let events = self
+            .reader
+            .take()
+            .map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default();
+
+        crate::path_utils::ensure_directory(&self.out_dir)?;
+        let mut summaries = BTreeMap::new();
+        for tp in &self.tracepoints {
+            let id = tp.id();
+            let lines: Vec<&str> = events
+                .iter()
+                .filter_map(|line| line.strip_prefix(&id)?.strip_prefix(' '))
+                .collect();
+            let path = self
+                .out_dir
+                .join(format!("{}_{}.log", tp.provider, tp.name));
+            let mut contents = lines.join("\n");
+            if !contents.is_empty() {
+                contents.push('\n');
+            }
+            std::fs::write(&path, contents)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            summaries.insert(id, summarize(tp, &lines));
+        }
+        Ok(summaries)
+    }
+}
+
+impl Drop for UsdtTracer {
+    /// Don't leave bpftrace running when a run fails before the tracer is finished
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Count the events of a tracepoint and add up their durations
+fn summarize(tp: &Tracepoint, lines: &[&str]) -> TracepointSummary {
+    let duration_ms = tp.duration.map(|(position, units_per_ms)| {
+        lines
+            .iter()
+            .filter_map(|line| {
+                // The timestamp comes before the arguments
+                let value: f64 = line.split_whitespace().nth(position + 1)?.parse().ok()?;
+                Some(DurationMs(value / units_per_ms))
+            })
+            .sum()
+    });
+    if tp.duration.is_some() && lines.is_empty() {
+        warn!("Tracepoint {} never fired", tp.id());
+    }
+    TracepointSummary {
+        count: lines.len() as u64,
+        duration_ms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_tracepoints() {
+        let resolved = resolve_tracepoints(&[
+            "utxocache:flush".to_string(),
+            "mempool".to_string(),
+            "utxocache:flush".to_string(),
+        ])
+        .unwrap();
+        let ids: Vec<String> = resolved.iter().map(Tracepoint::id).collect();
+        assert_eq!(
+            ids,
+            [
+                "utxocache:flush",
+                "mempool:added",
+                "mempool:removed",
+                "mempool:replaced",
+                "mempool:rejected"
+            ]
+        );
+        let err = resolve_tracepoints(&["utxocache:flushed".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("validation:block_connected"));
+    }
+
+    #[test]
+    fn test_parse_stapsdt_notes() {
+        let notes = "
+Displaying notes found in: .note.gnu.build-id
+  Owner                Data size \tDescription
+  GNU                  0x00000014\tNT_GNU_BUILD_ID (unique build ID bitstring)\t    Build ID: 1f2e
+Displaying notes found in: .note.stapsdt
+  Owner                Data size \tDescription
+  stapsdt              0x00000052\tNT_STAPSDT (SystemTap probe descriptors)
+    Provider: utxocache
+    Name: flush
+    Location: 0x00000000004d1f2c, Base: 0x0000000000b2b6c8, Semaphore: 0x0000000000000000
+    Arguments: -8@%rax 4@%ecx -8@%rdx 8@%rbx 1@%sil
+  stapsdt              0x00000040\tNT_STAPSDT (SystemTap probe descriptors)
+    Provider: net
+    Name: inbound_message
+    Location: 0x00000000004e2f10, Base: 0x0000000000b2b6c8, Semaphore: 0x0000000000000000
+  stapsdt              0x00000040\tNT_STAPSDT (SystemTap probe descriptors)
+    Provider: net
+    Name: inbound_message
+    Location: 0x00000000004e3a04, Base: 0x0000000000b2b6c8, Semaphore: 0x0000000000000000
+";
+        let probes = parse_stapsdt_notes(notes);
+        assert_eq!(probes, ["utxocache:flush", "net:inbound_message"]);
+        assert!(parse_stapsdt_notes("Displaying notes found in: .note.ABI-tag").is_empty());
+
+        let tracepoints =
+            resolve_tracepoints(&["utxocache:flush".to_string(), "net".to_string()]).unwrap();
+        assert_eq!(
+            missing_probes(&probes, &tracepoints),
+            ["net:outbound_message"]
+        );
+    }
+
+    #[test]
+    fn test_bpftrace_script_and_summary() {
+        let tracepoints = resolve_tracepoints(&[
+            "utxocache:flush".to_string(),
+            "mempool:rejected".to_string(),
+        ])
+        .unwrap();
+        let script = bpftrace_script(Path::new("/bin/bitcoind"), &tracepoints);
+        assert!(script.contains(
+            "usdt:/bin/bitcoind:utxocache:flush { printf(\"utxocache:flush %lu %ld %ld %ld %ld %ld\\n\", \
+             nsecs, (int64)arg0, (int64)arg1, (int64)arg2, (int64)arg3, (int64)arg4); }"
+        ));
+        assert!(script.contains("printf(\"mempool:rejected %lu\\n\", nsecs); }"));
+
+        // Two flushes, of 1.5ms and 250ms
+        let summary = summarize(
+            &tracepoints[0],
+            &["100 1500 2 5000 900000 0", "200 250000 1 6000 950000 0"],
+        );
+        assert_eq!(
+            summary,
+            TracepointSummary {
+                count: 2,
+                duration_ms: Some(DurationMs(251.5))
+            }
+        );
+        assert_eq!(summarize(&tracepoints[1], &["100"]).duration_ms, None);
+    }
+}
@@ -1,33 +1,58 @@
+use log::warn;
+use regex::Regex;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+use crate::benchmarks::metadata::BinaryMetadata;
+use crate::types::Network;
+
 /// Default port for Bitcoin Core P2P connections
 pub const DEFAULT_P2P_PORT: u16 = 12000;
 
 /// Default port for Bitcoin Core RPC connections
 pub const DEFAULT_RPC_PORT: u16 = 12001;
 
-/// Check if a binary exists for a given commit
+/// Get the identifier used to name a binary built from a commit and optional build variant
+pub fn binary_id(commit: &str, variant: Option<&str>) -> String {
+    match variant {
+        Some(variant) => format!("{commit}-{variant}"),
+        None => commit.to_string(),
+    }
+}
+
+/// Check if a binary exists for a given commit and matches its recorded sha256
 pub fn binary_exists(bin_dir: &Path, commit: &str) -> bool {
     let binary_path = get_binary_path(bin_dir, commit);
-    binary_path.exists()
+    binary_path.exists() && binary_matches_metadata(&binary_path)
+}
+
+/// Check a binary against its sidecar metadata, treating unreadable metadata as a mismatch
+fn binary_matches_metadata(binary_path: &Path) -> bool {
+    match BinaryMetadata::verify_binary(binary_path) {
+        Ok(matches) => matches,
+        Err(e) => {
+            warn!("Failed to verify {}: {e}", binary_path.display());
+            false
+        }
+    }
 }
 
 /// Get the full path to a binary for a given commit
 pub fn get_binary_path(bin_dir: &Path, commit: &str) -> PathBuf {
-    bin_dir.join(format!("bitcoind-{commit}"))
+    bin_dir.join(format!("bitcoind-{commit}{}", std::env::consts::EXE_SUFFIX))
 }
 
 /// Check if all required binaries exist and return missing ones
 pub fn check_binaries_exist(
     bin_dir: &Path,
-    commits: &[String],
+    binary_ids: &[String],
 ) -> Result<(), Vec<(String, PathBuf)>> {
     let mut missing_binaries = Vec::new();
 
-    for commit in commits {
-        let binary_path = get_binary_path(bin_dir, commit);
-        if !binary_path.exists() {
-            missing_binaries.push((commit.clone(), binary_path));
+    for id in binary_ids {
+        let binary_path = get_binary_path(bin_dir, id);
+        if !binary_path.exists() || !binary_matches_metadata(&binary_path) {
+            missing_binaries.push((id.clone(), binary_path));
         }
     }
 
@@ -39,9 +64,9 @@ pub fn check_binaries_exist(
 }
 
 /// Build base bitcoind command arguments that are common across all invocations
-pub fn build_bitcoind_base_args(network: &str, datadir: &Path, connect: &str) -> Vec<String> {
+pub fn build_bitcoind_base_args(network: Network, datadir: &Path, connect: &str) -> Vec<String> {
     let mut args = vec![
-        format!("-chain={}", network),
+        format!("-chain={}", network.chain_arg()),
         format!("-port={}", DEFAULT_P2P_PORT),
         format!("-rpcport={}", DEFAULT_RPC_PORT),
         format!("-datadir={}", datadir.display()),
@@ -54,17 +79,108 @@ pub fn build_bitcoind_base_args(network: &str, datadir: &Path, connect: &str) ->
     args
 }
 
-/// Build the full benchmark command with parameter substitution
+/// Expand `bitcoind` in a command template to the benchmarked binary and its base arguments
+///
+/// The binary, network, datadir and connect address are written as built-in placeholders,
+/// which the runner resolves for each parameter combination and run.
 pub fn build_benchmark_command(
-    bin_dir: &Path,
-    commit_placeholder: &str,
-    network: &str,
-    datadir: &Path,
     connect: &str,
+    extra_args: &[String],
     command_template: &str,
 ) -> String {
-    let bitcoind_path = format!("{}/bitcoind-{}", bin_dir.display(), commit_placeholder);
-    let base_args = build_bitcoind_base_args(network, datadir, connect);
-    let base_args_str = base_args.join(" ");
-    command_template.replace("bitcoind", &format!("{} {}", bitcoind_path, base_args_str))
+    let mut args = vec![
+        "{binary}".to_string(),
+        "-chain={network}".to_string(),
+        format!("-port={}", DEFAULT_P2P_PORT),
+        format!("-rpcport={}", DEFAULT_RPC_PORT),
+        "-datadir={datadir}".to_string(),
+    ];
+    if !connect.is_empty() {
+        args.push("-connect={connect}".to_string());
+    }
+    args.extend_from_slice(extra_args);
+    command_template.replace("bitcoind", &args.join(" "))
+}
+
+/// The settings a node ran with, from the arguments it logs to debug.log at startup
+///
+/// Command-line arguments take precedence over settings.json, which takes precedence over the
+/// config file, as in bitcoind. Arguments given several times (e.g. `-loadblock`) are joined
+/// with commas.
+pub fn parse_node_settings(debug_log: &str) -> BTreeMap<String, String> {
+    let arg =
+        Regex::new(r#"(Command-line|Setting file|Config file) arg: (?:\[\w+\] )?([^=\s]+)=(.*)$"#)
+            .unwrap();
+    let mut settings: BTreeMap<String, (u8, String)> = BTreeMap::new();
+    for line in debug_log.lines() {
+        let Some(caps) = arg.captures(line) else {
+            continue;
+        };
+        let precedence = match &caps[1] {
+            "Command-line" => 3,
+            "Setting file" => 2,
+            _ => 1,
+        };
+        let value = caps[3].trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value)
+            .to_string();
+        match settings.get_mut(&caps[2]) {
+            Some((existing, values)) if *existing == precedence => {
+                values.push(',');
+                values.push_str(&value);
+            }
+            Some((existing, _)) if *existing > precedence => {}
+            _ => {
+                settings.insert(caps[2].to_string(), (precedence, value));
+            }
+        }
+    }
+    settings
+        .into_iter()
+        .map(|(name, (_, value))| (name, value))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_benchmark_command() {
+        assert_eq!(
+            build_benchmark_command("127.0.0.1:38333", &[], "taskset -c 1 bitcoind -dbcache=450"),
+            "taskset -c 1 {binary} -chain={network} -port=12000 -rpcport=12001 \
+             -datadir={datadir} -connect={connect} -dbcache=450"
+        );
+        assert_eq!(
+            build_benchmark_command("", &["-stopafterblockimport=1".to_string()], "bitcoind"),
+            "{binary} -chain={network} -port=12000 -rpcport=12001 -datadir={datadir} \
+             -stopafterblockimport=1"
+        );
+    }
+
+    #[test]
+    fn test_parse_node_settings() {
+        let log = r#"2025-01-31T12:00:00Z Bitcoin Core version v28.1.0 (release build)
+2025-01-31T12:00:00Z Config file arg: [main] dbcache="100"
+2025-01-31T12:00:00Z Config file arg: prune="550"
+2025-01-31T12:00:00Z Command-line arg: dbcache="4500"
+2025-01-31T12:00:00Z Command-line arg: loadblock="/blocks/blk00000.dat"
+2025-01-31T12:00:00Z Command-line arg: loadblock="/blocks/blk00001.dat"
+2025-01-31T12:00:00Z Command-line arg: rpcpassword=****
+2025-01-31T12:00:01Z UpdateTip: new best=00000000839a height=1
+"#;
+        let settings = parse_node_settings(log);
+        assert_eq!(settings["dbcache"], "4500");
+        assert_eq!(settings["prune"], "550");
+        assert_eq!(
+            settings["loadblock"],
+            "/blocks/blk00000.dat,/blocks/blk00001.dat"
+        );
+        assert_eq!(settings["rpcpassword"], "****");
+        assert_eq!(settings.len(), 4);
+    }
 }
@@ -0,0 +1,262 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde_json::json;
+use std::net::{Ipv4Addr, TcpListener};
+use std::path::Path;
+use std::process::Child;
+use std::time::{Duration, Instant};
+
+use crate::benchmarks::repository::resolve_built_commits;
+use crate::benchmarks::{binary_id, get_binary_path};
+use crate::command::CommandExecutor;
+use crate::config::GlobalConfig;
+use crate::process;
+use crate::rpc::{RpcClient, RpcError, RPC_IN_WARMUP};
+use crate::telemetry;
+
+/// How often a starting node is polled for its RPC server
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lines of debug.log shown when a smoke test fails
+const LOG_TAIL_LINES: usize = 10;
+
+/// Outcome of smoke testing one binary
+#[derive(Debug)]
+pub struct SmokeTest {
+    /// Binary id, `<commit>` or `<commit>-<variant>`
+    pub id: String,
+    pub duration: Duration,
+    /// Why the binary failed, None if it passed
+    pub error: Option<String>,
+}
+
+impl SmokeTest {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Smoke test the binaries of `commits`, or of every configured commit if none are given
+///
+/// Each binary is started on a fresh regtest datadir, mines a block over RPC and must shut down
+/// cleanly within `timeout`, so broken builds are caught before a long run.
+pub fn verify_commits(
+    config: &GlobalConfig,
+    commits: &[String],
+    timeout: Duration,
+) -> Result<Vec<SmokeTest>> {
+    let global = &config.bench.global;
+    let commits = if commits.is_empty() {
+        &global.commits
+    } else {
+        commits
+    };
+    // Binaries are named by short commit hash, so resolve commits the same way the builder did
+    let commits = resolve_built_commits(global, commits)?;
+
+    let mut results = Vec::new();
+    for commit in &commits {
+        for variant in global.variant_names() {
+            let id = binary_id(commit, variant.as_deref());
+            let binary = get_binary_path(&config.app.bin_dir, &id);
+            info!("Smoke testing {id}");
+            let start = Instant::now();
+            let mut span = telemetry::span("verify");
+            span.attr("benchkit.binary_id", &id);
+            let result = span.record(smoke_test(&binary, timeout));
+            results.push(SmokeTest {
+                id,
+                duration: start.elapsed(),
+                error: result.err().map(|e| format!("{e:#}")),
+            });
+        }
+    }
+    Ok(results)
+}
+
+/// Start a binary on regtest, mine a block and stop it, failing on any misbehaviour
+pub fn smoke_test(binary: &Path, timeout: Duration) -> Result<()> {
+    if !binary.is_file() {
+        anyhow::bail!("{} is not built", binary.display());
+    }
+    let datadir = tempfile::tempdir().context("Failed to create a datadir")?;
+    let network_datadir = datadir.path().join("regtest");
+    let (p2p_port, rpc_port) = (free_port()?, free_port()?);
+    let args = [
+        "-regtest".to_string(),
+        format!("-datadir={}", datadir.path().display()),
+        format!("-port={p2p_port}"),
+        format!("-rpcport={rpc_port}"),
+        "-bind=127.0.0.1".to_string(),
+        "-rpcbind=127.0.0.1".to_string(),
+        "-rpcallowip=127.0.0.1".to_string(),
+        "-server=1".to_string(),
+        "-listen=0".to_string(),
+        "-printtoconsole=0".to_string(),
+    ];
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let child = CommandExecutor::builder()
+        .name("smoke test")
+        .capture_output(false)
+        .process_group(true)
+        .build()?
+        .launch_command(&binary.to_string_lossy(), &args)?;
+    let mut node = Node { child: Some(child) };
+
+    let deadline = Instant::now() + timeout;
+    let result = node
+        .exercise(&network_datadir, rpc_port, deadline)
+        .and_then(|()| node.wait(deadline));
+    result.map_err(|e| match log_tail(&network_datadir.join("debug.log")) {
+        Some(tail) => e.context(format!("debug.log ends with:\n{tail}")),
+        None => e,
+    })
+}
+
+/// A node under test, killed if the test fails before it stopped
+struct Node {
+    child: Option<Child>,
+}
+
+impl Node {
+    /// Wait for the RPC server, mine a block and ask the node to stop
+    fn exercise(&mut self, network_datadir: &Path, rpc_port: u16, deadline: Instant) -> Result<()> {
+        let rpc = self.wait_for_rpc(network_datadir, rpc_port, deadline)?;
+        let descriptor = rpc.call("getdescriptorinfo", json!(["raw(51)"]))?;
+        let descriptor = descriptor["descriptor"]
+            .as_str()
+            .context("getdescriptorinfo returned no descriptor")?;
+        rpc.call("generatetodescriptor", json!([1, descriptor]))?;
+        let height = rpc.call("getblockcount", json!([]))?;
+        if height != 1 {
+            anyhow::bail!("Mined a block, but the height is {height} instead of 1");
+        }
+        debug!("Mined a block, stopping the node");
+        rpc.call("stop", json!([]))?;
+        Ok(())
+    }
+
+    /// An RPC client once the node has written its cookie and finished warming up
+    fn wait_for_rpc(
+        &mut self,
+        network_datadir: &Path,
+        rpc_port: u16,
+        deadline: Instant,
+    ) -> Result<RpcClient> {
+        let cookie = network_datadir.join(".cookie");
+        loop {
+            self.check_running()?;
+            if cookie.exists() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let rpc = RpcClient::from_cookie(rpc_port, &cookie, remaining)?;
+                match rpc.call("getblockcount", json!([])) {
+                    Ok(_) => return Ok(rpc),
+                    Err(e) => match e.downcast_ref::<RpcError>() {
+                        Some(error) if error.code == RPC_IN_WARMUP => {}
+                        Some(_) => return Err(e),
+                        None => debug!("RPC not ready yet: {e:#}"),
+                    },
+                }
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("The RPC server didn't become ready in time");
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn check_running(&mut self) -> Result<()> {
+        if let Some(status) = self
+            .child
+            .as_mut()
+            .map(Child::try_wait)
+            .transpose()?
+            .flatten()
+        {
+            self.child = None;
+            anyhow::bail!("The node exited with {status} while starting");
+        }
+        Ok(())
+    }
+
+    /// Wait for the node to shut down cleanly
+    fn wait(&mut self, deadline: Instant) -> Result<()> {
+        let Some(child) = self.child.as_mut() else {
+            return Ok(());
+        };
+        loop {
+            if let Some(status) = child.try_wait()? {
+                self.child = None;
+                if !status.success() {
+                    anyhow::bail!("The node shut down with {status}");
+                }
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                anyhow::bail!("The node didn't shut down in time");
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for Node {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = process::terminate(&mut child, Duration::from_secs(5));
+        }
+    }
+}
+
+/// An unused local port
+fn free_port() -> Result<u16> {
+    Ok(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+        .local_addr()?
+        .port())
+}
+
+/// The last lines of a log, if it exists
+fn log_tail(path: &Path) -> Option<String> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let lines: Vec<&str> = contents.lines().collect();
+    Some(lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].join("\n"))
+}
+
+// The fake nodes are shell scripts
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_smoke_test_failures() {
+        let temp_dir = tempdir().unwrap();
+        let binary = temp_dir.path().join("bitcoind-abc");
+        let err = smoke_test(&binary, Duration::from_secs(5)).unwrap_err();
+        assert!(err.to_string().contains("is not built"), "{err}");
+
+        // A node crashing on startup fails with the end of its debug.log
+        std::fs::write(
+            &binary,
+            "#!/bin/sh\nfor arg; do case $arg in -datadir=*) dir=${arg#-datadir=};; esac; done\n\
+             mkdir -p $dir/regtest\necho 'Error: Corrupted block database' > $dir/regtest/debug.log\n\
+             exit 1\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let err = smoke_test(&binary, Duration::from_secs(5)).unwrap_err();
+        let err = format!("{err:#}");
+        assert!(err.contains("exited with"), "{err}");
+        assert!(err.contains("Corrupted block database"), "{err}");
+
+        // A node that never serves RPC times out and is killed
+        std::fs::write(&binary, "#!/bin/sh\nexec sleep 60\n").unwrap();
+        let start = Instant::now();
+        let err = smoke_test(&binary, Duration::from_millis(500)).unwrap_err();
+        assert!(err.to_string().contains("didn't become ready"), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        assert!(log_tail(&temp_dir.path().join("missing")).is_none());
+    }
+}
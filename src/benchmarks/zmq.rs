@@ -0,0 +1,362 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Topic bitcoind publishes block connections and disconnections on
+const SEQUENCE_TOPIC: &[u8] = b"sequence";
+
+/// How long to wait between attempts to connect to the node, which binds its socket at startup
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// ZMTP frame flags
+const FLAG_MORE: u8 = 0x01;
+const FLAG_LONG: u8 = 0x02;
+const FLAG_COMMAND: u8 = 0x04;
+
+/// Longest frame accepted, well above the few dozen bytes of a `sequence` notification, so a
+/// corrupt length can't make the listener allocate without bound
+const MAX_FRAME_LEN: u64 = 4096;
+
+/// Block progress of the node from its ZMQ `sequence` notifications, which is cheaper and more
+/// robust than matching its log
+///
+/// Each run's command gets `-zmqpubsequence` on a free local port, so bitcoind must be built with
+/// ZMQ.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ZmqProgress {
+    /// Stop the node gracefully once it connected this many blocks (Unix only)
+    pub stop_after_blocks: Option<u64>,
+}
+
+impl ZmqProgress {
+    pub fn validate(&self) -> Result<()> {
+        if self.stop_after_blocks == Some(0) {
+            anyhow::bail!("zmq_progress stop_after_blocks cannot be zero");
+        }
+        if self.stop_after_blocks.is_some() && cfg!(not(unix)) {
+            anyhow::bail!("zmq_progress stop_after_blocks is only supported on Unix");
+        }
+        Ok(())
+    }
+
+    /// Free local address for the node to publish on
+    pub fn address() -> Result<SocketAddr> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .context("Failed to find a free port for ZMQ notifications")?;
+        Ok(listener.local_addr()?)
+    }
+
+    /// The command with the node publishing its `sequence` notifications to `address`
+    pub fn command(command: &str, address: SocketAddr) -> String {
+        format!("{command} -zmqpubsequence=tcp://{address}")
+    }
+
+    /// Count the blocks the node publishing to `address` connects on another thread, across
+    /// restarts, terminating the process group in `group` once `stop_after_blocks` is reached
+    ///
+    /// `group` holds the pid of the group leader currently running, 0 while there is none.
+    pub fn listen(&self, address: SocketAddr, group: Arc<AtomicU32>) -> ZmqListener {
+        let stop_after_blocks = self.stop_after_blocks;
+        let stop = Arc::new(AtomicBool::new(false));
+        let socket = Arc::new(Mutex::new(None::<TcpStream>));
+        let blocks = Arc::new(AtomicU64::new(0));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let socket = Arc::clone(&socket);
+            let blocks = Arc::clone(&blocks);
+            thread::spawn(move || {
+                let mut stopped = false;
+                let mut on_block = |connected: bool| {
+                    let count = if connected {
+                        blocks.fetch_add(1, Ordering::SeqCst) + 1
+                    } else {
+                        blocks
+                            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                                Some(count.saturating_sub(1))
+                            })
+                            .unwrap_or_default()
+                            .saturating_sub(1)
+                    };
+                    if stopped || stop_after_blocks.is_none_or(|target| count < target) {
+                        return;
+                    }
+                    stopped = true;
+                    info!("Node connected {count} blocks, stopping it");
+                    #[cfg(unix)]
+                    if let Err(e) = crate::process::terminate_group(
+                        group.load(Ordering::SeqCst),
+                        crate::process::GRACE_PERIOD,
+                    ) {
+                        warn!("Failed to stop the node: {e:#}");
+                    }
+                };
+                while !stop.load(Ordering::SeqCst) {
+                    let Ok(mut stream) = TcpStream::connect(address) else {
+                        thread::sleep(CONNECT_RETRY_INTERVAL);
+                        continue;
+                    };
+                    *socket.lock().unwrap() = stream.try_clone().ok();
+                    // Checked after storing the stream, so `finish` can always shut it down
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    debug!("Subscribed to ZMQ notifications on {address}");
+                    if let Err(e) = follow_sequence(&mut stream, &mut on_block) {
+                        debug!("ZMQ connection to {address} closed: {e}");
+                    }
+                }
+            })
+        };
+        ZmqListener {
+            stop,
+            socket,
+            blocks,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Block counting running alongside a command, which stops when it is dropped
+pub struct ZmqListener {
+    stop: Arc<AtomicBool>,
+    socket: Arc<Mutex<Option<TcpStream>>>,
+    blocks: Arc<AtomicU64>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl ZmqListener {
+    /// Blocks connected less blocks disconnected so far
+    pub fn blocks(&self) -> u64 {
+        self.blocks.load(Ordering::SeqCst)
+    }
+
+    /// Stop listening and return the number of blocks connected
+    pub fn finish(mut self) -> u64 {
+        self.disconnect();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                debug!("ZMQ listener thread panicked");
+            }
+        }
+        self.blocks()
+    }
+
+    fn disconnect(&self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(socket) = self.socket.lock().unwrap().take() {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Drop for ZmqListener {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
+
+/// Subscribe to the `sequence` topic and call `on_block` with whether each block was connected or
+/// disconnected, until the connection closes
+fn follow_sequence(stream: &mut TcpStream, on_block: &mut impl FnMut(bool)) -> io::Result<()> {
+    handshake(stream, b"SUB")?;
+    // ZMTP 3.0 subscriptions are messages starting with 1
+    write_frame(stream, 0, &[b"\x01", SEQUENCE_TOPIC].concat())?;
+    loop {
+        // The body is the block hash, a label and, for mempool events, a mempool sequence
+        let message = read_message(stream)?;
+        if let [topic, body, ..] = message.as_slice() {
+            if topic == SEQUENCE_TOPIC && body.len() > 32 {
+                match body[32] {
+                    b'C' => on_block(true),
+                    b'D' => on_block(false),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// ZMTP 3.0 greeting for the NULL security mechanism
+fn greeting() -> [u8; 64] {
+    let mut greeting = [0; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+/// Exchange greetings with the peer and announce the socket type
+fn handshake(stream: &mut (impl Read + Write), socket_type: &[u8]) -> io::Result<()> {
+    stream.write_all(&greeting())?;
+    let mut ready = b"\x05READY\x0bSocket-Type".to_vec();
+    ready.extend((socket_type.len() as u32).to_be_bytes());
+    ready.extend(socket_type);
+    write_frame(stream, FLAG_COMMAND, &ready)?;
+
+    let mut peer = [0; 64];
+    stream.read_exact(&mut peer)?;
+    if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "peer doesn't speak ZMTP 3",
+        ));
+    }
+    Ok(())
+}
+
+fn write_frame(stream: &mut impl Write, flags: u8, body: &[u8]) -> io::Result<()> {
+    match u8::try_from(body.len()) {
+        Ok(len) => stream.write_all(&[flags, len])?,
+        Err(_) => {
+            stream.write_all(&[flags | FLAG_LONG])?;
+            stream.write_all(&(body.len() as u64).to_be_bytes())?;
+        }
+    }
+    stream.write_all(body)
+}
+
+/// Read the next frame, returning its flags and body
+fn read_frame(stream: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+    let mut flags = [0; 1];
+    stream.read_exact(&mut flags)?;
+    let len = if flags[0] & FLAG_LONG != 0 {
+        let mut len = [0; 8];
+        stream.read_exact(&mut len)?;
+        let len = u64::from_be_bytes(len);
+        if len > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("frame of {len} bytes is longer than {MAX_FRAME_LEN}"),
+            ));
+        }
+        len as usize
+    } else {
+        let mut len = [0; 1];
+        stream.read_exact(&mut len)?;
+        len[0] as usize
+    };
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body)?;
+    Ok((flags[0], body))
+}
+
+/// Read the frames of the next message, skipping commands
+fn read_message(stream: &mut impl Read) -> io::Result<Vec<Vec<u8>>> {
+    let mut frames = Vec::new();
+    loop {
+        let (flags, body) = read_frame(stream)?;
+        if flags & FLAG_COMMAND != 0 {
+            continue;
+        }
+        frames.push(body);
+        if flags & FLAG_MORE == 0 {
+            return Ok(frames);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    /// Accept one subscriber and publish `labels` as `sequence` notifications
+    fn publish(listener: TcpListener, labels: &'static [u8]) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            handshake(&mut stream, b"PUB").unwrap();
+            let subscription = read_message(&mut stream).unwrap();
+            assert_eq!(subscription, [b"\x01sequence".to_vec()]);
+            for (sequence, &label) in labels.iter().enumerate() {
+                let mut body = vec![0xab; 32];
+                body.push(label);
+                write_frame(&mut stream, FLAG_MORE, SEQUENCE_TOPIC).unwrap();
+                write_frame(&mut stream, FLAG_MORE, &body).unwrap();
+                write_frame(&mut stream, 0, &(sequence as u32).to_le_bytes()).unwrap();
+            }
+        })
+    }
+
+    fn wait_for(listener: &ZmqListener, blocks: u64) {
+        let start = Instant::now();
+        while listener.blocks() != blocks && start.elapsed() < Duration::from_secs(10) {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_zmq_progress() {
+        let progress = ZmqProgress::default();
+        assert!(progress.validate().is_ok());
+        let address = ZmqProgress::address().unwrap();
+        assert_eq!(
+            ZmqProgress::command("bitcoind", address),
+            format!(
+                "bitcoind -zmqpubsequence=tcp://127.0.0.1:{}",
+                address.port()
+            )
+        );
+
+        // Mempool events are ignored and disconnected blocks subtracted
+        let node = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = node.local_addr().unwrap();
+        let listener = progress.listen(address, Arc::new(AtomicU32::new(0)));
+        publish(node, b"CCACDC").join().unwrap();
+        wait_for(&listener, 3);
+        assert_eq!(listener.finish(), 3);
+
+        assert!(ZmqProgress {
+            stop_after_blocks: Some(0)
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, FLAG_MORE, &[1; 300]).unwrap();
+        let (flags, body) = read_frame(&mut frame.as_slice()).unwrap();
+        assert_eq!((flags, body.len()), (FLAG_MORE | FLAG_LONG, 300));
+
+        // A length beyond the cap is rejected before anything is allocated
+        let mut frame = vec![FLAG_LONG];
+        frame.extend(u64::MAX.to_be_bytes());
+        let err = read_frame(&mut frame.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_zmq_stop_after_blocks() {
+        use std::os::unix::process::CommandExt;
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let node = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let progress = ZmqProgress {
+            stop_after_blocks: Some(2),
+        };
+        let listener = progress.listen(
+            node.local_addr().unwrap(),
+            Arc::new(AtomicU32::new(child.id())),
+        );
+        let publisher = publish(node, b"CCC");
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        publisher.join().unwrap();
+        wait_for(&listener, 3);
+        assert_eq!(listener.finish(), 3);
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Root of the cgroup v2 hierarchy
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// A cgroup v2 group benchmark commands are moved into to cap their memory or CPU bandwidth
+///
+/// The group is removed when dropped.
+#[derive(Debug)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Create a group for this benchkit process with the controllers, e.g. "memory" and "cpu",
+    /// enabled (needs root)
+    pub fn create(controllers: &[&str]) -> Result<Self> {
+        let path = Path::new(CGROUP_ROOT).join(format!("benchkit-{}", std::process::id()));
+        fs::create_dir_all(&path).with_context(|| {
+            format!(
+                "Failed to create cgroup {}, enforcing memory_limit or cpu_limits requires root \
+                 and cgroup v2",
+                path.display()
+            )
+        })?;
+        let cgroup = Self { path };
+        for controller in controllers {
+            if !cgroup.path.join(format!("{controller}.max")).exists() {
+                anyhow::bail!(
+                    "The {controller} controller is not enabled for {}, \
+                     add it with `echo +{controller} > {CGROUP_ROOT}/cgroup.subtree_control`",
+                    cgroup.path.display()
+                );
+            }
+        }
+        info!("Created cgroup {}", cgroup.path.display());
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Cap the memory of the group, without letting it spill into swap
+    pub fn set_memory_limit_mb(&self, limit_mb: u64) -> Result<()> {
+        let bytes = (limit_mb * 1024 * 1024).to_string();
+        fs::write(self.path.join("memory.max"), &bytes)
+            .with_context(|| format!("Failed to set memory.max of {}", self.path.display()))?;
+        // Without swap accounting the file is missing and swapping is only detected afterwards
+        let swap_max = self.path.join("memory.swap.max");
+        if swap_max.exists() {
+            fs::write(&swap_max, "0").with_context(|| {
+                format!("Failed to set memory.swap.max of {}", self.path.display())
+            })?;
+        }
+        debug!("Limited cgroup {} to {limit_mb} MiB", self.path.display());
+        Ok(())
+    }
+
+    /// Set the group's `cpu.max`, e.g. "400000 100000" for four CPUs' worth of time or "max"
+    pub fn set_cpu_max(&self, cpu_max: &str) -> Result<()> {
+        fs::write(self.path.join("cpu.max"), cpu_max)
+            .with_context(|| format!("Failed to set cpu.max of {}", self.path.display()))?;
+        debug!("Set cpu.max of cgroup {} to {cpu_max}", self.path.display());
+        Ok(())
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            warn!("Failed to remove cgroup {}: {e}", self.path.display());
+        }
+    }
+}
@@ -1,15 +1,246 @@
 use anyhow::{Context, Result};
-#[cfg(target_os = "linux")]
-use log::warn;
-use log::{debug, info};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt::Debug;
+#[cfg(unix)]
 use std::os::unix::process::CommandExt;
-use std::path::Path;
+#[cfg(windows)]
+use std::os::windows::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::Mutex;
 
 #[cfg(target_os = "linux")]
 use crate::cpu_binding::CpuBinder;
+use crate::error::BenchkitError;
+
+/// Shell and flag used to run command lines
+#[cfg(not(windows))]
+pub const SHELL: [&str; 2] = ["sh", "-c"];
+/// Shell and flag used to run command lines
+#[cfg(windows)]
+pub const SHELL: [&str; 2] = ["cmd", "/C"];
+
+/// Windows process creation flag giving the child its own process group
+#[cfg(windows)]
+const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+
+/// Construct an ExitStatus for a process which exited with `code`
+pub fn exit_status_from_code(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        // A raw wait status stores the exit code in the second byte
+        ExitStatus::from_raw((code & 0xff) << 8)
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// I/O scheduling class, as set by ionice(1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+/// I/O scheduling class and level, written as e.g. "idle", "best-effort:4" or "realtime:0"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IoPriority {
+    pub class: IoClass,
+    /// Level within the class, from 0 (highest) to 7 (lowest). Ignored for the idle class.
+    pub level: u8,
+}
+
+impl std::str::FromStr for IoPriority {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (
+                class,
+                level
+                    .parse()
+                    .with_context(|| format!("Invalid ionice level: {s}"))?,
+            ),
+            None => (s, 4),
+        };
+        let class = match class {
+            "realtime" => IoClass::Realtime,
+            "best-effort" => IoClass::BestEffort,
+            "idle" => IoClass::Idle,
+            _ => anyhow::bail!(
+                "Invalid ionice class '{}': use realtime, best-effort or idle",
+                class
+            ),
+        };
+        if level > 7 {
+            anyhow::bail!("ionice level must be between 0 and 7: {}", s);
+        }
+        Ok(Self { class, level })
+    }
+}
+
+impl TryFrom<String> for IoPriority {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<IoPriority> for String {
+    fn from(priority: IoPriority) -> Self {
+        match priority.class {
+            IoClass::Realtime => format!("realtime:{}", priority.level),
+            IoClass::BestEffort => format!("best-effort:{}", priority.level),
+            IoClass::Idle => "idle".to_string(),
+        }
+    }
+}
+
+/// Scheduling and OOM killer priority of a process (Linux only)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessPriority {
+    /// Niceness, from -20 (highest priority) to 19 (lowest)
+    pub nice: Option<i32>,
+    /// I/O scheduling class and level
+    pub ionice: Option<IoPriority>,
+    /// OOM killer score adjustment, from -1000 (never kill) to 1000 (kill first)
+    pub oom_score_adj: Option<i32>,
+}
+
+impl ProcessPriority {
+    /// Check the values are within the ranges accepted by the kernel
+    pub fn validate(&self) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                anyhow::bail!("nice must be between -20 and 19: {}", nice);
+            }
+        }
+        if let Some(oom_score_adj) = self.oom_score_adj {
+            if !(-1000..=1000).contains(&oom_score_adj) {
+                anyhow::bail!(
+                    "oom_score_adj must be between -1000 and 1000: {}",
+                    oom_score_adj
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the priority to the current process
+    pub fn apply_to_current_process(&self) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            info!("Setting benchkit process priority: {self:?}");
+            self.prepare().apply().context(
+                "Failed to set process priority (lowering nice or oom_score_adj requires root)",
+            )?;
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            warn!("Process priority is only supported on Linux, ignoring: {self:?}");
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn prepare(&self) -> PreparedPriority {
+        PreparedPriority {
+            nice: self.nice,
+            ioprio: self.ionice.map(|ionice| {
+                let (class, level) = match ionice.class {
+                    IoClass::Realtime => (1, ionice.level),
+                    IoClass::BestEffort => (2, ionice.level),
+                    IoClass::Idle => (3, 0),
+                };
+                (class << 13) | libc::c_int::from(level)
+            }),
+            oom_score_adj: self
+                .oom_score_adj
+                .map(|value| format!("{value}\n").into_bytes()),
+        }
+    }
+}
+
+/// A priority converted to system call arguments ahead of time
+///
+/// Applying it doesn't allocate, so it is safe to run between fork and exec.
+#[cfg(target_os = "linux")]
+struct PreparedPriority {
+    nice: Option<i32>,
+    ioprio: Option<libc::c_int>,
+    oom_score_adj: Option<Vec<u8>>,
+}
+
+#[cfg(target_os = "linux")]
+impl PreparedPriority {
+    /// Apply to the calling process
+    fn apply(&self) -> std::io::Result<()> {
+        use std::io::Error;
+
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        if let Some(ioprio) = self.ioprio {
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+                return Err(Error::last_os_error());
+            }
+        }
+        if let Some(value) = &self.oom_score_adj {
+            write_file(c"/proc/self/oom_score_adj", value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write to a file without allocating, so it is safe to run between fork and exec
+#[cfg(target_os = "linux")]
+fn write_file(path: &std::ffi::CStr, value: &[u8]) -> std::io::Result<()> {
+    use std::io::Error;
+
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY) };
+    if fd < 0 {
+        return Err(Error::last_os_error());
+    }
+    let written = unsafe { libc::write(fd, value.as_ptr().cast(), value.len()) };
+    let error = Error::last_os_error();
+    unsafe { libc::close(fd) };
+    if written < 0 {
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Raise the soft core file size limit of the calling process to its hard limit
+#[cfg(unix)]
+fn raise_core_limit() -> std::io::Result<()> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    limit.rlim_cur = limit.rlim_max;
+    if unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
 
 /// Command execution context
 #[derive(Debug, Clone, Default)]
@@ -28,6 +259,13 @@ pub struct CommandContext {
     pub capture_output: bool,
     /// Allow command to fail without returning an error
     pub allow_failure: bool,
+    /// Scheduling and OOM killer priority of the command
+    pub priority: Option<ProcessPriority>,
+    /// cgroup v2 directory to start the command in (Linux only)
+    pub cgroup: Option<PathBuf>,
+    /// Raise the command's core file size limit to its hard limit, so it dumps core on a crash
+    /// (Unix only)
+    pub core_dumps: bool,
 }
 
 /// Builder for CommandExecutor
@@ -91,6 +329,24 @@ impl CommandExecutorBuilder {
         self
     }
 
+    /// Set the scheduling and OOM killer priority of the command
+    pub fn priority(mut self, priority: Option<ProcessPriority>) -> Self {
+        self.context.priority = priority;
+        self
+    }
+
+    /// Start the command in a cgroup v2 group, so its children are accounted to it too
+    pub fn cgroup(mut self, cgroup: Option<PathBuf>) -> Self {
+        self.context.cgroup = cgroup;
+        self
+    }
+
+    /// Let the command dump core when it crashes
+    pub fn core_dumps(mut self, enable: bool) -> Self {
+        self.context.core_dumps = enable;
+        self
+    }
+
     /// Set a name for the command for logging purposes
     pub fn name(mut self, name: impl Into<String>) -> Self {
         self.context.command_name = Some(name.into());
@@ -159,7 +415,12 @@ impl CommandExecutor {
 
     /// Execute a shell command line and wait for it to complete, returning the output
     pub fn execute_shell(&self, cmd_line: &str) -> Result<Output> {
-        self.execute_command_with_args("sh", &["-c", cmd_line])
+        self.execute_command_with_args(SHELL[0], &[SHELL[1], cmd_line])
+    }
+
+    /// Launch a shell command line, returning the child process handle
+    pub fn launch_shell(&self, cmd_line: &str) -> Result<Child> {
+        self.launch_command(SHELL[0], &[SHELL[1], cmd_line])
     }
 
     /// Execute a command with arguments and wait for it to complete, returning the output
@@ -174,13 +435,12 @@ impl CommandExecutor {
         })?;
 
         if !output.status.success() && !self.context.allow_failure {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!(
-                "Command failed with status {}: {}\nStderr: {}",
-                output.status.code().unwrap_or(-1),
-                self.format_command(cmd, args),
-                stderr
-            ));
+            return Err(BenchkitError::Command {
+                command: self.format_command(cmd, args),
+                exit_code: output.status.code().unwrap_or(-1),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+            }
+            .into());
         }
 
         Ok(output)
@@ -213,13 +473,65 @@ impl CommandExecutor {
 
         // Create process group if requested
         if self.context.process_group {
+            #[cfg(unix)]
             command.process_group(0);
+            #[cfg(windows)]
+            command.creation_flags(CREATE_NEW_PROCESS_GROUP);
+        }
+
+        if let Some(priority) = &self.context.priority {
+            #[cfg(target_os = "linux")]
+            {
+                let prepared = priority.prepare();
+                // Safety: PreparedPriority::apply only makes async-signal-safe system calls
+                unsafe {
+                    command.pre_exec(move || prepared.apply());
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            debug!("Process priority is only supported on Linux, ignoring: {priority:?}");
+        }
+
+        if let Some(cgroup) = &self.context.cgroup {
+            #[cfg(target_os = "linux")]
+            {
+                use std::os::unix::ffi::OsStrExt;
+
+                // Writing 0 moves the writing process, before it execs the command
+                let procs = std::ffi::CString::new(
+                    cgroup.join("cgroup.procs").as_os_str().as_bytes().to_vec(),
+                )?;
+                // Safety: write_file only makes async-signal-safe system calls
+                unsafe {
+                    command.pre_exec(move || write_file(&procs, b"0"));
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            debug!(
+                "cgroups are only supported on Linux, ignoring: {}",
+                cgroup.display()
+            );
+        }
+
+        if self.context.core_dumps {
+            #[cfg(unix)]
+            // Safety: raise_core_limit only makes async-signal-safe system calls
+            unsafe {
+                command.pre_exec(raise_core_limit);
+            }
         }
 
         // Spawn the command
-        let child = command
-            .spawn()
-            .with_context(|| format!("Failed to spawn command: {command_str}"))?;
+        let child = command.spawn().with_context(|| {
+            if self.context.priority.is_some() {
+                format!(
+                    "Failed to spawn command: {command_str} \
+                     (lowering nice or oom_score_adj requires root)"
+                )
+            } else {
+                format!("Failed to spawn command: {command_str}")
+            }
+        })?;
 
         // Apply CPU affinity if specified
         if let Some(cores) = &self.context.cpu_cores {
@@ -320,7 +632,123 @@ impl CommandExecutor {
             env_vars: options.env_vars.clone(),
             allow_failure: options.allow_failure,
             command_name: options.command_name.clone(),
+            priority: None,
+            cgroup: None,
+            core_dumps: false,
+        }
+    }
+}
+
+/// Runs processes to completion
+///
+/// Code which starts processes through this can be unit tested with `MockProcessRunner`, without
+/// spawning anything.
+pub trait ProcessRunner: Send + Sync {
+    /// Run a program in a context and wait for it, failing on a non-zero exit status unless the
+    /// context allows failure
+    fn run(&self, program: &str, args: &[&str], context: &CommandContext) -> Result<Output>;
+}
+
+/// Runs processes with `CommandExecutor`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemProcessRunner;
+
+impl ProcessRunner for SystemProcessRunner {
+    fn run(&self, program: &str, args: &[&str], context: &CommandContext) -> Result<Output> {
+        CommandExecutor::with_context(context.clone()).execute_command_with_args(program, args)
+    }
+}
+
+/// A process a `MockProcessRunner` was asked to run
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockCall {
+    pub program: String,
+    pub args: Vec<String>,
+    pub working_dir: Option<String>,
+    pub env_vars: HashMap<String, String>,
+}
+
+impl MockCall {
+    /// The program and its arguments, separated by spaces
+    pub fn command_line(&self) -> String {
+        std::iter::once(self.program.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Records the processes it is asked to run instead of running them
+///
+/// Every process succeeds with no output, unless a response was registered for a prefix of its
+/// command line. The most recently registered matching response is used.
+#[derive(Debug, Default)]
+pub struct MockProcessRunner {
+    responses: Vec<(String, i32, String)>,
+    calls: Mutex<Vec<MockCall>>,
+}
+
+impl MockProcessRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Exit with `exit_code` and print `stdout` for command lines starting with `prefix`
+    pub fn respond(
+        mut self,
+        prefix: impl Into<String>,
+        exit_code: i32,
+        stdout: impl Into<String>,
+    ) -> Self {
+        self.responses
+            .push((prefix.into(), exit_code, stdout.into()));
+        self
+    }
+
+    /// Every process run so far, in order
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Command lines of every process run so far, in order
+    pub fn command_lines(&self) -> Vec<String> {
+        self.calls().iter().map(MockCall::command_line).collect()
+    }
+}
+
+impl ProcessRunner for MockProcessRunner {
+    fn run(&self, program: &str, args: &[&str], context: &CommandContext) -> Result<Output> {
+        let call = MockCall {
+            program: program.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            working_dir: context.working_dir.clone(),
+            env_vars: context.env_vars.clone(),
+        };
+        let command_line = call.command_line();
+        debug!("Mock running: {command_line}");
+        self.calls.lock().unwrap().push(call);
+
+        let (exit_code, stdout) = self
+            .responses
+            .iter()
+            .rev()
+            .find(|(prefix, _, _)| command_line.starts_with(prefix.as_str()))
+            .map_or((0, ""), |(_, exit_code, stdout)| {
+                (*exit_code, stdout.as_str())
+            });
+        if exit_code != 0 && !context.allow_failure {
+            return Err(BenchkitError::Command {
+                command: context.command_name.clone().unwrap_or(command_line),
+                exit_code,
+                stderr: String::new(),
+            }
+            .into());
         }
+        Ok(Output {
+            status: exit_status_from_code(exit_code),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        })
     }
 }
 
@@ -389,6 +817,70 @@ mod tests {
         assert!(executor.context.allow_failure);
     }
 
+    #[test]
+    fn test_io_priority() {
+        let priority: IoPriority = "best-effort:2".parse().unwrap();
+        assert_eq!(priority.class, IoClass::BestEffort);
+        assert_eq!(priority.level, 2);
+        assert_eq!(String::from(priority), "best-effort:2");
+        assert_eq!(String::from("idle".parse::<IoPriority>().unwrap()), "idle");
+        assert_eq!("realtime".parse::<IoPriority>().unwrap().level, 4);
+        assert!("best-effort:8".parse::<IoPriority>().is_err());
+        assert!("fast".parse::<IoPriority>().is_err());
+
+        let priority: ProcessPriority =
+            serde_yaml::from_str("{nice: 5, ionice: idle, oom_score_adj: 500}").unwrap();
+        assert_eq!(priority.nice, Some(5));
+        assert!(priority.validate().is_ok());
+        let invalid = ProcessPriority {
+            nice: Some(20),
+            ..ProcessPriority::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_execute_with_priority() {
+        // Lowering priority doesn't need privileges
+        let executor = CommandExecutor::builder()
+            .capture_output(true)
+            .priority(Some(ProcessPriority {
+                nice: Some(19),
+                ionice: Some("idle".parse().unwrap()),
+                oom_score_adj: Some(1000),
+            }))
+            .build()
+            .unwrap();
+
+        let output = executor
+            .execute_shell("cat /proc/self/oom_score_adj; cut -d' ' -f19 /proc/self/stat")
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(stdout.lines().collect::<Vec<_>>(), vec!["1000", "19"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_execute_with_core_dumps() {
+        let limit = |core_dumps| {
+            let output = CommandExecutor::builder()
+                .capture_output(true)
+                .core_dumps(core_dumps)
+                .build()
+                .unwrap()
+                .execute_shell("ulimit -c -H; ulimit -c")
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+            let lines: Vec<String> = stdout.lines().map(str::to_string).collect();
+            (lines[0].clone(), lines[1].clone())
+        };
+        // The soft limit is raised to the hard limit
+        let (hard, soft) = limit(true);
+        assert_eq!(soft, hard);
+        assert_eq!(limit(false).0, hard);
+    }
+
     #[test]
     fn test_execute_shell_success() {
         let executor = CommandExecutor::builder()
@@ -458,6 +950,41 @@ mod tests {
         assert!(!result.unwrap().status.success());
     }
 
+    #[test]
+    fn test_mock_process_runner() {
+        let runner = MockProcessRunner::new()
+            .respond("git", 0, "main\n")
+            .respond("git diff", 1, "");
+        let context = CommandContext {
+            working_dir: Some("/src".to_string()),
+            ..CommandContext::default()
+        };
+
+        let output = runner
+            .run("git", &["symbolic-ref", "HEAD"], &context)
+            .unwrap();
+        assert_eq!(output.stdout, b"main\n");
+        assert!(runner.run("git", &["diff", "--quiet"], &context).is_err());
+        let allowed = CommandContext {
+            allow_failure: true,
+            ..context.clone()
+        };
+        let output = runner.run("git", &["diff", "--quiet"], &allowed).unwrap();
+        assert_eq!(output.status.code(), Some(1));
+        assert!(runner.run("make", &[], &context).unwrap().status.success());
+
+        assert_eq!(
+            runner.command_lines(),
+            [
+                "git symbolic-ref HEAD",
+                "git diff --quiet",
+                "git diff --quiet",
+                "make"
+            ]
+        );
+        assert_eq!(runner.calls()[0].working_dir.as_deref(), Some("/src"));
+    }
+
     #[test]
     fn test_format_command() {
         // Test with command name
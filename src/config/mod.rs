@@ -1,11 +1,28 @@
 use anyhow::{Context, Result};
-use log::debug;
+use log::{debug, warn};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::{collections::HashMap, path::PathBuf};
-
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+};
+
+use crate::benchmarks::{
+    start_order, CheckpointConfig, CommandOverride, ExportConfig, FailFast, HookStage, NodeLogging,
+    NodeRole, ParameterUtils, Requirements, ZmqProgress, BUILTIN_PLACEHOLDERS,
+};
+use crate::command::ProcessPriority;
+use crate::cpu_limit::CpuLimits;
+use crate::dataset::DatasetConfig;
+use crate::disk_guard::DiskGuard;
+use crate::error::BenchkitError;
+use crate::memory::MemoryLimit;
 use crate::path_utils;
+use crate::quiescence::QuietGate;
+use crate::reference::{ReferenceBinary, REFERENCE_COMMIT};
+use crate::system_info::CoreAssignment;
+use crate::types::{CacheState, Network};
 
 /// Application configuration loaded from config.yml
 #[derive(Debug, Deserialize, Clone)]
@@ -14,6 +31,33 @@ pub struct AppConfig {
     pub home_dir: PathBuf,
     pub patch_dir: PathBuf,
     pub snapshot_dir: PathBuf,
+    /// Directory datasets are cached in, defaults to `<home_dir>/datasets`
+    #[serde(default)]
+    pub dataset_dir: PathBuf,
+    /// Hex-encoded Ed25519 public keys. When set, only jobs signed by one of these are accepted.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// Time windows or cron expressions jobs may start in (see `Schedule`), any time if empty
+    #[serde(default)]
+    pub schedule: Vec<String>,
+    /// Directory each job gets an isolated workspace in, defaults to `<home_dir>/jobs`
+    #[serde(default)]
+    pub job_dir: PathBuf,
+    /// How long job workspaces are kept
+    #[serde(default)]
+    pub job_retention: crate::job::JobRetention,
+    /// File holding a hex-encoded Ed25519 key to sign each run's results with
+    pub signing_key: Option<PathBuf>,
+    /// Record each benchmark's summary under `<home_dir>/history` and print the change since the
+    /// last run on this machine
+    #[serde(default)]
+    pub history: bool,
+    /// Reuse results of parameter combinations measured earlier on this machine with the same
+    /// binary and configuration, kept under `<home_dir>/result-cache`
+    #[serde(default)]
+    pub result_cache: bool,
+    /// Export traces of builds, benchmarks, hooks and runs to an OpenTelemetry collector
+    pub telemetry: Option<crate::telemetry::TelemetryConfig>,
     #[serde(default)]
     pub path: PathBuf,
 }
@@ -23,6 +67,10 @@ pub struct AppConfig {
 pub struct BenchmarkOptions {
     #[serde(default = "default_warmup")]
     pub warmup: usize,
+    /// Cheaper command for warmup runs (e.g. a lower `-stopatheight`), defaults to `command`
+    pub warmup_command: Option<String>,
+    /// Per-run hook stages to run around warmup runs, defaults to prepare and conclude
+    pub warmup_hooks: Option<Vec<HookStage>>,
     #[serde(default = "default_runs")]
     pub runs: usize,
     #[serde(default)]
@@ -33,8 +81,64 @@ pub struct BenchmarkOptions {
     pub profile_interval: Option<u64>,
     pub stop_on_log_pattern: Option<String>,
     pub perf_instrumentation: Option<bool>,
+    /// Bundle the symbols perf.data refers to after each perf-instrumented run
+    pub perf_archive: Option<bool>,
+    /// Attach perf this many seconds after the command started, to skip its startup
+    pub perf_delay_secs: Option<u64>,
+    /// Attach perf once this regex matches a line of the node's debug.log
+    pub perf_attach_on_log_pattern: Option<String>,
+    /// USDT tracepoints to collect with bpftrace in each run, as `provider:name` or `provider`
+    /// (Linux only)
+    pub tracepoints: Option<Vec<String>>,
+    /// nice, ionice and oom_score_adj of the benchmark command (Linux only)
+    pub priority: Option<ProcessPriority>,
+    /// Wait for the system to be quiet before each run (Linux only)
+    pub quiet_gate: Option<QuietGate>,
+    /// Keep adding runs past `runs` until the 95% confidence interval of the mean is within this
+    /// fraction of the mean
+    pub target_relative_error: Option<f64>,
+    /// Maximum number of runs when `target_relative_error` is set
+    pub max_runs: Option<usize>,
+    /// Page cache states to run each benchmark from, added as a `cache_state` parameter axis
+    pub cache_state: Option<Vec<CacheState>>,
+    /// Check the command's `-dbcache` fits in RAM, optionally capping it with a cgroup (Linux only)
+    pub memory_limit: Option<MemoryLimit>,
+    /// CPU bandwidth and `-par` scenarios modelling weaker hardware, added as `cpu_quota` and
+    /// `par` parameter axes
+    pub cpu_limits: Option<CpuLimits>,
+    /// Record failed runs and hooks and carry on with the rest of the parameter matrix
+    pub continue_on_failure: Option<bool>,
+    /// Skip the remaining runs of a parameter combination once one of its runs failed
+    pub skip_runs_after_failure: Option<bool>,
+    /// Let the node dump core when it crashes, collecting the core dump and its backtrace into
+    /// the run's output directory (Unix only)
+    pub core_dumps: Option<bool>,
+    /// Layout of the runs' output directories within the output directory, e.g.
+    /// `{benchmark}/{commit_short}-{dbcache}/{iteration}`
+    pub out_dir_template: Option<String>,
+    /// Skip the remaining runs of a parameter combination which is obviously broken
+    pub fail_fast: Option<FailFast>,
+    /// Sync headers once per network and copy them into each run's datadir in assumeutxo mode,
+    /// defaults to true
+    pub header_cache: Option<bool>,
+    /// Stop and restart the node at regular intervals, recording its progress so long runs can
+    /// be resumed with `run --resume`
+    pub checkpoint: Option<CheckpointConfig>,
+    /// Stop runs before the datadir or output directory filesystem fills up (Unix only)
+    pub disk_guard: Option<DiskGuard>,
+    /// Changes to the command for commits or versions which renamed or lack some of its flags
+    pub command_overrides: Option<Vec<CommandOverride>>,
+    /// Count the blocks the node connects from its ZMQ notifications, optionally stopping it
+    pub zmq_progress: Option<ZmqProgress>,
+    /// Debug categories and timestamps the node logs with, added to the command as flags
+    pub node_logging: Option<NodeLogging>,
+    /// Nodes started alongside the benchmarked one for each run, e.g. a peer feeding it blocks
+    pub nodes: Option<Vec<NodeRole>>,
 }
 
+/// Cap on the number of runs when `target_relative_error` is set without `max_runs`
+pub const DEFAULT_MAX_RUNS: usize = 30;
+
 fn default_warmup() -> usize {
     0
 }
@@ -53,6 +157,8 @@ impl BenchmarkOptions {
     pub fn new() -> Self {
         Self {
             warmup: default_warmup(),
+            warmup_command: None,
+            warmup_hooks: None,
             runs: default_runs(),
             capture_output: false,
             command: None,
@@ -61,6 +167,29 @@ impl BenchmarkOptions {
             profile_interval: None,
             stop_on_log_pattern: None,
             perf_instrumentation: None,
+            perf_archive: None,
+            perf_delay_secs: None,
+            perf_attach_on_log_pattern: None,
+            tracepoints: None,
+            priority: None,
+            quiet_gate: None,
+            target_relative_error: None,
+            max_runs: None,
+            cache_state: None,
+            memory_limit: None,
+            cpu_limits: None,
+            continue_on_failure: None,
+            skip_runs_after_failure: None,
+            core_dumps: None,
+            out_dir_template: None,
+            fail_fast: None,
+            header_cache: None,
+            checkpoint: None,
+            disk_guard: None,
+            command_overrides: None,
+            zmq_progress: None,
+            node_logging: None,
+            nodes: None,
         }
     }
 
@@ -89,6 +218,121 @@ impl BenchmarkOptions {
             }
         }
 
+        if let Some(pattern) = &self.perf_attach_on_log_pattern {
+            if let Err(e) = Regex::new(pattern) {
+                anyhow::bail!("Invalid regex pattern in perf_attach_on_log_pattern: {}", e);
+            }
+        }
+        if (self.perf_delay_secs.is_some() || self.perf_attach_on_log_pattern.is_some())
+            && self.perf_instrumentation != Some(true)
+        {
+            anyhow::bail!(
+                "perf_delay_secs and perf_attach_on_log_pattern require perf_instrumentation"
+            );
+        }
+
+        if let Some(tracepoints) = &self.tracepoints {
+            if cfg!(not(target_os = "linux")) {
+                anyhow::bail!("tracepoints are only supported on Linux");
+            }
+            crate::benchmarks::resolve_tracepoints(tracepoints)?;
+        }
+
+        if let Some(priority) = &self.priority {
+            priority.validate()?;
+        }
+
+        if let Some(quiet_gate) = &self.quiet_gate {
+            quiet_gate.validate()?;
+        }
+
+        if let Some(memory_limit) = &self.memory_limit {
+            memory_limit.validate()?;
+        }
+
+        if let Some(cpu_limits) = &self.cpu_limits {
+            cpu_limits.validate()?;
+        }
+
+        if let Some(disk_guard) = &self.disk_guard {
+            disk_guard.validate()?;
+        }
+
+        if let Some(zmq_progress) = &self.zmq_progress {
+            zmq_progress.validate()?;
+        }
+
+        if let Some(node_logging) = &self.node_logging {
+            node_logging.validate()?;
+        }
+
+        if let Some(nodes) = &self.nodes {
+            start_order(nodes)?;
+            // Resumed runs would find the datadirs of the other nodes gone
+            if self.checkpoint.is_some() {
+                anyhow::bail!("nodes cannot be combined with checkpoint");
+            }
+        }
+
+        if let Some(fail_fast) = &self.fail_fast {
+            fail_fast.validate()?;
+        }
+
+        for command_override in self.command_overrides.iter().flatten() {
+            command_override.validate()?;
+        }
+
+        if let Some(checkpoint) = &self.checkpoint {
+            checkpoint.validate()?;
+            if self.profile == Some(true)
+                || self.perf_instrumentation == Some(true)
+                || self.stop_on_log_pattern.is_some()
+            {
+                anyhow::bail!(
+                    "checkpoint cannot be combined with profile, perf_instrumentation or \
+                     stop_on_log_pattern"
+                );
+            }
+        }
+
+        if let Some(target) = self.target_relative_error {
+            if !(target > 0.0 && target < 1.0) {
+                anyhow::bail!("target_relative_error must be between 0 and 1, got {target}");
+            }
+        }
+        if let Some(max_runs) = self.max_runs {
+            if self.target_relative_error.is_none() {
+                anyhow::bail!("max_runs requires target_relative_error to be set");
+            }
+            if max_runs < self.runs {
+                anyhow::bail!(
+                    "max_runs ({max_runs}) cannot be less than runs ({})",
+                    self.runs
+                );
+            }
+        }
+
+        if let Some(stages) = &self.warmup_hooks {
+            if let Some(stage) = stages
+                .iter()
+                .find(|stage| !matches!(stage, HookStage::Prepare | HookStage::Conclude))
+            {
+                anyhow::bail!(
+                    "warmup_hooks can only contain prepare and conclude, \
+                     {stage:?} runs once per benchmark"
+                );
+            }
+        }
+
+        if let Some(states) = &self.cache_state {
+            if states.is_empty() {
+                anyhow::bail!("cache_state cannot be empty");
+            }
+            if states.contains(&CacheState::Cold) && cfg!(not(target_os = "linux")) {
+                anyhow::bail!("cache_state cold is only supported on Linux");
+            }
+        }
+
         Ok(())
     }
 
@@ -107,6 +351,15 @@ impl BenchmarkOptions {
             result.warmup = warmup as usize;
         }
 
+        if let Some(command) = map.get("warmup_command").and_then(|v| v.as_str()) {
+            result.warmup_command = Some(command.to_string());
+        }
+
+        if let Some(stages) = map.get("warmup_hooks") {
+            result.warmup_hooks =
+                Some(serde_json::from_value(stages.clone()).context("Invalid warmup_hooks")?);
+        }
+
         if let Some(runs) = map.get("runs").and_then(|v| v.as_u64()) {
             result.runs = runs as usize;
         }
@@ -141,21 +394,374 @@ impl BenchmarkOptions {
             result.perf_instrumentation = Some(perf_instrumentation);
         }
 
+        if let Some(perf_archive) = map.get("perf_archive").and_then(|v| v.as_bool()) {
+            result.perf_archive = Some(perf_archive);
+        }
+
+        if let Some(delay) = map.get("perf_delay_secs").and_then(|v| v.as_u64()) {
+            result.perf_delay_secs = Some(delay);
+        }
+
+        if let Some(pattern) = map
+            .get("perf_attach_on_log_pattern")
+            .and_then(|v| v.as_str())
+        {
+            result.perf_attach_on_log_pattern = Some(pattern.to_string());
+        }
+
+        if let Some(tracepoints) = map.get("tracepoints") {
+            result.tracepoints =
+                Some(serde_json::from_value(tracepoints.clone()).context("Invalid tracepoints")?);
+        }
+
+        if let Some(continue_on_failure) = map.get("continue_on_failure").and_then(|v| v.as_bool())
+        {
+            result.continue_on_failure = Some(continue_on_failure);
+        }
+
+        if let Some(skip) = map.get("skip_runs_after_failure").and_then(|v| v.as_bool()) {
+            result.skip_runs_after_failure = Some(skip);
+        }
+
+        if let Some(core_dumps) = map.get("core_dumps").and_then(|v| v.as_bool()) {
+            result.core_dumps = Some(core_dumps);
+        }
+
+        if let Some(template) = map.get("out_dir_template").and_then(|v| v.as_str()) {
+            result.out_dir_template = Some(template.to_string());
+        }
+
+        if let Some(fail_fast) = map.get("fail_fast") {
+            result.fail_fast =
+                Some(serde_json::from_value(fail_fast.clone()).context("Invalid fail_fast")?);
+        }
+
+        if let Some(header_cache) = map.get("header_cache").and_then(|v| v.as_bool()) {
+            result.header_cache = Some(header_cache);
+        }
+
+        if let Some(checkpoint) = map.get("checkpoint") {
+            result.checkpoint =
+                Some(serde_json::from_value(checkpoint.clone()).context("Invalid checkpoint")?);
+        }
+
+        if let Some(priority) = map.get("priority") {
+            result.priority =
+                Some(serde_json::from_value(priority.clone()).context("Invalid priority")?);
+        }
+
+        if let Some(quiet_gate) = map.get("quiet_gate") {
+            result.quiet_gate =
+                Some(serde_json::from_value(quiet_gate.clone()).context("Invalid quiet_gate")?);
+        }
+
+        if let Some(target) = map.get("target_relative_error").and_then(|v| v.as_f64()) {
+            result.target_relative_error = Some(target);
+        }
+
+        if let Some(max_runs) = map.get("max_runs").and_then(|v| v.as_u64()) {
+            result.max_runs = Some(max_runs as usize);
+        }
+
+        if let Some(cache_state) = map.get("cache_state") {
+            result.cache_state =
+                Some(serde_json::from_value(cache_state.clone()).context("Invalid cache_state")?);
+        }
+
+        if let Some(memory_limit) = map.get("memory_limit") {
+            result.memory_limit =
+                Some(serde_json::from_value(memory_limit.clone()).context("Invalid memory_limit")?);
+        }
+
+        if let Some(cpu_limits) = map.get("cpu_limits") {
+            result.cpu_limits =
+                Some(serde_json::from_value(cpu_limits.clone()).context("Invalid cpu_limits")?);
+        }
+
+        if let Some(disk_guard) = map.get("disk_guard") {
+            result.disk_guard =
+                Some(serde_json::from_value(disk_guard.clone()).context("Invalid disk_guard")?);
+        }
+
+        if let Some(command_overrides) = map.get("command_overrides") {
+            result.command_overrides = Some(
+                serde_json::from_value(command_overrides.clone())
+                    .context("Invalid command_overrides")?,
+            );
+        }
+
+        if let Some(zmq_progress) = map.get("zmq_progress") {
+            result.zmq_progress =
+                Some(serde_json::from_value(zmq_progress.clone()).context("Invalid zmq_progress")?);
+        }
+
+        if let Some(node_logging) = map.get("node_logging") {
+            result.node_logging =
+                Some(serde_json::from_value(node_logging.clone()).context("Invalid node_logging")?);
+        }
+
+        if let Some(nodes) = map.get("nodes") {
+            result.nodes = Some(serde_json::from_value(nodes.clone()).context("Invalid nodes")?);
+        }
+
         Ok(result)
     }
 }
 
+/// A named build configuration producing its own binary for every commit
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct BuildVariant {
+    pub name: String,
+    /// Value passed as CMAKE_BUILD_TYPE (e.g. Release, RelWithDebInfo, Debug)
+    pub build_type: Option<String>,
+    /// Additional cmake arguments, appended after the global cmake_build_args
+    #[serde(default)]
+    pub cmake_build_args: Vec<String>,
+    /// Additional ./configure arguments, used for commits predating cmake
+    #[serde(default)]
+    pub configure_args: Vec<String>,
+    /// Wrapper command for this variant's toolchain, replacing `build.wrapper`
+    pub wrapper: Option<Vec<String>>,
+}
+
+impl BuildVariant {
+    /// All cmake configure arguments contributed by this variant
+    pub fn cmake_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(build_type) = &self.build_type {
+            args.push(format!("-DCMAKE_BUILD_TYPE={build_type}"));
+        }
+        args.extend(self.cmake_build_args.iter().cloned());
+        args
+    }
+}
+
+/// Configuration for building with Bitcoin Core's depends system
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct DependsConfig {
+    /// Target host triplet. Defaults to the build machine, as reported by depends/config.guess
+    pub host: Option<String>,
+    /// Additional variables passed to make (e.g. NO_QT=1)
+    #[serde(default)]
+    pub make_args: Vec<String>,
+}
+
+/// Configuration for building binaries
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct BuildConfig {
+    /// Build variants to produce for each commit. Each variant becomes a results dimension.
+    #[serde(default)]
+    pub variants: Vec<BuildVariant>,
+    /// Build against a toolchain from depends/ instead of the system libraries
+    pub depends: Option<DependsConfig>,
+    /// Arguments passed to ./configure for commits predating cmake (v28 and earlier)
+    #[serde(default)]
+    pub configure_args: Vec<String>,
+    /// Shell command run in the source directory after checking out and patching each commit
+    pub pre_build: Option<String>,
+    /// Shell command run in the source directory after each binary is built and copied
+    pub post_build: Option<String>,
+    /// Command the configure and build steps run under, e.g. `["nix", "develop", "-c"]` to build
+    /// in a hermetic environment
+    #[serde(default)]
+    pub wrapper: Vec<String>,
+    /// Build on another machine over ssh and copy the binaries back, keeping the benchmark
+    /// machine cool
+    pub remote: Option<RemoteBuildConfig>,
+    /// Download prebuilt binaries published by CI instead of building the standard configuration
+    pub artifacts: Option<crate::artifact::ArtifactConfig>,
+}
+
+/// A build server benchkit builds on over ssh
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteBuildConfig {
+    /// Host to build on, like "user@buildbox"
+    pub host: String,
+    /// Directory on the host for the build's configs, scratch and binaries, relative to the home
+    /// directory of the ssh user
+    #[serde(default = "default_remote_dir")]
+    pub dir: String,
+    /// benchkit on the host
+    #[serde(default = "default_remote_benchkit")]
+    pub benchkit: String,
+    /// ssh command with its options, e.g. `["ssh", "-p", "2222"]`
+    #[serde(default = "default_ssh")]
+    pub ssh: Vec<String>,
+}
+
+fn default_remote_dir() -> String {
+    "benchkit-builds".to_string()
+}
+
+fn default_remote_benchkit() -> String {
+    "benchkit".to_string()
+}
+
+fn default_ssh() -> Vec<String> {
+    vec!["ssh".to_string()]
+}
+
+impl BuildConfig {
+    /// Wrapper command of a variant's build, the global one unless the variant has its own
+    pub fn wrapper_for<'a>(&'a self, variant: Option<&'a BuildVariant>) -> &'a [String] {
+        variant
+            .and_then(|variant| variant.wrapper.as_deref())
+            .unwrap_or(&self.wrapper)
+    }
+}
+
+/// An additional git remote to fetch into a cached remote repository
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RemoteConfig {
+    pub name: String,
+    pub url: String,
+    /// Also fetch GitHub pull request heads as `<name>/pr/<number>`
+    #[serde(default)]
+    pub pull_requests: bool,
+}
+
+/// Configuration for cached clones of a remote `source` repository
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct RepositoryConfig {
+    /// Remotes to add alongside origin (e.g. upstream and forks)
+    #[serde(default)]
+    pub remotes: Vec<RemoteConfig>,
+    /// Prune remote-tracking refs which no longer exist on the remote when fetching
+    #[serde(default)]
+    pub fetch_prune: bool,
+    /// Run `git gc` on the cached repository when the last gc is older than this many days
+    pub gc_interval_days: Option<u64>,
+    /// Use `git gc --aggressive` for periodic garbage collection
+    #[serde(default)]
+    pub gc_aggressive: bool,
+    /// Evict least recently used cached repositories once the cache exceeds this size
+    pub max_cache_size_gb: Option<u64>,
+}
+
+fn default_patch_repo() -> String {
+    "bitcoin-dev-tools/benchkit".to_string()
+}
+
+fn default_patch_ref() -> String {
+    "master".to_string()
+}
+
+/// Configuration for where benchkit patches come from and which versions are accepted
+#[derive(Debug, Deserialize, Clone)]
+pub struct PatchConfig {
+    /// GitHub repository hosting the patches/ directory, as owner/name
+    #[serde(default = "default_patch_repo")]
+    pub repo: String,
+    /// Branch, tag or commit of the repository to fetch patches from
+    #[serde(default = "default_patch_ref", rename = "ref")]
+    pub git_ref: String,
+    /// Local directory to copy patches from instead of downloading them
+    pub local: Option<PathBuf>,
+    /// Expected sha256 of each patch, keyed by patch file name
+    #[serde(default)]
+    pub sha256: HashMap<String, String>,
+}
+
+impl Default for PatchConfig {
+    fn default() -> Self {
+        Self {
+            repo: default_patch_repo(),
+            git_ref: default_patch_ref(),
+            local: None,
+            sha256: HashMap::new(),
+        }
+    }
+}
+
 /// Global configuration for all benchmarks
 #[derive(Debug, Deserialize, Clone)]
 pub struct BenchmarkGlobalConfig {
     pub benchmark: Option<BenchmarkOptions>,
     pub benchmark_cores: Option<String>,
     pub runner_cores: Option<String>,
+    /// nice, ionice and oom_score_adj of the benchkit process itself (Linux only)
+    pub runner_priority: Option<ProcessPriority>,
+    /// Refuse to run unless the required system checks pass (Linux only)
+    #[serde(default)]
+    pub require_tuned_system: bool,
     pub cmake_build_args: Option<Vec<String>>,
+    #[serde(default)]
+    pub build: BuildConfig,
+    #[serde(default)]
+    pub patches: PatchConfig,
+    #[serde(default)]
+    pub repository: RepositoryConfig,
+    /// Static datasets benchmarks can refer to with `{dataset:<name>}`
+    #[serde(default)]
+    pub datasets: Vec<DatasetConfig>,
+    /// Files written to the output directory once all benchmarks have run
+    #[serde(default)]
+    pub exports: Vec<ExportConfig>,
     pub source: PathBuf,
     pub scratch: PathBuf,
     pub commits: Vec<String>,
+    /// Binary path or release tag (e.g. `v28.1`) benchmarked as an extra commit and used as
+    /// the baseline of comparisons
+    pub reference_binary: Option<String>,
     pub tmp_data_dir: PathBuf,
+    /// Labels commits were configured with, keyed by the short commit hash they resolved to
+    #[serde(skip)]
+    pub commit_labels: HashMap<String, String>,
+    /// The cores `benchmark_cores` and `runner_cores` name, after resolving `auto`
+    #[serde(skip)]
+    pub cores: CoreAssignment,
+}
+
+impl BenchmarkGlobalConfig {
+    /// Replace the configured commits with their resolved short hashes
+    ///
+    /// Labels which differ from their hash (branches, tags) are kept for display.
+    pub fn set_resolved_commits(&mut self, resolved: Vec<String>) {
+        for (label, hash) in self.commits.iter().zip(&resolved) {
+            if label != hash {
+                self.commit_labels.insert(hash.clone(), label.clone());
+            }
+        }
+        self.commits = resolved;
+    }
+
+    /// Names of the configured build variants, or a single `None` for the default build
+    pub fn variant_names(&self) -> Vec<Option<String>> {
+        if self.build.variants.is_empty() {
+            vec![None]
+        } else {
+            self.build
+                .variants
+                .iter()
+                .map(|v| Some(v.name.clone()))
+                .collect()
+        }
+    }
+
+    /// Whether binaries are built without any custom settings, as CI builds them
+    pub fn standard_build(&self) -> bool {
+        self.build.variants.is_empty()
+            && self.build.depends.is_none()
+            && self.build.configure_args.is_empty()
+            && self.build.pre_build.is_none()
+            && self.build.post_build.is_none()
+            && self.build.wrapper.is_empty()
+            && self.cmake_build_args.as_ref().is_none_or(Vec::is_empty)
+    }
+
+    /// Binary identifiers for every commit and build variant combination
+    pub fn binary_ids(&self) -> Vec<String> {
+        let variants = self.variant_names();
+        self.commits
+            .iter()
+            .flat_map(|commit| {
+                variants
+                    .iter()
+                    .map(move |variant| crate::benchmarks::binary_id(commit, variant.as_deref()))
+            })
+            .collect()
+    }
 }
 
 /// Configuration for a single benchmark
@@ -163,13 +769,127 @@ pub struct BenchmarkGlobalConfig {
 pub struct SingleConfig {
     pub name: String,
     pub env: Option<HashMap<String, String>>,
-    pub network: String,
+    pub network: Network,
     pub connect: Option<String>,
+    /// RPC of the node at `connect`, to check it is synced and not serving other syncing nodes
+    /// before running
+    pub connect_rpc: Option<crate::benchmarks::FeederRpc>,
     #[serde(default)]
     pub mode: Option<String>,
+    /// Labels for selecting benchmarks with `run --tags` and `--exclude-tags`
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Block files to replay with -loadblock in loadblock mode, may use `{dataset:<name>}`
+    #[serde(default)]
+    pub loadblock: Vec<String>,
+    /// Features and version every binary must have, checked before any benchmark runs
+    pub requires: Option<Requirements>,
     pub benchmark: HashMap<String, Value>,
 }
 
+impl SingleConfig {
+    /// Whether this benchmark has any of `include` (or `include` is empty) and none of `exclude`
+    pub fn matches_tags(&self, include: &[String], exclude: &[String]) -> bool {
+        (include.is_empty() || self.tags.iter().any(|tag| include.contains(tag)))
+            && !self.tags.iter().any(|tag| exclude.contains(tag))
+    }
+}
+
+/// Benchmarks chosen on the command line by name, name pattern or position
+///
+/// A benchmark is selected if it matches any of the criteria, or if none are given.
+#[derive(Debug, Clone, Default)]
+pub struct BenchmarkSelection {
+    /// Exact benchmark names
+    pub names: Vec<String>,
+    /// Name patterns: globs with `*` and `?`, or regexes written as `/regex/`
+    pub filters: Vec<String>,
+    /// Positions in `benchmarks`, counting from 0, as single indices or ranges like `2-5`
+    pub indices: Vec<String>,
+}
+
+impl BenchmarkSelection {
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.filters.is_empty() && self.indices.is_empty()
+    }
+
+    /// The selected benchmarks with their indices, in configuration order
+    pub fn select<'a>(
+        &self,
+        benchmarks: &'a [SingleConfig],
+    ) -> Result<Vec<(usize, &'a SingleConfig)>> {
+        if let Some(name) = self
+            .names
+            .iter()
+            .find(|name| !benchmarks.iter().any(|b| &b.name == *name))
+        {
+            anyhow::bail!("Benchmark not found: {name}");
+        }
+        let filters = self
+            .filters
+            .iter()
+            .map(|filter| name_pattern(filter))
+            .collect::<Result<Vec<_>>>()?;
+        let mut indices = Vec::new();
+        for spec in &self.indices {
+            let range = parse_index_range(spec)?;
+            if *range.end() >= benchmarks.len() {
+                anyhow::bail!(
+                    "Benchmark index {spec} is out of range, there are {} benchmarks",
+                    benchmarks.len()
+                );
+            }
+            indices.push(range);
+        }
+
+        Ok(benchmarks
+            .iter()
+            .enumerate()
+            .filter(|(index, bench)| {
+                self.is_empty()
+                    || self.names.contains(&bench.name)
+                    || filters.iter().any(|filter| filter.is_match(&bench.name))
+                    || indices.iter().any(|range| range.contains(index))
+            })
+            .collect())
+    }
+}
+
+/// Compile a `/regex/` or a glob into a regex matching whole benchmark names
+fn name_pattern(filter: &str) -> Result<Regex> {
+    let pattern = match filter
+        .strip_prefix('/')
+        .and_then(|rest| rest.strip_suffix('/'))
+    {
+        Some(regex) => regex.to_string(),
+        None => {
+            let glob = regex::escape(filter)
+                .replace(r"\*", ".*")
+                .replace(r"\?", ".");
+            format!("^{glob}$")
+        }
+    };
+    Regex::new(&pattern).with_context(|| format!("Invalid benchmark filter: {filter}"))
+}
+
+/// Parse a benchmark index like `3` or an inclusive range like `2-5`
+fn parse_index_range(spec: &str) -> Result<std::ops::RangeInclusive<usize>> {
+    let parse = |index: &str| {
+        index
+            .trim()
+            .parse::<usize>()
+            .with_context(|| format!("Invalid benchmark index: {spec}"))
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(spec)?, parse(spec)?),
+    };
+    if start > end {
+        anyhow::bail!("Invalid benchmark index range: {spec}");
+    }
+    Ok(start..=end)
+}
+
 /// Complete benchmark configuration
 #[derive(Debug, Deserialize, Clone)]
 pub struct BenchmarkConfig {
@@ -188,6 +908,16 @@ pub struct GlobalConfig {
 
 /// Load application configuration from a YAML file
 pub fn load_app_config(app_config_path: &PathBuf) -> Result<AppConfig> {
+    read_app_config(app_config_path).map_err(|source| {
+        BenchkitError::Config {
+            path: app_config_path.clone(),
+            source,
+        }
+        .into()
+    })
+}
+
+fn read_app_config(app_config_path: &PathBuf) -> Result<AppConfig> {
     if !app_config_path.exists() {
         anyhow::bail!("App config file not found: {:?}", app_config_path);
     }
@@ -204,6 +934,11 @@ pub fn load_app_config(app_config_path: &PathBuf) -> Result<AppConfig> {
 
     config.path = app_config_path.to_path_buf();
 
+    crate::job::parse_trusted_keys(&config.trusted_keys)
+        .with_context(|| format!("Invalid trusted_keys in {app_config_path:?}"))?;
+    crate::schedule::Schedule::parse(&config.schedule)
+        .with_context(|| format!("Invalid schedule in {app_config_path:?}"))?;
+
     // Expand any relative paths to absolute
     expand_paths(
         &mut [
@@ -214,6 +949,20 @@ pub fn load_app_config(app_config_path: &PathBuf) -> Result<AppConfig> {
         ],
         config_dir,
     )?;
+    if config.dataset_dir.as_os_str().is_empty() {
+        config.dataset_dir = config.home_dir.join("datasets");
+    } else {
+        expand_paths(&mut [&mut config.dataset_dir], config_dir)?;
+    }
+    if let Some(signing_key) = &mut config.signing_key {
+        path_utils::process_paths(&mut [signing_key], config_dir, false)?;
+        crate::attestation::load_signing_key(signing_key)?;
+    }
+    if config.job_dir.as_os_str().is_empty() {
+        config.job_dir = config.home_dir.join("jobs");
+    } else {
+        expand_paths(&mut [&mut config.job_dir], config_dir)?;
+    }
 
     for dir in [&config.bin_dir, &config.patch_dir, &config.snapshot_dir] {
         if !dir.exists() {
@@ -229,8 +978,34 @@ pub fn load_app_config(app_config_path: &PathBuf) -> Result<AppConfig> {
     Ok(config)
 }
 
-/// Load benchmark configuration from a YAML file
+/// Load benchmark configuration from a YAML file, resolving its core specs against this machine
 pub fn load_bench_config(bench_config_path: &PathBuf) -> Result<BenchmarkConfig> {
+    let config = read_bench_config(bench_config_path).and_then(|mut config| {
+        resolve_cores(&mut config.global)?;
+        Ok(config)
+    });
+    config.map_err(|source| {
+        BenchkitError::Config {
+            path: bench_config_path.clone(),
+            source,
+        }
+        .into()
+    })
+}
+
+/// Parse and validate benchmark configuration without checking it against this machine, for
+/// configurations meant to run elsewhere
+pub fn parse_bench_config(bench_config_path: &PathBuf) -> Result<BenchmarkConfig> {
+    read_bench_config(bench_config_path).map_err(|source| {
+        BenchkitError::Config {
+            path: bench_config_path.clone(),
+            source,
+        }
+        .into()
+    })
+}
+
+fn read_bench_config(bench_config_path: &PathBuf) -> Result<BenchmarkConfig> {
     if !bench_config_path.exists() {
         anyhow::bail!("Benchmark config file not found: {:?}", bench_config_path);
     }
@@ -271,6 +1046,30 @@ pub fn load_bench_config(bench_config_path: &PathBuf) -> Result<BenchmarkConfig>
         )?;
     }
 
+    if let Some(local) = &mut config.global.patches.local {
+        path_utils::process_paths(&mut [local], config_dir, false)?;
+    }
+
+    // Parameter values read from files become part of the configuration
+    if let Some(lists) = config
+        .global
+        .benchmark
+        .as_mut()
+        .and_then(|options| options.parameter_lists.as_mut())
+    {
+        ParameterUtils::resolve_values_from(lists, config_dir)?;
+    }
+    for bench in &mut config.benchmarks {
+        if let Some(lists) = bench
+            .benchmark
+            .get_mut("parameter_lists")
+            .and_then(|lists| lists.as_array_mut())
+        {
+            ParameterUtils::resolve_values_from(lists, config_dir)
+                .with_context(|| format!("Invalid parameter_lists of benchmark {}", bench.name))?;
+        }
+    }
+
     validate_config(&config)?;
 
     debug!(
@@ -295,9 +1094,18 @@ pub fn get_merged_options(
     benchmark_index: usize,
 ) -> Result<BenchmarkOptions> {
     let benchmark = &config.benchmarks[benchmark_index];
-    let options = merge_benchmark_options(&config.global.benchmark, &benchmark.benchmark)?;
-    options.validate_for_execution()?;
-    Ok(options)
+    merge_benchmark_options(&config.global.benchmark, &benchmark.benchmark)
+        .and_then(|options| {
+            options.validate_for_execution()?;
+            Ok(options)
+        })
+        .map_err(|source| {
+            BenchkitError::Config {
+                path: config.path.clone(),
+                source,
+            }
+            .into()
+        })
 }
 
 fn expand_paths(paths: &mut [&mut PathBuf], config_dir: &std::path::Path) -> Result<()> {
@@ -314,16 +1122,86 @@ fn validate_config(config: &BenchmarkConfig) -> Result<()> {
         anyhow::bail!("No commits specified for benchmarking");
     }
 
+    if let Some(reference) = &config.global.reference_binary {
+        ReferenceBinary::parse(reference)?;
+        if config.global.commits.iter().any(|c| c == REFERENCE_COMMIT) {
+            anyhow::bail!("Commit name '{REFERENCE_COMMIT}' is reserved for reference_binary");
+        }
+    }
+
+    if let Some(artifacts) = &config.global.build.artifacts {
+        artifacts.validate()?;
+    }
+
+    let mut dataset_names = std::collections::HashSet::new();
+    for dataset in &config.global.datasets {
+        dataset.validate()?;
+        if !dataset_names.insert(dataset.name.as_str()) {
+            anyhow::bail!("Duplicate dataset name: {}", dataset.name);
+        }
+    }
+
+    let mut export_paths = std::collections::HashSet::new();
+    for export in &config.global.exports {
+        export.validate()?;
+        let path = export.path(std::path::Path::new(""));
+        if !export_paths.insert(path.clone()) {
+            anyhow::bail!("Several exports write to {}", path.display());
+        }
+    }
+
+    let mut remote_names = std::collections::HashSet::new();
+    for remote in &config.global.repository.remotes {
+        if remote.name == "origin" {
+            anyhow::bail!("Remote name 'origin' is reserved for the source repository");
+        }
+        if !remote_names.insert(remote.name.as_str()) {
+            anyhow::bail!("Duplicate remote name: {}", remote.name);
+        }
+    }
+
+    for (patch, hash) in &config.global.patches.sha256 {
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("Invalid sha256 pin for patch {}: {}", patch, hash);
+        }
+    }
+
     // Validate CPU core specifications
     if let Some(cores) = &config.global.benchmark_cores {
-        if !is_valid_cpu_cores(cores) {
-            anyhow::bail!("Invalid benchmark_cores format: {}", cores);
+        if cores != AUTO_CORES {
+            parse_core_list(cores).with_context(|| format!("Invalid benchmark_cores: {cores}"))?;
         }
     }
 
     if let Some(cores) = &config.global.runner_cores {
-        if !is_valid_cpu_cores(cores) {
-            anyhow::bail!("Invalid runner_cores format: {}", cores);
+        if cores != AUTO_CORES {
+            parse_core_list(cores).with_context(|| format!("Invalid runner_cores: {cores}"))?;
+        }
+        if cores == AUTO_CORES && config.global.benchmark_cores.is_none() {
+            anyhow::bail!("runner_cores: auto needs benchmark_cores to take the complement of");
+        }
+    }
+
+    if let Some(priority) = &config.global.runner_priority {
+        priority.validate().context("Invalid runner_priority")?;
+    }
+
+    // Validate build variants
+    let mut variant_names = std::collections::HashSet::new();
+    for variant in &config.global.build.variants {
+        if variant.name.is_empty()
+            || !variant
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        {
+            anyhow::bail!(
+                "Invalid build variant name '{}': use only letters, digits, '-', '_' and '.'",
+                variant.name
+            );
+        }
+        if !variant_names.insert(variant.name.as_str()) {
+            anyhow::bail!("Duplicate build variant name: {}", variant.name);
         }
     }
 
@@ -336,36 +1214,255 @@ fn validate_config(config: &BenchmarkConfig) -> Result<()> {
         if benchmark.name.is_empty() {
             anyhow::bail!("Benchmark name cannot be empty");
         }
+        let options = merge_benchmark_options(&config.global.benchmark, &benchmark.benchmark)?;
+        check_placeholders(config, &benchmark.name, &options)?;
+        if let Some(requires) = &benchmark.requires {
+            requires
+                .validate()
+                .with_context(|| format!("Invalid requires of benchmark {}", benchmark.name))?;
+        }
+        if let Some(rpc) = &benchmark.connect_rpc {
+            rpc.validate()
+                .with_context(|| format!("Invalid connect_rpc of benchmark {}", benchmark.name))?;
+        }
 
-        match benchmark.network.as_str() {
-            "main" | "test" | "signet" | "regtest" => {}
-            _ => anyhow::bail!("Invalid network type: {}", benchmark.network),
+        let mode = match &benchmark.mode {
+            Some(mode) => crate::benchmarks::HookMode::mode_from_str(mode)?,
+            None => crate::benchmarks::HookMode::default(),
+        };
+        match (mode, benchmark.loadblock.is_empty()) {
+            (crate::benchmarks::HookMode::LoadBlock, true) => anyhow::bail!(
+                "Benchmark {} uses mode loadblock but lists no loadblock files",
+                benchmark.name
+            ),
+            (crate::benchmarks::HookMode::LoadBlock, false) | (_, true) => {}
+            (_, false) => anyhow::bail!(
+                "Benchmark {} lists loadblock files but doesn't use mode loadblock",
+                benchmark.name
+            ),
         }
+    }
 
-        if let Some(mode) = &benchmark.mode {
-            use crate::benchmarks::HookMode;
-            HookMode::mode_from_str(mode)?;
+    Ok(())
+}
+
+/// Check a benchmark's command templates only use placeholders the runner substitutes
+fn check_placeholders(
+    config: &BenchmarkConfig,
+    name: &str,
+    options: &BenchmarkOptions,
+) -> Result<()> {
+    let parameter_lists = match &options.parameter_lists {
+        Some(lists) => ParameterUtils::create_parameter_lists(&Value::Array(lists.clone()))?,
+        None => Vec::new(),
+    };
+    // commit, variant, cache_state, cpu_quota and par select how the command is run, so they
+    // needn't appear in it
+    let parameters: Vec<String> = parameter_lists
+        .into_iter()
+        .map(|list| list.var)
+        .filter(|var| {
+            !matches!(
+                var.as_str(),
+                "commit" | "variant" | "cache_state" | "cpu_quota" | "par"
+            )
+        })
+        .collect();
+    let mut available: BTreeSet<String> = BUILTIN_PLACEHOLDERS
+        .iter()
+        .map(|name| name.to_string())
+        .chain(parameters.iter().cloned())
+        .collect();
+    if !config.global.build.variants.is_empty() {
+        available.insert("variant".to_string());
+    }
+    if options.cache_state.is_some() {
+        available.insert("cache_state".to_string());
+    }
+    if let Some(cpu_limits) = &options.cpu_limits {
+        if !cpu_limits.quotas.is_empty() {
+            available.insert("cpu_quota".to_string());
+        }
+        if !cpu_limits.par.is_empty() {
+            available.insert("par".to_string());
         }
     }
 
+    let node_commands = options
+        .nodes
+        .iter()
+        .flatten()
+        .map(|role| (format!("nodes {} command", role.name), Some(&role.command)));
+    for (field, template) in [
+        ("command".to_string(), options.command.as_ref()),
+        (
+            "warmup_command".to_string(),
+            options.warmup_command.as_ref(),
+        ),
+    ]
+    .into_iter()
+    .chain(node_commands)
+    {
+        let Some(template) = template else {
+            continue;
+        };
+        let unused = ParameterUtils::check_placeholders(template, &available, &parameters)
+            .with_context(|| format!("Benchmark {name} {field}"))?;
+        if !field.starts_with("nodes") {
+            NodeLogging::check_command(
+                options.node_logging.as_ref(),
+                options.stop_on_log_pattern.is_some(),
+                template,
+            )
+            .with_context(|| format!("Benchmark {name} {field}"))?;
+        }
+        if field == "command" && !unused.is_empty() {
+            warn!(
+                "Benchmark {name} command doesn't use the parameters {}, they only reach hooks \
+                 as BENCHKIT_PARAM_ variables",
+                unused.join(", ")
+            );
+        }
+    }
     Ok(())
 }
 
-fn is_valid_cpu_cores(cores: &str) -> bool {
-    for part in cores.split(',') {
-        if part.contains('-') {
-            let range: Vec<&str> = part.split('-').collect();
-            if range.len() != 2
-                || range[0].parse::<usize>().is_err()
-                || range[1].parse::<usize>().is_err()
-            {
-                return false;
+/// Core spec which lets benchkit choose the cores from the machine's topology
+pub const AUTO_CORES: &str = "auto";
+
+/// Resolve the core specs against this machine's topology, recording the cores each names in
+/// `cores` and rewriting the specs in canonical form (e.g. "0-3,5")
+///
+/// `benchmark_cores: auto` takes one thread of each core of a NUMA node, preferring isolated
+/// cores and skipping core 0 (see `select_benchmark_cores`), and `runner_cores: auto` the
+/// remaining cores. Other specs must only name cores the machine has. As jobs' configurations
+/// are loaded on the worker, they are checked against the worker's topology. Core binding is
+/// Linux only, so elsewhere `auto` is dropped and specs aren't checked.
+fn resolve_cores(global: &mut BenchmarkGlobalConfig) -> Result<()> {
+    let is_auto = |spec: &Option<String>| spec.as_deref() == Some(AUTO_CORES);
+    let auto = is_auto(&global.benchmark_cores) || is_auto(&global.runner_cores);
+
+    #[cfg(target_os = "linux")]
+    {
+        use crate::cpu_binding::{
+            complement_cores, isolated_cores, missing_cores, select_benchmark_cores, CpuBinder,
+        };
+
+        if global.benchmark_cores.is_none() && global.runner_cores.is_none() {
+            return Ok(());
+        }
+        let pus = CpuBinder::new()?.processing_units()?;
+        let available = || format_core_list(&pus.iter().map(|pu| pu.os_index).collect::<Vec<_>>());
+        let check = |name: &str, spec: &str| -> Result<Vec<u32>> {
+            let cores = parse_core_list(spec)?;
+            let missing = missing_cores(&cores, &pus);
+            if !missing.is_empty() {
+                anyhow::bail!(
+                    "{name} {spec} names cores {} which this machine doesn't have, it has {}",
+                    format_core_list(&missing),
+                    available()
+                );
+            }
+            Ok(cores)
+        };
+
+        let benchmark = match global.benchmark_cores.as_deref() {
+            Some(AUTO_CORES) => {
+                let cores = select_benchmark_cores(&pus, &isolated_cores())?;
+                log::info!("Chose benchmark_cores {}", format_core_list(&cores));
+                cores
+            }
+            Some(spec) => check("benchmark_cores", spec)?,
+            None => Vec::new(),
+        };
+        let runner = match global.runner_cores.as_deref() {
+            Some(AUTO_CORES) => {
+                let cores = complement_cores(&pus, &benchmark);
+                if cores.is_empty() {
+                    anyhow::bail!("No cores left for runner_cores: auto besides benchmark_cores");
+                }
+                log::info!("Chose runner_cores {}", format_core_list(&cores));
+                cores
+            }
+            Some(spec) => check("runner_cores", spec)?,
+            None => Vec::new(),
+        };
+        if global.benchmark_cores.is_some() {
+            global.benchmark_cores = Some(format_core_list(&benchmark));
+        }
+        if global.runner_cores.is_some() {
+            global.runner_cores = Some(format_core_list(&runner));
+        }
+        global.cores = CoreAssignment {
+            benchmark,
+            runner,
+            auto,
+        };
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    if auto {
+        debug!("Core binding is only supported on Linux, ignoring auto core specs");
+        for spec in [&mut global.benchmark_cores, &mut global.runner_cores] {
+            if spec.as_deref() == Some(AUTO_CORES) {
+                *spec = None;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a core spec like "0-3,5,7-9", as taskset takes them, into the sorted cores it names
+pub fn parse_core_list(spec: &str) -> Result<Vec<u32>> {
+    let number = |core: &str| {
+        core.trim()
+            .parse::<u32>()
+            .with_context(|| format!("Invalid core number: '{core}'"))
+    };
+
+    let mut cores = Vec::new();
+    for part in spec.split(',') {
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (number(start)?, number(end)?);
+                if start > end {
+                    anyhow::bail!("Invalid core range {part}: it starts after it ends");
+                }
+                cores.extend(start..=end);
             }
-        } else if part.parse::<usize>().is_err() {
-            return false;
+            None => cores.push(number(part)?),
+        }
+    }
+    cores.sort_unstable();
+    cores.dedup();
+    Ok(cores)
+}
+
+/// Write cores as a canonical spec like "0-3,5"
+pub fn format_core_list(cores: &[u32]) -> String {
+    let mut cores = cores.to_vec();
+    cores.sort_unstable();
+    cores.dedup();
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for core in cores {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == core => *end = core,
+            _ => ranges.push((core, core)),
         }
     }
-    true
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 #[cfg(test)]
@@ -376,22 +1473,38 @@ mod tests {
     use tempfile::tempdir;
 
     #[test]
-    fn test_is_valid_cpu_cores() {
-        assert!(is_valid_cpu_cores("0"));
-        assert!(is_valid_cpu_cores("0,1,2"));
-        assert!(is_valid_cpu_cores("0-3"));
-        assert!(is_valid_cpu_cores("0-3,5,7-9"));
-
-        assert!(!is_valid_cpu_cores(""));
-        assert!(!is_valid_cpu_cores("a"));
-        assert!(!is_valid_cpu_cores("0-"));
-        assert!(!is_valid_cpu_cores("-3"));
+    fn test_parse_core_list() {
+        assert_eq!(parse_core_list("0").unwrap(), [0]);
+        assert_eq!(parse_core_list("0,1,2").unwrap(), [0, 1, 2]);
+        assert_eq!(parse_core_list("0-3").unwrap(), [0, 1, 2, 3]);
+        assert_eq!(
+            parse_core_list("7-9,5,0-3,2").unwrap(),
+            [0, 1, 2, 3, 5, 7, 8, 9]
+        );
+
+        assert!(parse_core_list("").is_err());
+        assert!(parse_core_list("a").is_err());
+        assert!(parse_core_list("0-").is_err());
+        assert!(parse_core_list("-3").is_err());
+        assert!(parse_core_list("0-1-2").is_err());
+        let err = parse_core_list("7-3").unwrap_err().to_string();
+        assert_eq!(err, "Invalid core range 7-3: it starts after it ends");
+
+        assert_eq!(format_core_list(&[5, 0, 1, 2, 7, 9, 8]), "0-2,5,7-9");
+        assert_eq!(format_core_list(&[3, 3]), "3");
+        assert_eq!(format_core_list(&[]), "");
+        assert_eq!(
+            format_core_list(&parse_core_list("7-9,1,0").unwrap()),
+            "0-1,7-9"
+        );
     }
 
     #[test]
     fn test_benchmark_options_merge() {
         let base_opts = BenchmarkOptions {
             warmup: 1,
+            warmup_command: None,
+            warmup_hooks: None,
             runs: 2,
             capture_output: false,
             command: Some("base command".to_string()),
@@ -400,23 +1513,318 @@ mod tests {
             profile_interval: Some(5),
             stop_on_log_pattern: None,
             perf_instrumentation: None,
+            perf_archive: None,
+            perf_delay_secs: None,
+            perf_attach_on_log_pattern: None,
+            tracepoints: None,
+            priority: None,
+            quiet_gate: None,
+            target_relative_error: None,
+            max_runs: None,
+            cache_state: None,
+            memory_limit: None,
+            cpu_limits: None,
+            continue_on_failure: None,
+            skip_runs_after_failure: None,
+            core_dumps: None,
+            out_dir_template: None,
+            fail_fast: None,
+            header_cache: None,
+            checkpoint: None,
+            disk_guard: None,
+            command_overrides: None,
+            zmq_progress: None,
+            node_logging: None,
+            nodes: None,
         };
 
         let mut override_map = HashMap::new();
         override_map.insert("warmup".to_string(), Value::from(3));
+        override_map.insert(
+            "warmup_command".to_string(),
+            Value::from("bitcoind -stopatheight=1000"),
+        );
+        override_map.insert("warmup_hooks".to_string(), serde_json::json!(["prepare"]));
         override_map.insert("runs".to_string(), Value::from(4));
         override_map.insert("capture_output".to_string(), Value::from(true));
         override_map.insert("command".to_string(), Value::from("override command"));
         override_map.insert("profile".to_string(), Value::from(true));
+        override_map.insert(
+            "priority".to_string(),
+            serde_json::json!({"nice": -5, "ionice": "realtime:0", "oom_score_adj": -1000}),
+        );
+        override_map.insert(
+            "quiet_gate".to_string(),
+            serde_json::json!({"max_load": 1.5, "timeout": 600}),
+        );
+        override_map.insert(
+            "cache_state".to_string(),
+            serde_json::json!(["cold", "warm"]),
+        );
+        override_map.insert("cpu_limits".to_string(), serde_json::json!({"par": [1, 4]}));
+        override_map.insert("continue_on_failure".to_string(), Value::from(true));
+        override_map.insert(
+            "disk_guard".to_string(),
+            serde_json::json!({"min_free_mb": 2048}),
+        );
+        override_map.insert(
+            "nodes".to_string(),
+            serde_json::json!([{"name": "feeder", "command": "bitcoind -port=18500"}]),
+        );
+        override_map.insert(
+            "node_logging".to_string(),
+            serde_json::json!({"categories": ["bench"]}),
+        );
+        override_map.insert(
+            "zmq_progress".to_string(),
+            serde_json::json!({"stop_after_blocks": 1000}),
+        );
+        override_map.insert(
+            "command_overrides".to_string(),
+            serde_json::json!([{"versions": "<26", "rename": {"-dbbatchsize": "-dbbatch"}}]),
+        );
 
         let merged = base_opts.merge_from_map(&override_map).unwrap();
 
         assert_eq!(merged.warmup, 3);
+        assert_eq!(
+            merged.warmup_command.as_deref(),
+            Some("bitcoind -stopatheight=1000")
+        );
+        assert_eq!(merged.warmup_hooks, Some(vec![HookStage::Prepare]));
+        assert!(merged.validate().is_ok());
+        let mut setup_hook = merged.clone();
+        setup_hook.warmup_hooks = Some(vec![HookStage::Setup]);
+        assert!(setup_hook.validate().is_err());
         assert_eq!(merged.runs, 4);
         assert!(merged.capture_output);
         assert_eq!(merged.command, Some("override command".to_string()));
         assert_eq!(merged.profile, Some(true));
         assert_eq!(merged.profile_interval, Some(5)); // Unchanged
+        assert_eq!(merged.continue_on_failure, Some(true));
+        assert_eq!(merged.skip_runs_after_failure, None);
+        let priority = merged.priority.unwrap();
+        assert_eq!(priority.nice, Some(-5));
+        assert_eq!(priority.oom_score_adj, Some(-1000));
+        let quiet_gate = merged.quiet_gate.unwrap();
+        assert_eq!(quiet_gate.max_load, Some(1.5));
+        assert_eq!(quiet_gate.interval, 1);
+        assert_eq!(quiet_gate.timeout, Some(600));
+        let disk_guard = merged.disk_guard.unwrap();
+        assert_eq!(disk_guard.min_free_mb, 2048);
+        assert_eq!(disk_guard.interval, 5);
+        let nodes = merged.nodes.clone().unwrap();
+        assert_eq!(nodes[0].name, "feeder");
+        assert_eq!(nodes[0].ready_timeout, 60);
+        let node_logging = merged.node_logging.unwrap();
+        assert_eq!(node_logging.categories, ["bench"]);
+        assert!(!node_logging.microseconds);
+        assert_eq!(merged.zmq_progress.unwrap().stop_after_blocks, Some(1000));
+        let cpu_limits = merged.cpu_limits.unwrap();
+        assert_eq!(cpu_limits.par, [1, 4]);
+        assert!(cpu_limits.quotas.is_empty());
+        let command_overrides = merged.command_overrides.unwrap();
+        assert_eq!(command_overrides[0].versions.as_deref(), Some("<26"));
+        assert_eq!(command_overrides[0].rename["-dbbatchsize"], "-dbbatch");
+        assert_eq!(
+            merged.cache_state,
+            Some(vec![CacheState::Cold, CacheState::Warm])
+        );
+
+        override_map.insert(
+            "checkpoint".to_string(),
+            serde_json::json!({"interval_hours": 6}),
+        );
+        let checkpointed = base_opts.merge_from_map(&override_map).unwrap();
+        assert_eq!(
+            checkpointed.checkpoint.as_ref().map(|c| c.interval_hours),
+            Some(6.0)
+        );
+        // The profiler can't follow the node across restarts
+        assert!(checkpointed.validate().is_err());
+        override_map.remove("checkpoint");
+
+        override_map.insert(
+            "priority".to_string(),
+            serde_json::json!({"ionice": "urgent"}),
+        );
+        assert!(base_opts.merge_from_map(&override_map).is_err());
+    }
+
+    #[test]
+    fn test_matches_tags() {
+        let bench: SingleConfig = serde_yaml::from_str(
+            r#"
+            name: ibd
+            network: signet
+            tags: [ibd, nightly]
+            benchmark: {}
+            "#,
+        )
+        .unwrap();
+        let tags = |tags: &[&str]| tags.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+
+        assert!(bench.matches_tags(&[], &[]));
+        assert!(bench.matches_tags(&tags(&["quick", "nightly"]), &[]));
+        assert!(!bench.matches_tags(&tags(&["quick"]), &[]));
+        assert!(!bench.matches_tags(&[], &tags(&["ibd"])));
+        assert!(!bench.matches_tags(&tags(&["nightly"]), &tags(&["ibd"])));
+    }
+
+    #[test]
+    fn test_benchmark_selection() {
+        let benchmarks: Vec<SingleConfig> = ["ibd-signet", "ibd-mainnet", "reindex", "assumeutxo"]
+            .iter()
+            .map(|name| {
+                serde_yaml::from_str(&format!(
+                    "{{name: {name}, network: signet, benchmark: {{}}}}"
+                ))
+                .unwrap()
+            })
+            .collect();
+        let names = |selection: BenchmarkSelection| {
+            selection
+                .select(&benchmarks)
+                .unwrap()
+                .into_iter()
+                .map(|(index, bench)| format!("{index}:{}", bench.name))
+                .collect::<Vec<_>>()
+        };
+        let strings = |s: &[&str]| s.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(names(BenchmarkSelection::default()).len(), 4);
+        assert_eq!(
+            names(BenchmarkSelection {
+                names: strings(&["reindex", "ibd-signet"]),
+                ..Default::default()
+            }),
+            ["0:ibd-signet", "2:reindex"]
+        );
+        assert_eq!(
+            names(BenchmarkSelection {
+                filters: strings(&["ibd-*"]),
+                indices: strings(&["3"]),
+                ..Default::default()
+            }),
+            ["0:ibd-signet", "1:ibd-mainnet", "3:assumeutxo"]
+        );
+        assert_eq!(
+            names(BenchmarkSelection {
+                filters: strings(&["/main/", "re?ndex"]),
+                ..Default::default()
+            }),
+            ["1:ibd-mainnet", "2:reindex"]
+        );
+        assert_eq!(
+            names(BenchmarkSelection {
+                indices: strings(&["1-2"]),
+                ..Default::default()
+            }),
+            ["1:ibd-mainnet", "2:reindex"]
+        );
+        // A glob matches whole names only
+        assert!(names(BenchmarkSelection {
+            filters: strings(&["ibd"]),
+            ..Default::default()
+        })
+        .is_empty());
+
+        for invalid in [
+            BenchmarkSelection {
+                names: strings(&["missing"]),
+                ..Default::default()
+            },
+            BenchmarkSelection {
+                indices: strings(&["4"]),
+                ..Default::default()
+            },
+            BenchmarkSelection {
+                indices: strings(&["2-1"]),
+                ..Default::default()
+            },
+            BenchmarkSelection {
+                filters: strings(&["/(/"]),
+                ..Default::default()
+            },
+        ] {
+            assert!(invalid.select(&benchmarks).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn test_binary_ids_with_variants() {
+        let mut global: BenchmarkGlobalConfig = serde_yaml::from_str(
+            r#"
+            source: /src
+            scratch: /scratch
+            commits: ["aaa", "bbb"]
+            tmp_data_dir: /tmp/benchkit
+            "#,
+        )
+        .unwrap();
+        assert_eq!(global.binary_ids(), vec!["aaa", "bbb"]);
+
+        global.build = serde_yaml::from_str(
+            r#"
+            variants:
+              - name: release
+                build_type: Release
+              - name: asan
+                cmake_build_args: ["-DSANITIZERS=address"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            global.binary_ids(),
+            vec!["aaa-release", "aaa-asan", "bbb-release", "bbb-asan"]
+        );
+        assert_eq!(
+            global.build.variants[0].cmake_args(),
+            vec!["-DCMAKE_BUILD_TYPE=Release"]
+        );
+        assert_eq!(
+            global.build.variants[1].cmake_args(),
+            vec!["-DSANITIZERS=address"]
+        );
+    }
+
+    #[test]
+    fn test_set_resolved_commits() {
+        let mut global: BenchmarkGlobalConfig = serde_yaml::from_str(
+            r#"
+            source: /src
+            scratch: /scratch
+            commits: ["master", "0123456789ab"]
+            tmp_data_dir: /tmp/benchkit
+            "#,
+        )
+        .unwrap();
+        global.set_resolved_commits(vec!["ba9876543210".to_string(), "0123456789ab".to_string()]);
+
+        assert_eq!(global.commits, vec!["ba9876543210", "0123456789ab"]);
+        assert_eq!(global.commit_labels.len(), 1);
+        assert_eq!(global.commit_labels["ba9876543210"], "master");
+    }
+
+    #[test]
+    fn test_patch_config() {
+        let default: PatchConfig = serde_yaml::from_str("{}").unwrap();
+        assert_eq!(default.repo, "bitcoin-dev-tools/benchkit");
+        assert_eq!(default.git_ref, "master");
+        assert!(default.local.is_none());
+
+        let pinned: PatchConfig = serde_yaml::from_str(
+            r#"
+            repo: someone/benchkit
+            ref: v0.2.1
+            sha256:
+              0001-test.patch: ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad
+            "#,
+        )
+        .unwrap();
+        assert_eq!(pinned.repo, "someone/benchkit");
+        assert_eq!(pinned.git_ref, "v0.2.1");
+        assert_eq!(pinned.sha256.len(), 1);
     }
 
     #[test]
@@ -442,4 +1850,49 @@ mod tests {
         assert!(config.snapshot_dir.is_absolute());
         assert_eq!(config.path, config_path);
     }
+
+    #[test]
+    fn test_placeholder_validation() {
+        let examples = std::path::Path::new(env!("CARGO_MANIFEST_DIR"));
+        for example in ["benchmark.yml", "config-examples/parameter-matrix.yml"] {
+            parse_bench_config(&examples.join(example)).unwrap();
+        }
+
+        let tempdir = tempdir().unwrap();
+        let config_path = tempdir.path().join("benchmark.yml");
+        let write_config = |command: &str| {
+            let config = format!(
+                r#"
+global:
+  source: ./bitcoin
+  scratch: ./scratch
+  commits: ["abc"]
+  tmp_data_dir: ./data
+benchmarks:
+  - name: ibd
+    network: signet
+    benchmark:
+      command: "{command}"
+      parameter_lists:
+        - var: maxmempool
+          values: ["5", "300"]
+"#
+            );
+            fs::write(&config_path, config).unwrap();
+        };
+
+        write_config("bitcoind -maxmempool={maxmempool} -datadir={datadir} -par=${NPROC}");
+        load_bench_config(&config_path).unwrap();
+
+        write_config("bitcoind -dbcache={dbcache}");
+        let err = format!("{:#}", load_bench_config(&config_path).unwrap_err());
+        assert!(
+            err.contains(
+                "Benchmark ibd command: unknown placeholders {dbcache} (available: {binary}, \
+                 {commit}, {connect}, {datadir}, {iteration}, {maxmempool}, {network}, \
+                 {out_dir}, {snapshot}), parameters not used: {maxmempool}"
+            ),
+            "{err}"
+        );
+    }
 }
@@ -1,7 +1,9 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use hwloc::{CpuSet, ObjectType, Topology, CPUBIND_PROCESS};
 use log::{debug, info};
 
+use crate::config::parse_core_list;
+
 // Re-export these for backward compatibility, but new code should use the CommandExecutor API
 pub use crate::command::CommandExecutor;
 
@@ -57,36 +59,44 @@ impl CpuBinder {
     /// Roughyl mirrors taskset syntax
     fn parse_cores_spec(&self, cores_spec: &str) -> Result<CpuSet> {
         let mut cpuset = CpuSet::new();
+        for core in parse_core_list(cores_spec)? {
+            cpuset.set(core);
+        }
+        Ok(cpuset)
+    }
 
-        // Split by commas
-        for part in cores_spec.split(',') {
-            if part.contains('-') {
-                // Handle ranges like "0-3"
-                let range: Vec<&str> = part.split('-').collect();
-                if range.len() != 2 {
-                    anyhow::bail!("Invalid core range specification: {}", part);
-                }
-
-                let start = range[0]
-                    .parse::<u32>()
-                    .with_context(|| format!("Invalid core number: {}", range[0]))?;
-                let end = range[1]
-                    .parse::<u32>()
-                    .with_context(|| format!("Invalid core number: {}", range[1]))?;
+    /// Processing units of the machine with the core and NUMA node each belongs to
+    pub fn processing_units(&self) -> Result<Vec<ProcessingUnit>> {
+        let cores = self
+            .topology
+            .objects_with_type(&ObjectType::Core)
+            .map_err(|e| anyhow::anyhow!("Failed to read the cores of the topology: {e:?}"))?;
+        let numa_nodes = self
+            .topology
+            .objects_with_type(&ObjectType::NUMANode)
+            .unwrap_or_default();
 
-                for core in start..=end {
-                    cpuset.set(core);
-                }
-            } else {
-                // Handle single core like "5"
-                let core = part
-                    .parse::<u32>()
-                    .with_context(|| format!("Invalid core number: {part}"))?;
-                cpuset.set(core);
+        let mut pus = Vec::new();
+        for (core, object) in cores.iter().enumerate() {
+            let Some(cpuset) = object.cpuset() else {
+                continue;
+            };
+            for os_index in cpuset {
+                let numa_node = numa_nodes
+                    .iter()
+                    .position(|node| node.cpuset().is_some_and(|set| set.is_set(os_index)))
+                    .unwrap_or(0);
+                pus.push(ProcessingUnit {
+                    os_index,
+                    core: core as u32,
+                    numa_node: numa_node as u32,
+                });
             }
         }
-
-        Ok(cpuset)
+        if pus.is_empty() {
+            anyhow::bail!("No processing units found in the topology");
+        }
+        Ok(pus)
     }
 
     /// Get information about available cores
@@ -209,3 +219,130 @@ impl CpuBinder {
         Ok(())
     }
 }
+
+/// A processing unit (hardware thread) and where it sits in the topology
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProcessingUnit {
+    /// OS index, as used in core specs
+    pub os_index: u32,
+    /// Index of the physical core it belongs to, shared by SMT siblings
+    pub core: u32,
+    /// Index of the NUMA node it belongs to
+    pub numa_node: u32,
+}
+
+/// Cores isolated from the scheduler with `isolcpus` (Linux only)
+pub fn isolated_cores() -> Vec<u32> {
+    std::fs::read_to_string("/sys/devices/system/cpu/isolated")
+        .ok()
+        .map(|isolated| isolated.trim().to_string())
+        .filter(|isolated| !isolated.is_empty())
+        .and_then(|isolated| parse_core_list(&isolated).ok())
+        .unwrap_or_default()
+}
+
+/// Cores to bind benchmarks to for `benchmark_cores: auto`
+///
+/// Uses one processing unit of each physical core, so no SMT sibling shares a core with the
+/// benchmark, and leaves out the core of processing unit 0, which handles most interrupts and
+/// housekeeping. If any of the remaining cores are isolated only those are used. All cores come
+/// from the NUMA node with the most candidates, so the benchmark's memory stays local.
+pub fn select_benchmark_cores(pus: &[ProcessingUnit], isolated: &[u32]) -> Result<Vec<u32>> {
+    let housekeeping = pus.iter().find(|pu| pu.os_index == 0).map(|pu| pu.core);
+    let mut candidates: Vec<ProcessingUnit> = Vec::new();
+    for pu in pus {
+        if Some(pu.core) == housekeeping {
+            continue;
+        }
+        match candidates.iter_mut().find(|c| c.core == pu.core) {
+            Some(first) if first.os_index > pu.os_index => *first = *pu,
+            Some(_) => {}
+            None => candidates.push(*pu),
+        }
+    }
+    if candidates.iter().any(|pu| isolated.contains(&pu.os_index)) {
+        candidates.retain(|pu| isolated.contains(&pu.os_index));
+    }
+
+    let mut nodes: Vec<u32> = candidates.iter().map(|pu| pu.numa_node).collect();
+    nodes.sort_unstable();
+    nodes.dedup();
+    let count = |node: u32| candidates.iter().filter(|pu| pu.numa_node == node).count();
+    // The first of the nodes with the most candidates
+    let Some(node) = nodes.iter().copied().rev().max_by_key(|&node| count(node)) else {
+        anyhow::bail!("No cores left for benchmark_cores: auto besides the housekeeping core");
+    };
+
+    let mut cores: Vec<u32> = candidates
+        .iter()
+        .filter(|pu| pu.numa_node == node)
+        .map(|pu| pu.os_index)
+        .collect();
+    cores.sort_unstable();
+    Ok(cores)
+}
+
+/// Cores to bind benchkit to for `runner_cores: auto`: every processing unit of the cores the
+/// benchmark doesn't use
+pub fn complement_cores(pus: &[ProcessingUnit], benchmark_cores: &[u32]) -> Vec<u32> {
+    let used: Vec<u32> = pus
+        .iter()
+        .filter(|pu| benchmark_cores.contains(&pu.os_index))
+        .map(|pu| pu.core)
+        .collect();
+    let mut cores: Vec<u32> = pus
+        .iter()
+        .filter(|pu| !used.contains(&pu.core) && !benchmark_cores.contains(&pu.os_index))
+        .map(|pu| pu.os_index)
+        .collect();
+    cores.sort_unstable();
+    cores
+}
+
+/// Cores of a spec which aren't processing units of the machine
+pub fn missing_cores(cores: &[u32], pus: &[ProcessingUnit]) -> Vec<u32> {
+    cores
+        .iter()
+        .copied()
+        .filter(|core| !pus.iter().any(|pu| pu.os_index == *core))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two NUMA nodes of four cores with two SMT siblings each, numbered like Linux does:
+    /// 0-7 are the first siblings of each core and 8-15 the second
+    fn topology() -> Vec<ProcessingUnit> {
+        (0..16)
+            .map(|os_index| ProcessingUnit {
+                os_index,
+                core: os_index % 8,
+                numa_node: (os_index % 8) / 4,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_select_benchmark_cores() {
+        let pus = topology();
+
+        // The node with most cores left after core 0 is the second
+        let cores = select_benchmark_cores(&pus, &[]).unwrap();
+        assert_eq!(cores, [4, 5, 6, 7]);
+        assert_eq!(complement_cores(&pus, &cores), [0, 1, 2, 3, 8, 9, 10, 11]);
+
+        // Isolated cores are preferred
+        let cores = select_benchmark_cores(&pus, &[1, 2, 9, 10]).unwrap();
+        assert_eq!(cores, [1, 2]);
+        assert_eq!(
+            complement_cores(&pus, &cores),
+            [0, 3, 4, 5, 6, 7, 8, 11, 12, 13, 14, 15]
+        );
+
+        let single = &pus[..1];
+        assert!(select_benchmark_cores(single, &[]).is_err());
+        assert_eq!(missing_cores(&[0, 15, 16, 17], &pus), [16, 17]);
+    }
+}
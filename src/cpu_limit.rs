@@ -0,0 +1,87 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Period of the CPU bandwidth quota, in microseconds
+const CPU_PERIOD_US: u64 = 100_000;
+
+/// Scenarios modelling weaker hardware on a big machine, each added as a parameter axis so the
+/// limits are recorded in the results
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CpuLimits {
+    /// CPU bandwidth of the command's cgroup as a percentage of one core, like "400%" for four
+    /// cores' worth of time, or "max" for no limit, added as a `cpu_quota` axis (Linux only)
+    #[serde(default)]
+    pub quotas: Vec<String>,
+    /// Script verification threads, each passed to the node as `-par=<n>` and added as a `par`
+    /// axis
+    #[serde(default)]
+    pub par: Vec<u32>,
+}
+
+impl CpuLimits {
+    pub fn validate(&self) -> Result<()> {
+        if self.quotas.is_empty() && self.par.is_empty() {
+            anyhow::bail!("cpu_limits needs quotas or par");
+        }
+        if !self.quotas.is_empty() && cfg!(not(target_os = "linux")) {
+            anyhow::bail!("cpu_limits quotas are only supported on Linux");
+        }
+        for quota in &self.quotas {
+            cpu_max(quota)?;
+        }
+        Ok(())
+    }
+}
+
+/// The `cpu.max` of a quota like "400%", e.g. "400000 100000", or "max" for no limit
+pub fn cpu_max(quota: &str) -> Result<String> {
+    if quota == "max" {
+        return Ok("max".to_string());
+    }
+    let percent: f64 = quota
+        .strip_suffix('%')
+        .and_then(|percent| percent.trim().parse().ok())
+        .with_context(|| format!("Invalid cpu_limits quota '{quota}', expected e.g. \"400%\""))?;
+    if !(percent.is_finite() && percent >= 1.0) {
+        anyhow::bail!("cpu_limits quota must be at least 1%, got '{quota}'");
+    }
+    let quota_us = (percent / 100.0 * CPU_PERIOD_US as f64).round() as u64;
+    Ok(format!("{quota_us} {CPU_PERIOD_US}"))
+}
+
+/// The command with `-par` set to `par`, appended so it overrides any earlier value
+pub fn par_command(command: &str, par: &str) -> String {
+    format!("{command} -par={par}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu_limits() {
+        assert_eq!(cpu_max("400%").unwrap(), "400000 100000");
+        assert_eq!(cpu_max("150.5%").unwrap(), "150500 100000");
+        assert_eq!(cpu_max("max").unwrap(), "max");
+        assert!(cpu_max("400").is_err());
+        assert!(cpu_max("0.5%").is_err());
+        assert!(cpu_max("-100%").is_err());
+        assert_eq!(
+            par_command("bitcoind -par=8", "2"),
+            "bitcoind -par=8 -par=2"
+        );
+
+        assert!(CpuLimits::default().validate().is_err());
+        let par_only = CpuLimits {
+            quotas: Vec::new(),
+            par: vec![1, 4],
+        };
+        assert!(par_only.validate().is_ok());
+        let invalid = CpuLimits {
+            quotas: vec!["4 cores".to_string()],
+            par: Vec::new(),
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use log::info;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::benchmarks::sha256_file;
+use crate::command::CommandExecutor;
+use crate::download::download_file;
+use crate::path_utils;
+
+/// File recording the sha256 a dataset was verified against, marking it as complete
+const MARKER_FILE: &str = ".benchkit-dataset";
+
+/// A named static dataset (e.g. raw block files or qa-assets) used by benchmarks
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DatasetConfig {
+    /// Name used in `{dataset:<name>}` placeholders and `benchkit dataset download <name>`
+    pub name: String,
+    /// URL to download the dataset from
+    pub url: String,
+    /// Expected sha256 of the downloaded file
+    pub sha256: String,
+    /// Unpack the downloaded tar archive, so the placeholder refers to the extracted directory
+    #[serde(default)]
+    pub extract: bool,
+}
+
+impl DatasetConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.contains(['/', '}']) {
+            anyhow::bail!("Invalid dataset name: {:?}", self.name);
+        }
+        if self.sha256.len() != 64 || !self.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            anyhow::bail!("Dataset {} sha256 must be 64 hex characters", self.name);
+        }
+        if self.file_name().is_empty() {
+            anyhow::bail!("Dataset {} URL has no file name: {}", self.name, self.url);
+        }
+        Ok(())
+    }
+
+    /// Name of the downloaded file, taken from the URL
+    fn file_name(&self) -> &str {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        path.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// Directory the dataset is cached in
+    pub fn dir(&self, dataset_dir: &Path) -> PathBuf {
+        dataset_dir.join(&self.name)
+    }
+
+    /// Path a benchmark uses: the extracted directory, or the downloaded file
+    pub fn path(&self, dataset_dir: &Path) -> PathBuf {
+        if self.extract {
+            self.dir(dataset_dir)
+        } else {
+            self.dir(dataset_dir).join(self.file_name())
+        }
+    }
+
+    /// Whether the dataset has been downloaded and verified against its current sha256
+    pub fn is_cached(&self, dataset_dir: &Path) -> bool {
+        std::fs::read_to_string(self.dir(dataset_dir).join(MARKER_FILE))
+            .is_ok_and(|sha256| sha256.trim().eq_ignore_ascii_case(&self.sha256))
+    }
+
+    /// Download, verify and (if configured) extract the dataset, unless it is already cached
+    pub fn download(&self, dataset_dir: &Path) -> Result<PathBuf> {
+        if self.is_cached(dataset_dir) {
+            info!("Dataset {} is already downloaded", self.name);
+            return Ok(self.path(dataset_dir));
+        }
+
+        // Start from scratch so files from an older version of the dataset don't linger
+        let dir = self.dir(dataset_dir);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)
+                .with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        path_utils::ensure_directory(&dir)?;
+
+        let file = dir.join(self.file_name());
+        download_file(&self.url, &file)
+            .with_context(|| format!("Failed to download dataset {}", self.name))?;
+
+        let sha256 = sha256_file(&file)?;
+        if !sha256.eq_ignore_ascii_case(&self.sha256) {
+            std::fs::remove_file(&file)?;
+            anyhow::bail!(
+                "Dataset {} has sha256 {sha256}, expected {}",
+                self.name,
+                self.sha256
+            );
+        }
+
+        if self.extract {
+            info!("Extracting {}", file.display());
+            CommandExecutor::builder()
+                .name(format!("extract dataset {}", self.name))
+                .working_dir(Some(&dir))
+                .build()?
+                .execute_command_with_args("tar", &["-xf", &file.to_string_lossy()])
+                .with_context(|| format!("Failed to extract dataset {}", self.name))?;
+            std::fs::remove_file(&file)?;
+        }
+
+        std::fs::write(dir.join(MARKER_FILE), &self.sha256)?;
+        info!(
+            "Dataset {} is available at {}",
+            self.name,
+            self.path(dataset_dir).display()
+        );
+        Ok(self.path(dataset_dir))
+    }
+}
+
+/// Replace `{dataset:<name>}` placeholders with the paths of downloaded datasets
+pub fn expand_dataset_placeholders(
+    template: &str,
+    datasets: &[DatasetConfig],
+    dataset_dir: &Path,
+) -> Result<String> {
+    let placeholder = Regex::new(r"\{dataset:([^}]*)\}").unwrap();
+    let mut error = None;
+    let expanded = placeholder.replace_all(template, |caps: &regex::Captures| {
+        let name = &caps[1];
+        match datasets.iter().find(|d| d.name == name) {
+            Some(dataset) if dataset.is_cached(dataset_dir) => {
+                dataset.path(dataset_dir).display().to_string()
+            }
+            Some(_) => {
+                error.get_or_insert_with(|| {
+                    anyhow::anyhow!(
+                        "Dataset {name} has not been downloaded, \
+                         run `benchkit dataset download {name}`"
+                    )
+                });
+                String::new()
+            }
+            None => {
+                error.get_or_insert_with(|| anyhow::anyhow!("Unknown dataset: {name}"));
+                String::new()
+            }
+        }
+    });
+    match error {
+        Some(error) => Err(error),
+        None => Ok(expanded.into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn dataset(name: &str, extract: bool) -> DatasetConfig {
+        DatasetConfig {
+            name: name.to_string(),
+            url: format!("https://example.com/{name}.tar.gz?download=1"),
+            sha256: "ab".repeat(32),
+            extract,
+        }
+    }
+
+    #[test]
+    fn test_dataset_paths() {
+        let dir = Path::new("/datasets");
+        let blocks = dataset("blocks", false);
+        assert!(blocks.validate().is_ok());
+        assert_eq!(
+            blocks.path(dir),
+            PathBuf::from("/datasets/blocks/blocks.tar.gz")
+        );
+        assert_eq!(
+            dataset("qa-assets", true).path(dir),
+            PathBuf::from("/datasets/qa-assets")
+        );
+
+        assert!(DatasetConfig {
+            sha256: "abc".to_string(),
+            ..blocks.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(dataset("a/b", false).validate().is_err());
+    }
+
+    #[test]
+    fn test_expand_dataset_placeholders() {
+        let temp_dir = tempdir().unwrap();
+        let datasets = vec![dataset("blocks", false), dataset("qa-assets", true)];
+
+        let template = "bitcoind -loadblock={dataset:blocks}";
+        let err = expand_dataset_placeholders(template, &datasets, temp_dir.path()).unwrap_err();
+        assert!(err.to_string().contains("benchkit dataset download blocks"));
+
+        let blocks_dir = datasets[0].dir(temp_dir.path());
+        std::fs::create_dir_all(&blocks_dir).unwrap();
+        std::fs::write(blocks_dir.join(MARKER_FILE), "ab".repeat(32)).unwrap();
+        assert!(datasets[0].is_cached(temp_dir.path()));
+        assert_eq!(
+            expand_dataset_placeholders(template, &datasets, temp_dir.path()).unwrap(),
+            format!(
+                "bitcoind -loadblock={}",
+                blocks_dir.join("blocks.tar.gz").display()
+            )
+        );
+
+        let err = expand_dataset_placeholders("{dataset:missing}", &datasets, temp_dir.path())
+            .unwrap_err();
+        assert!(err.to_string().contains("Unknown dataset"));
+
+        // A changed sha256 invalidates the cached copy
+        let mut changed = datasets[0].clone();
+        changed.sha256 = "cd".repeat(32);
+        assert!(!changed.is_cached(temp_dir.path()));
+    }
+}
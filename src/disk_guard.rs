@@ -0,0 +1,221 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::BenchkitError;
+
+/// How often a watch checks whether it was stopped while waiting for the next check
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Free space the datadir and output directory filesystems must keep while the benchmark
+/// command runs (Unix only)
+///
+/// bitcoind running out of space fails with an I/O error and can leave a corrupt datadir, so
+/// the command is stopped gracefully before that happens.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DiskGuard {
+    /// Stop the run when either filesystem has less than this many MiB free
+    pub min_free_mb: u64,
+    /// Seconds between checks
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+impl DiskGuard {
+    pub fn validate(&self) -> Result<()> {
+        if cfg!(not(unix)) {
+            anyhow::bail!("disk_guard is only supported on Unix");
+        }
+        if self.min_free_mb == 0 {
+            anyhow::bail!("disk_guard min_free_mb cannot be zero");
+        }
+        if self.interval == 0 {
+            anyhow::bail!("disk_guard interval cannot be zero");
+        }
+        Ok(())
+    }
+
+    /// Fail with a `DiskFull` error if the filesystem of any of `paths` is below the minimum
+    pub fn check(&self, paths: &[PathBuf]) -> Result<()> {
+        for path in paths {
+            let free_mb = free_bytes(path)? / (1024 * 1024);
+            debug!("{free_mb} MiB free on the filesystem of {}", path.display());
+            if free_mb < self.min_free_mb {
+                return Err(BenchkitError::DiskFull {
+                    path: path.clone(),
+                    free_mb,
+                    min_free_mb: self.min_free_mb,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the filesystems of `paths` every interval on another thread, terminating the
+    /// process group in `group` once one runs low
+    ///
+    /// `group` holds the pid of the group leader currently running, 0 while there is none.
+    pub fn watch(&self, paths: Vec<PathBuf>, group: Arc<AtomicU32>) -> DiskWatch {
+        let guard = self.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let tripped = Arc::new(Mutex::new(None));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let tripped = Arc::clone(&tripped);
+            thread::spawn(move || {
+                let interval = Duration::from_secs(guard.interval);
+                while !stop.load(Ordering::SeqCst) {
+                    let error = match guard.check(&paths) {
+                        Ok(()) => None,
+                        Err(error) if BenchkitError::find(&error).is_some() => Some(error),
+                        Err(error) => {
+                            debug!("Failed to check free disk space: {error:#}");
+                            None
+                        }
+                    };
+                    if let Some(error) = error {
+                        warn!("{error}, stopping the run");
+                        let pid = group.load(Ordering::SeqCst);
+                        *tripped.lock().unwrap() = Some(error);
+                        #[cfg(unix)]
+                        if pid != 0 {
+                            if let Err(e) =
+                                crate::process::terminate_group(pid, crate::process::GRACE_PERIOD)
+                            {
+                                warn!("Failed to stop the run: {e:#}");
+                            }
+                        }
+                        return;
+                    }
+                    let mut waited = Duration::ZERO;
+                    while waited < interval && !stop.load(Ordering::SeqCst) {
+                        thread::sleep(STOP_POLL_INTERVAL);
+                        waited += STOP_POLL_INTERVAL;
+                    }
+                }
+            })
+        };
+        DiskWatch {
+            stop,
+            tripped,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// Free space checks running alongside a command, which stop when it is dropped
+pub struct DiskWatch {
+    stop: Arc<AtomicBool>,
+    tripped: Arc<Mutex<Option<anyhow::Error>>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DiskWatch {
+    /// Stop checking, failing with the `DiskFull` error the command was stopped for, if it was
+    pub fn finish(mut self) -> Result<()> {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                debug!("Disk watch thread panicked");
+            }
+        }
+        match self.tripped.lock().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for DiskWatch {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Bytes available to unprivileged users on the filesystem of a path, or of its closest
+/// existing ancestor if it doesn't exist yet
+#[cfg(unix)]
+pub fn free_bytes(path: &Path) -> Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(Path::new("/"));
+    let c_path = std::ffi::CString::new(existing.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path {}", existing.display()))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("Failed to statvfs {}", existing.display()));
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_bytes(path: &Path) -> Result<u64> {
+    anyhow::bail!("Free space of {} is only known on Unix", path.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_disk_guard() {
+        let dir = tempfile::tempdir().unwrap();
+        // Directories which don't exist yet are measured on their parent's filesystem
+        let datadir = dir.path().join("data/0");
+        assert!(free_bytes(&datadir).unwrap() > 0);
+
+        let roomy = DiskGuard {
+            min_free_mb: 1,
+            interval: 1,
+        };
+        assert!(roomy.validate().is_ok());
+        assert!(roomy.check(std::slice::from_ref(&datadir)).is_ok());
+
+        let greedy = DiskGuard {
+            min_free_mb: u64::MAX,
+            interval: 1,
+        };
+        let error = greedy.check(std::slice::from_ref(&datadir)).unwrap_err();
+        assert!(matches!(
+            BenchkitError::find(&error),
+            Some(BenchkitError::DiskFull { .. })
+        ));
+        assert!(error.to_string().starts_with("Disk full imminent"));
+
+        // A watch that trips stops the running group
+        use std::os::unix::process::CommandExt;
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let watch = greedy.watch(vec![datadir], Arc::new(AtomicU32::new(child.id())));
+        let status = child.wait().unwrap();
+        assert!(!status.success());
+        assert!(watch.finish().is_err());
+
+        assert!(DiskGuard {
+            min_free_mb: 0,
+            interval: 1
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -28,6 +28,7 @@ impl SnapshotInfo {
                 filename: "utxo-signet-160000.dat",
                 height: 160000,
             }),
+            Network::Testnet | Network::Testnet4 | Network::Regtest => None,
         }
     }
 }
@@ -43,12 +44,16 @@ pub fn download_snapshot(network: &Network, snapshot_dir: &Path) -> Result<()> {
     let filename = snapshot_info.filename;
 
     let url = format!("{SNAPSHOT_HOST}{filename}");
+    download_file(&url, &snapshot_dir.join(filename))
+}
+
+/// Download a URL to a file, showing a progress bar
+pub fn download_file(url: &str, filepath: &Path) -> Result<()> {
     let client = Client::new();
-    let filepath = snapshot_dir.join(filename);
     info!("Downloading {url} to {filepath:?}");
 
     // Get the content length for the progress bar
-    let response = client.get(&url).send()?;
+    let response = client.get(url).send()?.error_for_status()?;
     let total_size = response.content_length().unwrap_or(0);
 
     let pb = ProgressBar::new(total_size);
@@ -59,7 +64,7 @@ pub fn download_snapshot(network: &Network, snapshot_dir: &Path) -> Result<()> {
             .progress_chars("⟨⟨⟨⟨⟨····· "),
     );
 
-    let mut file = File::create(&filepath)?;
+    let mut file = File::create(filepath)?;
     let mut downloaded = 0u64;
     let mut stream = response;
 
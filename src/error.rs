@@ -0,0 +1,111 @@
+//! Error categories for programmatic consumers of the library
+//!
+//! Library functions return `anyhow::Result`, so errors keep their context chain. Failures in
+//! configuration, building, running and hooks carry a [`BenchkitError`] in that chain, which
+//! [`BenchkitError::find`] recovers.
+
+use std::path::PathBuf;
+use thiserror::Error;
+
+use crate::benchmarks::HookStage;
+
+/// Category of a benchkit failure
+#[derive(Debug, Error)]
+pub enum BenchkitError {
+    /// A configuration file could not be loaded or is invalid
+    #[error("Invalid configuration in {}", path.display())]
+    Config {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// Building Bitcoin Core failed for a commit
+    #[error("Failed to build commit {commit}")]
+    Build {
+        commit: String,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// A benchmark could not be run to completion
+    #[error("Benchmark {name} failed")]
+    Benchmark {
+        name: String,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// A lifecycle hook failed
+    #[error("{stage:?} hook failed")]
+    Hook {
+        stage: HookStage,
+        #[source]
+        source: anyhow::Error,
+    },
+
+    /// A filesystem the benchmark writes to ran low on space, so the run was stopped
+    #[error(
+        "Disk full imminent: {free_mb} MiB free on the filesystem of {}, below min_free_mb of \
+         {min_free_mb} MiB",
+        path.display()
+    )]
+    DiskFull {
+        path: PathBuf,
+        free_mb: u64,
+        min_free_mb: u64,
+    },
+
+    /// An external command exited unsuccessfully
+    #[error("Command failed with status {exit_code}: {command}\nStderr: {stderr}")]
+    Command {
+        command: String,
+        exit_code: i32,
+        stderr: String,
+    },
+}
+
+impl BenchkitError {
+    /// The outermost benchkit error in an error's chain, if any
+    ///
+    /// Later errors in the chain are more specific causes, e.g. the failed `Command` behind a
+    /// `Hook` failure, and can be found by walking `error.chain()`.
+    pub fn find(error: &anyhow::Error) -> Option<&BenchkitError> {
+        error.chain().find_map(|cause| cause.downcast_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let command = BenchkitError::Command {
+            command: "false".to_string(),
+            exit_code: 1,
+            stderr: String::new(),
+        };
+        let error = anyhow::Error::new(BenchkitError::Hook {
+            stage: HookStage::Setup,
+            source: command.into(),
+        })
+        .context("Running benchmarks");
+
+        assert!(matches!(
+            BenchkitError::find(&error),
+            Some(BenchkitError::Hook {
+                stage: HookStage::Setup,
+                ..
+            })
+        ));
+        assert!(matches!(
+            error
+                .chain()
+                .filter_map(|cause| cause.downcast_ref::<BenchkitError>())
+                .nth(1),
+            Some(BenchkitError::Command { exit_code: 1, .. })
+        ));
+        assert!(BenchkitError::find(&anyhow::anyhow!("other")).is_none());
+    }
+}
@@ -0,0 +1,748 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::benchmarks::{RepoSource, ValuesFrom};
+use crate::config::BenchmarkConfig;
+use crate::path_utils;
+use crate::reference::ReferenceBinary;
+use crate::system::{SystemCheck, SystemChecker};
+use crate::system_info::SystemInfo;
+
+/// Current version of the job envelope format
+///
+/// Version 2 length-prefixes the signed fields and always signs `require_tuned`. Signatures of
+/// older envelopes aren't accepted.
+pub const JOB_ENVELOPE_VERSION: u32 = 2;
+
+/// A benchmark job as sent between machines
+///
+/// Unknown fields are ignored when deserializing so that older clients can accept envelopes
+/// from newer submitters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobEnvelope {
+    /// Envelope format version
+    pub version: u32,
+    /// Unique identifier of the job
+    pub job_id: String,
+    /// Who submitted the job
+    pub submitter: String,
+    /// Unix timestamp of when the job was created
+    pub created_at: u64,
+    /// Benchmark configuration (YAML)
+    pub payload: String,
+    /// Only run the job on a worker whose required system checks pass
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_tuned: bool,
+    /// Signature over the envelope, if signed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<JobSignature>,
+}
+
+/// Ed25519 signature of a job envelope
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSignature {
+    /// Hex-encoded public key of the signer
+    pub public_key: String,
+    /// Hex-encoded signature
+    pub signature: String,
+}
+
+impl JobEnvelope {
+    /// Create an unsigned envelope for a benchmark configuration
+    pub fn new(job_id: impl Into<String>, submitter: impl Into<String>, payload: String) -> Self {
+        Self {
+            version: JOB_ENVELOPE_VERSION,
+            job_id: job_id.into(),
+            submitter: submitter.into(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            payload,
+            require_tuned: false,
+            signature: None,
+        }
+    }
+
+    /// Require the worker to be tuned for benchmarking
+    pub fn require_tuned(mut self, require_tuned: bool) -> Self {
+        self.require_tuned = require_tuned;
+        self
+    }
+
+    /// Parse an envelope from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        let envelope: Self = serde_json::from_str(json).context("Failed to parse job envelope")?;
+        if envelope.version > JOB_ENVELOPE_VERSION {
+            warn!(
+                "Job {} uses envelope version {}, newer than supported version {}",
+                envelope.job_id, envelope.version, JOB_ENVELOPE_VERSION
+            );
+        }
+        Ok(envelope)
+    }
+
+    /// Serialize the envelope to JSON
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).context("Failed to serialize job envelope")
+    }
+
+    /// Bytes covered by the signature
+    ///
+    /// Each field is prefixed with its length, so no field's contents can shift the boundaries
+    /// of another.
+    fn signing_message(&self) -> Vec<u8> {
+        let version = self.version.to_string();
+        let created_at = self.created_at.to_string();
+        let require_tuned = format!("require_tuned={}", u8::from(self.require_tuned));
+        let fields = [
+            version.as_bytes(),
+            self.job_id.as_bytes(),
+            self.submitter.as_bytes(),
+            created_at.as_bytes(),
+            require_tuned.as_bytes(),
+            self.payload.as_bytes(),
+        ];
+
+        let mut message = b"benchkit-job".to_vec();
+        for field in fields {
+            message.extend_from_slice(&(field.len() as u64).to_be_bytes());
+            message.extend_from_slice(field);
+        }
+        message
+    }
+
+    /// Sign the envelope, replacing any existing signature
+    pub fn sign(&mut self, key: &SigningKey) {
+        let signature = key.sign(&self.signing_message());
+        self.signature = Some(JobSignature {
+            public_key: encode_hex(key.verifying_key().as_bytes()),
+            signature: encode_hex(&signature.to_bytes()),
+        });
+    }
+
+    /// Check the envelope's signature
+    ///
+    /// With no trusted keys, unsigned envelopes are accepted and signed ones only need a valid
+    /// signature. Otherwise the envelope must be signed by one of the trusted keys.
+    pub fn verify(&self, trusted_keys: &[VerifyingKey]) -> Result<()> {
+        let Some(signature) = &self.signature else {
+            if trusted_keys.is_empty() {
+                return Ok(());
+            }
+            anyhow::bail!(
+                "Job {} is unsigned, but trusted keys are configured",
+                self.job_id
+            );
+        };
+
+        if self.version < JOB_ENVELOPE_VERSION {
+            anyhow::bail!(
+                "Job {} is signed in envelope version {}, whose signatures are no longer \
+                 accepted, it needs to be signed again",
+                self.job_id,
+                self.version
+            );
+        }
+        let public_key = parse_public_key(&signature.public_key)?;
+        if !trusted_keys.is_empty() && !trusted_keys.contains(&public_key) {
+            anyhow::bail!(
+                "Job {} is signed by untrusted key {}",
+                self.job_id,
+                signature.public_key
+            );
+        }
+
+        let bytes = decode_hex(&signature.signature)?;
+        let signature = Signature::from_slice(&bytes).context("Invalid job signature")?;
+        public_key
+            .verify(&self.signing_message(), &signature)
+            .with_context(|| format!("Signature verification failed for job {}", self.job_id))
+    }
+
+    /// Check a worker may run the job, given the report it made when the job started
+    pub fn check_worker(&self, report: &WorkerReport) -> Result<()> {
+        let failed = report.failed_checks();
+        if !self.require_tuned || failed.is_empty() {
+            return Ok(());
+        }
+        let mut msg = format!(
+            "Job {} requires a tuned machine, but worker {} is not:\n",
+            self.job_id, report.fingerprint
+        );
+        for check in failed {
+            msg.push_str(&format!("  {check}\n"));
+        }
+        anyhow::bail!(msg)
+    }
+}
+
+/// File in a job's workspace holding the report of the worker it ran on
+pub const WORKER_REPORT_FILE: &str = "worker.json";
+
+/// State of the machine a job runs on, attached to the job's status and results
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkerReport {
+    /// Fingerprint of the worker machine (see `SystemInfo`)
+    pub fingerprint: String,
+    /// Results of the system check suite when the job started
+    pub system_checks: Vec<SystemCheck>,
+}
+
+impl WorkerReport {
+    /// Run the system checks on this machine, with the storage of `data_dir` in its fingerprint
+    pub fn collect(data_dir: &Path) -> Result<Self> {
+        let system_checks = SystemChecker::new()
+            .and_then(|checker| checker.checks())
+            .context("Failed to run system checks")?;
+        Ok(Self {
+            fingerprint: SystemInfo::collect(data_dir).fingerprint,
+            system_checks,
+        })
+    }
+
+    /// Checks that `require_tuned` needs to pass but which failed
+    pub fn failed_checks(&self) -> Vec<&SystemCheck> {
+        self.system_checks
+            .iter()
+            .filter(|check| check.required && !check.ok)
+            .collect()
+    }
+}
+
+/// An isolated directory a job runs in, `<job_dir>/<job id>`, with its own scratch, data and
+/// output directories
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobWorkspace {
+    root: PathBuf,
+}
+
+impl JobWorkspace {
+    /// Create the workspace of a job, refusing job ids which aren't a plain directory name
+    pub fn create(job_dir: &Path, job_id: &str) -> Result<Self> {
+        let valid = !job_id.is_empty()
+            && job_id.len() <= 128
+            && !job_id.starts_with('.')
+            && job_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            anyhow::bail!("Job id '{job_id}' can't be used as a workspace directory name");
+        }
+        let workspace = Self {
+            root: job_dir.join(job_id),
+        };
+        for dir in [
+            workspace.root.clone(),
+            workspace.scratch(),
+            workspace.data_dir(),
+            workspace.out_dir(),
+        ] {
+            path_utils::ensure_directory(&dir)?;
+        }
+        // Resolve symlinks once, so confined paths are compared against the real location
+        let root = workspace.root.canonicalize()?;
+        Ok(Self { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Scratch directory for the job's repository clones and builds
+    pub fn scratch(&self) -> PathBuf {
+        self.root.join("scratch")
+    }
+
+    /// Directory node datadirs are created in, the job's `tmp_data_dir`
+    pub fn data_dir(&self) -> PathBuf {
+        self.root.join("data")
+    }
+
+    /// Output directory of the job's run
+    pub fn out_dir(&self) -> PathBuf {
+        self.root.join("out")
+    }
+
+    /// Write the job's benchmark configuration into the workspace, so relative paths in it are
+    /// resolved inside the workspace when it is loaded
+    ///
+    /// `values_from` files are read while loading the configuration, before `confine` sees it,
+    /// so they're refused here if they're outside the workspace.
+    pub fn write_config(&self, envelope: &JobEnvelope) -> Result<PathBuf> {
+        let config: serde_yaml::Value = serde_yaml::from_str(&envelope.payload)
+            .with_context(|| format!("Failed to parse the config of job {}", envelope.job_id))?;
+        for file in values_from_files(&config) {
+            self.contain(&file)
+                .context("A values_from file of the job is outside its workspace")?;
+        }
+
+        let path = self.root.join("benchmark.yml");
+        std::fs::write(&path, &envelope.payload)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Start a job in the workspace, refusing it if it requires a tuned worker and this one
+    /// isn't
+    ///
+    /// The worker's report is written to the workspace either way, so a refused job's status
+    /// tells which checks failed.
+    pub fn start(&self, envelope: &JobEnvelope) -> Result<WorkerReport> {
+        let report = WorkerReport::collect(&self.data_dir())?;
+        self.write_report(&report)?;
+        envelope.check_worker(&report)?;
+        Ok(report)
+    }
+
+    /// Attach the worker's report to the job, next to its output directory
+    pub fn write_report(&self, report: &WorkerReport) -> Result<PathBuf> {
+        let path = self.root.join(WORKER_REPORT_FILE);
+        std::fs::write(&path, serde_json::to_string_pretty(report)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Point a job's configuration at the workspace
+    ///
+    /// The scratch and data directories are always the workspace's own. Every other path the
+    /// job supplies must be inside the workspace, so a local `source` repository, local patches,
+    /// a reference binary and loadblock files are resolved within it and exports must be written
+    /// into it. Remote repositories are cloned into its scratch, and loadblock files may still
+    /// be in the worker's datasets.
+    pub fn confine(&self, config: &mut BenchmarkConfig) -> Result<()> {
+        let global = &mut config.global;
+        global.scratch = self.scratch();
+        global.tmp_data_dir = self.data_dir();
+        if let RepoSource::Local(source) = RepoSource::new(&global.source.to_string_lossy()) {
+            global.source = self
+                .contain(&source)
+                .context("The job's source repository is outside its workspace")?;
+        }
+        if let Some(local) = &global.patches.local {
+            global.patches.local = Some(
+                self.contain(local)
+                    .context("The job's patches are outside its workspace")?,
+            );
+        }
+        if let Some(reference) = &global.reference_binary {
+            if let ReferenceBinary::Path(path) = ReferenceBinary::parse(reference)? {
+                let path = self
+                    .contain(&path)
+                    .context("The job's reference binary is outside its workspace")?;
+                global.reference_binary = Some(path.to_string_lossy().into_owned());
+            }
+        }
+        for export in &global.exports {
+            self.contain(&export.path(&self.out_dir()))
+                .context("An export of the job is outside its workspace")?;
+        }
+
+        for bench in &mut config.benchmarks {
+            for file in &mut bench.loadblock {
+                // Datasets are downloaded into the worker's dataset directory
+                let in_dataset = file
+                    .strip_prefix("{dataset:")
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(_, rest)| rest);
+                match in_dataset {
+                    Some(rest) => {
+                        if Path::new(rest)
+                            .components()
+                            .any(|c| c == Component::ParentDir)
+                        {
+                            anyhow::bail!(
+                                "Loadblock file {file} of benchmark {} leaves its dataset",
+                                bench.name
+                            );
+                        }
+                    }
+                    None => {
+                        *file = self
+                            .contain(Path::new(file))
+                            .with_context(|| {
+                                format!(
+                                    "A loadblock file of benchmark {} is outside the job's \
+                                     workspace",
+                                    bench.name
+                                )
+                            })?
+                            .to_string_lossy()
+                            .into_owned();
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve a path the job supplied, relative to the workspace unless absolute, refusing
+    /// any which lead out of it, including through symlinks
+    pub fn contain(&self, path: &Path) -> Result<PathBuf> {
+        let joined = self.root.join(path);
+        let mut normalized = PathBuf::new();
+        for component in joined.components() {
+            match component {
+                Component::ParentDir => {
+                    normalized.pop();
+                }
+                Component::CurDir => {}
+                other => normalized.push(other),
+            }
+        }
+        // The longest existing prefix decides where the path really leads
+        let existing = normalized
+            .ancestors()
+            .find(|dir| dir.exists())
+            .unwrap_or(&normalized);
+        let real = existing.canonicalize()?;
+        if !normalized.starts_with(&self.root) || !real.starts_with(&self.root) {
+            anyhow::bail!(
+                "{} is outside the job workspace {}",
+                path.display(),
+                self.root.display()
+            );
+        }
+        Ok(normalized)
+    }
+}
+
+/// The `values_from` files of the parameter lists in a benchmark configuration
+fn values_from_files(config: &serde_yaml::Value) -> Vec<PathBuf> {
+    let global = config
+        .get("global")
+        .and_then(|global| global.get("benchmark"));
+    let benchmarks = config
+        .get("benchmarks")
+        .and_then(|benchmarks| benchmarks.as_sequence())
+        .into_iter()
+        .flatten()
+        .filter_map(|bench| bench.get("benchmark"));
+    global
+        .into_iter()
+        .chain(benchmarks)
+        .filter_map(|options| options.get("parameter_lists")?.as_sequence())
+        .flatten()
+        .filter_map(|list| serde_yaml::from_value(list.get("values_from")?.clone()).ok())
+        .map(|from| match from {
+            ValuesFrom::Lines(file) | ValuesFrom::Column { file, .. } => file,
+        })
+        .collect()
+}
+
+/// How long finished job workspaces are kept
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobRetention {
+    /// Keep at most this many of the most recent workspaces
+    pub keep_last: Option<usize>,
+    /// Remove workspaces not modified for this many days
+    pub max_age_days: Option<u64>,
+}
+
+impl JobRetention {
+    /// Remove the workspaces in `job_dir` the policy no longer keeps, returning their paths
+    pub fn prune(&self, job_dir: &Path) -> Result<Vec<PathBuf>> {
+        if !job_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut workspaces: Vec<(SystemTime, PathBuf)> = std::fs::read_dir(job_dir)?
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let metadata = entry.metadata().ok()?;
+                if !metadata.is_dir() {
+                    return None;
+                }
+                Some((metadata.modified().ok()?, entry.path()))
+            })
+            .collect();
+        // Newest first
+        workspaces.sort_by(|a, b| b.cmp(a));
+
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+        for (i, (modified, path)) in workspaces.into_iter().enumerate() {
+            let too_many = self.keep_last.is_some_and(|keep| i >= keep);
+            let too_old = self.max_age_days.is_some_and(|days| {
+                now.duration_since(modified)
+                    .is_ok_and(|age| age > Duration::from_secs(days * 86400))
+            });
+            if too_many || too_old {
+                std::fs::remove_dir_all(&path).with_context(|| {
+                    format!("Failed to remove job workspace {}", path.display())
+                })?;
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Parse a hex-encoded Ed25519 public key
+pub fn parse_public_key(hex: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = decode_hex(hex)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Public key must be 32 bytes: {}", hex))?;
+    VerifyingKey::from_bytes(&bytes).with_context(|| format!("Invalid public key: {hex}"))
+}
+
+/// Parse a list of hex-encoded Ed25519 public keys
+pub fn parse_trusted_keys(keys: &[String]) -> Result<Vec<VerifyingKey>> {
+    keys.iter().map(|key| parse_public_key(key)).collect()
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        anyhow::bail!("Invalid hex string: {}", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid hex string: {}", hex))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::benchmarks::{ExportConfig, ExportFormat};
+    use tempfile::tempdir;
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = signing_key(1);
+        let mut envelope = JobEnvelope::new("job-1", "alice", "global: {}".to_string());
+
+        // Unsigned envelopes are only accepted without trusted keys
+        assert!(envelope.verify(&[]).is_ok());
+        assert!(envelope.verify(&[key.verifying_key()]).is_err());
+
+        envelope.sign(&key);
+        let envelope = JobEnvelope::from_json(&envelope.to_json().unwrap()).unwrap();
+        assert!(envelope.verify(&[]).is_ok());
+        assert!(envelope.verify(&[key.verifying_key()]).is_ok());
+        assert!(envelope.verify(&[signing_key(2).verifying_key()]).is_err());
+
+        let mut tampered = envelope.clone();
+        tampered.payload = "global: {tampered: true}".to_string();
+        assert!(tampered.verify(&[key.verifying_key()]).is_err());
+
+        // Moving bytes between fields changes the signed message
+        let mut shifted = envelope.clone();
+        shifted.job_id = "job-1\nalice".to_string();
+        shifted.submitter = String::new();
+        assert!(shifted.verify(&[key.verifying_key()]).is_err());
+
+        let mut old = envelope.clone();
+        old.version = 1;
+        old.sign(&key);
+        assert!(old.verify(&[]).is_err());
+    }
+
+    #[test]
+    fn test_require_tuned_is_signed() {
+        let key = signing_key(4);
+        let mut signed =
+            JobEnvelope::new("job-3", "alice", "global: {}".to_string()).require_tuned(true);
+        signed.sign(&key);
+        assert!(signed.verify(&[key.verifying_key()]).is_ok());
+
+        let mut stripped = signed.clone();
+        stripped.require_tuned = false;
+        assert!(stripped.verify(&[key.verifying_key()]).is_err());
+        // Nor can the flag be moved into the payload
+        stripped.payload = format!("require_tuned\n{}", signed.payload);
+        assert!(stripped.verify(&[key.verifying_key()]).is_err());
+    }
+
+    #[test]
+    fn test_require_tuned_worker() {
+        let check = |name: &str, ok: bool| SystemCheck {
+            section: "CPU".to_string(),
+            name: name.to_string(),
+            value: String::new(),
+            want: String::new(),
+            ok,
+            required: true,
+            detail: None,
+        };
+        let tuned = WorkerReport {
+            fingerprint: "0123456789abcdef".to_string(),
+            system_checks: vec![check("governor", true)],
+        };
+        let untuned = WorkerReport {
+            fingerprint: "fedcba9876543210".to_string(),
+            system_checks: vec![check("governor", true), check("ASLR", false)],
+        };
+
+        let job = JobEnvelope::new("job-3", "alice", String::new());
+        assert!(job.check_worker(&untuned).is_ok());
+        let job = job.require_tuned(true);
+        assert!(job.check_worker(&tuned).is_ok());
+        let err = job.check_worker(&untuned).unwrap_err().to_string();
+        assert!(
+            err.contains("ASLR") && !err.contains("governor") && err.contains("fedcba9876543210"),
+            "{err}"
+        );
+
+        // The report is attached to the job's workspace
+        let job_dir = tempdir().unwrap();
+        let workspace = JobWorkspace::create(job_dir.path(), "job-3").unwrap();
+        let path = workspace.write_report(&untuned).unwrap();
+        let report: WorkerReport =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(report, untuned);
+    }
+
+    #[test]
+    fn test_job_workspace() {
+        let job_dir = tempdir().unwrap();
+        for invalid in ["", "..", ".hidden", "a/b", "job 1"] {
+            assert!(
+                JobWorkspace::create(job_dir.path(), invalid).is_err(),
+                "{invalid}"
+            );
+        }
+        let workspace = JobWorkspace::create(job_dir.path(), "job-4").unwrap();
+        let root = workspace.root().to_path_buf();
+        assert!(workspace.data_dir().is_dir() && workspace.out_dir().is_dir());
+
+        assert_eq!(
+            workspace.contain(Path::new("src/bitcoin")).unwrap(),
+            root.join("src/bitcoin")
+        );
+        assert_eq!(
+            workspace.contain(&root.join("out/../scratch")).unwrap(),
+            root.join("scratch")
+        );
+        assert!(workspace.contain(Path::new("../job-5")).is_err());
+        assert!(workspace.contain(Path::new("/etc")).is_err());
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", root.join("escape")).unwrap();
+            assert!(workspace.contain(Path::new("escape/passwd")).is_err());
+        }
+    }
+
+    #[test]
+    fn test_confine() {
+        let job_dir = tempdir().unwrap();
+        let workspace = JobWorkspace::create(job_dir.path(), "job-6").unwrap();
+        let root = workspace.root().to_path_buf();
+        std::fs::write(root.join("dbcaches.txt"), "450\n").unwrap();
+        let envelope = |values_from: &str| {
+            let payload = format!(
+                "global:\n  source: ./bitcoin\n  scratch: ./shared\n  commits: [main]\n  \
+                 tmp_data_dir: ./tmp\n  benchmark:\n    parameter_lists:\n      \
+                 - var: dbcache\n        values_from: {values_from}\nbenchmarks:\n  \
+                 - name: replay\n    network: signet\n    mode: loadblock\n    \
+                 loadblock: [blocks/blk00000.dat]\n    benchmark:\n      \
+                 command: bitcoind -dbcache={{dbcache}}\n"
+            );
+            JobEnvelope::new("job-6", "alice", payload)
+        };
+
+        // values_from files are read on loading, so they're checked before
+        for escaping in ["/etc/passwd", "../job-7/dbcaches.txt"] {
+            assert!(
+                workspace.write_config(&envelope(escaping)).is_err(),
+                "{escaping}"
+            );
+        }
+        let path = workspace.write_config(&envelope("dbcaches.txt")).unwrap();
+        let mut config = crate::config::parse_bench_config(&path).unwrap();
+        workspace.confine(&mut config).unwrap();
+        assert_eq!(config.global.scratch, workspace.scratch());
+        assert_eq!(config.global.tmp_data_dir, workspace.data_dir());
+        assert_eq!(
+            config.benchmarks[0].loadblock,
+            [root.join("blocks/blk00000.dat").to_string_lossy()]
+        );
+
+        for escaping in ["/etc/cron.d/benchkit", "../../export.csv"] {
+            let mut escaped = config.clone();
+            escaped.global.exports = vec![ExportConfig {
+                format: ExportFormat::Csv,
+                path: Some(PathBuf::from(escaping)),
+                threshold: None,
+                measurement: None,
+            }];
+            assert!(workspace.confine(&mut escaped).is_err(), "{escaping}");
+        }
+        for escaping in [
+            "/var/blocks/blk00000.dat",
+            "{dataset:blocks}/../../blk00000.dat",
+        ] {
+            let mut escaped = config.clone();
+            escaped.benchmarks[0].loadblock = vec![escaping.to_string()];
+            assert!(workspace.confine(&mut escaped).is_err(), "{escaping}");
+        }
+        let mut escaped = config.clone();
+        escaped.global.patches.local = Some(PathBuf::from("../patches"));
+        assert!(workspace.confine(&mut escaped).is_err());
+        let mut escaped = config.clone();
+        escaped.global.reference_binary = Some("/usr/bin/bitcoind".to_string());
+        assert!(workspace.confine(&mut escaped).is_err());
+    }
+
+    #[test]
+    fn test_job_retention() {
+        let job_dir = tempdir().unwrap();
+        for id in ["job-1", "job-2", "job-3"] {
+            JobWorkspace::create(job_dir.path(), id).unwrap();
+            // Modification times need to differ
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert!(JobRetention::default()
+            .prune(job_dir.path())
+            .unwrap()
+            .is_empty());
+        let removed = JobRetention {
+            keep_last: Some(1),
+            max_age_days: None,
+        }
+        .prune(job_dir.path())
+        .unwrap();
+        assert_eq!(removed.len(), 2);
+        assert!(job_dir.path().join("job-3").exists());
+        assert!(!job_dir.path().join("job-1").exists());
+    }
+
+    #[test]
+    fn test_unknown_fields_are_ignored() {
+        let json = r#"{
+            "version": 3,
+            "job_id": "job-2",
+            "submitter": "bob",
+            "created_at": 0,
+            "payload": "",
+            "priority": "high"
+        }"#;
+        let envelope = JobEnvelope::from_json(json).unwrap();
+        assert_eq!(envelope.version, 3);
+        assert!(envelope.signature.is_none());
+    }
+
+    #[test]
+    fn test_parse_public_key() {
+        let key = signing_key(3).verifying_key();
+        let hex = encode_hex(key.as_bytes());
+        assert_eq!(parse_public_key(&hex).unwrap(), key);
+        assert!(parse_public_key("abcd").is_err());
+        assert!(parse_public_key("zz").is_err());
+    }
+}
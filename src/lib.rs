@@ -1,9 +1,28 @@
+pub mod artifact;
+pub mod attestation;
 pub mod benchmarks;
+pub mod cgroup;
 pub mod command;
 pub mod config;
+#[cfg(target_os = "linux")]
 pub mod cpu_binding;
+pub mod cpu_limit;
+pub mod dataset;
+pub mod disk_guard;
 pub mod download;
+pub mod error;
+pub mod job;
+pub mod logging;
+pub mod memory;
 pub mod path_utils;
+pub mod process;
+pub mod quiescence;
+pub mod reference;
+pub mod rpc;
+pub mod rusage;
+pub mod schedule;
 pub mod system;
 pub mod system_info;
+pub mod telemetry;
+pub mod timing;
 pub mod types;
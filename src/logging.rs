@@ -0,0 +1,216 @@
+use anyhow::{Context, Result};
+use env_logger::Env;
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the log file written to the output directory
+pub const LOG_FILE_NAME: &str = "benchkit.log";
+
+/// Size at which the log file is rotated
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Number of rotated log files to keep (benchkit.log.1 is the most recent)
+const MAX_ROTATED_LOGS: usize = 5;
+
+/// Initialize logging to the terminal, and to `benchkit.log` in `out_dir` if given
+///
+/// The terminal honours `RUST_LOG` (defaulting to info), while the log file always records debug
+/// level messages so failed runs can be investigated after the fact.
+pub fn init(out_dir: Option<&Path>) -> Result<()> {
+    let terminal = env_logger::Builder::from_env(Env::default().default_filter_or("info")).build();
+    let file = out_dir
+        .map(|dir| {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create output directory: {}", dir.display()))?;
+            RotatingFile::open(dir.join(LOG_FILE_NAME), MAX_LOG_SIZE, MAX_ROTATED_LOGS)
+        })
+        .transpose()?;
+
+    let max_level = if file.is_some() {
+        terminal.filter().max(LevelFilter::Debug)
+    } else {
+        terminal.filter()
+    };
+    log::set_boxed_logger(Box::new(DualLogger {
+        terminal,
+        file: file.map(Mutex::new),
+    }))
+    .context("Failed to initialize logging")?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+/// Logger writing to the terminal through env_logger and to a log file at debug level
+struct DualLogger {
+    terminal: env_logger::Logger,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Log for DualLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.terminal.enabled(metadata) || (self.file.is_some() && file_enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.terminal.matches(record) {
+            self.terminal.log(record);
+        }
+        if let Some(file) = &self.file {
+            if file_enabled(record.metadata()) {
+                let line = format!(
+                    "[{} {:<5} {}] {}\n",
+                    format_timestamp(SystemTime::now()),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                );
+                if let Ok(mut file) = file.lock() {
+                    // Logging must never fail the run
+                    let _ = file.write_all(line.as_bytes());
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.terminal.flush();
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+/// Only benchkit's own debug messages go to the file, to keep dependencies' chatter out of it
+fn file_enabled(metadata: &Metadata) -> bool {
+    metadata.level() <= LevelFilter::Info
+        || (metadata.level() <= LevelFilter::Debug && metadata.target().starts_with("benchkit"))
+}
+
+/// Append-only file which is rotated once it exceeds a maximum size
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_rotated: usize,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_rotated: usize) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+            max_rotated,
+        })
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(())
+    }
+
+    /// Shift benchkit.log.N to benchkit.log.N+1, dropping the oldest, and start a new file
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            PathBuf::from(name)
+        };
+        let _ = std::fs::remove_file(rotated(self.max_rotated));
+        for n in (1..self.max_rotated).rev() {
+            let _ = std::fs::rename(rotated(n), rotated(n + 1));
+        }
+        std::fs::rename(&self.path, rotated(1))?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Format a time as an RFC 3339 UTC timestamp with millisecond precision
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of the month of a day counted from 1970-01-01 (Howard Hinnant's
+/// algorithm)
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rotation() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(LOG_FILE_NAME);
+        let mut file = RotatingFile::open(path.clone(), 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        let read = |name: &str| std::fs::read_to_string(temp_dir.path().join(name)).unwrap();
+        assert_eq!(read("benchkit.log"), "fourth\n");
+        assert_eq!(read("benchkit.log.1"), "third\n");
+        assert_eq!(read("benchkit.log.2"), "second\n");
+        assert!(!temp_dir.path().join("benchkit.log.3").exists());
+
+        // Reopening continues the existing file
+        let file = RotatingFile::open(path, 10, 2).unwrap();
+        assert_eq!(file.size, 7);
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(format_timestamp(time), "2024-02-29T12:34:56.789Z");
+    }
+}
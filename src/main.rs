@@ -1,16 +1,19 @@
 #![warn(unused_extern_crates)]
-use anyhow::Result;
+use anyhow::{Context, Result};
 use benchkit::{
-    benchmarks,
-    config::{load_app_config, load_bench_config, AppConfig, BenchmarkConfig, GlobalConfig},
+    attestation, benchmarks,
+    config::{
+        load_app_config, load_bench_config, AppConfig, BenchmarkConfig, BenchmarkSelection,
+        GlobalConfig,
+    },
     download::download_snapshot,
+    logging,
     system::SystemChecker,
     types::Network,
 };
 
 use clap::{Parser, Subcommand};
-use env_logger::Env;
-use log::{info, warn};
+use log::{debug, info, warn};
 use std::{path::PathBuf, process};
 
 const DEFAULT_CONFIG: &str = "config.yml";
@@ -38,22 +41,136 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Build bitcoin core binaries
-    Build {},
+    Build {
+        /// Only build this commit of the config, may be repeated (builds all if not given)
+        #[arg(long = "commit")]
+        commits: Vec<String>,
+
+        /// Rebuild binaries that already exist, removing them and their build directories
+        #[arg(long)]
+        force_rebuild: bool,
+
+        /// Parallel compile jobs, defaulting to cmake's default or the number of CPUs for make
+        #[arg(short, long)]
+        jobs: Option<usize>,
+
+        /// Build on this machine even if a build server is configured in build.remote
+        #[arg(long)]
+        local: bool,
+    },
     /// Run benchmarks
     Run {
-        /// Benchmark name to run (optional - runs all if not specified)
+        /// Benchmark name to run, may be repeated (runs all if no selection is given)
         #[arg(short, long)]
-        name: Option<String>,
+        name: Vec<String>,
+
+        /// Run benchmarks whose name matches a glob (e.g. 'ibd-*') or a /regex/
+        #[arg(long)]
+        filter: Vec<String>,
+
+        /// Run benchmarks by position in benchmark.yml, from 0 (e.g. 0,3-5)
+        #[arg(long, value_delimiter = ',')]
+        index: Vec<String>,
 
         /// Output directory for storing benchmark artifacts
         #[arg(short, long, required = true)]
         out_dir: PathBuf,
+
+        /// Run binaries even if they were built with different build settings
+        #[arg(long)]
+        allow_stale: bool,
+
+        /// Only run benchmarks with one of these tags
+        #[arg(long, value_delimiter = ',')]
+        tags: Vec<String>,
+
+        /// Skip benchmarks with any of these tags
+        #[arg(long, value_delimiter = ',')]
+        exclude_tags: Vec<String>,
+
+        /// Runs of each parameter combination, overriding the configuration
+        #[arg(long)]
+        runs: Option<usize>,
+
+        /// Warmup runs, overriding the configuration
+        #[arg(long)]
+        warmup: Option<usize>,
+
+        /// Parameter values overriding the configuration, e.g. dbcache=1000 or dbcache=450,1000
+        #[arg(long = "param")]
+        params: Vec<String>,
+
+        /// Only warn if require_tuned_system is set and the system is not tuned
+        #[arg(long)]
+        allow_untuned: bool,
+
+        /// Don't print the summary table after the run
+        #[arg(short, long)]
+        quiet: bool,
+
+        /// Run even if the node at a benchmark's connect address isn't reachable
+        #[arg(long)]
+        skip_connect_check: bool,
+
+        /// Wait until the schedule in config.yml allows running before starting
+        #[arg(long)]
+        wait_for_schedule: bool,
+
+        /// Continue an interrupted run in out_dir from the checkpoints of its runs
+        #[arg(long)]
+        resume: bool,
+    },
+    /// Find the commit which made a benchmark slower with git bisect
+    Bisect {
+        /// Commit without the regression
+        #[arg(long)]
+        good: String,
+
+        /// Commit with the regression
+        #[arg(long)]
+        bad: String,
+
+        /// Name of the benchmark to bisect with
+        #[arg(long)]
+        benchmark: String,
+
+        /// Slowdown relative to the good commit that makes a commit bad, e.g. 3%
+        #[arg(long, default_value = "3%")]
+        threshold: String,
+
+        /// Output directory for the runs of each commit and the bisection report
+        #[arg(short, long, required = true)]
+        out_dir: PathBuf,
+
+        /// Runs of each commit, before any added to reach the precision needed to decide
+        #[arg(long)]
+        runs: Option<usize>,
+
+        /// Parameter values overriding the configuration, pinning the benchmark to a single
+        /// parameter combination, e.g. dbcache=1000
+        #[arg(long = "param")]
+        params: Vec<String>,
+    },
+    /// Smoke test built binaries on regtest before long runs
+    Verify {
+        /// Only verify this commit of the config, may be repeated (verifies all if not given)
+        #[arg(long = "commit")]
+        commits: Vec<String>,
+
+        /// Seconds each binary has to start, mine a block and shut down
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
     },
     /// Download an assumeutxo snapshot
     Snapshot {
         #[command(subcommand)]
         command: SnapshotCommands,
     },
+    /// Manage static datasets used by benchmarks
+    Dataset {
+        #[command(subcommand)]
+        command: DatasetCommands,
+    },
     /// Check system performance settings
     System {
         #[command(subcommand)]
@@ -64,6 +181,41 @@ enum Commands {
         #[command(subcommand)]
         command: PatchCommands,
     },
+    /// Inspect benchmark results
+    Results {
+        #[command(subcommand)]
+        command: ResultsCommands,
+    },
+    /// Combine results from several output directories into one report
+    Report {
+        /// Output directories (or results.json files) to merge
+        #[arg(long, required = true, num_args = 1..)]
+        merge: Vec<PathBuf>,
+
+        /// Also write the merged results to this results.json
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Write SVG charts of each directory's profiles into its charts/ subdirectory
+        #[arg(long)]
+        charts: bool,
+    },
+    /// Follow a run in progress: its hook stages, finished runs and the node's debug.log
+    Tail {
+        /// Output directory of the run
+        out_dir: PathBuf,
+    },
+    /// Clean up cached state
+    Clean {
+        /// Report cached repository clones and evict least recently used ones
+        /// above the configured max_cache_size_gb
+        #[arg(long)]
+        repos: bool,
+
+        /// Remove job workspaces the configured job_retention no longer keeps
+        #[arg(long)]
+        jobs: bool,
+    },
 }
 
 #[derive(Subcommand, Debug)]
@@ -71,7 +223,11 @@ enum SystemCommands {
     /// Check current system configuration
     Check,
     /// Tune the system for benchmarking (requires sudo)
-    Tune,
+    Tune {
+        /// Pin all CPUs to this frequency in KHz instead of their maximum
+        #[arg(long, value_name = "KHZ")]
+        fixed_freq: Option<u64>,
+    },
     /// Reset a previous tune
     Reset,
 }
@@ -80,24 +236,95 @@ enum SystemCommands {
 enum SnapshotCommands {
     /// Download a snapshot
     Download {
-        /// Network (mainnet or signet)
+        /// Network (snapshots are available for main and signet)
         #[arg(value_enum)]
         network: Network,
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum DatasetCommands {
+    /// Download, verify and cache a dataset
+    Download {
+        /// Name of the dataset in the benchmark config
+        name: String,
+    },
+    /// List configured datasets and whether they are downloaded
+    List,
+}
+
 #[derive(Subcommand, Debug)]
 enum PatchCommands {
     /// Download latest patches from GitHub
-    Update {},
+    Update {
+        /// Replace patches which changed, after showing their diffs
+        #[arg(short, long)]
+        yes: bool,
+    },
     /// Test the patches will apply cleanly
     Test {},
+    /// Apply the patches to a local worktree on a new branch
+    Apply {
+        /// Worktree to patch (defaults to the configured local source)
+        #[arg(short, long)]
+        worktree: Option<PathBuf>,
+
+        /// Branch to create for the patched code
+        #[arg(short, long, default_value = "benchkit-patched")]
+        branch: String,
+    },
+    /// Remove the patches from a branch created by `patch apply`
+    Revert {
+        /// Worktree which was patched (defaults to the configured local source)
+        #[arg(short, long)]
+        worktree: Option<PathBuf>,
+
+        /// Branch created by `patch apply`
+        #[arg(short, long, default_value = "benchkit-patched")]
+        branch: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ResultsCommands {
+    /// Print the summary, per-parameter breakdown and warnings for a results.json
+    Show {
+        /// Path to a results.json, or the output directory containing it
+        path: PathBuf,
+    },
+    /// Write a manifest of an output directory and sign it
+    Sign {
+        /// Output directory containing a results.json
+        out_dir: PathBuf,
+
+        /// File holding the hex-encoded Ed25519 key to sign with
+        #[arg(long)]
+        key_file: PathBuf,
+    },
+    /// Check the signature of an output directory and that its files are unchanged
+    Verify {
+        /// Output directory containing a signed manifest
+        out_dir: PathBuf,
+
+        /// Require the signature to be made by one of these hex-encoded public keys
+        #[arg(long)]
+        trusted_key: Vec<String>,
+    },
 }
 
 fn main() -> Result<()> {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     let cli = Cli::parse();
+    let log_dir = match &cli.command {
+        Commands::Run { out_dir, .. } | Commands::Bisect { out_dir, .. } => Some(out_dir.as_path()),
+        _ => None,
+    };
+    logging::init(log_dir)?;
 
+    // Record fatal errors in the log file as well
+    run(&cli).inspect_err(|e| debug!("Exiting with error: {e:?}"))
+}
+
+fn run(cli: &Cli) -> Result<()> {
     // Run system commands without loading any configuration
     if let Commands::System { command } = &cli.command {
         if std::env::consts::OS != "linux" {
@@ -106,50 +333,378 @@ fn main() -> Result<()> {
         let checker = SystemChecker::new()?;
         match command {
             SystemCommands::Check => checker.run_checks()?,
-            SystemCommands::Tune => checker.tune()?,
+            SystemCommands::Tune { fixed_freq } => checker.tune(*fixed_freq)?,
             SystemCommands::Reset => checker.reset()?,
         }
         process::exit(0);
     }
 
+    // Results are self-contained and don't need any configuration either
+    if let Commands::Results { command } = &cli.command {
+        match command {
+            ResultsCommands::Show { path } => show_results(path)?,
+            ResultsCommands::Sign { out_dir, key_file } => {
+                let key = attestation::load_signing_key(key_file)?;
+                attestation::sign_output(out_dir, &key)?;
+            }
+            ResultsCommands::Verify {
+                out_dir,
+                trusted_key,
+            } => {
+                let trusted_keys = benchkit::job::parse_trusted_keys(trusted_key)?;
+                let (manifest, signer) = attestation::verify_output(out_dir, &trusted_keys)?;
+                println!(
+                    "{} files verified, signed by {} on machine {} with benchkit {}",
+                    manifest.files.len(),
+                    signer.public_key,
+                    manifest
+                        .machine_fingerprint
+                        .as_deref()
+                        .unwrap_or("<unknown>"),
+                    manifest.benchkit_version
+                );
+            }
+        }
+        process::exit(0);
+    }
+    if let Commands::Tail { out_dir } = &cli.command {
+        benchmarks::tail(out_dir)?;
+        process::exit(0);
+    }
+    if let Commands::Report {
+        merge,
+        output,
+        charts,
+    } = &cli.command
+    {
+        let sources = benchmarks::ResultSource::load_all(merge)?;
+        print!("{}", benchmarks::merged_report(&sources));
+        if *charts {
+            for source in &sources {
+                benchmarks::write_charts(
+                    &source.results,
+                    source.dir(),
+                    &source.dir().join("charts"),
+                )?;
+            }
+        }
+        if let Some(output) = output {
+            benchmarks::ResultExporter::export_json_multiple(
+                &benchmarks::merge_results(&sources),
+                output,
+            )?;
+            info!("Wrote merged results to {}", output.display());
+        }
+        process::exit(0);
+    }
+
     let app: AppConfig = load_app_config(&cli.app_config)?;
     let bench: BenchmarkConfig = load_bench_config(&cli.bench_config)?;
     let config = GlobalConfig { app, bench };
+    if let Some(telemetry) = &config.app.telemetry {
+        benchkit::telemetry::init(telemetry)?;
+    }
+
+    let result = run_command(&cli.command, &config);
+    // Spans are exported in the background, send the ones still queued before exiting
+    benchkit::telemetry::shutdown();
+    result
+}
 
-    match &cli.command {
-        Commands::Build {} => {
-            let mut builder = benchmarks::Builder::new(config.clone())?;
-            builder.build()?;
+/// Run a command which needs the app and benchmark configs
+fn run_command(command: &Commands, config: &GlobalConfig) -> Result<()> {
+    match command {
+        Commands::Build {
+            commits,
+            force_rebuild,
+            jobs,
+            local,
+        } => {
+            let mut span = benchkit::telemetry::span("benchkit build");
+            let mut builder = benchmarks::Builder::new(config.clone())?
+                .commits(commits.clone())
+                .force_rebuild(*force_rebuild)
+                .jobs(*jobs)
+                .local(*local);
+            span.record(builder.build())?;
         }
-        Commands::Run { name, out_dir } => {
+        Commands::Run {
+            name,
+            filter,
+            index,
+            out_dir,
+            allow_stale,
+            allow_untuned,
+            tags,
+            exclude_tags,
+            quiet,
+            skip_connect_check,
+            wait_for_schedule,
+            resume,
+            runs,
+            warmup,
+            params,
+        } => {
+            if *wait_for_schedule {
+                benchkit::schedule::Schedule::parse(&config.app.schedule)?.wait_until_open();
+            }
+            if cfg!(not(target_os = "linux")) && config.bench.global.benchmark_cores.is_some() {
+                warn!("CPU binding is only supported on Linux, benchmark_cores will be ignored");
+            }
             if let Some(runner_cores) = &config.bench.global.runner_cores {
                 use benchkit::command::CommandExecutor;
                 CommandExecutor::bind_current_process_to_cores(runner_cores)?;
             }
-            let runner = benchmarks::Runner::new(config.clone(), out_dir.clone())?;
-            runner.run(name.as_deref())?;
-            info!(
-                "{} completed successfully.",
-                name.as_deref().unwrap_or("All benchmarks")
-            );
+            if let Some(priority) = &config.bench.global.runner_priority {
+                priority.apply_to_current_process()?;
+            }
+            let overrides = benchmarks::RunOverrides {
+                runs: *runs,
+                warmup: *warmup,
+                params: benchmarks::RunOverrides::parse_params(params)?,
+            };
+            let runner = if *resume {
+                benchmarks::Runner::resume(config.clone(), out_dir.clone())?
+            } else {
+                benchmarks::Runner::new(config.clone(), out_dir.clone())?
+            };
+            let runner = runner
+                .allow_stale(*allow_stale)
+                .allow_untuned(*allow_untuned)
+                .tags(tags.clone(), exclude_tags.clone())
+                .quiet(*quiet)
+                .skip_connect_check(*skip_connect_check)
+                .overrides(overrides);
+            let selection = BenchmarkSelection {
+                names: name.clone(),
+                filters: filter.clone(),
+                indices: index.clone(),
+            };
+            let mut span = benchkit::telemetry::span("benchkit run");
+            span.attr("benchkit.out_dir", out_dir.display().to_string());
+            span.record(runner.run(&selection))?;
+            drop(span);
+            if selection.is_empty() {
+                info!("All benchmarks completed successfully.");
+            } else {
+                info!("Selected benchmarks completed successfully.");
+            }
+        }
+        Commands::Bisect {
+            good,
+            bad,
+            benchmark,
+            threshold,
+            out_dir,
+            runs,
+            params,
+        } => {
+            let overrides = benchmarks::RunOverrides {
+                runs: *runs,
+                warmup: None,
+                params: benchmarks::RunOverrides::parse_params(params)?,
+            };
+            let mut span = benchkit::telemetry::span("benchkit bisect");
+            span.attr("benchkit.benchmark", benchmark)
+                .attr("benchkit.good", good)
+                .attr("benchkit.bad", bad);
+            let report = span.record(
+                benchmarks::Bisect::new(
+                    config.clone(),
+                    out_dir.clone(),
+                    benchmark,
+                    good,
+                    bad,
+                    benchmarks::parse_threshold(threshold)?,
+                )
+                .overrides(overrides)
+                .run(),
+            )?;
+            span.attr("benchkit.first_bad", &report.first_bad);
+            drop(span);
+            for step in &report.steps {
+                let change = step
+                    .relative
+                    .map(|relative| format!("{:+.1}%", relative * 100.0))
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{:<14} {:>8}  {}",
+                    step.commit,
+                    change,
+                    step.verdict.as_str()
+                );
+            }
+            println!("\n{} is the first bad commit", report.first_bad);
+        }
+        Commands::Verify { commits, timeout } => {
+            let mut span = benchkit::telemetry::span("benchkit verify");
+            let results = span.record(benchmarks::verify_commits(
+                config,
+                commits,
+                std::time::Duration::from_secs(*timeout),
+            ))?;
+            for result in &results {
+                match &result.error {
+                    None => println!(
+                        "✓ {} passed in {:.1}s",
+                        result.id,
+                        result.duration.as_secs_f64()
+                    ),
+                    Some(error) => println!("✗ {} failed: {error}", result.id),
+                }
+            }
+            let failed = results.iter().filter(|result| !result.passed()).count();
+            if failed > 0 {
+                anyhow::bail!("{failed} of {} binaries failed verification", results.len());
+            }
         }
         Commands::Snapshot { command } => match command {
             SnapshotCommands::Download { network } => {
                 download_snapshot(network, &config.app.snapshot_dir)?;
             }
         },
+        Commands::Dataset { command } => {
+            let datasets = &config.bench.global.datasets;
+            let dataset_dir = &config.app.dataset_dir;
+            match command {
+                DatasetCommands::Download { name } => {
+                    let dataset = datasets
+                        .iter()
+                        .find(|d| &d.name == name)
+                        .with_context(|| format!("Dataset not found in config: {name}"))?;
+                    dataset.download(dataset_dir)?;
+                }
+                DatasetCommands::List => {
+                    for dataset in datasets {
+                        println!(
+                            "{} {}: {}",
+                            if dataset.is_cached(dataset_dir) {
+                                "✓"
+                            } else {
+                                "✗"
+                            },
+                            dataset.name,
+                            dataset.path(dataset_dir).display()
+                        );
+                    }
+                }
+            }
+        }
         Commands::Patch { command } => match command {
             PatchCommands::Test {} => {
                 let mut builder = benchmarks::Builder::new(config.clone())?;
                 builder.test_patch_commits()?;
             }
-            PatchCommands::Update {} => {
+            PatchCommands::Update { yes } => {
+                let builder = benchmarks::Builder::new(config.clone())?;
+                builder.update_patches(true, *yes)?;
+            }
+            PatchCommands::Apply { worktree, branch } => {
+                let worktree = worktree
+                    .clone()
+                    .unwrap_or_else(|| config.bench.global.source.clone());
+                let builder = benchmarks::Builder::new(config.clone())?;
+                builder.apply_worktree_patches(&worktree, branch)?;
+            }
+            PatchCommands::Revert { worktree, branch } => {
+                let worktree = worktree
+                    .clone()
+                    .unwrap_or_else(|| config.bench.global.source.clone());
                 let builder = benchmarks::Builder::new(config.clone())?;
-                builder.update_patches(true)?;
+                builder.revert_worktree_patches(&worktree, branch)?;
             }
         },
+        Commands::Clean { repos, jobs } => {
+            if !repos && !jobs {
+                anyhow::bail!(
+                    "Nothing to clean, pass --repos to clean the repository cache or --jobs to \
+                     remove old job workspaces"
+                );
+            }
+            if *repos {
+                clean_repos(config)?;
+            }
+            if *jobs {
+                clean_jobs(config)?;
+            }
+        }
         _ => {}
     }
 
     Ok(())
 }
+
+fn clean_repos(config: &GlobalConfig) -> Result<()> {
+    let cache_dir = config.bench.global.scratch.join("repos");
+    let repos = benchmarks::cached_repositories(&cache_dir)?;
+    if repos.is_empty() {
+        println!("No cached repositories in {}", cache_dir.display());
+        return Ok(());
+    }
+
+    println!("Cached repositories in {}:", cache_dir.display());
+    for repo in &repos {
+        let last_used = repo
+            .last_used
+            .and_then(|time| time.elapsed().ok())
+            .map(|age| format!("{} days ago", age.as_secs() / (24 * 60 * 60)))
+            .unwrap_or_else(|| "unknown".to_string());
+        println!(
+            "  {:<60} {:>10}  last used {}",
+            repo.path.display(),
+            benchmarks::format_size(repo.size),
+            last_used
+        );
+    }
+    let total: u64 = repos.iter().map(|repo| repo.size).sum();
+    println!("Total: {}", benchmarks::format_size(total));
+
+    match config.bench.global.repository.max_cache_size_gb {
+        Some(max_gb) => {
+            let evicted = benchmarks::evict_lru(&cache_dir, max_gb << 30, None)?;
+            for repo in &evicted {
+                println!(
+                    "Evicted {} ({})",
+                    repo.path.display(),
+                    benchmarks::format_size(repo.size)
+                );
+            }
+            if evicted.is_empty() {
+                println!("Cache is within the configured maximum of {max_gb} GiB");
+            }
+        }
+        None => println!("No max_cache_size_gb configured, nothing evicted"),
+    }
+    Ok(())
+}
+
+fn clean_jobs(config: &GlobalConfig) -> Result<()> {
+    let job_dir = &config.app.job_dir;
+    let retention = &config.app.job_retention;
+    if *retention == benchkit::job::JobRetention::default() {
+        println!("No job_retention configured, no job workspaces removed");
+        return Ok(());
+    }
+    let removed = retention.prune(job_dir)?;
+    for path in &removed {
+        println!("Removed job workspace {}", path.display());
+    }
+    if removed.is_empty() {
+        println!("No job workspaces in {} to remove", job_dir.display());
+    }
+    Ok(())
+}
+
+fn show_results(path: &std::path::Path) -> Result<()> {
+    let path = if path.is_dir() {
+        path.join("results.json")
+    } else {
+        path.to_path_buf()
+    };
+    let results = benchmarks::ResultExporter::load_json(&path)?;
+    print!(
+        "{}",
+        benchmarks::results_report(&path.display().to_string(), &results)
+    );
+    Ok(())
+}
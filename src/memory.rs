@@ -0,0 +1,172 @@
+use anyhow::{Context, Result};
+use log::debug;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+/// bitcoind's `-dbcache` when the command doesn't set one, in MiB
+pub const DEFAULT_DBCACHE_MB: u64 = 450;
+
+/// Memory bitcoind is allowed to use, derived from the command's `-dbcache` (Linux only)
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MemoryLimit {
+    /// Memory used besides the UTXO cache (mempool, block index, buffers), in MiB
+    #[serde(default = "default_overhead_mb")]
+    pub overhead_mb: u64,
+    /// Run the command in a cgroup with `memory.max` set to dbcache + overhead
+    #[serde(default)]
+    pub enforce: bool,
+}
+
+fn default_overhead_mb() -> u64 {
+    1024
+}
+
+impl MemoryLimit {
+    pub fn validate(&self) -> Result<()> {
+        if cfg!(not(target_os = "linux")) {
+            anyhow::bail!("memory_limit is only supported on Linux");
+        }
+        Ok(())
+    }
+
+    /// Memory the command needs, in MiB
+    pub fn required_mb(&self, command: &str) -> u64 {
+        dbcache_mb(command).unwrap_or(DEFAULT_DBCACHE_MB) + self.overhead_mb
+    }
+
+    /// Fail if the command's dbcache plus overhead doesn't fit in the machine's RAM
+    pub fn check(&self, command: &str) -> Result<()> {
+        let meminfo = fs::read_to_string("/proc/meminfo").context("Failed to read meminfo")?;
+        let total_mb = parse_mem_total_kb(&meminfo)? / 1024;
+        let required_mb = self.required_mb(command);
+        if required_mb > total_mb {
+            anyhow::bail!(
+                "Command needs {required_mb} MiB (dbcache + {} MiB overhead) \
+                 but the machine only has {total_mb} MiB of RAM: {command}",
+                self.overhead_mb
+            );
+        }
+        debug!("Command needs {required_mb} of {total_mb} MiB of RAM");
+        Ok(())
+    }
+}
+
+/// The last `-dbcache` value in a command, which is the one bitcoind uses, in MiB
+pub fn dbcache_mb(command: &str) -> Option<u64> {
+    let dbcache = Regex::new(r"(?:^|\s)--?dbcache=(\d+)").unwrap();
+    dbcache
+        .captures_iter(command)
+        .last()
+        .and_then(|caps| caps[1].parse().ok())
+}
+
+/// MemTotal from /proc/meminfo, in KiB
+fn parse_mem_total_kb(meminfo: &str) -> Result<u64> {
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|value| value.split_whitespace().next())
+        .and_then(|kb| kb.parse().ok())
+        .with_context(|| format!("No MemTotal in meminfo: {meminfo}"))
+}
+
+/// System-wide counts of pages swapped in and out since boot
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SwapCounters {
+    pub swap_in: u64,
+    pub swap_out: u64,
+}
+
+impl SwapCounters {
+    pub fn read() -> Result<Self> {
+        let vmstat = fs::read_to_string("/proc/vmstat").context("Failed to read /proc/vmstat")?;
+        parse_swap_counters(&vmstat)
+    }
+
+    /// Pages swapped since `earlier`
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            swap_in: self.swap_in.saturating_sub(earlier.swap_in),
+            swap_out: self.swap_out.saturating_sub(earlier.swap_out),
+        }
+    }
+
+    /// Whether any page was swapped in or out
+    pub fn swapped(&self) -> bool {
+        self.swap_in > 0 || self.swap_out > 0
+    }
+}
+
+fn parse_swap_counters(vmstat: &str) -> Result<SwapCounters> {
+    let counter = |name: &str| {
+        vmstat
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .and_then(|value| value.trim().parse().ok())
+            .with_context(|| format!("No {name} in /proc/vmstat"))
+    };
+    Ok(SwapCounters {
+        swap_in: counter("pswpin")?,
+        swap_out: counter("pswpout")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dbcache_mb() {
+        assert_eq!(
+            dbcache_mb("bitcoind -datadir=/tmp -dbcache=4500"),
+            Some(4500)
+        );
+        // bitcoind uses the last value
+        assert_eq!(
+            dbcache_mb("bitcoind -dbcache=450 --dbcache=16000"),
+            Some(16000)
+        );
+        assert_eq!(dbcache_mb("bitcoind -maxmempool=300"), None);
+        assert_eq!(dbcache_mb("bitcoind -nodbcache=5"), None);
+
+        let limit = MemoryLimit {
+            overhead_mb: 1024,
+            enforce: false,
+        };
+        assert_eq!(limit.required_mb("bitcoind -dbcache=1000"), 2024);
+        assert_eq!(limit.required_mb("bitcoind"), DEFAULT_DBCACHE_MB + 1024);
+    }
+
+    #[test]
+    fn test_parse_meminfo_and_vmstat() {
+        let meminfo = "MemTotal:       32780000 kB\nMemFree:         1000000 kB\n";
+        assert_eq!(parse_mem_total_kb(meminfo).unwrap(), 32780000);
+        assert!(parse_mem_total_kb("MemFree: 1 kB\n").is_err());
+
+        let before =
+            parse_swap_counters("pgpgin 10\npswpin 5\npswpout 7\npswpin_other 1\n").unwrap();
+        assert_eq!(
+            before,
+            SwapCounters {
+                swap_in: 5,
+                swap_out: 7
+            }
+        );
+        assert!(!before.since(&before).swapped());
+        let after = SwapCounters {
+            swap_out: 8,
+            ..before
+        };
+        assert_eq!(
+            after.since(&before),
+            SwapCounters {
+                swap_in: 0,
+                swap_out: 1
+            }
+        );
+        assert!(after.since(&before).swapped());
+        assert!(parse_swap_counters("pgpgin 10\n").is_err());
+    }
+}
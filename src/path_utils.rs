@@ -66,8 +66,14 @@ pub fn process_paths(
 pub fn prepare_output_directory(dir: &Path) -> Result<()> {
     ensure_directory(dir)?;
 
-    // Check if empty
-    if std::fs::read_dir(dir)?.next().is_some() {
+    // Check if empty, ignoring the log file which is opened before the directory is prepared
+    let mut entries = std::fs::read_dir(dir)?.filter_map(|entry| entry.ok());
+    if entries.any(|entry| {
+        !entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(crate::logging::LOG_FILE_NAME)
+    }) {
         anyhow::bail!(
             "Output directory '{}' is not empty. Please clear it before running benchmarks",
             dir.display()
@@ -85,6 +91,24 @@ pub fn copy_file(source: &Path, dest: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Copy a directory and everything in it, creating `dest` if needed
+pub fn copy_dir_all(source: &Path, dest: &Path) -> Result<()> {
+    ensure_directory(dest)?;
+    for entry in std::fs::read_dir(source)
+        .with_context(|| format!("Failed to read directory: {source:?}"))?
+    {
+        let entry = entry?;
+        let target = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir_all(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)
+                .with_context(|| format!("Failed to copy {:?} to {target:?}", entry.path()))?;
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,6 +230,10 @@ mod tests {
         assert!(output_dir.exists());
         assert!(output_dir.is_dir());
 
+        // The log file doesn't count as content
+        fs::write(output_dir.join(crate::logging::LOG_FILE_NAME), "log").unwrap();
+        prepare_output_directory(&output_dir).unwrap();
+
         // With non-empty directory
         let file_path = output_dir.join("test_file.txt");
         let mut file = fs::File::create(&file_path).unwrap();
@@ -241,5 +269,17 @@ mod tests {
         let nonexistent = tempdir.path().join("nonexistent.txt");
         let result = copy_file(&nonexistent, &dest_path);
         assert!(result.is_err());
+
+        // Directories are copied recursively
+        let source_dir = tempdir.path().join("datadir");
+        fs::create_dir_all(source_dir.join("blocks/index")).unwrap();
+        fs::write(source_dir.join("blocks/index/000001.ldb"), "headers").unwrap();
+        let dest_dir = tempdir.path().join("copy");
+        copy_dir_all(&source_dir, &dest_dir).unwrap();
+        assert_eq!(
+            fs::read_to_string(dest_dir.join("blocks/index/000001.ldb")).unwrap(),
+            "headers"
+        );
+        assert!(copy_dir_all(&nonexistent, &dest_dir).is_err());
     }
 }
@@ -0,0 +1,314 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::process::{Child, ExitStatus};
+use std::time::{Duration, Instant};
+
+/// How long a terminated process group gets to shut down before it is killed
+///
+/// Long enough for bitcoind to flush a large dbcache to disk.
+pub const GRACE_PERIOD: Duration = Duration::from_secs(120);
+
+/// How often a terminating process group is checked
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long killed processes are waited for before giving up
+#[cfg(unix)]
+const KILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Terminate a child and the process group it leads, and wait for all of them to exit
+///
+/// The group is sent SIGTERM, and SIGKILL if anything in it is still running after
+/// `grace_period`. The child is reaped; descendants which outlive it are reparented and reaped
+/// by init, so this returns once the group is empty. On Windows the process tree is killed.
+pub fn terminate(child: &mut Child, grace_period: Duration) -> Result<ExitStatus> {
+    let pid = child.id();
+
+    #[cfg(unix)]
+    {
+        let pgid = group_id(pid)?;
+        if let Some(status) = child.try_wait()? {
+            // The group outlived its leader, so its id can't have been reused
+            if !group_exists(pgid) {
+                return Ok(status);
+            }
+        }
+
+        debug!("Sending SIGTERM to process group {pid}");
+        signal_group(pgid, libc::SIGTERM);
+        let deadline = Instant::now() + grace_period;
+        while Instant::now() < deadline {
+            if child.try_wait()?.is_some() && !group_exists(pgid) {
+                return Ok(child.wait()?);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+
+        warn!(
+            "Process group {pid} did not exit within {}s of SIGTERM, killing it",
+            grace_period.as_secs()
+        );
+        signal_group(pgid, libc::SIGKILL);
+        let status = child
+            .wait()
+            .with_context(|| format!("Failed to wait for process {pid}"))?;
+        // Killed orphans disappear as soon as init reaps them
+        let deadline = Instant::now() + KILL_TIMEOUT;
+        while group_exists(pgid) {
+            if Instant::now() >= deadline {
+                warn!("Processes of group {pid} are still left after SIGKILL");
+                break;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        Ok(status)
+    }
+
+    #[cfg(windows)]
+    {
+        let _ = grace_period;
+        if let Err(e) = std::process::Command::new("taskkill")
+            .args(["/T", "/F", "/PID", &pid.to_string()])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+        {
+            debug!("Failed to run taskkill for process {pid}: {e}");
+            child.kill()?;
+        }
+        child
+            .wait()
+            .with_context(|| format!("Failed to wait for process {pid}"))
+    }
+}
+
+/// Interrupt a child, as Ctrl-C would, so that it can write out what it collected
+///
+/// Used for tracers which flush their output on SIGINT. On Windows the child is killed.
+pub fn interrupt(child: &mut Child) -> Result<()> {
+    let pid = child.id();
+
+    #[cfg(unix)]
+    {
+        let pid = libc::pid_t::try_from(pid)
+            .ok()
+            .filter(|pid| *pid > 1)
+            .with_context(|| format!("Refusing to signal process {pid}"))?;
+        if unsafe { libc::kill(pid, libc::SIGINT) } != 0 {
+            let error = std::io::Error::last_os_error();
+            if error.raw_os_error() != Some(libc::ESRCH) {
+                warn!("Failed to interrupt process {pid}: {error}");
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    {
+        child
+            .kill()
+            .with_context(|| format!("Failed to kill process {pid}"))
+    }
+}
+
+/// Terminate the process group a child leads from another thread than the one waiting for it
+///
+/// Like `terminate`, the group is sent SIGTERM and then SIGKILL if it's still running after
+/// `grace_period`, but the child is left for its owner to reap.
+#[cfg(unix)]
+pub fn terminate_group(pid: u32, grace_period: Duration) -> Result<()> {
+    let pgid = group_id(pid)?;
+    debug!("Sending SIGTERM to process group {pid}");
+    signal_group(pgid, libc::SIGTERM);
+    let deadline = Instant::now() + grace_period;
+    while group_exists(pgid) {
+        if Instant::now() >= deadline {
+            warn!(
+                "Process group {pid} did not exit within {}s of SIGTERM, killing it",
+                grace_period.as_secs()
+            );
+            signal_group(pgid, libc::SIGKILL);
+            break;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Process group id of a child launched as a group leader
+///
+/// Rejects ids that `kill` would interpret as "our own group" (0) or "every process" (1).
+#[cfg(unix)]
+fn group_id(pid: u32) -> Result<libc::pid_t> {
+    match libc::pid_t::try_from(pid) {
+        Ok(pgid) if pgid > 1 => Ok(pgid),
+        _ => anyhow::bail!("Refusing to signal process group {pid}"),
+    }
+}
+
+#[cfg(unix)]
+fn signal_group(pgid: libc::pid_t, signal: libc::c_int) {
+    // Negative PID addresses the whole process group
+    if unsafe { libc::kill(-pgid, signal) } != 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ESRCH) {
+            warn!("Failed to signal process group {pgid}: {error}");
+        }
+    }
+}
+
+/// Whether any process, including an unreaped zombie, is still in the group
+#[cfg(unix)]
+fn group_exists(pgid: libc::pid_t) -> bool {
+    let alive = unsafe { libc::kill(-pgid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// A process and all of its descendants, found in a single scan of /proc
+///
+/// Descendants are the processes in the group `pid` leads, which includes children that were
+/// reparented after their parent exited, and the children of those processes which moved to
+/// another group. Processes which exit during the scan are skipped.
+#[cfg(target_os = "linux")]
+pub fn descendants(pid: u32) -> Vec<u32> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let processes: Vec<(u32, u32, u32)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(entry.path().join("stat")).ok()?;
+            let (ppid, pgrp) = parse_stat(&stat)?;
+            Some((pid, ppid, pgrp))
+        })
+        .collect();
+    related_pids(pid, &processes)
+}
+
+/// `pid` and its descendants among (pid, ppid, pgrp) entries
+#[cfg(target_os = "linux")]
+fn related_pids(pid: u32, processes: &[(u32, u32, u32)]) -> Vec<u32> {
+    let mut related: Vec<u32> = processes
+        .iter()
+        .filter(|&&(p, _, pgrp)| p == pid || pgrp == pid)
+        .map(|&(p, _, _)| p)
+        .collect();
+    let mut i = 0;
+    while i < related.len() {
+        let parent = related[i];
+        for &(p, ppid, _) in processes {
+            if ppid == parent && !related.contains(&p) {
+                related.push(p);
+            }
+        }
+        i += 1;
+    }
+    related
+}
+
+/// Parent pid and process group from the contents of /proc/<pid>/stat
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> Option<(u32, u32)> {
+    // The command name is in parentheses and may itself contain spaces and parentheses
+    let (_, rest) = stat.rsplit_once(')')?;
+    let mut fields = rest.split_whitespace().skip(1);
+    let ppid = fields.next()?.parse().ok()?;
+    let pgrp = fields.next()?.parse().ok()?;
+    Some((ppid, pgrp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_descendants() {
+        assert_eq!(
+            parse_stat("1234 (bitcoin-) msghand) S 1200 1100 1100 0 -1 4194560"),
+            Some((1200, 1100))
+        );
+        assert_eq!(parse_stat("1234 (sh"), None);
+
+        // 20 leads group 20; 22 was reparented to init; 23 moved to its own group
+        let processes = [
+            (1, 0, 1),
+            (20, 1, 20),
+            (21, 20, 20),
+            (22, 1, 20),
+            (23, 21, 23),
+            (24, 23, 23),
+            (30, 1, 30),
+        ];
+        let mut related = related_pids(20, &processes);
+        related.sort();
+        assert_eq!(related, [20, 21, 22, 23, 24]);
+        assert!(related_pids(40, &processes).is_empty());
+
+        use std::os::unix::process::CommandExt;
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "sleep 30 & wait"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let related = descendants(child.id());
+        terminate(&mut child, GRACE_PERIOD).unwrap();
+        assert_eq!(related.len(), 2, "{related:?}");
+        assert!(related.contains(&child.id()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_terminate_process_group() {
+        use std::os::unix::process::{CommandExt, ExitStatusExt};
+        use std::process::Command;
+
+        assert!(group_id(0).is_err());
+        assert!(group_id(1).is_err());
+        assert!(group_id(u32::MAX).is_err());
+
+        // A shell ignoring SIGTERM, with a grandchild in the same group
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; sleep 30 & wait"])
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let pgid = group_id(child.id()).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+
+        let start = Instant::now();
+        let status = terminate(&mut child, Duration::from_millis(300)).unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(!group_exists(pgid));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // A group which exits on SIGTERM isn't killed
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        let status = terminate(&mut child, GRACE_PERIOD).unwrap();
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+
+        // Terminating an exited child just returns its status
+        assert_eq!(
+            terminate(&mut child, GRACE_PERIOD).unwrap().signal(),
+            Some(libc::SIGTERM)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_interrupt() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let mut child = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        interrupt(&mut child).unwrap();
+        assert_eq!(child.wait().unwrap().signal(), Some(libc::SIGINT));
+    }
+}
@@ -0,0 +1,293 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Thresholds a system must be under before a benchmark run starts (Linux only)
+///
+/// Each threshold is optional, only the configured ones are checked.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QuietGate {
+    /// Maximum 1-minute load average
+    pub max_load: Option<f64>,
+    /// Maximum CPU usage in percent, averaged over the benchmark cores (or all cores)
+    pub max_cpu_usage: Option<f64>,
+    /// Maximum percentage of time some tasks were stalled on I/O over the last 10 seconds (PSI)
+    pub max_io_pressure: Option<f64>,
+    /// Seconds between samples, also the window CPU usage is measured over
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    /// Seconds after which the run is started even if the system is still busy
+    pub timeout: Option<u64>,
+}
+
+fn default_interval() -> u64 {
+    1
+}
+
+impl QuietGate {
+    pub fn validate(&self) -> Result<()> {
+        if cfg!(not(target_os = "linux")) {
+            anyhow::bail!("quiet_gate is only supported on Linux");
+        }
+
+        if self.max_load.is_none() && self.max_cpu_usage.is_none() && self.max_io_pressure.is_none()
+        {
+            anyhow::bail!(
+                "quiet_gate needs at least one of max_load, max_cpu_usage or max_io_pressure"
+            );
+        }
+        for (name, value) in [
+            ("max_load", self.max_load),
+            ("max_cpu_usage", self.max_cpu_usage),
+            ("max_io_pressure", self.max_io_pressure),
+        ] {
+            if value.is_some_and(|v| v < 0.0 || v.is_nan()) {
+                anyhow::bail!("quiet_gate {name} cannot be negative");
+            }
+        }
+        if self.interval == 0 {
+            anyhow::bail!("quiet_gate interval cannot be zero");
+        }
+        Ok(())
+    }
+
+    /// Block until the system is quiet or the timeout expires, returning how long that took
+    ///
+    /// CPU usage is measured on `cores`, or across all cores if empty.
+    pub fn wait(&self, cores: &[u32]) -> Result<Duration> {
+        let start = Instant::now();
+        let interval = Duration::from_secs(self.interval);
+        let timeout = self.timeout.map(Duration::from_secs);
+
+        let mut logged = false;
+        loop {
+            let busy = self.busy_reasons(cores, interval)?;
+            if busy.is_empty() {
+                break;
+            }
+            let busy = busy.join(", ");
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                warn!(
+                    "System still busy after {}s ({busy}), starting run anyway",
+                    start.elapsed().as_secs()
+                );
+                break;
+            }
+            if logged {
+                debug!("System still busy: {busy}");
+            } else {
+                info!("Waiting for the system to become quiet: {busy}");
+                logged = true;
+            }
+            // Sampling CPU usage already waited for an interval
+            if self.max_cpu_usage.is_none() {
+                thread::sleep(interval);
+            }
+        }
+
+        let waited = start.elapsed();
+        if logged {
+            info!("System quiet after {:.1}s", waited.as_secs_f64());
+        }
+        Ok(waited)
+    }
+
+    /// Describe every threshold the system currently exceeds
+    fn busy_reasons(&self, cores: &[u32], interval: Duration) -> Result<Vec<String>> {
+        let mut reasons = Vec::new();
+
+        if let Some(max) = self.max_load {
+            let loadavg = fs::read_to_string("/proc/loadavg").context("Failed to read loadavg")?;
+            let load = parse_load_average(&loadavg)?;
+            if load > max {
+                reasons.push(format!("load average {load:.2} > {max}"));
+            }
+        }
+
+        if let Some(max) = self.max_io_pressure {
+            match fs::read_to_string("/proc/pressure/io") {
+                Ok(pressure) => {
+                    let pressure = parse_io_pressure(&pressure)?;
+                    if pressure > max {
+                        reasons.push(format!("I/O pressure {pressure:.2}% > {max}%"));
+                    }
+                }
+                Err(e) => warn!("I/O pressure is unavailable, ignoring max_io_pressure: {e}"),
+            }
+        }
+
+        if let Some(max) = self.max_cpu_usage {
+            let before = fs::read_to_string("/proc/stat").context("Failed to read /proc/stat")?;
+            thread::sleep(interval);
+            let after = fs::read_to_string("/proc/stat").context("Failed to read /proc/stat")?;
+            let usage = cpu_usage(&cpu_times(&before, cores)?, &cpu_times(&after, cores)?);
+            if usage > max {
+                reasons.push(format!("CPU usage {usage:.1}% > {max}%"));
+            }
+        }
+
+        Ok(reasons)
+    }
+}
+
+/// Busy and total jiffies of a set of CPUs
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CpuTimes {
+    busy: u64,
+    total: u64,
+}
+
+/// Sum the CPU times of `cores` from /proc/stat, or use the aggregate line if empty
+fn cpu_times(stat: &str, cores: &[u32]) -> Result<CpuTimes> {
+    let mut times = CpuTimes { busy: 0, total: 0 };
+    let mut found = 0;
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(name) = fields.next().and_then(|name| name.strip_prefix("cpu")) else {
+            continue;
+        };
+        let selected = if cores.is_empty() {
+            name.is_empty()
+        } else {
+            name.parse().is_ok_and(|cpu: u32| cores.contains(&cpu))
+        };
+        if !selected {
+            continue;
+        }
+
+        let values = fields
+            .map(|v| v.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Invalid /proc/stat line: {line}"))?;
+        // user nice system idle iowait irq softirq steal, guest time is included in user
+        let total: u64 = values.iter().take(8).sum();
+        let idle = values.get(3).copied().unwrap_or(0) + values.get(4).copied().unwrap_or(0);
+        times.busy += total - idle;
+        times.total += total;
+        found += 1;
+    }
+
+    if found == 0 {
+        anyhow::bail!("No CPU times found in /proc/stat for cores {cores:?}");
+    }
+    Ok(times)
+}
+
+/// Percentage of time the CPUs were busy between two samples
+fn cpu_usage(before: &CpuTimes, after: &CpuTimes) -> f64 {
+    let total = after.total.saturating_sub(before.total);
+    if total == 0 {
+        return 0.0;
+    }
+    after.busy.saturating_sub(before.busy) as f64 / total as f64 * 100.0
+}
+
+/// The 1-minute load average from /proc/loadavg
+fn parse_load_average(loadavg: &str) -> Result<f64> {
+    loadavg
+        .split_whitespace()
+        .next()
+        .and_then(|load| load.parse().ok())
+        .with_context(|| format!("Invalid loadavg: {loadavg}"))
+}
+
+/// The 10 second average of the "some" line of /proc/pressure/io
+fn parse_io_pressure(pressure: &str) -> Result<f64> {
+    pressure
+        .lines()
+        .find_map(|line| line.strip_prefix("some "))
+        .and_then(|line| {
+            line.split_whitespace()
+                .find_map(|field| field.strip_prefix("avg10="))
+        })
+        .and_then(|avg| avg.parse().ok())
+        .with_context(|| format!("Invalid I/O pressure: {pressure}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "cpu  400 0 100 1400 100 0 0 0 0 0
+cpu0 100 0 50 800 50 0 0 0 0 0
+cpu1 300 0 50 600 50 0 0 0 0 0
+intr 12345 0 0
+ctxt 6789
+";
+
+    #[test]
+    fn test_cpu_usage() {
+        let all = cpu_times(STAT, &[]).unwrap();
+        assert_eq!(
+            all,
+            CpuTimes {
+                busy: 500,
+                total: 2000
+            }
+        );
+        let cpu1 = cpu_times(STAT, &[1]).unwrap();
+        assert_eq!(
+            cpu1,
+            CpuTimes {
+                busy: 350,
+                total: 1000
+            }
+        );
+        assert!(cpu_times(STAT, &[7]).is_err());
+
+        let later = CpuTimes {
+            busy: 400,
+            total: 1200,
+        };
+        assert_eq!(cpu_usage(&cpu1, &later), 25.0);
+        assert_eq!(cpu_usage(&cpu1, &cpu1), 0.0);
+    }
+
+    #[test]
+    fn test_parse_pressure_and_load() {
+        assert_eq!(
+            parse_load_average("0.52 0.58 0.59 1/467 12345\n").unwrap(),
+            0.52
+        );
+        let pressure = "some avg10=1.25 avg60=0.50 avg300=0.10 total=12345
+full avg10=0.75 avg60=0.25 avg300=0.05 total=6789
+";
+        assert_eq!(parse_io_pressure(pressure).unwrap(), 1.25);
+        assert!(parse_io_pressure("").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_validate() {
+        let gate = QuietGate {
+            max_load: Some(1.0),
+            max_cpu_usage: None,
+            max_io_pressure: None,
+            interval: 1,
+            timeout: None,
+        };
+        assert!(gate.validate().is_ok());
+        assert!(QuietGate {
+            max_load: None,
+            ..gate.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(QuietGate {
+            interval: 0,
+            ..gate.clone()
+        }
+        .validate()
+        .is_err());
+        assert!(QuietGate {
+            max_load: Some(-1.0),
+            ..gate
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::{Context, Result};
+use log::info;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::command::CommandExecutor;
+use crate::download::download_file;
+use crate::path_utils;
+
+/// Commit name the reference binary is benchmarked as
+pub const REFERENCE_COMMIT: &str = "reference";
+
+const RELEASE_HOST: &str = "https://bitcoincore.org/bin";
+
+/// A pinned bitcoind every benchmark is compared against
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceBinary {
+    /// A bitcoind binary on disk
+    Path(PathBuf),
+    /// A Bitcoin Core release, e.g. version "28.1" with rc None, or "29.0" with rc Some(2)
+    Release { version: String, rc: Option<u32> },
+}
+
+impl ReferenceBinary {
+    /// Parse a release tag like `v28.1` or `v29.0rc2`, or anything else as a path
+    pub fn parse(spec: &str) -> Result<Self> {
+        if spec.is_empty() {
+            anyhow::bail!("reference_binary cannot be empty");
+        }
+        let tag = Regex::new(r"^v?(\d+\.\d+(?:\.\d+)?)(?:rc(\d+))?$").unwrap();
+        Ok(match tag.captures(spec) {
+            Some(caps) => Self::Release {
+                version: caps[1].to_string(),
+                rc: caps.get(2).map(|rc| rc.as_str().parse()).transpose()?,
+            },
+            None => Self::Path(PathBuf::from(shellexpand::tilde(spec).as_ref())),
+        })
+    }
+
+    /// Get the reference bitcoind, downloading a release into `releases_dir` if needed
+    pub fn resolve(&self, releases_dir: &Path) -> Result<PathBuf> {
+        match self {
+            Self::Path(path) => {
+                if !path.is_file() {
+                    anyhow::bail!("Reference binary not found: {}", path.display());
+                }
+                Ok(path.clone())
+            }
+            Self::Release { .. } => self.download_release(releases_dir),
+        }
+    }
+
+    /// Release name as used in file names, e.g. "28.1" or "29.0rc2"
+    fn release_name(version: &str, rc: Option<u32>) -> String {
+        match rc {
+            Some(rc) => format!("{version}rc{rc}"),
+            None => version.to_string(),
+        }
+    }
+
+    /// URL of the directory a release's files are published in
+    fn release_url(version: &str, rc: Option<u32>) -> String {
+        match rc {
+            Some(rc) => format!("{RELEASE_HOST}/bitcoin-core-{version}/test.rc{rc}"),
+            None => format!("{RELEASE_HOST}/bitcoin-core-{version}"),
+        }
+    }
+
+    /// Download, verify and extract a release, unless it was already downloaded
+    fn download_release(&self, releases_dir: &Path) -> Result<PathBuf> {
+        let Self::Release { version, rc } = self else {
+            unreachable!("only releases are downloaded");
+        };
+        let name = Self::release_name(version, *rc);
+        let dir = releases_dir.join(&name);
+        let bitcoind = dir
+            .join(format!("bitcoin-{name}"))
+            .join("bin")
+            .join("bitcoind");
+        if bitcoind.is_file() {
+            return Ok(bitcoind);
+        }
+
+        path_utils::ensure_directory(&dir)?;
+        let tarball = format!("bitcoin-{name}-{}.tar.gz", release_platform()?);
+        let url = Self::release_url(version, *rc);
+        let tarball_path = dir.join(&tarball);
+        let sums_path = dir.join("SHA256SUMS");
+        download_file(&format!("{url}/{tarball}"), &tarball_path)?;
+        download_file(&format!("{url}/SHA256SUMS"), &sums_path)?;
+
+        let sums = std::fs::read_to_string(&sums_path)?;
+        let expected = expected_sha256(&sums, &tarball)
+            .with_context(|| format!("{tarball} is not listed in SHA256SUMS"))?;
+        let actual = crate::benchmarks::sha256_file(&tarball_path)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            std::fs::remove_file(&tarball_path)?;
+            anyhow::bail!("{tarball} has sha256 {actual}, expected {expected}");
+        }
+
+        CommandExecutor::builder()
+            .name(format!("extract {tarball}"))
+            .working_dir(Some(&dir))
+            .build()?
+            .execute_command_with_args("tar", &["-xzf", &tarball])
+            .with_context(|| format!("Failed to extract {tarball}"))?;
+        std::fs::remove_file(&tarball_path)?;
+
+        if !bitcoind.is_file() {
+            anyhow::bail!("{tarball} did not contain {}", bitcoind.display());
+        }
+        info!(
+            "Reference release {name} is available at {}",
+            bitcoind.display()
+        );
+        Ok(bitcoind)
+    }
+
+    /// Install the reference bitcoind into `bin_dir` as the binary of every build variant
+    pub fn install(
+        &self,
+        releases_dir: &Path,
+        bin_dir: &Path,
+        variants: &[Option<String>],
+    ) -> Result<()> {
+        let source = self.resolve(releases_dir)?;
+        path_utils::ensure_directory(bin_dir)?;
+        for variant in variants {
+            let id = crate::benchmarks::binary_id(REFERENCE_COMMIT, variant.as_deref());
+            let dest = crate::benchmarks::get_binary_path(bin_dir, &id);
+            std::fs::copy(&source, &dest).with_context(|| {
+                format!(
+                    "Failed to copy reference binary {} to {}",
+                    source.display(),
+                    dest.display()
+                )
+            })?;
+        }
+        info!("Installed reference binary {}", source.display());
+        Ok(())
+    }
+}
+
+/// Platform suffix of release tarballs for this machine
+fn release_platform() -> Result<&'static str> {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => Ok("x86_64-linux-gnu"),
+        ("linux", "aarch64") => Ok("aarch64-linux-gnu"),
+        ("macos", "x86_64") => Ok("x86_64-apple-darwin"),
+        ("macos", "aarch64") => Ok("arm64-apple-darwin"),
+        (os, arch) => anyhow::bail!("No Bitcoin Core release is published for {arch} {os}"),
+    }
+}
+
+/// The sha256 of a file in a SHA256SUMS listing
+pub(crate) fn expected_sha256<'a>(sums: &'a str, file: &str) -> Option<&'a str> {
+    sums.lines().find_map(|line| {
+        let (hash, name) = line.split_once(char::is_whitespace)?;
+        (name.trim_start().trim_start_matches('*') == file).then_some(hash)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reference_binary() {
+        assert_eq!(
+            ReferenceBinary::parse("v28.1").unwrap(),
+            ReferenceBinary::Release {
+                version: "28.1".to_string(),
+                rc: None
+            }
+        );
+        assert_eq!(
+            ReferenceBinary::parse("29.0rc2").unwrap(),
+            ReferenceBinary::Release {
+                version: "29.0".to_string(),
+                rc: Some(2)
+            }
+        );
+        assert_eq!(
+            ReferenceBinary::parse("/opt/bitcoind").unwrap(),
+            ReferenceBinary::Path(PathBuf::from("/opt/bitcoind"))
+        );
+        assert!(ReferenceBinary::parse("").is_err());
+
+        assert_eq!(
+            ReferenceBinary::release_url("29.0", Some(2)),
+            "https://bitcoincore.org/bin/bitcoin-core-29.0/test.rc2"
+        );
+        assert_eq!(ReferenceBinary::release_name("29.0", Some(2)), "29.0rc2");
+    }
+
+    #[test]
+    fn test_expected_sha256() {
+        let sums = "\
+aaaa  bitcoin-28.1-aarch64-linux-gnu.tar.gz
+bbbb  bitcoin-28.1-x86_64-linux-gnu.tar.gz
+cccc *bitcoin-28.1-win64.zip
+";
+        assert_eq!(
+            expected_sha256(sums, "bitcoin-28.1-x86_64-linux-gnu.tar.gz"),
+            Some("bbbb")
+        );
+        assert_eq!(
+            expected_sha256(sums, "bitcoin-28.1-win64.zip"),
+            Some("cccc")
+        );
+        assert_eq!(expected_sha256(sums, "bitcoin-28.1.tar.gz"), None);
+    }
+}
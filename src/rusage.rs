@@ -0,0 +1,88 @@
+use anyhow::Result;
+use std::time::Duration;
+
+/// Resource usage of the child processes benchkit has waited for (Unix only)
+///
+/// Counters are cumulative, so a run's usage is the difference between readings taken before
+/// and after it. Descendants count once their parent has waited for them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChildUsage {
+    /// Page faults that needed I/O
+    pub major_faults: u64,
+    /// Page faults served without I/O
+    pub minor_faults: u64,
+    /// CPU time spent in user mode
+    pub user_time: Duration,
+    /// CPU time spent in the kernel
+    pub system_time: Duration,
+    /// Context switches because a process waited, e.g. for I/O or a lock
+    pub voluntary_context_switches: u64,
+    /// Context switches because a process was preempted
+    pub involuntary_context_switches: u64,
+}
+
+impl ChildUsage {
+    #[cfg(unix)]
+    pub fn read() -> Result<Self> {
+        let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) } != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok(Self {
+            major_faults: usage.ru_majflt as u64,
+            minor_faults: usage.ru_minflt as u64,
+            user_time: timeval_duration(usage.ru_utime),
+            system_time: timeval_duration(usage.ru_stime),
+            voluntary_context_switches: usage.ru_nvcsw as u64,
+            involuntary_context_switches: usage.ru_nivcsw as u64,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn read() -> Result<Self> {
+        anyhow::bail!("Resource usage is only supported on Unix")
+    }
+
+    /// Usage accumulated since `earlier`
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            major_faults: self.major_faults.saturating_sub(earlier.major_faults),
+            minor_faults: self.minor_faults.saturating_sub(earlier.minor_faults),
+            user_time: self.user_time.saturating_sub(earlier.user_time),
+            system_time: self.system_time.saturating_sub(earlier.system_time),
+            voluntary_context_switches: self
+                .voluntary_context_switches
+                .saturating_sub(earlier.voluntary_context_switches),
+            involuntary_context_switches: self
+                .involuntary_context_switches
+                .saturating_sub(earlier.involuntary_context_switches),
+        }
+    }
+
+    /// User plus system CPU time
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+#[cfg(unix)]
+fn timeval_duration(time: libc::timeval) -> Duration {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_child_usage() {
+        let before = ChildUsage::read().unwrap();
+        std::process::Command::new("true").status().unwrap();
+        let after = ChildUsage::read().unwrap();
+        // Starting any process faults in pages, and waiting for it to exec is a voluntary switch
+        assert!(after.since(&before).minor_faults > 0);
+        assert!(after.since(&before).voluntary_context_switches > 0);
+        assert_eq!(before.since(&after), ChildUsage::default());
+    }
+}
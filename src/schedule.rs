@@ -0,0 +1,388 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::time::Duration;
+
+use crate::logging::civil_from_days;
+
+/// How often a closed schedule is checked again
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+const DAY_NAMES: [&str; 7] = [
+    "sunday",
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+];
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// A point in local time, as far as schedules care
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime {
+    /// Day of the week, from 0 for Sunday
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    /// Day of the month, from 1
+    pub day: u32,
+    /// Month, from 1
+    pub month: u32,
+}
+
+impl LocalTime {
+    /// The current time in the system's time zone (UTC where it isn't known)
+    pub fn now() -> Self {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+
+        #[cfg(unix)]
+        {
+            let time = now as libc::time_t;
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            if !unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+                return Self {
+                    weekday: tm.tm_wday as u32,
+                    hour: tm.tm_hour as u32,
+                    minute: tm.tm_min as u32,
+                    day: tm.tm_mday as u32,
+                    month: tm.tm_mon as u32 + 1,
+                };
+            }
+        }
+
+        Self::from_unix_utc(now)
+    }
+
+    /// UTC time of a Unix timestamp
+    fn from_unix_utc(secs: u64) -> Self {
+        let days = secs / 86400;
+        let minutes = (secs % 86400) / 60;
+        let (_, month, day) = civil_from_days(days as i64);
+        Self {
+            // 1970-01-01 was a Thursday
+            weekday: ((days + 4) % 7) as u32,
+            hour: (minutes / 60) as u32,
+            minute: (minutes % 60) as u32,
+            day,
+            month,
+        }
+    }
+
+    fn minute_of_day(&self) -> u32 {
+        self.hour * 60 + self.minute
+    }
+}
+
+/// When jobs may run, e.g. nights and weekends on a shared machine
+///
+/// Each entry is either a time window, `[days] HH:MM-HH:MM` such as `Mon-Fri 22:00-06:00` or
+/// `Sat,Sun 00:00-24:00`, or a five field cron expression such as `0 2 * * *`. Windows ending
+/// before they start run past midnight into the next day. A cron expression allows starting
+/// jobs during the minutes it matches. An empty schedule allows running at any time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+    Window {
+        /// Days the window starts on, indexed from Sunday
+        days: [bool; 7],
+        /// Minutes since midnight
+        start: u32,
+        end: u32,
+    },
+    Cron(Cron),
+}
+
+/// Allowed values of each field of a cron expression
+#[derive(Debug, Clone, PartialEq)]
+struct Cron {
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    /// Whether the day of month and day of week fields were restricted
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    /// Parse the entries of a schedule
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let rules = entries
+            .iter()
+            .map(|entry| {
+                let fields: Vec<&str> = entry.split_whitespace().collect();
+                let rule = if fields.len() == 5 {
+                    parse_cron(&fields).map(Rule::Cron)
+                } else {
+                    parse_window(&fields)
+                };
+                rule.with_context(|| format!("Invalid schedule entry '{entry}'"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether jobs may start at `time`
+    pub fn allows(&self, time: &LocalTime) -> bool {
+        self.is_empty() || self.rules.iter().any(|rule| rule.allows(time))
+    }
+
+    /// Hold until the schedule allows starting a job
+    pub fn wait_until_open(&self) {
+        if self.allows(&LocalTime::now()) {
+            return;
+        }
+        info!("Waiting for the schedule to allow running");
+        while !self.allows(&LocalTime::now()) {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        info!("Schedule allows running, starting");
+    }
+}
+
+impl Rule {
+    fn allows(&self, time: &LocalTime) -> bool {
+        match self {
+            Rule::Window { days, start, end } => {
+                let minute = time.minute_of_day();
+                let today = days[time.weekday as usize];
+                if start <= end {
+                    today && (*start..*end).contains(&minute)
+                } else {
+                    let yesterday = days[(time.weekday as usize + 6) % 7];
+                    (today && minute >= *start) || (yesterday && minute < *end)
+                }
+            }
+            Rule::Cron(cron) => {
+                let day = cron.days.contains(&time.day);
+                let weekday = cron.weekdays.contains(&time.weekday);
+                // Like cron, when both day fields are restricted either may match
+                let day_matches = if cron.days_restricted && cron.weekdays_restricted {
+                    day || weekday
+                } else {
+                    day && weekday
+                };
+                cron.minutes.contains(&time.minute)
+                    && cron.hours.contains(&time.hour)
+                    && cron.months.contains(&time.month)
+                    && day_matches
+            }
+        }
+    }
+}
+
+/// Parse `[days] HH:MM-HH:MM`
+fn parse_window(fields: &[&str]) -> Result<Rule> {
+    let (days, times) = match fields {
+        [times] => ([true; 7], *times),
+        [days, times] => (parse_days(days)?, *times),
+        _ => anyhow::bail!("expected '[days] HH:MM-HH:MM' or a five field cron expression"),
+    };
+    let (start, end) = times
+        .split_once('-')
+        .context("expected a time range like 22:00-06:00")?;
+    Ok(Rule::Window {
+        days,
+        start: parse_time(start)?,
+        end: parse_time(end)?,
+    })
+}
+
+/// Parse `HH:MM` into minutes since midnight, allowing 24:00 as the end of the day
+fn parse_time(time: &str) -> Result<u32> {
+    let (hour, minute) = time
+        .split_once(':')
+        .with_context(|| format!("expected HH:MM, got '{time}'"))?;
+    let hour: u32 = hour
+        .parse()
+        .with_context(|| format!("invalid hour in '{time}'"))?;
+    let minute: u32 = minute
+        .parse()
+        .with_context(|| format!("invalid minute in '{time}'"))?;
+    let minutes = hour * 60 + minute;
+    if minute >= 60 || minutes > MINUTES_PER_DAY {
+        anyhow::bail!("'{time}' is not a time of day");
+    }
+    Ok(minutes)
+}
+
+/// Parse days of the week like `Mon-Fri` or `Sat,Sun`
+fn parse_days(spec: &str) -> Result<[bool; 7]> {
+    let day = |name: &str| -> Result<usize> {
+        let name = name.to_ascii_lowercase();
+        DAY_NAMES
+            .iter()
+            .position(|day| name.len() >= 3 && day.starts_with(&name))
+            .with_context(|| format!("unknown day '{name}'"))
+    };
+    let mut days = [false; 7];
+    for part in spec.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (day(first)?, day(last)?);
+                // Ranges may wrap around the end of the week, e.g. Fri-Mon
+                let mut d = first;
+                loop {
+                    days[d] = true;
+                    if d == last {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days[day(part)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+/// Parse the five fields of a cron expression
+fn parse_cron(fields: &[&str]) -> Result<Cron> {
+    let mut weekdays = parse_cron_field(fields[4], 0, 7).context("invalid day of week")?;
+    // Both 0 and 7 are Sunday
+    if weekdays.contains(&7) {
+        weekdays.retain(|&d| d != 7);
+        if !weekdays.contains(&0) {
+            weekdays.push(0);
+        }
+    }
+    Ok(Cron {
+        minutes: parse_cron_field(fields[0], 0, 59).context("invalid minute")?,
+        hours: parse_cron_field(fields[1], 0, 23).context("invalid hour")?,
+        days: parse_cron_field(fields[2], 1, 31).context("invalid day of month")?,
+        months: parse_cron_field(fields[3], 1, 12).context("invalid month")?,
+        weekdays,
+        days_restricted: fields[2] != "*",
+        weekdays_restricted: fields[4] != "*",
+    })
+}
+
+/// Parse a cron field of `*`, values, ranges and steps like `1-5,*/15`
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>> {
+    let mut values = Vec::new();
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (first.parse()?, last.parse()?),
+            None => {
+                let value = range.parse()?;
+                // A single value with a step runs from the value to the end of the range
+                (value, if step > 1 { max } else { value })
+            }
+        };
+        if step == 0 || first < min || last > max || first > last {
+            anyhow::bail!("'{part}' is outside {min}-{max}");
+        }
+        values.extend((first..=last).step_by(step as usize));
+    }
+    values.sort();
+    values.dedup();
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(weekday: u32, hour: u32, minute: u32) -> LocalTime {
+        LocalTime {
+            weekday,
+            hour,
+            minute,
+            day: 15,
+            month: 6,
+        }
+    }
+
+    fn schedule(entries: &[&str]) -> Schedule {
+        Schedule::parse(&entries.iter().map(|e| e.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn test_time_windows() {
+        let nights = schedule(&["Mon-Fri 22:00-06:00", "Sat,Sun 00:00-24:00"]);
+        // Monday 23:00 and the Saturday morning after Friday night
+        assert!(nights.allows(&at(1, 23, 0)));
+        assert!(nights.allows(&at(6, 5, 59)));
+        // Tuesday morning belongs to Monday night's window, Sunday's ends at midnight
+        assert!(nights.allows(&at(2, 3, 0)));
+        assert!(!nights.allows(&at(1, 3, 0)));
+        assert!(!nights.allows(&at(1, 12, 0)));
+        assert!(!nights.allows(&at(2, 6, 0)));
+        assert!(nights.allows(&at(0, 12, 0)));
+
+        let wrapping = schedule(&["Fri-Mon 09:00-17:00"]);
+        assert!(wrapping.allows(&at(0, 9, 0)));
+        assert!(!wrapping.allows(&at(3, 9, 0)));
+
+        assert!(Schedule::default().allows(&at(3, 12, 0)));
+        assert!(schedule(&["saturday 10:00-11:00"]).allows(&at(6, 10, 30)));
+        for invalid in [
+            "Mon-Fri",
+            "Funday 22:00-06:00",
+            "Monster 10:00-11:00",
+            "25:00-06:00",
+            "22:00-06:60",
+        ] {
+            assert!(
+                Schedule::parse(&[invalid.to_string()]).is_err(),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_cron() {
+        let nightly = schedule(&["*/30 2 * * 1-5"]);
+        assert!(nightly.allows(&at(1, 2, 30)));
+        assert!(!nightly.allows(&at(1, 2, 15)));
+        assert!(!nightly.allows(&at(0, 2, 0)));
+
+        // With both day fields restricted, either matches
+        let either = schedule(&["0 0 1 * 7"]);
+        assert!(either.allows(&at(0, 0, 0)));
+        assert!(either.allows(&LocalTime {
+            day: 1,
+            ..at(3, 0, 0)
+        }));
+        assert!(!either.allows(&at(3, 0, 0)));
+
+        assert_eq!(parse_cron_field("5/20", 0, 59).unwrap(), [5, 25, 45]);
+        assert_eq!(parse_cron_field("1-3,2", 1, 12).unwrap(), [1, 2, 3]);
+        assert!(Schedule::parse(&["60 * * * *".to_string()]).is_err());
+        assert!(Schedule::parse(&["* * 0 * *".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_from_unix_utc() {
+        // Thursday 1970-01-01 00:00 and Thursday 2024-02-29 13:45
+        assert_eq!(LocalTime::from_unix_utc(0), at(4, 0, 0).with_date(1, 1));
+        assert_eq!(
+            LocalTime::from_unix_utc(1_709_214_300),
+            at(4, 13, 45).with_date(29, 2)
+        );
+    }
+
+    impl LocalTime {
+        fn with_date(self, day: u32, month: u32) -> Self {
+            Self { day, month, ..self }
+        }
+    }
+}
@@ -1,9 +1,104 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Subcommand;
+use serde::{Deserialize, Serialize};
 use std::{fs, path::Path};
 
 use crate::command::CommandExecutor;
 
+/// Directory of the transparent huge page settings
+const THP_DIR: &str = "/sys/kernel/mm/transparent_hugepage";
+
+/// Number of explicitly reserved huge pages
+const NR_HUGEPAGES: &str = "/proc/sys/vm/nr_hugepages";
+
+/// Writing 3 drops the page cache, dentries and inodes
+#[cfg(target_os = "linux")]
+const DROP_CACHES: &str = "/proc/sys/vm/drop_caches";
+
+/// Kernel scheduler and timer settings: name, path, wanted value and kernel default
+const KERNEL_SETTINGS: [(&str, &str, &str, &str); 4] = [
+    (
+        "Scheduler autogroup",
+        "/proc/sys/kernel/sched_autogroup_enabled",
+        "0",
+        "1",
+    ),
+    (
+        "Timer migration",
+        "/proc/sys/kernel/timer_migration",
+        "0",
+        "1",
+    ),
+    ("Watchdog", "/proc/sys/kernel/watchdog", "0", "1"),
+    (
+        "NUMA balancing",
+        "/proc/sys/kernel/numa_balancing",
+        "0",
+        "1",
+    ),
+];
+
+/// Result of a single system check
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SystemCheck {
+    pub section: String,
+    pub name: String,
+    pub value: String,
+    pub want: String,
+    pub ok: bool,
+    /// Whether `require_tuned_system` refuses to run when this check fails
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl SystemCheck {
+    fn new(
+        section: &'static str,
+        name: impl Into<String>,
+        value: impl Into<String>,
+        want: impl Into<String>,
+        ok: bool,
+    ) -> Self {
+        Self {
+            section: section.to_string(),
+            name: name.into(),
+            value: value.into(),
+            want: want.into(),
+            ok,
+            required: false,
+            detail: None,
+        }
+    }
+
+    fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    fn detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+}
+
+impl std::fmt::Display for SystemCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: {} (want: {})",
+            if self.ok { "✓" } else { "✗" },
+            self.name,
+            self.value,
+            self.want
+        )?;
+        if let Some(detail) = &self.detail {
+            write!(f, ", {detail}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Subcommand, Debug)]
 pub enum SystemCommands {
     Check,
@@ -45,6 +140,21 @@ impl SystemChecker {
         Ok((has_isolcpus, has_rcu_nocbs))
     }
 
+    /// Read a sysctl, or None if the kernel doesn't provide it
+    fn get_sysctl(path: &str) -> Result<Option<String>> {
+        if !Path::new(path).exists() {
+            return Ok(None);
+        }
+        Ok(Some(fs::read_to_string(path)?.trim().to_string()))
+    }
+
+    fn set_sysctl(path: &str, value: &str) -> Result<()> {
+        if Path::new(path).exists() {
+            fs::write(path, value)?;
+        }
+        Ok(())
+    }
+
     fn check_intel_pstate() -> Result<bool> {
         Ok(Path::new("/sys/devices/system/cpu/intel_pstate").exists())
     }
@@ -76,6 +186,50 @@ impl SystemChecker {
         Ok(())
     }
 
+    fn set_cpu_max_freq(&self, cpu: usize, freq: u64) -> Result<()> {
+        let path = format!("/sys/devices/system/cpu/cpu{cpu}/cpufreq/scaling_max_freq");
+        fs::write(path, freq.to_string())?;
+        Ok(())
+    }
+
+    /// Hardware frequency limits of a CPU in KHz
+    fn get_cpuinfo_freq(&self, cpu: usize) -> Result<(u64, u64)> {
+        let min_path = format!("/sys/devices/system/cpu/cpu{cpu}/cpufreq/cpuinfo_min_freq");
+        let max_path = format!("/sys/devices/system/cpu/cpu{cpu}/cpufreq/cpuinfo_max_freq");
+
+        let min = fs::read_to_string(&min_path)?.trim().parse()?;
+        let max = fs::read_to_string(&max_path)?.trim().parse()?;
+
+        Ok((min, max))
+    }
+
+    /// Discrete frequencies a CPU supports, for drivers which list them (e.g. acpi-cpufreq)
+    fn get_available_freqs(&self, cpu: usize) -> Result<Option<Vec<u64>>> {
+        let path =
+            format!("/sys/devices/system/cpu/cpu{cpu}/cpufreq/scaling_available_frequencies");
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+        let freqs = fs::read_to_string(&path)?
+            .split_whitespace()
+            .map(|f| f.parse())
+            .collect::<Result<_, _>>()?;
+        Ok(Some(freqs))
+    }
+
+    /// Pin a CPU to a fixed frequency by setting both its minimum and maximum
+    fn set_cpu_fixed_freq(&self, cpu: usize, freq: u64) -> Result<()> {
+        let range = self.get_cpuinfo_freq(cpu)?;
+        validate_fixed_freq(freq, range, self.get_available_freqs(cpu)?.as_deref())
+            .with_context(|| format!("Cannot pin CPU {cpu} to {freq} KHz"))?;
+
+        // The kernel rejects a minimum above the maximum and vice versa, so open the range first
+        self.set_cpu_min_freq(cpu, range.0)?;
+        self.set_cpu_max_freq(cpu, freq)?;
+        self.set_cpu_min_freq(cpu, freq)?;
+        Ok(())
+    }
+
     fn check_irqbalance() -> Result<bool> {
         let executor = CommandExecutor::builder()
             .name("Check IRQ balancing")
@@ -115,6 +269,33 @@ impl SystemChecker {
         Ok(())
     }
 
+    /// Read a transparent huge page setting, or None if THP is unavailable
+    fn get_thp_setting(name: &str) -> Result<Option<String>> {
+        let path = Path::new(THP_DIR).join(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        Ok(selected_option(&contents).map(str::to_string))
+    }
+
+    fn set_thp_setting(name: &str, value: &str) -> Result<()> {
+        let path = Path::new(THP_DIR).join(name);
+        if path.exists() {
+            fs::write(path, value)?;
+        }
+        Ok(())
+    }
+
+    fn get_nr_hugepages() -> Result<u64> {
+        Ok(fs::read_to_string(NR_HUGEPAGES)?.trim().parse()?)
+    }
+
+    fn set_nr_hugepages(pages: u64) -> Result<()> {
+        fs::write(NR_HUGEPAGES, pages.to_string())?;
+        Ok(())
+    }
+
     fn check_power_supply() -> Result<bool> {
         let ac_online = Path::new("/sys/class/power_supply/AC/online");
         if ac_online.exists() {
@@ -146,104 +327,169 @@ impl SystemChecker {
         Ok(())
     }
 
-    pub fn run_checks(&self) -> Result<()> {
-        println!("System Performance Checks:");
-
-        println!("\nKernel Settings:");
-        match Self::check_aslr()? {
-            true => println!("✓ ASLR: Full randomization enabled (want: enabled)"),
-            false => println!("✗ ASLR: Full randomization not enabled (want: enabled)"),
-        }
+    /// Run all checks, grouped by section in the order they are reported
+    pub fn checks(&self) -> Result<Vec<SystemCheck>> {
+        let mut checks = Vec::new();
+
+        let aslr = Self::check_aslr()?;
+        checks.push(
+            SystemCheck::new(
+                "Kernel",
+                "ASLR",
+                if aslr {
+                    "Full randomization enabled"
+                } else {
+                    "Full randomization not enabled"
+                },
+                "enabled",
+                aslr,
+            )
+            .required(),
+        );
 
         let (has_isolcpus, has_rcu_nocbs) = Self::check_cpu_isolation()?;
-        println!(
-            "{} CPU Isolation: {} (want: set)",
-            if has_isolcpus { "✓" } else { "✗" },
-            if has_isolcpus { "set" } else { "not set" }
-        );
-        println!(
-            "{} RCU: {} (want: set)",
-            if has_rcu_nocbs { "✓" } else { "✗" },
-            if has_rcu_nocbs { "set" } else { "not set" }
+        let set = |enabled: bool| if enabled { "set" } else { "not set" };
+        checks.push(
+            SystemCheck::new(
+                "Kernel",
+                "CPU Isolation",
+                set(has_isolcpus),
+                "set",
+                has_isolcpus,
+            )
+            .required(),
         );
+        checks.push(SystemCheck::new(
+            "Kernel",
+            "RCU",
+            set(has_rcu_nocbs),
+            "set",
+            has_rcu_nocbs,
+        ));
+
+        for (name, path, want, _) in KERNEL_SETTINGS {
+            checks.push(match Self::get_sysctl(path)? {
+                Some(value) => {
+                    let ok = value == want;
+                    SystemCheck::new("Kernel", name, value, want, ok)
+                }
+                None => SystemCheck::new("Kernel", name, "unavailable", want, true),
+            });
+        }
 
-        println!("\nCPU Settings:");
         for cpu in &self.cpus {
             let governor = self.get_scaling_governor(*cpu)?;
             let (min_freq, max_freq) = self.get_cpu_freq(*cpu)?;
-            let gov_status = if governor == "performance" {
-                "✓"
-            } else {
-                "✗"
-            };
-            println!(
-                "{gov_status} CPU {cpu:2}: Governor: {governor} (want: performance), Freq: {min_freq}-{max_freq} KHz"
+            let ok = governor == "performance";
+            checks.push(
+                SystemCheck::new(
+                    "CPU",
+                    format!("CPU {cpu:2}: Governor"),
+                    governor,
+                    "performance",
+                    ok,
+                )
+                .detail(format!("Freq: {min_freq}-{max_freq} KHz"))
+                .required(),
             );
         }
 
-        println!("\nSystem Settings:");
-        let irq_status = if !Self::check_irqbalance()? {
-            "✓"
-        } else {
-            "✗"
-        };
-        println!(
-            "{} IRQ Balancing: {} (want: inactive)",
-            irq_status,
-            if Self::check_irqbalance()? {
-                "active"
-            } else {
-                "inactive"
-            }
-        );
+        let irqbalance = Self::check_irqbalance()?;
+        checks.push(SystemCheck::new(
+            "System",
+            "IRQ Balancing",
+            if irqbalance { "active" } else { "inactive" },
+            "inactive",
+            !irqbalance,
+        ));
 
         let perf_rate = Self::get_perf_sample_rate()?;
-        let perf_status = if perf_rate == 1 { "✓" } else { "✗" };
-        println!("{perf_status} Perf sample rate: {perf_rate} (want: 1)");
-
-        let power_status = if Self::check_power_supply()? {
-            "✓"
-        } else {
-            "✗"
-        };
-        println!(
-            "{} Power Supply: {} (want: AC power)",
-            power_status,
-            if Self::check_power_supply()? {
-                "AC power"
-            } else {
-                "battery"
-            }
+        checks.push(SystemCheck::new(
+            "System",
+            "Perf sample rate",
+            perf_rate.to_string(),
+            "1",
+            perf_rate == 1,
+        ));
+
+        let ac_power = Self::check_power_supply()?;
+        checks.push(SystemCheck::new(
+            "System",
+            "Power Supply",
+            if ac_power { "AC power" } else { "battery" },
+            "AC power",
+            ac_power,
+        ));
+
+        // Turbo state can only be read through intel_pstate, so it only gates runs there
+        let turbo = self.check_turbo_boost()?;
+        let turbo_check = SystemCheck::new(
+            "System",
+            "Turbo Boost",
+            if turbo { "enabled" } else { "disabled" },
+            "enabled",
+            turbo,
         );
-
-        let turbo_status = if self.check_turbo_boost()? {
-            "✓"
+        checks.push(if Self::check_intel_pstate()? {
+            turbo_check.required()
         } else {
-            "✗"
-        };
-        println!(
-            "{} Turbo Boost: {} (want: enabled)",
-            turbo_status,
-            if self.check_turbo_boost()? {
-                "enabled"
-            } else {
-                "disabled"
+            turbo_check
+        });
+
+        for name in ["enabled", "defrag"] {
+            let name_label = format!("Transparent huge pages {name}");
+            checks.push(match Self::get_thp_setting(name)? {
+                Some(value) => {
+                    let ok = value == "never";
+                    SystemCheck::new("Memory", name_label, value, "never", ok)
+                }
+                None => SystemCheck::new("Memory", name_label, "unavailable", "never", true),
+            });
+        }
+        let hugepages = Self::get_nr_hugepages()?;
+        checks.push(SystemCheck::new(
+            "Memory",
+            "Reserved huge pages",
+            hugepages.to_string(),
+            "0",
+            hugepages == 0,
+        ));
+
+        Ok(checks)
+    }
+
+    pub fn run_checks(&self) -> Result<()> {
+        println!("System Performance Checks:");
+
+        let mut section = String::new();
+        for check in self.checks()? {
+            if check.section != section {
+                section = check.section.clone();
+                println!("\n{section} Settings:");
             }
-        );
+            println!("{check}");
+        }
 
         Ok(())
     }
 
-    pub fn tune(&self) -> Result<()> {
+    /// Tune the system for benchmarking, pinning CPUs to `fixed_freq` KHz if given instead of
+    /// their maximum frequency
+    pub fn tune(&self, fixed_freq: Option<u64>) -> Result<()> {
         println!("Tuning system for benchmarking...");
 
         // Set CPU governor to performance
         for cpu in &self.cpus {
             self.set_scaling_governor(*cpu, "performance")?;
 
-            // Set min frequency to max
-            let (_, max_freq) = self.get_cpu_freq(*cpu)?;
-            self.set_cpu_min_freq(*cpu, max_freq)?;
+            match fixed_freq {
+                Some(freq) => self.set_cpu_fixed_freq(*cpu, freq)?,
+                None => {
+                    // Set min frequency to max
+                    let (_, max_freq) = self.get_cpu_freq(*cpu)?;
+                    self.set_cpu_min_freq(*cpu, max_freq)?;
+                }
+            }
         }
 
         // Stop IRQ balancing
@@ -256,6 +502,16 @@ impl SystemChecker {
         // Enable Turbo Boost
         self.set_turbo_boost(true)?;
 
+        for (_, path, want, _) in KERNEL_SETTINGS {
+            Self::set_sysctl(path, want)?;
+        }
+
+        // Avoid huge page allocation and khugepaged compaction varying between runs, and
+        // release reserved huge pages which bitcoind can't use
+        Self::set_thp_setting("enabled", "never")?;
+        Self::set_thp_setting("defrag", "never")?;
+        Self::set_nr_hugepages(0)?;
+
         println!("System tuned for benchmarking");
         Ok(())
     }
@@ -267,9 +523,10 @@ impl SystemChecker {
         for cpu in &self.cpus {
             self.set_scaling_governor(*cpu, "powersave")?;
 
-            // Reset min frequency to minimum
-            let (min_freq, _) = self.get_cpu_freq(*cpu)?;
+            // Reset the frequency range to the hardware limits, undoing any fixed frequency
+            let (min_freq, max_freq) = self.get_cpuinfo_freq(*cpu)?;
             self.set_cpu_min_freq(*cpu, min_freq)?;
+            self.set_cpu_max_freq(*cpu, max_freq)?;
         }
 
         // Start IRQ balancing
@@ -281,7 +538,91 @@ impl SystemChecker {
         // Reset Turbo Boost to default (enabled)
         self.set_turbo_boost(true)?;
 
+        for (_, path, _, default) in KERNEL_SETTINGS {
+            Self::set_sysctl(path, default)?;
+        }
+
+        // Reset transparent huge pages to the usual distribution defaults
+        Self::set_thp_setting("enabled", "madvise")?;
+        Self::set_thp_setting("defrag", "madvise")?;
+
         println!("System reset to default settings");
         Ok(())
     }
 }
+
+/// Flush dirty pages and drop the OS caches so the next read comes from disk (Linux, needs root)
+#[cfg(target_os = "linux")]
+pub fn drop_caches() -> Result<()> {
+    // Dirty pages can't be dropped, write them out first
+    unsafe { libc::sync() };
+    fs::write(DROP_CACHES, "3")
+        .with_context(|| format!("Failed to write {DROP_CACHES}, dropping caches requires root"))
+}
+
+/// The selected value of a sysfs option list such as "always [madvise] never"
+pub(crate) fn selected_option(contents: &str) -> Option<&str> {
+    contents
+        .split_whitespace()
+        .find_map(|option| option.strip_prefix('[')?.strip_suffix(']'))
+}
+
+/// Check a fixed frequency lies within a CPU's range and, if the driver only supports discrete
+/// frequencies, is one of them
+fn validate_fixed_freq(freq: u64, (min, max): (u64, u64), available: Option<&[u64]>) -> Result<()> {
+    if freq < min || freq > max {
+        anyhow::bail!("{freq} KHz is outside the supported range of {min}-{max} KHz");
+    }
+    if let Some(available) = available {
+        if !available.contains(&freq) {
+            let available: Vec<String> = available.iter().map(u64::to_string).collect();
+            anyhow::bail!(
+                "{freq} KHz is not an available frequency, choose one of: {}",
+                available.join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selected_option() {
+        assert_eq!(selected_option("always [madvise] never\n"), Some("madvise"));
+        assert_eq!(
+            selected_option("always defer defer+madvise [madvise] never"),
+            Some("madvise")
+        );
+        assert_eq!(selected_option("always madvise never"), None);
+    }
+
+    #[test]
+    fn test_validate_fixed_freq() {
+        let range = (800_000, 4_000_000);
+        assert!(validate_fixed_freq(3_500_000, range, None).is_ok());
+        assert!(validate_fixed_freq(800_000, range, None).is_ok());
+        assert!(validate_fixed_freq(4_500_000, range, None).is_err());
+        assert!(validate_fixed_freq(700_000, range, None).is_err());
+
+        let available = [800_000, 2_000_000, 3_500_000];
+        assert!(validate_fixed_freq(3_500_000, range, Some(&available)).is_ok());
+        assert!(validate_fixed_freq(3_000_000, range, Some(&available)).is_err());
+    }
+
+    #[test]
+    fn test_system_check_display() {
+        let check = SystemCheck::new("CPU", "CPU  0: Governor", "powersave", "performance", false)
+            .detail("Freq: 800000-4000000 KHz".to_string())
+            .required();
+        assert!(check.required);
+        assert_eq!(
+            check.to_string(),
+            "✗ CPU  0: Governor: powersave (want: performance), Freq: 800000-4000000 KHz"
+        );
+        let check = SystemCheck::new("Kernel", "Watchdog", "0", "0", true);
+        assert_eq!(check.to_string(), "✓ Watchdog: 0 (want: 0)");
+    }
+}
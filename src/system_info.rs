@@ -1,60 +1,467 @@
-use anyhow::Result;
-use log::info;
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use anyhow::{Context, Result};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
 
 use crate::path_utils;
 
 use sysinfo::System;
 
-#[rustfmt::skip]
-pub fn dump_sys_info(file: &PathBuf) -> Result<()> {
-    info!("Writing system info to {file:?}");
-
-    // Ensure parent directory exists
-    if let Some(parent) = file.parent() {
-        path_utils::ensure_directory(parent)?;
-    }
-
-    let mut file = File::create(file)?;
-    let mut sys = System::new_all();
-    sys.refresh_all();
-
-    {
-    writeln!(file, "{:<25}{}", "System name:", System::name().unwrap_or_else(|| "<unknown>".to_owned()))?;
-    writeln!(file, "{:<25}{}", "System kernel version:", System::kernel_version().unwrap_or_else(|| "<unknown>".to_owned()))?;
-    writeln!(file, "{:<25}{}", "System OS version:", System::long_os_version().unwrap_or_else(|| "<unknown>".to_owned()))?;
-    writeln!(file, "{:<25}{}", "Distribution ID:", System::distribution_id())?;
-    }
-    // Cpu info
-    writeln!(file, "{:<25}{}", "CPU Arch:", System::cpu_arch())?;
-    let processors = sys.cpus();
-    if !processors.is_empty() {
-        let processor = &processors[0];
-        let cpu_brand = processor.brand();
-        let cpu_count = processors.len();
-        let cpu_freq = processor.frequency();
-        let cpu_name = processor.name();
-    writeln!(file, "{:<25}{} {} ({}) @ {:.2} GHz",
-        "CPU:",
-            cpu_name,
-            cpu_brand,
-            cpu_count,
-            cpu_freq as f64 / 1000.0)?;
-    } else {
-        writeln!(file, "CPU: Unknown")?;
-    }
-
-    // RAM and swap:
-    writeln!(file, "{:<25}{} bytes", "Total memory:", sys.total_memory())?;
-    writeln!(file, "{:<25}{} bytes", "Used memory:", sys.used_memory())?;
-    writeln!(file, "{:<25}{} bytes", "Total swap:", sys.total_swap())?;
-    writeln!(file, "{:<25}{} bytes", "Used swap:", sys.used_swap())?;
-
-    // Uptime
-    let uptime = System::uptime();
-    writeln!(file, "{:<25}{}", "Uptime (seconds):", uptime)?;
-    writeln!(file, "{:<25}{}", "Uptime (days):", uptime / 86400)?;
-    Ok(())
+/// File in the output directory describing the machine the benchmarks ran on
+pub const SYSTEM_INFO_FILE: &str = "system_info.json";
+
+/// Directory in the output directory holding the raw files `system_info.json` was read from
+pub const SYSTEM_INFO_RAW_DIR: &str = "system_info";
+
+/// Files copied verbatim into the raw capture directory, where they exist
+const RAW_CAPTURES: &[(&str, &str)] = &[
+    ("/proc/cpuinfo", "cpuinfo"),
+    ("/proc/meminfo", "meminfo"),
+    ("/proc/cmdline", "cmdline"),
+    ("/proc/version", "version"),
+    ("/proc/self/mountinfo", "mountinfo"),
+];
+
+/// The machine benchmarks run on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemInfo {
+    pub os: OsInfo,
+    pub cpu: CpuInfo,
+    pub memory: MemoryInfo,
+    pub uptime_secs: u64,
+    /// Storage backing `tmp_data_dir`, if it could be determined
+    pub storage: Option<StorageInfo>,
+    /// Short hash of the hardware, kernel, hypervisor, CPU flags and mitigations
+    pub fingerprint: String,
+    /// Cores the benchmarks and benchkit were bound to
+    #[serde(default)]
+    pub cores: CoreAssignment,
+}
+
+/// Cores benchmark commands and the benchkit process were bound to
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CoreAssignment {
+    /// Cores of the benchmark commands, empty if they weren't bound
+    pub benchmark: Vec<u32>,
+    /// Cores of the benchkit process, empty if it wasn't bound
+    pub runner: Vec<u32>,
+    /// Whether benchkit chose any of them itself, with `auto`
+    pub auto: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OsInfo {
+    pub name: Option<String>,
+    pub kernel_version: Option<String>,
+    pub os_version: Option<String>,
+    pub distribution_id: String,
+    pub kernel_cmdline: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuInfo {
+    pub arch: String,
+    pub brand: Option<String>,
+    pub count: usize,
+    pub frequency_mhz: Option<u64>,
+    /// Hypervisor the system runs under, None on bare metal
+    pub hypervisor: Option<String>,
+    /// Flags of the first CPU in /proc/cpuinfo (Linux only)
+    pub flags: Vec<String>,
+    /// Mitigation state of each CPU vulnerability the kernel knows about (Linux only)
+    pub vulnerabilities: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemoryInfo {
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub total_swap_bytes: u64,
+    pub used_swap_bytes: u64,
+}
+
+impl SystemInfo {
+    /// Describe this machine, with the storage of `data_dir`
+    pub fn collect(data_dir: &Path) -> Self {
+        let mut sys = System::new_all();
+        sys.refresh_all();
+        let processor = sys.cpus().first();
+
+        let mut info = Self {
+            os: OsInfo {
+                name: System::name(),
+                kernel_version: System::kernel_version(),
+                os_version: System::long_os_version(),
+                distribution_id: System::distribution_id(),
+                kernel_cmdline: std::fs::read_to_string("/proc/cmdline")
+                    .ok()
+                    .map(|cmdline| cmdline.trim().to_string()),
+            },
+            cpu: CpuInfo {
+                arch: System::cpu_arch(),
+                brand: processor.map(|p| p.brand().to_string()),
+                count: sys.cpus().len(),
+                frequency_mhz: processor.map(|p| p.frequency()),
+                hypervisor: detect_hypervisor(),
+                flags: read_cpu_flags(),
+                vulnerabilities: read_mitigations(),
+            },
+            memory: MemoryInfo {
+                total_bytes: sys.total_memory(),
+                used_bytes: sys.used_memory(),
+                total_swap_bytes: sys.total_swap(),
+                used_swap_bytes: sys.used_swap(),
+            },
+            uptime_secs: System::uptime(),
+            storage: StorageInfo::for_path(data_dir),
+            fingerprint: String::new(),
+            cores: CoreAssignment::default(),
+        };
+        info.fingerprint = info.machine_fingerprint();
+        info
+    }
+
+    /// Hash of what identifies the machine, leaving out what changes from run to run like
+    /// memory use and uptime
+    fn machine_fingerprint(&self) -> String {
+        let vulnerabilities: Vec<String> = self
+            .cpu
+            .vulnerabilities
+            .iter()
+            .map(|(name, state)| format!("{name}={state}"))
+            .collect();
+        machine_fingerprint(&[
+            &self.cpu.arch,
+            self.cpu.brand.as_deref().unwrap_or_default(),
+            &self.cpu.count.to_string(),
+            &self.memory.total_bytes.to_string(),
+            self.os.kernel_version.as_deref().unwrap_or_default(),
+            self.cpu.hypervisor.as_deref().unwrap_or("none"),
+            &self.cpu.flags.join(" "),
+            &vulnerabilities.join(";"),
+        ])
+    }
+
+    /// Load a `system_info.json`
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read system info: {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse system info: {}", path.display()))
+    }
+}
+
+/// Write `system_info.json` and the raw files it was read from into `out_dir`
+pub fn dump_sys_info(
+    out_dir: &Path,
+    data_dir: &Path,
+    cores: &CoreAssignment,
+) -> Result<SystemInfo> {
+    let path = out_dir.join(SYSTEM_INFO_FILE);
+    info!("Writing system info to {}", path.display());
+    path_utils::ensure_directory(out_dir)?;
+
+    let info = SystemInfo {
+        cores: cores.clone(),
+        ..SystemInfo::collect(data_dir)
+    };
+    std::fs::write(&path, serde_json::to_string_pretty(&info)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    let raw_dir = out_dir.join(SYSTEM_INFO_RAW_DIR);
+    path_utils::ensure_directory(&raw_dir)?;
+    for (source, name) in RAW_CAPTURES {
+        // Missing on other platforms
+        if let Err(e) = std::fs::copy(source, raw_dir.join(name)) {
+            debug!("Not capturing {source}: {e}");
+        }
+    }
+    if !info.cpu.vulnerabilities.is_empty() {
+        let vulnerabilities: String = info
+            .cpu
+            .vulnerabilities
+            .iter()
+            .map(|(name, state)| format!("{name}: {state}\n"))
+            .collect();
+        std::fs::write(raw_dir.join("vulnerabilities"), vulnerabilities)?;
+    }
+    Ok(info)
+}
+
+/// Short hash identifying a machine's hardware, kernel and mitigations, so results from
+/// machines which differ in any of them aren't mistaken for each other
+fn machine_fingerprint(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+/// Hypervisor the system runs under, None on bare metal
+fn detect_hypervisor() -> Option<String> {
+    if let Ok(output) = std::process::Command::new("systemd-detect-virt").output() {
+        let virt = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        // Exits non-zero and prints "none" on bare metal
+        return (output.status.success() && !virt.is_empty() && virt != "none").then_some(virt);
+    }
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    if !cpu_flags(&cpuinfo).iter().any(|flag| flag == "hypervisor") {
+        return None;
+    }
+    ["/sys/hypervisor/type", "/sys/class/dmi/id/sys_vendor"]
+        .iter()
+        .find_map(|path| {
+            let contents = std::fs::read_to_string(path).ok()?;
+            Some(contents.trim().to_string()).filter(|vendor| !vendor.is_empty())
+        })
+        .or_else(|| Some("unknown".to_string()))
+}
+
+/// Flags of the first CPU in /proc/cpuinfo (Linux only)
+fn read_cpu_flags() -> Vec<String> {
+    std::fs::read_to_string("/proc/cpuinfo")
+        .map(|cpuinfo| cpu_flags(&cpuinfo))
+        .unwrap_or_default()
+}
+
+/// Flags of the first CPU listed in cpuinfo, "flags" on x86 and "Features" on ARM
+fn cpu_flags(cpuinfo: &str) -> Vec<String> {
+    cpuinfo
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            matches!(key.trim(), "flags" | "Features").then_some(value)
+        })
+        .map(|flags| flags.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// Mitigation state of each CPU vulnerability the kernel knows about (Linux only)
+fn read_mitigations() -> BTreeMap<String, String> {
+    let Ok(entries) = std::fs::read_dir("/sys/devices/system/cpu/vulnerabilities") else {
+        return BTreeMap::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let state = std::fs::read_to_string(entry.path()).ok()?;
+            Some((
+                entry.file_name().to_string_lossy().into_owned(),
+                state.trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// A line of /proc/self/mountinfo
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MountEntry {
+    /// `major:minor` of the mounted device
+    pub dev: String,
+    pub mount_point: String,
+    /// Per-mount options, e.g. "rw,noatime"
+    pub options: String,
+    pub fs_type: String,
+    pub source: String,
+    /// Filesystem options, e.g. "rw,discard,errors=remount-ro"
+    pub super_options: String,
+}
+
+impl MountEntry {
+    /// Whether blocks are discarded as files are deleted, rather than by a periodic fstrim
+    pub fn online_discard(&self) -> bool {
+        self.options
+            .split(',')
+            .chain(self.super_options.split(','))
+            .any(|option| option == "discard" || option.starts_with("discard="))
+    }
+}
+
+/// The mount containing `path`, the one with the longest mount point which is a prefix of it
+fn find_mount(mountinfo: &str, path: &Path) -> Option<MountEntry> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let mount: Vec<&str> = mount.split(' ').collect();
+            let fs: Vec<&str> = fs.split(' ').collect();
+            Some(MountEntry {
+                dev: mount.get(2)?.to_string(),
+                mount_point: unescape_mount_field(mount.get(4)?),
+                options: mount.get(5)?.to_string(),
+                fs_type: fs.first()?.to_string(),
+                source: unescape_mount_field(fs.get(1)?),
+                super_options: fs.get(2).unwrap_or(&"").to_string(),
+            })
+        })
+        .filter(|entry| path.starts_with(&entry.mount_point))
+        // Later mounts over the same point hide earlier ones
+        .max_by_key(|entry| Path::new(&entry.mount_point).components().count())
+}
+
+/// Undo the octal escaping of spaces, tabs, newlines and backslashes in mountinfo fields
+fn unescape_mount_field(field: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = field;
+    while let Some(pos) = rest.find('\\') {
+        unescaped.push_str(&rest[..pos]);
+        let code = rest.get(pos + 1..pos + 4);
+        match code.and_then(|code| u8::from_str_radix(code, 8).ok()) {
+            Some(byte) => {
+                unescaped.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                unescaped.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+/// How the storage of a directory is set up (Linux only)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageInfo {
+    pub mount: MountEntry,
+    /// Whole disk the filesystem lives on, e.g. "nvme0n1" for a partition on it
+    pub device: Option<String>,
+    pub model: Option<String>,
+    pub rotational: Option<bool>,
+    pub scheduler: Option<String>,
+    /// Largest discard the device accepts, 0 if it doesn't support discard
+    pub discard_max_bytes: Option<u64>,
+    /// Whether the filesystem discards blocks as files are deleted
+    pub online_discard: bool,
+}
+
+impl StorageInfo {
+    /// Storage of `path`, or its closest existing ancestor if it hasn't been created yet
+    fn for_path(path: &Path) -> Option<Self> {
+        let existing = path.ancestors().find(|dir| dir.exists())?;
+        let path = existing.canonicalize().ok()?;
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+        let mount = find_mount(&mountinfo, &path)?;
+
+        // Partitions have no queue of their own, it belongs to the disk they are on
+        let mut disk = Path::new("/sys/dev/block")
+            .join(&mount.dev)
+            .canonicalize()
+            .ok();
+        if let Some(dir) = &disk {
+            if !dir.join("queue").exists() {
+                disk = dir.parent().map(Path::to_path_buf);
+            }
+        }
+        let read = |file: &str| -> Option<String> {
+            let contents = std::fs::read_to_string(disk.as_ref()?.join(file)).ok()?;
+            Some(contents.trim().to_string()).filter(|value| !value.is_empty())
+        };
+        Some(Self {
+            device: disk
+                .as_ref()
+                .filter(|dir| dir.join("queue").exists())
+                .and_then(|dir| Some(dir.file_name()?.to_string_lossy().into_owned())),
+            model: read("device/model"),
+            rotational: read("queue/rotational").map(|value| value == "1"),
+            scheduler: read("queue/scheduler").map(|value| {
+                crate::system::selected_option(&value)
+                    .unwrap_or(&value)
+                    .to_string()
+            }),
+            discard_max_bytes: read("queue/discard_max_bytes").and_then(|value| value.parse().ok()),
+            online_discard: mount.online_discard(),
+            mount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dump_sys_info() {
+        let out_dir = tempdir().unwrap();
+        let cores = CoreAssignment {
+            benchmark: vec![1, 2],
+            runner: vec![0],
+            auto: true,
+        };
+        let info =
+            dump_sys_info(out_dir.path(), Path::new("/nonexistent/benchkit"), &cores).unwrap();
+        assert!(info.cpu.count > 0);
+        assert_eq!(info.cores, cores);
+        assert_eq!(info.fingerprint, info.machine_fingerprint());
+
+        let loaded = SystemInfo::load(&out_dir.path().join(SYSTEM_INFO_FILE)).unwrap();
+        assert_eq!(loaded, info);
+        assert!(out_dir.path().join(SYSTEM_INFO_RAW_DIR).is_dir());
+    }
+
+    #[test]
+    fn test_cpu_flags_and_fingerprint() {
+        let x86 = "processor\t: 0\nvendor_id\t: GenuineIntel\nflags\t\t: fpu sse2 hypervisor\n\n\
+                   processor\t: 1\nflags\t\t: fpu\n";
+        assert_eq!(cpu_flags(x86), ["fpu", "sse2", "hypervisor"]);
+        let arm = "processor\t: 0\nBogoMIPS\t: 50.00\nFeatures\t: fp asimd aes\n";
+        assert_eq!(cpu_flags(arm), ["fp", "asimd", "aes"]);
+        assert!(cpu_flags("processor\t: 0\n").is_empty());
+
+        let mitigated = machine_fingerprint(&["x86_64", "spectre_v2=Mitigation: IBRS"]);
+        assert_eq!(mitigated.len(), 16);
+        assert_eq!(
+            mitigated,
+            machine_fingerprint(&["x86_64", "spectre_v2=Mitigation: IBRS"])
+        );
+        assert_ne!(
+            mitigated,
+            machine_fingerprint(&["x86_64", "spectre_v2=Vulnerable"])
+        );
+        // Parts can't run into each other
+        assert_ne!(
+            machine_fingerprint(&["ab", "c"]),
+            machine_fingerprint(&["a", "bc"])
+        );
+    }
+
+    #[test]
+    fn test_find_mount() {
+        let mountinfo = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw,errors=remount-ro
+23 22 0:22 / /proc rw,nosuid,nodev,noexec,relatime shared:12 - proc proc rw
+40 22 8:17 / /mnt/bench\\040disk rw,noatime shared:30 - xfs /dev/sdb1 rw,attr2,discard,inode64
+41 22 0:40 / /tmp rw,nosuid,nodev shared:31 - tmpfs tmpfs rw,size=8G
+";
+        let mount = find_mount(mountinfo, Path::new("/mnt/bench disk/benchkit")).unwrap();
+        assert_eq!(mount.dev, "8:17");
+        assert_eq!(mount.mount_point, "/mnt/bench disk");
+        assert_eq!(mount.options, "rw,noatime");
+        assert_eq!(mount.fs_type, "xfs");
+        assert_eq!(mount.source, "/dev/sdb1");
+        assert!(mount.online_discard());
+
+        let root = find_mount(mountinfo, Path::new("/home/user")).unwrap();
+        assert_eq!(root.fs_type, "ext4");
+        assert!(!root.online_discard());
+        assert_eq!(
+            find_mount(mountinfo, Path::new("/tmp/benchkit"))
+                .unwrap()
+                .fs_type,
+            "tmpfs"
+        );
+        // /tmpfoo is not under /tmp
+        assert_eq!(
+            find_mount(mountinfo, Path::new("/tmpfoo")).unwrap().fs_type,
+            "ext4"
+        );
+
+        assert_eq!(unescape_mount_field("a\\040b\\134c\\"), "a b\\c\\");
+    }
 }
@@ -0,0 +1,573 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::job::encode_hex;
+
+/// OpenTelemetry span kind of spans started by benchkit itself
+const SPAN_KIND_INTERNAL: u8 = 1;
+
+/// OpenTelemetry status code of a failed span
+const STATUS_CODE_ERROR: u8 = 2;
+
+/// How long the exporter waits for more spans to end before sending a batch
+const BATCH_DELAY: Duration = Duration::from_secs(1);
+
+/// Most spans sent in one export
+const MAX_BATCH_SIZE: usize = 512;
+
+/// Tracer spans are exported through, once `init` was called
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+thread_local! {
+    /// Ids of the spans open on this thread, the last one being the parent of new spans
+    static ACTIVE_SPANS: RefCell<Vec<[u8; 8]>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Export of traces to an OpenTelemetry collector (e.g. Tempo or Jaeger) over OTLP/HTTP
+///
+/// Each build, benchmark, parameter combination, hook and run becomes a span of one trace per
+/// benchkit invocation. Spans are sent in batches from a background thread, so a slow collector
+/// doesn't hold up the runs.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// Base URL of the collector's OTLP/HTTP receiver, like "http://localhost:4318"
+    pub endpoint: String,
+    /// `service.name` of the traces
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Headers sent with each export, e.g. for authentication
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Seconds to wait for the collector before giving up on an export
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_service_name() -> String {
+    "benchkit".to_string()
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+impl TelemetryConfig {
+    /// URL spans are posted to
+    fn traces_url(&self) -> String {
+        format!("{}/v1/traces", self.endpoint.trim_end_matches('/'))
+    }
+}
+
+/// Start exporting spans as configured, for the rest of the process
+pub fn init(config: &TelemetryConfig) -> Result<()> {
+    let tracer = Tracer::new(config)?;
+    info!("Exporting traces to {}", tracer.url);
+    TRACER
+        .set(tracer)
+        .map_err(|_| anyhow::anyhow!("Telemetry is already initialized"))
+}
+
+/// Start a span, a child of the span last started on this thread that is still open
+///
+/// The span ends when dropped. Without telemetry configured it records nothing.
+pub fn span(name: impl Into<String>) -> Span {
+    match TRACER.get() {
+        Some(tracer) => tracer.span(name),
+        None => Span { data: None },
+    }
+}
+
+/// Export the spans which ended but weren't sent yet, and stop exporting
+///
+/// Call before exiting, spans ending afterwards are dropped.
+pub fn shutdown() {
+    if let Some(tracer) = TRACER.get() {
+        tracer.shutdown();
+    }
+}
+
+struct Tracer {
+    url: String,
+    trace_id: [u8; 16],
+    /// Queue of ended spans, None once shut down
+    queue: Mutex<Option<Sender<SpanData>>>,
+    exporter: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Tracer {
+    fn new(config: &TelemetryConfig) -> Result<Self> {
+        let exporter = Exporter::new(config)?;
+        let mut trace_id = [0; 16];
+        trace_id.copy_from_slice(&random_id()[..16]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = std::thread::Builder::new()
+            .name("telemetry".to_string())
+            .spawn(move || exporter.run(receiver))
+            .context("Failed to start the telemetry exporter")?;
+        Ok(Self {
+            url: config.traces_url(),
+            trace_id,
+            queue: Mutex::new(Some(sender)),
+            exporter: Mutex::new(Some(handle)),
+        })
+    }
+
+    fn span(&self, name: impl Into<String>) -> Span {
+        let mut span_id = [0; 8];
+        span_id.copy_from_slice(&random_id()[..8]);
+        let parent_id = ACTIVE_SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            let parent = spans.last().copied();
+            spans.push(span_id);
+            parent
+        });
+        Span {
+            data: Some(SpanData {
+                trace_id: self.trace_id,
+                span_id,
+                parent_id,
+                name: name.into(),
+                start: SystemTime::now(),
+                end: None,
+                attributes: Vec::new(),
+                error: None,
+            }),
+        }
+    }
+
+    /// Queue an ended span for export
+    fn export(&self, span: SpanData) {
+        let queue = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        match queue.as_ref() {
+            // The exporter only hangs up once it stopped exporting
+            Some(sender) => {
+                let _ = sender.send(span);
+            }
+            None => debug!("Telemetry is shut down, dropping span {}", span.name),
+        }
+    }
+
+    fn shutdown(&self) {
+        // The exporter sends what is left once the queue is closed
+        drop(self.queue.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let handle = self
+            .exporter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(handle) = handle {
+            if handle.join().is_err() {
+                warn!("The telemetry exporter panicked");
+            }
+        }
+    }
+}
+
+/// Sends the spans queued by the tracer to the collector
+struct Exporter {
+    client: reqwest::blocking::Client,
+    url: String,
+    service_name: String,
+    headers: BTreeMap<String, String>,
+}
+
+impl Exporter {
+    fn new(config: &TelemetryConfig) -> Result<Self> {
+        if !config.endpoint.starts_with("http://") && !config.endpoint.starts_with("https://") {
+            anyhow::bail!(
+                "Invalid telemetry endpoint '{}', expected an http(s) URL",
+                config.endpoint
+            );
+        }
+        let client = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .context("Failed to create the telemetry client")?;
+        Ok(Self {
+            client,
+            url: config.traces_url(),
+            service_name: config.service_name.clone(),
+            headers: config.headers.clone(),
+        })
+    }
+
+    /// Export batches of spans until the queue is closed or an export fails
+    ///
+    /// Once the collector failed, it isn't tried again, so that an unreachable one costs a
+    /// single timeout.
+    fn run(&self, queue: Receiver<SpanData>) {
+        while let Some(batch) = next_batch(&queue) {
+            if let Err(e) = self.export(&batch) {
+                warn!(
+                    "Failed to export {} spans to {}, not exporting any more: {e:#}",
+                    batch.len(),
+                    self.url
+                );
+                return;
+            }
+            debug!("Exported {} spans", batch.len());
+        }
+    }
+
+    fn export(&self, spans: &[SpanData]) -> Result<()> {
+        let body = otlp_json(&self.service_name, spans);
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string());
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send()?;
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            status => anyhow::bail!("collector responded with {status}"),
+        }
+    }
+}
+
+/// The next spans to export together: the next one to end, and those ending within
+/// `BATCH_DELAY` of it. None once the queue is closed and empty.
+fn next_batch(queue: &Receiver<SpanData>) -> Option<Vec<SpanData>> {
+    let mut batch = vec![queue.recv().ok()?];
+    let deadline = Instant::now() + BATCH_DELAY;
+    while batch.len() < MAX_BATCH_SIZE {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match queue.recv_timeout(timeout) {
+            Ok(span) => batch.push(span),
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(batch)
+}
+
+/// A span of the trace, exported when dropped
+pub struct Span {
+    data: Option<SpanData>,
+}
+
+impl Span {
+    /// Set an attribute of the span
+    pub fn attr(&mut self, key: &str, value: impl Into<AttributeValue>) -> &mut Self {
+        if let Some(data) = &mut self.data {
+            data.attributes.push((key.to_string(), value.into()));
+        }
+        self
+    }
+
+    /// Mark the span as failed
+    pub fn error(&mut self, message: impl Into<String>) {
+        if let Some(data) = &mut self.data {
+            data.error = Some(message.into());
+        }
+    }
+
+    /// Mark the span as failed if `result` is an error, and pass it on
+    pub fn record<T>(&mut self, result: Result<T>) -> Result<T> {
+        if let Err(e) = &result {
+            self.error(format!("{e:#}"));
+        }
+        result
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(mut data) = self.data.take() else {
+            return;
+        };
+        data.end = Some(SystemTime::now());
+        ACTIVE_SPANS.with(|spans| spans.borrow_mut().retain(|id| *id != data.span_id));
+        if let Some(tracer) = TRACER.get() {
+            tracer.export(data);
+        }
+    }
+}
+
+/// Value of a span attribute
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&String> for AttributeValue {
+    fn from(value: &String) -> Self {
+        Self::String(value.clone())
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> Self {
+        Self::Int(value.into())
+    }
+}
+
+impl From<usize> for AttributeValue {
+    fn from(value: usize) -> Self {
+        Self::Int(value as i64)
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> Self {
+        Self::Double(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl AttributeValue {
+    /// OTLP JSON `AnyValue`, with integers as strings like the protobuf JSON mapping of int64
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::String(value) => serde_json::json!({ "stringValue": value }),
+            Self::Int(value) => serde_json::json!({ "intValue": value.to_string() }),
+            Self::Double(value) => serde_json::json!({ "doubleValue": value }),
+            Self::Bool(value) => serde_json::json!({ "boolValue": value }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SpanData {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_id: Option<[u8; 8]>,
+    name: String,
+    start: SystemTime,
+    end: Option<SystemTime>,
+    attributes: Vec<(String, AttributeValue)>,
+    error: Option<String>,
+}
+
+/// OTLP/HTTP JSON request exporting the spans
+fn otlp_json(service_name: &str, spans: &[SpanData]) -> serde_json::Value {
+    let attributes = |attributes: &[(String, AttributeValue)]| {
+        attributes
+            .iter()
+            .map(|(key, value)| serde_json::json!({ "key": key, "value": value.to_json() }))
+            .collect::<Vec<_>>()
+    };
+    let nanos = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string()
+    };
+    let spans: Vec<serde_json::Value> = spans
+        .iter()
+        .map(|span| {
+            let mut json = serde_json::json!({
+                "traceId": encode_hex(&span.trace_id),
+                "spanId": encode_hex(&span.span_id),
+                "name": span.name,
+                "kind": SPAN_KIND_INTERNAL,
+                "startTimeUnixNano": nanos(span.start),
+                "endTimeUnixNano": nanos(span.end.unwrap_or(span.start)),
+                "attributes": attributes(&span.attributes),
+            });
+            if let Some(parent_id) = &span.parent_id {
+                json["parentSpanId"] = encode_hex(parent_id).into();
+            }
+            if let Some(message) = &span.error {
+                json["status"] = serde_json::json!({
+                    "code": STATUS_CODE_ERROR,
+                    "message": message,
+                });
+            }
+            json
+        })
+        .collect();
+    serde_json::json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": attributes(&[(
+                    "service.name".to_string(),
+                    AttributeValue::from(service_name),
+                )]),
+            },
+            "scopeSpans": [{
+                "scope": { "name": "benchkit", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Bytes unique to this process and call, for trace and span ids
+fn random_id() -> [u8; 32] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut hasher = Sha256::new();
+    hasher.update(std::process::id().to_le_bytes());
+    hasher.update(now.to_le_bytes());
+    hasher.update(COUNTER.fetch_add(1, Ordering::SeqCst).to_le_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn test_otlp_export() {
+        let config: TelemetryConfig =
+            serde_yaml::from_str("endpoint: http://127.0.0.1:1/").unwrap();
+        assert_eq!(config.service_name, "benchkit");
+        assert_eq!(config.traces_url(), "http://127.0.0.1:1/v1/traces");
+        let invalid = TelemetryConfig {
+            endpoint: "localhost:4318".to_string(),
+            ..config.clone()
+        };
+        assert!(Tracer::new(&invalid).is_err());
+
+        // Spans nest in the order they are started on a thread
+        let tracer = Tracer::new(&config).unwrap();
+        let mut benchmark = tracer.span("benchmark");
+        benchmark.attr("benchkit.benchmark", "ibd");
+        let mut run = tracer.span("run");
+        run.attr("benchkit.iteration", 2usize)
+            .attr("benchkit.duration_ms", 1.5)
+            .attr("benchkit.cached", false);
+        run.record(Err::<(), _>(anyhow::anyhow!("exited with 1")))
+            .unwrap_err();
+        let benchmark_data = benchmark.data.take().unwrap();
+        let run_data = run.data.take().unwrap();
+        assert_eq!(run_data.parent_id, Some(benchmark_data.span_id));
+        assert_eq!(benchmark_data.parent_id, None);
+        assert_ne!(run_data.span_id, benchmark_data.span_id);
+        ACTIVE_SPANS.with(|spans| spans.borrow_mut().clear());
+
+        let json = otlp_json("benchkit", &[benchmark_data, run_data.clone()]);
+        let resource = &json["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "benchkit"
+        );
+        let spans = &resource["scopeSpans"][0]["spans"];
+        assert_eq!(spans[0]["name"], "benchmark");
+        assert!(spans[0].get("parentSpanId").is_none());
+        assert_eq!(spans[1]["parentSpanId"], spans[0]["spanId"]);
+        assert_eq!(spans[0]["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(spans[1]["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(spans[1]["attributes"][0]["value"]["intValue"], "2");
+        assert_eq!(spans[1]["attributes"][1]["value"]["doubleValue"], 1.5);
+        assert_eq!(spans[1]["status"]["code"], STATUS_CODE_ERROR);
+        assert_eq!(spans[1]["status"]["message"], "exited with 1");
+
+        // Spans are posted to the collector with the configured headers
+        let collector = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let config = TelemetryConfig {
+            endpoint: format!("http://{}", collector.local_addr().unwrap()),
+            headers: BTreeMap::from([("x-scope-orgid".to_string(), "bench".to_string())]),
+            ..config
+        };
+        let received = std::thread::spawn(move || {
+            let (stream, _) = collector.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            (head, String::from_utf8(body).unwrap())
+        });
+        let tracer = Tracer::new(&config).unwrap();
+        tracer.export(run_data.clone());
+        tracer.shutdown();
+        let (head, body) = received.join().unwrap();
+        assert!(head.starts_with("POST /v1/traces "), "{head}");
+        assert!(head.contains("x-scope-orgid: bench"), "{head}");
+        assert!(body.contains("\"exited with 1\""), "{body}");
+    }
+
+    #[test]
+    fn test_export_batches() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let span = |name: &str| SpanData {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            parent_id: None,
+            name: name.to_string(),
+            start: SystemTime::now(),
+            end: None,
+            attributes: Vec::new(),
+            error: None,
+        };
+        for name in ["hook", "run"] {
+            sender.send(span(name)).unwrap();
+        }
+        drop(sender);
+        let batch = next_batch(&receiver).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[1].name, "run");
+        assert!(next_batch(&receiver).is_none());
+
+        // An unreachable collector stops the export instead of delaying later spans
+        let config: TelemetryConfig =
+            serde_yaml::from_str("endpoint: http://127.0.0.1:1/").unwrap();
+        let tracer = Tracer::new(&config).unwrap();
+        tracer.export(span("run"));
+        std::thread::sleep(BATCH_DELAY * 2);
+        let start = Instant::now();
+        tracer.export(span("run"));
+        tracer.shutdown();
+        assert!(start.elapsed() < BATCH_DELAY);
+        tracer.export(span("after shutdown"));
+    }
+}
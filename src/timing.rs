@@ -0,0 +1,104 @@
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::rusage::ChildUsage;
+use crate::types::DurationMs;
+
+/// How long a run took, according to each clock
+///
+/// `monotonic_ms` is what benchkit reports as a run's duration. Wall-clock time can jump when
+/// the system clock is adjusted, and CPU time counts every thread of the command and its waited
+/// for descendants, so it can exceed the elapsed time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RunTiming {
+    /// When the run started, in milliseconds since the Unix epoch
+    pub started_at_ms: u64,
+    /// Elapsed time on the monotonic clock
+    pub monotonic_ms: DurationMs,
+    /// Difference of the system clock between start and end
+    pub wall_ms: DurationMs,
+    /// User plus system CPU time of the command (Unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_ms: Option<DurationMs>,
+}
+
+/// Reads every clock when a run starts and again when it ends
+#[derive(Debug)]
+pub struct Stopwatch {
+    instant: Instant,
+    system_time: SystemTime,
+    usage: Option<ChildUsage>,
+}
+
+impl Stopwatch {
+    /// Start timing. The resource usage of children is read first, so reading it isn't timed.
+    pub fn start() -> Self {
+        let usage = read_child_usage();
+        Self {
+            system_time: SystemTime::now(),
+            instant: Instant::now(),
+            usage,
+        }
+    }
+
+    /// Stop timing, returning the timings and the resource usage of the children waited for
+    /// since the start
+    pub fn stop(self) -> (RunTiming, Option<ChildUsage>) {
+        let monotonic = self.instant.elapsed();
+        let end = SystemTime::now();
+        let usage = match (self.usage, read_child_usage()) {
+            (Some(before), Some(after)) => Some(after.since(&before)),
+            _ => None,
+        };
+        let wall_ms = match end.duration_since(self.system_time) {
+            Ok(elapsed) => DurationMs::from(elapsed),
+            // The clock was set back during the run
+            Err(e) => DurationMs(0.0) - DurationMs::from(e.duration()),
+        };
+        let timing = RunTiming {
+            started_at_ms: self
+                .system_time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            monotonic_ms: monotonic.into(),
+            wall_ms,
+            cpu_ms: usage.map(|usage| usage.cpu_time().into()),
+        };
+        (timing, usage)
+    }
+}
+
+/// Read the resource usage of waited-for children, if available
+fn read_child_usage() -> Option<ChildUsage> {
+    ChildUsage::read()
+        .inspect_err(|e| debug!("Not tracking resource usage: {e}"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stopwatch() {
+        let stopwatch = Stopwatch::start();
+        std::process::Command::new("sh")
+            .args([
+                "-c",
+                "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; sleep 0.2",
+            ])
+            .status()
+            .unwrap();
+        let (timing, usage) = stopwatch.stop();
+
+        assert!(timing.monotonic_ms >= DurationMs(200.0));
+        assert!((timing.wall_ms - timing.monotonic_ms).0.abs() < 100.0);
+        // The shell's loop used CPU, the sleep didn't
+        let cpu_ms = timing.cpu_ms.unwrap();
+        assert!(cpu_ms > DurationMs(0.0) && cpu_ms < timing.monotonic_ms);
+        assert!(usage.unwrap().minor_faults > 0);
+        assert!(timing.started_at_ms > 0);
+    }
+}
@@ -1,7 +1,323 @@
 use clap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, Div, Sub};
+use std::time::Duration;
 
-#[derive(clap::ValueEnum, Clone, Debug)]
+/// Bitcoin network a benchmark runs on
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Network {
+    #[serde(alias = "mainnet")]
+    #[value(alias = "mainnet")]
     Main,
+    /// testnet3
+    #[serde(alias = "test")]
+    #[value(alias = "test")]
+    Testnet,
+    Testnet4,
     Signet,
+    Regtest,
+}
+
+impl Network {
+    /// Value of bitcoind's `-chain` argument
+    pub fn chain_arg(&self) -> &'static str {
+        match self {
+            Network::Main => "main",
+            Network::Testnet => "test",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        }
+    }
+
+    /// Port bitcoind listens for peers on by default
+    pub fn default_p2p_port(&self) -> u16 {
+        match self {
+            Network::Main => 8333,
+            Network::Testnet => 18333,
+            Network::Testnet4 => 48333,
+            Network::Signet => 38333,
+            Network::Regtest => 18444,
+        }
+    }
+
+    /// Port bitcoind serves RPC on by default
+    pub fn default_rpc_port(&self) -> u16 {
+        match self {
+            Network::Main => 8332,
+            Network::Testnet => 18332,
+            Network::Testnet4 => 48332,
+            Network::Signet => 38332,
+            Network::Regtest => 18443,
+        }
+    }
+
+    /// Message start bytes of the network's P2P messages (custom signets have their own)
+    pub fn magic(&self) -> [u8; 4] {
+        match self {
+            Network::Main => [0xf9, 0xbe, 0xb4, 0xd9],
+            Network::Testnet => [0x0b, 0x11, 0x09, 0x07],
+            Network::Testnet4 => [0x1c, 0x16, 0x3f, 0x28],
+            Network::Signet => [0x0a, 0x03, 0xcf, 0x40],
+            Network::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+        }
+    }
+
+    /// Subdirectory of the datadir bitcoind stores this network's data in, if any
+    pub fn data_subdir(&self) -> Option<&'static str> {
+        match self {
+            Network::Main => None,
+            Network::Testnet => Some("testnet3"),
+            Network::Testnet4 => Some("testnet4"),
+            Network::Signet => Some("signet"),
+            Network::Regtest => Some("regtest"),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.chain_arg())
+    }
+}
+
+/// Page cache state a benchmark run starts from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheState {
+    /// OS caches are dropped right before the run (Linux only, needs root)
+    Cold,
+    /// Caches are left as the previous run left them
+    Warm,
+}
+
+impl CacheState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheState::Cold => "cold",
+            CacheState::Warm => "warm",
+        }
+    }
+}
+
+impl fmt::Display for CacheState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for CacheState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "cold" => Ok(CacheState::Cold),
+            "warm" => Ok(CacheState::Warm),
+            _ => anyhow::bail!("Invalid cache_state {s:?}, expected cold or warm"),
+        }
+    }
+}
+
+/// A duration in milliseconds, the unit results record durations in
+///
+/// Serialized as a plain number of milliseconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DurationMs(pub f64);
+
+impl DurationMs {
+    pub fn from_secs_f64(secs: f64) -> Self {
+        Self(secs * 1000.0)
+    }
+
+    pub fn as_secs_f64(self) -> f64 {
+        self.0 / 1000.0
+    }
+
+    pub fn total_cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl From<Duration> for DurationMs {
+    fn from(duration: Duration) -> Self {
+        Self::from_secs_f64(duration.as_secs_f64())
+    }
+}
+
+impl Add for DurationMs {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0)
+    }
+}
+
+impl Sub for DurationMs {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self(self.0 - other.0)
+    }
+}
+
+impl Div<f64> for DurationMs {
+    type Output = Self;
+
+    fn div(self, divisor: f64) -> Self {
+        Self(self.0 / divisor)
+    }
+}
+
+/// The ratio of two durations
+impl Div for DurationMs {
+    type Output = f64;
+
+    fn div(self, divisor: Self) -> f64 {
+        self.0 / divisor.0
+    }
+}
+
+impl Sum for DurationMs {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        Self(iter.map(|d| d.0).sum())
+    }
+}
+
+/// Formats the number of milliseconds, honouring precision like `{:.2}`
+impl fmt::Display for DurationMs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+/// (De)serialize a `DurationMs` as a number of seconds, for formats that record seconds
+pub mod as_secs {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        duration: &DurationMs,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DurationMs, D::Error> {
+        f64::deserialize(deserializer).map(DurationMs::from_secs_f64)
+    }
+}
+
+/// A size in bytes
+///
+/// Serialized as a plain number of bytes.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Bytes(pub u64);
+
+impl Bytes {
+    pub fn as_f64(self) -> f64 {
+        self.0 as f64
+    }
+}
+
+impl From<u64> for Bytes {
+    fn from(bytes: u64) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Add for Bytes {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0)
+    }
+}
+
+impl Div<u64> for Bytes {
+    type Output = Self;
+
+    fn div(self, divisor: u64) -> Self {
+        Self(self.0 / divisor)
+    }
+}
+
+impl Sum for Bytes {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        Self(iter.map(|b| b.0).sum())
+    }
+}
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn test_unit_newtypes() {
+        let run = DurationMs::from(Duration::from_millis(1500));
+        assert_eq!(run, DurationMs(1500.0));
+        assert_eq!(run.as_secs_f64(), 1.5);
+        let total: DurationMs = [run, DurationMs(500.0)].into_iter().sum();
+        assert_eq!(total / 2.0, DurationMs(1000.0));
+        assert_eq!(format!("{:.2}", run - DurationMs(0.5)), "1499.50");
+        assert_eq!(serde_json::to_string(&run).unwrap(), "1500.0");
+
+        #[derive(Serialize, Deserialize)]
+        struct Profile {
+            #[serde(with = "as_secs")]
+            duration: DurationMs,
+        }
+        let json = serde_json::to_string(&Profile { duration: run }).unwrap();
+        assert_eq!(json, r#"{"duration":1.5}"#);
+        let profile: Profile = serde_json::from_str(&json).unwrap();
+        assert_eq!(profile.duration, run);
+
+        let size: Bytes = [Bytes(300), Bytes::from(100)].into_iter().sum();
+        assert_eq!(size / 2, Bytes(200));
+        assert_eq!(serde_json::from_str::<Bytes>("1024").unwrap(), Bytes(1024));
+    }
+
+    #[test]
+    fn test_network_names() {
+        for (name, network) in [
+            ("main", Network::Main),
+            ("mainnet", Network::Main),
+            ("test", Network::Testnet),
+            ("testnet", Network::Testnet),
+            ("testnet4", Network::Testnet4),
+            ("signet", Network::Signet),
+            ("regtest", Network::Regtest),
+        ] {
+            let parsed: Network = serde_yaml::from_str(name).unwrap();
+            assert_eq!(parsed, network);
+            assert_eq!(Network::from_str(name, true).unwrap(), network);
+        }
+        assert!(serde_yaml::from_str::<Network>("testnet5").is_err());
+
+        assert_eq!(Network::Testnet.to_string(), "test");
+        assert_eq!(Network::Testnet.data_subdir(), Some("testnet3"));
+        assert_eq!(Network::Main.data_subdir(), None);
+    }
+
+    #[test]
+    fn test_cache_state_names() {
+        for state in [CacheState::Cold, CacheState::Warm] {
+            assert_eq!(state.to_string().parse::<CacheState>().unwrap(), state);
+            let yaml: CacheState = serde_yaml::from_str(state.as_str()).unwrap();
+            assert_eq!(yaml, state);
+        }
+        assert!("hot".parse::<CacheState>().is_err());
+    }
 }
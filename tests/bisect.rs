@@ -0,0 +1,104 @@
+//! Bisecting a regression between commits of a real git repository with fake binaries
+
+mod support;
+
+use std::path::Path;
+use std::process::Command;
+
+use benchkit::benchmarks::{Bisect, BisectVerdict, BISECT_FILE};
+use support::Harness;
+
+fn git(repo: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .current_dir(repo)
+        .args([
+            "-c",
+            "user.name=benchkit",
+            "-c",
+            "user.email=benchkit@example.com",
+        ])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed");
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+#[test]
+fn test_bisect() {
+    let mut harness = Harness::new(&[]);
+    let repo = harness.path("bitcoin");
+    std::fs::create_dir_all(&repo).unwrap();
+    git(&repo, &["init", "-q"]);
+    let mut commits = Vec::new();
+    for i in 0..5 {
+        git(
+            &repo,
+            &[
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                &format!("commit {i}"),
+            ],
+        );
+        commits.push(git(&repo, &["rev-parse", "--short=12", "HEAD"]));
+    }
+    for commit in &commits {
+        harness.install_binary(commit);
+    }
+    harness.commits = vec![commits[0].clone()];
+
+    // Commits from the third on sleep for longer
+    harness.write_bench_config(
+        &format!(
+            "    runs: 2\n    command_overrides:\n      - commits: [{}]\n        append: \
+             [-fakesleep=0.6]",
+            commits[2..].join(", ")
+        ),
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep=0.2""#,
+    );
+    std::fs::create_dir_all(harness.path("data")).unwrap();
+
+    let report = Bisect::new(
+        harness.config(),
+        harness.out_dir(),
+        "fake-ibd",
+        &commits[0],
+        &commits[4],
+        0.5,
+    )
+    .run()
+    .unwrap();
+    assert_eq!(report.first_bad, commits[2]);
+    assert_eq!(report.steps[0].verdict, BisectVerdict::Good);
+    assert_eq!(report.steps[1].verdict, BisectVerdict::Bad);
+    for step in &report.steps {
+        assert!(harness
+            .out_dir()
+            .join(&step.out_dir)
+            .join("results.json")
+            .exists());
+    }
+    assert!(harness.out_dir().join(BISECT_FILE).exists());
+    // The repository is left as it was
+    assert!(!repo.join(".git/BISECT_START").exists());
+
+    // Without a regression between the commits there is nothing to bisect
+    std::fs::remove_dir_all(harness.out_dir()).unwrap();
+    let err = Bisect::new(
+        harness.config(),
+        harness.out_dir(),
+        "fake-ibd",
+        &commits[0],
+        &commits[1],
+        0.5,
+    )
+    .run()
+    .unwrap_err();
+    assert!(err.to_string().contains("no regression"), "{err}");
+}
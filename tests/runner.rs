@@ -0,0 +1,505 @@
+//! End-to-end runs of the benchmark matrix against a fake bitcoind
+
+mod support;
+
+use benchkit::benchmarks::{
+    Activity, LiveStatus, RunOverrides, Runner, OVERRIDES_FILE, PARTIAL_RESULTS_FILE,
+};
+use benchkit::config::BenchmarkSelection;
+use benchkit::types::DurationMs;
+use support::{exists, result_with, Harness, FAKE_VERSION};
+
+#[test]
+fn test_parameter_matrix() {
+    let harness = Harness::new(&["aaaaaaa", "bbbbbbb"]);
+    harness.write_bench_config(
+        "    runs: 2",
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep={sleep} -fakelog=UpdateTip -fakealloc=1"
+      parameter_lists:
+        - var: sleep
+          values: ["0", "0.3"]"#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    assert_eq!(results.len(), 4);
+    for result in &results {
+        assert_eq!(result.runs.len(), 2);
+        assert!(result.failures.is_empty());
+        assert_eq!(result.version.as_deref(), Some(FAKE_VERSION));
+        let run = &result.runs[0];
+        assert_eq!(run.exit_code, 0);
+        assert_eq!(run.node_settings["chain"], "regtest");
+        assert_eq!(run.node_settings["fakesleep"], result.parameters["sleep"]);
+    }
+    assert!(
+        result_with(&results, "sleep", "0.3").summary.mean
+            > result_with(&results, "sleep", "0").summary.mean + DurationMs(200.0)
+    );
+
+    // The conclude hook keeps each run's debug.log
+    let debug_log = harness.out_dir().join("bbbbbbb/sleep-0.3/1/debug.log");
+    let debug_log = std::fs::read_to_string(&debug_log).unwrap();
+    assert!(debug_log.contains("UpdateTip"), "{debug_log}");
+
+    // benchkit tail sees every run as it finished, and the run as finished
+    let partial = std::fs::read_to_string(harness.out_dir().join(PARTIAL_RESULTS_FILE)).unwrap();
+    assert_eq!(partial.lines().count(), 8);
+    let status = LiveStatus::load(&harness.out_dir()).unwrap().unwrap();
+    assert_eq!(status.activity, Activity::Finished);
+}
+
+#[test]
+fn test_out_dir_template() {
+    let harness = Harness::new(&["aaaaaaa0123456789", "bbbbbbb0123456789"]);
+    harness.write_bench_config(
+        r#"    runs: 1
+    out_dir_template: "{benchmark}/{commit_short}-{sleep}/{iteration}""#,
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep={sleep} -fakelog=UpdateTip"
+      parameter_lists:
+        - var: sleep
+          values: ["0", "0.1"]"#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    assert_eq!(results.len(), 4);
+    for result in &results {
+        let dir = result.out_dir.clone().unwrap();
+        let expected = format!(
+            "fake-ibd/{}-{}",
+            &result.parameters["commit"][..12],
+            result.parameters["sleep"]
+        );
+        assert_eq!(dir.to_string_lossy(), expected);
+        // The conclude hook moves debug.log into the templated directory
+        assert!(harness.out_dir().join(&dir).join("0/debug.log").is_file());
+    }
+
+    // Combinations sharing a directory are refused before anything runs
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        r#"    runs: 1
+    out_dir_template: "{benchmark}/{commit}/{iteration}""#,
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep={sleep}"
+      parameter_lists:
+        - var: sleep
+          values: ["0", "0.1"]"#,
+    );
+    let err = format!("{:#}", harness.run().unwrap_err());
+    assert!(
+        err.contains("add the parameters telling them apart"),
+        "{err}"
+    );
+}
+
+#[test]
+fn test_failing_runs() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        r#"    runs: 5
+    continue_on_failure: true
+    fail_fast:
+      max_consecutive_failures: 2"#,
+        r#"  - name: fake-crash
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakeexit={exit}"
+      parameter_lists:
+        - var: exit
+          values: ["0", "3"]"#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    let passing = result_with(&results, "exit", "0");
+    assert_eq!(passing.runs.len(), 5);
+    assert!(passing.failures.is_empty());
+
+    let failing = result_with(&results, "exit", "3");
+    // Runs exiting with an error are recorded, until fail_fast gives up on the combination
+    assert_eq!(failing.runs.len(), 2);
+    assert!(failing.runs.iter().all(|run| run.exit_code == 3));
+    // Each with its diagnostics bundled next to its output directory
+    for run in &failing.runs {
+        let bundle = run.failure_bundle.as_ref().unwrap();
+        assert!(bundle.is_file(), "{}", bundle.display());
+        assert!(bundle
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with(&format!("failure-aaaaaaa-{}.tar.", run.iteration)));
+    }
+    assert!(passing.runs.iter().all(|run| run.failure_bundle.is_none()));
+    assert_eq!(failing.failures.len(), 1);
+    assert_eq!(
+        failing.failures[0].message,
+        "Aborted: 2 runs in a row failed"
+    );
+    assert_eq!(failing.failures[0].iteration, None);
+}
+
+#[test]
+fn test_crashed_runs() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        r#"    runs: 1
+    continue_on_failure: true
+    core_dumps: true"#,
+        r#"  - name: fake-abort
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakeexit=134""#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    let run = &results[0].runs[0];
+    assert_eq!(run.exit_code, 134);
+    // A shell reporting a signal as 128 + N counts as a crash too
+    let crash = run.crash.as_ref().unwrap();
+    assert_eq!(crash.signal, 6);
+    assert_eq!(crash.signal_name, "SIGABRT");
+    // The fake node dumped no core
+    assert!(crash.core.is_none());
+}
+
+#[test]
+fn test_checkpoints() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        r#"    runs: 1
+    checkpoint:
+      interval_hours: 0.0001"#,
+        r#"  - name: fake-long-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakeblocks={blocks}"
+      parameter_lists:
+        - var: blocks
+          values: ["10"]"#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    let run = &results[0].runs[0];
+    assert_eq!(run.exit_code, 0);
+    // Stopped every 0.36s, and once more when the node finished
+    let checkpoints = &run.checkpoints;
+    assert!(checkpoints.len() >= 2, "{checkpoints:?}");
+    assert_eq!(checkpoints.last().unwrap().height, Some(10));
+    assert!(checkpoints.windows(2).all(
+        |pair| pair[0].elapsed_ms.0 < pair[1].elapsed_ms.0 && pair[0].height <= pair[1].height
+    ));
+    assert_eq!(run.duration_ms, checkpoints.last().unwrap().elapsed_ms);
+    let run_dir = harness.out_dir().join("aaaaaaa/blocks-10/0");
+    assert!(exists(&run_dir, "checkpoints.json"));
+    assert!(exists(&run_dir, "checkpoints/0-debug.log"));
+
+    // Resuming keeps the finished run rather than running the node again
+    std::fs::write(
+        harness.bin_dir().join("bitcoind-aaaaaaa"),
+        "#!/bin/sh\nexit 1\n",
+    )
+    .unwrap();
+    harness.resume().unwrap();
+    let resumed = harness.results();
+    assert_eq!(resumed[0].runs[0].exit_code, 0);
+    assert_eq!(resumed[0].runs[0].checkpoints, *checkpoints);
+}
+
+#[test]
+fn test_disk_guard() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        r#"    runs: 2
+    continue_on_failure: true
+    disk_guard:
+      min_free_mb: 1000000000000"#,
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind""#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    assert!(results[0].runs.is_empty());
+    assert_eq!(results[0].failures.len(), 2);
+    let failure = &results[0].failures[0];
+    assert!(
+        failure.message.starts_with("Disk full imminent"),
+        "{failure:?}"
+    );
+
+    // Giving up after runs which couldn't complete doesn't record the last of them twice
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        r#"    runs: 3
+    continue_on_failure: true
+    fail_fast:
+      max_consecutive_failures: 2
+    disk_guard:
+      min_free_mb: 1000000000000"#,
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind""#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    let iterations: Vec<Option<usize>> = results[0]
+        .failures
+        .iter()
+        .map(|failure| failure.iteration)
+        .collect();
+    assert_eq!(iterations, vec![Some(0), Some(1), None]);
+    assert_eq!(
+        results[0].failures[2].message,
+        "Aborted: 2 runs in a row failed"
+    );
+}
+
+#[test]
+fn test_requirements() {
+    let harness = Harness::new(&["aaaaaaa", "bbbbbbb"]);
+    let benchmark = |requires: &str| {
+        format!(
+            r#"  - name: fake-zmq
+    network: regtest
+    mode: full_ibd
+    requires:
+{requires}
+    benchmark:
+      command: "bitcoind""#
+        )
+    };
+    harness.write_bench_config(
+        "    runs: 1",
+        &benchmark("      features: [zmq]\n      min_version: \"99.0\""),
+    );
+    harness.run().unwrap();
+
+    harness.write_bench_config(
+        "    runs: 1",
+        &benchmark("      features: [zmq, wallet]\n      min_version: \"100\""),
+    );
+    std::fs::remove_dir_all(harness.out_dir()).unwrap();
+    let err = format!("{:#}", harness.run().unwrap_err());
+    for binary in ["bitcoind-aaaaaaa", "bitcoind-bbbbbbb"] {
+        assert!(
+            err.contains(&format!(
+                "fake-zmq: {binary}: version 99.0.0 is older than 100"
+            )),
+            "{err}"
+        );
+        assert!(
+            err.contains(&format!("fake-zmq: {binary}: it was built without wallet")),
+            "{err}"
+        );
+    }
+    // Nothing ran
+    assert!(!harness.out_dir().join("results.json").exists());
+}
+
+#[test]
+fn test_command_overrides() {
+    let harness = Harness::new(&["aaaaaaa", "bbbbbbb"]);
+    harness.write_bench_config(
+        r#"    runs: 1
+    command_overrides:
+      - commits: [aaaaaaa]
+        rename:
+          -fakenew: -fakeold
+      - versions: ">=99.0"
+        remove: [-fakegone]
+        append: [-fakeadded=1]"#,
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakenew=2 -fakegone""#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    let old = result_with(&results, "commit", "aaaaaaa");
+    let new = result_with(&results, "commit", "bbbbbbb");
+    assert_eq!(old.runs[0].node_settings["fakeold"], "2");
+    assert!(!old.runs[0].node_settings.contains_key("fakenew"));
+    assert_eq!(new.runs[0].node_settings["fakenew"], "2");
+    for result in [old, new] {
+        assert!(!result.runs[0].node_settings.contains_key("fakegone"));
+        assert_eq!(result.runs[0].node_settings["fakeadded"], "1");
+        assert!(
+            result.command.ends_with("-fakeadded=1"),
+            "{}",
+            result.command
+        );
+    }
+}
+
+#[test]
+fn test_run_overrides() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        "    runs: 3\n    warmup: 2",
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep={sleep}"
+      parameter_lists:
+        - var: sleep
+          values: ["0", "0.3"]"#,
+    );
+    harness
+        .run_with_overrides(RunOverrides {
+            runs: Some(1),
+            warmup: Some(0),
+            params: RunOverrides::parse_params(&["sleep=0.1".to_string()]).unwrap(),
+        })
+        .unwrap();
+
+    let results = harness.results();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].parameters["sleep"], "0.1");
+    assert_eq!(results[0].runs.len(), 1);
+    let recorded = std::fs::read_to_string(harness.out_dir().join(OVERRIDES_FILE)).unwrap();
+    assert!(recorded.contains("\"sleep\""), "{recorded}");
+}
+
+#[test]
+fn test_cpu_limits() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    harness.write_bench_config(
+        "    runs: 1\n    cpu_limits:\n      par: [1, 4]",
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep=0""#,
+    );
+    harness.run().unwrap();
+
+    let results = harness.results();
+    assert_eq!(results.len(), 2);
+    for par in ["1", "4"] {
+        let result = result_with(&results, "par", par);
+        assert_eq!(result.runs[0].node_settings["par"], par);
+    }
+}
+
+#[test]
+fn test_result_cache() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    let app_config = harness.path("config.yml");
+    let contents = std::fs::read_to_string(&app_config).unwrap();
+    std::fs::write(&app_config, format!("{contents}result_cache: true\n")).unwrap();
+    let write_config = |values: &str| {
+        harness.write_bench_config(
+            "    runs: 1",
+            &format!(
+                r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind -fakesleep={{sleep}} -fakelog=ran-{{sleep}}"
+      parameter_lists:
+        - var: sleep
+          values: [{values}]"#
+            ),
+        );
+    };
+    write_config("\"0\"");
+    harness.run().unwrap();
+    assert!(harness.results()[0].cached_from.is_none());
+
+    // Adding a value only runs the new combination
+    std::fs::rename(harness.out_dir(), harness.path("first")).unwrap();
+    write_config("\"0\", \"0.1\"");
+    harness.run().unwrap();
+    let results = harness.results();
+    let cached = result_with(&results, "sleep", "0");
+    assert!(cached.cached_from.as_ref().unwrap().ends_with("out"));
+    assert_eq!(cached.runs.len(), 1);
+    assert!(result_with(&results, "sleep", "0.1").cached_from.is_none());
+    assert!(!exists(&harness.out_dir(), "aaaaaaa/sleep-0"));
+    assert!(exists(&harness.out_dir(), "aaaaaaa/sleep-0.1/0/debug.log"));
+}
+
+#[test]
+fn test_missing_binary() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    std::fs::remove_file(harness.bin_dir().join("bitcoind-aaaaaaa")).unwrap();
+    harness.write_bench_config(
+        "    runs: 1",
+        r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    benchmark:
+      command: "bitcoind""#,
+    );
+    let err = format!("{:#}", harness.run().unwrap_err());
+    assert!(err.contains("bitcoind-aaaaaaa"), "{err}");
+}
+
+#[test]
+fn test_connect_check() {
+    let harness = Harness::new(&["aaaaaaa"]);
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+    harness.write_bench_config(
+        "    runs: 1",
+        &format!(
+            r#"  - name: fake-ibd
+    network: regtest
+    mode: full_ibd
+    connect: {address}
+    benchmark:
+      command: "bitcoind""#
+        ),
+    );
+    let err = format!("{:#}", harness.run().unwrap_err());
+    assert!(
+        err.contains(&format!(
+            "fake-ibd: Nothing is listening at the connect address {address}"
+        )),
+        "{err}"
+    );
+    assert!(err.contains("--skip-connect-check"), "{err}");
+    // Nothing ran
+    assert!(!harness.out_dir().join("results.json").exists());
+
+    std::fs::remove_dir_all(harness.out_dir()).unwrap();
+    std::fs::create_dir_all(harness.path("data")).unwrap();
+    Runner::new(harness.config(), harness.out_dir())
+        .unwrap()
+        .quiet(true)
+        .skip_connect_check(true)
+        .run(&BenchmarkSelection::default())
+        .unwrap();
+    // The feeder is recorded even though it couldn't be reached
+    let feeder = harness.results()[0].feeder.clone().unwrap();
+    assert_eq!(feeder.address, address.to_string());
+    assert!(feeder.before.is_none() && !feeder.changed);
+}
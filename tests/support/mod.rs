@@ -0,0 +1,203 @@
+//! Support for running benchkit end to end without building Bitcoin Core
+//!
+//! `Harness` sets up a benchkit home with a fake `bitcoind` for each commit, a shell script
+//! which behaves like a node as far as benchkit can tell. Besides the usual arguments, it
+//! understands:
+//!
+//! - `-fakesleep=<seconds>`: how long the node "syncs" for
+//! - `-fakeexit=<code>`: the exit code
+//! - `-fakealloc=<MiB>`: memory to allocate while running
+//! - `-fakelog=<line>`: a line to log, may be given several times
+//! - `-fakeblocks=<n>`: blocks to "sync", one every 0.1s, logging an UpdateTip line for each.
+//!   Restarted nodes continue from the height they reached.
+//!
+//! Its `-help` lists the ZMQ options but no wallet ones, as if built without the wallet.
+//!
+//! Like bitcoind, it logs its version and `Command-line arg:` lines to `debug.log` in the
+//! network's datadir, and to stdout.
+
+#![allow(dead_code)]
+
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use benchkit::benchmarks::{BenchmarkResult, ResultExporter, RunOverrides, Runner};
+use benchkit::config::{load_app_config, load_bench_config, BenchmarkSelection, GlobalConfig};
+use tempfile::TempDir;
+
+/// Version line the fake binary reports
+pub const FAKE_VERSION: &str = "Bitcoin Core version v99.0.0-fake";
+
+const FAKE_BITCOIND: &str = r#"#!/bin/sh
+# Fake bitcoind for benchkit's integration tests
+datadir=.
+chain=main
+sleep_secs=0
+exit_code=0
+alloc_mb=0
+blocks=0
+for arg in "$@"; do
+    case "$arg" in
+        -version) echo "@VERSION@"; exit 0 ;;
+        -help) echo "  -datadir=<dir>"; echo "  -zmqpubrawblock=<address>"; exit 0 ;;
+        -datadir=*) datadir="${arg#-datadir=}" ;;
+        -chain=*) chain="${arg#-chain=}" ;;
+        -fakesleep=*) sleep_secs="${arg#-fakesleep=}" ;;
+        -fakeexit=*) exit_code="${arg#-fakeexit=}" ;;
+        -fakealloc=*) alloc_mb="${arg#-fakealloc=}" ;;
+        -fakeblocks=*) blocks="${arg#-fakeblocks=}" ;;
+    esac
+done
+case "$chain" in
+    main) logdir="$datadir" ;;
+    test) logdir="$datadir/testnet3" ;;
+    *) logdir="$datadir/$chain" ;;
+esac
+mkdir -p "$logdir"
+log() {
+    echo "2025-01-01T00:00:00Z $*" | tee -a "$logdir/debug.log"
+}
+log "@VERSION@"
+for arg in "$@"; do
+    case "$arg" in
+        -fakelog=*) ;;
+        -*=*) name="${arg%%=*}"; log "Command-line arg: ${name#-}=\"${arg#*=}\"" ;;
+    esac
+done
+for arg in "$@"; do
+    case "$arg" in
+        -fakelog=*) log "${arg#-fakelog=}" ;;
+    esac
+done
+if [ "$alloc_mb" -gt 0 ]; then
+    awk -v mb="$alloc_mb" 'BEGIN { s = sprintf("%*s", mb * 1048576, ""); system("sleep 0.1") }'
+fi
+trap 'exit 0' TERM
+height=0
+if [ -f "$logdir/fakeheight" ]; then
+    height=$(cat "$logdir/fakeheight")
+fi
+while [ "$height" -lt "$blocks" ]; do
+    sleep 0.1
+    height=$((height + 1))
+    echo "$height" > "$logdir/fakeheight"
+    log "UpdateTip: new best=fake height=$height"
+done
+sleep "$sleep_secs"
+exit "$exit_code"
+"#;
+
+/// A benchkit home with fake binaries, and configs to run them with
+pub struct Harness {
+    pub dir: TempDir,
+    pub commits: Vec<String>,
+}
+
+impl Harness {
+    /// Install a fake bitcoind for each commit and write the app config
+    pub fn new(commits: &[&str]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let harness = Self {
+            dir,
+            commits: commits.iter().map(|c| c.to_string()).collect(),
+        };
+        std::fs::create_dir_all(harness.bin_dir()).unwrap();
+        for commit in commits {
+            harness.install_binary(commit);
+        }
+        std::fs::write(
+            harness.path("config.yml"),
+            "home_dir: ./home\nbin_dir: ./bin\npatch_dir: ./patches\nsnapshot_dir: ./snapshots\n",
+        )
+        .unwrap();
+        harness
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn bin_dir(&self) -> PathBuf {
+        self.path("bin")
+    }
+
+    pub fn out_dir(&self) -> PathBuf {
+        self.path("out")
+    }
+
+    /// Write the fake binary of a commit
+    pub fn install_binary(&self, commit: &str) {
+        let binary = self.bin_dir().join(format!("bitcoind-{commit}"));
+        std::fs::write(&binary, FAKE_BITCOIND.replace("@VERSION@", FAKE_VERSION)).unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// Write a benchmark config with the given global benchmark options and benchmarks, both
+    /// as indented YAML
+    pub fn write_bench_config(&self, options: &str, benchmarks: &str) -> PathBuf {
+        let commits = self
+            .commits
+            .iter()
+            .map(|c| format!("\"{c}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let config = format!(
+            "global:\n  benchmark:\n{options}\n  source: ./bitcoin\n  scratch: ./scratch\n  \
+             commits: [{commits}]\n  tmp_data_dir: ./data\nbenchmarks:\n{benchmarks}\n"
+        );
+        let path = self.path("benchmark.yml");
+        std::fs::write(&path, config).unwrap();
+        path
+    }
+
+    /// Load the configs written so far
+    pub fn config(&self) -> GlobalConfig {
+        GlobalConfig {
+            app: load_app_config(&self.path("config.yml")).unwrap(),
+            bench: load_bench_config(&self.path("benchmark.yml")).unwrap(),
+        }
+    }
+
+    /// Run every benchmark into the output directory
+    pub fn run(&self) -> anyhow::Result<()> {
+        self.run_with_overrides(RunOverrides::default())
+    }
+
+    /// Run every benchmark with configuration overridden as on the command line
+    pub fn run_with_overrides(&self, overrides: RunOverrides) -> anyhow::Result<()> {
+        std::fs::create_dir_all(self.path("data")).unwrap();
+        Runner::new(self.config(), self.out_dir())?
+            .quiet(true)
+            .overrides(overrides)
+            .run(&BenchmarkSelection::default())
+    }
+
+    /// Continue the run in the output directory
+    pub fn resume(&self) -> anyhow::Result<()> {
+        Runner::resume(self.config(), self.out_dir())?
+            .quiet(true)
+            .run(&BenchmarkSelection::default())
+    }
+
+    /// Results the last benchmark wrote
+    pub fn results(&self) -> Vec<BenchmarkResult> {
+        ResultExporter::load_json(&self.out_dir().join("results.json")).unwrap()
+    }
+}
+
+/// The result with a parameter set to a value
+pub fn result_with<'a>(
+    results: &'a [BenchmarkResult],
+    name: &str,
+    value: &str,
+) -> &'a BenchmarkResult {
+    results
+        .iter()
+        .find(|r| r.parameters.get(name).map(String::as_str) == Some(value))
+        .unwrap_or_else(|| panic!("No result with {name}={value}"))
+}
+
+/// Whether a path exists below a directory
+pub fn exists(dir: &Path, relative: &str) -> bool {
+    dir.join(relative).exists()
+}